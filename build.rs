@@ -0,0 +1,11 @@
+// Generates the messages and the server of the gRPC service from its proto file
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let proto = "proto/drino/v1/routing.proto";
+    println!("cargo:rerun-if-changed={proto}");
+
+    let descriptors = protox::compile([proto], ["proto"])?;
+    tonic_build::configure()
+        .build_client(false)
+        .compile_fds(descriptors)?;
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Conversions of the ids from and to the values in the tables of polars
+
+use super::{LineId, SeqNum, StationId, StopId, TripId};
+use polars::datatypes::AnyValue;
+
+pub fn u32_from_any_value(value: AnyValue) -> Result<u32, ()> {
+    match value {
+        AnyValue::UInt32(value) => Ok(value),
+        AnyValue::UInt16(value) => Ok(value as u32),
+        AnyValue::UInt8(value) => Ok(value as u32),
+        _ => Err(())
+    }
+}
+
+pub fn f64_from_any_value(value: AnyValue) -> Result<f64, ()> {
+    match value {
+        AnyValue::Float64(value) => Ok(value),
+        AnyValue::Float32(value) => Ok(value as f64),
+        _ => Err(())
+    }
+}
+
+impl<'a> From<StopId> for AnyValue<'a> {
+    fn from(value: StopId) -> AnyValue<'a> {
+        AnyValue::UInt32(value.0)
+    }
+}
+
+impl<'a> TryFrom<AnyValue<'a>> for StopId {
+    type Error = ();
+
+    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
+        u32_from_any_value(value).map(Self)
+    }
+}
+
+impl<'a> From<StationId> for AnyValue<'a> {
+    fn from(value: StationId) -> AnyValue<'a> {
+        AnyValue::UInt32(value.0)
+    }
+}
+
+impl<'a> TryFrom<AnyValue<'a>> for StationId {
+    type Error = ();
+
+    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
+        u32_from_any_value(value).map(Self)
+    }
+}
+
+impl<'a> From<LineId> for AnyValue<'a> {
+    fn from(value: LineId) -> AnyValue<'a> {
+        AnyValue::UInt32(value.0)
+    }
+}
+
+impl<'a> TryFrom<AnyValue<'a>> for LineId {
+    type Error = ();
+
+    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
+        u32_from_any_value(value).map(Self)
+    }
+}
+
+impl<'a> From<TripId> for AnyValue<'a> {
+    fn from(value: TripId) -> AnyValue<'a> {
+        AnyValue::UInt32(value.0)
+    }
+}
+
+impl<'a> TryFrom<AnyValue<'a>> for TripId {
+    type Error = ();
+
+    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
+        u32_from_any_value(value).map(Self)
+    }
+}
+
+impl<'a> From<SeqNum> for AnyValue<'a> {
+    fn from(value: SeqNum) -> AnyValue<'a> {
+        AnyValue::UInt32(value.0)
+    }
+}
+
+impl<'a> TryFrom<AnyValue<'a>> for SeqNum {
+    type Error = ();
+
+    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
+        u32_from_any_value(value).map(Self)
+    }
+}
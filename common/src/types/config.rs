@@ -1,13 +1,494 @@
 use serde::{Deserialize, Serialize};
-use crate::types::dataset::{Dataset, DatasetGroup};
+use chrono::TimeDelta;
+use std::fmt::{Display, Formatter};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use crate::types::dataset::{Dataset, DatasetFilter, DatasetGroup};
+use crate::types::mode::{Mode, ModeRegistry};
+use crate::util::size::ByteSize;
+use crate::util::speed::{Speed, MAX_WALKING_DURATION, MAX_WALKING_SPEED};
 
+/// The config file. Options that older versions of drino would silently ignore, like the
+/// credentials of a dataset, need a new version, so that these versions reject the file instead.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(tag = "version")]
 pub enum Config {
     #[serde(rename = "1")]
-    Version1 {
-        datasets: Vec<Dataset>,
+    Version1(Settings),
+    // Adds the credentials, refresh interval, filter and attribution of datasets
+    #[serde(rename = "2")]
+    Version2(Settings),
+}
+
+/// The sections of the config, which are the same in all versions
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Settings {
+    pub datasets: Vec<Dataset>,
+    #[serde(default)]
+    pub dataset_groups: Vec<DatasetGroup>,
+    #[serde(default)]
+    pub merge: MergeConfig,
+    #[serde(default)]
+    pub simplify: SimplifyConfig,
+    #[serde(default)]
+    pub routing: RoutingConfig,
+    // Added to or replacing the built-in modes, see [ModeRegistry]
+    #[serde(default)]
+    pub modes: Vec<Mode>,
+    // Applies to every dataset, for the options that the filter of a dataset doesn't set
+    #[serde(default)]
+    pub filter: DatasetFilter,
+}
+
+/// The version of a config file. Readers take it from the file before the settings, since the
+/// derived [Config] would mistake the number 1 in JSON or TOML for the index of `Version2`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ConfigHeader {
+    pub version: ConfigVersion,
+}
+
+/// A number in JSON and TOML, a string in YAML
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ConfigVersion {
+    Number(u64),
+    Text(String),
+}
+
+impl Display for ConfigVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigVersion::Number(version) => write!(f, "{}", version),
+            ConfigVersion::Text(version) => write!(f, "{}", version),
+        }
+    }
+}
+
+impl Config {
+    /// The config of the settings in the version, if the version is known. The global filter is
+    /// applied to the filters of the datasets.
+    pub fn new(version: &ConfigVersion, mut settings: Settings) -> Option<Self> {
+        for dataset in &mut settings.datasets {
+            dataset.filter = dataset.filter.or(&settings.filter);
+        }
+        match version.to_string().as_str() {
+            "1" => Some(Config::Version1(settings)),
+            "2" => Some(Config::Version2(settings)),
+            _ => None,
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        match self {
+            Config::Version1(settings) | Config::Version2(settings) => settings,
+        }
+    }
+
+    pub fn into_settings(self) -> Settings {
+        match self {
+            Config::Version1(settings) | Config::Version2(settings) => settings,
+        }
+    }
+
+    pub fn mode_registry(&self) -> ModeRegistry {
+        ModeRegistry::new(&self.settings().modes)
+    }
+
+    /// The options that are set although the version of the config doesn't know them yet, e.g.
+    /// "`credentials:` of dataset de:vvs:gtfs"
+    pub fn unsupported_options(&self) -> Vec<String> {
+        let Config::Version1(settings) = self else {
+            return vec![];
+        };
+
+        let global = (!settings.filter.is_empty()).then(|| "`filter:`".to_string());
+        global.into_iter()
+            .chain(settings.datasets.iter().flat_map(|dataset| {
+                dataset.version2_options().into_iter().map(|option| format!("`{}:` of dataset {}", option, dataset.id))
+            }))
+            .collect()
+    }
+}
+
+/// Settings for merging several datasets into one
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MergeConfig {
+    // Stops of different datasets within this many meters and with similar names are merged into
+    // one stop. A radius of 0 disables merging stops.
+    #[serde(default = "default_stop_match_radius")]
+    pub stop_match_radius: f32,
+    // Walking transfers are generated between stops of different datasets within this many
+    // meters. A radius of 0 disables generating transfers.
+    #[serde(default = "default_transfer_radius")]
+    pub transfer_radius: f32,
+}
+
+fn default_stop_match_radius() -> f32 {
+    25.0
+}
+
+fn default_transfer_radius() -> f32 {
+    200.0
+}
+
+impl Default for MergeConfig {
+    fn default() -> Self {
+        Self { stop_match_radius: default_stop_match_radius(), transfer_radius: default_transfer_radius() }
+    }
+}
+
+/// Settings for simplifying the merged datasets before routing
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SimplifyConfig {
+    // Passes that are run, in this order. Only pruning by default: the other passes make the
+    // routing data smaller, but drop the ids of the trips and services that they remove, so that
+    // realtime updates and clients can't refer to them anymore.
+    #[serde(default = "default_simplify_passes")]
+    pub passes: Vec<SimplifyPass>,
+}
+
+/// A step of the simplification that can be turned off
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SimplifyPass {
+    // Removes trips that are contained in several datasets, with the ids of all but one copy
+    Dedup,
+    // Removes services, trips and stops that are never used
+    Prune,
+    // Turns trips that run at a constant headway into frequency-based trips, with the ids of all
+    // but the first run
+    FrequencyCompress,
+    // Merges services of a dataset that run on the same days, with the ids of all but one service
+    DayReduce,
+}
+
+// The lossy passes are opt-in
+fn default_simplify_passes() -> Vec<SimplifyPass> {
+    vec![SimplifyPass::Prune]
+}
+
+impl Default for SimplifyConfig {
+    fn default() -> Self {
+        Self { passes: default_simplify_passes() }
+    }
+}
+
+/// Settings for the preprocessing and querying of the routing algorithm
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RoutingConfig {
+    // Only optimal journeys with at most this many rides (walking transfers don't count) are
+    // stored as transfer patterns. Longer journeys are very rare, but make up a lot of patterns.
+    #[serde(default = "default_max_legs")]
+    pub max_legs: u32,
+    // Number of clusters that the stops are partitioned into for scalable transfer patterns.
+    // Transfer patterns are computed within every cluster, more clusters need less time and RAM
+    // per cluster but have more border stops.
+    #[serde(default = "default_num_clusters")]
+    pub num_clusters: u32,
+    // Number of start stops that a thread takes at once while computing transfer patterns. By
+    // default every stop is a task of its own, so that threads that are done steal the stops of
+    // those that are stuck at hubs. Larger values save overhead on networks of similar stops.
+    #[serde(default)]
+    pub starts_per_task: Option<NonZeroU32>,
+    // Resident memory while preprocessing above which intermediate tables and partial transfer
+    // patterns are written to temporary files, e.g. "4GiB". Slower, but large networks fit on
+    // machines with less RAM. No budget by default.
+    #[serde(default)]
+    pub memory_budget: Option<ByteSize>,
+    // Minimum time in minutes to change between vehicles at a station. Queries leave at least this
+    // much time for every transfer, and after preprocessing the scheduled connections at the
+    // busiest stations are checked against it.
+    #[serde(default = "default_min_transfer_minutes")]
+    pub min_transfer_minutes: u32,
+    // Compression of the routing data that is written to disk
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub mode: RoutingMode,
+    // Stops whose lookups are cached before serving, so that the first queries after a deploy
+    // don't have to wait for them. Ids are the ones of their dataset prefixed with the dataset id.
+    #[serde(default)]
+    pub warm_up_stops: Vec<String>,
+    // Share of the journeys that the server plans, between 0 and 1, which plain RAPTOR plans as
+    // well, logging whenever it arrives at another time than the transfer patterns. None by
+    // default, since RAPTOR is built when the server starts and the sampled queries take longer.
+    #[serde(default)]
+    pub shadow_sample_rate: f64,
+    #[serde(default)]
+    pub transfers: TransferConfig,
+    #[serde(default)]
+    pub park_and_ride: ParkAndRideConfig,
+}
+
+/// How walks between stops are estimated, both while preprocessing and answering queries
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct TransferConfig {
+    // Average walking speed in km/h along the straight line between two stops
+    #[serde(default = "default_walking_speed")]
+    pub walking_speed: f64,
+    // Longer walks are not considered, without the penalty
+    #[serde(default = "default_max_walking_minutes")]
+    pub max_walking_minutes: u32,
+    // Stops further apart are never connected by a walk, regardless of the walking speed
+    #[serde(default)]
+    pub max_distance_meters: Option<f32>,
+    // Added to every walk, e.g. for finding the way and getting to the platform
+    #[serde(default)]
+    pub penalty_seconds: u32,
+    // OpenStreetMap extract (.osm.pbf) whose footways are walked instead of straight lines
+    #[serde(default)]
+    pub osm_extract: Option<PathBuf>,
+    // Routing engine that is asked for the walks instead
+    #[serde(default)]
+    pub router: Option<ExternalRouter>,
+    // Providers that are asked for a transfer in this order, the first one that knows both stops
+    // answers. Providers that aren't configured are skipped.
+    #[serde(default = "default_chain")]
+    pub chain: Vec<TransferSource>,
+}
+
+/// Where the duration of a transfer between two stops comes from
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransferSource {
+    // Minimum transfer times of transfers.txt of the datasets
+    Timetable,
+    // Walks requested from the router
+    Router,
+    // Walks along the ways of the OSM extract
+    OsmExtract,
+    // Walks along the straight line
+    CrowFly,
+}
+
+/// A routing engine with an HTTP API, whose walks between stops are requested once while
+/// preprocessing and cached in the working directory
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ExternalRouter {
+    pub engine: RouterEngine,
+    // Base URL of the API, e.g. http://localhost:5000
+    pub url: String,
+    // Sources and targets of a single table request, servers reject larger ones
+    #[serde(default = "default_max_locations")]
+    pub max_locations: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum RouterEngine {
+    // https://project-osrm.org, walks at the speed of the profile the server was started with
+    Osrm,
+    // https://valhalla.github.io/valhalla, walks at the configured walking speed
+    Valhalla,
+}
+
+// The default limit of OSRM's table service
+fn default_max_locations() -> usize {
+    100
+}
+
+fn default_chain() -> Vec<TransferSource> {
+    vec![TransferSource::Timetable, TransferSource::Router, TransferSource::OsmExtract, TransferSource::CrowFly]
+}
+
+fn default_walking_speed() -> f64 {
+    MAX_WALKING_SPEED.0
+}
+
+fn default_max_walking_minutes() -> u32 {
+    MAX_WALKING_DURATION.num_minutes() as u32
+}
+
+impl TransferConfig {
+    pub fn walking_speed(&self) -> Speed {
+        Speed(self.walking_speed)
+    }
+
+    pub fn max_duration(&self) -> TimeDelta {
+        TimeDelta::minutes(self.max_walking_minutes as i64)
+    }
+
+    pub fn penalty(&self) -> TimeDelta {
+        TimeDelta::seconds(self.penalty_seconds as i64)
+    }
+
+    /// Whether the source is part of the chain and configured
+    pub fn uses(&self, source: TransferSource) -> bool {
+        let configured = match source {
+            TransferSource::Router => self.router.is_some(),
+            TransferSource::OsmExtract => self.osm_extract.is_some(),
+            TransferSource::Timetable | TransferSource::CrowFly => true,
+        };
+        configured && self.chain.contains(&source)
+    }
+
+    /// Whether walks follow actual ways and are therefore computed while preprocessing
+    pub fn follows_ways(&self) -> bool {
+        self.uses(TransferSource::Router) || self.uses(TransferSource::OsmExtract)
+    }
+}
+
+impl Default for TransferConfig {
+    fn default() -> Self {
+        Self {
+            walking_speed: default_walking_speed(),
+            max_walking_minutes: default_max_walking_minutes(),
+            max_distance_meters: None,
+            penalty_seconds: 0,
+            osm_extract: None,
+            router: None,
+            chain: default_chain(),
+        }
+    }
+}
+
+/// Stops where travellers may park a car or a bike to continue by public transport, and how far
+/// they go to get there
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct ParkAndRideConfig {
+    // Ids of P+R stops, the ones of their dataset prefixed with the dataset id
+    #[serde(default)]
+    pub stops: Vec<String>,
+    // OpenStreetMap extract (.osm.pbf) whose sites tagged with `park_ride` mark the stops around
+    // them as P+R stops
+    #[serde(default)]
+    pub osm_extract: Option<PathBuf>,
+    // Stops within this many meters of a tagged site are P+R stops
+    #[serde(default = "default_park_and_ride_radius")]
+    pub radius_meters: f32,
+    // Average driving speed in km/h along the straight line, which is slower than on the roads
+    #[serde(default = "default_driving_speed")]
+    pub driving_speed: f64,
+    // Longer drives and rides to a P+R stop are not considered
+    #[serde(default = "default_max_access_minutes")]
+    pub max_access_minutes: u32,
+    // Only the nearest P+R stops are tried at the start and target, since each pair takes a
+    // search of its own
+    #[serde(default = "default_max_park_and_ride_stops")]
+    pub max_stops: usize,
+}
+
+fn default_park_and_ride_radius() -> f32 {
+    300.0
+}
+
+fn default_driving_speed() -> f64 {
+    30.0
+}
+
+fn default_max_access_minutes() -> u32 {
+    30
+}
+
+fn default_max_park_and_ride_stops() -> usize {
+    5
+}
+
+impl ParkAndRideConfig {
+    pub fn driving_speed(&self) -> Speed {
+        Speed(self.driving_speed)
+    }
+
+    pub fn max_access_duration(&self) -> TimeDelta {
+        TimeDelta::minutes(self.max_access_minutes as i64)
+    }
+}
+
+impl Default for ParkAndRideConfig {
+    fn default() -> Self {
+        Self {
+            stops: vec![],
+            osm_extract: None,
+            radius_meters: default_park_and_ride_radius(),
+            driving_speed: default_driving_speed(),
+            max_access_minutes: default_max_access_minutes(),
+            max_stops: default_max_park_and_ride_stops(),
+        }
+    }
+}
+
+/// Which queries are answered. Everything but full journey planning works without the expensive
+/// preprocessing of the routing algorithm.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum RoutingMode {
+    // Journeys with transfers
+    #[default]
+    Journeys,
+    // Only connections without transfers and departure boards, straight from the timetable
+    TimetableLookup,
+}
+
+/// Codec for persisted tables. Smaller files take longer to load, the fastest startup is without
+/// compression. The reader detects the codec of a file, so changing it does not require to
+/// preprocess again.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "codec", rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Lz4,
+    // Levels range from 1 (fastest) to 22 (smallest), without a level zstd's default is used.
+    // Files in the IPC format are always compressed with the default level.
+    Zstd {
         #[serde(default)]
-        dataset_groups: Vec<DatasetGroup>,
+        level: Option<i32>,
+    },
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::Zstd { level: None }
+    }
+}
+
+fn default_max_legs() -> u32 {
+    4
+}
+
+fn default_num_clusters() -> u32 {
+    8
+}
+
+fn default_min_transfer_minutes() -> u32 {
+    2
+}
+
+impl Default for RoutingConfig {
+    fn default() -> Self {
+        Self {
+            max_legs: default_max_legs(),
+            num_clusters: default_num_clusters(),
+            starts_per_task: None,
+            memory_budget: None,
+            min_transfer_minutes: default_min_transfer_minutes(),
+            compression: Default::default(),
+            mode: Default::default(),
+            warm_up_stops: vec![],
+            shadow_sample_rate: 0.0,
+            transfers: Default::default(),
+            park_and_ride: Default::default(),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::dataset::Credentials;
+
+    const DATASET: &str = r#"{"id": "de:vvs:gtfs", "format": "gtfs", "src": {"path": "vvs.zip"}, "refresh": "1d",
+        "credentials": {"bearer": {"token_env": "VVS_TOKEN"}}}"#;
+
+    #[test]
+    fn test_version2_options() {
+        let config: Config = serde_json::from_str(&format!(r#"{{"version": "2", "datasets": [{DATASET}]}}"#)).unwrap();
+        let dataset = &config.settings().datasets[0];
+        assert_eq!(dataset.refresh.map(|refresh| refresh.0), Some(24 * 60 * 60));
+        assert_eq!(dataset.credentials, Some(Credentials::Bearer { token_env: "VVS_TOKEN".into() }));
+        assert!(config.unsupported_options().is_empty());
+        // Secrets are never part of the served config
+        assert!(!serde_json::to_string(&config).unwrap().contains("VVS_TOKEN"));
+
+        let config: Config = serde_json::from_str(&format!(r#"{{"version": "1", "datasets": [{DATASET}]}}"#)).unwrap();
+        assert_eq!(config.unsupported_options(), vec!["`credentials:` of dataset de:vvs:gtfs", "`refresh:` of dataset de:vvs:gtfs"]);
     }
-}
\ No newline at end of file
+}
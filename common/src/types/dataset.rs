@@ -1,4 +1,6 @@
 use crate::util::distance::{Distance, Radius};
+use crate::util::duration::Seconds;
+use crate::util::size::ByteSize;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use url::Url;
@@ -62,7 +64,141 @@ pub struct Dataset {
     pub license: Option<License>,
     #[serde(default, rename = "groups")]
     pub group_ids: Vec<String>,
-    // TODO: Fetch interval et al
+    // Overrides the severity of validation rules by their id for this dataset
+    #[serde(default)]
+    pub validation: HashMap<String, Severity>,
+    // Area in which all stops of the dataset are expected to be
+    #[serde(default)]
+    pub bounds: Option<BoundingBox>,
+    // Whether stops at implausible locations are removed instead of only being reported
+    #[serde(default)]
+    pub drop_implausible_stops: bool,
+    // Whether trivially fixable issues (e.g. duplicate stop times) are repaired before validation
+    #[serde(default)]
+    pub fix: bool,
+    // Trips of other datasets that are replaced by the trips of this dataset
+    #[serde(default)]
+    pub overrides: Vec<DatasetOverride>,
+    // GTFS-RT feed of the positions of the vehicles serving the trips of this dataset
+    #[serde(default)]
+    pub vehicle_positions: Option<RealtimeFeed>,
+    // GTFS-RT feed of the trip updates of this dataset, which `drino serve` pushes to the clients
+    // that follow a journey
+    #[serde(default)]
+    pub trip_updates: Option<RealtimeFeed>,
+    // GTFS-RT feed of the service alerts of this dataset, which `drino serve` adds to the legs of
+    // planned journeys
+    #[serde(default)]
+    pub alerts: Option<RealtimeFeed>,
+    // Authentication for downloading the source. Never serialized, since the config is served by
+    // the visualization.
+    #[serde(default, skip_serializing)]
+    pub credentials: Option<Credentials>,
+    // A download is reused until it is older than this. Without an interval, the source is
+    // downloaded on every run.
+    #[serde(default)]
+    pub refresh: Option<Seconds>,
+    // Parts of the dataset that are imported, the rest is dropped right away
+    #[serde(default)]
+    pub filter: DatasetFilter,
+    // Text that has to be shown with results from this dataset, as most licenses require
+    #[serde(default)]
+    pub attribution: Option<String>,
+}
+
+impl Dataset {
+    /// Names of the options that are set although they need `version: 2` of the config
+    pub fn version2_options(&self) -> Vec<&'static str> {
+        let mut options = vec![];
+        if self.credentials.is_some() {
+            options.push("credentials");
+        }
+        if self.refresh.is_some() {
+            options.push("refresh");
+        }
+        if !self.filter.is_empty() {
+            options.push("filter");
+        }
+        if self.attribution.is_some() {
+            options.push("attribution");
+        }
+        options
+    }
+}
+
+/// Secrets for downloading a dataset. They are read from environment variables when downloading,
+/// so that they don't have to be part of the config file.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Credentials {
+    Basic { username: String, password_env: String },
+    Bearer { token_env: String },
+}
+
+/// Restricts what is imported of a dataset, e.g. to the area of a city in a national feed. Options
+/// that aren't set keep everything.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct DatasetFilter {
+    // Stops outside of this area are dropped, and with them the stop times at these stops
+    #[serde(default)]
+    pub bbox: Option<BoundingBox>,
+    // Ids of the agencies in the dataset whose routes are kept
+    #[serde(default)]
+    pub agencies: Vec<String>,
+    // GTFS route types of the routes that are kept, e.g. 2 for rail
+    #[serde(default)]
+    pub route_types: Vec<u32>,
+}
+
+impl DatasetFilter {
+    pub fn is_empty(&self) -> bool {
+        self == &DatasetFilter::default()
+    }
+
+    /// This filter with the options it doesn't set taken from `global`
+    pub fn or(&self, global: &DatasetFilter) -> DatasetFilter {
+        DatasetFilter {
+            bbox: self.bbox.or(global.bbox),
+            agencies: non_empty_or(&self.agencies, &global.agencies),
+            route_types: non_empty_or(&self.route_types, &global.route_types),
+        }
+    }
+}
+
+fn non_empty_or<T: Clone>(own: &[T], global: &[T]) -> Vec<T> {
+    if own.is_empty() { global.to_vec() } else { own.to_vec() }
+}
+
+/// Trips of another dataset that are dropped when merging, e.g. because this dataset is the
+/// official feed of an agency that is also part of an aggregated feed. Agencies and routes are
+/// given by their ids in the overridden dataset. Without agencies and routes, all trips of the
+/// other dataset are dropped.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct DatasetOverride {
+    pub dataset: String,
+    #[serde(default)]
+    pub agency_ids: Vec<String>,
+    #[serde(default)]
+    pub route_ids: Vec<String>,
+}
+
+/// Rectangular area in degrees
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub min_lat: f32,
+    pub min_lon: f32,
+    pub max_lat: f32,
+    pub max_lon: f32,
+}
+
+/// Severity of a violated validation rule. Datasets with errors are skipped, warnings are only
+/// reported and ignored rules are not checked at all.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warn,
+    Ignore,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -83,12 +219,53 @@ pub enum DataSource {
         url: Url,
         #[serde(default)]
         headers: HashMap<String, String>,
+        // Maximum time for the whole download
+        #[serde(default = "default_fetch_timeout")]
+        timeout: Seconds,
+        // Downloads larger than this are aborted
+        #[serde(default = "default_max_size")]
+        max_size: ByteSize,
     },
     File {
         path: String
     }
 }
 
+/// A GTFS-RT feed that is fetched repeatedly
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RealtimeFeed {
+    pub src: DataSource,
+    #[serde(default = "default_realtime_interval")]
+    pub interval: Seconds,
+}
+
+fn default_realtime_interval() -> Seconds {
+    Seconds(30)
+}
+
+impl DataSource {
+    /// A remote source with the default limits if `src` is an HTTP(S) URL, otherwise a local path
+    pub fn from_url_or_path(src: &str) -> Self {
+        match Url::parse(src) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => DataSource::URL {
+                url,
+                headers: HashMap::new(),
+                timeout: default_fetch_timeout(),
+                max_size: default_max_size(),
+            },
+            _ => DataSource::File { path: src.to_string() },
+        }
+    }
+}
+
+fn default_fetch_timeout() -> Seconds {
+    Seconds(10 * 60)
+}
+
+fn default_max_size() -> ByteSize {
+    ByteSize(4 << 30)
+}
+
 // Identifiers: https://spdx.org/licenses/
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum License {
@@ -0,0 +1,107 @@
+// Marks source ids that aren't mapped
+const UNMAPPED: u32 = u32::MAX;
+
+/// Renumbers ids of one kind into the contiguous range 0..n, so that everything about them can be
+/// kept in vectors instead of hash maps. The n-th source id gets the dense id n, both directions
+/// are a single index into a vector.
+///
+/// The ids after simplify start at zero, but the engines are built from subsets of them, e.g. of
+/// the stops that lines serve. The source ids must not be much larger than their number, since
+/// the lookup of dense ids has an entry for every source id up to the largest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DenseIds<Id> {
+    // Source id of every dense id
+    sources: Vec<Id>,
+    // Dense id of every source id, UNMAPPED for source ids that aren't in here
+    dense: Vec<u32>,
+}
+
+impl<Id> Default for DenseIds<Id> {
+    fn default() -> Self {
+        Self { sources: vec![], dense: vec![] }
+    }
+}
+
+impl<Id> DenseIds<Id>
+where
+    Id: Copy + From<u32>,
+    u32: From<Id>,
+{
+    /// Maps every source id to its position in `sources`. Ids that occur multiple times are
+    /// mapped to their first position.
+    pub fn new(sources: impl IntoIterator<Item = Id>) -> Self {
+        let mut ids = Self::default();
+        sources.into_iter().for_each(|source| ids.push(source));
+        ids
+    }
+
+    /// Maps the source id to the next dense id, if it isn't mapped already
+    pub fn push(&mut self, source: Id) {
+        let idx = u32::from(source) as usize;
+        if idx >= self.dense.len() {
+            self.dense.resize(idx + 1, UNMAPPED);
+        }
+        if self.dense[idx] == UNMAPPED {
+            self.dense[idx] = self.sources.len() as u32;
+            self.sources.push(source);
+        }
+    }
+
+    /// The dense id of a source id, if it is mapped
+    pub fn dense(&self, source: Id) -> Option<Id> {
+        self.dense.get(u32::from(source) as usize)
+            .filter(|dense| **dense != UNMAPPED)
+            .map(|dense| Id::from(*dense))
+    }
+
+    /// The source id of a dense id
+    pub fn source(&self, dense: Id) -> Id {
+        self.sources[u32::from(dense) as usize]
+    }
+
+    pub fn contains(&self, source: &Id) -> bool {
+        self.dense(*source).is_some()
+    }
+
+    /// The source ids, ordered by their dense id
+    pub fn sources(&self) -> &[Id] {
+        &self.sources
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
+
+impl<Id> FromIterator<Id> for DenseIds<Id>
+where
+    Id: Copy + From<u32>,
+    u32: From<Id>,
+{
+    fn from_iter<T: IntoIterator<Item = Id>>(iter: T) -> Self {
+        Self::new(iter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::StopId;
+
+    #[test]
+    fn test_dense_ids() {
+        let ids: DenseIds<StopId> = [42, 7, 42, 9].into_iter().map(StopId).collect();
+
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids.sources(), [StopId(42), StopId(7), StopId(9)]);
+        assert_eq!(ids.dense(StopId(7)), Some(StopId(1)));
+        assert_eq!(ids.dense(StopId(8)), None);
+        assert_eq!(ids.dense(StopId(1000)), None);
+        assert_eq!(ids.source(StopId(2)), StopId(9));
+        assert!(ids.contains(&StopId(42)));
+    }
+}
@@ -1,6 +1,7 @@
 use std::fmt;
 use std::fmt::Formatter;
 use crate::types::StopId;
+use serde::{Deserialize, Serialize};
 
 #[derive(thiserror::Error, Debug)]
 pub struct UnknownStopIdError(pub StopId);
@@ -9,4 +10,69 @@ impl fmt::Display for UnknownStopIdError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "Unknown Stop ID {}", self.0)
     }
-}
\ No newline at end of file
+}
+
+/// Stable, machine-readable reason of a failed request. Clients should branch on these instead of
+/// the messages, which may change at any time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    // The requested location is not covered by any of the datasets
+    NoCoverage,
+    // The requested time is outside the validity of the datasets
+    DateOutOfRange,
+    StopNotFound,
+    NoRouteFound,
+    // The query took longer than allowed
+    Timeout,
+    // The data is being preprocessed or reloaded, the request can be retried later
+    DataReloading,
+    NotFound,
+    Internal,
+}
+
+impl ErrorCode {
+    /// The HTTP status code that fits the error best
+    pub fn http_status(&self) -> u16 {
+        match self {
+            ErrorCode::NoCoverage | ErrorCode::DateOutOfRange => 422,
+            ErrorCode::StopNotFound | ErrorCode::NoRouteFound | ErrorCode::NotFound => 404,
+            ErrorCode::Timeout => 504,
+            ErrorCode::DataReloading => 503,
+            ErrorCode::Internal => 500,
+        }
+    }
+
+    /// Short summary of the error, which is the same for every occurrence
+    pub fn title(&self) -> &'static str {
+        match self {
+            ErrorCode::NoCoverage => "Location is not covered",
+            ErrorCode::DateOutOfRange => "Date is out of range",
+            ErrorCode::StopNotFound => "Stop not found",
+            ErrorCode::NoRouteFound => "No route found",
+            ErrorCode::Timeout => "Query timed out",
+            ErrorCode::DataReloading => "Data is reloading",
+            ErrorCode::NotFound => "Not found",
+            ErrorCode::Internal => "Internal error",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.title())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_code_names() {
+        // The names are part of the API and must not change
+        let names = [ErrorCode::NoCoverage, ErrorCode::DateOutOfRange, ErrorCode::StopNotFound, ErrorCode::Timeout, ErrorCode::DataReloading]
+            .map(|code| serde_json::to_string(&code).unwrap());
+        assert_eq!(names, ["\"NO_COVERAGE\"", "\"DATE_OUT_OF_RANGE\"", "\"STOP_NOT_FOUND\"", "\"TIMEOUT\"", "\"DATA_RELOADING\""]);
+    }
+}
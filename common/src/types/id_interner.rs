@@ -0,0 +1,158 @@
+use crate::types::{StopId, TripId};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Bidirectional mapping between the original (string) identifiers of a dataset and the dense
+/// numeric ids used internally. The n-th interned identifier gets the id n.
+#[derive(Debug, Clone)]
+pub struct IdInterner<Id> {
+    ids: HashMap<Arc<str>, Id>,
+    originals: Vec<Arc<str>>,
+    _id: PhantomData<Id>,
+}
+
+impl<Id> Default for IdInterner<Id> {
+    fn default() -> Self {
+        Self {
+            ids: HashMap::new(),
+            originals: vec![],
+            _id: PhantomData,
+        }
+    }
+}
+
+impl<Id> IdInterner<Id>
+where
+    Id: Copy + From<u32>,
+    u32: From<Id>,
+{
+    /// Builds an interner where the id of every identifier is its position in `originals`. The
+    /// same identifier may occur multiple times (e.g. for runs of frequency-based trips), in this
+    /// case [IdInterner::get] returns the first id.
+    pub fn from_originals<'a>(originals: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut interner = Self::default();
+        originals.into_iter().for_each(|original| {
+            interner.push(original);
+        });
+        interner
+    }
+
+    /// Returns the id of the identifier, assigning the next free id if it is not known yet
+    pub fn intern(&mut self, original: &str) -> Id {
+        match self.ids.get(original) {
+            Some(id) => *id,
+            None => self.push(original),
+        }
+    }
+
+    /// Always assigns a new id, even if the identifier is already known
+    pub fn push(&mut self, original: &str) -> Id {
+        let id = Id::from(self.originals.len() as u32);
+        let original: Arc<str> = original.into();
+
+        self.ids.entry(original.clone()).or_insert(id);
+        self.originals.push(original);
+
+        id
+    }
+
+    /// The id of an original identifier
+    pub fn get(&self, original: &str) -> Option<Id> {
+        self.ids.get(original).copied()
+    }
+
+    /// The original identifier of an id
+    pub fn resolve(&self, id: Id) -> Option<&str> {
+        self.originals.get(u32::from(id) as usize).map(|original| original.as_ref())
+    }
+
+    pub fn len(&self) -> usize {
+        self.originals.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.originals.is_empty()
+    }
+}
+
+/// Original identifiers of all stops and trips, so that results can be reported with the ids of
+/// the source datasets. Identifiers are prefixed with the id of their dataset, e.g.
+/// "vvs:de:08111:6118".
+#[derive(Debug, Clone, Default)]
+pub struct OriginalIds {
+    pub stops: IdInterner<StopId>,
+    pub trips: IdInterner<TripId>,
+    // Identifiers that trips carried over from the previous versions of their feed, which stay the
+    // same when a new version renames the trips
+    pub stable_trips: IdInterner<TripId>,
+}
+
+impl OriginalIds {
+    pub fn stop(&self, id: StopId) -> Option<&str> {
+        self.stops.resolve(id)
+    }
+
+    pub fn trip(&self, id: TripId) -> Option<&str> {
+        self.trips.resolve(id)
+    }
+
+    /// The trip with the original identifier, otherwise the trip that carried the identifier over
+    /// from a previous version of its feed. Feeds that reuse an old identifier for another trip
+    /// mean the trip that has it now.
+    pub fn find_trip(&self, id: &str) -> Option<TripId> {
+        self.trips.get(id).or_else(|| self.stable_trips.get(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_and_resolve() {
+        let mut interner: IdInterner<StopId> = IdInterner::default();
+
+        assert_eq!(interner.intern("de:08111:6118"), StopId(0));
+        assert_eq!(interner.intern("de:08111:2"), StopId(1));
+        assert_eq!(interner.intern("de:08111:6118"), StopId(0));
+        assert_eq!(interner.len(), 2);
+
+        assert_eq!(interner.get("de:08111:2"), Some(StopId(1)));
+        assert_eq!(interner.get("unknown"), None);
+        assert_eq!(interner.resolve(StopId(0)), Some("de:08111:6118"));
+        assert_eq!(interner.resolve(StopId(2)), None);
+    }
+
+    #[test]
+    fn test_find_trip() {
+        // Trip 1 was called "d:old" in the previous version of the feed
+        let original_ids = OriginalIds {
+            trips: IdInterner::from_originals(["d:a", "d:new"]),
+            stable_trips: IdInterner::from_originals(["d:a", "d:old"]),
+            ..Default::default()
+        };
+
+        assert_eq!(original_ids.find_trip("d:old"), Some(TripId(1)));
+        assert_eq!(original_ids.find_trip("d:new"), Some(TripId(1)));
+        assert_eq!(original_ids.find_trip("d:unknown"), None);
+
+        // The new version calls trip 0 like trip 1 was called before
+        let original_ids = OriginalIds {
+            trips: IdInterner::from_originals(["d:old", "d:new"]),
+            stable_trips: IdInterner::from_originals(["d:a", "d:old"]),
+            ..Default::default()
+        };
+        assert_eq!(original_ids.find_trip("d:old"), Some(TripId(0)));
+    }
+
+    #[test]
+    fn test_from_originals_with_duplicates() {
+        let interner: IdInterner<TripId> = IdInterner::from_originals(["a", "b", "b", "c"]);
+
+        assert_eq!(interner.len(), 4);
+        assert_eq!(interner.get("b"), Some(TripId(1)));
+        assert_eq!(interner.resolve(TripId(2)), Some("b"));
+        assert_eq!(interner.resolve(TripId(3)), Some("c"));
+    }
+}
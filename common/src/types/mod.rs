@@ -1,28 +1,17 @@
 use chrono::{DateTime, NaiveDate, Utc};
-use polars::datatypes::AnyValue;
 use std::fmt::{Debug, Display, Formatter};
 
 pub mod dataset;
 pub mod config;
+pub mod dense_ids;
 pub mod errors;
+pub mod id_interner;
+pub mod mode;
+#[cfg(feature = "preprocessing")]
+mod any_value;
 
-pub fn u32_from_any_value(value: AnyValue) -> Result<u32, ()> {
-    match value {
-        AnyValue::UInt32(value) => Ok(value),
-        AnyValue::UInt16(value) => Ok(value as u32),
-        AnyValue::UInt8(value) => Ok(value as u32),
-        _ => Err(())
-    }
-}
-
-pub fn f64_from_any_value(value: AnyValue) -> Result<f64, ()> {
-    match value {
-        AnyValue::Float64(value) => Ok(value),
-        AnyValue::Float32(value) => Ok(value as f64),
-        _ => Err(())
-    }
-}
-
+#[cfg(feature = "preprocessing")]
+pub use any_value::{f64_from_any_value, u32_from_any_value};
 
 // a continuous stop id
 // "continuous" means that if we have n stops, all ids are from 0,...,n-1 and no number in that range
@@ -42,67 +31,49 @@ impl Debug for StopId {
     }
 }
 
-impl<'a> From<StopId> for AnyValue<'a> {
-    fn from(value: StopId) -> AnyValue<'a> {
-        AnyValue::UInt32(value.0)
+impl From<u32> for StopId {
+    fn from(value: u32) -> Self {
+        Self(value)
     }
 }
 
-impl<'a> TryFrom<AnyValue<'a>> for StopId {
-    type Error = ();
-
-    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
-        u32_from_any_value(value).map(Self)
+impl From<StopId> for u32 {
+    fn from(value: StopId) -> Self {
+        value.0
     }
 }
 
-impl From<u32> for StopId {
+
+// A station groups stops (e.g. the platforms of a train station) that should be treated as one
+// logical stop. Like stop ids, station ids are continuous.
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct StationId(pub u32);
+
+impl From<u32> for StationId {
     fn from(value: u32) -> Self {
         Self(value)
     }
 }
 
 
-#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct LineId(pub u32);
 
-impl<'a> From<LineId> for AnyValue<'a> {
-    fn from(value: LineId) -> AnyValue<'a> {
-        AnyValue::UInt32(value.0)
-    }
-}
-
-impl<'a> TryFrom<AnyValue<'a>> for LineId {
-    type Error = ();
-
-    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
-        u32_from_any_value(value).map(Self)
-    }
-}
-
 impl From<u32> for LineId {
     fn from(value: u32) -> Self {
         Self(value)
     }
 }
 
-
-#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
-pub struct TripId(pub u32);
-
-impl<'a> From<TripId> for AnyValue<'a> {
-    fn from(value: TripId) -> AnyValue<'a> {
-        AnyValue::UInt32(value.0)
+impl From<LineId> for u32 {
+    fn from(value: LineId) -> Self {
+        value.0
     }
 }
 
-impl<'a> TryFrom<AnyValue<'a>> for TripId {
-    type Error = ();
 
-    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
-        u32_from_any_value(value).map(Self)
-    }
-}
+#[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub struct TripId(pub u32);
 
 impl From<u32> for TripId {
     fn from(value: u32) -> Self {
@@ -110,6 +81,12 @@ impl From<u32> for TripId {
     }
 }
 
+impl From<TripId> for u32 {
+    fn from(value: TripId) -> Self {
+        value.0
+    }
+}
+
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub enum IndividualTrip {
     Calendar { id: TripId, start_day_utc: NaiveDate },
@@ -121,20 +98,6 @@ pub enum IndividualTrip {
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct SeqNum(pub u32);
 
-impl<'a> From<SeqNum> for AnyValue<'a> {
-    fn from(value: SeqNum) -> AnyValue<'a> {
-        AnyValue::UInt32(value.0)
-    }
-}
-
-impl<'a> TryFrom<AnyValue<'a>> for SeqNum {
-    type Error = ();
-
-    fn try_from(value: AnyValue<'a>) -> Result<Self, Self::Error> {
-        u32_from_any_value(value).map(Self)
-    }
-}
-
 impl From<u32> for SeqNum {
     fn from(value: u32) -> Self {
         Self(value)
@@ -0,0 +1,150 @@
+use serde::{Deserialize, Serialize};
+use crate::util::speed::{Speed, MAX_SPEED};
+
+/// A mode of transport like tram or ferry. Routes belong to a mode by their GTFS route type, and
+/// the mode decides how they are shown to users and treated by validation and routing.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Mode {
+    pub id: String,
+    // Shown to users, e.g. "Tram"
+    pub name: String,
+    // Name or URL of the icon clients show for the mode
+    #[serde(default)]
+    pub icon: Option<String>,
+    // Clients group modes of the same category, e.g. "rail" for trains, trams and subways
+    #[serde(default)]
+    pub category: Option<String>,
+    // Extended route types (e.g. 109 for suburban railway) belong to the mode of their basic route
+    // type (2 for rail) unless a mode lists them
+    pub route_types: Vec<u32>,
+    // Vehicles travelling faster than this many km/h between two stops are reported by the
+    // validation. Speeds above the one the routing assumes for all vehicles are capped.
+    #[serde(default = "default_max_speed")]
+    pub max_speed: f64,
+    // Minutes on top of the minimum transfer time to change to a vehicle of this mode, e.g. to get
+    // to the platform of a long-distance train
+    #[serde(default)]
+    pub transfer_slack_minutes: u32,
+}
+
+fn default_max_speed() -> f64 {
+    MAX_SPEED.0
+}
+
+/// The modes routes are assigned to. Modes of the config come first and replace built-in modes
+/// with the same id, the built-in modes cover the basic GTFS route types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeRegistry {
+    modes: Vec<Mode>,
+}
+
+impl Default for ModeRegistry {
+    fn default() -> Self {
+        let mode = |id: &str, name: &str, category: &str, route_types: Vec<u32>, max_speed: f64| Mode {
+            id: id.into(),
+            name: name.into(),
+            icon: Some(id.into()),
+            category: Some(category.into()),
+            route_types,
+            max_speed,
+            transfer_slack_minutes: 0,
+        };
+
+        Self {
+            modes: vec![
+                mode("tram", "Tram", "rail", vec![0], 120.0),
+                mode("subway", "Subway", "rail", vec![1], 150.0),
+                mode("rail", "Train", "rail", vec![2], MAX_SPEED.0),
+                mode("bus", "Bus", "road", vec![3], 150.0),
+                mode("ferry", "Ferry", "water", vec![4], 100.0),
+                mode("cable-tram", "Cable tram", "cable", vec![5], 60.0),
+                mode("aerial-lift", "Aerial lift", "cable", vec![6], 60.0),
+                mode("funicular", "Funicular", "cable", vec![7], 60.0),
+                mode("trolleybus", "Trolleybus", "road", vec![11], 120.0),
+                mode("monorail", "Monorail", "rail", vec![12], 150.0),
+            ],
+        }
+    }
+}
+
+impl ModeRegistry {
+    pub fn new(configured: &[Mode]) -> Self {
+        let built_in = Self::default().modes.into_iter()
+            .filter(|mode| configured.iter().all(|configured| configured.id != mode.id));
+        Self { modes: configured.iter().cloned().chain(built_in).collect() }
+    }
+
+    pub fn modes(&self) -> &[Mode] {
+        &self.modes
+    }
+
+    /// The mode that lists the route type, otherwise the mode of its basic route type
+    pub fn mode_of(&self, route_type: u32) -> Option<&Mode> {
+        let find = |route_type: u32| self.modes.iter().find(|mode| mode.route_types.contains(&route_type));
+        find(route_type).or_else(|| basic_route_type(route_type).and_then(find))
+    }
+
+    /// Maximum speed of vehicles of the route type, [MAX_SPEED] for route types without a mode
+    pub fn max_speed(&self, route_type: u32) -> Speed {
+        let max_speed = self.mode_of(route_type).map_or(MAX_SPEED.0, |mode| mode.max_speed);
+        Speed(max_speed.min(MAX_SPEED.0))
+    }
+}
+
+// The basic route type an extended route type is a variant of. Extended route types without a
+// basic counterpart like air services or taxis have none.
+fn basic_route_type(route_type: u32) -> Option<u32> {
+    match route_type {
+        100..=199 => Some(2),
+        200..=299 | 700..=799 => Some(3),
+        400..=699 => Some(1),
+        800..=899 => Some(11),
+        900..=999 => Some(0),
+        1000..=1099 | 1200..=1299 => Some(4),
+        1300..=1399 => Some(6),
+        1400..=1499 => Some(7),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_of() {
+        let registry = ModeRegistry::default();
+        assert_eq!(registry.mode_of(3).map(|mode| mode.id.as_str()), Some("bus"));
+        // Suburban railway and city tram are extended route types
+        assert_eq!(registry.mode_of(109).map(|mode| mode.id.as_str()), Some("rail"));
+        assert_eq!(registry.mode_of(900).map(|mode| mode.id.as_str()), Some("tram"));
+        // Air service
+        assert_eq!(registry.mode_of(1100), None);
+        assert_eq!(registry.max_speed(1100).0, MAX_SPEED.0);
+    }
+
+    #[test]
+    fn test_configured_modes() {
+        let configured = |id: &str, route_types: Vec<u32>, max_speed: f64| Mode {
+            id: id.into(),
+            name: id.into(),
+            icon: None,
+            category: None,
+            route_types,
+            max_speed,
+            transfer_slack_minutes: 0,
+        };
+        let registry = ModeRegistry::new(&[
+            configured("bus", vec![3], 80.0),
+            configured("s-bahn", vec![109], 140.0),
+            configured("rocket", vec![1100], 10_000.0),
+        ]);
+
+        // The configured bus replaces the built-in one
+        assert_eq!(registry.modes().iter().filter(|mode| mode.id == "bus").count(), 1);
+        assert_eq!(registry.max_speed(3).0, 80.0);
+        assert_eq!(registry.mode_of(109).map(|mode| mode.id.as_str()), Some("s-bahn"));
+        assert_eq!(registry.mode_of(100).map(|mode| mode.id.as_str()), Some("rail"));
+        assert_eq!(registry.max_speed(1100).0, MAX_SPEED.0);
+    }
+}
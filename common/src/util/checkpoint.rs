@@ -0,0 +1,18 @@
+use crate::util::paths;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Makes the steps of preprocessing keep their results in [dir], so that a run that crashed can
+/// be resumed from the last completed step with `drino preprocess --resume`. Off by default, e.g.
+/// for tests that preprocess small timetables.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Directory for the results of completed steps of preprocessing, if checkpoints are enabled.
+/// Steps reuse what they find in here, so it is emptied when a run starts from the beginning.
+pub fn dir() -> Option<PathBuf> {
+    ENABLED.load(Ordering::Relaxed).then(|| paths::work_dir().join("checkpoints"))
+}
@@ -1,3 +1,4 @@
+use crate::types::config::Compression;
 use geoarrow::error::GeoArrowError;
 use geoarrow::io::ipc::write_ipc;
 use geoarrow::table::Table;
@@ -5,8 +6,11 @@ use itertools::Itertools;
 use polars::datatypes::AnyValue;
 use polars::error::{PolarsError, PolarsResult};
 use polars::frame::DataFrame;
-use polars::io::SerWriter;
-use polars::prelude::{col, CsvWriter, IntoLazy, IpcWriter, LazyFrame, ParquetWriter, SortMultipleOptions};
+use polars::io::{SerReader, SerWriter};
+use polars::prelude::{
+    col, CsvReader, CsvWriter, IntoLazy, IpcCompression, IpcReader, IpcWriter, LazyFrame, ParquetCompression,
+    ParquetReader, ParquetWriter, SortMultipleOptions, ZstdLevel,
+};
 use std::fs::{create_dir_all, File};
 use std::path::PathBuf;
 
@@ -68,7 +72,17 @@ pub enum FileType {
 pub fn write_df_to_file(
     path: PathBuf,
     format: FileType,
-    mut df: DataFrame
+    df: DataFrame
+) -> Result<(), PolarsError> {
+    write_df_to_file_compressed(path, format, df, Compression::default())
+}
+
+/// Writes a table with a specific compression. CSV files are never compressed.
+pub fn write_df_to_file_compressed(
+    path: PathBuf,
+    format: FileType,
+    mut df: DataFrame,
+    compression: Compression,
 ) -> Result<(), PolarsError> {
     let mut file = prepare_file(path)?;
 
@@ -78,11 +92,21 @@ pub fn write_df_to_file(
             Ok(())
         },
         FileType::IPC => {
-            IpcWriter::new(&mut file).finish(&mut df)?;
+            let compression = match compression {
+                Compression::None => None,
+                Compression::Lz4 => Some(IpcCompression::LZ4),
+                Compression::Zstd { .. } => Some(IpcCompression::ZSTD),
+            };
+            IpcWriter::new(&mut file).with_compression(compression).finish(&mut df)?;
             Ok(())
         },
         FileType::PARQUET => {
-            ParquetWriter::new(&mut file).finish(&mut df)?;
+            let compression = match compression {
+                Compression::None => ParquetCompression::Uncompressed,
+                Compression::Lz4 => ParquetCompression::Lz4Raw,
+                Compression::Zstd { level } => ParquetCompression::Zstd(level.map(ZstdLevel::try_new).transpose()?),
+            };
+            ParquetWriter::new(&mut file).with_compression(compression).finish(&mut df)?;
             Ok::<(), PolarsError>(())
         },
     }?;
@@ -90,6 +114,24 @@ pub fn write_df_to_file(
     Ok(())
 }
 
+/// Reads a table that was written with any compression, the codec is detected from the file
+pub fn read_df_from_file(path: PathBuf, format: FileType) -> Result<DataFrame, PolarsError> {
+    let file = File::open(path)?;
+
+    match format {
+        FileType::CSV => CsvReader::new(file).finish(),
+        FileType::IPC => IpcReader::new(file).finish(),
+        FileType::PARQUET => ParquetReader::new(file).finish(),
+    }
+}
+
+/// Reads an uncompressed IPC file without copying it into memory. The columns refer to the pages
+/// of the mapped file, which the OS loads on first access and shares between processes that read
+/// the same file.
+pub fn read_df_memory_mapped(path: PathBuf) -> Result<DataFrame, PolarsError> {
+    IpcReader::new(File::open(&path)?).memory_mapped(Some(path)).finish()
+}
+
 pub fn write_geoarrow_to_file(
     path: PathBuf,
     format: FileType,
@@ -120,4 +162,32 @@ fn prepare_file(
     let file = File::create(path)?;
     
     Ok(file)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_compression_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let frame = df!("stop_id" => [0u32, 1, 2], "name" => ["a", "b", "c"]).unwrap();
+
+        let compressions = [Compression::None, Compression::Lz4, Compression::Zstd { level: Some(19) }];
+        for (index, compression) in compressions.into_iter().enumerate() {
+            for extension in ["parquet", "arrow"] {
+                let format = || if extension == "parquet" { FileType::PARQUET } else { FileType::IPC };
+                let path = dir.path().join(format!("{index}.{extension}"));
+                write_df_to_file_compressed(path.clone(), format(), frame.clone(), compression).unwrap();
+                assert_eq!(read_df_from_file(path, format()).unwrap(), frame);
+            }
+        }
+
+        let path = dir.path().join("0.arrow");
+        assert_eq!(read_df_memory_mapped(path).unwrap(), frame);
+
+        let invalid = Compression::Zstd { level: Some(100) };
+        assert!(write_df_to_file_compressed(dir.path().join("invalid.parquet"), FileType::PARQUET, frame, invalid).is_err());
+    }
+}
@@ -1,3 +1,68 @@
 use chrono::Duration;
+use either::Either;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 
-pub const INFINITY: Duration = Duration::max_value();
\ No newline at end of file
+pub const INFINITY: Duration = Duration::max_value();
+
+/// Duration in whole seconds, as used in the config
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "SerializedSeconds")]
+pub struct Seconds(pub u64);
+
+/// Serialized representation of Seconds
+/// Either 90 (integer) or "90s", "15m", "2h", "1d" (String)
+#[derive(Debug, Deserialize, Clone)]
+#[serde(transparent)]
+struct SerializedSeconds {
+    #[serde(with = "either::serde_untagged")]
+    seconds: Either<u64, String>
+}
+
+#[derive(thiserror::Error, Debug)]
+pub struct SecondsError;
+
+impl Display for SecondsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Wrong duration format. Example of valid formats: 90s, 15m, 2h, 1d")
+    }
+}
+
+impl TryFrom<SerializedSeconds> for Seconds {
+    type Error = SecondsError;
+
+    fn try_from(value: SerializedSeconds) -> Result<Self, Self::Error> {
+        match value.seconds {
+            Either::Right(value) => {
+                let regex = Regex::new(r"^(\d+)\s*([smhd])?$").unwrap();
+                let caps = regex.captures(value.trim()).ok_or(SecondsError)?;
+
+                let number = u64::from_str(&caps[1]).map_err(|_| SecondsError)?;
+                let factor = match caps.get(2).map(|unit| unit.as_str()) {
+                    None | Some("s") => 1,
+                    Some("m") => 60,
+                    Some("h") => 60 * 60,
+                    Some("d") => 24 * 60 * 60,
+                    Some(_) => return Err(SecondsError),
+                };
+
+                Ok(Self(number * factor))
+            }
+            Either::Left(value) => Ok(Self(value))
+        }
+    }
+}
+
+impl From<Seconds> for std::time::Duration {
+    fn from(value: Seconds) -> Self {
+        std::time::Duration::from_secs(value.0)
+    }
+}
+
+impl Display for Seconds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}s", self.0)
+    }
+}
@@ -0,0 +1,146 @@
+use geo::Point;
+
+/// Mean earth radius in meters, as the one of [geo::Haversine]
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Points on the earth in columns, for measuring the distances from one point to many others in a
+/// single pass. Every point is kept as a vector on the unit sphere, so that the distance to it is
+/// the arc of the chord between the vectors. The chord only needs multiplications and additions,
+/// which the compiler turns into SIMD instructions, and comparing it against the chord of a
+/// maximum distance needs no trigonometry at all. The distances are the ones of
+/// [geo::Haversine], up to rounding.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PointColumns {
+    x: Vec<f64>,
+    y: Vec<f64>,
+    z: Vec<f64>,
+}
+
+impl PointColumns {
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    /// The point at `idx` as a vector on the unit sphere, see [on_unit_sphere]
+    pub fn vector(&self, idx: usize) -> Option<[f64; 3]> {
+        Some([*self.x.get(idx)?, *self.y.get(idx)?, *self.z.get(idx)?])
+    }
+
+    /// Distance in meters from the point to every point of the columns, in their order
+    pub fn distances_from(&self, point: Point<f64>) -> Vec<f64> {
+        self.squared_chords(point).map(arc).collect()
+    }
+
+    /// Distance in meters between the points at two indices, unless one of them is missing
+    pub fn distance_between(&self, a: usize, b: usize) -> Option<f64> {
+        Some(arc(squared_chord(self.vector(a)?, self.vector(b)?)))
+    }
+
+    /// Indices of the points that are at most `max_distance` meters away from the point
+    pub fn within(&self, point: Point<f64>, max_distance: f64) -> Vec<usize> {
+        let max_squared_chord = max_squared_chord(max_distance);
+        self.squared_chords(point)
+            .enumerate()
+            .filter(|(_, squared_chord)| *squared_chord <= max_squared_chord)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// The indices of `candidates` whose points are at most `max_distance` meters away from the
+    /// point at `idx`, e.g. of the ones that a spatial index found nearby. Indices that aren't in
+    /// the columns are left out.
+    pub fn within_among(&self, idx: usize, candidates: impl IntoIterator<Item = usize>, max_distance: f64) -> Vec<usize> {
+        let Some(vector) = self.vector(idx) else { return vec![] };
+        let max_squared_chord = max_squared_chord(max_distance);
+        candidates.into_iter()
+            .filter(|candidate| self.vector(*candidate).is_some_and(|other| squared_chord(vector, other) <= max_squared_chord))
+            .collect()
+    }
+
+    fn squared_chords(&self, point: Point<f64>) -> impl Iterator<Item = f64> + '_ {
+        let vector = on_unit_sphere(point);
+        self.x.iter().zip(&self.y).zip(&self.z).map(move |((x, y), z)| squared_chord(vector, [*x, *y, *z]))
+    }
+}
+
+fn squared_chord([ax, ay, az]: [f64; 3], [bx, by, bz]: [f64; 3]) -> f64 {
+    let (dx, dy, dz) = (ax - bx, ay - by, az - bz);
+    dx * dx + dy * dy + dz * dz
+}
+
+// The length in meters of the arc on the earth over the chord
+fn arc(squared_chord: f64) -> f64 {
+    2.0 * EARTH_RADIUS * (squared_chord.sqrt() / 2.0).min(1.0).asin()
+}
+
+fn max_squared_chord(max_distance: f64) -> f64 {
+    // The arc can't be longer than half of the circumference
+    let max_chord = 2.0 * (max_distance.clamp(0.0, EARTH_RADIUS * std::f64::consts::PI) / (2.0 * EARTH_RADIUS)).sin();
+    max_chord * max_chord
+}
+
+impl Extend<Point<f64>> for PointColumns {
+    fn extend<T: IntoIterator<Item = Point<f64>>>(&mut self, iter: T) {
+        for point in iter {
+            let [x, y, z] = on_unit_sphere(point);
+            self.x.push(x);
+            self.y.push(y);
+            self.z.push(z);
+        }
+    }
+}
+
+impl FromIterator<Point<f64>> for PointColumns {
+    fn from_iter<T: IntoIterator<Item = Point<f64>>>(iter: T) -> Self {
+        let mut columns = Self::default();
+        columns.extend(iter);
+        columns
+    }
+}
+
+/// The point as a vector on the unit sphere, with x as longitude and y as latitude like
+/// [geo::Haversine] reads it
+pub fn on_unit_sphere(point: Point<f64>) -> [f64; 3] {
+    let (lon, lat) = (point.x().to_radians(), point.y().to_radians());
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::{Distance, Haversine};
+
+    #[test]
+    fn test_distances_from() {
+        let stuttgart = Point::new(9.1829, 48.7758);
+        let points = [
+            stuttgart,
+            // Another platform of the same station
+            Point::new(9.1831, 48.7759),
+            Point::new(11.5820, 48.1351),
+            Point::new(-73.9857, 40.7484),
+            // The other side of the earth
+            Point::new(-170.8171, -48.7758),
+        ];
+        let columns: PointColumns = points.into_iter().collect();
+
+        let distances = columns.distances_from(stuttgart);
+        for (point, distance) in points.iter().zip(distances) {
+            let expected = Haversine::distance(stuttgart, *point);
+            assert!((distance - expected).abs() <= 1e-6 * expected.max(1.0), "{distance} != {expected}");
+        }
+
+        assert_eq!(columns.within(stuttgart, 100.0), [0, 1]);
+        assert_eq!(columns.within(stuttgart, 200_000.0), [0, 1, 2]);
+        assert_eq!(columns.within(stuttgart, f64::MAX).len(), 5);
+
+        let distance = columns.distance_between(0, 2).unwrap();
+        assert!((distance - Haversine::distance(stuttgart, points[2])).abs() <= 1e-6 * distance);
+        assert_eq!(columns.distance_between(0, 5), None);
+        assert_eq!(columns.within_among(0, [1, 2, 3, 5], 200_000.0), [1, 2]);
+    }
+}
@@ -1,24 +1,63 @@
+use crate::util::memory::PeakMemory;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use indicatif_log_bridge::LogWrapper;
-use log::{info, LevelFilter};
+use std::fs::File;
+use std::future::Future;
+use std::io;
+use std::io::{BufWriter, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
 use std::time::{Duration, SystemTime};
+// Targets of tracing events have to be known at compile time, these records reach the subscriber
+// through its bridge for the log crate
+use log::{debug, info};
+pub use tracing::level_filters::LevelFilter;
+use tracing_flame::{FlameLayer, FlushGuard};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
-static mut MULTI: Option<MultiProgress> = None;
+// Progress bars of all tasks, which log lines are written above
+static MULTI: OnceLock<MultiProgress> = OnceLock::new();
 
+fn multi_progress() -> &'static MultiProgress {
+    MULTI.get_or_init(MultiProgress::new)
+}
+
+/// Logs to stderr and records the peak memory of each span. With `flamegraph`, how long each span
+/// took is written to a file of folded stacks, e.g. for `inferno-flamegraph`. The file is complete
+/// once the returned guard is dropped.
+pub fn init(log_level: LevelFilter, flamegraph: Option<&Path>) -> Option<FlushGuard<BufWriter<File>>> {
+    let filter = EnvFilter::builder()
+        .with_default_directive(log_level.into())
+        .from_env_lossy(); // Allow overriding log level through RUST_LOG env var
 
-pub fn init(log_level: LevelFilter) {
-    let logger = env_logger::builder()
-        .filter_level(log_level)
-        .parse_default_env() // Allow overriding log level through RUST_LOG env var
-        .build();
+    let writer = || AboveProgressBars(multi_progress().clone());
+    let fmt = tracing_subscriber::fmt::layer().with_writer(writer).with_filter(filter);
 
-    let multi = MultiProgress::new();
+    let (flame, guard) = match flamegraph {
+        Some(path) => {
+            let (flame, guard) = FlameLayer::with_file(path).expect("Unable to create flamegraph file");
+            (Some(flame), Some(guard))
+        }
+        None => (None, None),
+    };
 
-    let wrapper = LogWrapper::new(multi.clone(), logger);
-    wrapper.try_init().unwrap();
+    tracing_subscriber::registry().with(fmt).with(flame).with(PeakMemory).try_init().unwrap();
+
+    guard
+}
 
-    unsafe {
-        MULTI = Some(multi);
+/// Writes log lines while the progress bars are hidden, so that they don't jump around
+struct AboveProgressBars(MultiProgress);
+
+impl Write for AboveProgressBars {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
     }
 }
 
@@ -29,56 +68,162 @@ pub fn run_with_spinner<'a, F, Out>(
     F: FnOnce() -> Out,
 {
     let start_time = SystemTime::now();
+    let pb = start_spinner(task_desc);
+
+    let out = function();
+
+    finish_spinner(target, task_desc, pb, start_time);
+
+    out
+}
+
+/// Same as [run_with_spinner], but for tasks that run on the async runtime
+pub async fn run_with_spinner_async<'a, F, Out>(
+    target: &'a str, task_desc: &'a str, future: F,
+) -> Out where
+    F: Future<Output = Out>,
+{
+    let start_time = SystemTime::now();
+    let pb = start_spinner(task_desc);
+
+    let out = future.await;
+
+    finish_spinner(target, task_desc, pb, start_time);
 
+    out
+}
+
+fn start_spinner(task_desc: &str) -> ProgressBar {
     let pb = ProgressBar::new_spinner()
         .with_message(format!("{}...", task_desc))
         .with_style(ProgressStyle::with_template("{spinner:.white} [{elapsed:.green}] {msg}").unwrap());
     pb.enable_steady_tick(Duration::from_millis(100));
 
     // Set up connection with log library so that progress bars don't jump around
-    unsafe {
-        MULTI.clone().unwrap().add(pb.clone());
-    };
+    multi_progress().add(pb.clone());
 
-    let out = function();
+    pb
+}
 
+fn finish_spinner(target: &str, task_desc: &str, pb: ProgressBar, start_time: SystemTime) {
     pb.finish_and_clear();
-    unsafe { MULTI.clone().unwrap().remove(&pb); };
-    
+    multi_progress().remove(&pb);
+
     let elapsed = indicatif::HumanDuration(start_time.elapsed().unwrap());
     info!(target: target, "{} finished (took {})", task_desc, elapsed);
+}
 
-    out
+/// Where long-running tasks report their progress to. Steps may be reported from several threads
+/// at once, e.g. from a parallel iterator.
+pub trait ProgressSink: Send + Sync {
+    /// Starts a task that takes `total` steps. Dropping the returned task finishes it.
+    fn start(&self, task_desc: &str, total: u64) -> Box<dyn ProgressTask>;
 }
 
-pub fn run_with_pb<'a, F, Out>(
-    target: &'a str, task_desc: &'a str, total: u64, print_message: bool, function: F,
-) -> Out where
-    F: FnOnce(ProgressBar) -> Out,
-{
-    let start_time = SystemTime::now();
-    
-    let pb = ProgressBar::new(total)
-        .with_message(format!("{}...", task_desc))
-        .with_style(
-            ProgressStyle::with_template("[{elapsed:.green}] {msg} [{wide_bar:.cyan/blue}] {human_pos}/{human_len} [{eta}]")
-                .unwrap().progress_chars("=> ")
+pub trait ProgressTask: Send + Sync {
+    fn inc(&self, steps: u64);
+}
+
+/// Shows the progress of tasks as bars in the terminal, below the log output. If the log output
+/// is not a terminal, e.g. when it's collected from a container, the progress is logged instead.
+pub struct ProgressBars {
+    pub target: &'static str,
+}
+
+struct ProgressBarTask {
+    target: &'static str,
+    task_desc: String,
+    pb: ProgressBar,
+    start_time: SystemTime,
+}
+
+// Reports the progress of a task as a log line whenever another tenth of it is done
+struct ProgressLogTask {
+    target: &'static str,
+    task_desc: String,
+    total: u64,
+    done: AtomicU64,
+    start_time: SystemTime,
+}
+
+impl ProgressSink for ProgressBars {
+    fn start(&self, task_desc: &str, total: u64) -> Box<dyn ProgressTask> {
+        if !io::stderr().is_terminal() {
+            return Box::new(ProgressLogTask {
+                target: self.target,
+                task_desc: task_desc.to_string(),
+                total,
+                done: AtomicU64::new(0),
+                start_time: SystemTime::now(),
+            });
+        }
+
+        let pb = ProgressBar::new(total)
+            .with_message(format!("{}...", task_desc))
+            .with_style(
+                ProgressStyle::with_template("[{elapsed:.green}] {msg} [{wide_bar:.cyan/blue}] {human_pos}/{human_len} [{eta}]")
+                    .unwrap().progress_chars("=> ")
+            );
+        pb.enable_steady_tick(Duration::from_secs(1));
+
+        multi_progress().add(pb.clone());
+
+        Box::new(ProgressBarTask { target: self.target, task_desc: task_desc.to_string(), pb, start_time: SystemTime::now() })
+    }
+}
+
+impl ProgressTask for ProgressBarTask {
+    fn inc(&self, steps: u64) {
+        self.pb.inc(steps);
+    }
+}
+
+impl Drop for ProgressBarTask {
+    fn drop(&mut self) {
+        self.pb.finish_and_clear();
+        multi_progress().remove(&self.pb);
+
+        // Tasks can be nested and run many times, e.g. once per cluster, so only log this in debug
+        let elapsed = indicatif::HumanDuration(self.start_time.elapsed().unwrap());
+        debug!(target: self.target, "{} finished (took {})", self.task_desc, elapsed);
+    }
+}
+
+impl ProgressTask for ProgressLogTask {
+    fn inc(&self, steps: u64) {
+        let done = self.done.fetch_add(steps, Ordering::Relaxed) + steps;
+        let tenths = |done: u64| done * 10 / self.total.max(1);
+        if done >= self.total || tenths(done) == tenths(done - steps) {
+            return;
+        }
+
+        // Assumes that the remaining steps take as long as the ones so far
+        let elapsed = self.start_time.elapsed().unwrap();
+        let left = elapsed.mul_f64((self.total - done) as f64 / done as f64);
+        info!(
+            target: self.target,
+            "{}: {}/{} ({}%), about {} left",
+            self.task_desc, done, self.total, tenths(done) * 10, indicatif::HumanDuration(left),
         );
-    pb.enable_steady_tick(Duration::from_secs(1));
-    
-    unsafe {
-        MULTI.clone().unwrap().add(pb.clone());
-    };
-    
-    let out = function(pb.clone());
-    
-    pb.finish_and_clear();
-    unsafe { MULTI.clone().unwrap().remove(&pb); };
-    
-    if print_message {
-        let elapsed = indicatif::HumanDuration(start_time.elapsed().unwrap());
-        info!(target: target, "{} finished (took {})", task_desc, elapsed);
     }
-    
-    out
-}
\ No newline at end of file
+}
+
+impl Drop for ProgressLogTask {
+    fn drop(&mut self) {
+        let elapsed = indicatif::HumanDuration(self.start_time.elapsed().unwrap());
+        debug!(target: self.target, "{} finished (took {})", self.task_desc, elapsed);
+    }
+}
+
+/// Discards all progress, e.g. in tests where logging is not initialized
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn start(&self, _: &str, _: u64) -> Box<dyn ProgressTask> {
+        Box::new(NoProgress)
+    }
+}
+
+impl ProgressTask for NoProgress {
+    fn inc(&self, _: u64) {}
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::Duration;
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+// How often the resident memory is read while spans of drino are open. Reading it is a single
+// small file read on Linux, so this is cheap compared to the steps that are measured.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(200);
+
+// Spans that are open, with the highest resident memory that was sampled since they were opened
+static OPEN: Mutex<Vec<(Id, &'static str, Arc<AtomicU64>)>> = Mutex::new(Vec::new());
+// Highest resident memory of every closed span by its name, e.g. of all "dataset" spans together
+static PEAKS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+static SAMPLER: Once = Once::new();
+
+/// Resident memory of this process in bytes, or none on platforms other than Linux
+pub fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status.lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim().strip_suffix("kB")?
+        .trim().parse::<u64>().ok()?;
+    Some(kilobytes * 1024)
+}
+
+/// Records the peak resident memory of the spans of drino, e.g. of the steps of preprocessing.
+/// Memory is sampled in the background, so peaks between two samples might be missed.
+pub struct PeakMemory;
+
+impl<S: Subscriber> Layer<S> for PeakMemory {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _: Context<'_, S>) {
+        let metadata = attrs.metadata();
+        if !metadata.target().starts_with("drino") {
+            return;
+        }
+        let Some(current) = resident_memory() else {
+            return;
+        };
+
+        SAMPLER.call_once(|| {
+            thread::spawn(|| loop {
+                thread::sleep(SAMPLE_INTERVAL);
+                sample();
+            });
+        });
+
+        lock(&OPEN).push((id.clone(), metadata.name(), Arc::new(AtomicU64::new(current))));
+    }
+
+    fn on_close(&self, id: Id, _: Context<'_, S>) {
+        sample();
+
+        let mut open = lock(&OPEN);
+        let Some(index) = open.iter().position(|(open_id, _, _)| *open_id == id) else {
+            return;
+        };
+        let (_, name, peak) = open.swap_remove(index);
+        drop(open);
+
+        let peak = peak.load(Ordering::Relaxed);
+        lock(&PEAKS).entry(name).and_modify(|max| *max = (*max).max(peak)).or_insert(peak);
+    }
+}
+
+// Raises the peaks of all open spans to the current resident memory
+fn sample() {
+    let Some(current) = resident_memory() else {
+        return;
+    };
+    lock(&OPEN).iter().for_each(|(_, _, peak)| {
+        peak.fetch_max(current, Ordering::Relaxed);
+    });
+}
+
+/// Peak resident memory in bytes of all spans closed since the last call, by their name
+pub fn take_peaks() -> BTreeMap<&'static str, u64> {
+    std::mem::take(&mut *lock(&PEAKS))
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_peak_memory() {
+        assert!(resident_memory().unwrap() > 0);
+
+        let subscriber = tracing_subscriber::registry().with(PeakMemory);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("test_step").entered();
+            // Touch the memory, so that it is resident
+            let buffer = vec![1u8; 64 << 20];
+            assert_eq!(buffer.iter().map(|byte| *byte as u64).sum::<u64>(), 64 << 20);
+            drop(span);
+        });
+
+        assert!(take_peaks()["test_step"] >= 64 << 20);
+    }
+}
@@ -0,0 +1,124 @@
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Relative change of a metric between two builds that is reported as a drift
+pub const MAX_DRIFT: f64 = 0.2;
+
+// Metrics of the current build, recorded by the different preprocessing steps
+static METRICS: Mutex<BTreeMap<String, f64>> = Mutex::new(BTreeMap::new());
+
+/// Records the value of a quality metric of the current build, replacing an earlier value
+pub fn record(name: &str, value: f64) {
+    METRICS.lock().unwrap_or_else(|err| err.into_inner()).insert(name.to_string(), value);
+}
+
+/// All metrics recorded since the last call, ordered by name
+pub fn take() -> BTreeMap<String, f64> {
+    std::mem::take(&mut *METRICS.lock().unwrap_or_else(|err| err.into_inner()))
+}
+
+/// A metric that changed by more than [MAX_DRIFT] since the previous build
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    pub metric: String,
+    pub previous: f64,
+    pub current: f64,
+}
+
+impl Drift {
+    /// Change relative to the previous value, e.g. -0.5 if it halved
+    pub fn change(&self) -> f64 {
+        (self.current - self.previous) / self.previous.abs()
+    }
+}
+
+/// Appends the metrics of a build to the history at `path` and returns the ones that drifted
+/// since the latest build that recorded them. The history is a CSV file with one row per metric
+/// and build (columns "timestamp", "metric", "value"), which is created if it doesn't exist.
+pub fn append_to_history(
+    path: &Path,
+    timestamp: DateTime<Utc>,
+    metrics: &BTreeMap<String, f64>,
+) -> std::io::Result<Vec<Drift>> {
+    let history = match std::fs::read_to_string(path) {
+        Ok(history) => history,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+
+    // Rows are appended in chronological order, so later rows overwrite earlier ones
+    let previous: BTreeMap<&str, f64> = history.lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut columns = line.splitn(3, ',').skip(1);
+            Some((columns.next()?, columns.next()?.parse().ok()?))
+        })
+        .collect();
+
+    let drifts = metrics.iter()
+        .filter_map(|(metric, current)| {
+            let previous = *previous.get(metric.as_str())?;
+            let drift = Drift { metric: metric.clone(), previous, current: *current };
+            let drifted = if previous == 0.0 { *current != 0.0 } else { drift.change().abs() > MAX_DRIFT };
+            drifted.then_some(drift)
+        })
+        .collect();
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if history.is_empty() {
+        writeln!(file, "timestamp,metric,value")?;
+    }
+    for (metric, value) in metrics {
+        writeln!(file, "{},{},{}", timestamp.to_rfc3339(), metric, value)?;
+    }
+
+    Ok(drifts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn test_append_to_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("quality").join("history.csv");
+        let first_build = DateTime::<Utc>::UNIX_EPOCH;
+
+        let metrics = BTreeMap::from([("stops".to_string(), 100.0), ("trips_per_day".to_string(), 50.0)]);
+        assert!(append_to_history(&path, first_build, &metrics).unwrap().is_empty());
+
+        // Stops only changed slightly, but half of the trips are gone
+        let metrics = BTreeMap::from([
+            ("stops".to_string(), 110.0),
+            ("trips_per_day".to_string(), 25.0),
+            ("mean_transfers".to_string(), 1.5),
+        ]);
+        let drifts = append_to_history(&path, first_build + TimeDelta::days(1), &metrics).unwrap();
+        assert_eq!(drifts, [Drift { metric: "trips_per_day".into(), previous: 50.0, current: 25.0 }]);
+        assert_eq!(drifts[0].change(), -0.5);
+
+        // Compared to the latest build only
+        let metrics = BTreeMap::from([("trips_per_day".to_string(), 26.0)]);
+        assert!(append_to_history(&path, first_build + TimeDelta::days(2), &metrics).unwrap().is_empty());
+
+        let history = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(history.lines().count(), 7);
+    }
+
+    #[test]
+    fn test_record() {
+        record("test_metric", 1.0);
+        record("test_metric", 2.0);
+        assert_eq!(take().get("test_metric"), Some(&2.0));
+        assert_eq!(take().get("test_metric"), None);
+    }
+}
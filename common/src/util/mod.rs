@@ -1,7 +1,20 @@
+#[cfg(feature = "preprocessing")]
 pub mod logging;
 pub mod speed;
 pub mod time;
 pub mod duration;
+#[cfg(feature = "preprocessing")]
+pub mod checkpoint;
+#[cfg(feature = "preprocessing")]
 pub mod df;
 pub mod distance;
-pub mod geoarrow_lines;
\ No newline at end of file
+pub mod haversine;
+#[cfg(feature = "preprocessing")]
+pub mod geoarrow_lines;
+pub mod paths;
+pub mod size;
+pub mod metrics;
+#[cfg(feature = "preprocessing")]
+pub mod memory;
+#[cfg(feature = "preprocessing")]
+pub mod spill;
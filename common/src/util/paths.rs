@@ -0,0 +1,84 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static WORK_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets the directory in which all datasets, temporary files and preprocessing results are
+/// stored. Must be called before any of the other functions, later calls are ignored.
+pub fn init(work_dir: PathBuf) {
+    let _ = WORK_DIR.set(work_dir);
+}
+
+/// The working directory. Falls back to the platform default if [init] was not called.
+pub fn work_dir() -> &'static Path {
+    WORK_DIR.get_or_init(default_work_dir)
+}
+
+/// Directory for downloaded and imported datasets
+pub fn datasets_dir() -> PathBuf {
+    work_dir().join("datasets")
+}
+
+/// Directory for intermediate results of the preprocessing
+pub fn tmp_dir() -> PathBuf {
+    tmp_dir_in(work_dir())
+}
+
+/// Directory for the results of the preprocessing of routing algorithms
+pub fn preprocessing_dir() -> PathBuf {
+    preprocessing_dir_in(work_dir())
+}
+
+/// Directory for responses of external services that are reused by later preprocessing runs
+pub fn cache_dir() -> PathBuf {
+    work_dir().join("cache")
+}
+
+/// [tmp_dir] of another working directory than the one of this instance
+pub fn tmp_dir_in(work_dir: &Path) -> PathBuf {
+    work_dir.join("tmp")
+}
+
+/// [preprocessing_dir] of another working directory than the one of this instance
+pub fn preprocessing_dir_in(work_dir: &Path) -> PathBuf {
+    work_dir.join("preprocessing")
+}
+
+/// Default working directory following the conventions of the platform:
+/// - Linux: `$XDG_DATA_HOME/drino` or `~/.local/share/drino`
+/// - macOS: `~/Library/Application Support/drino`
+/// - Windows: `%LOCALAPPDATA%\drino`
+///
+/// If none of these can be determined, `./data` is used.
+pub fn default_work_dir() -> PathBuf {
+    let platform_dir = if cfg!(target_os = "windows") {
+        env::var_os("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))
+    };
+
+    match platform_dir {
+        Some(dir) => dir.join("drino"),
+        None => PathBuf::from(".").join("data"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subdirectories_are_in_work_dir() {
+        assert!(datasets_dir().starts_with(work_dir()));
+        assert!(tmp_dir().starts_with(work_dir()));
+        assert!(preprocessing_dir().starts_with(work_dir()));
+        assert!(cache_dir().starts_with(work_dir()));
+        assert!(default_work_dir().ends_with("drino") || default_work_dir().ends_with("data"));
+    }
+}
@@ -0,0 +1,84 @@
+use either::Either;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Size in bytes
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(try_from = "SerializedByteSize")]
+pub struct ByteSize(pub u64);
+
+/// Serialized representation of a ByteSize
+/// Either 1048576 (integer) or "1MiB", "500MB", "2 GB" (String)
+#[derive(Debug, Deserialize, Clone)]
+#[serde(transparent)]
+struct SerializedByteSize {
+    #[serde(with = "either::serde_untagged")]
+    bytes: Either<u64, String>
+}
+
+#[derive(thiserror::Error, Debug)]
+pub struct ByteSizeError;
+
+impl Display for ByteSizeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Wrong size format. Example of valid formats: 500MB, 2GiB, 1048576")
+    }
+}
+
+impl TryFrom<SerializedByteSize> for ByteSize {
+    type Error = ByteSizeError;
+
+    fn try_from(value: SerializedByteSize) -> Result<Self, Self::Error> {
+        match value.bytes {
+            Either::Right(value) => ByteSize::from_str(&value),
+            Either::Left(value) => Ok(Self(value))
+        }
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = ByteSizeError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let regex = Regex::new(r"^(\d+)\s*([KMGT]i?B|B)?$").unwrap();
+        let caps = regex.captures(value.trim()).ok_or(ByteSizeError)?;
+
+        let number = u64::from_str(&caps[1]).map_err(|_| ByteSizeError)?;
+        let factor: u64 = match caps.get(2).map(|unit| unit.as_str()) {
+            None | Some("B") => 1,
+            Some("KB") => 1_000,
+            Some("MB") => 1_000_000,
+            Some("GB") => 1_000_000_000,
+            Some("TB") => 1_000_000_000_000,
+            Some("KiB") => 1 << 10,
+            Some("MiB") => 1 << 20,
+            Some("GiB") => 1 << 30,
+            Some("TiB") => 1 << 40,
+            Some(_) => return Err(ByteSizeError),
+        };
+
+        number.checked_mul(factor).map(Self).ok_or(ByteSizeError)
+    }
+}
+
+impl Display for ByteSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}B", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(ByteSize::from_str("42").unwrap(), ByteSize(42));
+        assert_eq!(ByteSize::from_str("500MB").unwrap(), ByteSize(500_000_000));
+        assert_eq!(ByteSize::from_str("2 GiB").unwrap(), ByteSize(2 << 30));
+        assert!(ByteSize::from_str("2 apples").is_err());
+        assert!(ByteSize::from_str("-1B").is_err());
+    }
+}
@@ -3,14 +3,23 @@ use chrono::{Duration, TimeDelta};
 #[derive(Copy, Clone)]
 pub struct Speed(pub f64); // in km/h
 
+// The maximum speed in km/h that any vehicle can travel
+// This must be high enough, otherwise wrong routes might be calculated
+pub const MAX_SPEED: Speed = Speed(500.0);
 pub const MAX_WALKING_SPEED: Speed = Speed(7f64);
 pub const MAX_WALKING_DURATION: Duration = Duration::minutes(15);
+// What a casual cyclist averages, including getting on and off the bike
+pub const CYCLING_SPEED: Speed = Speed(15f64);
 
 impl Speed {
     pub fn time_to_travel_distance(&self, meters: f32) -> Duration {
         let hours = (1.0 / self.0) * (meters as f64 / 1_000.0);
         TimeDelta::milliseconds((hours * 60.0 * 60.0 * 1_000.0) as i64)
     }
+
+    pub fn distance_in(&self, duration: Duration) -> f32 {
+        (self.0 * 1_000.0 * duration.num_milliseconds() as f64 / (60.0 * 60.0 * 1_000.0)) as f32
+    }
 }
 
 #[cfg(test)]
@@ -21,5 +30,6 @@ mod tests {
     fn test_speed_to_distance() {
         assert_eq!(Duration::seconds(36), Speed(10.0).time_to_travel_distance(100.));
         assert_eq!(Duration::seconds(18), Speed(200.0).time_to_travel_distance(1_000.));
+        assert_eq!(100., Speed(10.0).distance_in(Duration::seconds(36)));
     }
 }
\ No newline at end of file
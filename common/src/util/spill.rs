@@ -0,0 +1,100 @@
+use crate::util::df::{write_df_to_file, FileType};
+use crate::util::memory::resident_memory;
+use crate::util::paths;
+use crate::util::size::ByteSize;
+use polars::error::PolarsResult;
+use polars::prelude::{IntoLazy, LazyFrame};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Keeps the names of spilled files unique, also between threads and directories
+static NEXT_FILE: AtomicUsize = AtomicUsize::new(0);
+
+/// The memory budget of preprocessing and where to write what doesn't fit into it. Once the
+/// resident memory exceeds the budget, intermediate tables and partial transfer patterns are
+/// written to temporary files in the directory instead of being kept in memory. Without a budget,
+/// or on platforms where the resident memory isn't known, everything stays in memory.
+#[derive(Debug, Clone)]
+pub struct Spill {
+    budget: Option<ByteSize>,
+    dir: PathBuf,
+}
+
+impl Spill {
+    pub fn new(budget: Option<ByteSize>, dir: PathBuf) -> Self {
+        Self { budget, dir }
+    }
+
+    /// The budget, spilling to the temporary directory of the working directory
+    pub fn in_work_dir(budget: Option<ByteSize>) -> Self {
+        Self::new(budget, paths::tmp_dir().join("spill"))
+    }
+
+    /// Whether the resident memory is above the budget
+    pub fn exceeded(&self) -> bool {
+        self.budget.is_some_and(|budget| resident_memory().is_some_and(|current| current > budget.0))
+    }
+
+    /// Directory of the spilled files, which [Spill::clean_up] removes
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// A new file in [Spill::dir], named after what is spilled to it
+    pub fn file(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{name}-{}.parquet", NEXT_FILE.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    /// The table collected into memory, or scanned from a temporary file if the budget is
+    /// exceeded. Steps that read the table several times then read it from disk, instead of
+    /// keeping it in memory in between. The file is written by the streaming engine, so the table
+    /// is never held in memory as a whole, unless parts of the query can't be streamed.
+    pub fn cache(&self, frame: LazyFrame, name: &str) -> PolarsResult<LazyFrame> {
+        if !self.exceeded() {
+            return Ok(frame.collect()?.lazy());
+        }
+
+        let path = self.file(name);
+        std::fs::create_dir_all(&self.dir)?;
+        if frame.clone().sink_parquet(&path, Default::default()).is_err() {
+            write_df_to_file(path.clone(), FileType::PARQUET, frame.collect()?)?;
+        }
+        LazyFrame::scan_parquet(path, Default::default())
+    }
+
+    /// Removes the spilled files, once preprocessing doesn't read them anymore
+    pub fn clean_up(&self) -> std::io::Result<()> {
+        match self.dir.exists() {
+            true => std::fs::remove_dir_all(&self.dir),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[test]
+    fn test_cache() {
+        let stops = df!("stop_id" => [0u32, 1, 2]).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        let spill = Spill::new(None, dir.path().join("spill"));
+        assert!(!spill.exceeded());
+        assert!(spill.cache(stops.clone().lazy(), "stops").unwrap().collect().unwrap().equals(&stops));
+        assert!(!spill.dir().exists());
+
+        // Any process is above a budget of zero bytes
+        #[cfg(target_os = "linux")] {
+            let spill = Spill::new(Some(ByteSize(0)), dir.path().join("spill"));
+            assert!(spill.exceeded());
+            assert!(spill.cache(stops.clone().lazy(), "stops").unwrap().collect().unwrap().equals(&stops));
+            assert_eq!(std::fs::read_dir(spill.dir()).unwrap().count(), 1);
+
+            spill.clean_up().unwrap();
+            assert!(!spill.dir().exists());
+        }
+    }
+}
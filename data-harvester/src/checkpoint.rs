@@ -0,0 +1,190 @@
+use crate::step2_import_data::ImportStepExtra;
+use crate::step3_validate_data::ValidateStepOutput;
+use crate::step4_merge_data::DatasetMergeOutput;
+use common::types::dataset::Dataset;
+use common::util::df::{write_df_to_file, FileType};
+use polars::error::PolarsError;
+use polars::frame::DataFrame;
+use polars::prelude::{IntoLazy, LazyFrame};
+use std::fmt;
+use std::fmt::Display;
+use std::path::Path;
+
+// Marks a dataset that was left out of merging due to validation errors
+const SKIPPED: &str = "skipped";
+
+/// Writes the imported and validated datasets, so that [read_validated] can continue with merging
+/// them. Datasets are stored by their position in the config, since ids aren't valid file names
+/// everywhere.
+pub fn write_validated(validated: &[ValidateStepOutput], dir: &Path) -> Result<(), CheckpointError> {
+    for (idx, data) in validated.iter().enumerate() {
+        let dataset_dir = dir.join(idx.to_string());
+        if data.skip {
+            std::fs::create_dir_all(&dataset_dir)?;
+            std::fs::write(dataset_dir.join(SKIPPED), "")?;
+            continue;
+        }
+
+        let ImportStepExtra::Gtfs { agencies, routes, booking_rules, calendar, stops, trips, stop_times, frequencies, transfers, .. } = &data.extra;
+        write_tables(&dataset_dir, [
+            ("agencies", agencies), ("routes", routes), ("booking_rules", booking_rules), ("calendar", calendar),
+            ("stops", stops), ("trips", trips), ("stop_times", stop_times), ("frequencies", frequencies),
+            ("transfers", transfers),
+        ])?;
+    }
+    Ok(())
+}
+
+/// The datasets written by [write_validated], which must be the datasets of the config in the
+/// same order. Their validation reports were written by the run that validated them, so they
+/// come without violations.
+pub fn read_validated(datasets: Vec<Dataset>, dir: &Path) -> Result<Vec<ValidateStepOutput>, CheckpointError> {
+    datasets.into_iter().enumerate()
+        .map(|(idx, dataset)| {
+            let dir = dir.join(idx.to_string());
+            let skip = dir.join(SKIPPED).exists();
+            let extra = ImportStepExtra::Gtfs {
+                agencies: read_table(&dir, "agencies")?,
+                routes: read_table(&dir, "routes")?,
+                booking_rules: read_table(&dir, "booking_rules")?,
+                calendar: read_table(&dir, "calendar")?,
+                stops: read_table(&dir, "stops")?,
+                trips: read_table(&dir, "trips")?,
+                stop_times: read_table(&dir, "stop_times")?,
+                frequencies: read_table(&dir, "frequencies")?,
+                transfers: read_table(&dir, "transfers")?,
+                temporary_files: vec![],
+            };
+            Ok(ValidateStepOutput { dataset, extra, violations: vec![], skip })
+        })
+        .collect()
+}
+
+/// Writes the merged datasets, so that [read_merged] can continue with simplifying them
+pub fn write_merged(merged: &DatasetMergeOutput, dir: &Path) -> Result<(), CheckpointError> {
+    write_tables(dir, [
+        ("agencies", &merged.agencies), ("routes", &merged.routes), ("booking_rules", &merged.booking_rules),
+        ("services", &merged.services), ("stops", &merged.stops), ("trips", &merged.trips),
+        ("stop_times", &merged.stop_times), ("frequencies", &merged.frequencies),
+        ("duplicate_trips", &merged.duplicate_trips), ("stop_duplicates", &merged.stop_duplicates),
+        ("transfers", &merged.transfers), ("dataset_transfers", &merged.dataset_transfers),
+    ])
+}
+
+/// The merged datasets written by [write_merged]. Simplifying doesn't need the imported data of
+/// the single datasets, so they are left out.
+pub fn read_merged(dir: &Path) -> Result<DatasetMergeOutput, CheckpointError> {
+    Ok(DatasetMergeOutput {
+        agencies: read_table(dir, "agencies")?,
+        routes: read_table(dir, "routes")?,
+        booking_rules: read_table(dir, "booking_rules")?,
+        services: read_table(dir, "services")?,
+        stops: read_table(dir, "stops")?,
+        trips: read_table(dir, "trips")?,
+        stop_times: read_table(dir, "stop_times")?,
+        frequencies: read_table(dir, "frequencies")?,
+        duplicate_trips: read_table(dir, "duplicate_trips")?,
+        stop_duplicates: read_table(dir, "stop_duplicates")?,
+        transfers: read_table(dir, "transfers")?,
+        dataset_transfers: read_table(dir, "dataset_transfers")?,
+        import_extras: vec![],
+    })
+}
+
+fn write_tables<'a>(dir: &Path, tables: impl IntoIterator<Item = (&'a str, &'a LazyFrame)>) -> Result<(), CheckpointError> {
+    std::fs::create_dir_all(dir)?;
+    for (name, table) in tables {
+        let table = table.clone().collect()?;
+        // Tables without columns, e.g. of optional files that a dataset doesn't have, can't be
+        // written as parquet and are read as empty tables again
+        if table.width() > 0 {
+            write_df_to_file(dir.join(format!("{name}.parquet")), FileType::PARQUET, table)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_table(dir: &Path, name: &str) -> Result<LazyFrame, PolarsError> {
+    let path = dir.join(format!("{name}.parquet"));
+    match path.exists() {
+        true => LazyFrame::scan_parquet(path, Default::default()),
+        false => Ok(DataFrame::empty().lazy()),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CheckpointError {
+    Polars(#[from] PolarsError),
+    IO(#[from] std::io::Error),
+    Json(#[from] serde_json::Error),
+}
+
+impl Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let err: &dyn Display = match self {
+            CheckpointError::Polars(err) => err,
+            CheckpointError::IO(err) => err,
+            CheckpointError::Json(err) => err,
+        };
+        write!(f, "{}", err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::dataset::{DataSource, DatasetFormat};
+    use polars::df;
+
+    fn dataset(id: &str) -> Dataset {
+        Dataset {
+            id: id.into(),
+            src: DataSource::File { path: "".into() },
+            format: DatasetFormat::Gtfs,
+            license: None,
+            group_ids: vec![],
+            validation: Default::default(),
+            bounds: None,
+            drop_implausible_stops: false,
+            fix: false,
+            overrides: vec![],
+            vehicle_positions: None,
+            trip_updates: None,
+            alerts: None,
+            credentials: None,
+            refresh: None,
+            filter: Default::default(),
+            attribution: None,
+        }
+    }
+
+    #[test]
+    fn test_validated() {
+        let dir = tempfile::tempdir().unwrap();
+        let stops = df!("stop_id" => ["a", "b"], "stop_lat" => [48.78f32, 48.80]).unwrap();
+        let extra = |stops: LazyFrame| ImportStepExtra::Gtfs {
+            agencies: DataFrame::empty().lazy(),
+            routes: DataFrame::empty().lazy(),
+            booking_rules: DataFrame::empty().lazy(),
+            calendar: DataFrame::empty().lazy(),
+            stops,
+            trips: DataFrame::empty().lazy(),
+            stop_times: DataFrame::empty().lazy(),
+            frequencies: DataFrame::empty().lazy(),
+            transfers: DataFrame::empty().lazy(),
+            temporary_files: vec![],
+        };
+        let validated = vec![
+            ValidateStepOutput { dataset: dataset("a"), extra: extra(stops.clone().lazy()), violations: vec![], skip: false },
+            ValidateStepOutput { dataset: dataset("b"), extra: extra(DataFrame::empty().lazy()), violations: vec![], skip: true },
+        ];
+
+        write_validated(&validated, dir.path()).unwrap();
+        let read = read_validated(vec![dataset("a"), dataset("b")], dir.path()).unwrap();
+
+        assert_eq!(read.iter().map(|data| data.skip).collect::<Vec<_>>(), [false, true]);
+        let ImportStepExtra::Gtfs { stops: read_stops, calendar, .. } = &read[0].extra;
+        assert!(read_stops.clone().collect().unwrap().equals(&stops));
+        assert_eq!(calendar.clone().collect().unwrap().width(), 0);
+    }
+}
@@ -0,0 +1,342 @@
+use common::util::df::{write_df_to_file, FileType};
+use common::util::paths;
+use geo::{Contains, Geometry, GeometryCollection, MultiPolygon, Point};
+use geojson::GeoJson;
+use polars::datatypes::DataType;
+use polars::error::PolarsError;
+use polars::frame::DataFrame;
+use polars::prelude::{as_struct, col, concat, Column, IntoLazy, JoinArgs, JoinType, LazyFrame, UnionArgs, UniqueKeepStrategy};
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Reads the region to crop to from a GeoJSON file. All polygons of the file are part of the
+/// region, other geometries are ignored.
+pub fn read_region(path: &Path) -> Result<MultiPolygon<f64>, CropError> {
+    let geojson: GeoJson = fs::read_to_string(path)?.parse()?;
+    let geometries: GeometryCollection<f64> = geojson::quick_collection(&geojson)?;
+
+    let polygons: Vec<_> = geometries.into_iter()
+        .flat_map(|geometry| match geometry {
+            Geometry::Polygon(polygon) => vec![polygon],
+            Geometry::MultiPolygon(polygons) => polygons.0,
+            _ => vec![],
+        })
+        .collect();
+
+    match polygons.is_empty() {
+        true => Err(CropError::NoPolygon),
+        false => Ok(MultiPolygon(polygons)),
+    }
+}
+
+/// Size of a cropped bundle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropSummary {
+    pub stops: u32,
+    pub trips: u32,
+    // Lines of the direct connections of all clusters
+    pub lines: u32,
+}
+
+/// Copies the parts of a preprocessed working directory that are relevant to a region into another
+/// one, e.g. to run an instance for a single city from a national build.
+///
+/// Trips that stop in the region are kept as a whole, together with all stops they serve. Ids are
+/// not reassigned, so that they match the ones of the full build. Transfer patterns are not
+/// persisted yet and therefore not part of the bundle.
+pub fn crop_preprocessed(
+    source_work_dir: &Path,
+    target_work_dir: &Path,
+    region: &MultiPolygon<f64>,
+) -> Result<CropSummary, CropError> {
+    let source_dir = paths::tmp_dir_in(source_work_dir).join("simplify");
+    let target_dir = paths::tmp_dir_in(target_work_dir).join("simplify");
+    let scan = |name: &str| LazyFrame::scan_parquet(source_dir.join(name), Default::default());
+
+    let stops_in_region = stops_in_region(scan("stops.parquet")?, region)?.lazy();
+
+    let trip_ids = scan("stop_times.parquet")?
+        .semi_join(stops_in_region.clone(), col("stop_id"), col("stop_id"))
+        .select([col("trip_id")])
+        .unique(None, UniqueKeepStrategy::Any)
+        .collect()?
+        .lazy();
+    let stop_times = scan("stop_times.parquet")?
+        .semi_join(trip_ids.clone(), col("trip_id"), col("trip_id"))
+        .collect()?;
+    let stop_ids = concat(
+        [stops_in_region.clone(), stop_times.clone().lazy().select([col("stop_id")])],
+        UnionArgs::default(),
+    )?
+        .unique(None, UniqueKeepStrategy::Any)
+        .collect()?
+        .lazy();
+    write_df_to_file(target_dir.join("stop_times.parquet"), FileType::PARQUET, stop_times)?;
+
+    let stops = scan("stops.parquet")?
+        .semi_join(stop_ids.clone(), col("stop_id"), col("stop_id"))
+        .collect()?;
+    let trips = scan("trips.parquet")?
+        .semi_join(trip_ids.clone(), col("trip_id"), col("trip_id"))
+        .collect()?;
+    let summary_stops = stops.height() as u32;
+    let summary_trips = trips.height() as u32;
+    write_df_to_file(target_dir.join("stops.parquet"), FileType::PARQUET, stops)?;
+
+    let by = |columns: &[&str]| columns.iter().map(|column| col(*column)).collect::<Vec<_>>();
+    let in_trips = |frame: LazyFrame, columns: &[&str]| frame.join(
+        trips.clone().lazy(),
+        by(columns),
+        by(columns),
+        JoinArgs::new(JoinType::Semi),
+    );
+    let routes = || scan("routes.parquet").map(|routes| in_trips(routes, &["dataset_id", "route_id_in_dataset"]));
+
+    type Crop<'a> = Box<dyn Fn(LazyFrame) -> Result<LazyFrame, PolarsError> + 'a>;
+    let tables: Vec<(&str, Crop)> = vec![
+        ("stations.parquet", Box::new(|stations| {
+            Ok(stations.semi_join(stop_ids.clone(), col("stop_id"), col("stop_id")))
+        })),
+        ("trip_runs.parquet", Box::new(|trip_runs| {
+            Ok(trip_runs.semi_join(trip_ids.clone(), col("trip_id"), col("trip_id")))
+        })),
+        ("services.parquet", Box::new(|services| {
+            Ok(in_trips(services, &["dataset_id", "service_id_in_dataset"]))
+        })),
+        ("transfers.parquet", Box::new(|transfers| {
+            Ok(transfers
+                .semi_join(stop_ids.clone(), col("from_stop_id"), col("stop_id"))
+                .semi_join(stop_ids.clone(), col("to_stop_id"), col("stop_id")))
+        })),
+        ("timetable_transfers.parquet", Box::new(|transfers| {
+            Ok(transfers
+                .semi_join(stop_ids.clone(), col("from_stop_id"), col("stop_id"))
+                .semi_join(stop_ids.clone(), col("to_stop_id"), col("stop_id")))
+        })),
+        ("booking_notes.parquet", Box::new(|notes| {
+            Ok(notes.semi_join(trip_ids.clone(), col("trip_id"), col("trip_id")))
+        })),
+        ("agencies.parquet", Box::new(|agencies| {
+            Ok(agencies.join(
+                routes()?,
+                by(&["dataset_id", "agency_id_in_dataset"]),
+                by(&["dataset_id", "agency_id_in_dataset"]),
+                JoinArgs::new(JoinType::Semi),
+            ))
+        })),
+        ("routes.parquet", Box::new(|_| routes())),
+        ("route_patterns.parquet", Box::new(|patterns| {
+            Ok(patterns_in_region(patterns, stops_in_region.clone()))
+        })),
+        ("trips.parquet", Box::new(|_| Ok(trips.clone().lazy()))),
+    ];
+
+    // Tables that are missing in older builds are left out of the bundle as well
+    for (name, crop) in tables {
+        if !source_dir.join(name).exists() {
+            continue;
+        }
+        write_df_to_file(target_dir.join(name), FileType::PARQUET, crop(scan(name)?)?.collect()?)?;
+    }
+
+    let lines = crop_direct_connections(source_work_dir, target_work_dir, trip_ids)?;
+
+    Ok(CropSummary { stops: summary_stops, trips: summary_trips, lines })
+}
+
+/// Ids of the stops that lie within the region (column "stop_id")
+fn stops_in_region(stops: LazyFrame, region: &MultiPolygon<f64>) -> Result<DataFrame, PolarsError> {
+    let stops = stops
+        .select([
+            col("stop_id"),
+            col("lat").cast(DataType::Float64),
+            col("lon").cast(DataType::Float64),
+        ])
+        .collect()?;
+
+    let stop_ids: Vec<u32> = stops.column("stop_id")?.u32()?.iter()
+        .zip(stops.column("lat")?.f64()?)
+        .zip(stops.column("lon")?.f64()?)
+        .filter_map(|((stop_id, lat), lon)| match (stop_id, lat, lon) {
+            (Some(stop_id), Some(lat), Some(lon)) if region.contains(&Point::new(lon, lat)) => Some(stop_id),
+            _ => None,
+        })
+        .collect();
+
+    DataFrame::new(vec![Column::new("stop_id".into(), stop_ids)])
+}
+
+/// Stop sequences of routes that serve at least one stop in the region
+fn patterns_in_region(patterns: LazyFrame, stops_in_region: LazyFrame) -> LazyFrame {
+    let patterns = patterns.with_row_index("pattern", None);
+
+    let in_region = patterns.clone()
+        .select([col("pattern"), col("stop_ids")])
+        .explode([col("stop_ids")])
+        .semi_join(stops_in_region, col("stop_ids"), col("stop_id"))
+        .select([col("pattern")]);
+
+    patterns
+        .semi_join(in_region, col("pattern"), col("pattern"))
+        .drop(["pattern"])
+}
+
+/// Keeps the lines of the direct connections of every cluster that contain one of the trips.
+/// Returns the number of lines that are left.
+fn crop_direct_connections(
+    source_work_dir: &Path,
+    target_work_dir: &Path,
+    trip_ids: LazyFrame,
+) -> Result<u32, CropError> {
+    let direct_connections_dir = |work_dir: &Path| {
+        paths::preprocessing_dir_in(work_dir).join("stp").join("direct_connections")
+    };
+    let source_dir = direct_connections_dir(source_work_dir);
+    let target_dir = direct_connections_dir(target_work_dir);
+
+    let clusters: Vec<PathBuf> = match fs::read_dir(source_dir.join("expanded_lines")) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| PathBuf::from(entry.file_name())))
+            .collect::<Result<_, _>>()?,
+        // Nothing was preprocessed yet
+        Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut num_lines = 0;
+    for cluster in clusters {
+        let path = |dir: &Path, table: &str| dir.join(table).join(&cluster).join("data.parquet");
+
+        let expanded_lines = LazyFrame::scan_parquet(path(&source_dir, "expanded_lines"), Default::default())?;
+        let line_ids = expanded_lines.clone()
+            .semi_join(trip_ids.clone(), col("trip_id"), col("trip_id"))
+            .select([col("line_id")])
+            .unique(None, UniqueKeepStrategy::Any)
+            .collect()?;
+        num_lines += line_ids.height() as u32;
+        let line_ids = line_ids.lazy();
+
+        let expanded_lines = expanded_lines
+            .semi_join(line_ids.clone(), col("line_id"), col("line_id"))
+            .collect()?;
+        write_df_to_file(path(&target_dir, "expanded_lines"), FileType::PARQUET, expanded_lines)?;
+
+        // Incidences of stops with lines that were removed are dropped as well
+        let stop_incidence = LazyFrame::scan_parquet(path(&source_dir, "stop_incidence"), Default::default())?
+            .explode([col("incidences")])
+            .unnest(["incidences"])
+            .semi_join(line_ids, col("line_id"), col("line_id"))
+            .group_by([col("stop_id")])
+            .agg([as_struct(vec![col("line_id"), col("stop_sequence")]).alias("incidences")])
+            .collect()?;
+        write_df_to_file(path(&target_dir, "stop_incidence"), FileType::PARQUET, stop_incidence)?;
+    }
+
+    Ok(num_lines)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CropError {
+    Polars(#[from] PolarsError),
+    IO(#[from] io::Error),
+    GeoJson(#[from] geojson::Error),
+    NoPolygon,
+}
+
+impl Display for CropError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let err: &dyn Display = match self {
+            CropError::Polars(err) => err,
+            CropError::IO(err) => err,
+            CropError::GeoJson(err) => err,
+            CropError::NoPolygon => &"The region does not contain any polygon",
+        };
+        write!(f, "{}", err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use geo::polygon;
+    use polars::df;
+
+    #[test]
+    fn test_crop_preprocessed() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let write = |name: &str, frame: DataFrame| {
+            let path = paths::tmp_dir_in(source.path()).join("simplify").join(name);
+            write_df_to_file(path, FileType::PARQUET, frame).unwrap();
+        };
+
+        // Stop 0 is in the region, stops 1 and 2 are not. Trip 0 runs from stop 0 to 1, trip 1
+        // from stop 1 to 2.
+        write("stops.parquet", df!(
+            "stop_id" => [0u32, 1, 2],
+            "lat"     => [48.5f32, 49.5, 50.5],
+            "lon"     => [9.5f32, 9.5, 9.5],
+        ).unwrap());
+        write("stop_times.parquet", df!(
+            "trip_id" => [0u32, 0, 1, 1],
+            "stop_id" => [0u32, 1, 1, 2],
+        ).unwrap());
+        write("trips.parquet", df!(
+            "trip_id"               => [0u32, 1],
+            "dataset_id"            => ["d", "d"],
+            "route_id_in_dataset"   => ["r1", "r2"],
+            "service_id_in_dataset" => ["s1", "s2"],
+        ).unwrap());
+        write("services.parquet", df!(
+            "dataset_id"            => ["d", "d"],
+            "service_id_in_dataset" => ["s1", "s2"],
+        ).unwrap());
+        write("transfers.parquet", df!(
+            "from_stop_id" => [0u32, 1],
+            "to_stop_id"   => [1u32, 2],
+        ).unwrap());
+
+        // Trips 0 and 1 are lines 0 and 1 of the same cluster
+        let direct_connections = paths::preprocessing_dir_in(source.path()).join("stp").join("direct_connections");
+        let expanded_lines = df!(
+            "line_id"       => [0u32, 0, 1, 1],
+            "trip_id"       => [0u32, 0, 1, 1],
+            "stop_id"       => [0u32, 1, 1, 2],
+            "stop_sequence" => [0u32, 1, 0, 1],
+        ).unwrap();
+        let stop_incidence = expanded_lines.clone().lazy()
+            .group_by([col("stop_id")])
+            .agg([as_struct(vec![col("line_id"), col("stop_sequence")]).alias("incidences")])
+            .collect().unwrap();
+        for (name, frame) in [("expanded_lines", expanded_lines), ("stop_incidence", stop_incidence)] {
+            let path = direct_connections.join(name).join("cluster_id=0").join("data.parquet");
+            write_df_to_file(path, FileType::PARQUET, frame).unwrap();
+        }
+
+        let region = MultiPolygon(vec![polygon![
+            (x: 9.0, y: 48.0), (x: 10.0, y: 48.0), (x: 10.0, y: 49.0), (x: 9.0, y: 49.0),
+        ]]);
+        let summary = crop_preprocessed(source.path(), target.path(), &region).unwrap();
+        assert_eq!(summary, CropSummary { stops: 2, trips: 1, lines: 1 });
+
+        let read = |name: &str| {
+            let path = paths::tmp_dir_in(target.path()).join("simplify").join(name);
+            LazyFrame::scan_parquet(path, Default::default()).unwrap().collect().unwrap()
+        };
+        assert_eq!(read("services.parquet").column("service_id_in_dataset").unwrap().str().unwrap().get(0), Some("s1"));
+        assert_eq!(read("transfers.parquet").height(), 1);
+        assert!(!paths::tmp_dir_in(target.path()).join("simplify").join("routes.parquet").exists());
+
+        // Stop 1 is only left with its incidence with line 0
+        let path = paths::preprocessing_dir_in(target.path())
+            .join("stp").join("direct_connections").join("stop_incidence").join("cluster_id=0").join("data.parquet");
+        let stop_incidence = LazyFrame::scan_parquet(path, Default::default()).unwrap()
+            .explode([col("incidences")])
+            .unnest(["incidences"])
+            .collect().unwrap();
+        assert_eq!(stop_incidence.column("line_id").unwrap().u32().unwrap().to_vec(), [Some(0), Some(0)]);
+    }
+}
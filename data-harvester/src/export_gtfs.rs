@@ -0,0 +1,382 @@
+use common::util::paths;
+use log::warn;
+use polars::datatypes::DataType;
+use polars::error::PolarsError;
+use polars::frame::DataFrame;
+use polars::io::SerWriter;
+use polars::prelude::{col, concat, lit, when, Column, CsvWriter, IntoLazy, LazyFrame, UnionArgs, UniqueKeepStrategy};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::Display;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// Size of an exported feed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportSummary {
+    pub agencies: u32,
+    pub routes: u32,
+    pub stops: u32,
+    pub trips: u32,
+    pub transfers: u32,
+}
+
+/// Writes the merged and simplified timetable of a working directory as a GTFS zip, e.g. to use
+/// drino as a merger and cleaner of feeds for other tools.
+///
+/// Ids are the ones of the datasets, prefixed by the id of the dataset like everywhere else in
+/// drino, so that they are unique across datasets. Stops that were merged into a stop of another
+/// dataset are replaced by that stop. Runs of frequency-based trips are written as trips of their
+/// own, with the number of the run appended to the id of the template. Minimum transfer times of
+/// the datasets and the walks between merged datasets become transfers.txt. Booking rules are not
+/// part of the feed.
+///
+/// Times are kept as they are, in the time zone of their agency. GTFS requires all agencies to share
+/// one time zone, which is only warned about if they don't.
+pub fn export_gtfs(work_dir: &Path, out: &Path) -> Result<ExportSummary, ExportError> {
+    let simplify_dir = paths::tmp_dir_in(work_dir).join("simplify");
+    let scan = |name: &str| LazyFrame::scan_parquet(simplify_dir.join(name), Default::default());
+
+    let agencies = scan("agencies.parquet")?.collect()?;
+    // Builds before the time zones were kept can't be written as valid GTFS
+    if !agencies.schema().contains("agency_timezone") {
+        return Err(ExportError::OutdatedWorkDir);
+    }
+    let routes = scan("routes.parquet")?.collect()?;
+    let stops = scan("stops.parquet")?.collect()?;
+    let trips = scan("trips.parquet")?.collect()?;
+    let services = scan("services.parquet")?.collect()?;
+
+    // New ids are the row numbers, so the exported ids of stops and trips can be looked up by them
+    let stop_ids = namespaced(&stops, "stop_id_in_dataset")?;
+    let trip_ids = run_ids(namespaced(&trips, "trip_id_in_dataset")?);
+
+    let timezones: HashSet<&str> = agencies.column("agency_timezone")?.str()?.iter().flatten().collect();
+    if timezones.len() > 1 {
+        warn!(target: "export", "Agencies are in different time zones, which GTFS doesn't allow: {:?}", timezones);
+    }
+
+    let agency_table = DataFrame::new(vec![
+        Column::new("agency_id".into(), namespaced(&agencies, "agency_id_in_dataset")?),
+        agencies.column("agency_name")?.clone(),
+        agencies.column("agency_url")?.clone(),
+        agencies.column("agency_timezone")?.clone(),
+    ])?;
+
+    let route_table = DataFrame::new(vec![
+        Column::new("route_id".into(), namespaced(&routes, "route_id_in_dataset")?),
+        Column::new("agency_id".into(), namespaced(&routes, "agency_id_in_dataset")?),
+        routes.column("route_short_name")?.clone(),
+        routes.column("route_long_name")?.clone(),
+        routes.column("route_type")?.clone(),
+    ])?;
+
+    // Parent stations that were removed during simplification are left out
+    let known_stops: HashSet<&str> = stop_ids.iter().map(String::as_str).collect();
+    let parent_stations: Vec<Option<String>> = stops.column("dataset_id")?.str()?.iter()
+        .zip(stops.column("parent_station")?.str()?.iter())
+        .map(|(dataset_id, parent)| Some(format!("{}:{}", dataset_id?, parent?)))
+        .map(|parent| parent.filter(|parent| known_stops.contains(parent.as_str())))
+        .collect();
+    let coordinate = |column: &str| -> Result<Vec<Option<String>>, PolarsError> {
+        Ok(stops.column(column)?.cast(&DataType::Float32)?.f32()?.iter()
+            .map(|value| value.map(|value| value.to_string()))
+            .collect())
+    };
+    let stop_table = DataFrame::new(vec![
+        Column::new("stop_id".into(), &stop_ids),
+        stops.column("stop_name")?.clone(),
+        Column::new("stop_lat".into(), coordinate("lat")?),
+        Column::new("stop_lon".into(), coordinate("lon")?),
+        stops.column("location_type")?.clone(),
+        Column::new("parent_station".into(), parent_stations),
+        stops.column("wheelchair_boarding")?.clone(),
+    ])?;
+
+    let trip_table = DataFrame::new(vec![
+        Column::new("route_id".into(), namespaced(&trips, "route_id_in_dataset")?),
+        Column::new("service_id".into(), namespaced(&trips, "service_id_in_dataset")?),
+        Column::new("trip_id".into(), &trip_ids),
+        trips.column("wheelchair_accessible")?.clone(),
+        trips.column("bikes_allowed")?.clone(),
+    ])?;
+
+    let weekdays = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+    let mut calendar_table = services.clone().lazy()
+        .select(
+            [col("service_id")].into_iter()
+                .chain(weekdays.iter().map(|day| col(*day).cast(DataType::UInt8)))
+                .chain([col("start_date"), col("end_date")])
+                .collect::<Vec<_>>()
+        )
+        .collect()?;
+    calendar_table.with_column(Column::new("service_id".into(), namespaced(&services, "service_id_in_dataset")?))?;
+
+    let stop_time_table = stop_time_table(
+        scan("stop_times.parquet")?.collect()?,
+        &stop_ids,
+        &trip_ids,
+    )?;
+
+    // Minimum transfer times of the datasets take precedence over the walks between datasets. A
+    // missing minimum time means that the transfer is not possible.
+    let with_type = |transfers: LazyFrame| transfers.select([
+        col("from_stop_id"),
+        col("to_stop_id"),
+        when(col("duration").is_null()).then(lit(3u32)).otherwise(lit(2u32)).alias("transfer_type"),
+        col("duration").cast(DataType::Int64),
+    ]);
+    let mut transfers = vec![with_type(scan("transfers.parquet")?)];
+    // Runs before minimum transfer times were imported have none
+    if simplify_dir.join("timetable_transfers.parquet").exists() {
+        transfers.insert(0, with_type(scan("timetable_transfers.parquet")?));
+    }
+    let transfers = concat(transfers, UnionArgs::default())?
+        .unique_stable(Some(vec!["from_stop_id".into(), "to_stop_id".into()]), UniqueKeepStrategy::First)
+        .collect()?;
+    let transfer_stop_ids = |column: &str| -> Result<Vec<Option<String>>, PolarsError> {
+        Ok(transfers.column(column)?.u32()?.iter()
+            .map(|stop| stop.and_then(|stop| stop_ids.get(stop as usize).cloned()))
+            .collect())
+    };
+    let transfer_table = DataFrame::new(vec![
+        Column::new("from_stop_id".into(), transfer_stop_ids("from_stop_id")?),
+        Column::new("to_stop_id".into(), transfer_stop_ids("to_stop_id")?),
+        transfers.column("transfer_type")?.clone(),
+        Column::new(
+            "min_transfer_time".into(),
+            transfers.column("duration")?.i64()?.iter()
+                .map(|duration| duration.map(|duration| (duration / 1000) as u32))
+                .collect::<Vec<_>>(),
+        ),
+    ])?;
+
+    let summary = ExportSummary {
+        agencies: agency_table.height() as u32,
+        routes: route_table.height() as u32,
+        stops: stop_table.height() as u32,
+        trips: trip_table.height() as u32,
+        transfers: transfer_table.height() as u32,
+    };
+
+    let mut zip = ZipWriter::new(File::create(out)?);
+    for (name, mut table) in [
+        ("agency.txt", agency_table),
+        ("routes.txt", route_table),
+        ("stops.txt", stop_table),
+        ("trips.txt", trip_table),
+        ("calendar.txt", calendar_table),
+        ("stop_times.txt", stop_time_table),
+        ("transfers.txt", transfer_table),
+    ] {
+        zip.start_file(name, SimpleFileOptions::default())?;
+        CsvWriter::new(&mut zip)
+            .include_header(true)
+            .with_date_format(Some("%Y%m%d".into()))
+            .finish(&mut table)?;
+    }
+    zip.finish()?.flush()?;
+
+    Ok(summary)
+}
+
+/// The stop times of every trip in the order of their sequence, with times as "HH:MM:SS" that
+/// exceed 24 hours for trips that run past midnight
+fn stop_time_table(stop_times: DataFrame, stop_ids: &[String], trip_ids: &[String]) -> Result<DataFrame, PolarsError> {
+    let stop_times = stop_times.lazy()
+        .select([
+            col("trip_id"),
+            col("arrival_time").cast(DataType::Int64),
+            col("departure_time").cast(DataType::Int64),
+            col("stop_id"),
+            col("stop_sequence"),
+        ])
+        .sort(["trip_id", "stop_sequence"], Default::default())
+        .collect()?;
+
+    let ids = |column: &str, ids: &[String]| -> Result<Vec<Option<String>>, PolarsError> {
+        Ok(stop_times.column(column)?.u32()?.iter()
+            .map(|id| id.and_then(|id| ids.get(id as usize).cloned()))
+            .collect())
+    };
+    let times = |column: &str| -> Result<Vec<Option<String>>, PolarsError> {
+        Ok(stop_times.column(column)?.i64()?.iter()
+            .map(|time| time.map(gtfs_time))
+            .collect())
+    };
+
+    DataFrame::new(vec![
+        Column::new("trip_id".into(), ids("trip_id", trip_ids)?),
+        Column::new("arrival_time".into(), times("arrival_time")?),
+        Column::new("departure_time".into(), times("departure_time")?),
+        Column::new("stop_id".into(), ids("stop_id", stop_ids)?),
+        stop_times.column("stop_sequence")?.clone(),
+    ])
+}
+
+// Milliseconds since the start of the service day, e.g. "25:10:00"
+fn gtfs_time(millis: i64) -> String {
+    let seconds = millis / 1000;
+    format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Ids of a column prefixed by the id of the dataset, like the original ids of stops and trips
+fn namespaced(frame: &DataFrame, column: &str) -> Result<Vec<String>, PolarsError> {
+    Ok(frame.column("dataset_id")?.str()?.iter()
+        .zip(frame.column(column)?.str()?.iter())
+        .map(|(dataset_id, id)| format!("{}:{}", dataset_id.unwrap_or_default(), id.unwrap_or_default()))
+        .collect())
+}
+
+// Runs of the same template share its id, so all of them get the number of the run appended
+fn run_ids(ids: Vec<String>) -> Vec<String> {
+    let mut num_runs: HashMap<&str, u32> = HashMap::new();
+    for id in &ids {
+        *num_runs.entry(id).or_default() += 1;
+    }
+
+    let mut runs: HashMap<&str, u32> = HashMap::new();
+    ids.iter()
+        .map(|id| match num_runs[id.as_str()] {
+            1 => id.clone(),
+            _ => {
+                let run = runs.entry(id).or_default();
+                *run += 1;
+                format!("{}#{}", id, run)
+            }
+        })
+        .collect()
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExportError {
+    Polars(#[from] PolarsError),
+    IO(#[from] io::Error),
+    Zip(#[from] zip::result::ZipError),
+    OutdatedWorkDir,
+}
+
+impl Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let err: &dyn Display = match self {
+            ExportError::Polars(err) => err,
+            ExportError::IO(err) => err,
+            ExportError::Zip(err) => err,
+            ExportError::OutdatedWorkDir => &"The working directory was simplified by an older version, which didn't keep the time zones of agencies. Preprocess it again.",
+        };
+        write!(f, "{}", err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use common::util::df::{write_df_to_file, FileType};
+    use polars::df;
+    use polars::prelude::TimeUnit;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    #[test]
+    fn test_export_gtfs() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, frame: DataFrame| {
+            let path = paths::tmp_dir_in(work_dir.path()).join("simplify").join(name);
+            write_df_to_file(path, FileType::PARQUET, frame).unwrap();
+        };
+        let duration = |millis: Vec<i64>| Column::new("".into(), millis)
+            .cast(&DataType::Duration(TimeUnit::Milliseconds)).unwrap();
+
+        write("agencies.parquet", df!(
+            "dataset_id"           => ["d"],
+            "agency_id_in_dataset" => ["d"],
+            "agency_name"          => ["Transit"],
+            "agency_url"           => ["https://example.com"],
+            "agency_timezone"      => ["Europe/Berlin"],
+        ).unwrap());
+        write("routes.parquet", df!(
+            "dataset_id"           => ["d"],
+            "route_id_in_dataset"  => ["r"],
+            "agency_id_in_dataset" => ["d"],
+            "route_short_name"     => ["1"],
+            "route_long_name"      => [None::<&str>],
+            "route_type"           => [3u32],
+        ).unwrap());
+        // Stop b of dataset e was merged into stop a, which has a parent station that was removed
+        write("stops.parquet", df!(
+            "stop_id"             => [0u32, 1],
+            "stop_id_in_dataset"  => ["a", "b"],
+            "dataset_id"          => ["d", "e"],
+            "lat"                 => [48.5f32, 48.6],
+            "lon"                 => [9.5f32, 9.6],
+            "location_type"       => [None::<u32>, None],
+            "parent_station"      => [Some("p"), None],
+            "stop_name"           => ["A", "B"],
+            "wheelchair_boarding" => [None::<u32>, None],
+        ).unwrap());
+        // Trip t has two runs of a frequency
+        write("trips.parquet", df!(
+            "trip_id"               => [0u32, 1],
+            "trip_id_in_dataset"    => ["t", "t"],
+            "route_id_in_dataset"   => ["r", "r"],
+            "service_id_in_dataset" => ["s", "s"],
+            "dataset_id"            => ["d", "d"],
+            "wheelchair_accessible" => [None::<u32>, None],
+            "bikes_allowed"         => [None::<u32>, None],
+        ).unwrap());
+        write("services.parquet", df!(
+            "service_id"            => [0u32],
+            "dataset_id"            => ["d"],
+            "service_id_in_dataset" => ["s"],
+            "monday"                => [true],
+            "tuesday"               => [true],
+            "wednesday"             => [true],
+            "thursday"              => [true],
+            "friday"                => [true],
+            "saturday"              => [false],
+            "sunday"                => [false],
+            "start_date"            => [NaiveDate::from_ymd_opt(2024, 1, 1).unwrap()],
+            "end_date"              => [NaiveDate::from_ymd_opt(2024, 12, 31).unwrap()],
+        ).unwrap());
+        let mut stop_times = df!(
+            "trip_id"       => [1u32, 1, 0, 0],
+            "stop_id"       => [0u32, 1, 0, 1],
+            "stop_sequence" => [1u32, 2, 1, 2],
+        ).unwrap();
+        stop_times.with_column(duration(vec![90_000_000, 90_600_000, 86_400_000, 87_000_000]).with_name("arrival_time".into())).unwrap();
+        stop_times.with_column(duration(vec![90_000_000, 90_600_000, 86_400_000, 87_000_000]).with_name("departure_time".into())).unwrap();
+        write("stop_times.parquet", stop_times);
+        let mut transfers = df!(
+            "from_stop_id" => [0u32],
+            "to_stop_id"   => [1u32],
+        ).unwrap();
+        transfers.with_column(duration(vec![120_000]).with_name("duration".into())).unwrap();
+        write("transfers.parquet", transfers);
+
+        let out = work_dir.path().join("gtfs.zip");
+        let summary = export_gtfs(work_dir.path(), &out).unwrap();
+        assert_eq!(summary, ExportSummary { agencies: 1, routes: 1, stops: 2, trips: 2, transfers: 1 });
+
+        let mut zip = ZipArchive::new(File::open(&out).unwrap()).unwrap();
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            zip.by_name(name).unwrap().read_to_string(&mut content).unwrap();
+            content
+        };
+        assert_eq!(read("stops.txt").lines().nth(1), Some("d:a,A,48.5,9.5,,,"));
+        assert_eq!(read("trips.txt").lines().skip(1).collect::<Vec<_>>(), ["d:r,d:s,d:t#1,,", "d:r,d:s,d:t#2,,"]);
+        assert_eq!(read("calendar.txt").lines().nth(1), Some("d:s,1,1,1,1,1,0,0,20240101,20241231"));
+        assert_eq!(read("stop_times.txt").lines().skip(1).collect::<Vec<_>>(), [
+            "d:t#1,24:00:00,24:00:00,d:a,1",
+            "d:t#1,24:10:00,24:10:00,e:b,2",
+            "d:t#2,25:00:00,25:00:00,d:a,1",
+            "d:t#2,25:10:00,25:10:00,e:b,2",
+        ]);
+        assert_eq!(read("transfers.txt").lines().nth(1), Some("d:a,e:b,2,120"));
+    }
+}
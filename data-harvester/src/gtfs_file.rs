@@ -1,12 +1,12 @@
 use polars::datatypes::{AnyValue, DataType};
 use polars::error::{ErrString, PolarsError};
-use polars::prelude::{Column, Field, StrptimeOptions};
+use polars::prelude::{col, lit, Column, Expr, Field, NULL, Schema, StrptimeOptions};
 use polars::series::Series;
 
 pub const GTFS_REQUIRED_FILES: [&str; 5] = [
     "agency.txt", "stops.txt", "routes.txt", "trips.txt", "stop_times.txt"
 ];
-pub const GTFS_OTHER_FILES: [&str; 21] = [
+pub const GTFS_OTHER_FILES: [&str; 22] = [
     "calendar.txt",
     "calendar_dates.txt",
     "fare_attributes.txt",
@@ -28,6 +28,8 @@ pub const GTFS_OTHER_FILES: [&str; 21] = [
     "translations.txt",
     "feed_info.txt",
     "attributions.txt",
+    // GTFS-Flex extension for demand-responsive services
+    "booking_rules.txt",
 ];
 pub const GTFS_FILES_TO_IMPORT: [&str; 4] = [
     "calendar.txt",
@@ -35,6 +37,14 @@ pub const GTFS_FILES_TO_IMPORT: [&str; 4] = [
     "trips.txt",
     "stop_times.txt"
 ];
+// Files that are imported if they are present in the dataset
+pub const GTFS_OPTIONAL_FILES_TO_IMPORT: [&str; 5] = [
+    "agency.txt",
+    "booking_rules.txt",
+    "frequencies.txt",
+    "routes.txt",
+    "transfers.txt",
+];
 
 pub fn gtfs_date_format() -> StrptimeOptions {
     StrptimeOptions {
@@ -78,14 +88,44 @@ pub fn gtfs_time_to_ms(times: Column) -> Result<Column, PolarsError> {
 pub struct GtfsFile {
     pub name: &'static str,
     pub required_fields: Vec<Field>,
+    // Fields that are not required by the GTFS spec. If a file does not contain them, they will be
+    // filled with null values on import.
+    pub optional_fields: Vec<Field>,
+}
+
+impl GtfsFile {
+    /// Returns the subset of optional fields that is actually present in the read file
+    pub fn present_optional_fields(&self, file_schema: &Schema) -> Vec<Field> {
+        self.optional_fields.iter()
+            .filter(|field| file_schema.contains(field.name()))
+            .cloned()
+            .collect()
+    }
+
+    /// Selects all optional fields. Fields missing in the read file are filled with nulls of the
+    /// expected data type, so that later steps can rely on the columns being there.
+    pub fn optional_columns(&self, file_schema: &Schema) -> Vec<Expr> {
+        self.optional_fields.iter()
+            .map(|field| {
+                if file_schema.contains(field.name()) {
+                    col(field.name().clone()).cast(field.dtype().clone())
+                } else {
+                    lit(NULL).cast(field.dtype().clone()).alias(field.name().clone())
+                }
+            })
+            .collect()
+    }
 }
 
 pub struct GtfsDataset {
     pub agency: GtfsFile,
+    pub booking_rules: GtfsFile,
     pub calendar: GtfsFile,
+    pub frequencies: GtfsFile,
     pub routes: GtfsFile,
     pub stop_times: GtfsFile,
     pub stops: GtfsFile,
+    pub transfers: GtfsFile,
     pub trips: GtfsFile,
 }
 
@@ -94,9 +134,35 @@ pub fn gtfs_schemas() -> GtfsDataset {
         agency: GtfsFile {
             name: "agency",
             required_fields: vec![
-                Field { name: "agency_id".into(), dtype: DataType::String },
+                Field { name: "agency_name".into(), dtype: DataType::String },
                 Field { name: "agency_timezone".into(), dtype: DataType::String },
             ],
+            optional_fields: vec![
+                // Only optional if the dataset contains a single agency
+                Field { name: "agency_id".into(), dtype: DataType::String },
+                // Contact information, used for booking if the booking rule does not have its own
+                Field { name: "agency_url".into(), dtype: DataType::String },
+                Field { name: "agency_phone".into(), dtype: DataType::String },
+                Field { name: "agency_email".into(), dtype: DataType::String },
+            ],
+        },
+        booking_rules: GtfsFile {
+            name: "booking_rules",
+            required_fields: vec![
+                Field { name: "booking_rule_id".into(), dtype: DataType::String },
+                // 0: real-time booking, 1: up to same-day booking with advance notice, 2: up to
+                // prior day(s) booking
+                Field { name: "booking_type".into(), dtype: DataType::UInt32 },
+            ],
+            optional_fields: vec![
+                Field { name: "prior_notice_duration_min".into(), dtype: DataType::UInt32 },
+                Field { name: "prior_notice_last_day".into(), dtype: DataType::UInt32 },
+                Field { name: "prior_notice_last_time".into(), dtype: DataType::String },
+                Field { name: "message".into(), dtype: DataType::String },
+                Field { name: "phone_number".into(), dtype: DataType::String },
+                Field { name: "info_url".into(), dtype: DataType::String },
+                Field { name: "booking_url".into(), dtype: DataType::String },
+            ],
         },
         calendar: GtfsFile {
             name: "calendar",
@@ -112,12 +178,30 @@ pub fn gtfs_schemas() -> GtfsDataset {
                 Field { name: "start_date".into(), dtype: DataType::String },
                 Field { name: "end_date".into(), dtype: DataType::String },
             ],
+            optional_fields: vec![],
+        },
+        frequencies: GtfsFile {
+            name: "frequencies",
+            required_fields: vec![
+                Field { name: "trip_id".into(), dtype: DataType::String },
+                Field { name: "start_time".into(), dtype: DataType::String },
+                Field { name: "end_time".into(), dtype: DataType::String },
+                Field { name: "headway_secs".into(), dtype: DataType::UInt32 },
+            ],
+            optional_fields: vec![],
         },
         routes: GtfsFile {
             name: "routes",
             required_fields: vec![
                 Field { name: "route_id".into(), dtype: DataType::String },
+                Field { name: "route_type".into(), dtype: DataType::UInt32 },
+            ],
+            optional_fields: vec![
+                // Only optional if the dataset contains a single agency
                 Field { name: "agency_id".into(), dtype: DataType::String },
+                // At least one of the names is given
+                Field { name: "route_short_name".into(), dtype: DataType::String },
+                Field { name: "route_long_name".into(), dtype: DataType::String },
             ],
         },
         stop_times: GtfsFile {
@@ -128,6 +212,11 @@ pub fn gtfs_schemas() -> GtfsDataset {
                 Field { name: "departure_time".into(), dtype: DataType::String },
                 Field { name: "stop_sequence".into(), dtype: DataType::UInt32 },
             ],
+            optional_fields: vec![
+                // Booking rules for boarding and alighting at demand-responsive stops
+                Field { name: "pickup_booking_rule_id".into(), dtype: DataType::String },
+                Field { name: "drop_off_booking_rule_id".into(), dtype: DataType::String },
+            ],
         },
         stops: GtfsFile {
             name: "stops",
@@ -136,6 +225,32 @@ pub fn gtfs_schemas() -> GtfsDataset {
                 Field { name: "stop_lat".into(), dtype: DataType::Float32 }, // f32 for coordinates might be too little (~2m precision?)
                 Field { name: "stop_lon".into(), dtype: DataType::Float32 }, // f32 for coordinates might be too little (~2m precision?)
             ],
+            optional_fields: vec![
+                // 0 or empty: stop/platform, 1: station, 2: entrance/exit, 3: generic node, 4: boarding area
+                Field { name: "location_type".into(), dtype: DataType::UInt32 },
+                Field { name: "parent_station".into(), dtype: DataType::String },
+                Field { name: "stop_name".into(), dtype: DataType::String },
+                // 0 or empty: unknown (or inherited from the parent station), 1: step-free, 2: not accessible
+                Field { name: "wheelchair_boarding".into(), dtype: DataType::UInt32 },
+            ],
+        },
+        transfers: GtfsFile {
+            name: "transfers",
+            required_fields: vec![
+                // 0 or empty: recommended, 1: timed, 2: needs min_transfer_time, 3: not possible
+                Field { name: "transfer_type".into(), dtype: DataType::UInt32 },
+            ],
+            optional_fields: vec![
+                // Only optional for transfers between trips or routes
+                Field { name: "from_stop_id".into(), dtype: DataType::String },
+                Field { name: "to_stop_id".into(), dtype: DataType::String },
+                Field { name: "from_route_id".into(), dtype: DataType::String },
+                Field { name: "to_route_id".into(), dtype: DataType::String },
+                Field { name: "from_trip_id".into(), dtype: DataType::String },
+                Field { name: "to_trip_id".into(), dtype: DataType::String },
+                // Seconds
+                Field { name: "min_transfer_time".into(), dtype: DataType::UInt32 },
+            ],
         },
         trips: GtfsFile {
             name: "trips",
@@ -144,6 +259,12 @@ pub fn gtfs_schemas() -> GtfsDataset {
                 Field { name: "service_id".into(), dtype: DataType::String },
                 Field { name: "trip_id".into(), dtype: DataType::String },
             ],
+            optional_fields: vec![
+                // 0 or empty: unknown, 1: accessible by wheelchair, 2: not accessible
+                Field { name: "wheelchair_accessible".into(), dtype: DataType::UInt32 },
+                // 0 or empty: unknown, 1: at least one bike can be taken along, 2: no bikes
+                Field { name: "bikes_allowed".into(), dtype: DataType::UInt32 },
+            ],
         },
     }
 }
\ No newline at end of file
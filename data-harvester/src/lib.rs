@@ -1,6 +1,11 @@
+pub mod checkpoint;
+pub mod crop;
+pub mod export_gtfs;
 pub mod step1_fetch_data;
 pub mod step2_import_data;
 pub mod step3_validate_data;
 pub mod step5_simplify;
+pub mod realtime;
 pub mod step4_merge_data;
+pub mod trip_identities;
 mod gtfs_file;
\ No newline at end of file
@@ -0,0 +1,149 @@
+use common::types::dataset::DataSource;
+use common::util::duration::Seconds;
+use common::util::size::ByteSize;
+use log::warn;
+use routing::realtime::alerts::Alert;
+use routing::realtime::gtfs_rt::{decode_alerts, decode_trip_updates, decode_vehicle_positions};
+use routing::realtime::vehicles::VehiclePosition;
+use routing::realtime::{DecodeError, TripUpdate};
+use std::fmt;
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Fetches a GTFS-RT feed and decodes its trip updates
+pub async fn fetch_trip_updates(src: &DataSource) -> Result<Vec<TripUpdate>, RealtimeError> {
+    Ok(decode_trip_updates(&fetch_feed(src).await?)?)
+}
+
+/// Fetches a GTFS-RT feed and decodes its service alerts
+pub async fn fetch_alerts(src: &DataSource) -> Result<Vec<Alert>, RealtimeError> {
+    Ok(decode_alerts(&fetch_feed(src).await?)?)
+}
+
+/// Fetches a GTFS-RT feed and decodes its vehicle positions
+pub async fn fetch_vehicle_positions(src: &DataSource) -> Result<Vec<VehiclePosition>, RealtimeError> {
+    Ok(decode_vehicle_positions(&fetch_feed(src).await?)?)
+}
+
+// Feeds are small and change every few seconds, so they are kept in memory instead of being
+// written to the datasets directory
+async fn fetch_feed(src: &DataSource) -> Result<Vec<u8>, RealtimeError> {
+    let message = match src {
+        DataSource::URL { url, headers, timeout, max_size } => {
+            let client = reqwest::Client::builder()
+                .timeout((*timeout).into())
+                .build()?;
+            let mut request = client.get(url.clone());
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+
+            let mut response = request.send().await
+                .map_err(|err| RealtimeError::from_reqwest(err, *timeout))?
+                .error_for_status()?;
+            let mut message = vec![];
+            while let Some(chunk) = response.chunk().await.map_err(|err| RealtimeError::from_reqwest(err, *timeout))? {
+                message.extend_from_slice(&chunk);
+                if message.len() as u64 > max_size.0 {
+                    return Err(RealtimeError::TooLarge { max_size: *max_size });
+                }
+            }
+            message
+        }
+        DataSource::File { path } => std::fs::read(path)?,
+    };
+    Ok(message)
+}
+
+/// Fetches the feed every `interval` and passes its trip updates on, until `on_update` returns
+/// false. A feed that can't be fetched is skipped, the next one is tried again in time.
+pub async fn poll_trip_updates(src: &DataSource, interval: Duration, on_update: impl FnMut(Vec<TripUpdate>) -> bool) {
+    poll(src, interval, decode_trip_updates, on_update).await
+}
+
+/// Fetches the feed every `interval` and passes its service alerts on, like [poll_trip_updates]
+pub async fn poll_alerts(src: &DataSource, interval: Duration, on_update: impl FnMut(Vec<Alert>) -> bool) {
+    poll(src, interval, decode_alerts, on_update).await
+}
+
+/// Fetches the feed every `interval` and passes its vehicle positions on, like [poll_trip_updates]
+pub async fn poll_vehicle_positions(src: &DataSource, interval: Duration, on_update: impl FnMut(Vec<VehiclePosition>) -> bool) {
+    poll(src, interval, decode_vehicle_positions, on_update).await
+}
+
+async fn poll<T>(
+    src: &DataSource,
+    interval: Duration,
+    decode: fn(&[u8]) -> Result<T, DecodeError>,
+    mut on_update: impl FnMut(T) -> bool,
+) {
+    let mut ticks = tokio::time::interval(interval);
+    // A slow server shouldn't cause a burst of requests once it responds again
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let feed = fetch_feed(src).await.and_then(|message| Ok(decode(&message)?));
+        match feed {
+            Ok(feed) => if !on_update(feed) {
+                return;
+            },
+            Err(err) => warn!(target: "realtime", "Skipping realtime feed: {}", err),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RealtimeError {
+    Reqwest(#[from] reqwest::Error),
+    File(#[from] std::io::Error),
+    Decode(#[from] DecodeError),
+    Timeout { timeout: Seconds },
+    TooLarge { max_size: ByteSize },
+}
+
+impl RealtimeError {
+    fn from_reqwest(err: reqwest::Error, timeout: Seconds) -> Self {
+        if err.is_timeout() {
+            RealtimeError::Timeout { timeout }
+        } else {
+            RealtimeError::Reqwest(err)
+        }
+    }
+}
+
+impl Display for RealtimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RealtimeError::Reqwest(err) => write!(f, "{}", err),
+            RealtimeError::File(err) => write!(f, "{}", err),
+            RealtimeError::Decode(err) => write!(f, "Invalid GTFS-RT feed: {}", err),
+            RealtimeError::Timeout { timeout } => write!(f, "Feed was not received within {}", timeout),
+            RealtimeError::TooLarge { max_size } => write!(f, "Feed is larger than {}", max_size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_poll_trip_updates() {
+        // An empty message is a valid feed without entities
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let src = DataSource::from_url_or_path(file.path().to_str().unwrap());
+
+        let mut polls = 0;
+        poll_trip_updates(&src, Duration::from_millis(1), |updates| {
+            assert!(updates.is_empty());
+            polls += 1;
+            polls < 3
+        }).await;
+        assert_eq!(polls, 3);
+
+        let missing = DataSource::from_url_or_path("/nonexistent/trip_updates.pb");
+        assert!(matches!(fetch_trip_updates(&missing).await, Err(RealtimeError::File(_))));
+        assert!(fetch_alerts(&src).await.unwrap().is_empty());
+        assert!(fetch_vehicle_positions(&src).await.unwrap().is_empty());
+    }
+}
@@ -1,29 +1,48 @@
 use std::fmt;
 use std::fmt::Display;
-use std::io::{Cursor};
+use std::collections::HashMap;
+use std::io::Write;
 use std::time::{SystemTime, UNIX_EPOCH};
-use common::types::dataset::{Dataset, DataSource};
-use std::fs::{create_dir_all, File};
+use common::types::dataset::{Credentials, Dataset, DataSource};
+use std::fs::{create_dir_all, read_dir, remove_file, File};
 use std::path::{Path, PathBuf};
+use common::util::duration::Seconds;
+use common::util::logging::ProgressSink;
+use common::util::paths;
+use common::util::size::ByteSize;
+use reqwest::header::CONTENT_LENGTH;
+use reqwest::{RequestBuilder, Url};
+use tracing::instrument;
 
+#[instrument(name = "fetch", skip_all)]
 pub async fn fetch_dataset(
-    dataset: Dataset
+    dataset: Dataset,
+    progress: &dyn ProgressSink,
 ) -> Result<FetchStepOutput, FetchError> {
     match dataset.clone().src {
-        DataSource::URL { url, .. } => {
-            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
-            let path_str = format!("./data/datasets/{}/imports/{}", dataset.id, timestamp);
-            let path = Path::new(&path_str);
-            create_dir_all(path.parent().unwrap())?;
-            let mut file = File::create(&path)?;
+        DataSource::URL { url, headers, timeout, max_size } => {
+            let imports_dir = paths::datasets_dir().join(&dataset.id).join("imports");
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+            if let Some(refresh) = dataset.refresh {
+                if let Some(path) = recent_download(&imports_dir, now, refresh)? {
+                    return Ok(FetchStepOutput { dataset, path });
+                }
+            }
 
-            let response = reqwest::get(url.clone()).await?;
-            let mut content = Cursor::new(response.bytes().await?);
-            std::io::copy(&mut content, &mut file)?;
+            let path = imports_dir.join(now.to_string());
+            create_dir_all(&imports_dir)?;
+
+            let task_desc = format!("Downloading dataset {}", dataset.id);
+            let result = download(url, &headers, dataset.credentials.as_ref(), timeout, max_size, &path, &task_desc, progress).await;
+            if result.is_err() {
+                // Don't leave partial downloads behind
+                let _ = remove_file(&path);
+            }
+            result?;
 
             Ok(FetchStepOutput {
                 dataset,
-                path: path.to_path_buf(),
+                path,
             })
         },
         DataSource::File { path } => {
@@ -35,19 +54,177 @@ pub async fn fetch_dataset(
     }
 }
 
+/// What is known about the archive of a dataset without downloading it
+#[derive(Debug)]
+pub struct SourceProbe {
+    // Size of the archive in bytes, if the server tells it
+    pub size: Option<u64>,
+    // The archive on disk, for datasets in local files and downloads that are reused due to the
+    // refresh interval of their dataset
+    pub local_file: Option<PathBuf>,
+}
+
+/// Asks the server of a dataset for the size of its archive with a HEAD request, which also checks
+/// that the URL and credentials work. Archives on disk are only looked at.
+pub async fn probe_dataset(dataset: &Dataset) -> Result<SourceProbe, FetchError> {
+    let on_disk = |path: PathBuf| -> Result<SourceProbe, FetchError> {
+        Ok(SourceProbe { size: Some(path.metadata()?.len()), local_file: Some(path) })
+    };
+
+    match &dataset.src {
+        DataSource::URL { url, headers, timeout, .. } => {
+            if let Some(refresh) = dataset.refresh {
+                let imports_dir = paths::datasets_dir().join(&dataset.id).join("imports");
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+                if let Some(path) = recent_download(&imports_dir, now, refresh)? {
+                    return on_disk(path);
+                }
+            }
+
+            let client = reqwest::Client::builder()
+                .timeout((*timeout).into())
+                .build()?;
+            let mut request = client.head(url.clone());
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            if let Some(credentials) = &dataset.credentials {
+                request = authenticate(request, credentials)?;
+            }
+
+            let response = request.send().await
+                .map_err(|err| FetchError::from_reqwest(err, *timeout))?
+                .error_for_status()?;
+            // The response of a HEAD request has no body, so its size is only in the header
+            let size = response.headers().get(CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok()?.parse().ok());
+
+            Ok(SourceProbe { size, local_file: None })
+        }
+        DataSource::File { path } => on_disk(PathBuf::from(path)),
+    }
+}
+
+/// The latest download in `imports_dir` if it is younger than `refresh`. Downloads are named by
+/// the time in milliseconds they were started at.
+fn recent_download(imports_dir: &Path, now: u128, refresh: Seconds) -> Result<Option<PathBuf>, FetchError> {
+    if !imports_dir.exists() {
+        return Ok(None);
+    }
+
+    let mut latest: Option<(u128, PathBuf)> = None;
+    for entry in read_dir(imports_dir)? {
+        let path = entry?.path();
+        let Some(timestamp) = path.file_name().and_then(|name| name.to_str()?.parse::<u128>().ok()) else {
+            continue;
+        };
+        if latest.as_ref().map_or(true, |(latest, _)| timestamp > *latest) {
+            latest = Some((timestamp, path));
+        }
+    }
+
+    Ok(latest
+        .filter(|(timestamp, _)| now.saturating_sub(*timestamp) < refresh.0 as u128 * 1000)
+        .map(|(_, path)| path))
+}
+
+/// Adds the credentials to the request, with the secret read from its environment variable
+fn authenticate(request: RequestBuilder, credentials: &Credentials) -> Result<RequestBuilder, FetchError> {
+    let secret = |variable: &String| std::env::var(variable)
+        .map_err(|_| FetchError::MissingSecret { variable: variable.clone() });
+
+    Ok(match credentials {
+        Credentials::Basic { username, password_env } => request.basic_auth(username, Some(secret(password_env)?)),
+        Credentials::Bearer { token_env } => request.bearer_auth(secret(token_env)?),
+    })
+}
+
+/// Downloads the file at `url` to `path`, aborting after `timeout` or once more than `max_size`
+/// bytes were received. Progress is reported in bytes if the server tells the size of the file.
+#[allow(clippy::too_many_arguments)]
+async fn download(
+    url: Url,
+    headers: &HashMap<String, String>,
+    credentials: Option<&Credentials>,
+    timeout: Seconds,
+    max_size: ByteSize,
+    path: &Path,
+    task_desc: &str,
+    progress: &dyn ProgressSink,
+) -> Result<(), FetchError> {
+    let client = reqwest::Client::builder()
+        .timeout(timeout.into())
+        .build()?;
+
+    let mut request = client.get(url.clone());
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    if let Some(credentials) = credentials {
+        request = authenticate(request, credentials)?;
+    }
+
+    let mut response = request.send().await
+        .map_err(|err| FetchError::from_reqwest(err, timeout))?
+        .error_for_status()?;
+
+    // Fail early if the server already tells us that the file is too large
+    if let Some(content_length) = response.content_length() {
+        if content_length > max_size.0 {
+            return Err(FetchError::TooLarge { max_size });
+        }
+    }
+
+    let content_length = response.content_length();
+    let task = progress.start(task_desc, content_length.unwrap_or(1));
+
+    let mut file = File::create(path)?;
+    let mut size = 0u64;
+    while let Some(chunk) = response.chunk().await.map_err(|err| FetchError::from_reqwest(err, timeout))? {
+        size += chunk.len() as u64;
+        if size > max_size.0 {
+            return Err(FetchError::TooLarge { max_size });
+        }
+        file.write_all(&chunk)?;
+        if content_length.is_some() {
+            task.inc(chunk.len() as u64);
+        }
+    }
+    if content_length.is_none() {
+        task.inc(1);
+    }
+
+    Ok(())
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum FetchError {
     Reqwest(#[from] reqwest::Error),
-    File(#[from] std::io::Error)
+    File(#[from] std::io::Error),
+    Timeout { timeout: Seconds },
+    TooLarge { max_size: ByteSize },
+    MissingSecret { variable: String },
+}
+
+impl FetchError {
+    fn from_reqwest(err: reqwest::Error, timeout: Seconds) -> Self {
+        if err.is_timeout() {
+            FetchError::Timeout { timeout }
+        } else {
+            FetchError::Reqwest(err)
+        }
+    }
 }
 
 impl Display for FetchError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let err: &dyn Display = match self {
-            FetchError::Reqwest(err) => err,
-            FetchError::File(err) => err
-        };
-        write!(f, "{}", err)
+        match self {
+            FetchError::Reqwest(err) => write!(f, "{}", err),
+            FetchError::File(err) => write!(f, "{}", err),
+            FetchError::Timeout { timeout } => write!(f, "Download did not finish within {}. Increase `timeout:` of the dataset source if this is expected.", timeout),
+            FetchError::TooLarge { max_size } => write!(f, "Download is larger than {}. Increase `max_size:` of the dataset source if this is expected.", max_size),
+            FetchError::MissingSecret { variable } => write!(f, "The environment variable {} of the dataset credentials is not set", variable),
+        }
     }
 }
 
@@ -56,4 +233,69 @@ impl Display for FetchError {
 pub struct FetchStepOutput {
     pub(crate) dataset: Dataset,
     pub(crate) path: PathBuf
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::util::logging::NoProgress;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    // Serves an endless response without content length to the first client
+    fn endless_server(delay: Duration) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+            loop {
+                thread::sleep(delay);
+                if stream.write_all(b"400\r\n").is_err()
+                    || stream.write_all(&[0u8; 1024]).is_err()
+                    || stream.write_all(b"\r\n").is_err() {
+                    break;
+                }
+            }
+        });
+
+        Url::parse(&format!("http://{address}/gtfs.zip")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_download_too_large() {
+        let url = endless_server(Duration::ZERO);
+        let path = std::env::temp_dir().join("drino_test_download_too_large");
+
+        let result = download(url, &HashMap::new(), None, Seconds(60), ByteSize(64 * 1024), &path, "Downloading", &NoProgress).await;
+        assert!(matches!(result, Err(FetchError::TooLarge { .. })), "{result:?}");
+        let _ = remove_file(path);
+    }
+
+    #[tokio::test]
+    async fn test_download_timeout() {
+        let url = endless_server(Duration::from_millis(200));
+        let path = std::env::temp_dir().join("drino_test_download_timeout");
+
+        let result = download(url, &HashMap::new(), None, Seconds(1), ByteSize(1 << 30), &path, "Downloading", &NoProgress).await;
+        assert!(matches!(result, Err(FetchError::Timeout { .. })), "{result:?}");
+        let _ = remove_file(path);
+    }
+
+    #[test]
+    fn test_recent_download() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["1000", "5000", "partial"] {
+            File::create(dir.path().join(name)).unwrap();
+        }
+
+        let refresh = Seconds(10);
+        assert_eq!(recent_download(dir.path(), 14_000, refresh).unwrap(), Some(dir.path().join("5000")));
+        assert_eq!(recent_download(dir.path(), 15_000, refresh).unwrap(), None);
+        assert_eq!(recent_download(&dir.path().join("missing"), 0, refresh).unwrap(), None);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::step2_import_data::ImportStepExtra;
+use crate::step3_validate_data::rules::inside_bounds;
+use common::types::dataset::DatasetFilter;
+use polars::df;
+use polars::error::PolarsError;
+use polars::prelude::{col, concat, len, lit, IntoLazy, JoinArgs, JoinType, LazyFrame, UnionArgs};
+
+/// Drops everything of a dataset that the filter excludes, before it is validated. Routes are kept
+/// by their agency and route type, stops by their location. Trips of dropped routes, frequencies of
+/// dropped trips, stop times of dropped trips and stops and transfers between dropped stops, routes
+/// or trips go with them, so that no reference dangles. Trips that are left with less than two stop
+/// times can't be ridden and are dropped too. Routes without an agency id are dropped if the filter
+/// lists agencies.
+pub(crate) fn apply_filter(filter: &DatasetFilter, extra: ImportStepExtra) -> Result<ImportStepExtra, PolarsError> {
+    if filter.is_empty() {
+        return Ok(extra);
+    }
+
+    let ImportStepExtra::Gtfs { mut agencies, mut routes, booking_rules, calendar, mut stops, mut trips, stop_times, frequencies, transfers, temporary_files } = extra;
+
+    if !filter.agencies.is_empty() {
+        let kept = df!("agency_id" => &filter.agencies)?.lazy();
+        agencies = keep(agencies, "agency_id", kept.clone(), "agency_id");
+        routes = keep(routes, "agency_id", kept, "agency_id");
+    }
+    if !filter.route_types.is_empty() {
+        routes = keep(routes, "route_type", df!("route_type" => &filter.route_types)?.lazy(), "route_type");
+    }
+    if !filter.agencies.is_empty() || !filter.route_types.is_empty() {
+        trips = keep(trips, "route_id", routes.clone(), "route_id");
+    }
+
+    if let Some(bbox) = &filter.bbox {
+        // Parent stations outside of the area are kept for the stops inside of it
+        let inside = stops.clone().filter(inside_bounds(bbox));
+        let kept = concat([
+            inside.clone().select([col("stop_id")]),
+            inside.select([col("parent_station").alias("stop_id")]).drop_nulls(None),
+        ], UnionArgs::default())?;
+        stops = keep(stops, "stop_id", kept, "stop_id");
+    }
+
+    let stop_times = keep(keep(stop_times, "trip_id", trips.clone(), "trip_id"), "stop_id", stops.clone(), "stop_id");
+    let ridden = stop_times.clone()
+        .group_by([col("trip_id")])
+        .agg([len().alias("num_stop_times")])
+        .filter(col("num_stop_times").gt_eq(lit(2)));
+    let trips = keep(trips, "trip_id", ridden, "trip_id");
+
+    // Transfers between trips or routes have no stops and the other way round
+    let schema = transfers.clone().collect_schema()?;
+    let mut transfers = transfers;
+    for (columns, kept, kept_column) in [
+        (["from_stop_id", "to_stop_id"], &stops, "stop_id"),
+        (["from_route_id", "to_route_id"], &routes, "route_id"),
+        (["from_trip_id", "to_trip_id"], &trips, "trip_id"),
+    ] {
+        for column in columns.into_iter().filter(|column| schema.contains(column)) {
+            transfers = keep_if_given(transfers, column, kept.clone(), kept_column);
+        }
+    }
+
+    Ok(ImportStepExtra::Gtfs {
+        stop_times: keep(stop_times, "trip_id", trips.clone(), "trip_id"),
+        frequencies: keep(frequencies, "trip_id", trips.clone(), "trip_id"),
+        agencies, routes, booking_rules, calendar, stops, trips, transfers, temporary_files,
+    })
+}
+
+/// Rows of `frame` whose value in `column` is in `kept_column` of `kept`
+fn keep(frame: LazyFrame, column: &str, kept: LazyFrame, kept_column: &str) -> LazyFrame {
+    frame.join(
+        kept.select([col(kept_column)]),
+        [col(column)],
+        [col(kept_column)],
+        JoinArgs::new(JoinType::Semi),
+    )
+}
+
+/// Like [keep], but also keeps the rows without a value in `column`
+fn keep_if_given(frame: LazyFrame, column: &str, kept: LazyFrame, kept_column: &str) -> LazyFrame {
+    frame
+        .join(
+            kept.select([col(kept_column).alias(column), lit(true).alias("kept")]),
+            [col(column)],
+            [col(column)],
+            JoinArgs::new(JoinType::Left),
+        )
+        .filter(col(column).is_null().or(col("kept").is_not_null()))
+        .drop(["kept"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::dataset::BoundingBox;
+    use polars::prelude::DataFrame;
+
+    fn test_data() -> ImportStepExtra {
+        ImportStepExtra::Gtfs {
+            agencies: df!("agency_id" => ["a1", "a2"]).unwrap().lazy(),
+            booking_rules: DataFrame::empty().lazy(),
+            routes: df!("route_id" => ["bus", "rail"], "agency_id" => ["a1", "a2"], "route_type" => [3u32, 2]).unwrap().lazy(),
+            calendar: df!("service_id" => ["s1"]).unwrap().lazy(),
+            stops: df!(
+                "stop_id"        => ["a", "b", "station", "far"],
+                "stop_lat"       => [48.7f32, 48.8, 49.5, 52.5],
+                "stop_lon"       => [9.1f32, 9.2, 9.5, 13.4],
+                "parent_station" => [None, Some("station"), None, None],
+            ).unwrap().lazy(),
+            trips: df!("trip_id" => ["t1", "t2"], "route_id" => ["bus", "rail"], "service_id" => ["s1", "s1"]).unwrap().lazy(),
+            stop_times: df!(
+                "trip_id" => ["t1", "t1", "t2", "t2"],
+                "stop_id" => ["a", "b", "b", "far"],
+            ).unwrap().lazy(),
+            frequencies: df!("trip_id" => ["t2"], "headway_secs" => [600u32]).unwrap().lazy(),
+            transfers: df!(
+                "from_stop_id"  => [Some("a"), Some("b"), None],
+                "to_stop_id"    => [Some("b"), Some("far"), None],
+                "from_trip_id"  => [None, None, Some("t1")],
+                "to_trip_id"    => [None, None, Some("t2")],
+                "transfer_type" => [0u32, 0, 1],
+            ).unwrap().lazy(),
+            temporary_files: vec![],
+        }
+    }
+
+    fn ids(frame: LazyFrame, column: &str) -> Vec<String> {
+        let frame = frame.collect().unwrap();
+        let mut ids: Vec<String> = frame.column(column).unwrap().str().unwrap().into_no_null_iter().map(String::from).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_filter_by_bbox() {
+        let filter = DatasetFilter {
+            bbox: Some(BoundingBox { min_lat: 48.0, min_lon: 9.0, max_lat: 49.0, max_lon: 10.0 }),
+            ..Default::default()
+        };
+        let ImportStepExtra::Gtfs { stops, stop_times, trips, frequencies, transfers, .. } = apply_filter(&filter, test_data()).unwrap();
+        assert_eq!(ids(stops, "stop_id"), vec!["a", "b", "station"]);
+        // t2 only stops at b inside of the area
+        assert_eq!(ids(stop_times, "stop_id"), vec!["a", "b"]);
+        assert_eq!(ids(trips, "trip_id"), vec!["t1"]);
+        assert!(ids(frequencies, "trip_id").is_empty());
+        assert_eq!(ids(transfers, "from_stop_id"), vec!["a"]);
+    }
+
+    #[test]
+    fn test_filter_by_agency_and_route_type() {
+        let by_agency = DatasetFilter { agencies: vec!["a1".into()], ..Default::default() };
+        let ImportStepExtra::Gtfs { agencies, trips, stop_times, frequencies, transfers, .. } = apply_filter(&by_agency, test_data()).unwrap();
+        assert_eq!(ids(agencies, "agency_id"), vec!["a1"]);
+        assert_eq!(ids(trips, "trip_id"), vec!["t1"]);
+        assert_eq!(ids(stop_times, "trip_id"), vec!["t1", "t1"]);
+        assert!(ids(frequencies, "trip_id").is_empty());
+        // The transfer between the trips goes with t2
+        assert_eq!(ids(transfers, "from_stop_id"), vec!["a", "b"]);
+
+        let by_route_type = DatasetFilter { route_types: vec![2], ..Default::default() };
+        let ImportStepExtra::Gtfs { routes, trips, .. } = apply_filter(&by_route_type, test_data()).unwrap();
+        assert_eq!(ids(routes, "route_id"), vec!["rail"]);
+        assert_eq!(ids(trips, "trip_id"), vec!["t2"]);
+    }
+}
@@ -1,28 +1,67 @@
 use polars::datatypes::DataType;
-use polars::prelude::{col, GetOutput, LazyCsvReader, LazyFileListReader, Schema, TimeUnit};
+use polars::frame::DataFrame;
+use polars::prelude::{col, Field, GetOutput, IntoLazy, LazyCsvReader, LazyFileListReader, LazyFrame, Schema, TimeUnit};
+use polars::io::RowIndex;
 use std::collections::HashMap;
-use std::fs::File;
+use std::fs::{create_dir_all, File};
+use std::io::{Read, Seek};
 use std::ops::Deref;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tempfile::NamedTempFile;
 use zip::ZipArchive;
+use bytes::Bytes;
+use polars_plan::plans::ScanSources;
+use common::util::logging::{ProgressSink, ProgressTask};
+use common::util::paths;
 
 use crate::gtfs_file::*;
 use crate::step1_fetch_data::FetchStepOutput;
-use crate::step2_import_data::{ImportError, ImportStepExtra, ImportStepOutput};
+use crate::step2_import_data::{ImportError, ImportStepExtra, ImportStepOutput, ROW_IN_FILE};
+
+/// Entries up to this (uncompressed) size are decompressed into memory and scanned from there.
+/// Larger ones, usually only stop_times.txt of big datasets, are extracted to a temporary file, so
+/// that they don't have to fit into memory.
+const MAX_IN_MEMORY_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+
+// Bytes at the start of every file that its number of rows is estimated from
+const ESTIMATE_SAMPLE_SIZE: u64 = 64 * 1024;
+
+// Where the scanner of a file in the archive reads from
+enum EntrySource {
+    Memory(Bytes),
+    File(PathBuf),
+}
+
+impl EntrySource {
+    fn reader(&self) -> Result<LazyCsvReader, ImportError> {
+        Ok(match self {
+            EntrySource::Memory(bytes) => LazyCsvReader::new_with_sources(ScanSources::Buffers([bytes.clone()].into())),
+            EntrySource::File(path) => LazyCsvReader::new(path.canonicalize()?.to_str().unwrap()),
+        })
+    }
+}
 
 pub(crate) async fn import_gtfs_data(
     FetchStepOutput {
         path,
         dataset
-    }: FetchStepOutput
+    }: FetchStepOutput,
+    progress: &dyn ProgressSink,
 ) -> Result<ImportStepOutput, ImportError> {
-    let mut zip_archive_file = File::open(path)?;
-    let mut zip_archive = ZipArchive::new(&mut zip_archive_file)?;
+    let num_files = GTFS_FILES_TO_IMPORT.len() + GTFS_OPTIONAL_FILES_TO_IMPORT.len();
+    let task = progress.start(&format!("Decompressing dataset {}", dataset.id), num_files as u64);
 
-    check_files_in_archive(&zip_archive)?;
-    let extra = import_gtfs_files(&mut zip_archive).await?;
+    // Decompressing is CPU-bound, so keep it off the async worker threads that import other
+    // datasets at the same time
+    let entries = tokio::task::spawn_blocking(move || {
+        let mut zip_archive = ZipArchive::new(File::open(path)?)?;
+        check_files_in_archive(&zip_archive)?;
+        read_entries(&mut zip_archive, MAX_IN_MEMORY_ENTRY_SIZE, &paths::tmp_dir().join("import"), task.as_ref())
+    })
+        .await
+        .expect("Reading the archive panicked")?;
+    let extra = import_gtfs_files(entries)?;
 
     Ok(ImportStepOutput {
         dataset,
@@ -30,7 +69,50 @@ pub(crate) async fn import_gtfs_data(
     })
 }
 
-fn check_files_in_archive(zip_archive: &ZipArchive<&mut File>) -> Result<(), ImportError> {
+/// Uncompressed size and estimated number of rows of a file in an archive
+#[derive(Debug)]
+pub struct FileEstimate {
+    pub file: &'static str,
+    pub size: u64,
+    pub rows: u64,
+}
+
+/// Estimates the number of rows of the files to import from the length of the lines at their
+/// start, without decompressing them completely
+pub(crate) fn estimate_gtfs_rows(path: &Path) -> Result<Vec<FileEstimate>, ImportError> {
+    estimate_entries(&mut ZipArchive::new(File::open(path)?)?, ESTIMATE_SAMPLE_SIZE)
+}
+
+// Files that are no larger than `sample_size` are counted exactly
+fn estimate_entries<R: Read + Seek>(
+    zip_archive: &mut ZipArchive<R>,
+    sample_size: u64,
+) -> Result<Vec<FileEstimate>, ImportError> {
+    let present_files = GTFS_FILES_TO_IMPORT.into_iter().chain(GTFS_OPTIONAL_FILES_TO_IMPORT)
+        .filter(|filename| zip_archive.index_for_name(filename).is_some())
+        .collect::<Vec<_>>();
+
+    present_files.into_iter()
+        .map(|filename| {
+            let file = zip_archive.by_name(filename)?;
+            let size = file.size();
+            let mut sample = Vec::with_capacity(size.min(sample_size) as usize);
+            file.take(sample_size).read_to_end(&mut sample)?;
+
+            let lines = if sample.len() as u64 >= size {
+                sample.split(|byte| *byte == b'\n').filter(|line| !line.is_empty()).count() as u64
+            } else {
+                let sampled_lines = sample.iter().filter(|byte| **byte == b'\n').count() as u64;
+                size * sampled_lines / sample.len() as u64
+            };
+
+            // Without the header
+            Ok(FileEstimate { file: filename, size, rows: lines.saturating_sub(1) })
+        })
+        .collect()
+}
+
+fn check_files_in_archive(zip_archive: &ZipArchive<File>) -> Result<(), ImportError> {
     let actual_file_names: Vec<&str> = zip_archive.file_names().collect();
 
     let mut missing_files_to_import = Vec::from(GTFS_FILES_TO_IMPORT);
@@ -55,27 +137,84 @@ fn check_files_in_archive(zip_archive: &ZipArchive<&mut File>) -> Result<(), Imp
     Ok(())
 }
 
-async fn import_gtfs_files<'lifetime>(
-    zip_archive: &mut ZipArchive<&mut File>
-) -> Result<ImportStepExtra, ImportError> {
-    let mut tmp_files: HashMap<String, PathBuf> = HashMap::default();
-    let schema = gtfs_schemas();
+// Keep track of the line every row came from, so that validation findings can point to it. Line 1
+// is the header, so the first row is on line 2.
+fn row_index() -> Option<RowIndex> {
+    Some(RowIndex { name: ROW_IN_FILE.into(), offset: 2 })
+}
+
+/// Reads all required and optional fields of a file that might be missing in the dataset. A missing
+/// file results in an empty table with the same columns.
+fn read_optional_file(source: Option<&EntrySource>, file: &GtfsFile) -> Result<LazyFrame, ImportError> {
+    let Some(source) = source else {
+        let fields = [Field::new(ROW_IN_FILE.into(), DataType::UInt32)].into_iter()
+            .chain(file.required_fields.iter().cloned())
+            .chain(file.optional_fields.iter().cloned());
+        return Ok(DataFrame::empty_with_schema(&Schema::from_iter(fields)).lazy());
+    };
+
+    let reader = source.reader()?;
+
+    let mut file_schema = reader.clone().finish()?.collect_schema()?.deref().clone();
+    let present_optional_fields = file.present_optional_fields(&file_schema);
+    file_schema.merge(Schema::from_iter(file.required_fields.clone()));
+    file_schema.merge(Schema::from_iter(present_optional_fields));
+    let optional_columns = file.optional_columns(&file_schema);
+
+    Ok(reader
+        .with_schema(Some(Arc::new(file_schema)))
+        .with_row_index(row_index())
+        .finish()?
+        .select([
+            vec![col(ROW_IN_FILE)],
+            file.required_fields.iter().map(|field| col(field.name().clone())).collect(),
+            optional_columns,
+        ].concat()))
+}
+
+// Reads the files to import from the archive, which are scanned from memory if they are no
+// larger than `max_in_memory` and extracted to `tmp_dir` otherwise. Keys are the file names
+// without extension. Every file to import is a step of `task`, also the missing optional ones.
+fn read_entries<R: Read + Seek>(
+    zip_archive: &mut ZipArchive<R>,
+    max_in_memory: u64,
+    tmp_dir: &Path,
+    task: &dyn ProgressTask,
+) -> Result<HashMap<String, EntrySource>, ImportError> {
+    let mut entries: HashMap<String, EntrySource> = HashMap::default();
+
+    let present_optional_files = GTFS_OPTIONAL_FILES_TO_IMPORT.into_iter()
+        .filter(|filename| zip_archive.index_for_name(filename).is_some())
+        .collect::<Vec<_>>();
+    task.inc((GTFS_OPTIONAL_FILES_TO_IMPORT.len() - present_optional_files.len()) as u64);
 
-    for filename in GTFS_FILES_TO_IMPORT {
-        let mut tmp_file = NamedTempFile::new()?;
+    for filename in GTFS_FILES_TO_IMPORT.into_iter().chain(present_optional_files) {
         let mut file = zip_archive.by_name(filename)?;
-        std::io::copy(&mut file, &mut tmp_file)?;
 
-        tmp_files.insert(
-            filename.replace(".txt", ""),
-            tmp_file.into_temp_path().keep()?,
-        );
+        let source = if file.size() <= max_in_memory {
+            let mut buffer = Vec::with_capacity(file.size() as usize);
+            file.read_to_end(&mut buffer)?;
+            EntrySource::Memory(Bytes::from(buffer))
+        } else {
+            create_dir_all(tmp_dir)?;
+            let mut tmp_file = NamedTempFile::new_in(tmp_dir)?;
+            std::io::copy(&mut file, &mut tmp_file)?;
+            EntrySource::File(tmp_file.into_temp_path().keep()?)
+        };
+
+        entries.insert(filename.replace(".txt", ""), source);
+        task.inc(1);
     }
 
+    Ok(entries)
+}
 
-    let calendar_reader = LazyCsvReader::new(
-        tmp_files.get("calendar").expect("No calendar file found").canonicalize()?.to_str().unwrap()
-    );
+fn import_gtfs_files(
+    entries: HashMap<String, EntrySource>,
+) -> Result<ImportStepExtra, ImportError> {
+    let schema = gtfs_schemas();
+
+    let calendar_reader = entries.get("calendar").expect("No calendar file found").reader()?;
 
     let mut calendar_schema = calendar_reader.clone().finish()?.collect_schema()?.deref().clone();
     let expected_calendar_schema = Schema::from_iter(schema.calendar.required_fields);
@@ -83,8 +222,10 @@ async fn import_gtfs_files<'lifetime>(
 
     let calendar = calendar_reader
         .with_schema(Some(Arc::new(calendar_schema)))
+        .with_row_index(row_index())
         .finish()?
         .select([
+            col(ROW_IN_FILE),
             col("service_id"),
             col("monday").cast(DataType::Boolean),
             col("tuesday").cast(DataType::Boolean),
@@ -98,79 +239,224 @@ async fn import_gtfs_files<'lifetime>(
         ]);
 
     
-    let stop_times_reader = LazyCsvReader::new(
-        tmp_files.get("stop_times").expect("No stop_times file found").canonicalize()?.to_str().unwrap()
-    );
+    let stop_times_reader = entries.get("stop_times").expect("No stop_times file found").reader()?;
 
     let mut stop_times_schema = stop_times_reader.clone().finish()?.collect_schema()?.deref().clone();
-    let expected_stop_times_schema = Schema::from_iter(schema.stop_times.required_fields);
+    let present_optional_stop_times_fields = schema.stop_times.present_optional_fields(&stop_times_schema);
+    let expected_stop_times_schema = Schema::from_iter(schema.stop_times.required_fields.clone());
     stop_times_schema.merge(expected_stop_times_schema);
+    stop_times_schema.merge(Schema::from_iter(present_optional_stop_times_fields));
+    let optional_stop_times_columns = schema.stop_times.optional_columns(&stop_times_schema);
 
     let stop_times = stop_times_reader
         .with_schema(Some(Arc::new(Schema::from_iter(stop_times_schema))))
+        .with_row_index(row_index())
         .finish()?
         .select([
-            col("trip_id"),
-            col("stop_id"),
-            // Cast arrival and departure time to durations, since GTFS spec allows for times that
-            // are larger than 24 hours (e.g. 25:42:00). Built-in methods for time of polars would
-            // fail in this case. Think of these fields as "duration from midnight".
-            col("arrival_time")
-                .map(
-                    |t| Ok(Some(gtfs_time_to_ms(t)?)),
-                    GetOutput::from_type(DataType::Duration(TimeUnit::Milliseconds)),
-                )
-                .cast(DataType::Duration(TimeUnit::Milliseconds)),
-            col("departure_time")
-                .map(
-                    |t| Ok(Some(gtfs_time_to_ms(t)?)),
-                    GetOutput::from_type(DataType::Duration(TimeUnit::Milliseconds)),
-                )
-                .cast(DataType::Duration(TimeUnit::Milliseconds)),
-            col("stop_sequence"),
-        ]);
+            vec![
+                col(ROW_IN_FILE),
+                col("trip_id"),
+                col("stop_id"),
+                // Cast arrival and departure time to durations, since GTFS spec allows for times that
+                // are larger than 24 hours (e.g. 25:42:00). Built-in methods for time of polars would
+                // fail in this case. Think of these fields as "duration from midnight".
+                col("arrival_time")
+                    .map(
+                        |t| Ok(Some(gtfs_time_to_ms(t)?)),
+                        GetOutput::from_type(DataType::Duration(TimeUnit::Milliseconds)),
+                    )
+                    .cast(DataType::Duration(TimeUnit::Milliseconds)),
+                col("departure_time")
+                    .map(
+                        |t| Ok(Some(gtfs_time_to_ms(t)?)),
+                        GetOutput::from_type(DataType::Duration(TimeUnit::Milliseconds)),
+                    )
+                    .cast(DataType::Duration(TimeUnit::Milliseconds)),
+                col("stop_sequence"),
+            ],
+            optional_stop_times_columns,
+        ].concat());
 
 
-    let stops_reader = LazyCsvReader::new(
-        tmp_files.get("stops").expect("No stop_times file found").canonicalize()?.to_str().unwrap()
-    );
+    let stops_reader = entries.get("stops").expect("No stops file found").reader()?;
 
     let mut stops_schema = stops_reader.clone().finish()?.collect_schema()?.deref().clone();
-    let expected_stops_schema = Schema::from_iter(schema.stops.required_fields);
+    let present_optional_stops_fields = schema.stops.present_optional_fields(&stops_schema);
+    let expected_stops_schema = Schema::from_iter(schema.stops.required_fields.clone());
     stops_schema.merge(expected_stops_schema);
+    stops_schema.merge(Schema::from_iter(present_optional_stops_fields));
+    let optional_stops_columns = schema.stops.optional_columns(&stops_schema);
 
     let stops = stops_reader
         .with_schema(Some(Arc::new(Schema::from_iter(stops_schema))))
+        .with_row_index(row_index())
         .finish()?
         .select([
-            col("stop_id"),
-            col("stop_lat"),
-            col("stop_lon"),
-        ]);
+            vec![
+                col(ROW_IN_FILE),
+                col("stop_id"),
+                col("stop_lat"),
+                col("stop_lon"),
+            ],
+            optional_stops_columns,
+        ].concat());
 
 
-    let trips_reader = LazyCsvReader::new(
-        tmp_files.get("trips").expect("No stop_times file found").canonicalize()?.to_str().unwrap()
-    );
+    let trips_reader = entries.get("trips").expect("No trips file found").reader()?;
 
     let mut trips_schema = trips_reader.clone().finish()?.collect_schema()?.deref().clone();
-    let expected_trips_schema = Schema::from_iter(schema.trips.required_fields);
+    let present_optional_trips_fields = schema.trips.present_optional_fields(&trips_schema);
+    let expected_trips_schema = Schema::from_iter(schema.trips.required_fields.clone());
     trips_schema.merge(expected_trips_schema);
+    trips_schema.merge(Schema::from_iter(present_optional_trips_fields));
+    let optional_trips_columns = schema.trips.optional_columns(&trips_schema);
 
     let trips = trips_reader
         .with_schema(Some(Arc::new(Schema::from_iter(trips_schema))))
+        .with_row_index(row_index())
         .finish()?
         .select([
-            col("route_id"),
-            col("service_id"),
-            col("trip_id"),
-        ]);
+            vec![
+                col(ROW_IN_FILE),
+                col("route_id"),
+                col("service_id"),
+                col("trip_id"),
+            ],
+            optional_trips_columns,
+        ].concat());
+
+    let frequencies = match entries.get("frequencies") {
+        Some(frequencies_source) => {
+            let frequencies_reader = frequencies_source.reader()?;
+
+            let mut frequencies_schema = frequencies_reader.clone().finish()?.collect_schema()?.deref().clone();
+            let expected_frequencies_schema = Schema::from_iter(schema.frequencies.required_fields);
+            frequencies_schema.merge(expected_frequencies_schema);
+
+            frequencies_reader
+                .with_schema(Some(Arc::new(frequencies_schema)))
+                .with_row_index(row_index())
+                .finish()?
+                .select([
+                    col(ROW_IN_FILE),
+                    col("trip_id"),
+                    // Same as for stop times: Times might be larger than 24 hours
+                    col("start_time")
+                        .map(
+                            |t| Ok(Some(gtfs_time_to_ms(t)?)),
+                            GetOutput::from_type(DataType::Duration(TimeUnit::Milliseconds)),
+                        )
+                        .cast(DataType::Duration(TimeUnit::Milliseconds)),
+                    col("end_time")
+                        .map(
+                            |t| Ok(Some(gtfs_time_to_ms(t)?)),
+                            GetOutput::from_type(DataType::Duration(TimeUnit::Milliseconds)),
+                        )
+                        .cast(DataType::Duration(TimeUnit::Milliseconds)),
+                    col("headway_secs"),
+                ])
+        }
+        // Most datasets don't include frequency-based trips, so use an empty table instead
+        None => DataFrame::empty_with_schema(&Schema::from_iter([
+            Field::new(ROW_IN_FILE.into(), DataType::UInt32),
+            Field::new("trip_id".into(), DataType::String),
+            Field::new("start_time".into(), DataType::Duration(TimeUnit::Milliseconds)),
+            Field::new("end_time".into(), DataType::Duration(TimeUnit::Milliseconds)),
+            Field::new("headway_secs".into(), DataType::UInt32),
+        ])).lazy(),
+    };
+
+    // Agencies and routes are only needed to browse the dataset, so they are not required for
+    // routing
+    let agencies = read_optional_file(entries.get("agency"), &schema.agency)?;
+    let routes = read_optional_file(entries.get("routes"), &schema.routes)?;
+    let booking_rules = read_optional_file(entries.get("booking_rules"), &schema.booking_rules)?;
+    let transfers = read_optional_file(entries.get("transfers"), &schema.transfers)?;
+
+    // Only entries that were too large to be read into memory have to be removed afterwards
+    let temporary_files = entries.into_values()
+        .filter_map(|source| match source {
+            EntrySource::File(path) => Some(path),
+            EntrySource::Memory(_) => None,
+        })
+        .collect();
 
     Ok(ImportStepExtra::Gtfs {
+        agencies,
+        routes,
+        booking_rules,
         calendar,
         stops,
         trips,
         stop_times,
-        temporary_files: tmp_files.into_iter().map(|(_, path)| path).collect(),
+        frequencies,
+        transfers,
+        temporary_files,
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::util::logging::NoProgress;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn archive() -> ZipArchive<Cursor<Vec<u8>>> {
+        let files = [
+            ("calendar.txt", "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\ns1,1,1,1,1,1,0,0,20240101,20241231\n"),
+            ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon\na,A,48.7,9.1\nb,B,48.8,9.2\n"),
+            ("trips.txt", "route_id,service_id,trip_id\nr1,s1,t1\n"),
+            ("stop_times.txt", "trip_id,arrival_time,departure_time,stop_id,stop_sequence\nt1,08:00:00,08:00:00,a,1\nt1,08:10:00,08:10:00,b,2\n"),
+            ("routes.txt", "route_id,route_short_name,route_type\nr1,1,3\n"),
+        ];
+
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, content) in files {
+            writer.start_file(name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        ZipArchive::new(Cursor::new(writer.finish().unwrap().into_inner())).unwrap()
+    }
+
+    #[test]
+    fn test_read_entries() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+
+        // Only calendar.txt and stop_times.txt are larger than 100 bytes
+        let entries = read_entries(&mut archive(), 100, tmp_dir.path(), &NoProgress).unwrap();
+        assert!(matches!(entries["stops"], EntrySource::Memory(_)));
+        assert!(matches!(entries["calendar"], EntrySource::File(_)));
+        assert!(matches!(entries["stop_times"], EntrySource::File(_)));
+        // Missing optional files are left out
+        assert!(!entries.contains_key("frequencies"));
+
+        let ImportStepExtra::Gtfs { stops, stop_times, routes, temporary_files, .. } = import_gtfs_files(entries).unwrap();
+        assert_eq!(stops.collect().unwrap().height(), 2);
+        assert_eq!(stop_times.collect().unwrap().height(), 2);
+        assert_eq!(routes.collect().unwrap().height(), 1);
+        assert_eq!(temporary_files.len(), 2);
+        assert!(temporary_files.iter().all(|path| path.starts_with(tmp_dir.path())));
+    }
+
+    #[test]
+    fn test_estimate_entries() {
+        let estimates = estimate_entries(&mut archive(), 1024).unwrap();
+        let rows: Vec<(&str, u64)> = estimates.iter().map(|estimate| (estimate.file, estimate.rows)).collect();
+        assert_eq!(rows, [("calendar.txt", 1), ("stops.txt", 2), ("trips.txt", 1), ("stop_times.txt", 2), ("routes.txt", 1)]);
+
+        // Larger files are extrapolated from their first lines
+        let stops = (0..1000).fold("stop_id,stop_name,stop_lat,stop_lon\n".to_string(), |stops, i| {
+            stops + &format!("s{i:04},Stop,48.70000,9.10000\n")
+        });
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        writer.start_file("stops.txt", SimpleFileOptions::default()).unwrap();
+        writer.write_all(stops.as_bytes()).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(writer.finish().unwrap().into_inner())).unwrap();
+
+        let estimates = estimate_entries(&mut archive, 1024).unwrap();
+        assert_eq!(estimates[0].size, stops.len() as u64);
+        assert!((950..=1050).contains(&estimates[0].rows));
+    }
+}
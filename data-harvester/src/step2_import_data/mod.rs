@@ -1,20 +1,32 @@
+mod filter;
 mod gtfs;
 
+pub use gtfs::FileEstimate;
+
 use crate::step1_fetch_data::FetchStepOutput;
-use crate::step2_import_data::gtfs::import_gtfs_data;
+use crate::step2_import_data::filter::apply_filter;
+use crate::step2_import_data::gtfs::{estimate_gtfs_rows, import_gtfs_data};
 use common::types::dataset::{Dataset, DatasetFormat};
+use common::util::logging::ProgressSink;
 use polars::prelude::LazyFrame;
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{fmt, io};
+use tracing::instrument;
+
+/// Column with the line number in the source file of every imported row
+pub const ROW_IN_FILE: &str = "row_in_file";
 
+#[instrument(name = "import", skip_all)]
 pub async fn import_data(
-    prev_step_out: FetchStepOutput
+    prev_step_out: FetchStepOutput,
+    progress: &dyn ProgressSink,
 ) -> Result<ImportStepOutput, ImportError> {
     match prev_step_out.dataset.format {
         DatasetFormat::Gtfs => {
-            let result = import_gtfs_data(prev_step_out).await?;
-            Ok(result)
+            let ImportStepOutput { dataset, extra } = import_gtfs_data(prev_step_out, progress).await?;
+            let extra = apply_filter(&dataset.filter, extra)?;
+            Ok(ImportStepOutput { dataset, extra })
         }
         DatasetFormat::GtfsRt => {
             todo!("GTFS RT is not yet supported")
@@ -22,6 +34,16 @@ pub async fn import_data(
     }
 }
 
+/// Estimates the number of rows of the files of a dataset from the start of each file, e.g. to plan
+/// a run without importing the dataset. GTFS RT feeds have no files with rows, so they aren't
+/// estimated.
+pub fn estimate_rows(dataset: &Dataset, path: &Path) -> Result<Option<Vec<FileEstimate>>, ImportError> {
+    match dataset.format {
+        DatasetFormat::Gtfs => estimate_gtfs_rows(path).map(Some),
+        DatasetFormat::GtfsRt => Ok(None),
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ImportError {
     Zip(#[from] zip::result::ZipError),
@@ -54,10 +76,16 @@ pub struct ImportStepOutput {
 #[derive(Clone)]
 pub enum ImportStepExtra {
     Gtfs {
+        agencies: LazyFrame,
+        routes: LazyFrame,
+        booking_rules: LazyFrame,
         calendar: LazyFrame,
         stops: LazyFrame,
         trips: LazyFrame,
         stop_times: LazyFrame,
+        frequencies: LazyFrame,
+        // Minimum times of changing between stops, trips or routes
+        transfers: LazyFrame,
         temporary_files: Vec<PathBuf>
     }
 }
@@ -0,0 +1,258 @@
+use crate::step2_import_data::{ImportStepExtra, ROW_IN_FILE};
+use common::util::df;
+use polars::datatypes::DataType;
+use polars::error::PolarsError;
+use polars::prelude::{col, len, lit, when, Expr, IntoLazy, LazyFrame, SortMultipleOptions, UniqueKeepStrategy, NULL};
+use serde::Serialize;
+
+/// A repair of trivially fixable issues of a dataset, that is applied before validating it if the
+/// dataset is configured with `fix: true`
+pub trait Fix: Sync + Send {
+    fn id(&self) -> &'static str;
+
+    /// The files of the dataset this fix changes
+    fn file(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Returns the repaired data and the number of changed rows
+    fn apply(&self, data: ImportStepExtra) -> Result<(ImportStepExtra, u32), PolarsError>;
+}
+
+/// A fix that changed at least one row of a dataset
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedFix {
+    pub fix_id: &'static str,
+    pub file: &'static str,
+    pub description: &'static str,
+    pub count: u32,
+}
+
+pub fn gtfs_fixes() -> Vec<Box<dyn Fix>> {
+    vec![
+        Box::new(TrimIds),
+        Box::new(DeduplicateStopTimes),
+        Box::new(RenumberStopSequences),
+    ]
+}
+
+/// Applies all fixes in order and returns the repaired data together with the fixes that changed
+/// anything
+pub fn apply_fixes(
+    fixes: &[Box<dyn Fix>],
+    mut data: ImportStepExtra,
+) -> Result<(ImportStepExtra, Vec<AppliedFix>), PolarsError> {
+    let mut applied = vec![];
+
+    for fix in fixes {
+        let (fixed, count) = fix.apply(data)?;
+        data = fixed;
+
+        if count > 0 {
+            applied.push(AppliedFix {
+                fix_id: fix.id(),
+                file: fix.file(),
+                description: fix.description(),
+                count,
+            });
+        }
+    }
+
+    Ok((data, applied))
+}
+
+/// Removes whitespace around the values of `columns`. Returns the trimmed frame and the number of
+/// rows that contained such whitespace.
+fn trim(frame: LazyFrame, columns: &[&str]) -> Result<(LazyFrame, u32), PolarsError> {
+    let schema = frame.clone().collect_schema()?;
+    let columns: Vec<&str> = columns.iter()
+        .copied()
+        .filter(|column| schema.get(column) == Some(&DataType::String))
+        .collect();
+
+    let trimmed = |column: &str| col(column).str().strip_chars(lit(NULL));
+    let needs_trimming = columns.iter()
+        .map(|column| col(*column).neq(trimmed(column)))
+        .reduce(Expr::or)
+        .unwrap_or(lit(false));
+
+    let count = df::count(frame.clone().filter(needs_trimming))?;
+    let frame = frame.with_columns(
+        columns.iter().map(|column| trimmed(column).alias(*column)).collect::<Vec<_>>()
+    );
+
+    Ok((frame, count))
+}
+
+struct TrimIds;
+
+impl Fix for TrimIds {
+    fn id(&self) -> &'static str { "trim_ids" }
+    fn file(&self) -> &'static str { "*.txt" }
+    fn description(&self) -> &'static str { "Removed whitespace around identifiers" }
+
+    fn apply(&self, data: ImportStepExtra) -> Result<(ImportStepExtra, u32), PolarsError> {
+        let ImportStepExtra::Gtfs {
+            agencies, routes, booking_rules, calendar, stops, trips, stop_times, frequencies, transfers, temporary_files,
+        } = data;
+
+        let (agencies, agencies_count) = trim(agencies, &["agency_id"])?;
+        let (routes, routes_count) = trim(routes, &["route_id", "agency_id"])?;
+        let (booking_rules, booking_rules_count) = trim(booking_rules, &["booking_rule_id"])?;
+        let (calendar, calendar_count) = trim(calendar, &["service_id"])?;
+        let (stops, stops_count) = trim(stops, &["stop_id", "parent_station"])?;
+        let (trips, trips_count) = trim(trips, &["trip_id", "route_id", "service_id"])?;
+        let (stop_times, stop_times_count) = trim(
+            stop_times,
+            &["trip_id", "stop_id", "pickup_booking_rule_id", "drop_off_booking_rule_id"],
+        )?;
+        let (frequencies, frequencies_count) = trim(frequencies, &["trip_id"])?;
+        let (transfers, transfers_count) = trim(
+            transfers,
+            &["from_stop_id", "to_stop_id", "from_route_id", "to_route_id", "from_trip_id", "to_trip_id"],
+        )?;
+
+        let count = agencies_count + routes_count + booking_rules_count + calendar_count + stops_count + trips_count
+            + stop_times_count + frequencies_count + transfers_count;
+
+        Ok((
+            ImportStepExtra::Gtfs {
+                agencies, routes, booking_rules, calendar, stops, trips, stop_times, frequencies, transfers, temporary_files,
+            },
+            count,
+        ))
+    }
+}
+
+struct DeduplicateStopTimes;
+
+impl Fix for DeduplicateStopTimes {
+    fn id(&self) -> &'static str { "deduplicate_stop_times" }
+    fn file(&self) -> &'static str { "stop_times.txt" }
+    fn description(&self) -> &'static str { "Removed stop times that are exact duplicates of another row" }
+
+    fn apply(&self, data: ImportStepExtra) -> Result<(ImportStepExtra, u32), PolarsError> {
+        let ImportStepExtra::Gtfs {
+            agencies, routes, booking_rules, calendar, stops, trips, stop_times, frequencies, transfers, temporary_files,
+        } = data;
+
+        // Rows only differ in the line they are on
+        let columns = stop_times.clone().collect_schema()?.iter_names()
+            .filter(|name| name.as_str() != ROW_IN_FILE)
+            .cloned()
+            .collect();
+
+        let stop_times = stop_times.collect()?;
+        let deduplicated = stop_times.clone().lazy()
+            .unique_stable(Some(columns), UniqueKeepStrategy::First)
+            .collect()?;
+        let count = (stop_times.height() - deduplicated.height()) as u32;
+
+        Ok((
+            ImportStepExtra::Gtfs {
+                agencies, routes, booking_rules, calendar, stops, trips, frequencies, transfers, temporary_files,
+                stop_times: deduplicated.lazy(),
+            },
+            count,
+        ))
+    }
+}
+
+struct RenumberStopSequences;
+
+impl Fix for RenumberStopSequences {
+    fn id(&self) -> &'static str { "renumber_stop_sequences" }
+    fn file(&self) -> &'static str { "stop_times.txt" }
+    fn description(&self) -> &'static str {
+        "Renumbered the stop_sequence of trips in which several stop times share the same value"
+    }
+
+    fn apply(&self, data: ImportStepExtra) -> Result<(ImportStepExtra, u32), PolarsError> {
+        let ImportStepExtra::Gtfs {
+            agencies, routes, booking_rules, calendar, stops, trips, stop_times, frequencies, transfers, temporary_files,
+        } = data;
+
+        let has_duplicates = col("stop_sequence").n_unique().over([col("trip_id")])
+            .lt(len().over([col("trip_id")]));
+        // Position of every stop time within its trip, starting at 1
+        let renumbered = (col("position") - col("position").min().over([col("trip_id")]) + lit(1))
+            .cast(DataType::UInt32);
+
+        // Stop times with the same sequence number are ordered by their time and then by the line
+        // they are on
+        let stop_times = stop_times
+            .sort(
+                ["trip_id", "stop_sequence", "departure_time", ROW_IN_FILE],
+                SortMultipleOptions::default().with_maintain_order(true),
+            )
+            .with_row_index("position", None)
+            .with_column(
+                when(has_duplicates)
+                    .then(renumbered)
+                    .otherwise(col("stop_sequence"))
+                    .alias("fixed_stop_sequence")
+            )
+            .collect()?
+            .lazy();
+
+        let count = df::count(stop_times.clone().filter(col("fixed_stop_sequence").neq(col("stop_sequence"))))?;
+        let stop_times = stop_times
+            .with_column(col("fixed_stop_sequence").alias("stop_sequence"))
+            .drop(["fixed_stop_sequence", "position"]);
+
+        Ok((
+            ImportStepExtra::Gtfs {
+                agencies, routes, booking_rules, calendar, stops, trips, frequencies, transfers, temporary_files,
+                stop_times,
+            },
+            count,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    fn gtfs_data(stop_times: LazyFrame) -> ImportStepExtra {
+        ImportStepExtra::Gtfs {
+            agencies: df!("agency_id" => ["a1"]).unwrap().lazy(),
+            booking_rules: df!("booking_rule_id" => ["b1"]).unwrap().lazy(),
+            routes: df!("route_id" => ["r1 "], "agency_id" => ["a1"]).unwrap().lazy(),
+            calendar: df!("service_id" => ["s1"]).unwrap().lazy(),
+            stops: df!("stop_id" => [" a", "b"], "parent_station" => [None::<&str>, None]).unwrap().lazy(),
+            trips: df!("trip_id" => ["t1"], "route_id" => ["r1"], "service_id" => ["s1"]).unwrap().lazy(),
+            stop_times,
+            frequencies: df!("trip_id" => ["t1"], "headway_secs" => [600u32]).unwrap().lazy(),
+            transfers: df!("from_stop_id" => ["a"], "to_stop_id" => ["b "], "transfer_type" => [2u32]).unwrap().lazy(),
+            temporary_files: vec![],
+        }
+    }
+
+    #[test]
+    fn test_fixes() {
+        let stop_times = df!(
+            "trip_id"        => ["t1", "t1", "t1", "t1", "t2", "t2"],
+            "stop_id"        => ["a", "b ", "b ", "c", "a", "b"],
+            "stop_sequence"  => [1u32, 2, 2, 2, 10, 20],
+            "departure_time" => [0i64, 60, 60, 120, 0, 60],
+        ).unwrap().lazy().with_row_index(ROW_IN_FILE, Some(2));
+
+        let (data, applied) = apply_fixes(&gtfs_fixes(), gtfs_data(stop_times)).unwrap();
+
+        let counts: Vec<(&str, u32)> = applied.iter().map(|fix| (fix.fix_id, fix.count)).collect();
+        assert_eq!(counts, [("trim_ids", 5), ("deduplicate_stop_times", 1), ("renumber_stop_sequences", 1)]);
+
+        let ImportStepExtra::Gtfs { stop_times, stops, transfers, .. } = data;
+        let stop_times = stop_times.collect().unwrap();
+        assert_eq!(stop_times.column("stop_id").unwrap().str().unwrap().get(1), Some("b"));
+        // Trips with unique (but not consecutive) sequence numbers are kept as they are
+        assert_eq!(
+            stop_times.column("stop_sequence").unwrap().u32().unwrap().to_vec(),
+            [Some(1), Some(2), Some(3), Some(10), Some(20)]
+        );
+        assert_eq!(stops.collect().unwrap().column("stop_id").unwrap().str().unwrap().get(0), Some("a"));
+        assert_eq!(transfers.collect().unwrap().column("to_stop_id").unwrap().str().unwrap().get(0), Some("b"));
+    }
+}
@@ -1,64 +1,308 @@
-use std::fmt::Error;
-use common::types::dataset::Dataset;
-use crate::step2_import_data::{ImportStepExtra, ImportStepOutput};
+use std::fmt;
+use std::fmt::Display;
+use common::types::dataset::{Dataset, Severity};
+use common::types::mode::ModeRegistry;
+use common::util::logging::{ProgressSink, ProgressTask};
+use common::util::{df, paths};
+use polars::datatypes::DataType;
+use polars::prelude::{col, IntoLazy, JoinArgs, JoinType};
+use log::{error, info, warn};
+use tracing::instrument;
+use crate::step2_import_data::{ImportStepExtra, ImportStepOutput, ROW_IN_FILE};
+use crate::step3_validate_data::fixes::{apply_fixes, gtfs_fixes};
+use crate::step3_validate_data::report::ValidationReport;
+use crate::step3_validate_data::rule_severity::severity_for;
+use crate::step3_validate_data::rule_violations::{RuleViolations, MAX_SAMPLES};
+use crate::step3_validate_data::rules::{dataset_rules, gtfs_rules, Rule};
 
-pub(crate) mod rules;
-pub(crate) mod rule_severity;
-pub(crate) mod rule_violations;
+pub mod fixes;
+pub mod rules;
+pub mod rule_severity;
+pub mod rule_violations;
+pub mod report;
 
+#[instrument(name = "validate", skip_all)]
 pub async fn validate_data(
-    imported_data: ImportStepOutput
+    imported_data: ImportStepOutput,
+    modes: &ModeRegistry,
+    html_report: bool,
+    progress: &dyn ProgressSink,
 ) -> Result<ValidateStepOutput, ValidateError> {
-    // TODO: Validate data
+    let rules: Vec<Box<dyn Rule>> = gtfs_rules(modes).into_iter()
+        .chain(dataset_rules(&imported_data.dataset))
+        .collect();
+    let (extra, fixes) = if imported_data.dataset.fix {
+        apply_fixes(&gtfs_fixes(), imported_data.extra)?
+    } else {
+        (imported_data.extra, vec![])
+    };
+
+    fixes.iter().for_each(|fix| {
+        info!(
+            target: "validation",
+            "Dataset {}: Fixed {} rows in {} ({})",
+            imported_data.dataset.id, fix.count, fix.file, fix.description,
+        );
+    });
+
+    let task = progress.start(&format!("Validating dataset {}", imported_data.dataset.id), rules.len() as u64);
+    let violations = check_rules(&rules, &imported_data.dataset, &extra, task.as_ref())?;
+    drop(task);
+
+    // Write the report next to the imports of the dataset
+    let report = ValidationReport::new(imported_data.dataset.id.clone(), &violations, fixes);
+    let report_dir = paths::datasets_dir().join(&imported_data.dataset.id).join("validation");
+    report.write_json(&report_dir.join("report.json"))?;
+    if html_report {
+        report.write_html(&report_dir.join("report.html"))?;
+    }
+
+    violations.iter().for_each(|violations| {
+        let message = format!(
+            "Dataset {}: {} violations of rule '{}' in {} ({})",
+            imported_data.dataset.id, violations.count, violations.rule_id,
+            violations.file, violations.description,
+        );
+        match violations.severity {
+            Severity::Error => error!(target: "validation", "{}", message),
+            Severity::Warn => warn!(target: "validation", "{}", message),
+            Severity::Ignore => {}
+        }
+    });
+
+    if report.dangling_references > 0 {
+        warn!(target: "validation", "Dataset {} has {} dangling references", imported_data.dataset.id, report.dangling_references);
+    }
+
+    // Datasets with errors are not usable, but other datasets might still be
+    let skip = violations.iter().any(|violations| violations.severity == Severity::Error);
+    if skip {
+        error!(target: "validation", "Skipping dataset {} due to validation errors", imported_data.dataset.id);
+    }
+
+    let extra = if imported_data.dataset.drop_implausible_stops {
+        drop_implausible_stops(&rules, &imported_data.dataset, extra)?
+    } else {
+        extra
+    };
+
     Ok(ValidateStepOutput {
         dataset: imported_data.dataset,
-        extra: imported_data.extra,
-        skip: false // TODO: Decide based on validation errors
+        extra,
+        violations,
+        skip,
     })
 }
 
-async fn validate_gtfs() -> Result<(Dataset, ), Error> {
-    /*match files_read_result {
-        Ok(_) => {
-            for rule in gtfs_rules() {
-                let violations = rule.get_violations(&ctx).await.expect("Unable to verify rules");
-                let count = violations.clone().count().await.expect("Unable to count violations");
-                if count > 0 {
-                    println!("{count} violations against rule '{:?}' (first 10 samples):", rule);
-                    violations.show_limit(10).await.expect("Unable to display violations");
-                }
-            }
+/// Runs all rules that are not ignored for this dataset and returns the violated ones. Every rule
+/// is a step of `task`.
+fn check_rules(
+    rules: &[Box<dyn Rule>],
+    dataset: &Dataset,
+    data: &ImportStepExtra,
+    task: &dyn ProgressTask,
+) -> Result<Vec<RuleViolations>, ValidateError> {
+    let mut all_violations = vec![];
+
+    for rule in rules {
+        task.inc(1);
+        let severity = severity_for(rule.as_ref(), dataset);
+        if severity == Severity::Ignore {
+            continue;
         }
-        Err(error) => {
-            match error {
-                DataFusionError::SchemaError(error, ..) => {
-                    println!("{}", error)
-                }
-                _ => panic!("Unknown error while reading files: {}", error),
-            }
+
+        let violations = rule.violations(data)?;
+        let count = df::count(violations.clone())?;
+
+        if count > 0 {
+            let rows = if violations.clone().collect_schema()?.contains(ROW_IN_FILE) {
+                violations.clone()
+                    .select([col(ROW_IN_FILE).cast(DataType::UInt32)])
+                    .collect()?
+                    .column(ROW_IN_FILE)?.u32()?
+                    .to_vec()
+            } else {
+                vec![None; count as usize]
+            };
+
+            all_violations.push(RuleViolations {
+                rule_id: rule.id(),
+                file: rule.file(),
+                description: rule.description(),
+                severity,
+                is_reference: rule.checks_reference(),
+                suggestion: rule.suggestion(),
+                count,
+                rows,
+                samples: violations.limit(MAX_SAMPLES).collect()?,
+            });
         }
-    }*/
+    }
+
+    Ok(all_violations)
+}
+
+/// Removes all stops that violate a rule checking their location. Stop times at these stops become
+/// dangling and are dropped later on.
+fn drop_implausible_stops(
+    rules: &[Box<dyn Rule>],
+    dataset: &Dataset,
+    data: ImportStepExtra,
+) -> Result<ImportStepExtra, ValidateError> {
+    let ImportStepExtra::Gtfs { stops: all_stops, .. } = &data;
+    let mut stops = all_stops.clone();
+
+    for rule in rules.iter().filter(|rule| rule.checks_location()) {
+        if severity_for(rule.as_ref(), dataset) == Severity::Ignore {
+            continue;
+        }
+
+        stops = stops.join(
+            rule.violations(&data)?.select([col("stop_id")]),
+            [col("stop_id")],
+            [col("stop_id")],
+            JoinArgs::new(JoinType::Anti),
+        );
+    }
+
+    let stops = stops.collect()?;
+    let num_dropped = df::count(all_stops.clone())? - stops.height() as u32;
+    if num_dropped > 0 {
+        warn!(target: "validation", "Dropping {} stops at implausible locations from dataset {}", num_dropped, dataset.id);
+    }
 
-    Ok(todo!())
+    let ImportStepExtra::Gtfs { agencies, routes, booking_rules, calendar, trips, stop_times, frequencies, transfers, temporary_files, .. } = data;
+    Ok(ImportStepExtra::Gtfs {
+        agencies, routes, booking_rules, calendar, trips, stop_times, frequencies, transfers, temporary_files,
+        stops: stops.lazy(),
+    })
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum ValidateError {
-    //RuleViolations(Vec<Box<dyn RuleViolations/*<dyn Rule<dyn Severity>, dyn Severity>*/>>)
+    Polars(#[from] polars::error::PolarsError),
+    IO(#[from] std::io::Error),
+    Json(#[from] serde_json::Error),
 }
 
-/*impl Display for ValidateError {
+impl Display for ValidateError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        /*let err: &dyn Display = match self {
-            ValidateError::RuleViolations(violations) => violations
+        let err: &dyn Display = match self {
+            ValidateError::Polars(err) => err,
+            ValidateError::IO(err) => err,
+            ValidateError::Json(err) => err,
         };
-        write!(f, "{}", err)*/
-        write!(f, "")
+        write!(f, "{}", err)
     }
-}*/
+}
 
 pub struct ValidateStepOutput {
     pub(crate) dataset: Dataset,
     pub extra: ImportStepExtra,
-    pub(crate) skip: bool
-}
\ No newline at end of file
+    pub violations: Vec<RuleViolations>,
+    // Whether the dataset has validation errors and is left out when merging
+    pub skip: bool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::dataset::{DataSource, DatasetFormat};
+    use common::util::logging::NoProgress;
+    use polars::df;
+    use polars::prelude::{DataFrame, LazyFrame, TimeUnit};
+
+    fn test_dataset() -> Dataset {
+        Dataset {
+            id: "test".into(),
+            src: DataSource::File { path: "".into() },
+            format: DatasetFormat::Gtfs,
+            license: None,
+            group_ids: vec![],
+            validation: Default::default(),
+            bounds: None,
+            drop_implausible_stops: false,
+            fix: false,
+            overrides: vec![],
+            vehicle_positions: None,
+            trip_updates: None,
+            alerts: None,
+            credentials: None,
+            refresh: None,
+            filter: Default::default(),
+            attribution: None,
+        }
+    }
+
+    fn test_data(stops: LazyFrame) -> ImportStepExtra {
+        ImportStepExtra::Gtfs {
+            agencies: df!("agency_id" => ["a1"]).unwrap().lazy(),
+            booking_rules: DataFrame::empty().lazy(),
+            routes: df!("route_id" => ["r1"], "agency_id" => ["a1"], "route_type" => [3u32]).unwrap().lazy(),
+            calendar: df!("service_id" => ["s1"]).unwrap().lazy(),
+            stops,
+            trips: df!("trip_id" => ["t1"], "route_id" => ["r1"], "service_id" => ["s1"]).unwrap().lazy(),
+            stop_times: df!(
+                "trip_id"        => ["t1"],
+                "stop_id"        => ["a"],
+                "stop_sequence"  => [1u32],
+                "arrival_time"   => [0i64],
+                "departure_time" => [0i64],
+            ).unwrap().lazy()
+                .with_row_index(ROW_IN_FILE, Some(2))
+                .with_columns([
+                    col("arrival_time").cast(DataType::Duration(TimeUnit::Milliseconds)),
+                    col("departure_time").cast(DataType::Duration(TimeUnit::Milliseconds)),
+                ]),
+            frequencies: df!("trip_id" => ["t1"], "headway_secs" => [600u32]).unwrap().lazy(),
+            transfers: DataFrame::empty().lazy(),
+            temporary_files: vec![],
+        }
+    }
+
+    #[test]
+    fn test_severity_overrides() {
+        let mut dataset = test_dataset();
+        let data = test_data(df!(
+            "stop_id"        => ["a", "a"],
+            "stop_lat"       => [1.0f64, 1.0],
+            "stop_lon"       => [1.0f64, 1.0],
+            "parent_station" => [None::<&str>, None],
+        ).unwrap().lazy());
+
+        let violations = check_rules(&gtfs_rules(&ModeRegistry::default()), &dataset, &data, &NoProgress).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].rule_id, "unique_stop_ids");
+        assert_eq!(violations[0].severity, Severity::Error);
+        assert_eq!(violations[0].count, 2);
+
+        dataset.validation.insert("unique_stop_ids".into(), Severity::Warn);
+        let violations = check_rules(&gtfs_rules(&ModeRegistry::default()), &dataset, &data, &NoProgress).unwrap();
+        assert_eq!(violations[0].severity, Severity::Warn);
+
+        dataset.validation.insert("unique_stop_ids".into(), Severity::Ignore);
+        let violations = check_rules(&gtfs_rules(&ModeRegistry::default()), &dataset, &data, &NoProgress).unwrap();
+        assert!(violations.is_empty());
+    }
+
+    #[test]
+    fn test_drop_implausible_stops() {
+        let mut dataset = test_dataset();
+        let data = test_data(df!(
+            "stop_id"  => ["a", "b", "c"],
+            "stop_lat" => [48.7f32, 48.8, 0.0],
+            "stop_lon" => [9.1f32, 9.2, 0.0],
+        ).unwrap().lazy());
+
+        let ImportStepExtra::Gtfs { stops, .. } = drop_implausible_stops(&gtfs_rules(&ModeRegistry::default()), &dataset, data.clone()).unwrap();
+        let stops = stops.collect().unwrap();
+        assert_eq!(stops.height(), 2);
+        assert!(!stops.column("stop_id").unwrap().str().unwrap().iter().any(|id| id == Some("c")));
+
+        // Ignored rules don't drop stops
+        dataset.validation.insert("stops_not_at_null_island".into(), Severity::Ignore);
+        dataset.validation.insert("stops_near_other_stops".into(), Severity::Ignore);
+        let ImportStepExtra::Gtfs { stops, .. } = drop_implausible_stops(&gtfs_rules(&ModeRegistry::default()), &dataset, data).unwrap();
+        assert_eq!(stops.collect().unwrap().height(), 3);
+    }
+}
@@ -0,0 +1,153 @@
+use crate::step3_validate_data::fixes::AppliedFix;
+use crate::step3_validate_data::rule_violations::RuleViolations;
+use crate::step3_validate_data::ValidateError;
+use common::types::dataset::Severity;
+use serde::Serialize;
+use std::fs::{create_dir_all, File};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::Path;
+
+/// Machine-readable list of all findings of the validation of a single dataset
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    pub dataset_id: String,
+    // Number of references to rows of other files that don't exist
+    pub dangling_references: u32,
+    pub findings: Vec<Finding>,
+    // Repairs applied to the dataset before it was validated
+    pub fixes: Vec<AppliedFix>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub rule_id: &'static str,
+    pub severity: Severity,
+    pub file: &'static str,
+    // Line in the file, if known
+    pub row: Option<u32>,
+    pub description: &'static str,
+    pub suggestion: Option<&'static str>,
+}
+
+impl ValidationReport {
+    pub fn new(dataset_id: String, violations: &[RuleViolations], fixes: Vec<AppliedFix>) -> Self {
+        let findings = violations.iter()
+            .flat_map(|violations| {
+                violations.rows.iter().map(|row| Finding {
+                    rule_id: violations.rule_id,
+                    severity: violations.severity,
+                    file: violations.file,
+                    row: *row,
+                    description: violations.description,
+                    suggestion: violations.suggestion,
+                })
+            })
+            .collect();
+
+        let dangling_references = violations.iter()
+            .filter(|violations| violations.is_reference)
+            .map(|violations| violations.count)
+            .sum();
+
+        Self { dataset_id, dangling_references, findings, fixes }
+    }
+
+    pub fn write_json(&self, path: &Path) -> Result<(), ValidateError> {
+        create_dir_all(path.parent().unwrap())?;
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn write_html(&self, path: &Path) -> Result<(), ValidateError> {
+        create_dir_all(path.parent().unwrap())?;
+        let mut file = File::create(path)?;
+        file.write_all(self.to_html().as_bytes())?;
+        Ok(())
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut rows = String::new();
+        for finding in &self.findings {
+            writeln!(
+                rows,
+                "<tr><td>{}</td><td>{:?}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(finding.rule_id),
+                finding.severity,
+                escape_html(finding.file),
+                finding.row.map(|row| row.to_string()).unwrap_or_default(),
+                escape_html(finding.description),
+                escape_html(finding.suggestion.unwrap_or_default()),
+            ).unwrap();
+        }
+
+        let mut fix_rows = String::new();
+        for fix in &self.fixes {
+            writeln!(
+                fix_rows,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(fix.fix_id),
+                escape_html(fix.file),
+                fix.count,
+                escape_html(fix.description),
+            ).unwrap();
+        }
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Validation report for {id}</title></head>\n<body>\n<h1>Validation report for {id}</h1>\n<p>{count} findings, {dangling_references} dangling references</p>\n<table>\n<tr><th>Rule</th><th>Severity</th><th>File</th><th>Row</th><th>Description</th><th>Suggestion</th></tr>\n{rows}</table>\n<h2>Applied fixes</h2>\n<table>\n<tr><th>Fix</th><th>File</th><th>Rows</th><th>Description</th></tr>\n{fix_rows}</table>\n</body>\n</html>\n",
+            id = escape_html(&self.dataset_id),
+            count = self.findings.len(),
+            dangling_references = self.dangling_references,
+        )
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::frame::DataFrame;
+
+    #[test]
+    fn test_report_lists_every_row() {
+        let violations = vec![RuleViolations {
+            rule_id: "unique_stop_ids",
+            file: "stops.txt",
+            description: "Every stop_id must be unique",
+            severity: Severity::Error,
+            is_reference: false,
+            suggestion: None,
+            count: 2,
+            rows: vec![Some(2), Some(5)],
+            samples: DataFrame::empty(),
+        }];
+
+        let fixes = vec![AppliedFix {
+            fix_id: "trim_ids",
+            file: "*.txt",
+            description: "Removed whitespace around identifiers",
+            count: 3,
+        }];
+
+        let report = ValidationReport::new("<test>".into(), &violations, fixes);
+        assert_eq!(report.findings.len(), 2);
+        assert_eq!(report.findings[1].row, Some(5));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["findings"][0]["rule_id"], "unique_stop_ids");
+        assert_eq!(json["findings"][0]["severity"], "error");
+        assert_eq!(json["findings"][0]["row"], 2);
+
+        assert_eq!(json["fixes"][0]["count"], 3);
+
+        assert!(report.to_html().contains("Validation report for &lt;test&gt;"));
+        assert!(report.to_html().contains("<td>trim_ids</td>"));
+    }
+}
@@ -1,10 +1,9 @@
-pub trait Severity {}
+use crate::step3_validate_data::rules::Rule;
+use common::types::dataset::{Dataset, Severity};
 
-pub struct FatalSeverity {}
-impl Severity for FatalSeverity {}
-
-pub struct HighSeverity {}
-impl Severity for HighSeverity {}
-
-pub struct LowSeverity {}
-impl Severity for LowSeverity {}
\ No newline at end of file
+/// The severity of a rule for a dataset, taking the overrides of the dataset config into account
+pub fn severity_for(rule: &dyn Rule, dataset: &Dataset) -> Severity {
+    dataset.validation.get(rule.id())
+        .copied()
+        .unwrap_or(rule.default_severity())
+}
@@ -1 +1,22 @@
-pub trait RuleViolations/*<R: Rule<S>, S: Severity>*/ {}
+use common::types::dataset::Severity;
+use polars::frame::DataFrame;
+
+/// Number of violating rows kept per rule, so that huge datasets don't blow up memory
+pub const MAX_SAMPLES: u32 = 10;
+
+/// All violations of a single rule in a dataset
+#[derive(Debug, Clone)]
+pub struct RuleViolations {
+    pub rule_id: &'static str,
+    pub file: &'static str,
+    pub description: &'static str,
+    pub severity: Severity,
+    // Whether the violations are dangling references to other files
+    pub is_reference: bool,
+    pub suggestion: Option<&'static str>,
+    pub count: u32,
+    // Line in the file of every violating row, if known
+    pub rows: Vec<Option<u32>>,
+    // The first violating rows
+    pub samples: DataFrame,
+}
@@ -1,43 +1,651 @@
-/*use std::fmt::Debug;
-use async_trait::async_trait;
-use crate::data_preprocessing::step3_validate_data::rule_severity::{FatalSeverity, Severity};
+use crate::step2_import_data::ImportStepExtra;
+use crate::step2_import_data::ROW_IN_FILE;
+use common::types::dataset::{BoundingBox, Dataset, Severity};
+use common::types::mode::ModeRegistry;
+use common::util::speed::{Speed, MAX_SPEED};
+use polars::datatypes::DataType;
+use polars::error::PolarsError;
+use polars::frame::DataFrame;
+use polars::prelude::{col, concat, len, lit, Column, Expr, IntoLazy, JoinArgs, JoinType, LazyFrame, SortMultipleOptions, UnionArgs, UniqueKeepStrategy};
 
-pub fn gtfs_rules() -> Vec<Box<dyn Rule<impl Severity>>> {
-    vec![
-        Box::new(UniqueRouteIds),
+/// A single check of a dataset. Every rule has a stable id, under which its severity can be
+/// overridden in the config of a dataset.
+pub trait Rule: Sync + Send {
+    fn id(&self) -> &'static str;
+
+    /// The file of the dataset this rule checks
+    fn file(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    fn default_severity(&self) -> Severity;
+
+    /// Whether this rule checks references between files. Violations of these rules are dangling
+    /// references.
+    fn checks_reference(&self) -> bool { false }
+
+    /// Whether this rule checks the location of stops. Stops violating these rules can be dropped.
+    fn checks_location(&self) -> bool { false }
+
+    /// How violations of this rule can usually be fixed
+    fn suggestion(&self) -> Option<&'static str> { None }
+
+    /// All rows violating this rule. An empty frame means the rule is fulfilled.
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError>;
+}
+
+pub fn gtfs_rules(modes: &ModeRegistry) -> Vec<Box<dyn Rule>> {
+    let rules: Vec<Box<dyn Rule>> = vec![
         Box::new(UniqueStopIds),
+        Box::new(UniqueTripIds),
+        Box::new(StopsWithCoordinates),
+        Box::new(FrequenciesWithPositiveHeadway),
+        Box::new(PlausibleSpeed::too_fast(modes)),
+        Box::new(PlausibleSpeed::too_slow(modes)),
+        Box::new(StopsNotAtNullIsland),
+        Box::new(StopsNearOtherStops),
+    ];
+
+    rules.into_iter()
+        .chain(reference_rules().into_iter().map(|rule| Box::new(rule) as Box<dyn Rule>))
+        .collect()
+}
+
+/// Rules that depend on the config of a dataset
+pub fn dataset_rules(dataset: &Dataset) -> Vec<Box<dyn Rule>> {
+    let mut rules: Vec<Box<dyn Rule>> = vec![];
+    if let Some(bounds) = dataset.bounds {
+        rules.push(Box::new(StopsInsideBounds(bounds)));
+    }
+    rules
+}
+
+/// Rows whose value in `column` occurs more than once
+fn duplicates(frame: LazyFrame, column: &str) -> LazyFrame {
+    frame
+        .filter(len().over([col(column)]).gt(lit(1)))
+}
+
+struct UniqueStopIds;
+
+impl Rule for UniqueStopIds {
+    fn id(&self) -> &'static str { "unique_stop_ids" }
+    fn file(&self) -> &'static str { "stops.txt" }
+    fn description(&self) -> &'static str { "Every stop_id must be unique" }
+    fn default_severity(&self) -> Severity { Severity::Error }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        match data {
+            ImportStepExtra::Gtfs { stops, .. } => Ok(duplicates(stops.clone(), "stop_id")),
+        }
+    }
+}
+
+struct UniqueTripIds;
+
+impl Rule for UniqueTripIds {
+    fn id(&self) -> &'static str { "unique_trip_ids" }
+    fn file(&self) -> &'static str { "trips.txt" }
+    fn description(&self) -> &'static str { "Every trip_id must be unique" }
+    fn default_severity(&self) -> Severity { Severity::Error }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        match data {
+            ImportStepExtra::Gtfs { trips, .. } => Ok(duplicates(trips.clone(), "trip_id")),
+        }
+    }
+}
+
+struct StopsWithCoordinates;
+
+impl Rule for StopsWithCoordinates {
+    fn id(&self) -> &'static str { "stops_with_coordinates" }
+    fn file(&self) -> &'static str { "stops.txt" }
+    fn description(&self) -> &'static str { "Every stop must have a latitude and longitude" }
+    fn default_severity(&self) -> Severity { Severity::Warn }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        match data {
+            ImportStepExtra::Gtfs { stops, .. } => Ok(
+                stops.clone().filter(col("stop_lat").is_null().or(col("stop_lon").is_null()))
+            ),
+        }
+    }
+}
+
+/// Checks that every value of `column` in the referencing table exists in `referenced_column` of the
+/// referenced table. Empty (optional) references are not checked.
+struct KnownReference {
+    id: &'static str,
+    file: &'static str,
+    description: &'static str,
+    severity: Severity,
+    column: &'static str,
+    referenced_column: &'static str,
+    // Returns the referencing and the referenced table
+    tables: fn(&ImportStepExtra) -> (LazyFrame, LazyFrame),
+}
+
+impl Rule for KnownReference {
+    fn id(&self) -> &'static str { self.id }
+    fn file(&self) -> &'static str { self.file }
+    fn description(&self) -> &'static str { self.description }
+    fn default_severity(&self) -> Severity { self.severity }
+    fn checks_reference(&self) -> bool { true }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        let (referencing, referenced) = (self.tables)(data);
+
+        Ok(referencing
+            .filter(col(self.column).is_not_null())
+            .join(
+                referenced.select([col(self.referenced_column)]),
+                [col(self.column)],
+                [col(self.referenced_column)],
+                JoinArgs::new(JoinType::Anti),
+            ))
+    }
+}
+
+fn reference_rules() -> Vec<KnownReference> {
+    vec![
+        KnownReference {
+            id: "stop_times_with_known_stop",
+            file: "stop_times.txt",
+            description: "Every stop_id of a stop time must exist in stops.txt",
+            severity: Severity::Error,
+            column: "stop_id",
+            referenced_column: "stop_id",
+            tables: |data| match data {
+                ImportStepExtra::Gtfs { stop_times, stops, .. } => (stop_times.clone(), stops.clone()),
+            },
+        },
+        KnownReference {
+            id: "stop_times_with_known_trip",
+            file: "stop_times.txt",
+            description: "Every trip_id of a stop time must exist in trips.txt",
+            severity: Severity::Error,
+            column: "trip_id",
+            referenced_column: "trip_id",
+            tables: |data| match data {
+                ImportStepExtra::Gtfs { stop_times, trips, .. } => (stop_times.clone(), trips.clone()),
+            },
+        },
+        KnownReference {
+            id: "trips_with_known_service",
+            file: "trips.txt",
+            description: "Every service_id of a trip must exist in calendar.txt",
+            // Services might also be defined in calendar_dates.txt only, which isn't imported yet
+            severity: Severity::Warn,
+            column: "service_id",
+            referenced_column: "service_id",
+            tables: |data| match data {
+                ImportStepExtra::Gtfs { trips, calendar, .. } => (trips.clone(), calendar.clone()),
+            },
+        },
+        KnownReference {
+            id: "stops_with_known_parent_station",
+            file: "stops.txt",
+            description: "Every parent_station of a stop must exist in stops.txt",
+            severity: Severity::Warn,
+            column: "parent_station",
+            referenced_column: "stop_id",
+            tables: |data| match data {
+                ImportStepExtra::Gtfs { stops, .. } => (stops.clone(), stops.clone()),
+            },
+        },
+        KnownReference {
+            id: "frequencies_with_known_trip",
+            file: "frequencies.txt",
+            description: "Every trip_id of a frequency must exist in trips.txt",
+            severity: Severity::Warn,
+            column: "trip_id",
+            referenced_column: "trip_id",
+            tables: |data| match data {
+                ImportStepExtra::Gtfs { frequencies, trips, .. } => (frequencies.clone(), trips.clone()),
+            },
+        },
     ]
 }
 
-#[async_trait]
-pub trait Rule<S: Severity>: Debug {
-    //async fn get_violations(&self, ctx: &SessionContext) -> Result<DataFrame>;
+struct FrequenciesWithPositiveHeadway;
+
+impl Rule for FrequenciesWithPositiveHeadway {
+    fn id(&self) -> &'static str { "frequencies_with_positive_headway" }
+    fn file(&self) -> &'static str { "frequencies.txt" }
+    fn description(&self) -> &'static str { "The headway of a frequency must be larger than zero" }
+    fn default_severity(&self) -> Severity { Severity::Warn }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        match data {
+            ImportStepExtra::Gtfs { frequencies, .. } => Ok(
+                frequencies.clone().filter(col("headway_secs").eq(lit(0)))
+            ),
+        }
+    }
 }
 
-#[derive(Debug)]
-struct UniqueRouteIds;
+// Slower vehicles than this are most likely caused by wrong times or coordinates
+const MIN_PLAUSIBLE_SPEED: Speed = Speed(1.0);
+// Very short distances are also travelled slowly, e.g. within large stations
+const MIN_DISTANCE_FOR_SLOW_SPEED: f64 = 1_000.0;
+// Times in GTFS are usually rounded to full minutes, so consecutive stops often share the same time
+const TIME_ROUNDING_TOLERANCE_MS: i64 = 60 * 1_000;
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+/// Great-circle distance in meters between two coordinates given in degrees
+fn haversine_distance(lat_a: Expr, lon_a: Expr, lat_b: Expr, lon_b: Expr) -> Expr {
+    let to_radians = |degrees: Expr| degrees.cast(DataType::Float64) * lit(std::f64::consts::PI / 180.0);
+    let (lat_a, lon_a, lat_b, lon_b) = (to_radians(lat_a), to_radians(lon_a), to_radians(lat_b), to_radians(lon_b));
 
-#[async_trait]
-impl Rule<FatalSeverity> for UniqueRouteIds {
-    /*async fn get_violations(&self, ctx: &SessionContext) -> Result<DataFrame> {
-        Ok(ctx.table("routes").await?.get_and_count_duplicated("route_id".into(), "COUNT(routes.route_id)")?)
-    }*/
+    let half_lat = ((lat_b.clone() - lat_a.clone()) / lit(2.0)).sin();
+    let half_lon = ((lon_b - lon_a) / lit(2.0)).sin();
+    let a = half_lat.clone() * half_lat + lat_a.cos() * lat_b.cos() * half_lon.clone() * half_lon;
+
+    lit(2.0 * EARTH_RADIUS) * a.sqrt().arcsin()
 }
 
-#[derive(Debug)]
-struct UniqueStopIds;
-#[async_trait]
-impl Rule<FatalSeverity> for UniqueStopIds {
-/*async fn get_violations(&self, ctx: &SessionContext) -> Result<DataFrame> {
-    Ok(ctx.table("stops").await?.get_and_count_duplicated("stop_id".into(), "COUNT(stops.stop_id)")?)
-}*/
-}
-
-#[derive(Debug)]
-struct UniqueAgencyIds;
-#[async_trait]
-impl Rule<FatalSeverity> for UniqueAgencyIds {
-/*async fn get_violations(&self, ctx: &SessionContext) -> Result<DataFrame> {
-    Ok(ctx.table("agency").await?.get_and_count_duplicated("agency_id".into(), "COUNT(agency.agency_id)")?)
-}*/
-}*/
\ No newline at end of file
+/// Checks the speed a vehicle needs to travel between consecutive stops of a trip, based on the
+/// straight-line distance of the stops. Wrong speeds break the assumption of the routing that no
+/// vehicle is faster than [MAX_SPEED].
+struct PlausibleSpeed {
+    id: &'static str,
+    description: &'static str,
+    suggestion: &'static str,
+    // Whether a segment with the given speed in km/h, distance in meters and maximum speed of the
+    // mode of the trip in km/h violates the rule
+    is_violation: fn(Expr, Expr, Expr) -> Expr,
+    modes: ModeRegistry,
+}
+
+impl PlausibleSpeed {
+    fn too_fast(modes: &ModeRegistry) -> Self {
+        Self {
+            id: "stop_times_below_max_speed",
+            description: "Vehicles must not travel faster than the maximum speed of their mode between two stops",
+            suggestion: "Check the coordinates of both stops and the times of the stop times, a stop might be placed at the wrong location",
+            is_violation: |speed, _, max_speed| speed.gt(max_speed),
+            modes: modes.clone(),
+        }
+    }
+
+    fn too_slow(modes: &ModeRegistry) -> Self {
+        Self {
+            id: "stop_times_above_min_speed",
+            description: "Vehicles should not travel absurdly slow between two distant stops",
+            suggestion: "Check the times of the stop times, the arrival might be on the wrong day or the time might be off by hours",
+            is_violation: |speed, distance, _| {
+                speed.lt(lit(MIN_PLAUSIBLE_SPEED.0)).and(distance.gt_eq(lit(MIN_DISTANCE_FOR_SLOW_SPEED)))
+            },
+            modes: modes.clone(),
+        }
+    }
+
+    // The maximum speed in km/h of the mode of every trip (columns "trip_id", "max_speed_kmh")
+    fn max_speeds(&self, routes: LazyFrame, trips: LazyFrame) -> Result<LazyFrame, PolarsError> {
+        let routes = routes
+            .select([col("route_id"), col("route_type").cast(DataType::UInt32)])
+            .collect()?;
+        let max_speeds: Vec<f64> = routes.column("route_type")?.u32()?.iter()
+            .map(|route_type| route_type.map_or(MAX_SPEED, |route_type| self.modes.max_speed(route_type)).0)
+            .collect();
+        let max_speeds = DataFrame::new(vec![
+            routes.column("route_id")?.clone(),
+            Column::new("max_speed_kmh".into(), max_speeds),
+        ])?;
+
+        Ok(trips
+            .select([col("trip_id"), col("route_id")])
+            .join(max_speeds.lazy(), [col("route_id")], [col("route_id")], JoinArgs::new(JoinType::Inner))
+            // Duplicate trip ids are reported by another rule
+            .unique_stable(Some(vec!["trip_id".into()]), UniqueKeepStrategy::First)
+            .select([col("trip_id"), col("max_speed_kmh")]))
+    }
+}
+
+impl Rule for PlausibleSpeed {
+    fn id(&self) -> &'static str { self.id }
+    fn file(&self) -> &'static str { "stop_times.txt" }
+    fn description(&self) -> &'static str { self.description }
+    fn default_severity(&self) -> Severity { Severity::Warn }
+    fn suggestion(&self) -> Option<&'static str> { Some(self.suggestion) }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        let ImportStepExtra::Gtfs { stop_times, stops, routes, trips, .. } = data;
+
+        let previous = |column: &str| col(column).shift(lit(1)).over([col("trip_id")]);
+        let travel_time_ms = col("arrival_time").cast(DataType::Int64)
+            - previous("departure_time").cast(DataType::Int64);
+
+        let segments = stop_times.clone()
+            .join(
+                stops.clone().select([col("stop_id"), col("stop_lat"), col("stop_lon")]),
+                [col("stop_id")],
+                [col("stop_id")],
+                JoinArgs::new(JoinType::Inner),
+            )
+            .join(
+                self.max_speeds(routes.clone(), trips.clone())?,
+                [col("trip_id")],
+                [col("trip_id")],
+                JoinArgs::new(JoinType::Left),
+            )
+            .sort(["trip_id", "stop_sequence"], SortMultipleOptions::default())
+            .with_columns([
+                // Trips without a known route can't have a known mode
+                col("max_speed_kmh").fill_null(lit(MAX_SPEED.0)),
+                haversine_distance(
+                    previous("stop_lat"), previous("stop_lon"), col("stop_lat"), col("stop_lon"),
+                ).alias("distance_m"),
+                (travel_time_ms / lit(1_000)).alias("travel_time_s"),
+            ])
+            .with_column(
+                ((col("distance_m") / lit(1_000.0))
+                    / ((col("travel_time_s").cast(DataType::Float64) + lit(TIME_ROUNDING_TOLERANCE_MS as f64 / 1_000.0))
+                    / lit(60.0 * 60.0)))
+                    .alias("speed_kmh")
+            );
+
+        // The first stop of every trip has no previous stop and thus no speed
+        Ok(segments
+            .filter((self.is_violation)(col("speed_kmh"), col("distance_m"), col("max_speed_kmh")).fill_null(lit(false)))
+            .select([
+                col(ROW_IN_FILE), col("trip_id"), col("stop_id"), col("stop_sequence"),
+                col("distance_m"), col("travel_time_s"), col("speed_kmh"), col("max_speed_kmh"),
+            ]))
+    }
+}
+
+struct StopsNotAtNullIsland;
+
+impl Rule for StopsNotAtNullIsland {
+    fn id(&self) -> &'static str { "stops_not_at_null_island" }
+    fn file(&self) -> &'static str { "stops.txt" }
+    fn description(&self) -> &'static str { "Stops must not be located at 0,0" }
+    fn default_severity(&self) -> Severity { Severity::Warn }
+    fn checks_location(&self) -> bool { true }
+    fn suggestion(&self) -> Option<&'static str> {
+        Some("The coordinates are most likely missing in the source data, set them or remove the stop")
+    }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        let ImportStepExtra::Gtfs { stops, .. } = data;
+        Ok(stops.clone().filter(col("stop_lat").eq(lit(0.0)).and(col("stop_lon").eq(lit(0.0)))))
+    }
+}
+
+struct StopsInsideBounds(BoundingBox);
+
+impl Rule for StopsInsideBounds {
+    fn id(&self) -> &'static str { "stops_inside_bounds" }
+    fn file(&self) -> &'static str { "stops.txt" }
+    fn description(&self) -> &'static str { "Every stop must be inside the declared bounds of the dataset" }
+    fn default_severity(&self) -> Severity { Severity::Warn }
+    fn checks_location(&self) -> bool { true }
+    fn suggestion(&self) -> Option<&'static str> {
+        Some("Check whether latitude and longitude are swapped or the bounds of the dataset are too small")
+    }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        let ImportStepExtra::Gtfs { stops, .. } = data;
+        Ok(stops.clone().filter(inside_bounds(&self.0).not()))
+    }
+}
+
+/// Whether the stop of a row of stops.txt is inside the bounds
+pub(crate) fn inside_bounds(bounds: &BoundingBox) -> Expr {
+    let BoundingBox { min_lat, min_lon, max_lat, max_lon } = *bounds;
+    col("stop_lat").gt_eq(lit(min_lat))
+        .and(col("stop_lat").lt_eq(lit(max_lat)))
+        .and(col("stop_lon").gt_eq(lit(min_lon)))
+        .and(col("stop_lon").lt_eq(lit(max_lon)))
+}
+
+// Size of the grid cells in degrees that are used to find isolated stops. A stop that is the only
+// one in its cell and all adjacent cells is at least ~190km (the width of a cell at 70° latitude)
+// away from every other stop.
+const ISOLATION_CELL_SIZE: f64 = 5.0;
+
+/// Stops that are far away from every other stop of the dataset, e.g. a single stop in the ocean
+struct StopsNearOtherStops;
+
+impl Rule for StopsNearOtherStops {
+    fn id(&self) -> &'static str { "stops_near_other_stops" }
+    fn file(&self) -> &'static str { "stops.txt" }
+    fn description(&self) -> &'static str { "Every stop should have another stop within a few hundred kilometers" }
+    fn default_severity(&self) -> Severity { Severity::Warn }
+    fn checks_location(&self) -> bool { true }
+    fn suggestion(&self) -> Option<&'static str> {
+        Some("Check the coordinates of the stop, they might be swapped or have a wrong sign")
+    }
+
+    fn violations(&self, data: &ImportStepExtra) -> Result<LazyFrame, PolarsError> {
+        let ImportStepExtra::Gtfs { stops, .. } = data;
+
+        // Shift coordinates to be positive, so that casting rounds down. Cells don't wrap around
+        // the antimeridian, which only leads to missed stops.
+        let cell = |column: &str, shift: f64| {
+            ((col(column).cast(DataType::Float64) + lit(shift)) / lit(ISOLATION_CELL_SIZE)).cast(DataType::Int32)
+        };
+        let stops = stops.clone()
+            .filter(col("stop_lat").is_not_null().and(col("stop_lon").is_not_null()))
+            .with_columns([
+                cell("stop_lat", 90.0).alias("cell_lat"),
+                cell("stop_lon", 180.0).alias("cell_lon"),
+            ]);
+
+        let stops_per_cell = stops.clone()
+            .group_by([col("cell_lat"), col("cell_lon")])
+            .agg([len().alias("num_stops")]);
+
+        // Every cell contributes its stops to itself and its adjacent cells
+        let neighbourhoods = (-1..=1)
+            .flat_map(|lat_offset| (-1..=1).map(move |lon_offset| (lat_offset, lon_offset)))
+            .map(|(lat_offset, lon_offset)| {
+                stops_per_cell.clone().select([
+                    (col("cell_lat") + lit(lat_offset)).alias("cell_lat"),
+                    (col("cell_lon") + lit(lon_offset)).alias("cell_lon"),
+                    col("num_stops"),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let stops_nearby = concat(neighbourhoods, UnionArgs::default())?
+            .group_by([col("cell_lat"), col("cell_lon")])
+            .agg([col("num_stops").sum().alias("num_stops_nearby")]);
+
+        Ok(stops
+            .join(
+                stops_nearby,
+                [col("cell_lat"), col("cell_lon")],
+                [col("cell_lat"), col("cell_lon")],
+                JoinArgs::new(JoinType::Inner),
+            )
+            // The stop itself is always counted
+            .filter(col("num_stops_nearby").lt_eq(lit(1)))
+            .drop(["cell_lat", "cell_lon", "num_stops_nearby"]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use polars::prelude::{DataFrame, IntoLazy, TimeUnit};
+
+    // Turns the times given in milliseconds into durations and adds the rows, like they are imported
+    fn stop_times(frame: DataFrame) -> LazyFrame {
+        frame.lazy().with_row_index(ROW_IN_FILE, Some(2)).with_columns([
+            col("arrival_time").cast(DataType::Duration(TimeUnit::Milliseconds)),
+            col("departure_time").cast(DataType::Duration(TimeUnit::Milliseconds)),
+        ])
+    }
+
+    fn gtfs_data() -> ImportStepExtra {
+        ImportStepExtra::Gtfs {
+            agencies: df!("agency_id" => ["a1"]).unwrap().lazy(),
+            booking_rules: DataFrame::empty().lazy(),
+            routes: df!("route_id" => ["r1", "r2"], "agency_id" => ["a1", "a1"], "route_type" => [2u32, 3]).unwrap().lazy(),
+            calendar: df!("service_id" => ["s1"]).unwrap().lazy(),
+            stops: df!(
+                "stop_id"        => ["a", "b", "b"],
+                "stop_lat"       => [Some(1.0f64), None, Some(2.0)],
+                "stop_lon"       => [Some(1.0f64), Some(1.0), Some(2.0)],
+                "parent_station" => [None, Some("a"), Some("p")],
+            ).unwrap().lazy(),
+            trips: df!(
+                "trip_id"    => ["t1", "t2"],
+                "route_id"   => ["r1", "r1"],
+                "service_id" => ["s1", "s2"],
+            ).unwrap().lazy(),
+            stop_times: stop_times(df!(
+                "trip_id"        => ["t1", "t1", "t3"],
+                "stop_id"        => ["a", "c", "b"],
+                "stop_sequence"  => [1u32, 2, 1],
+                "arrival_time"   => [0i64, 60_000, 0],
+                "departure_time" => [0i64, 60_000, 0],
+            ).unwrap()),
+            frequencies: df!(
+                "trip_id"      => ["t1", "t4"],
+                "headway_secs" => [600u32, 0],
+            ).unwrap().lazy(),
+            transfers: DataFrame::empty().lazy(),
+            temporary_files: vec![],
+        }
+    }
+
+    fn count_violations(rule_id: &str) -> usize {
+        let rule = gtfs_rules(&ModeRegistry::default()).into_iter().find(|rule| rule.id() == rule_id).unwrap();
+        rule.violations(&gtfs_data()).unwrap().collect().unwrap().height()
+    }
+
+    #[test]
+    fn test_gtfs_rules() {
+        assert_eq!(count_violations("unique_stop_ids"), 2);
+        assert_eq!(count_violations("unique_trip_ids"), 0);
+        assert_eq!(count_violations("stops_with_coordinates"), 1);
+        assert_eq!(count_violations("frequencies_with_positive_headway"), 1);
+    }
+
+    #[test]
+    fn test_reference_rules() {
+        assert_eq!(count_violations("stop_times_with_known_stop"), 1);
+        assert_eq!(count_violations("stop_times_with_known_trip"), 1);
+        assert_eq!(count_violations("trips_with_known_service"), 1);
+        // Stops without a parent station are fine
+        assert_eq!(count_violations("stops_with_known_parent_station"), 1);
+        assert_eq!(count_violations("frequencies_with_known_trip"), 1);
+        assert!(reference_rules().iter().all(|rule| rule.checks_reference()));
+    }
+
+    #[test]
+    fn test_speed_rules() {
+        let ImportStepExtra::Gtfs { agencies, routes, booking_rules, calendar, trips, frequencies, transfers, temporary_files, .. } = gtfs_data();
+        let data = ImportStepExtra::Gtfs {
+            agencies, routes, booking_rules, calendar, trips, frequencies, transfers, temporary_files,
+            // Stops are roughly 11km apart
+            stops: df!(
+                "stop_id"  => ["a", "b", "c"],
+                "stop_lat" => [48.0f32, 48.1, 48.2],
+                "stop_lon" => [9.0f32, 9.0, 9.0],
+            ).unwrap().lazy(),
+            stop_times: stop_times(df!(
+                "trip_id"        => ["t1", "t1", "t1", "t2", "t2", "t3", "t3"],
+                "stop_id"        => ["a", "b", "c", "a", "b", "a", "b"],
+                "stop_sequence"  => [1u32, 2, 3, 1, 2, 1, 2],
+                // t1 takes 10 minutes per segment, t2 "jumps" to the next stop (which is still too
+                // fast with a minute of rounding tolerance) and t3 takes a whole day
+                "arrival_time"   => [0i64, 600_000, 1_200_000, 0, 0, 0, 86_400_000],
+                "departure_time" => [0i64, 600_000, 1_200_000, 0, 0, 0, 86_400_000],
+            ).unwrap()),
+        };
+
+        let modes = ModeRegistry::default();
+        let too_fast = PlausibleSpeed::too_fast(&modes).violations(&data).unwrap().collect().unwrap();
+        let too_slow = PlausibleSpeed::too_slow(&modes).violations(&data).unwrap().collect().unwrap();
+
+        assert_eq!(too_fast.column("trip_id").unwrap().str().unwrap().iter().collect::<Vec<_>>(), [Some("t2")]);
+        assert_eq!(too_fast.column(ROW_IN_FILE).unwrap().u32().unwrap().to_vec(), [Some(6)]);
+        assert_eq!(too_slow.column("trip_id").unwrap().str().unwrap().iter().collect::<Vec<_>>(), [Some("t3")]);
+    }
+
+    #[test]
+    fn test_speed_of_mode() {
+        let ImportStepExtra::Gtfs { agencies, routes, booking_rules, calendar, frequencies, transfers, temporary_files, .. } = gtfs_data();
+        let data = ImportStepExtra::Gtfs {
+            agencies, routes, booking_rules, calendar, frequencies, transfers, temporary_files,
+            // Stops are roughly 11km apart
+            stops: df!(
+                "stop_id"  => ["a", "b"],
+                "stop_lat" => [48.0f32, 48.1],
+                "stop_lon" => [9.0f32, 9.0],
+            ).unwrap().lazy(),
+            // A train and a bus at ~220 km/h
+            trips: df!(
+                "trip_id"    => ["t1", "t2"],
+                "route_id"   => ["r1", "r2"],
+                "service_id" => ["s1", "s1"],
+            ).unwrap().lazy(),
+            stop_times: stop_times(df!(
+                "trip_id"        => ["t1", "t1", "t2", "t2"],
+                "stop_id"        => ["a", "b", "a", "b"],
+                "stop_sequence"  => [1u32, 2, 1, 2],
+                "arrival_time"   => [0i64, 120_000, 0, 120_000],
+                "departure_time" => [0i64, 120_000, 0, 120_000],
+            ).unwrap()),
+        };
+
+        let too_fast = PlausibleSpeed::too_fast(&ModeRegistry::default()).violations(&data).unwrap().collect().unwrap();
+        assert_eq!(too_fast.column("trip_id").unwrap().str().unwrap().iter().collect::<Vec<_>>(), [Some("t2")]);
+    }
+
+    #[test]
+    fn test_location_rules() {
+        let ImportStepExtra::Gtfs { agencies, routes, booking_rules, calendar, trips, stop_times, frequencies, transfers, temporary_files, .. } = gtfs_data();
+        let data = ImportStepExtra::Gtfs {
+            agencies, routes, booking_rules, calendar, trips, stop_times, frequencies, transfers, temporary_files,
+            stops: df!(
+                "stop_id"  => ["a", "b", "c", "d"],
+                // d is in the ocean
+                "stop_lat" => [48.7f32, 48.8, 0.0, -40.0],
+                "stop_lon" => [9.1f32, 9.2, 0.0, -30.0],
+            ).unwrap().lazy(),
+        };
+        let dataset = Dataset {
+            id: "test".into(),
+            src: common::types::dataset::DataSource::File { path: "".into() },
+            format: common::types::dataset::DatasetFormat::Gtfs,
+            license: None,
+            group_ids: vec![],
+            validation: Default::default(),
+            bounds: Some(BoundingBox { min_lat: 48.0, min_lon: 8.0, max_lat: 50.0, max_lon: 10.0 }),
+            drop_implausible_stops: false,
+            fix: false,
+            overrides: vec![],
+            vehicle_positions: None,
+            trip_updates: None,
+            alerts: None,
+            credentials: None,
+            refresh: None,
+            filter: Default::default(),
+            attribution: None,
+        };
+
+        let stop_ids = |rule: &dyn Rule| -> Vec<Option<String>> {
+            let violations = rule.violations(&data).unwrap().sort(["stop_id"], Default::default()).collect().unwrap();
+            violations.column("stop_id").unwrap().str().unwrap().iter().map(|id| id.map(str::to_owned)).collect()
+        };
+
+        assert_eq!(stop_ids(&StopsNotAtNullIsland), [Some("c".into())]);
+        assert_eq!(stop_ids(&StopsNearOtherStops), [Some("c".into()), Some("d".into())]);
+        assert_eq!(stop_ids(dataset_rules(&dataset)[0].as_ref()), [Some("c".into()), Some("d".into())]);
+    }
+
+    #[test]
+    fn test_rule_ids_are_unique() {
+        let rules = gtfs_rules(&ModeRegistry::default());
+        let mut ids: Vec<&str> = rules.iter().map(|rule| rule.id()).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), rules.len());
+    }
+}
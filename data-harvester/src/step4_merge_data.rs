@@ -1,56 +1,845 @@
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fmt::Display;
-use polars::prelude::{LazyFrame, lit};
+use common::types::config::MergeConfig;
+use common::types::dataset::DatasetOverride;
+use common::util::df::{write_df_to_file, FileType};
+use common::util::logging::ProgressSink;
+use common::util::paths;
+use common::util::speed::MAX_WALKING_SPEED;
+use geo::{Distance, Haversine, Point};
+use log::{info, warn};
+use tracing::instrument;
+use polars::datatypes::DataType;
+use polars::frame::DataFrame;
+use polars::prelude::{col, concat, lit, when, Column, IntoLazy, JoinArgs, JoinType, LazyFrame, TimeUnit, UnionArgs, NULL};
 use crate::step2_import_data::ImportStepExtra;
 use crate::step3_validate_data::ValidateStepOutput;
 
-pub async fn merge(input: Vec<ValidateStepOutput>) -> Result<DatasetMergeOutput, MergeError> {
-    // TODO: Actually merge datasets
-    let first = input.into_iter()
-        .filter(|data| !data.skip)
-        .next()
-        .expect("No valid dataset provided");
-    let dataset_id = first.dataset.id;
-
-    match first.extra.clone() { ImportStepExtra::Gtfs {
-        calendar, stops, trips, stop_times, ..
-    } => {
-        let services = calendar
-            .with_columns([
-                lit(dataset_id.clone()).alias("dataset_id"),
-            ]);
-        let stops = stops
-            .with_columns([
-                lit(dataset_id.clone()).alias("dataset_id"),
-            ]);
-        let trips = trips.with_column(lit(dataset_id.clone()).alias("dataset_id"));
-        let stop_times = stop_times.with_column(lit(dataset_id.clone()).alias("dataset_id"));
-
-        Ok(DatasetMergeOutput {
-            services, stops, trips, stop_times,
-            import_extra: first.extra
+// Length of a degree of latitude in meters
+const METERS_PER_DEGREE: f32 = 111_000.0;
+
+const WEEKDAYS: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+/// Every trip of a dataset with a signature of the locations and times of its stops, together with
+/// the days its service runs on
+fn trip_signatures(dataset_id: &str, data: &ImportStepExtra) -> Result<LazyFrame, MergeError> {
+    let ImportStepExtra::Gtfs { stops, trips, stop_times, calendar, .. } = data;
+
+    // Coordinates are compared with 4 decimal places (~10m), since different datasets rarely place
+    // a stop at exactly the same location. They are rounded, since casting alone truncates and puts
+    // e.g. 48.78 (a little less as a float) and 48.78001 into different places.
+    let coordinate = |column: &str| {
+        let scaled = col(column).cast(DataType::Float64) * lit(10_000.0);
+        (scaled.clone() + when(scaled.gt_eq(lit(0.0))).then(lit(0.5)).otherwise(lit(-0.5))).cast(DataType::Int64)
+    };
+
+    let stop_times = stop_times.clone()
+        .join(
+            stops.clone().select([col("stop_id"), col("stop_lat"), col("stop_lon")]),
+            [col("stop_id")],
+            [col("stop_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .sort(["trip_id", "stop_sequence"], Default::default())
+        .select([
+            col("trip_id"),
+            coordinate("stop_lat").alias("lat"),
+            coordinate("stop_lon").alias("lon"),
+            col("arrival_time").cast(DataType::Int64),
+            col("departure_time").cast(DataType::Int64),
+        ])
+        .collect()?;
+
+    // Stop times are sorted by trip, so the signature of a trip is complete once the next one starts
+    let mut trip_ids: Vec<&str> = vec![];
+    let mut signatures: Vec<String> = vec![];
+    let values = stop_times.column("trip_id")?.str()?.iter()
+        .zip(stop_times.column("lat")?.i64()?.iter())
+        .zip(stop_times.column("lon")?.i64()?.iter())
+        .zip(stop_times.column("arrival_time")?.i64()?.iter())
+        .zip(stop_times.column("departure_time")?.i64()?.iter());
+    for ((((trip_id, lat), lon), arrival), departure) in values {
+        let Some(trip_id) = trip_id else { continue };
+        if trip_ids.last() != Some(&trip_id) {
+            trip_ids.push(trip_id);
+            signatures.push(String::new());
+        }
+        let signature = signatures.last_mut().unwrap();
+        signature.push_str(&format!("{:?},{:?},{:?},{:?};", lat, lon, arrival, departure));
+    }
+    let signatures = DataFrame::new(vec![
+        Column::new("trip_id".into(), trip_ids),
+        Column::new("signature".into(), signatures),
+    ])?;
+
+    let services = calendar.clone()
+        .select([
+            vec![col("service_id"), col("start_date"), col("end_date")],
+            WEEKDAYS.iter().map(|day| col(*day)).collect(),
+        ].concat());
+
+    Ok(trips.clone()
+        .select([col("trip_id"), col("service_id")])
+        .join(signatures.lazy(), [col("trip_id")], [col("trip_id")], JoinArgs::new(JoinType::Inner))
+        .join(services, [col("service_id")], [col("service_id")], JoinArgs::new(JoinType::Inner))
+        .with_column(lit(dataset_id.to_owned()).alias("dataset_id")))
+}
+
+/// Finds trips of different datasets that serve the same stops at the same times on at least one
+/// common day. Trips that also share their id are marked as `identical_id`.
+pub fn find_duplicate_trips(datasets: &[(&str, &ImportStepExtra)]) -> Result<DataFrame, MergeError> {
+    let signatures = datasets.iter()
+        .map(|(dataset_id, data)| trip_signatures(dataset_id, data))
+        .collect::<Result<Vec<_>, MergeError>>()?;
+    // A single dataset has no duplicates, but later steps still select the columns
+    if signatures.len() < 2 {
+        let no_ids = || Vec::<String>::new();
+        return Ok(DataFrame::new(vec![
+            Column::new("dataset_id".into(), no_ids()),
+            Column::new("trip_id".into(), no_ids()),
+            Column::new("duplicate_dataset_id".into(), no_ids()),
+            Column::new("duplicate_trip_id".into(), no_ids()),
+            Column::new("identical_id".into(), Vec::<bool>::new()),
+        ])?);
+    }
+    let signatures = concat(signatures, UnionArgs::default())?;
+
+    let common_weekday = WEEKDAYS.iter()
+        .map(|day| col(*day).and(col(format!("{day}_duplicate"))))
+        .reduce(|a, b| a.or(b))
+        .unwrap();
+    let overlapping_period = col("start_date").lt_eq(col("end_date_duplicate"))
+        .and(col("start_date_duplicate").lt_eq(col("end_date")));
+
+    let duplicates = signatures.clone()
+        .join(
+            signatures,
+            [col("signature")],
+            [col("signature")],
+            JoinArgs::new(JoinType::Inner).with_suffix(Some("_duplicate".into())),
+        )
+        // Only report every pair once and ignore duplicates within a dataset
+        .filter(col("dataset_id").lt(col("dataset_id_duplicate")))
+        .filter(common_weekday.and(overlapping_period))
+        .select([
+            col("dataset_id"),
+            col("trip_id"),
+            col("dataset_id_duplicate").alias("duplicate_dataset_id"),
+            col("trip_id_duplicate").alias("duplicate_trip_id"),
+            col("trip_id").eq(col("trip_id_duplicate")).alias("identical_id"),
+        ])
+        .collect()?;
+
+    Ok(duplicates)
+}
+
+// A stop that other stops can be merged into
+struct StopCandidate {
+    dataset_id: String,
+    stop_id: String,
+    location: Point<f32>,
+    name_tokens: HashSet<String>,
+}
+
+fn name_tokens(name: &str) -> HashSet<String> {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+// Names are similar if at least half of the words of the shorter name occur in the other one, e.g.
+// "Stuttgart Hauptbahnhof" and "Hauptbahnhof (tief)"
+fn similar_names(a: &HashSet<String>, b: &HashSet<String>) -> bool {
+    let shorter = a.len().min(b.len());
+    shorter > 0 && a.intersection(b).count() * 2 >= shorter
+}
+
+// Spatial index of stop locations with cells that are at least `radius` high, so that all stops
+// within `radius` of a location are in the neighbouring cells
+struct StopGrid {
+    cell_size: f32,
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+impl StopGrid {
+    fn new(radius: f32) -> Self {
+        Self { cell_size: radius / METERS_PER_DEGREE, cells: HashMap::new() }
+    }
+
+    fn cell(&self, location: &Point<f32>) -> (i64, i64) {
+        ((location.y() / self.cell_size).floor() as i64, (location.x() / self.cell_size).floor() as i64)
+    }
+
+    fn insert(&mut self, location: &Point<f32>, index: usize) {
+        let cell = self.cell(location);
+        self.cells.entry(cell).or_default().push(index);
+    }
+
+    // Indices of all stops that might be within `radius` of the location
+    fn neighbours(&self, location: &Point<f32>) -> impl Iterator<Item = usize> + '_ {
+        // Longitude degrees get shorter towards the poles, so more cells have to be searched
+        let (cell_lat, cell_lon) = self.cell(location);
+        let lon_cells = (1.0 / location.y().to_radians().cos().max(0.01)).ceil() as i64;
+        (-1..=1)
+            .flat_map(move |d_lat| (-lon_cells..=lon_cells).map(move |d_lon| (cell_lat + d_lat, cell_lon + d_lon)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+// The platforms of a dataset that are served by trips (columns "stop_id", "stop_name",
+// "stop_lat", "stop_lon")
+fn served_platforms(data: &ImportStepExtra) -> Result<DataFrame, MergeError> {
+    let ImportStepExtra::Gtfs { stops, stop_times, .. } = data;
+    Ok(stops.clone()
+        .filter(col("location_type").fill_null(lit(0)).eq(lit(0)))
+        .join(
+            stop_times.clone().select([col("stop_id")]),
+            [col("stop_id")],
+            [col("stop_id")],
+            JoinArgs::new(JoinType::Semi),
+        )
+        .select([col("stop_id"), col("stop_name"), col("stop_lat"), col("stop_lon")])
+        .collect()?)
+}
+
+/// Finds stops of later datasets that represent the same physical stop as a stop of an earlier
+/// dataset, i.e. that are within `radius` meters and have a similar name. Only platforms that are
+/// served by trips are considered. Returns the provenance of every merged stop (columns
+/// "dataset_id", "stop_id", "canonical_dataset_id", "canonical_stop_id", "distance").
+pub fn find_duplicate_stops(datasets: &[(&str, &ImportStepExtra)], radius: f32) -> Result<DataFrame, MergeError> {
+    let mut candidates: Vec<StopCandidate> = vec![];
+    let mut grid = StopGrid::new(radius);
+
+    let mut dataset_ids = vec![];
+    let mut stop_ids = vec![];
+    let mut canonical_dataset_ids = vec![];
+    let mut canonical_stop_ids = vec![];
+    let mut distances = vec![];
+
+    for (dataset_id, data) in datasets {
+        let stops = served_platforms(data)?;
+
+        let mut new_candidates = vec![];
+        let values = stops.column("stop_id")?.str()?.iter()
+            .zip(stops.column("stop_name")?.str()?.iter())
+            .zip(stops.column("stop_lat")?.f32()?.iter())
+            .zip(stops.column("stop_lon")?.f32()?.iter());
+
+        for (((stop_id, name), lat), lon) in values {
+            let (Some(stop_id), Some(lat), Some(lon)) = (stop_id, lat, lon) else { continue };
+            let location = Point::new(lon, lat);
+            let name_tokens = name.map(name_tokens).unwrap_or_default();
+
+            let closest = grid.neighbours(&location)
+                .map(|index| &candidates[index])
+                .filter(|candidate| similar_names(&candidate.name_tokens, &name_tokens))
+                .map(|candidate| (Haversine::distance(candidate.location, location), candidate))
+                .filter(|(distance, _)| *distance <= radius)
+                .min_by(|(a, _), (b, _)| a.total_cmp(b));
+
+            match closest {
+                Some((distance, candidate)) => {
+                    dataset_ids.push(dataset_id.to_string());
+                    stop_ids.push(stop_id.to_owned());
+                    canonical_dataset_ids.push(candidate.dataset_id.clone());
+                    canonical_stop_ids.push(candidate.stop_id.clone());
+                    distances.push(distance);
+                }
+                None => new_candidates.push(StopCandidate {
+                    dataset_id: dataset_id.to_string(),
+                    stop_id: stop_id.to_owned(),
+                    location,
+                    name_tokens,
+                }),
+            }
+        }
+
+        // Stops of the same dataset are never merged, so they only become candidates afterwards
+        for candidate in new_candidates {
+            grid.insert(&candidate.location, candidates.len());
+            candidates.push(candidate);
+        }
+    }
+
+    Ok(DataFrame::new(vec![
+        Column::new("dataset_id".into(), dataset_ids),
+        Column::new("stop_id".into(), stop_ids),
+        Column::new("canonical_dataset_id".into(), canonical_dataset_ids),
+        Column::new("canonical_stop_id".into(), canonical_stop_ids),
+        Column::new("distance".into(), distances),
+    ])?)
+}
+
+/// Generates walking transfers in both directions between served platforms of different datasets
+/// that are within `radius` meters of each other, since datasets only contain transfers between
+/// their own stops. The duration is the walking time on a straight line (columns
+/// "from_dataset_id", "from_stop_id", "to_dataset_id", "to_stop_id", "distance", "duration").
+pub fn find_cross_dataset_transfers(datasets: &[(&str, &ImportStepExtra)], radius: f32) -> Result<DataFrame, MergeError> {
+    let mut platforms: Vec<(&str, String, Point<f32>)> = vec![];
+    let mut grid = StopGrid::new(radius);
+
+    let mut from_dataset_ids = vec![];
+    let mut from_stop_ids = vec![];
+    let mut to_dataset_ids = vec![];
+    let mut to_stop_ids = vec![];
+    let mut distances = vec![];
+
+    for (dataset_id, data) in datasets {
+        let stops = served_platforms(data)?;
+
+        let mut new_platforms = vec![];
+        let values = stops.column("stop_id")?.str()?.iter()
+            .zip(stops.column("stop_lat")?.f32()?.iter())
+            .zip(stops.column("stop_lon")?.f32()?.iter());
+
+        for ((stop_id, lat), lon) in values {
+            let (Some(stop_id), Some(lat), Some(lon)) = (stop_id, lat, lon) else { continue };
+            let location = Point::new(lon, lat);
+
+            // Only platforms of earlier datasets are in the grid, so every pair is found once
+            for index in grid.neighbours(&location) {
+                let (other_dataset_id, other_stop_id, other_location) = &platforms[index];
+                let distance = Haversine::distance(*other_location, location);
+                if distance > radius {
+                    continue;
+                }
+
+                for ((from_dataset_id, from_stop_id), (to_dataset_id, to_stop_id)) in [
+                    ((*dataset_id, stop_id), (*other_dataset_id, other_stop_id.as_str())),
+                    ((*other_dataset_id, other_stop_id.as_str()), (*dataset_id, stop_id)),
+                ] {
+                    from_dataset_ids.push(from_dataset_id.to_owned());
+                    from_stop_ids.push(from_stop_id.to_owned());
+                    to_dataset_ids.push(to_dataset_id.to_owned());
+                    to_stop_ids.push(to_stop_id.to_owned());
+                    distances.push(distance);
+                }
+            }
+
+            new_platforms.push((*dataset_id, stop_id.to_owned(), location));
+        }
+
+        // Datasets already contain the transfers between their own stops
+        for platform in new_platforms {
+            grid.insert(&platform.2, platforms.len());
+            platforms.push(platform);
+        }
+    }
+
+    let durations: Vec<i64> = distances.iter()
+        .map(|distance| MAX_WALKING_SPEED.time_to_travel_distance(*distance).num_milliseconds())
+        .collect();
+
+    Ok(DataFrame::new(vec![
+        Column::new("from_dataset_id".into(), from_dataset_ids),
+        Column::new("from_stop_id".into(), from_stop_ids),
+        Column::new("to_dataset_id".into(), to_dataset_ids),
+        Column::new("to_stop_id".into(), to_stop_ids),
+        Column::new("distance".into(), distances),
+        Column::new("duration".into(), durations),
+    ])?
+        .lazy()
+        .with_column(col("duration").cast(DataType::Duration(TimeUnit::Milliseconds)))
+        .collect()?)
+}
+
+/// The minimum times of changing between two stops that transfers.txt of a dataset gives (columns
+/// "from_stop_id", "to_stop_id", "duration"). Transfers that are not possible have no duration.
+/// Transfers between specific routes or trips are left out, since walks don't depend on the ride.
+pub fn stop_transfers(transfers: LazyFrame) -> LazyFrame {
+    let between_stops = col("from_stop_id").is_not_null()
+        .and(col("to_stop_id").is_not_null())
+        .and(col("from_route_id").is_null())
+        .and(col("to_route_id").is_null())
+        .and(col("from_trip_id").is_null())
+        .and(col("to_trip_id").is_null());
+    let not_possible = col("transfer_type").eq(lit(3u32));
+    let with_min_time = col("transfer_type").eq(lit(2u32)).and(col("min_transfer_time").is_not_null());
+
+    transfers
+        .filter(between_stops.and(not_possible.clone().or(with_min_time)))
+        .select([
+            col("from_stop_id"),
+            col("to_stop_id"),
+            when(not_possible)
+                .then(lit(NULL).cast(DataType::Int64))
+                .otherwise(col("min_transfer_time").cast(DataType::Int64) * lit(1_000i64))
+                .cast(DataType::Duration(TimeUnit::Milliseconds))
+                .alias("duration"),
+        ])
+}
+
+// Ids of the trips of a dataset that are overridden by another dataset
+fn overridden_trips(data: &ImportStepExtra, dataset_override: &DatasetOverride) -> Result<LazyFrame, MergeError> {
+    let ImportStepExtra::Gtfs { routes, trips, .. } = data;
+    if dataset_override.agency_ids.is_empty() && dataset_override.route_ids.is_empty() {
+        return Ok(trips.clone().select([col("trip_id")]));
+    }
+
+    let ids = |column: &str, ids: &[String]| DataFrame::new(vec![Column::new(column.into(), ids)]);
+    let routes = concat(
+        [
+            routes.clone().join(
+                ids("agency_id", &dataset_override.agency_ids)?.lazy(),
+                [col("agency_id")],
+                [col("agency_id")],
+                JoinArgs::new(JoinType::Semi),
+            ),
+            routes.clone().join(
+                ids("route_id", &dataset_override.route_ids)?.lazy(),
+                [col("route_id")],
+                [col("route_id")],
+                JoinArgs::new(JoinType::Semi),
+            ),
+        ].map(|routes| routes.select([col("route_id")])),
+        UnionArgs::default(),
+    )?;
+
+    Ok(trips.clone()
+        .join(routes, [col("route_id")], [col("route_id")], JoinArgs::new(JoinType::Semi))
+        .select([col("trip_id")]))
+}
+
+/// Drops the trips of datasets that are overridden by another dataset, together with their stop
+/// times and frequencies. Only valid datasets override others, so that the trips are still
+/// available if the overriding dataset is skipped.
+fn apply_overrides(mut datasets: Vec<ValidateStepOutput>) -> Result<Vec<ValidateStepOutput>, MergeError> {
+    let overrides: Vec<(String, DatasetOverride)> = datasets.iter()
+        .flat_map(|data| {
+            data.dataset.overrides.iter()
+                .filter(|dataset_override| dataset_override.dataset != data.dataset.id)
+                .map(|dataset_override| (data.dataset.id.clone(), dataset_override.clone()))
         })
-    } }
+        .collect();
+
+    for data in datasets.iter_mut() {
+        for (overriding_id, dataset_override) in overrides.iter().filter(|(_, dataset_override)| dataset_override.dataset == data.dataset.id) {
+            let overridden = overridden_trips(&data.extra, dataset_override)?.collect()?;
+            if overridden.height() == 0 {
+                continue;
+            }
+            info!(
+                target: "preprocessing",
+                "Dropping {} trips of dataset {} that are overridden by dataset {}",
+                overridden.height(), data.dataset.id, overriding_id,
+            );
+
+            let ImportStepExtra::Gtfs { trips, stop_times, frequencies, .. } = &mut data.extra;
+            let without_overridden = |frame: &LazyFrame| frame.clone().join(
+                overridden.clone().lazy(),
+                [col("trip_id")],
+                [col("trip_id")],
+                JoinArgs::new(JoinType::Anti),
+            );
+            *trips = without_overridden(trips);
+            *stop_times = without_overridden(stop_times);
+            *frequencies = without_overridden(frequencies);
+        }
+    }
+
+    Ok(datasets)
+}
+
+#[instrument(name = "merge", skip_all)]
+pub async fn merge(
+    input: Vec<ValidateStepOutput>,
+    config: &MergeConfig,
+    progress: &dyn ProgressSink,
+) -> Result<DatasetMergeOutput, MergeError> {
+    // Overrides, duplicate trips, duplicate stops and transfers between datasets are the steps
+    // that compare datasets, concatenating them is left to the later steps
+    let task = progress.start("Merging datasets", 4);
+
+    let valid: Vec<ValidateStepOutput> = input.into_iter()
+        .filter(|data| !data.skip)
+        .collect();
+    // Overridden trips are dropped first, so that they are not reported as duplicates
+    let valid = apply_overrides(valid)?;
+    task.inc(1);
+
+    // Feeds of overlapping operators often contain the same trips, which would otherwise be
+    // counted twice
+    let duplicate_trips = find_duplicate_trips(
+        &valid.iter().map(|data| (data.dataset.id.as_str(), &data.extra)).collect::<Vec<_>>()
+    )?;
+    if duplicate_trips.height() > 0 {
+        warn!(target: "preprocessing", "Found {} trips that are contained in several datasets", duplicate_trips.height());
+        write_df_to_file(
+            paths::tmp_dir().join("merge").join("duplicate_trips.parquet"),
+            FileType::PARQUET,
+            duplicate_trips.clone(),
+        )?;
+    }
+    task.inc(1);
+
+    if valid.is_empty() {
+        return Err(MergeError::NoValidDataset);
+    }
+
+    // Without merging them, changing between two agencies at the same stop would need a walk
+    let matched_datasets = match config.stop_match_radius > 0.0 {
+        true => valid.iter().map(|data| (data.dataset.id.as_str(), &data.extra)).collect::<Vec<_>>(),
+        false => vec![],
+    };
+    let stop_duplicates = find_duplicate_stops(&matched_datasets, config.stop_match_radius)?;
+    if stop_duplicates.height() > 0 {
+        info!(target: "preprocessing", "Merging {} stops that are contained in several datasets", stop_duplicates.height());
+        write_df_to_file(
+            paths::tmp_dir().join("merge").join("stop_provenance.parquet"),
+            FileType::PARQUET,
+            stop_duplicates.clone(),
+        )?;
+    }
+    task.inc(1);
+
+    // Datasets don't know about each other, so changing between them needs generated transfers
+    let transfer_datasets = match config.transfer_radius > 0.0 {
+        true => valid.iter().map(|data| (data.dataset.id.as_str(), &data.extra)).collect::<Vec<_>>(),
+        false => vec![],
+    };
+    let transfers = find_cross_dataset_transfers(&transfer_datasets, config.transfer_radius)?;
+    if transfers.height() > 0 {
+        info!(target: "preprocessing", "Generated {} transfers between stops of different datasets", transfers.height());
+    }
+    task.inc(1);
+
+    // Ids are only unique within their dataset, so every row keeps the id of its dataset. Later
+    // steps always identify entities by the pair of dataset id and original id.
+    let with_dataset_id = |frame: LazyFrame, dataset_id: &str| {
+        frame.with_column(lit(dataset_id.to_owned()).alias("dataset_id"))
+    };
+
+    let mut agencies = vec![];
+    let mut routes = vec![];
+    let mut booking_rules = vec![];
+    let mut services = vec![];
+    let mut stops = vec![];
+    let mut trips = vec![];
+    let mut stop_times = vec![];
+    let mut frequencies = vec![];
+    let mut dataset_transfers = vec![];
+    let mut import_extras = vec![];
+
+    for data in valid {
+        let dataset_id = data.dataset.id.as_str();
+        let ImportStepExtra::Gtfs {
+            agencies: dataset_agencies,
+            routes: dataset_routes,
+            booking_rules: dataset_booking_rules,
+            calendar,
+            stops: dataset_stops,
+            trips: dataset_trips,
+            stop_times: dataset_stop_times,
+            frequencies: dataset_frequencies,
+            transfers: dataset_stop_transfers,
+            ..
+        } = data.extra.clone();
+
+        agencies.push(with_dataset_id(dataset_agencies, dataset_id));
+        routes.push(with_dataset_id(dataset_routes, dataset_id));
+        booking_rules.push(with_dataset_id(dataset_booking_rules, dataset_id));
+        services.push(with_dataset_id(calendar, dataset_id));
+        stops.push(with_dataset_id(dataset_stops, dataset_id));
+        trips.push(with_dataset_id(dataset_trips, dataset_id));
+        stop_times.push(with_dataset_id(dataset_stop_times, dataset_id));
+        frequencies.push(with_dataset_id(dataset_frequencies, dataset_id));
+        dataset_transfers.push(with_dataset_id(stop_transfers(dataset_stop_transfers), dataset_id));
+        import_extras.push(data.extra);
+    }
+
+    Ok(DatasetMergeOutput {
+        agencies: concat(agencies, UnionArgs::default())?,
+        routes: concat(routes, UnionArgs::default())?,
+        booking_rules: concat(booking_rules, UnionArgs::default())?,
+        services: concat(services, UnionArgs::default())?,
+        stops: concat(stops, UnionArgs::default())?,
+        trips: concat(trips, UnionArgs::default())?,
+        stop_times: concat(stop_times, UnionArgs::default())?,
+        frequencies: concat(frequencies, UnionArgs::default())?,
+        duplicate_trips: duplicate_trips.lazy(),
+        stop_duplicates: stop_duplicates.lazy(),
+        transfers: transfers.lazy(),
+        dataset_transfers: concat(dataset_transfers, UnionArgs::default())?,
+        import_extras,
+    })
 }
 
 pub struct DatasetMergeOutput {
+    pub agencies: LazyFrame,
+    pub routes: LazyFrame,
+    pub booking_rules: LazyFrame,
     pub services: LazyFrame, // corresponds to calendar.txt in GTFS
     pub stops: LazyFrame,
     pub trips: LazyFrame,
     pub stop_times: LazyFrame,
-    pub import_extra: ImportStepExtra
+    pub frequencies: LazyFrame,
+    // Trips that occur in several datasets (columns "dataset_id", "trip_id",
+    // "duplicate_dataset_id", "duplicate_trip_id", "identical_id")
+    pub duplicate_trips: LazyFrame,
+    // Stops that are merged into a stop of another dataset (columns "dataset_id", "stop_id",
+    // "canonical_dataset_id", "canonical_stop_id", "distance")
+    pub stop_duplicates: LazyFrame,
+    // Walking transfers between stops of different datasets (columns "from_dataset_id",
+    // "from_stop_id", "to_dataset_id", "to_stop_id", "distance", "duration")
+    pub transfers: LazyFrame,
+    // Minimum times of changing between stops of the same dataset as given by its transfers.txt
+    // (columns "from_stop_id", "to_stop_id", "duration", "dataset_id"), see [stop_transfers]
+    pub dataset_transfers: LazyFrame,
+    // The imported data of every merged dataset
+    pub import_extras: Vec<ImportStepExtra>,
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum MergeError {
     Polars(#[from] polars::error::PolarsError),
+    NoValidDataset,
 }
 
 impl Display for MergeError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let err: &dyn Display = match self {
             MergeError::Polars(err) => err,
+            MergeError::NoValidDataset => &"No valid dataset provided",
         };
         write!(f, "{}", err)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::dataset::{DataSource, Dataset, DatasetFormat};
+    use common::util::logging::NoProgress;
+    use polars::df;
+
+    // A dataset with a single trip per entry of `trip_ids`, all serving the same two stops. The
+    // departure of every trip is shifted by the given minutes.
+    fn gtfs_data(trip_ids: &[&str], shifts: &[i64], weekend_only: bool) -> ImportStepExtra {
+        let stop_trip_ids: Vec<&str> = trip_ids.iter().flat_map(|id| [*id, *id]).collect();
+        let times: Vec<i64> = shifts.iter().flat_map(|shift| [shift * 60_000, shift * 60_000 + 600_000]).collect();
+
+        ImportStepExtra::Gtfs {
+            agencies: DataFrame::empty().lazy(),
+            routes: DataFrame::empty().lazy(),
+            booking_rules: DataFrame::empty().lazy(),
+            calendar: df!(
+                "service_id" => ["s"],
+                "monday"     => [!weekend_only],
+                "tuesday"    => [!weekend_only],
+                "wednesday"  => [!weekend_only],
+                "thursday"   => [!weekend_only],
+                "friday"     => [!weekend_only],
+                "saturday"   => [weekend_only],
+                "sunday"     => [weekend_only],
+                "start_date" => [20250101i32],
+                "end_date"   => [20251231i32],
+            ).unwrap().lazy(),
+            stops: df!(
+                "stop_id"       => ["a", "b"],
+                "stop_name"     => ["Hauptbahnhof", "Charlottenplatz"],
+                "stop_lat"      => [48.78f32, 48.80],
+                "stop_lon"      => [9.18f32, 9.21],
+                "location_type" => [None::<u32>, None],
+            ).unwrap().lazy(),
+            trips: df!(
+                "trip_id"    => trip_ids,
+                "service_id" => vec!["s"; trip_ids.len()],
+            ).unwrap().lazy(),
+            stop_times: df!(
+                "trip_id"        => stop_trip_ids,
+                "stop_id"        => trip_ids.iter().flat_map(|_| ["a", "b"]).collect::<Vec<_>>(),
+                "stop_sequence"  => trip_ids.iter().flat_map(|_| [1u32, 2]).collect::<Vec<_>>(),
+                "arrival_time"   => times.clone(),
+                "departure_time" => times,
+            ).unwrap().lazy().with_columns([
+                col("arrival_time").cast(DataType::Duration(TimeUnit::Milliseconds)),
+                col("departure_time").cast(DataType::Duration(TimeUnit::Milliseconds)),
+            ]),
+            frequencies: DataFrame::empty().lazy(),
+            transfers: df!(
+                "transfer_type"     => [2u32, 3, 2, 0],
+                "from_stop_id"      => [Some("a"), Some("b"), Some("a"), Some("a")],
+                "to_stop_id"        => [Some("b"), Some("a"), Some("b"), Some("b")],
+                "from_route_id"     => [None::<&str>, None, Some("r"), None],
+                "to_route_id"       => [None::<&str>, None, None, None],
+                "from_trip_id"      => [None::<&str>, None, None, None],
+                "to_trip_id"        => [None::<&str>, None, None, None],
+                "min_transfer_time" => [Some(240u32), None, Some(60), None],
+            ).unwrap().lazy(),
+            temporary_files: vec![],
+        }
+    }
+
+    #[test]
+    fn test_find_duplicate_trips() {
+        let a = gtfs_data(&["t1", "t2"], &[0, 30], false);
+        let mut b = gtfs_data(&["t1", "x2", "x3"], &[0, 30, 45], false);
+        // About a meter apart from the stops of the other dataset
+        let ImportStepExtra::Gtfs { stops, .. } = &mut b;
+        *stops = stops.clone().with_column(col("stop_lat") + lit(0.00001f32));
+        let weekend = gtfs_data(&["t1"], &[0], true);
+
+        let duplicates = find_duplicate_trips(&[("a", &a), ("b", &b), ("weekend", &weekend)]).unwrap()
+            .sort(["trip_id"], Default::default()).unwrap();
+
+        // Trips on the weekend are not the same as the ones during the week
+        assert_eq!(duplicates.height(), 2);
+        let duplicate_trip_ids: Vec<Option<&str>> = duplicates.column("duplicate_trip_id").unwrap().str().unwrap().iter().collect();
+        assert_eq!(duplicate_trip_ids, [Some("t1"), Some("x2")]);
+        let identical_ids: Vec<Option<bool>> = duplicates.column("identical_id").unwrap().bool().unwrap().iter().collect();
+        assert_eq!(identical_ids, [Some(true), Some(false)]);
+    }
+
+    fn validated(id: &str, extra: ImportStepExtra, skip: bool) -> ValidateStepOutput {
+        ValidateStepOutput {
+            dataset: Dataset {
+                id: id.into(),
+                src: DataSource::File { path: "".into() },
+                format: DatasetFormat::Gtfs,
+                license: None,
+                group_ids: vec![],
+                validation: Default::default(),
+                bounds: None,
+                drop_implausible_stops: false,
+                fix: false,
+                overrides: vec![],
+                vehicle_positions: None,
+                trip_updates: None,
+                alerts: None,
+                credentials: None,
+                refresh: None,
+                filter: Default::default(),
+                attribution: None,
+            },
+            extra,
+            violations: vec![],
+            skip,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge() {
+
+        // Both datasets use the same stop and trip ids. Merging stops is disabled, since the
+        // provenance of merged stops would be written to the working directory.
+        let config = MergeConfig { stop_match_radius: 0.0, transfer_radius: 0.0 };
+        let merged = merge(vec![
+            validated("a", gtfs_data(&["t1", "t2"], &[0, 30], false), false),
+            validated("skipped", gtfs_data(&["t1"], &[0], false), true),
+            validated("weekend", gtfs_data(&["t1"], &[0], true), false),
+        ], &config, &NoProgress).await.unwrap();
+
+        let stops = merged.stops.collect().unwrap();
+        assert_eq!(stops.height(), 4);
+        let dataset_ids: Vec<Option<&str>> = stops.column("dataset_id").unwrap().str().unwrap().iter().collect();
+        assert_eq!(dataset_ids, [Some("a"), Some("a"), Some("weekend"), Some("weekend")]);
+        assert_eq!(merged.stop_times.collect().unwrap().height(), 6);
+        assert_eq!(merged.import_extras.len(), 2);
+        assert_eq!(merged.stop_duplicates.collect().unwrap().height(), 0);
+        assert_eq!(merged.transfers.collect().unwrap().height(), 0);
+
+        // Only the transfers between stops with a minimum time or that are not possible are kept
+        let dataset_transfers = merged.dataset_transfers.filter(col("dataset_id").eq(lit("a"))).collect().unwrap();
+        assert_eq!(dataset_transfers.height(), 2);
+        let durations: Vec<Option<i64>> = dataset_transfers.column("duration").unwrap().duration().unwrap().iter().collect();
+        assert_eq!(durations, [Some(240_000), None]);
+
+        assert!(matches!(merge(vec![], &config, &NoProgress).await, Err(MergeError::NoValidDataset)));
+    }
+
+    #[test]
+    fn test_find_duplicate_stops() {
+        let a = gtfs_data(&["t1"], &[0], false);
+        let mut b = gtfs_data(&["t1"], &[0], false);
+        let ImportStepExtra::Gtfs { stops, .. } = &mut b;
+        // "a" is ~10m away with a longer name, "b" is at the same location but has another name
+        *stops = df!(
+            "stop_id"       => ["a", "b"],
+            "stop_name"     => ["Stuttgart Hauptbahnhof (tief)", "Rotebühlplatz"],
+            "stop_lat"      => [48.7801f32, 48.80],
+            "stop_lon"      => [9.18f32, 9.21],
+            "location_type" => [Some(0u32), None],
+        ).unwrap().lazy();
+
+        let duplicates = find_duplicate_stops(&[("a", &a), ("b", &b)], 25.0).unwrap();
+        assert_eq!(duplicates.height(), 1);
+        let row = duplicates.get_row(0).unwrap().0;
+        assert_eq!(row[0].get_str(), Some("b"));
+        assert_eq!(row[1].get_str(), Some("a"));
+        assert_eq!(row[2].get_str(), Some("a"));
+        assert_eq!(row[3].get_str(), Some("a"));
+
+        assert_eq!(find_duplicate_stops(&[("a", &a), ("b", &b)], 5.0).unwrap().height(), 0);
+    }
+
+    #[test]
+    fn test_find_cross_dataset_transfers() {
+        let a = gtfs_data(&["t1"], &[0], false);
+        let mut b = gtfs_data(&["t1"], &[0], false);
+        let ImportStepExtra::Gtfs { stops, .. } = &mut b;
+        // "x" is ~110m north of "a", "y" is far away from both stops of "a"
+        *stops = df!(
+            "stop_id"       => ["x", "y"],
+            "stop_name"     => ["Arnulf-Klett-Platz", "Rotebühlplatz"],
+            "stop_lat"      => [48.781f32, 48.90],
+            "stop_lon"      => [9.18f32, 9.21],
+            "location_type" => [None::<u32>, None],
+        ).unwrap().lazy();
+        let ImportStepExtra::Gtfs { stop_times, .. } = &mut b;
+        *stop_times = stop_times.clone().with_column(
+            when(col("stop_id").eq(lit("a"))).then(lit("x")).otherwise(lit("y")).alias("stop_id")
+        );
+
+        let transfers = find_cross_dataset_transfers(&[("a", &a), ("b", &b)], 200.0).unwrap();
+        assert_eq!(transfers.height(), 2);
+        let from: Vec<Option<&str>> = transfers.column("from_stop_id").unwrap().str().unwrap().iter().collect();
+        let to: Vec<Option<&str>> = transfers.column("to_stop_id").unwrap().str().unwrap().iter().collect();
+        assert_eq!(from, [Some("x"), Some("a")]);
+        assert_eq!(to, [Some("a"), Some("x")]);
+        // 111m take about a minute at walking speed
+        let duration = transfers.column("duration").unwrap().duration().unwrap().get(0).unwrap();
+        assert!((50_000..=70_000).contains(&duration));
+
+        // Stops of the same dataset never get transfers
+        assert_eq!(find_cross_dataset_transfers(&[("a", &a)], 200.0).unwrap().height(), 0);
+        assert_eq!(find_cross_dataset_transfers(&[("a", &a), ("b", &b)], 50.0).unwrap().height(), 0);
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        // Trip t1 belongs to agency a1, t2 to agency a2 and t3 to a route of a2
+        let mut national = gtfs_data(&["t1", "t2", "t3"], &[0, 30, 60], false);
+        let ImportStepExtra::Gtfs { routes, trips, frequencies, .. } = &mut national;
+        *routes = df!("route_id" => ["r1", "r2", "r3"], "agency_id" => ["a1", "a2", "a2"]).unwrap().lazy();
+        *trips = trips.clone().with_column(
+            when(col("trip_id").eq(lit("t1"))).then(lit("r1"))
+                .when(col("trip_id").eq(lit("t2"))).then(lit("r2"))
+                .otherwise(lit("r3"))
+                .alias("route_id")
+        );
+        *frequencies = df!("trip_id" => ["t1", "t3"], "headway_secs" => [600u32, 600]).unwrap().lazy();
+
+        let mut local = validated("local", gtfs_data(&["t1"], &[0], false), false);
+        local.dataset.overrides = vec![DatasetOverride {
+            dataset: "national".into(),
+            agency_ids: vec!["a1".into()],
+            route_ids: vec!["r3".into()],
+        }];
+
+        let trip_ids = |data: &ValidateStepOutput| -> Vec<Option<String>> {
+            let ImportStepExtra::Gtfs { trips, .. } = &data.extra;
+            trips.clone().collect().unwrap().column("trip_id").unwrap().str().unwrap()
+                .iter().map(|id| id.map(str::to_owned)).collect()
+        };
+
+        let datasets = apply_overrides(vec![validated("national", national, false), local]).unwrap();
+        assert_eq!(trip_ids(&datasets[0]), [Some("t2".to_owned())]);
+        assert_eq!(trip_ids(&datasets[1]), [Some("t1".to_owned())]);
+        let ImportStepExtra::Gtfs { stop_times, frequencies, .. } = &datasets[0].extra;
+        assert_eq!(stop_times.clone().collect().unwrap().height(), 2);
+        assert_eq!(frequencies.clone().collect().unwrap().height(), 0);
+    }
+}
@@ -1,11 +1,22 @@
+use crate::step2_import_data::ROW_IN_FILE;
 use crate::step4_merge_data::DatasetMergeOutput;
+use crate::trip_identities::{stable_ids, stable_trip_ids, PreviousTrips};
+use common::types::config::{SimplifyConfig, SimplifyPass};
 use common::util::df::{write_df_to_file, FileType};
+use common::types::id_interner::{IdInterner, OriginalIds};
+use common::util::{metrics, paths};
+use log::info;
+use tracing::instrument;
+use polars::datatypes::DataType;
 use polars::frame::DataFrame;
-use polars::prelude::{col, Column, IntoLazy, JoinArgs, JoinType};
+use polars::prelude::{coalesce, col, concat, len, lit, Column, Expr, IntoLazy, JoinArgs, JoinType, LazyFrame, NamedFrom, SortMultipleOptions, TimeUnit, UnionArgs, UniqueKeepStrategy};
 use polars::series::Series;
+use routing::accessibility::inherit_wheelchair_boarding;
 use routing::algorithm::PreprocessingInput;
 use std::fmt;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 fn assign_new_ids(
     mut frame: DataFrame,
@@ -21,49 +32,667 @@ fn assign_new_ids(
     Ok(frame)
 }
 
-pub async fn simplify(
-    DatasetMergeOutput {
+/// Original ids are only unique within their dataset, so they are prefixed with the id of the
+/// dataset (e.g. "vvs:de:08111:6118")
+fn namespaced_ids(frame: &DataFrame, column: &str) -> Result<Vec<String>, SimplifyError> {
+    let dataset_ids = frame.column("dataset_id")?.str()?;
+    let ids = frame.column(column)?.str()?;
+
+    Ok(dataset_ids.iter()
+        .zip(ids.iter())
+        .map(|(dataset_id, id)| format!("{}:{}", dataset_id.unwrap_or_default(), id.unwrap_or_default()))
+        .collect())
+}
+
+/// Turns every entry of frequencies.txt into the concrete runs of its template trip. Each run is
+/// described by the offset of its first departure to the first departure of the template, kept as
+/// an exact number of milliseconds so that the times of a run can always be reproduced from the
+/// template.
+fn expand_frequencies(
+    frequencies: LazyFrame,
+    stop_times: LazyFrame,
+) -> Result<DataFrame, SimplifyError> {
+    let first_departures = stop_times
+        .group_by([col("dataset_id"), col("trip_id")])
+        .agg([col("departure_time").min().alias("first_departure")]);
+
+    let frequencies = frequencies
+        .join(
+            first_departures,
+            [col("dataset_id"), col("trip_id")],
+            [col("dataset_id"), col("trip_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .select([
+            col("dataset_id"),
+            col("trip_id"),
+            col("start_time").cast(DataType::Int64),
+            col("end_time").cast(DataType::Int64),
+            col("headway_secs").cast(DataType::Int64),
+            col("first_departure").cast(DataType::Int64),
+        ])
+        .collect()?;
+
+    let mut dataset_ids = vec![];
+    let mut trip_ids = vec![];
+    let mut run_offsets = vec![];
+
+    for i in 0..frequencies.height() {
+        let row = frequencies.get_row(i)?.0;
+        let [dataset_id, trip_id, start_time, end_time, headway_secs, first_departure] = row.as_slice() else {
+            unreachable!()
+        };
+
+        let (Ok(start_time), Ok(end_time), Ok(headway_secs), Ok(first_departure)) = (
+            start_time.try_extract::<i64>(),
+            end_time.try_extract::<i64>(),
+            headway_secs.try_extract::<i64>(),
+            first_departure.try_extract::<i64>(),
+        ) else {
+            continue;
+        };
+
+        // A headway of zero would never reach the end time
+        if headway_secs <= 0 {
+            continue;
+        }
+
+        // Runs depart at start_time + k * headway until the end time is reached
+        let mut run_start = start_time;
+        while run_start < end_time {
+            dataset_ids.push(dataset_id.get_str().unwrap_or_default().to_owned());
+            trip_ids.push(trip_id.get_str().unwrap_or_default().to_owned());
+            run_offsets.push(run_start - first_departure);
+
+            run_start += headway_secs * 1000;
+        }
+    }
+
+    let runs = DataFrame::new(vec![
+        Column::new("dataset_id".into(), dataset_ids),
+        Column::new("trip_id_in_dataset".into(), trip_ids),
+        Series::new("run_offset".into(), run_offsets)
+            .cast(&DataType::Duration(TimeUnit::Milliseconds))?
+            .into(),
+    ])?;
+
+    Ok(runs)
+}
+
+/// The distinct stop sequences of the trips of every route together with the number of trips
+/// serving them, so that clients can show all stops of a line
+fn route_patterns(trips: LazyFrame, stop_times: LazyFrame) -> LazyFrame {
+    stop_times
+        .group_by([col("trip_id")])
+        .agg([col("stop_id").sort_by([col("stop_sequence")], Default::default()).alias("stop_ids")])
+        .join(
+            trips.select([col("trip_id"), col("dataset_id"), col("route_id_in_dataset")]),
+            [col("trip_id")],
+            [col("trip_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .group_by([col("dataset_id"), col("route_id_in_dataset"), col("stop_ids")])
+        .agg([len().alias("num_trips")])
+        .sort(
+            ["dataset_id", "route_id_in_dataset", "num_trips"],
+            SortMultipleOptions::default().with_order_descending_multi([false, false, true]),
+        )
+}
+
+/// The booking rules that apply when boarding or alighting at a stop of a demand-responsive trip.
+/// Rules without their own contact information use the one of the agency operating the trip.
+fn booking_notes(
+    agencies: LazyFrame,
+    routes: LazyFrame,
+    booking_rules: LazyFrame,
+    trips: LazyFrame,
+    stop_times: LazyFrame,
+) -> Result<LazyFrame, SimplifyError> {
+    let contacts = trips
+        .select([col("trip_id"), col("dataset_id"), col("route_id_in_dataset")])
+        .join(
+            routes.select([
+                col("dataset_id"),
+                col("route_id").alias("route_id_in_dataset"),
+                col("agency_id").fill_null(col("dataset_id")),
+            ]),
+            [col("dataset_id"), col("route_id_in_dataset")],
+            [col("dataset_id"), col("route_id_in_dataset")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .join(
+            agencies.select([
+                col("dataset_id"),
+                col("agency_id").fill_null(col("dataset_id")),
+                col("agency_phone"),
+                col("agency_url"),
+            ]),
+            [col("dataset_id"), col("agency_id")],
+            [col("dataset_id"), col("agency_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .select([col("trip_id"), col("agency_phone"), col("agency_url")]);
+
+    let rules_at_stops = ["pickup", "drop_off"].map(|kind| {
+        stop_times.clone()
+            .filter(col(format!("{kind}_booking_rule_id")).is_not_null())
+            .select([
+                col("trip_id"),
+                col("stop_id"),
+                col("dataset_id"),
+                col(format!("{kind}_booking_rule_id")).alias("booking_rule_id"),
+                lit(kind).alias("kind"),
+            ])
+    });
+
+    Ok(concat(rules_at_stops, UnionArgs::default())?
+        .join(
+            booking_rules,
+            [col("dataset_id"), col("booking_rule_id")],
+            [col("dataset_id"), col("booking_rule_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .join(contacts, [col("trip_id")], [col("trip_id")], JoinArgs::new(JoinType::Left))
+        .select([
+            col("trip_id"),
+            col("stop_id"),
+            col("kind"),
+            col("booking_type"),
+            col("prior_notice_duration_min"),
+            col("message"),
+            coalesce(&[col("phone_number"), col("agency_phone")]).alias("phone_number"),
+            coalesce(&[col("booking_url"), col("info_url"), col("agency_url")]).alias("url"),
+        ]))
+}
+
+/// Writes agencies, routes and the stop sequences of routes, which are not needed for routing but
+/// allow clients to browse the datasets
+fn write_browse_tables(
+    agencies: LazyFrame,
+    routes: LazyFrame,
+    trips: LazyFrame,
+    stop_times: LazyFrame,
+) -> Result<(), SimplifyError> {
+    let simplify_dir = paths::tmp_dir().join("simplify");
+
+    // The agency id may be omitted if a dataset only contains a single agency
+    let agencies = agencies
+        .select([
+            col("dataset_id"),
+            col("agency_id").fill_null(col("dataset_id")).alias("agency_id_in_dataset"),
+            col("agency_name"),
+            // Only needed to write the timetable as GTFS again
+            col("agency_url"),
+            col("agency_timezone"),
+        ])
+        .collect()?;
+    write_df_to_file(simplify_dir.join("agencies.parquet"), FileType::PARQUET, agencies)?;
+
+    let routes = routes
+        .select([
+            col("dataset_id"),
+            col("route_id").alias("route_id_in_dataset"),
+            col("agency_id").fill_null(col("dataset_id")).alias("agency_id_in_dataset"),
+            col("route_short_name"),
+            col("route_long_name"),
+            col("route_type"),
+        ])
+        .collect()?;
+    write_df_to_file(simplify_dir.join("routes.parquet"), FileType::PARQUET, routes)?;
+
+    let route_patterns = route_patterns(trips, stop_times).collect()?;
+    write_df_to_file(simplify_dir.join("route_patterns.parquet"), FileType::PARQUET, route_patterns)?;
+
+    Ok(())
+}
+
+// Weekday flags of calendar.txt in the order of the days since 1970-01-01, which was a thursday
+const WEEKDAYS_FROM_EPOCH: [&str; 7] = ["thursday", "friday", "saturday", "sunday", "monday", "tuesday", "wednesday"];
+
+/// Removes services that run on no day between their start and end date, and trips that belong to
+/// such a service or have fewer than two stops, since neither can be part of any journey. Returns
+/// the remaining trips, services and stop times together with the number of removed trips and
+/// services.
+fn prune_unused(
+    trips: LazyFrame,
+    services: LazyFrame,
+    stop_times: LazyFrame,
+) -> Result<(LazyFrame, LazyFrame, LazyFrame, u32, u32), SimplifyError> {
+    let services = services.collect()?;
+    let days = |column: &str| services.column(column)
+        .and_then(|dates| dates.cast(&DataType::Int32))
+        .and_then(|days| Ok(days.i32()?.clone()));
+    let (start_days, end_days) = (days("start_date")?, days("end_date")?);
+    let weekdays = WEEKDAYS_FROM_EPOCH.iter()
+        .map(|column| Ok(services.column(column)?.bool()?.clone()))
+        .collect::<Result<Vec<_>, SimplifyError>>()?;
+
+    // Every weekday occurs within a week, so at most the first seven days have to be checked
+    let active: Vec<bool> = (0..services.height())
+        .map(|row| match (start_days.get(row), end_days.get(row)) {
+            (Some(start), Some(end)) => (start..=end.min(start + 6))
+                .any(|day| weekdays[day.rem_euclid(7) as usize].get(row) == Some(true)),
+            _ => false,
+        })
+        .collect();
+    let num_services = services.height() as u32;
+    let services = services.filter(Series::new("active".into(), active).bool()?)?;
+    let num_pruned_services = num_services - services.height() as u32;
+
+    let trips_with_stops = stop_times.clone()
+        .group_by([col("dataset_id"), col("trip_id")])
+        .agg([len().alias("num_stops")])
+        .filter(col("num_stops").gt_eq(lit(2)));
+
+    let trips = trips.collect()?;
+    let kept_trips = trips.clone().lazy()
+        .join(
+            services.clone().lazy().select([col("dataset_id"), col("service_id")]),
+            [col("dataset_id"), col("service_id")],
+            [col("dataset_id"), col("service_id")],
+            JoinArgs::new(JoinType::Semi),
+        )
+        .join(
+            trips_with_stops,
+            [col("dataset_id"), col("trip_id")],
+            [col("dataset_id"), col("trip_id")],
+            JoinArgs::new(JoinType::Semi),
+        )
+        .collect()?;
+    let num_pruned_trips = (trips.height() - kept_trips.height()) as u32;
+
+    let stop_times = stop_times.join(
+        kept_trips.clone().lazy().select([col("dataset_id"), col("trip_id")]),
+        [col("dataset_id"), col("trip_id")],
+        [col("dataset_id"), col("trip_id")],
+        JoinArgs::new(JoinType::Semi),
+    );
+
+    Ok((kept_trips.lazy(), services.lazy(), stop_times, num_pruned_trips, num_pruned_services))
+}
+
+/// Of trips that are contained in several datasets, only keeps the one of the dataset with the
+/// smallest id. Returns the remaining trips, stop times and frequencies together with the number of
+/// removed trips.
+fn remove_duplicate_trips(
+    trips: LazyFrame,
+    stop_times: LazyFrame,
+    frequencies: LazyFrame,
+    duplicate_trips: LazyFrame,
+) -> Result<(LazyFrame, LazyFrame, LazyFrame, u32), SimplifyError> {
+    let duplicates = duplicate_trips
+        .select([col("duplicate_dataset_id").alias("dataset_id"), col("duplicate_trip_id").alias("trip_id")])
+        .unique(None, UniqueKeepStrategy::Any)
+        .collect()?
+        .lazy();
+    let without_duplicates = |frame: LazyFrame| frame.join(
+        duplicates.clone(),
+        [col("dataset_id"), col("trip_id")],
+        [col("dataset_id"), col("trip_id")],
+        JoinArgs::new(JoinType::Anti),
+    );
+
+    let trips = trips.collect()?;
+    let kept_trips = without_duplicates(trips.clone().lazy()).collect()?;
+    let num_removed = (trips.height() - kept_trips.height()) as u32;
+
+    Ok((kept_trips.lazy(), without_duplicates(stop_times), without_duplicates(frequencies), num_removed))
+}
+
+// Trips are only turned into a frequency-based trip if at least this many of them run at the same
+// headway
+const MIN_FREQUENCY_RUNS: usize = 3;
+
+/// Replaces trips that only differ by a constant headway from each other with the first of them
+/// and an entry of frequencies.txt, which is expanded into the same runs again later. Only trips
+/// that are the same in all other columns of trips.txt and stop_times.txt are combined, the ids
+/// of the others are lost. Returns the remaining trips, stop times and frequencies together with
+/// the number of removed trips.
+fn compress_frequencies(
+    trips: LazyFrame,
+    stop_times: LazyFrame,
+    frequencies: LazyFrame,
+) -> Result<(LazyFrame, LazyFrame, LazyFrame, u32), SimplifyError> {
+    let by_trip = [col("dataset_id"), col("trip_id")];
+    let other_columns = |frame: &LazyFrame, excluded: &[&str]| -> Result<Vec<String>, SimplifyError> {
+        Ok(frame.clone().collect_schema()?.iter_names()
+            .map(|name| name.to_string())
+            .filter(|name| !excluded.contains(&name.as_str()))
+            .collect())
+    };
+    let trip_columns = other_columns(&trips, &["trip_id", ROW_IN_FILE])?;
+    let stop_time_columns = other_columns(
+        &stop_times,
+        &["dataset_id", "trip_id", "arrival_time", "departure_time", ROW_IN_FILE],
+    )?;
+
+    // Trips with the same times relative to their first departure share a signature
+    let first_departure = col("departure_time").cast(DataType::Int64).min();
+    let in_order = |expr: Expr| expr.sort_by([col("stop_sequence")], Default::default());
+    let signatures = stop_times.clone()
+        .join(
+            trips.clone().join(frequencies.clone(), by_trip.clone(), by_trip.clone(), JoinArgs::new(JoinType::Anti)),
+            by_trip.clone(),
+            by_trip.clone(),
+            JoinArgs::new(JoinType::Semi),
+        )
+        .group_by(by_trip.clone())
+        .agg([
+            vec![
+                first_departure.clone().alias("first_departure"),
+                in_order(col("arrival_time").cast(DataType::Int64) - first_departure.clone()).alias("relative_arrivals"),
+                in_order(col("departure_time").cast(DataType::Int64) - first_departure).alias("relative_departures"),
+            ],
+            stop_time_columns.iter().map(|column| in_order(col(column))).collect(),
+        ].concat())
+        .filter(col("first_departure").is_not_null())
+        .join(trips.clone(), by_trip.clone(), by_trip.clone(), JoinArgs::new(JoinType::Inner));
+
+    let key: Vec<Expr> = [
+        vec![col("relative_arrivals"), col("relative_departures")],
+        [trip_columns, stop_time_columns].concat().iter().map(col).collect(),
+    ].concat();
+    let groups = signatures
+        .group_by(key)
+        .agg([
+            col("trip_id").sort_by([col("first_departure")], Default::default()).alias("trip_ids"),
+            col("first_departure").sort(Default::default()).alias("first_departures"),
+        ])
+        .filter(col("trip_ids").list().len().gt_eq(lit(MIN_FREQUENCY_RUNS as u32)))
+        .select([col("dataset_id"), col("trip_ids"), col("first_departures")])
+        .collect()?;
+
+    let mut new_frequencies = (vec![], vec![], vec![], vec![], vec![]);
+    let mut removed_trips = (vec![], vec![]);
+
+    let rows = groups.column("dataset_id")?.str()?.iter()
+        .zip(groups.column("trip_ids")?.list()?)
+        .zip(groups.column("first_departures")?.list()?);
+    for ((dataset_id, trip_ids), departures) in rows {
+        let (Some(dataset_id), Some(trip_ids), Some(departures)) = (dataset_id, trip_ids, departures) else { continue };
+        let trip_ids: Vec<&str> = trip_ids.str()?.iter().flatten().collect();
+        let departures: Vec<i64> = departures.i64()?.iter().flatten().collect();
+
+        // Greedily looks for the longest sequence of trips with the same headway, starting at
+        // every trip that is not part of an earlier sequence
+        let mut start = 0;
+        while start + 1 < departures.len() {
+            let headway = departures[start + 1] - departures[start];
+            let mut end = start + 2;
+            while end < departures.len() && departures[end] - departures[end - 1] == headway {
+                end += 1;
+            }
+
+            // Headways are given in whole seconds
+            if end - start < MIN_FREQUENCY_RUNS || headway <= 0 || headway % 1000 != 0 {
+                start += 1;
+                continue;
+            }
+
+            new_frequencies.0.push(dataset_id.to_owned());
+            new_frequencies.1.push(trip_ids[start].to_owned());
+            new_frequencies.2.push(departures[start]);
+            // The end time is exclusive
+            new_frequencies.3.push(departures[end - 1] + 1000);
+            new_frequencies.4.push((headway / 1000) as u32);
+            for trip_id in &trip_ids[start + 1..end] {
+                removed_trips.0.push(dataset_id.to_owned());
+                removed_trips.1.push(trip_id.to_string());
+            }
+
+            start = end;
+        }
+    }
+
+    let num_removed = removed_trips.1.len() as u32;
+    let removed_trips = DataFrame::new(vec![
+        Column::new("dataset_id".into(), removed_trips.0),
+        Column::new("trip_id".into(), removed_trips.1),
+    ])?.lazy();
+    let without_removed = |frame: LazyFrame| frame.join(
+        removed_trips.clone(),
+        by_trip.clone(),
+        by_trip.clone(),
+        JoinArgs::new(JoinType::Anti),
+    );
+
+    let as_time = |values: Vec<i64>| Series::new("".into(), values).cast(&DataType::Duration(TimeUnit::Milliseconds));
+    let new_frequencies = DataFrame::new(vec![
+        Column::new("dataset_id".into(), new_frequencies.0),
+        Column::new("trip_id".into(), new_frequencies.1),
+        as_time(new_frequencies.2)?.with_name("start_time".into()).into(),
+        as_time(new_frequencies.3)?.with_name("end_time".into()).into(),
+        Column::new("headway_secs".into(), new_frequencies.4),
+    ])?.lazy();
+    let frequency_columns = [col("dataset_id"), col("trip_id"), col("start_time"), col("end_time"), col("headway_secs")];
+    let frequencies = concat(
+        [frequencies.select(frequency_columns.clone()), new_frequencies.select(frequency_columns)],
+        UnionArgs::default(),
+    )?;
+
+    Ok((without_removed(trips), without_removed(stop_times), frequencies, num_removed))
+}
+
+/// Merges services of a dataset that run on the same weekdays in the same period into one.
+/// Returns the remaining trips and services together with the number of removed services.
+fn merge_identical_services(
+    trips: LazyFrame,
+    services: LazyFrame,
+) -> Result<(LazyFrame, LazyFrame, u32), SimplifyError> {
+    let calendar: Vec<Expr> = [
+        vec![col("dataset_id"), col("start_date"), col("end_date")],
+        WEEKDAYS_FROM_EPOCH.iter().map(|column| col(*column)).collect(),
+    ].concat();
+
+    let services = services
+        .with_column(col("service_id").min().over(calendar).alias("merged_service_id"))
+        .collect()?;
+
+    let trips = trips
+        .join(
+            services.clone().lazy().select([col("dataset_id"), col("service_id"), col("merged_service_id")]),
+            [col("dataset_id"), col("service_id")],
+            [col("dataset_id"), col("service_id")],
+            JoinArgs::new(JoinType::Left),
+        )
+        .with_column(coalesce(&[col("merged_service_id"), col("service_id")]).alias("service_id"))
+        .drop(["merged_service_id"]);
+
+    let merged_services = services.clone().lazy()
+        .filter(col("service_id").eq(col("merged_service_id")))
+        .drop(["merged_service_id"])
+        .collect()?;
+    let num_removed = (services.height() - merged_services.height()) as u32;
+
+    Ok((trips, merged_services.lazy(), num_removed))
+}
+
+/// Runs a single pass of the simplification on the merged datasets
+fn apply_pass(pass: SimplifyPass, merged: DatasetMergeOutput) -> Result<DatasetMergeOutput, SimplifyError> {
+    match pass {
+        SimplifyPass::Dedup => {
+            let (trips, stop_times, frequencies, num_removed) = remove_duplicate_trips(
+                merged.trips, merged.stop_times, merged.frequencies, merged.duplicate_trips.clone(),
+            )?;
+            info!(target: "preprocessing", "Removed {} trips that are contained in another dataset", num_removed);
+            Ok(DatasetMergeOutput { trips, stop_times, frequencies, ..merged })
+        }
+        SimplifyPass::Prune => {
+            let (trips, services, stop_times, num_pruned_trips, num_pruned_services) =
+                prune_unused(merged.trips, merged.services, merged.stop_times)?;
+
+            let stops = merged.stops.collect()?;
+            let used_stops = stops.clone().lazy()
+                .join(
+                    stop_times.clone().select([col("dataset_id"), col("stop_id")]),
+                    [col("dataset_id"), col("stop_id")],
+                    [col("dataset_id"), col("stop_id")],
+                    JoinArgs::new(JoinType::Semi),
+                )
+                .collect()?;
+            info!(
+                target: "preprocessing",
+                "Pruned {} stops, {} trips and {} services that are never used",
+                stops.height() - used_stops.height(), num_pruned_trips, num_pruned_services,
+            );
+            Ok(DatasetMergeOutput { stops: used_stops.lazy(), trips, services, stop_times, ..merged })
+        }
+        SimplifyPass::FrequencyCompress => {
+            let (trips, stop_times, frequencies, num_removed) =
+                compress_frequencies(merged.trips, merged.stop_times, merged.frequencies)?;
+            info!(target: "preprocessing", "Compressed {} trips that run at a constant headway", num_removed);
+            Ok(DatasetMergeOutput { trips, stop_times, frequencies, ..merged })
+        }
+        SimplifyPass::DayReduce => {
+            let (trips, services, num_removed) = merge_identical_services(merged.trips, merged.services)?;
+            info!(target: "preprocessing", "Merged {} services into another one with the same days", num_removed);
+            Ok(DatasetMergeOutput { trips, services, ..merged })
+        }
+    }
+}
+
+#[instrument(name = "simplify", skip_all)]
+pub async fn simplify(merged: DatasetMergeOutput, config: &SimplifyConfig) -> Result<PreprocessingInput, SimplifyError> {
+    let merged = config.passes.iter()
+        .try_fold(merged, |merged, pass| apply_pass(*pass, merged))?;
+
+    // The tables of the previous import are overwritten below
+    let previous_trips = PreviousTrips::read(&paths::tmp_dir().join("simplify"))?;
+
+    let DatasetMergeOutput {
+        agencies,
+        routes,
+        booking_rules,
         stops,
         trips,
         services,
         stop_times,
+        frequencies,
+        stop_duplicates,
+        transfers,
+        dataset_transfers,
         ..
-    }: DatasetMergeOutput
-) -> Result<PreprocessingInput, SimplifyError> {
-    // Turn stop ids into integers
-    let stops = stops
-        // Only include stops that are used in trips
-        .semi_join(
-            stop_times.clone(),
-            col("stop_id"),
-            col("stop_id")
-        ).select([
+    } = merged;
+
+    // Stops that are merged into a stop of another dataset get the id of that stop. Turn stop ids
+    // into integers.
+    let stops = inherit_wheelchair_boarding(stops, "stop_id")
+        .join(
+            stop_duplicates.clone(),
+            [col("dataset_id"), col("stop_id")],
+            [col("dataset_id"), col("stop_id")],
+            JoinArgs::new(JoinType::Anti),
+        )
+        .select([
             // Keep "old" id-pairs (stop_id + dataset_id) so that we can match in other tables
             col("stop_id").alias("stop_id_in_dataset"),
             col("dataset_id"),
             col("stop_lat").alias("lat"),
             col("stop_lon").alias("lon"),
+            col("location_type"),
+            col("parent_station"),
+            col("stop_name"),
+            col("wheelchair_boarding"),
         ]);
 
     // Generate a new stop_id
     let stops = assign_new_ids(stops.collect()?, "stop_id")?;
+    let stop_ids = IdInterner::from_originals(namespaced_ids(&stops, "stop_id_in_dataset")?.iter().map(String::as_str));
+    metrics::record("stops", stops.height() as f64);
 
-    write_df_to_file("data/tmp/simplify/stops.parquet".into(), FileType::PARQUET, stops.clone())?;
+    write_df_to_file(paths::tmp_dir().join("simplify").join("stops.parquet"), FileType::PARQUET, stops.clone())?;
     let stops = stops.lazy();
 
+    // Build the station hierarchy: Platforms that share a parent station belong to the same
+    // station, stops without a parent station form a station on their own.
+    let stops_with_station = stops.clone()
+        .select([
+            col("stop_id"),
+            col("dataset_id"),
+            coalesce(&[col("parent_station"), col("stop_id_in_dataset")]).alias("station_id_in_dataset"),
+        ]);
+
+    let station_ids = stops_with_station.clone()
+        .select([col("dataset_id"), col("station_id_in_dataset")])
+        .unique_stable(None, UniqueKeepStrategy::First);
+    let station_ids = assign_new_ids(station_ids.collect()?, "station_id")?;
+
+    let stations = stops_with_station
+        .join(
+            station_ids.lazy(),
+            [col("dataset_id"), col("station_id_in_dataset")],
+            [col("dataset_id"), col("station_id_in_dataset")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .select([col("stop_id"), col("station_id")])
+        .collect()?;
+
+    write_df_to_file(paths::tmp_dir().join("simplify").join("stations.parquet"), FileType::PARQUET, stations.clone())?;
+    let stations = stations.lazy();
+
+    // The route type decides the mode of a trip
     let trips = trips
         .select([
             col("trip_id").alias("trip_id_in_dataset"),
             col("route_id").alias("route_id_in_dataset"),
             col("service_id").alias("service_id_in_dataset"),
             col("dataset_id"),
-        ]);
+            col("wheelchair_accessible"),
+            col("bikes_allowed"),
+        ])
+        .join(
+            routes.clone()
+                .select([col("dataset_id"), col("route_id").alias("route_id_in_dataset"), col("route_type")])
+                // Duplicate route ids must not duplicate trips
+                .unique_stable(Some(vec!["dataset_id".into(), "route_id_in_dataset".into()]), UniqueKeepStrategy::First),
+            [col("dataset_id"), col("route_id_in_dataset")],
+            [col("dataset_id"), col("route_id_in_dataset")],
+            JoinArgs::new(JoinType::Left),
+        );
+
+    // Frequency-based trips are expanded into one trip per run. Regular trips are a single run
+    // without an offset.
+    let runs = expand_frequencies(frequencies.clone(), stop_times.clone())?;
+
+    let regular_trips = trips.clone()
+        .join(
+            frequencies.select([col("dataset_id"), col("trip_id").alias("trip_id_in_dataset")]),
+            [col("dataset_id"), col("trip_id_in_dataset")],
+            [col("dataset_id"), col("trip_id_in_dataset")],
+            JoinArgs::new(JoinType::Anti),
+        )
+        .with_column(lit(0i64).cast(DataType::Duration(TimeUnit::Milliseconds)).alias("run_offset"));
+
+    let frequency_trips = trips
+        .join(
+            runs.lazy(),
+            [col("dataset_id"), col("trip_id_in_dataset")],
+            [col("dataset_id"), col("trip_id_in_dataset")],
+            JoinArgs::new(JoinType::Inner),
+        );
+
+    let trips = concat([regular_trips, frequency_trips], UnionArgs::default())?;
 
     let trips = assign_new_ids(trips.collect()?, "trip_id")?;
+    let trip_ids = IdInterner::from_originals(namespaced_ids(&trips, "trip_id_in_dataset")?.iter().map(String::as_str));
 
-    write_df_to_file("data/tmp/simplify/trips.parquet".into(), FileType::PARQUET, trips.clone())?;
+    write_df_to_file(paths::tmp_dir().join("simplify").join("trips.parquet"), FileType::PARQUET, trips.clone())?;
     let trips = trips.lazy();
 
+    // All runs of the same template share the lowest trip id among them as template
+    let trip_runs = trips.clone()
+        .select([
+            col("trip_id"),
+            col("trip_id").min()
+                .over([col("dataset_id"), col("trip_id_in_dataset")])
+                .alias("template_trip_id"),
+            col("run_offset"),
+        ])
+        .collect()?;
+
+    write_df_to_file(paths::tmp_dir().join("simplify").join("trip_runs.parquet"), FileType::PARQUET, trip_runs.clone())?;
+    let trip_runs = trip_runs.lazy();
+
     let services = services
         .select([
             col("dataset_id"),
@@ -75,9 +704,69 @@ pub async fn simplify(
 
     let services = assign_new_ids(services.collect()?, "service_id")?;
 
-    write_df_to_file("data/tmp/simplify/services.parquet".into(), FileType::PARQUET, services.clone())?;
+    write_df_to_file(paths::tmp_dir().join("simplify").join("services.parquet"), FileType::PARQUET, services.clone())?;
     let services = services.lazy();
 
+    // Lookup of the new id for every stop of every dataset, including the merged ones
+    let stop_lookup = concat(
+        [
+            stops.clone().select([col("dataset_id"), col("stop_id_in_dataset"), col("stop_id")]),
+            stop_duplicates
+                .join(
+                    stops.clone().select([col("dataset_id"), col("stop_id_in_dataset"), col("stop_id").alias("new_stop_id")]),
+                    [col("canonical_dataset_id"), col("canonical_stop_id")],
+                    [col("dataset_id"), col("stop_id_in_dataset")],
+                    JoinArgs::new(JoinType::Inner),
+                )
+                .select([col("dataset_id"), col("stop_id").alias("stop_id_in_dataset"), col("new_stop_id").alias("stop_id")]),
+        ],
+        UnionArgs::default(),
+    )?;
+
+    // Transfers from or to merged stops use the stop they are merged into, transfers that end up
+    // at the stop they start at are not needed
+    let transfers = transfers
+        .join(
+            stop_lookup.clone().select([col("dataset_id"), col("stop_id_in_dataset"), col("stop_id").alias("from_stop")]),
+            [col("from_dataset_id"), col("from_stop_id")],
+            [col("dataset_id"), col("stop_id_in_dataset")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .join(
+            stop_lookup.clone().select([col("dataset_id"), col("stop_id_in_dataset"), col("stop_id").alias("to_stop")]),
+            [col("to_dataset_id"), col("to_stop_id")],
+            [col("dataset_id"), col("stop_id_in_dataset")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .filter(col("from_stop").neq(col("to_stop")))
+        .select([col("from_stop").alias("from_stop_id"), col("to_stop").alias("to_stop_id"), col("duration")])
+        .unique_stable(Some(vec!["from_stop_id".into(), "to_stop_id".into()]), UniqueKeepStrategy::First)
+        .collect()?;
+    write_df_to_file(paths::tmp_dir().join("simplify").join("transfers.parquet"), FileType::PARQUET, transfers.clone())?;
+
+    // Minimum times of transfers.txt are between stops of the same dataset. Merged stops get one of
+    // the times of the stop they are merged into. The walks between datasets are added, so that
+    // routing can change between them, but the times of the datasets take precedence.
+    let dataset_transfers = dataset_transfers
+        .join(
+            stop_lookup.clone().select([col("dataset_id"), col("stop_id_in_dataset"), col("stop_id").alias("from_stop")]),
+            [col("dataset_id"), col("from_stop_id")],
+            [col("dataset_id"), col("stop_id_in_dataset")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .join(
+            stop_lookup.clone().select([col("dataset_id"), col("stop_id_in_dataset"), col("stop_id").alias("to_stop")]),
+            [col("dataset_id"), col("to_stop_id")],
+            [col("dataset_id"), col("stop_id_in_dataset")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .filter(col("from_stop").neq(col("to_stop")))
+        .select([col("from_stop").alias("from_stop_id"), col("to_stop").alias("to_stop_id"), col("duration")]);
+    let timetable_transfers = concat([dataset_transfers, transfers.lazy()], UnionArgs::default())?
+        .unique_stable(Some(vec!["from_stop_id".into(), "to_stop_id".into()]), UniqueKeepStrategy::First)
+        .collect()?;
+    write_df_to_file(paths::tmp_dir().join("simplify").join("timetable_transfers.parquet"), FileType::PARQUET, timetable_transfers.clone())?;
+
     let stop_times = stop_times
         .select([
             col("trip_id").alias("trip_id_in_dataset"),
@@ -86,25 +775,129 @@ pub async fn simplify(
             col("stop_id").alias("stop_id_in_dataset"),
             col("dataset_id"),
             col("stop_sequence"),
+            col("pickup_booking_rule_id"),
+            col("drop_off_booking_rule_id"),
         ])
         // Convert stop_ids to numeric ones
         .join(
-            stops.clone().select([col("dataset_id"), col("stop_id_in_dataset"), col("stop_id")]),
+            stop_lookup,
             [col("dataset_id"), col("stop_id_in_dataset")],
             [col("dataset_id"), col("stop_id_in_dataset")],
             JoinArgs::new(JoinType::Inner),
         )
-        // Convert trip_ids to numeric ones
+        // Convert trip_ids to numeric ones. Trips with several runs are duplicated here.
         .join(
-            trips.clone().select([col("dataset_id"), col("trip_id_in_dataset"), col("trip_id")]),
+            trips.clone().select([col("dataset_id"), col("trip_id_in_dataset"), col("trip_id"), col("run_offset")]),
             [col("dataset_id"), col("trip_id_in_dataset")],
             [col("dataset_id"), col("trip_id_in_dataset")],
             JoinArgs::new(JoinType::Inner),
-        );
+        )
+        // Move the times of every run by its offset to the template
+        .with_columns([
+            (col("arrival_time") + col("run_offset")).alias("arrival_time"),
+            (col("departure_time") + col("run_offset")).alias("departure_time"),
+        ])
+        .drop(["run_offset"]);
+
+    write_df_to_file(paths::tmp_dir().join("simplify").join("stop_times.parquet"), FileType::PARQUET, stop_times.clone().collect()?)?;
+
+    let (trip_identities, num_carried_over) = stable_trip_ids(&trips.clone().collect()?, stop_times.clone(), previous_trips)?;
+    if num_carried_over > 0 {
+        info!(target: "preprocessing", "Carried over the identifiers of {} trips from the previous import", num_carried_over);
+    }
+    let stable_trips = IdInterner::from_originals(stable_ids(&trip_identities)?.iter().map(String::as_str));
+    write_df_to_file(paths::tmp_dir().join("simplify").join("trip_identities.parquet"), FileType::PARQUET, trip_identities)?;
+
+    let booking_notes = booking_notes(agencies.clone(), routes.clone(), booking_rules, trips.clone(), stop_times.clone())?.collect()?;
+    write_df_to_file(paths::tmp_dir().join("simplify").join("booking_notes.parquet"), FileType::PARQUET, booking_notes)?;
+
+    write_browse_tables(agencies, routes, trips.clone(), stop_times.clone())?;
+
+    Ok(PreprocessingInput {
+        transfers: timetable_transfers.lazy(),
+        ..routing_input(stops, stations, trips, trip_runs, services, stop_times, OriginalIds { stops: stop_ids, trips: trip_ids, stable_trips })
+    })
+}
 
-    write_df_to_file("data/tmp/simplify/stop_times.parquet".into(), FileType::PARQUET, stop_times.clone().collect()?)?;
+/// The directory of a working directory that [simplify] writes its tables to
+pub fn simplified_dir(work_dir: &Path) -> PathBuf {
+    paths::tmp_dir_in(work_dir).join("simplify")
+}
 
-    let stop_times = stop_times.drop(["stop_id_in_dataset"])
+/// Reads the tables that [simplify] wrote to the temporary directory of a working directory, so
+/// that the timetable of an earlier run can be used without fetching and processing the datasets
+/// again
+pub fn read_simplified(work_dir: &Path) -> Result<PreprocessingInput, SimplifyError> {
+    read_simplified_from(&simplified_dir(work_dir))
+}
+
+/// Like [read_simplified], but from a directory with the tables themselves, e.g. the copy that was
+/// saved with the results of preprocessing
+pub fn read_simplified_from(dir: &Path) -> Result<PreprocessingInput, SimplifyError> {
+    let scan = |name: &str| LazyFrame::scan_parquet(dir.join(format!("{name}.parquet")), Default::default());
+
+    let stops = scan("stops")?.collect()?;
+    let trips = scan("trips")?.collect()?;
+    let original_ids = original_ids(dir, &stops, &trips)?;
+    // Runs before minimum transfer times were imported have none
+    let timetable_transfers = match dir.join("timetable_transfers.parquet").exists() {
+        true => scan("timetable_transfers")?,
+        false => LazyFrame::default(),
+    };
+
+    Ok(PreprocessingInput {
+        transfers: timetable_transfers,
+        ..routing_input(
+            stops.lazy(), scan("stations")?, trips.lazy(), scan("trip_runs")?, scan("services")?, scan("stop_times")?, original_ids,
+        )
+    })
+}
+
+/// The original ids of the stops and trips that [simplify] wrote, without reading the rest of the
+/// timetable, e.g. to match realtime feeds to the trips
+pub fn read_original_ids(work_dir: &Path) -> Result<OriginalIds, SimplifyError> {
+    read_original_ids_from(&simplified_dir(work_dir))
+}
+
+/// Like [read_original_ids], but from a directory with the tables themselves
+pub fn read_original_ids_from(dir: &Path) -> Result<OriginalIds, SimplifyError> {
+    let scan = |name: &str, column: &str| LazyFrame::scan_parquet(dir.join(format!("{name}.parquet")), Default::default())?
+        .select([col("dataset_id"), col(column)])
+        .collect();
+
+    let stops = scan("stops", "stop_id_in_dataset")?;
+    let trips = scan("trips", "trip_id_in_dataset")?;
+    original_ids(dir, &stops, &trips)
+}
+
+fn original_ids(dir: &Path, stops: &DataFrame, trips: &DataFrame) -> Result<OriginalIds, SimplifyError> {
+    // New ids are the row numbers, so the interners can be built from the rows in their order
+    let trip_ids = namespaced_ids(trips, "trip_id_in_dataset")?;
+    // Runs before stable identifiers existed use the original identifiers
+    let identities = dir.join("trip_identities.parquet");
+    let stable_trip_ids = if identities.exists() {
+        stable_ids(&LazyFrame::scan_parquet(identities, Default::default())?.collect()?)?
+    } else {
+        trip_ids.clone()
+    };
+    Ok(OriginalIds {
+        stops: IdInterner::from_originals(namespaced_ids(stops, "stop_id_in_dataset")?.iter().map(String::as_str)),
+        trips: IdInterner::from_originals(trip_ids.iter().map(String::as_str)),
+        stable_trips: IdInterner::from_originals(stable_trip_ids.iter().map(String::as_str)),
+    })
+}
+
+// Removes the columns of the tables that only the written tables need
+fn routing_input(
+    stops: LazyFrame,
+    stations: LazyFrame,
+    trips: LazyFrame,
+    trip_runs: LazyFrame,
+    services: LazyFrame,
+    stop_times: LazyFrame,
+    original_ids: OriginalIds,
+) -> PreprocessingInput {
+    let stop_times = stop_times.drop(["stop_id_in_dataset", "pickup_booking_rule_id", "drop_off_booking_rule_id"])
         .drop(["dataset_id", "trip_id_in_dataset"]);
 
     let trips = trips
@@ -118,23 +911,28 @@ pub async fn simplify(
 
     let stops = stops
         .drop([
-            "stop_id_in_dataset", "dataset_id"
+            "stop_id_in_dataset", "dataset_id", "parent_station", "stop_name"
         ]);
 
     let trips = trips.drop([
-        "trip_id_in_dataset", "dataset_id"
+        "trip_id_in_dataset", "dataset_id", "run_offset"
     ]);
 
     let services = services.drop([
         "service_id_in_dataset", "dataset_id"
     ]);
 
-    Ok(PreprocessingInput {
+    PreprocessingInput {
         services,
         stops,
         trips,
         stop_times,
-    })
+        stations,
+        trip_runs,
+        // Transfers have no columns to remove, the callers set them
+        transfers: LazyFrame::default(),
+        original_ids: Arc::new(original_ids),
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -149,4 +947,282 @@ impl Display for SimplifyError {
         };
         write!(f, "{}", err)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use common::types::{StopId, TripId};
+    use polars::df;
+
+    #[test]
+    fn test_route_patterns() {
+        let trips = df!(
+            "trip_id"             => [0u32, 1, 2],
+            "dataset_id"          => ["d", "d", "d"],
+            "route_id_in_dataset" => ["r1", "r1", "r1"],
+        ).unwrap().lazy();
+        // Trips 0 and 2 serve the same stops, trip 1 only a part of them
+        let stop_times = df!(
+            "trip_id"       => [0u32, 0, 0, 1, 1, 2, 2, 2],
+            "stop_id"       => [10u32, 11, 12, 11, 12, 12, 10, 11],
+            "stop_sequence" => [1u32, 2, 3, 1, 2, 3, 1, 2],
+        ).unwrap().lazy();
+
+        let patterns = route_patterns(trips, stop_times).collect().unwrap();
+
+        assert_eq!(patterns.height(), 2);
+        assert_eq!(patterns.column("num_trips").unwrap().u32().unwrap().to_vec(), [Some(2), Some(1)]);
+        let stop_ids = patterns.column("stop_ids").unwrap().list().unwrap().get_as_series(0).unwrap();
+        assert_eq!(stop_ids.u32().unwrap().to_vec(), [Some(10), Some(11), Some(12)]);
+    }
+
+    #[test]
+    fn test_booking_notes() {
+        let agencies = df!(
+            "dataset_id"   => ["d"],
+            "agency_id"    => [None::<&str>],
+            "agency_phone" => ["+49 711 123"],
+            "agency_url"   => ["https://example.com"],
+        ).unwrap().lazy();
+        let routes = df!(
+            "dataset_id" => ["d"],
+            "route_id"   => ["r1"],
+            "agency_id"  => [None::<&str>],
+        ).unwrap().lazy();
+        let booking_rules = df!(
+            "dataset_id"                => ["d", "d"],
+            "booking_rule_id"           => ["call", "app"],
+            "booking_type"              => [1u32, 0],
+            "prior_notice_duration_min" => [Some(60u32), None],
+            "message"                   => [None::<&str>, Some("Book in the app")],
+            "phone_number"              => [None::<&str>, None],
+            "info_url"                  => [None::<&str>, None],
+            "booking_url"               => [None::<&str>, Some("https://example.com/app")],
+        ).unwrap().lazy();
+        let trips = df!(
+            "trip_id"             => [0u32],
+            "dataset_id"          => ["d"],
+            "route_id_in_dataset" => ["r1"],
+        ).unwrap().lazy();
+        let stop_times = df!(
+            "trip_id"                  => [0u32, 0, 0],
+            "stop_id"                  => [10u32, 11, 12],
+            "dataset_id"               => ["d", "d", "d"],
+            "pickup_booking_rule_id"   => [Some("call"), None, None],
+            "drop_off_booking_rule_id" => [None, Some("app"), None],
+        ).unwrap().lazy();
+
+        let notes = booking_notes(agencies, routes, booking_rules, trips, stop_times).unwrap()
+            .sort(["stop_id"], Default::default())
+            .collect().unwrap();
+
+        assert_eq!(notes.height(), 2);
+        let kinds: Vec<Option<&str>> = notes.column("kind").unwrap().str().unwrap().iter().collect();
+        assert_eq!(kinds, [Some("pickup"), Some("drop_off")]);
+        // The rule without contact information uses the phone number of the agency
+        let phone_numbers: Vec<Option<&str>> = notes.column("phone_number").unwrap().str().unwrap().iter().collect();
+        assert_eq!(phone_numbers, [Some("+49 711 123"), Some("+49 711 123")]);
+        let urls: Vec<Option<&str>> = notes.column("url").unwrap().str().unwrap().iter().collect();
+        assert_eq!(urls, [Some("https://example.com"), Some("https://example.com/app")]);
+    }
+
+    #[test]
+    fn test_prune_unused() {
+        // 2025-01-06 was a monday. Service "mon" only runs on mondays, but only from tuesday to
+        // sunday of that week.
+        let services = df!(
+            "dataset_id" => ["d", "d"],
+            "service_id" => ["daily", "mon"],
+            "monday"     => [true, true],
+            "tuesday"    => [true, false],
+            "wednesday"  => [true, false],
+            "thursday"   => [true, false],
+            "friday"     => [true, false],
+            "saturday"   => [true, false],
+            "sunday"     => [true, false],
+            "start_date" => [NaiveDate::from_ymd_opt(2025, 1, 6).unwrap(), NaiveDate::from_ymd_opt(2025, 1, 7).unwrap()],
+            "end_date"   => [NaiveDate::from_ymd_opt(2025, 1, 6).unwrap(), NaiveDate::from_ymd_opt(2025, 1, 12).unwrap()],
+        ).unwrap().lazy();
+        let trips = df!(
+            "dataset_id" => ["d", "d", "d"],
+            "trip_id"    => ["t1", "t2", "t3"],
+            "service_id" => ["daily", "daily", "mon"],
+        ).unwrap().lazy();
+        // t2 only has a single stop
+        let stop_times = df!(
+            "dataset_id" => ["d", "d", "d", "d", "d"],
+            "trip_id"    => ["t1", "t1", "t2", "t3", "t3"],
+            "stop_id"    => ["a", "b", "a", "a", "b"],
+        ).unwrap().lazy();
+
+        let (trips, services, stop_times, num_trips, num_services) = prune_unused(trips, services, stop_times).unwrap();
+        assert_eq!((num_trips, num_services), (2, 1));
+        assert_eq!(trips.collect().unwrap().column("trip_id").unwrap().str().unwrap().get(0), Some("t1"));
+        assert_eq!(services.collect().unwrap().height(), 1);
+        assert_eq!(stop_times.collect().unwrap().height(), 2);
+    }
+
+    #[test]
+    fn test_remove_duplicate_trips() {
+        let trips = df!(
+            "dataset_id" => ["a", "b", "b"],
+            "trip_id"    => ["t1", "t1", "t2"],
+        ).unwrap().lazy();
+        let stop_times = df!(
+            "dataset_id" => ["a", "a", "b", "b", "b", "b"],
+            "trip_id"    => ["t1", "t1", "t1", "t1", "t2", "t2"],
+        ).unwrap().lazy();
+        let frequencies = df!("dataset_id" => ["b"], "trip_id" => ["t1"]).unwrap().lazy();
+        let duplicate_trips = df!(
+            "dataset_id"           => ["a"],
+            "trip_id"              => ["t1"],
+            "duplicate_dataset_id" => ["b"],
+            "duplicate_trip_id"    => ["t1"],
+        ).unwrap().lazy();
+
+        let (trips, stop_times, frequencies, num_removed) =
+            remove_duplicate_trips(trips, stop_times, frequencies, duplicate_trips).unwrap();
+        assert_eq!(num_removed, 1);
+        assert_eq!(trips.collect().unwrap().height(), 2);
+        assert_eq!(stop_times.collect().unwrap().height(), 4);
+        assert_eq!(frequencies.collect().unwrap().height(), 0);
+    }
+
+    #[test]
+    fn test_compress_frequencies() {
+        let minutes = |values: &[i64]| Series::new("".into(), values.iter().map(|minutes| minutes * 60_000).collect::<Vec<_>>())
+            .cast(&DataType::Duration(TimeUnit::Milliseconds)).unwrap();
+        // t1 to t4 leave every 10 minutes and take 5 minutes, t5 as well but takes 6 minutes. t6
+        // follows the headway again, but belongs to another route.
+        let trips = df!(
+            "dataset_id" => ["d"; 6],
+            "trip_id"    => ["t1", "t2", "t3", "t4", "t5", "t6"],
+            "route_id"   => ["r1", "r1", "r1", "r1", "r1", "r2"],
+        ).unwrap().lazy();
+        let times: Vec<i64> = [(0, 5), (10, 15), (20, 25), (30, 35), (40, 46), (50, 55)].iter()
+            .flat_map(|(departure, arrival)| [*departure, *arrival])
+            .collect();
+        let stop_times = df!(
+            "dataset_id"     => ["d"; 12],
+            "trip_id"        => ["t1", "t1", "t2", "t2", "t3", "t3", "t4", "t4", "t5", "t5", "t6", "t6"],
+            "stop_id"        => ["a", "b"].repeat(6),
+            "stop_sequence"  => [1u32, 2].repeat(6),
+            "arrival_time"   => minutes(&times),
+            "departure_time" => minutes(&times),
+        ).unwrap().lazy();
+        let frequencies = df!(
+            "dataset_id"   => [""; 0],
+            "trip_id"      => [""; 0],
+            "start_time"   => minutes(&[]),
+            "end_time"     => minutes(&[]),
+            "headway_secs" => [0u32; 0],
+        ).unwrap().lazy();
+
+        let (trips, stop_times, frequencies, num_removed) = compress_frequencies(trips, stop_times, frequencies).unwrap();
+        assert_eq!(num_removed, 3);
+        let trips = trips.sort(["trip_id"], Default::default()).collect().unwrap();
+        let trip_ids: Vec<Option<&str>> = trips.column("trip_id").unwrap().str().unwrap().iter().collect();
+        assert_eq!(trip_ids, [Some("t1"), Some("t5"), Some("t6")]);
+        assert_eq!(stop_times.collect().unwrap().height(), 6);
+
+        // Expanding the frequency results in the same runs again
+        let frequencies = frequencies.collect().unwrap();
+        assert_eq!(frequencies.column("headway_secs").unwrap().u32().unwrap().get(0), Some(600));
+        let runs = expand_frequencies(frequencies.lazy(), df!(
+            "dataset_id"     => ["d"],
+            "trip_id"        => ["t1"],
+            "departure_time" => minutes(&[0]),
+        ).unwrap().lazy()).unwrap();
+        assert_eq!(runs.height(), 4);
+    }
+
+    #[test]
+    fn test_merge_identical_services() {
+        let date = |day: u32| NaiveDate::from_ymd_opt(2025, 1, day).unwrap();
+        // "s1" and "s2" run on the same days, "s3" in another period and "x" in another dataset
+        let services = df!(
+            "dataset_id" => ["d", "d", "d", "e"],
+            "service_id" => ["s2", "s1", "s3", "x"],
+            "monday"     => [true; 4],
+            "tuesday"    => [true; 4],
+            "wednesday"  => [true; 4],
+            "thursday"   => [true; 4],
+            "friday"     => [true; 4],
+            "saturday"   => [false; 4],
+            "sunday"     => [false; 4],
+            "start_date" => [date(1), date(1), date(2), date(1)],
+            "end_date"   => [date(31); 4],
+        ).unwrap().lazy();
+        let trips = df!(
+            "dataset_id" => ["d", "d", "d", "e"],
+            "trip_id"    => ["t1", "t2", "t3", "t4"],
+            "service_id" => ["s1", "s2", "s3", "x"],
+        ).unwrap().lazy();
+
+        let (trips, services, num_removed) = merge_identical_services(trips, services).unwrap();
+        assert_eq!(num_removed, 1);
+        assert_eq!(services.collect().unwrap().height(), 3);
+        let trips = trips.sort(["trip_id"], Default::default()).collect().unwrap();
+        let service_ids: Vec<Option<&str>> = trips.column("service_id").unwrap().str().unwrap().iter().collect();
+        assert_eq!(service_ids, [Some("s1"), Some("s1"), Some("s3"), Some("x")]);
+    }
+
+    #[test]
+    fn test_read_simplified() {
+        let work_dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, frame: DataFrame| {
+            let path = paths::tmp_dir_in(work_dir.path()).join("simplify").join(format!("{name}.parquet"));
+            write_df_to_file(path, FileType::PARQUET, frame).unwrap();
+        };
+
+        write("stops", df!(
+            "stop_id"            => [0u32, 1],
+            "stop_id_in_dataset" => ["a", "b"],
+            "dataset_id"         => ["d", "d"],
+            "parent_station"     => [None::<&str>, None],
+            "stop_name"          => ["A", "B"],
+        ).unwrap());
+        write("stations", df!(
+            "stop_id"    => [0u32, 1],
+            "station_id" => [0u32, 1],
+        ).unwrap());
+        write("trips", df!(
+            "trip_id"               => [0u32],
+            "trip_id_in_dataset"    => ["t"],
+            "service_id_in_dataset" => ["s"],
+            "dataset_id"            => ["d"],
+            "run_offset"            => [0i64],
+        ).unwrap());
+        write("trip_runs", df!(
+            "trip_id"          => [0u32],
+            "template_trip_id" => [0u32],
+        ).unwrap());
+        write("services", df!(
+            "service_id"            => [0u32],
+            "service_id_in_dataset" => ["s"],
+            "dataset_id"            => ["d"],
+        ).unwrap());
+        write("stop_times", df!(
+            "trip_id"                  => [0u32, 0],
+            "stop_id"                  => [0u32, 1],
+            "trip_id_in_dataset"       => ["t", "t"],
+            "stop_id_in_dataset"       => ["a", "b"],
+            "dataset_id"               => ["d", "d"],
+            "pickup_booking_rule_id"   => [None::<&str>, None],
+            "drop_off_booking_rule_id" => [None::<&str>, None],
+        ).unwrap());
+
+        let input = read_simplified(work_dir.path()).unwrap();
+        assert_eq!(input.original_ids.stops.get("d:b"), Some(StopId(1)));
+        assert_eq!(input.original_ids.trip(TripId(0)), Some("d:t"));
+        // Without the stable identifiers of the trips, the original ones are used
+        assert_eq!(input.original_ids.find_trip("d:t"), Some(TripId(0)));
+        assert_eq!(read_original_ids(work_dir.path()).unwrap().find_trip("d:t"), Some(TripId(0)));
+
+        let trips = input.trips.collect().unwrap();
+        assert_eq!(trips.get_column_names_str(), ["trip_id", "service_id"]);
+        assert_eq!(input.stops.collect().unwrap().get_column_names_str(), ["stop_id"]);
+        assert_eq!(input.stop_times.collect().unwrap().get_column_names_str(), ["trip_id", "stop_id"]);
+    }
+}
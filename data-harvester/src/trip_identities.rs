@@ -0,0 +1,213 @@
+use crate::step5_simplify::SimplifyError;
+use polars::datatypes::DataType;
+use polars::frame::DataFrame;
+use polars::prelude::{col, Column, IntoLazy, JoinArgs, JoinType, LazyFrame};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::path::Path;
+
+/// The stable identifiers of the trips of the previous import, by the signature of the trip.
+/// Trips with the same signature are kept in the order of their ids.
+#[derive(Debug, Default)]
+pub struct PreviousTrips(HashMap<String, VecDeque<String>>);
+
+impl PreviousTrips {
+    /// Reads the trips that the previous import wrote to the `simplify` directory. Imports before
+    /// stable identifiers existed use the original identifiers of their trips.
+    pub fn read(simplify_dir: &Path) -> Result<Self, SimplifyError> {
+        let [trips, stop_times, identities] = ["trips", "stop_times", "trip_identities"]
+            .map(|name| simplify_dir.join(format!("{name}.parquet")));
+        if !trips.exists() || !stop_times.exists() {
+            return Ok(Self::default());
+        }
+
+        let scan = |path: &Path| LazyFrame::scan_parquet(path, Default::default());
+        let identities = identities.exists().then(|| scan(&identities)).transpose()?;
+        Self::from_frames(scan(&trips)?, scan(&stop_times)?, identities)
+    }
+
+    // `trips` has the columns "trip_id", "dataset_id", "trip_id_in_dataset" and
+    // "route_id_in_dataset", `identities` maps "trip_id" to "stable_trip_id"
+    fn from_frames(trips: LazyFrame, stop_times: LazyFrame, identities: Option<LazyFrame>) -> Result<Self, SimplifyError> {
+        let trips = trips.collect()?;
+        let signatures = signatures(&trips, stop_times)?;
+
+        let stable_ids = match identities {
+            Some(identities) => {
+                let identities = trips.clone().lazy()
+                    .select([col("trip_id")])
+                    .join(identities, [col("trip_id")], [col("trip_id")], JoinArgs::new(JoinType::Left))
+                    .collect()?;
+                identities.column("stable_trip_id")?.str()?.iter()
+                    .map(|id| id.unwrap_or_default().to_string())
+                    .collect()
+            }
+            None => original_ids(&trips)?,
+        };
+
+        let mut previous: HashMap<String, VecDeque<String>> = HashMap::new();
+        for (signature, stable_id) in signatures.into_iter().zip(stable_ids) {
+            previous.entry(signature).or_default().push_back(stable_id);
+        }
+        Ok(Self(previous))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.values().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Gives every trip the stable identifier of the trip of the previous import with the same
+/// dataset, route, stops and departures, so that identifiers that clients and realtime feeds know
+/// keep resolving when a new version of a feed renames its trips. Other trips keep their original
+/// identifier, with a suffix like "~1" if another trip carries it over already. Returns the
+/// columns "trip_id" and "stable_trip_id" in the order of `trips`, and the number of trips whose
+/// identifier was carried over.
+pub fn stable_trip_ids(
+    trips: &DataFrame,
+    stop_times: LazyFrame,
+    mut previous: PreviousTrips,
+) -> Result<(DataFrame, usize), SimplifyError> {
+    let signatures = signatures(trips, stop_times)?;
+
+    let mut stable_ids: Vec<Option<String>> = signatures.iter()
+        .map(|signature| previous.0.get_mut(signature).and_then(VecDeque::pop_front))
+        .collect();
+    let num_carried_over = stable_ids.iter().flatten().count();
+
+    // Identifiers are only taken once all trips that carry one over are known
+    let mut taken: HashSet<String> = stable_ids.iter().flatten().cloned().collect();
+    for (stable_id, original_id) in stable_ids.iter_mut().zip(original_ids(trips)?) {
+        if stable_id.is_some() {
+            continue;
+        }
+        let mut id = original_id.clone();
+        let mut suffix = 0;
+        while taken.contains(&id) {
+            suffix += 1;
+            id = format!("{original_id}~{suffix}");
+        }
+        taken.insert(id.clone());
+        *stable_id = Some(id);
+    }
+
+    let identities = DataFrame::new(vec![
+        trips.column("trip_id")?.clone(),
+        Column::new("stable_trip_id".into(), stable_ids.into_iter().flatten().collect::<Vec<_>>()),
+    ])?;
+    Ok((identities, num_carried_over))
+}
+
+// Dataset, route and the stops with their departures of every trip of `trips`, in its order.
+// `stop_times` has the columns "trip_id", "stop_id_in_dataset", "departure_time" and
+// "stop_sequence".
+fn signatures(trips: &DataFrame, stop_times: LazyFrame) -> Result<Vec<String>, SimplifyError> {
+    let stop_times = stop_times
+        .select([
+            col("trip_id"),
+            col("stop_id_in_dataset"),
+            col("departure_time").cast(DataType::Int64),
+            col("stop_sequence"),
+        ])
+        .sort(["trip_id", "stop_sequence"], Default::default())
+        .collect()?;
+
+    let mut calls: HashMap<u32, String> = HashMap::new();
+    let rows = stop_times.column("trip_id")?.u32()?.iter()
+        .zip(stop_times.column("stop_id_in_dataset")?.str()?.iter())
+        .zip(stop_times.column("departure_time")?.i64()?.iter());
+    for ((trip, stop), departure) in rows {
+        let Some(trip) = trip else { continue };
+        let calls = calls.entry(trip).or_default();
+        let _ = write!(calls, "{}@", stop.unwrap_or_default());
+        if let Some(departure) = departure {
+            let _ = write!(calls, "{departure}");
+        }
+        calls.push(';');
+    }
+
+    let signatures = trips.column("trip_id")?.u32()?.iter()
+        .zip(trips.column("dataset_id")?.str()?.iter())
+        .zip(trips.column("route_id_in_dataset")?.str()?.iter())
+        .map(|((trip, dataset_id), route_id)| format!(
+            "{}|{}|{}",
+            dataset_id.unwrap_or_default(),
+            route_id.unwrap_or_default(),
+            trip.and_then(|trip| calls.get(&trip)).map(String::as_str).unwrap_or_default(),
+        ))
+        .collect();
+    Ok(signatures)
+}
+
+// Like the namespaced ids of the simplify step, e.g. "vvs:4711"
+fn original_ids(trips: &DataFrame) -> Result<Vec<String>, SimplifyError> {
+    let dataset_ids = trips.column("dataset_id")?.str()?;
+    let ids = trips.column("trip_id_in_dataset")?.str()?;
+    Ok(dataset_ids.iter()
+        .zip(ids.iter())
+        .map(|(dataset_id, id)| format!("{}:{}", dataset_id.unwrap_or_default(), id.unwrap_or_default()))
+        .collect())
+}
+
+/// The stable identifiers of an import in the order of the trip ids, as written by
+/// [stable_trip_ids]
+pub fn stable_ids(identities: &DataFrame) -> Result<Vec<String>, SimplifyError> {
+    Ok(identities.column("stable_trip_id")?.str()?.iter()
+        .map(|id| id.unwrap_or_default().to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use polars::prelude::{NamedFrom, TimeUnit};
+    use polars::series::Series;
+
+    fn stop_times(trips: &[u32], stops: &[&str], departures: &[i64]) -> LazyFrame {
+        let departures = Series::new("departure_time".into(), departures.iter().map(|minute| minute * 60_000).collect::<Vec<_>>())
+            .cast(&DataType::Duration(TimeUnit::Milliseconds))
+            .unwrap();
+        df!(
+            "trip_id"            => trips,
+            "stop_id_in_dataset" => stops,
+            "departure_time"     => departures,
+            "stop_sequence"      => (0..trips.len() as u32).collect::<Vec<_>>(),
+        ).unwrap().lazy()
+    }
+
+    #[test]
+    fn test_stable_trip_ids() {
+        // Two trips of route r1 at 8:00 and 9:00 in the previous version
+        let previous = PreviousTrips::from_frames(
+            df!(
+                "trip_id"             => [0u32, 1],
+                "dataset_id"          => ["d", "d"],
+                "trip_id_in_dataset"  => ["t1", "t2"],
+                "route_id_in_dataset" => ["r1", "r1"],
+            ).unwrap().lazy(),
+            stop_times(&[0, 0, 1, 1], &["a", "b", "a", "b"], &[480, 490, 540, 550]),
+            None,
+        ).unwrap();
+        assert_eq!(previous.len(), 2);
+
+        // The new version renames the trip at 8:00 to x and the one at 9:00 to t1, and adds a trip
+        // t2 at 10:00. Both renamed trips keep their old identifier, so the new trip can't have
+        // the identifier t2.
+        let trips = df!(
+            "trip_id"             => [0u32, 1, 2],
+            "dataset_id"          => ["d", "d", "d"],
+            "trip_id_in_dataset"  => ["x", "t1", "t2"],
+            "route_id_in_dataset" => ["r1", "r1", "r1"],
+        ).unwrap();
+        let stop_times = stop_times(&[0, 0, 1, 1, 2, 2], &["a", "b", "a", "b", "a", "b"], &[480, 490, 540, 550, 600, 610]);
+
+        let (identities, num_carried_over) = stable_trip_ids(&trips, stop_times, previous).unwrap();
+        assert_eq!(num_carried_over, 2);
+        assert_eq!(stable_ids(&identities).unwrap(), ["d:t1", "d:t2", "d:t2~1"]);
+    }
+}
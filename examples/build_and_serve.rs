@@ -0,0 +1,58 @@
+//! Builds a routing engine from the bundled feed and answers queries read from stdin, one per line
+//! as `<from> <to> <HH:MM>` with the stop ids of the feed, until stdin is closed. drino itself
+//! doesn't serve over HTTP yet, so this stands in for a server.
+//!
+//! Any algorithm that can be preprocessed works the same way. The Connection Scan Algorithm
+//! doesn't need lookup tables of lines, so it is ready the fastest.
+//!
+//! ```sh
+//! echo "a d 07:55" | cargo run --example build_and_serve
+//! ```
+
+mod fixture;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use common::util::logging::NoProgress;
+use common::util::paths;
+use routing::algorithm::{JourneyPlanner, PreprocessInit};
+use routing::csa::ConnectionScanAlgorithm;
+use std::error::Error;
+use std::io::BufRead;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let work_dir = tempfile::tempdir()?;
+    paths::init(work_dir.path().to_path_buf());
+
+    let archive = fixture::zip_feed(work_dir.path())?;
+    let input = fixture::import(&archive).await?
+        .running_on(NaiveDate::from_ymd_opt(2025, 6, 2).unwrap())?;
+    let engine = <ConnectionScanAlgorithm as PreprocessInit>::preprocess(input.clone(), &Default::default(), false, &NoProgress)?;
+    eprintln!("Ready, enter queries as `<from> <to> <HH:MM>`");
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let [from, to, at] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+            eprintln!("Expected `<from> <to> <HH:MM>`");
+            continue;
+        };
+        let (Some(from), Some(to)) = (fixture::stop_id(&input, from), fixture::stop_id(&input, to)) else {
+            eprintln!("Unknown stop");
+            continue;
+        };
+        let Ok(at) = NaiveTime::parse_from_str(at, "%H:%M") else {
+            eprintln!("Expected a time like 07:55");
+            continue;
+        };
+
+        let departure = DateTime::<Utc>::UNIX_EPOCH + (at - NaiveTime::MIN);
+        match engine.query_ea(from, to, departure) {
+            Ok(journey) => match journey.arrival() {
+                Some(arrival) => println!("Arrives at {} after {} legs", arrival.format("%H:%M"), journey.legs().count()),
+                None => println!("Arrives by walking only"),
+            },
+            Err(err) => println!("{err}"),
+        }
+    }
+    Ok(())
+}
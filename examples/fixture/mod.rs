@@ -0,0 +1,82 @@
+//! The tiny feed in `fixtures/tiny` and the import pipeline that turns it into routing input,
+//! shared by the examples and the integration tests.
+//!
+//! Bus 1 runs from Hauptbahnhof to Marktplatz at 8:00 and 8:30, tram U2 from Marktplatz to
+//! Universität at 8:15 and 8:45. Mensa is a short walk from Universität, in the evening bus 3
+//! runs from there back to Hauptbahnhof at 17:00.
+
+use common::types::config::{MergeConfig, SimplifyConfig};
+use common::types::dataset::{DataSource, Dataset, DatasetFormat};
+use common::types::mode::ModeRegistry;
+use common::util::logging::NoProgress;
+use data_harvester::step1_fetch_data::fetch_dataset;
+use data_harvester::step2_import_data::import_data;
+use data_harvester::step3_validate_data::validate_data;
+use data_harvester::step4_merge_data::merge;
+use data_harvester::step5_simplify::simplify;
+use routing::algorithm::PreprocessingInput;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+pub const DATASET_ID: &str = "tiny";
+
+/// Directory with the text files of the feed
+pub fn feed_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(DATASET_ID)
+}
+
+/// Zips the feed into `dir`, since datasets are read from archives like they are published
+pub fn zip_feed(dir: &Path) -> io::Result<PathBuf> {
+    let path = dir.join(format!("{DATASET_ID}.zip"));
+    let mut writer = ZipWriter::new(File::create(&path)?);
+    for entry in fs::read_dir(feed_dir())? {
+        let entry = entry?;
+        writer.start_file(entry.file_name().to_string_lossy(), SimpleFileOptions::default())?;
+        writer.write_all(&fs::read(entry.path())?)?;
+    }
+    writer.finish()?;
+    Ok(path)
+}
+
+pub fn dataset(archive: &Path) -> Dataset {
+    Dataset {
+        id: DATASET_ID.to_string(),
+        src: DataSource::File { path: archive.to_string_lossy().into_owned() },
+        format: DatasetFormat::Gtfs,
+        license: None,
+        group_ids: vec![],
+        validation: Default::default(),
+        bounds: None,
+        drop_implausible_stops: false,
+        fix: false,
+        overrides: vec![],
+        vehicle_positions: None,
+        trip_updates: None,
+        alerts: None,
+        credentials: None,
+        refresh: None,
+        filter: Default::default(),
+        attribution: None,
+    }
+}
+
+/// Fetches, imports, validates, merges and simplifies the feed like the preprocessing of drino
+/// does for the datasets of its config. The results are written to the working directory, which
+/// must be set with [common::util::paths::init] before.
+pub async fn import(archive: &Path) -> Result<PreprocessingInput, Box<dyn Error>> {
+    let fetched = fetch_dataset(dataset(archive), &NoProgress).await?;
+    let imported = import_data(fetched, &NoProgress).await?;
+    let validated = validate_data(imported, &ModeRegistry::default(), false, &NoProgress).await?;
+    let merged = merge(vec![validated], &MergeConfig::default(), &NoProgress).await?;
+    Ok(simplify(merged, &SimplifyConfig::default()).await?)
+}
+
+/// The id of a stop of the feed in the routing input, e.g. "a" for Hauptbahnhof
+pub fn stop_id(input: &PreprocessingInput, stop: &str) -> Option<common::types::StopId> {
+    input.original_ids.stops.get(&format!("{DATASET_ID}:{stop}"))
+}
@@ -0,0 +1,49 @@
+//! A complete cycle on the bundled feed: import it into a temporary working directory, build the
+//! routing data for a day and plan a journey from Hauptbahnhof to the Mensa.
+//!
+//! ```sh
+//! cargo run --example plan_journey
+//! ```
+
+mod fixture;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use common::util::paths;
+use routing::algorithm::JourneyPlanner;
+use routing::direct_connections::DirectConnections;
+use routing::itinerary::{Itinerary, ItineraryLeg};
+use routing::raptor::RaptorAlgorithm;
+use std::error::Error;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let work_dir = tempfile::tempdir()?;
+    paths::init(work_dir.path().to_path_buf());
+
+    let archive = fixture::zip_feed(work_dir.path())?;
+    let input = fixture::import(&archive).await?
+        .running_on(NaiveDate::from_ymd_opt(2025, 6, 2).unwrap())?;
+
+    let direct_connections = DirectConnections::try_from(input.clone())?;
+    let raptor = RaptorAlgorithm::preprocess(input.clone(), direct_connections.clone())?;
+
+    let (Some(from), Some(to)) = (fixture::stop_id(&input, "a"), fixture::stop_id(&input, "d")) else {
+        return Err("The feed doesn't contain the stops".into());
+    };
+    // Times of the timetable are relative to the start of the service day
+    let departure = DateTime::<Utc>::UNIX_EPOCH + TimeDelta::minutes(7 * 60 + 55);
+    let journey = raptor.query_ea(from, to, departure)?;
+
+    for leg in Itinerary::reconstruct(&journey, &direct_connections)?.legs {
+        match leg {
+            ItineraryLeg::Ride { trip, departure, arrival, .. } => println!(
+                "{}-{}  trip {}",
+                departure.format("%H:%M"),
+                arrival.format("%H:%M"),
+                input.original_ids.trip(trip).unwrap_or("?"),
+            ),
+            ItineraryLeg::Transfer { duration, .. } => println!("{:>5} min    walk", duration.num_minutes()),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,20 @@
+// Generates the C header from the exported functions, so that it can't miss any of them. The header
+// is written to OUT_DIR, so that builds don't change the crate. With DRINO_FFI_UPDATE_HEADER set,
+// it is also copied to include/drino.h, which is committed.
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("Cargo sets the directory of the crate");
+    let out_dir = std::env::var("OUT_DIR").expect("Cargo sets the output directory of the build script");
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=DRINO_FFI_UPDATE_HEADER");
+
+    let config = cbindgen::Config::from_file(format!("{crate_dir}/cbindgen.toml")).expect("Unable to read cbindgen.toml");
+    let header = format!("{out_dir}/drino.h");
+    cbindgen::generate_with_config(&crate_dir, config)
+        .expect("Unable to generate the C header")
+        .write_to_file(&header);
+
+    if std::env::var_os("DRINO_FFI_UPDATE_HEADER").is_some() {
+        std::fs::copy(&header, format!("{crate_dir}/include/drino.h")).expect("Unable to update include/drino.h");
+    }
+}
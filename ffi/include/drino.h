@@ -0,0 +1,103 @@
+/*
+ * C interface of the drino routing engine on a compact timetable, as written by
+ * `drino export compact`.
+ *
+ * Strings are UTF-8 and NUL-terminated. Strings returned by the library are owned by the caller
+ * and released with drino_string_free. Functions that fail return NULL and leave the reason for
+ * drino_last_error_code and drino_last_error_message on the calling thread.
+ */
+
+#ifndef DRINO_H
+#define DRINO_H
+
+/* Generated by the build script of drino-ffi from src/lib.rs, don't edit it by hand */
+
+#include <stdint.h>
+
+/**
+ * The timetable of one day, opened with [drino_engine_open]. It is only read by queries, so one
+ * engine answers queries of several threads at once.
+ */
+typedef struct DrinoEngine DrinoEngine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Opens the compact timetable that `drino export compact` wrote to `path`. Returns NULL if it
+ * can't be read.
+ *
+ * # Safety
+ *
+ * `path` is NULL or a NUL-terminated string.
+ */
+DrinoEngine *drino_engine_open(const char *path);
+
+/**
+ * Releases an engine of [drino_engine_open]. NULL is ignored.
+ *
+ * # Safety
+ *
+ * `engine` is NULL or an engine that was not released yet.
+ */
+void drino_engine_free(DrinoEngine *engine);
+
+/**
+ * The stops of the timetable as a JSON array of `{"stop_id", "name", "lat", "lon"}`, e.g. for
+ * searching them in the app
+ *
+ * # Safety
+ *
+ * `engine` is NULL or an engine that was not released yet.
+ */
+char *drino_stops(const DrinoEngine *engine);
+
+/**
+ * The journey from `from` to `to` with the earliest arrival when departing at or after
+ * `departure`, as JSON like `/api/v1/plan` of `drino serve` answers. Stops are given by their id
+ * in the dataset, prefixed by the id of the dataset, or by their name. Times are seconds since
+ * midnight of the day of the timetable.
+ *
+ * A negative `max_transfers` doesn't limit the transfers. `min_transfer_buffer` is in minutes,
+ * like `--min-transfer-buffer` of `drino query`. Returns NULL with `NO_ROUTE_FOUND` if no
+ * journey is found.
+ *
+ * # Safety
+ *
+ * `engine` is NULL or an engine that was not released yet, `from` and `to` are NULL or
+ * NUL-terminated strings.
+ */
+char *drino_plan(const DrinoEngine *engine,
+                 const char *from,
+                 const char *to,
+                 int64_t departure,
+                 int32_t max_transfers,
+                 uint32_t min_transfer_buffer);
+
+/**
+ * Releases a string that the library returned. NULL is ignored.
+ *
+ * # Safety
+ *
+ * `string` is NULL or a string of the library that was not released yet.
+ */
+void drino_string_free(char *string);
+
+/**
+ * The code of the last failure on this thread, e.g. `STOP_NOT_FOUND`, like the `code` of errors
+ * of `drino serve`. NULL if nothing failed yet. The string is owned by the library and valid until
+ * the next call on this thread.
+ */
+const char *drino_last_error_code(void);
+
+/**
+ * The message of the last failure on this thread, owned like [drino_last_error_code]
+ */
+const char *drino_last_error_message(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DRINO_H */
@@ -0,0 +1,375 @@
+//! C interface of RAPTOR on a compact timetable (see `routing::compact`), so that the routing engine
+//! is embedded in apps written in C++, Swift or Kotlin. The build script generates the declarations in
+//! `include/drino.h` from the exported functions.
+//!
+//! Strings are UTF-8 and NUL-terminated. The ones that the library returns are owned by the caller
+//! and released with [drino_string_free]. Functions that fail return NULL and leave the reason for
+//! [drino_last_error_code] and [drino_last_error_message] on the calling thread.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use common::types::errors::ErrorCode;
+use common::types::StopId;
+use routing::algorithm::{EarliestArrival, JourneyPlanner, QueryError, QueryOptions};
+use routing::compact::{CompactError, CompactStop, CompactTimetable};
+use routing::journey::{Journey, Leg};
+use routing::raptor::RaptorAlgorithm;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::fmt;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::BufReader;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+/// The timetable of one day, opened with [drino_engine_open]. It is only read by queries, so one
+/// engine answers queries of several threads at once.
+pub struct DrinoEngine {
+    raptor: RaptorAlgorithm,
+    stops: Vec<CompactStop>,
+}
+
+impl DrinoEngine {
+    // By the id in the dataset first, then by the name
+    fn find_stop(&self, stop: &str) -> Result<&CompactStop, FfiError> {
+        self.stops.iter().find(|candidate| candidate.original_id == stop)
+            .or_else(|| self.stops.iter().find(|candidate| candidate.name.eq_ignore_ascii_case(stop)))
+            .ok_or_else(|| FfiError::UnknownStop(stop.to_string()))
+    }
+
+    fn stop(&self, stop: StopId) -> Stop {
+        self.stops.iter().find(|candidate| candidate.id == stop.0)
+            .map(Stop::from)
+            .unwrap_or_else(|| Stop { stop_id: String::new(), name: String::new(), lat: f64::NAN, lon: f64::NAN })
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(CString, CString)>> = const { RefCell::new(None) };
+}
+
+/// Opens the compact timetable that `drino export compact` wrote to `path`. Returns NULL if it
+/// can't be read.
+///
+/// # Safety
+///
+/// `path` is NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn drino_engine_open(path: *const c_char) -> *mut DrinoEngine {
+    call(|| {
+        let file = File::open(string_argument(path, "path")?)?;
+        let timetable = CompactTimetable::read(BufReader::new(file))?;
+        let stops = timetable.stops.clone();
+        let engine = DrinoEngine { raptor: RaptorAlgorithm::try_from(timetable)?, stops };
+        Ok(Box::into_raw(Box::new(engine)))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Releases an engine of [drino_engine_open]. NULL is ignored.
+///
+/// # Safety
+///
+/// `engine` is NULL or an engine that was not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn drino_engine_free(engine: *mut DrinoEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// The stops of the timetable as a JSON array of `{"stop_id", "name", "lat", "lon"}`, e.g. for
+/// searching them in the app
+///
+/// # Safety
+///
+/// `engine` is NULL or an engine that was not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn drino_stops(engine: *const DrinoEngine) -> *mut c_char {
+    call(|| {
+        let engine = engine_argument(engine)?;
+        json(&engine.stops.iter().map(Stop::from).collect::<Vec<_>>())
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// The journey from `from` to `to` with the earliest arrival when departing at or after
+/// `departure`, as JSON like `/api/v1/plan` of `drino serve` answers. Stops are given by their id
+/// in the dataset, prefixed by the id of the dataset, or by their name. Times are seconds since
+/// midnight of the day of the timetable.
+///
+/// A negative `max_transfers` doesn't limit the transfers. `min_transfer_buffer` is in minutes,
+/// like `--min-transfer-buffer` of `drino query`. Returns NULL with `NO_ROUTE_FOUND` if no
+/// journey is found.
+///
+/// # Safety
+///
+/// `engine` is NULL or an engine that was not released yet, `from` and `to` are NULL or
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn drino_plan(
+    engine: *const DrinoEngine,
+    from: *const c_char,
+    to: *const c_char,
+    departure: i64,
+    max_transfers: i32,
+    min_transfer_buffer: u32,
+) -> *mut c_char {
+    call(|| {
+        let engine = engine_argument(engine)?;
+        let start = engine.find_stop(string_argument(from, "from")?)?;
+        let target = engine.find_stop(string_argument(to, "to")?)?;
+        let options = QueryOptions {
+            max_transfers: usize::try_from(max_transfers).ok(),
+            min_transfer_buffer: TimeDelta::minutes(min_transfer_buffer as i64),
+            ..Default::default()
+        };
+        let departure = DateTime::<Utc>::UNIX_EPOCH + TimeDelta::seconds(departure);
+        let input = EarliestArrival::new(StopId(start.id), departure).with_options(&options);
+        let journey = engine.raptor.query_ea_with(input, StopId(target.id))?;
+        json(&planned_journey(engine, &journey))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Releases a string that the library returned. NULL is ignored.
+///
+/// # Safety
+///
+/// `string` is NULL or a string of the library that was not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn drino_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        drop(CString::from_raw(string));
+    }
+}
+
+/// The code of the last failure on this thread, e.g. `STOP_NOT_FOUND`, like the `code` of errors
+/// of `drino serve`. NULL if nothing failed yet. The string is owned by the library and valid until
+/// the next call on this thread.
+#[no_mangle]
+pub extern "C" fn drino_last_error_code() -> *const c_char {
+    LAST_ERROR.with_borrow(|error| error.as_ref().map_or(ptr::null(), |(code, _)| code.as_ptr()))
+}
+
+/// The message of the last failure on this thread, owned like [drino_last_error_code]
+#[no_mangle]
+pub extern "C" fn drino_last_error_message() -> *const c_char {
+    LAST_ERROR.with_borrow(|error| error.as_ref().map_or(ptr::null(), |(_, message)| message.as_ptr()))
+}
+
+// Runs the body of a function, recording failures instead of unwinding into the caller
+fn call<T>(body: impl FnOnce() -> Result<T, FfiError>) -> Option<T> {
+    let result = catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|panic| {
+        let message = panic.downcast_ref::<&str>().map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        Err(FfiError::Panic(message))
+    });
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            let code = serde_json::to_value(err.code()).ok()
+                .and_then(|code| code.as_str().map(str::to_string))
+                .unwrap_or_default();
+            // Messages with NUL bytes are cut at the first one
+            let message = err.to_string();
+            let message = message.split('\0').next().unwrap_or_default();
+            let error = (CString::new(code).unwrap_or_default(), CString::new(message).unwrap_or_default());
+            LAST_ERROR.set(Some(error));
+            None
+        }
+    }
+}
+
+unsafe fn string_argument<'a>(string: *const c_char, name: &'static str) -> Result<&'a str, FfiError> {
+    if string.is_null() {
+        return Err(FfiError::NullArgument(name));
+    }
+    CStr::from_ptr(string).to_str().map_err(|_| FfiError::InvalidUtf8(name))
+}
+
+unsafe fn engine_argument<'a>(engine: *const DrinoEngine) -> Result<&'a DrinoEngine, FfiError> {
+    engine.as_ref().ok_or(FfiError::NullArgument("engine"))
+}
+
+fn json(value: &impl Serialize) -> Result<*mut c_char, FfiError> {
+    // JSON escapes control characters, so it has no NUL bytes
+    Ok(CString::new(serde_json::to_string(value)?).unwrap_or_default().into_raw())
+}
+
+#[derive(Serialize)]
+struct Stop {
+    stop_id: String,
+    name: String,
+    lat: f64,
+    lon: f64,
+}
+
+impl From<&CompactStop> for Stop {
+    fn from(stop: &CompactStop) -> Self {
+        Self { stop_id: stop.original_id.clone(), name: stop.name.clone(), lat: stop.lat, lon: stop.lon }
+    }
+}
+
+#[derive(Serialize)]
+struct PlannedJourney {
+    departure: Option<i64>,
+    arrival: Option<i64>,
+    legs: Vec<PlannedLeg>,
+}
+
+// Compact timetables don't keep the ids of trips in their datasets, so rides have the id of their
+// trip in the timetable
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum PlannedLeg {
+    Ride { trip: u32, from: Stop, to: Stop, departure: i64, arrival: i64 },
+    Walk { from: Stop, to: Stop, duration_seconds: i64 },
+}
+
+fn planned_journey(engine: &DrinoEngine, journey: &Journey) -> PlannedJourney {
+    let legs = journey.legs()
+        .map(|leg| match leg {
+            Leg::Ride { trip, boarding_stop, alight_stop, boarding_time, alight_time } => PlannedLeg::Ride {
+                trip: trip.0,
+                from: engine.stop(*boarding_stop),
+                to: engine.stop(*alight_stop),
+                departure: seconds(*boarding_time),
+                arrival: seconds(*alight_time),
+            },
+            Leg::Transfer { start, end, duration } => PlannedLeg::Walk {
+                from: engine.stop(*start),
+                to: engine.stop(*end),
+                duration_seconds: duration.num_seconds(),
+            },
+        })
+        .collect();
+    PlannedJourney {
+        departure: journey.departure().map(seconds),
+        arrival: journey.arrival().map(seconds),
+        legs,
+    }
+}
+
+// Times of the timetable are relative to its midnight
+fn seconds(time: DateTime<Utc>) -> i64 {
+    (time - DateTime::<Utc>::UNIX_EPOCH).num_seconds()
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FfiError {
+    NullArgument(&'static str),
+    InvalidUtf8(&'static str),
+    IO(#[from] std::io::Error),
+    Compact(#[from] CompactError),
+    Json(#[from] serde_json::Error),
+    UnknownStop(String),
+    Query(#[from] QueryError),
+    Panic(String),
+}
+
+impl FfiError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            FfiError::UnknownStop(_) => ErrorCode::StopNotFound,
+            FfiError::Query(err) => err.code(),
+            FfiError::IO(err) if err.kind() == std::io::ErrorKind::NotFound => ErrorCode::NotFound,
+            _ => ErrorCode::Internal,
+        }
+    }
+}
+
+impl Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FfiError::NullArgument(name) => write!(f, "Argument {} is NULL", name),
+            FfiError::InvalidUtf8(name) => write!(f, "Argument {} is not UTF-8", name),
+            FfiError::IO(err) => write!(f, "Error during IO: {}", err),
+            FfiError::Compact(err) => write!(f, "Error while loading the compact timetable: {}", err),
+            FfiError::Json(err) => write!(f, "Error while writing the result: {}", err),
+            FfiError::UnknownStop(stop) => write!(f, "No stop with this name or id: {}", stop),
+            FfiError::Query(err) => write!(f, "Error while answering the query: {}", err),
+            FfiError::Panic(message) => write!(f, "Unexpected failure: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use std::path::Path;
+
+    unsafe fn take_string(string: *mut c_char) -> String {
+        assert!(!string.is_null(), "{:?}", CStr::from_ptr(drino_last_error_message()));
+        let value = CStr::from_ptr(string).to_str().unwrap().to_string();
+        drino_string_free(string);
+        value
+    }
+
+    unsafe fn last_error_code() -> &'static str {
+        CStr::from_ptr(drino_last_error_code()).to_str().unwrap()
+    }
+
+    #[test]
+    fn test_header_is_current() {
+        // Run `DRINO_FFI_UPDATE_HEADER=1 cargo build -p drino-ffi` to update the header
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/drino.h"));
+        assert_eq!(include_str!("../include/drino.h"), generated, "include/drino.h is out of date");
+    }
+
+    #[test]
+    fn test_plan() {
+        // `drino export compact` of fixtures/tiny on a weekday
+        let json = std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("../fixtures/tiny_compact.json")).unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let mut encoder = GzEncoder::new(file.as_file(), flate2::Compression::default());
+        encoder.write_all(&json).unwrap();
+        encoder.finish().unwrap();
+
+        unsafe {
+            let path = CString::new(file.path().to_str().unwrap()).unwrap();
+            let engine = drino_engine_open(path.as_ptr());
+            assert!(!engine.is_null());
+
+            let stops: serde_json::Value = serde_json::from_str(&take_string(drino_stops(engine))).unwrap();
+            assert_eq!(stops.as_array().unwrap().len(), 4);
+
+            let (from, to) = (CString::new("tiny:a").unwrap(), CString::new("Mensa").unwrap());
+            let journey = take_string(drino_plan(engine, from.as_ptr(), to.as_ptr(), 7 * 3600 + 55 * 60, -1, 0));
+            let journey: serde_json::Value = serde_json::from_str(&journey).unwrap();
+            // Walking to Marktplatz catches the same trip to Universität as riding there
+            let legs = journey["legs"].as_array().unwrap();
+            assert_eq!(legs.iter().map(|leg| leg["type"].as_str().unwrap()).collect::<Vec<_>>(), ["walk", "ride", "walk"]);
+            assert_eq!(legs[0]["from"]["name"], "Hauptbahnhof");
+            assert_eq!(legs[1]["departure"], 8 * 3600 + 15 * 60);
+            assert_eq!(legs[2]["to"]["stop_id"], "tiny:d");
+
+            // The last trips leave before midnight
+            assert!(drino_plan(engine, from.as_ptr(), to.as_ptr(), 23 * 3600, -1, 0).is_null());
+            assert_eq!(last_error_code(), "NO_ROUTE_FOUND");
+
+            let unknown = CString::new("Nowhere").unwrap();
+            assert!(drino_plan(engine, unknown.as_ptr(), to.as_ptr(), 0, -1, 0).is_null());
+            assert_eq!(last_error_code(), "STOP_NOT_FOUND");
+
+            assert!(drino_plan(engine, ptr::null(), to.as_ptr(), 0, -1, 0).is_null());
+            assert_eq!(last_error_code(), "INTERNAL");
+
+            drino_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_open_missing_file() {
+        unsafe {
+            let path = CString::new("/nonexistent/timetable.json.gz").unwrap();
+            assert!(drino_engine_open(path.as_ptr()).is_null());
+            assert_eq!(last_error_code(), "NOT_FOUND");
+            assert!(!drino_last_error_message().is_null());
+        }
+    }
+}
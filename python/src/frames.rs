@@ -0,0 +1,49 @@
+use crate::BindingError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::str::FromStr;
+
+/// The library that the frames of results are made with. It is imported when the first frame is
+/// built, so the module works with either of them installed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FrameKind {
+    Pandas,
+    Polars,
+}
+
+impl FromStr for FrameKind {
+    type Err = BindingError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "pandas" => Ok(FrameKind::Pandas),
+            "polars" => Ok(FrameKind::Polars),
+            _ => Err(BindingError::UnknownFrame(value.to_string())),
+        }
+    }
+}
+
+/// The columns of a frame, in the order they are added
+pub(crate) struct Frame<'py> {
+    columns: Bound<'py, PyDict>,
+}
+
+impl<'py> Frame<'py> {
+    pub(crate) fn new(py: Python<'py>) -> Self {
+        Self { columns: PyDict::new_bound(py) }
+    }
+
+    pub(crate) fn column<T: IntoPy<PyObject>>(self, name: &str, values: Vec<T>) -> PyResult<Self> {
+        self.columns.set_item(name, values.into_py(self.columns.py()))?;
+        Ok(self)
+    }
+
+    pub(crate) fn build(self, kind: &str) -> PyResult<PyObject> {
+        let module = match kind.parse()? {
+            FrameKind::Pandas => "pandas",
+            FrameKind::Polars => "polars",
+        };
+        let py = self.columns.py();
+        Ok(py.import_bound(module)?.getattr("DataFrame")?.call1((self.columns,))?.unbind())
+    }
+}
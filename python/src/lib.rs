@@ -0,0 +1,120 @@
+//! The `drino` Python module, so that experiments can be scripted without writing Rust. It runs
+//! the pipeline of a config up to the simplified timetable and plans journeys in it with RAPTOR,
+//! like `drino query`. Results are pandas or polars frames, whichever `frame` asks for.
+//!
+//! ```python
+//! import drino
+//!
+//! network = drino.preprocess("config.yaml", work_dir="data")
+//! legs = network.query("Hauptbahnhof", "Stadtmitte", datetime(2024, 5, 1, 8), frame="polars")
+//! ```
+
+mod frames;
+mod network;
+mod pipeline;
+
+use common::util::paths;
+use data_harvester::step1_fetch_data::FetchError;
+use data_harvester::step2_import_data::ImportError;
+use data_harvester::step3_validate_data::ValidateError;
+use data_harvester::step4_merge_data::MergeError;
+use data_harvester::step5_simplify::SimplifyError;
+use network::Network;
+use polars::error::PolarsError;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use routing::algorithm::{PreprocessingError, QueryError};
+use std::fmt;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+create_exception!(drino, DrinoError, PyException, "Raised when preprocessing or a query fails");
+
+/// Fetches, imports, validates, merges and simplifies the datasets of the config, and returns the
+/// network of the simplified timetable. Its tables are written to the working directory, where
+/// [open] finds them again.
+#[pyfunction]
+#[pyo3(signature = (config, work_dir = None))]
+fn preprocess(py: Python<'_>, config: PathBuf, work_dir: Option<PathBuf>) -> PyResult<Network> {
+    init_work_dir(work_dir.as_deref())?;
+    let config = pipeline::load_config(&config).map_err(BindingError::from)?;
+    py.allow_threads(|| pipeline::preprocess(config))?;
+    Ok(Network::open(paths::work_dir())?)
+}
+
+/// The network that an earlier [preprocess] or `drino` run left in the working directory
+#[pyfunction]
+#[pyo3(signature = (work_dir = None))]
+fn open(work_dir: Option<PathBuf>) -> PyResult<Network> {
+    init_work_dir(work_dir.as_deref())?;
+    Ok(Network::open(paths::work_dir())?)
+}
+
+// The pipeline reads and writes the working directory of the process, which can only be set once
+fn init_work_dir(work_dir: Option<&Path>) -> Result<(), BindingError> {
+    let Some(work_dir) = work_dir else { return Ok(()) };
+    paths::init(work_dir.to_path_buf());
+    if paths::work_dir() != work_dir {
+        return Err(BindingError::WorkDirChanged(paths::work_dir().to_path_buf()));
+    }
+    Ok(())
+}
+
+#[pymodule]
+fn drino(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(preprocess, m)?)?;
+    m.add_function(wrap_pyfunction!(open, m)?)?;
+    m.add_class::<Network>()?;
+    m.add("DrinoError", m.py().get_type_bound::<DrinoError>())?;
+    Ok(())
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum BindingError {
+    Config(#[from] pipeline::ConfigError),
+    Fetch(#[from] FetchError),
+    Import(#[from] ImportError),
+    Validate(#[from] ValidateError),
+    Merge(#[from] MergeError),
+    Simplify(#[from] SimplifyError),
+    Polars(#[from] PolarsError),
+    Preprocessing(#[from] PreprocessingError),
+    Query(#[from] QueryError),
+    IO(#[from] std::io::Error),
+    UnknownStop(String),
+    // The frame is neither "pandas" nor "polars"
+    UnknownFrame(String),
+    // The process already uses this working directory
+    WorkDirChanged(PathBuf),
+}
+
+impl Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindingError::Config(err) => write!(f, "Error while reading config file: {}", err),
+            BindingError::Fetch(err) => write!(f, "Error while fetching a dataset: {}", err),
+            BindingError::Import(err) => write!(f, "Error while importing a dataset: {}", err),
+            BindingError::Validate(err) => write!(f, "Error while validating a dataset: {}", err),
+            BindingError::Merge(err) => write!(f, "Error while merging datasets: {}", err),
+            BindingError::Simplify(err) => write!(f, "Error while simplifying a dataset: {}", err),
+            BindingError::Polars(err) => write!(f, "Error while processing dataset data: {}", err),
+            BindingError::Preprocessing(err) => write!(f, "Error while preprocessing data: {}", err),
+            BindingError::Query(err) => write!(f, "Error while answering the query: {}", err),
+            BindingError::IO(err) => write!(f, "Error during IO: {}", err),
+            BindingError::UnknownStop(stop) => write!(f, "No stop with this name or id: {}", stop),
+            BindingError::UnknownFrame(frame) => write!(f, "Frames are \"pandas\" or \"polars\", not \"{}\"", frame),
+            BindingError::WorkDirChanged(work_dir) => write!(
+                f,
+                "The working directory is already {}, it can't be changed within a process",
+                work_dir.display(),
+            ),
+        }
+    }
+}
+
+impl From<BindingError> for PyErr {
+    fn from(err: BindingError) -> Self {
+        DrinoError::new_err(err.to_string())
+    }
+}
@@ -0,0 +1,245 @@
+use crate::frames::Frame;
+use crate::BindingError;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Utc};
+use common::types::StopId;
+use common::util::paths;
+use data_harvester::step5_simplify::read_simplified;
+use hashbrown::HashMap;
+use polars::prelude::{col, DataType, LazyFrame};
+use pyo3::prelude::*;
+use routing::algorithm::{EarliestArrival, JourneyPlanner, OneToManyPlanner, PreprocessingInput, ProfilePlanner, QueryError, QueryOptions};
+use routing::direct_connections::DirectConnections;
+use routing::journey::{Journey, Leg};
+use routing::raptor::RaptorAlgorithm;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The simplified timetable of a working directory. Queries plan with RAPTOR on the day they
+/// depart, which is built on the first query of a day and kept for the later ones.
+///
+/// Stops are given by their id in the dataset, prefixed by the id of the dataset, or by their
+/// name. Times are in the local time of the timetable, like the times of `drino query`.
+#[pyclass(frozen, module = "drino")]
+pub struct Network {
+    input: PreprocessingInput,
+    stops: Vec<Stop>,
+    days: Mutex<HashMap<NaiveDate, Arc<RaptorAlgorithm>>>,
+}
+
+struct Stop {
+    name: String,
+    lat: f64,
+    lon: f64,
+}
+
+#[pymethods]
+impl Network {
+    /// The stops with their ids, names and coordinates
+    #[pyo3(signature = (*, frame = "pandas"))]
+    fn stops(&self, py: Python<'_>, frame: &str) -> PyResult<PyObject> {
+        let ids = (0..self.stops.len() as u32).map(|id| self.original_id(StopId(id))).collect();
+        Frame::new(py)
+            .column("stop_id", ids)?
+            .column("name", self.stops.iter().map(|stop| stop.name.clone()).collect())?
+            .column("lat", self.stops.iter().map(|stop| stop.lat).collect())?
+            .column("lon", self.stops.iter().map(|stop| stop.lon).collect())?
+            .build(frame)
+    }
+
+    /// The legs of the journey with the earliest arrival when departing at or after `at`, with
+    /// no rows if there is none
+    #[pyo3(signature = (start, target, at, *, max_transfers = None, min_transfer_buffer = 0, frame = "pandas"))]
+    #[allow(clippy::too_many_arguments)]
+    fn query(
+        &self,
+        py: Python<'_>,
+        start: &str,
+        target: &str,
+        at: NaiveDateTime,
+        max_transfers: Option<usize>,
+        min_transfer_buffer: u32,
+        frame: &str,
+    ) -> PyResult<PyObject> {
+        let (start, target) = (self.find_stop(start)?, self.find_stop(target)?);
+        let options = Self::options(max_transfers, min_transfer_buffer);
+        let journey = py.allow_threads(|| {
+            let input = EarliestArrival::new(start, in_timetable(at)).with_options(&options);
+            Ok::<_, BindingError>(self.raptor(at.date())?.query_ea_with(input, target))
+        })?;
+        let journeys = match journey {
+            Err(QueryError::NoRouteFound) => vec![],
+            journey => vec![journey.map_err(BindingError::from)?],
+        };
+        self.legs(py, &journeys, at.date(), frame)
+    }
+
+    /// The legs of all Pareto-optimal journeys that depart between `earliest` and `latest`, sorted
+    /// by departure. The `journey` column tells them apart.
+    #[pyo3(signature = (start, target, earliest, latest, *, max_transfers = None, min_transfer_buffer = 0, frame = "pandas"))]
+    #[allow(clippy::too_many_arguments)]
+    fn profile(
+        &self,
+        py: Python<'_>,
+        start: &str,
+        target: &str,
+        earliest: NaiveDateTime,
+        latest: NaiveDateTime,
+        max_transfers: Option<usize>,
+        min_transfer_buffer: u32,
+        frame: &str,
+    ) -> PyResult<PyObject> {
+        let (start, target) = (self.find_stop(start)?, self.find_stop(target)?);
+        let options = Self::options(max_transfers, min_transfer_buffer);
+        let day = earliest.date();
+        let journeys = py.allow_threads(|| {
+            let latest = in_timetable(earliest) + (latest - earliest);
+            Ok::<_, BindingError>(self.raptor(day)?.query_profile_with(start, target, in_timetable(earliest), latest, &options))
+        })?;
+        let journeys = match journeys {
+            Err(QueryError::NoRouteFound) => vec![],
+            journeys => journeys.map_err(BindingError::from)?,
+        };
+        self.legs(py, &journeys, day, frame)
+    }
+
+    /// The earliest arrival at every stop that is reached from `start` when departing at `at`,
+    /// with the number of transfers on the way, e.g. for accessibility analyses
+    #[pyo3(signature = (start, at, *, max_transfers = None, min_transfer_buffer = 0, frame = "pandas"))]
+    fn reach(
+        &self,
+        py: Python<'_>,
+        start: &str,
+        at: NaiveDateTime,
+        max_transfers: Option<usize>,
+        min_transfer_buffer: u32,
+        frame: &str,
+    ) -> PyResult<PyObject> {
+        let start = self.find_stop(start)?;
+        let options = Self::options(max_transfers, min_transfer_buffer);
+        let journeys = py.allow_threads(|| {
+            let input = EarliestArrival::new(start, in_timetable(at)).with_options(&options);
+            Ok::<_, BindingError>(self.raptor(at.date())?.query_ea_to_all_with(input)?)
+        })?;
+
+        let mut journeys: Vec<(StopId, Journey)> = journeys.into_iter().collect();
+        journeys.sort_unstable_by_key(|(stop, _)| *stop);
+        Frame::new(py)
+            .column("stop_id", journeys.iter().map(|(stop, _)| self.original_id(*stop)).collect())?
+            .column("name", journeys.iter().map(|(stop, _)| self.name(*stop)).collect())?
+            .column("arrival", journeys.iter().map(|(_, journey)| journey.arrival().map(|time| local(at.date(), time))).collect())?
+            .column("transfers", journeys.iter().map(|(_, journey)| journey.transfers()).collect())?
+            .build(frame)
+    }
+}
+
+impl Network {
+    pub(crate) fn open(work_dir: &Path) -> Result<Self, BindingError> {
+        let input = read_simplified(work_dir)?;
+        // The stop names are only in the written table, the routing input doesn't need them
+        let names = LazyFrame::scan_parquet(paths::tmp_dir_in(work_dir).join("simplify").join("stops.parquet"), Default::default())?
+            .select([col("stop_name")])
+            .collect()?;
+        let coordinates = input.stops.clone()
+            .select([col("lat").cast(DataType::Float64), col("lon").cast(DataType::Float64)])
+            .collect()?;
+        let stops = names.column("stop_name")?.str()?.iter()
+            .zip(coordinates.column("lat")?.f64()?.iter())
+            .zip(coordinates.column("lon")?.f64()?.iter())
+            .map(|((name, lat), lon)| Stop {
+                name: name.unwrap_or_default().to_string(),
+                lat: lat.unwrap_or(f64::NAN),
+                lon: lon.unwrap_or(f64::NAN),
+            })
+            .collect();
+        Ok(Self { input, stops, days: Mutex::default() })
+    }
+
+    fn raptor(&self, day: NaiveDate) -> Result<Arc<RaptorAlgorithm>, BindingError> {
+        if let Some(raptor) = self.days.lock().unwrap().get(&day) {
+            return Ok(Arc::clone(raptor));
+        }
+        let input = self.input.running_on(day)?;
+        let raptor = Arc::new(RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input)?)?);
+        self.days.lock().unwrap().insert(day, Arc::clone(&raptor));
+        Ok(raptor)
+    }
+
+    fn options(max_transfers: Option<usize>, min_transfer_buffer: u32) -> QueryOptions {
+        QueryOptions {
+            max_transfers,
+            min_transfer_buffer: TimeDelta::minutes(min_transfer_buffer as i64),
+            ..Default::default()
+        }
+    }
+
+    // By the id in the dataset first, then by the name like `drino query`
+    fn find_stop(&self, stop: &str) -> Result<StopId, BindingError> {
+        self.input.original_ids.stops.get(stop)
+            .or_else(|| self.stops.iter()
+                .position(|candidate| candidate.name.eq_ignore_ascii_case(stop))
+                .map(|id| StopId(id as u32)))
+            .ok_or_else(|| BindingError::UnknownStop(stop.to_string()))
+    }
+
+    fn original_id(&self, stop: StopId) -> String {
+        self.input.original_ids.stop(stop).unwrap_or_default().to_string()
+    }
+
+    fn name(&self, stop: StopId) -> String {
+        self.stops.get(stop.0 as usize).map(|stop| stop.name.clone()).unwrap_or_default()
+    }
+
+    // One row per leg. Walks only have a duration, rides also their times.
+    fn legs(&self, py: Python<'_>, journeys: &[Journey], day: NaiveDate, frame: &str) -> PyResult<PyObject> {
+        let legs: Vec<(usize, usize, &Leg)> = journeys.iter().enumerate()
+            .flat_map(|(journey, legs)| legs.legs().enumerate().map(move |(idx, leg)| (journey, idx, leg)))
+            .collect();
+        let (mut kinds, mut trips, mut froms, mut tos, mut departures, mut arrivals, mut durations) =
+            (vec![], vec![], vec![], vec![], vec![], vec![], vec![]);
+        for (_, _, leg) in &legs {
+            match leg {
+                Leg::Ride { trip, boarding_stop, alight_stop, boarding_time, alight_time } => {
+                    kinds.push("ride");
+                    trips.push(self.input.original_ids.trip(*trip).map(str::to_string));
+                    froms.push(*boarding_stop);
+                    tos.push(*alight_stop);
+                    departures.push(Some(local(day, *boarding_time)));
+                    arrivals.push(Some(local(day, *alight_time)));
+                    durations.push((*alight_time - *boarding_time).num_seconds());
+                }
+                Leg::Transfer { start, end, duration } => {
+                    kinds.push("walk");
+                    trips.push(None);
+                    froms.push(*start);
+                    tos.push(*end);
+                    departures.push(None);
+                    arrivals.push(None);
+                    durations.push(duration.num_seconds());
+                }
+            }
+        }
+
+        Frame::new(py)
+            .column("journey", legs.iter().map(|(journey, _, _)| *journey).collect())?
+            .column("leg", legs.iter().map(|(_, leg, _)| *leg).collect())?
+            .column("kind", kinds)?
+            .column("trip_id", trips)?
+            .column("from_stop_id", froms.iter().map(|stop| self.original_id(*stop)).collect())?
+            .column("from_name", froms.iter().map(|stop| self.name(*stop)).collect())?
+            .column("to_stop_id", tos.iter().map(|stop| self.original_id(*stop)).collect())?
+            .column("to_name", tos.iter().map(|stop| self.name(*stop)).collect())?
+            .column("departure", departures)?
+            .column("arrival", arrivals)?
+            .column("duration_seconds", durations)?
+            .build(frame)
+    }
+}
+
+// Times of the timetable of a day are relative to its midnight
+fn in_timetable(at: NaiveDateTime) -> DateTime<Utc> {
+    DateTime::<Utc>::UNIX_EPOCH + (at - at.date().and_time(NaiveTime::MIN))
+}
+
+fn local(day: NaiveDate, time: DateTime<Utc>) -> NaiveDateTime {
+    day.and_time(NaiveTime::MIN) + (time - DateTime::<Utc>::UNIX_EPOCH)
+}
@@ -0,0 +1,91 @@
+use crate::BindingError;
+use common::types::config::{Config, ConfigHeader, Settings};
+use common::util::logging::NoProgress;
+use data_harvester::step1_fetch_data::fetch_dataset;
+use data_harvester::step2_import_data::{import_data, ImportStepExtra};
+use data_harvester::step3_validate_data::validate_data;
+use data_harvester::step4_merge_data::merge;
+use data_harvester::step5_simplify::simplify;
+use log::warn;
+use std::fmt;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::TempPath;
+use tokio::runtime::Runtime;
+
+/// Reads a config like `drino --config` does, as YAML, JSON or TOML by its extension
+pub(crate) fn load_config(path: &Path) -> Result<Config, ConfigError> {
+    let text = fs::read_to_string(path)?;
+    let (header, settings): (ConfigHeader, Settings) = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yml") | Some("yaml") => (serde_yml::from_str(&text)?, serde_yml::from_str(&text)?),
+        Some("json") => (serde_json::from_str(&text)?, serde_json::from_str(&text)?),
+        Some("toml") => (toml::from_str(&text)?, toml::from_str(&text)?),
+        _ => return Err(ConfigError::UnknownFileExtension),
+    };
+    let config = Config::new(&header.version, settings)
+        .ok_or_else(|| ConfigError::UnknownVersion(header.version.to_string()))?;
+    if let Some(option) = config.unsupported_options().into_iter().next() {
+        return Err(ConfigError::RequiresVersion2(option));
+    }
+    Ok(config)
+}
+
+/// The steps of the preprocessing of `drino` up to the simplified timetable, which is what RAPTOR
+/// plans in. The datasets are fetched and imported one after another.
+pub(crate) fn preprocess(config: Config) -> Result<(), BindingError> {
+    let modes = config.mode_registry();
+    let Settings { datasets, merge: merge_config, simplify: simplify_config, .. } = config.into_settings();
+    if datasets.is_empty() {
+        return Err(ConfigError::NoDatasets.into());
+    }
+
+    let mut files_to_clean_up: Vec<PathBuf> = vec![];
+    let result = Runtime::new()?.block_on(async {
+        let mut validated = vec![];
+        for dataset in datasets {
+            let imported = import_data(fetch_dataset(dataset, &NoProgress).await?, &NoProgress).await?;
+            let dataset = validate_data(imported, &modes, false, &NoProgress).await?;
+            let ImportStepExtra::Gtfs { temporary_files, .. } = &dataset.extra;
+            files_to_clean_up.extend(temporary_files.iter().cloned());
+            validated.push(dataset);
+        }
+        let merged = merge(validated, &merge_config, &NoProgress).await?;
+        simplify(merged, &simplify_config).await?;
+        Ok::<(), BindingError>(())
+    });
+
+    for file in files_to_clean_up {
+        if let Err(err) = TempPath::from_path(&file).close() {
+            warn!(target: "preprocessing", "Unable to clean up temp file at {}: {}", file.display(), err);
+        }
+    }
+    result
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    IO(#[from] std::io::Error),
+    DeserializationYaml(#[from] serde_yml::Error),
+    DeserializationJson(#[from] serde_json::Error),
+    DeserializationToml(#[from] toml::de::Error),
+    UnknownFileExtension,
+    UnknownVersion(String),
+    NoDatasets,
+    RequiresVersion2(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::IO(err) => write!(f, "{}", err),
+            ConfigError::DeserializationYaml(err) => write!(f, "{}", err),
+            ConfigError::DeserializationJson(err) => write!(f, "{}", err),
+            ConfigError::DeserializationToml(err) => write!(f, "{}", err),
+            ConfigError::UnknownFileExtension => write!(f, "Expected a .yml, .yaml, .json or .toml file"),
+            ConfigError::UnknownVersion(version) => write!(f, "Unknown config version {}", version),
+            ConfigError::NoDatasets => write!(f, "No datasets provided."),
+            ConfigError::RequiresVersion2(option) => write!(f, "{} requires `version: 2` of the config", option),
+        }
+    }
+}
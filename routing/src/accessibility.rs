@@ -0,0 +1,240 @@
+use crate::journey::{Journey, Leg};
+use common::types::{StopId, TripId};
+use hashbrown::{HashMap, HashSet};
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::{col, lit, when, DataType, JoinArgs, JoinType, LazyFrame, NULL};
+use std::hash::Hash;
+
+/// Whether a stop or trip can be used with a wheelchair, as given by `wheelchair_boarding` of
+/// stops.txt and `wheelchair_accessible` of trips.txt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WheelchairSupport {
+    #[default]
+    Unknown,
+    StepFree,
+    NotAccessible,
+}
+
+impl From<u32> for WheelchairSupport {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => WheelchairSupport::StepFree,
+            2 => WheelchairSupport::NotAccessible,
+            _ => WheelchairSupport::Unknown,
+        }
+    }
+}
+
+/// Overall accessibility of a journey, so that clients can badge journeys without looking at the
+/// individual legs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessibilitySummary {
+    // Every stop and vehicle of the journey is known to be step-free
+    StepFree,
+    // At least one stop or vehicle is known to be not accessible without assistance
+    AssistanceRequired,
+    // Nothing is known to be inaccessible, but the data is incomplete
+    Unknown,
+}
+
+/// Lookup of the wheelchair support of stops and trips. Built from the `stops` and `trips` tables
+/// of the preprocessing input, stops and trips without information are unknown.
+///
+/// Stops and trips that aren't in the tables are unknown as well, they can't be avoided by
+/// [AccessibilityInfo::inaccessible_stops] and [AccessibilityInfo::inaccessible_trips] though.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessibilityInfo {
+    stops: HashMap<StopId, WheelchairSupport>,
+    trips: HashMap<TripId, WheelchairSupport>,
+}
+
+impl AccessibilityInfo {
+    pub fn from_frames(stops: LazyFrame, trips: LazyFrame) -> Result<Self, PolarsError> {
+        // Tables simplified before platforms inherited the information of their stations
+        let schema = stops.clone().collect_schema()?;
+        let stops = match ["dataset_id", "stop_id_in_dataset", "parent_station", "wheelchair_boarding"].iter().all(|column| schema.contains(column)) {
+            true => inherit_wheelchair_boarding(stops, "stop_id_in_dataset"),
+            false => stops,
+        };
+        let stops = collect_support(stops, "stop_id", "wheelchair_boarding")?.into_iter()
+            .map(|(stop_id, support)| (StopId(stop_id), support))
+            .collect();
+        let trips = collect_support(trips, "trip_id", "wheelchair_accessible")?.into_iter()
+            .map(|(trip_id, support)| (TripId(trip_id), support))
+            .collect();
+
+        Ok(Self { stops, trips })
+    }
+
+    pub fn stop(&self, stop: StopId) -> WheelchairSupport {
+        self.stops.get(&stop).copied().unwrap_or_default()
+    }
+
+    pub fn trip(&self, trip: TripId) -> WheelchairSupport {
+        self.trips.get(&trip).copied().unwrap_or_default()
+    }
+
+    /// Stops that aren't known to be step-free
+    pub fn inaccessible_stops(&self) -> HashSet<StopId> {
+        not_step_free(&self.stops)
+    }
+
+    /// Trips that aren't known to be step-free
+    pub fn inaccessible_trips(&self) -> HashSet<TripId> {
+        not_step_free(&self.trips)
+    }
+
+    /// The support of every place and vehicle a leg requires. A ride needs the trip and both the
+    /// boarding and alighting stop. Pathways between stops are not imported, so a transfer is
+    /// judged by the stops at both of its ends.
+    fn leg(&self, leg: &Leg) -> Vec<WheelchairSupport> {
+        match leg {
+            Leg::Ride { trip, boarding_stop, alight_stop, .. } => {
+                vec![self.trip(*trip), self.stop(*boarding_stop), self.stop(*alight_stop)]
+            }
+            Leg::Transfer { start, end, .. } => vec![self.stop(*start), self.stop(*end)],
+        }
+    }
+
+    pub fn summarize(&self, journey: &Journey) -> AccessibilitySummary {
+        let supports = journey.legs()
+            .flat_map(|leg| self.leg(leg))
+            .collect::<Vec<_>>();
+
+        if supports.contains(&WheelchairSupport::NotAccessible) {
+            AccessibilitySummary::AssistanceRequired
+        } else if supports.contains(&WheelchairSupport::Unknown) {
+            AccessibilitySummary::Unknown
+        } else {
+            AccessibilitySummary::StepFree
+        }
+    }
+}
+
+/// Platforms without wheelchair information (0 or empty) inherit the one of their parent station,
+/// as in the GTFS reference. `id_column` holds the ids of the dataset that `parent_station` refers to.
+pub fn inherit_wheelchair_boarding(stops: LazyFrame, id_column: &str) -> LazyFrame {
+    let parents = stops.clone()
+        .select([
+            col("dataset_id"),
+            col(id_column).alias("parent_station"),
+            col("wheelchair_boarding").alias("parent_wheelchair_boarding"),
+        ]);
+    stops
+        .join(
+            parents,
+            [col("dataset_id"), col("parent_station")],
+            [col("dataset_id"), col("parent_station")],
+            JoinArgs::new(JoinType::Left),
+        )
+        .with_column(
+            when(col("wheelchair_boarding").fill_null(lit(0u32)).eq(lit(0u32)))
+                .then(col("parent_wheelchair_boarding"))
+                .otherwise(col("wheelchair_boarding"))
+                .alias("wheelchair_boarding")
+        )
+        .drop(["parent_wheelchair_boarding"])
+}
+
+fn not_step_free<Id: Copy + Eq + Hash>(supports: &HashMap<Id, WheelchairSupport>) -> HashSet<Id> {
+    supports.iter()
+        .filter(|(_, support)| **support != WheelchairSupport::StepFree)
+        .map(|(id, _)| *id)
+        .collect()
+}
+
+// Tables without the column (e.g. from older preprocessing runs) have no information at all
+fn collect_support(frame: LazyFrame, id_column: &str, support_column: &str) -> Result<Vec<(u32, WheelchairSupport)>, PolarsError> {
+    let frame = if frame.clone().collect_schema()?.contains(support_column) {
+        frame.select([col(id_column), col(support_column)]).collect()?
+    } else {
+        frame.select([col(id_column), lit(NULL).cast(DataType::UInt32).alias(support_column)]).collect()?
+    };
+
+    let ids = frame.column(id_column)?.u32()?;
+    let supports = frame.column(support_column)?.u32()?;
+
+    Ok(izip!(ids, supports)
+        .filter_map(|(id, support)| Some((id?, support.map(WheelchairSupport::from).unwrap_or_default())))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeDelta, Utc};
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    fn ride(trip: u32, boarding_stop: u32, alight_stop: u32) -> Leg {
+        let start = DateTime::<Utc>::UNIX_EPOCH;
+        Leg::Ride {
+            trip: TripId(trip),
+            boarding_stop: StopId(boarding_stop),
+            alight_stop: StopId(alight_stop),
+            boarding_time: start,
+            alight_time: start + TimeDelta::minutes(10),
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let stops = df!(
+            "stop_id"             => [0u32, 1, 2, 3],
+            "wheelchair_boarding" => [Some(1u32), Some(1), Some(2), None],
+        ).unwrap().lazy();
+        let trips = df!(
+            "trip_id"               => [0u32, 1],
+            "wheelchair_accessible" => [1u32, 0],
+        ).unwrap().lazy();
+        let info = AccessibilityInfo::from_frames(stops, trips).unwrap();
+
+        let step_free = Journey::from(vec![ride(0, 0, 1)]);
+        assert_eq!(info.summarize(&step_free), AccessibilitySummary::StepFree);
+
+        // Trip 1 has no information
+        let unknown = Journey::from(vec![ride(1, 0, 1)]);
+        assert_eq!(info.summarize(&unknown), AccessibilitySummary::Unknown);
+
+        // Stop 2 is known to be inaccessible, which outweighs the unknown stop 3
+        let assistance = Journey::from(vec![
+            ride(0, 0, 2),
+            Leg::Transfer { start: StopId(2), end: StopId(3), duration: TimeDelta::minutes(2) },
+        ]);
+        assert_eq!(info.summarize(&assistance), AccessibilitySummary::AssistanceRequired);
+
+        assert_eq!(info.inaccessible_stops(), HashSet::from([StopId(2), StopId(3)]));
+        assert_eq!(info.inaccessible_trips(), HashSet::from([TripId(1)]));
+    }
+
+    #[test]
+    fn test_inherit_wheelchair_boarding() {
+        let stops = df!(
+            "dataset_id"          => ["d", "d", "d", "d"],
+            "stop_id"             => ["station", "platform 1", "platform 2", "other"],
+            "parent_station"      => [None, Some("station"), Some("station"), None],
+            "wheelchair_boarding" => [Some(1u32), Some(0), Some(2), None],
+        ).unwrap().lazy();
+
+        let stops = inherit_wheelchair_boarding(stops, "stop_id").collect().unwrap();
+        let supports: Vec<Option<u32>> = stops.column("wheelchair_boarding").unwrap().u32().unwrap().iter().collect();
+        // Platform 2 keeps its own information
+        assert_eq!(supports, [Some(1), Some(1), Some(2), None]);
+    }
+
+    #[test]
+    fn test_from_simplified_stops() {
+        let stops = df!(
+            "stop_id"             => [0u32, 1],
+            "dataset_id"          => ["d", "d"],
+            "stop_id_in_dataset"  => ["station", "platform"],
+            "parent_station"      => [None, Some("station")],
+            "wheelchair_boarding" => [Some(1u32), Some(0)],
+        ).unwrap().lazy();
+        let trips = df!("trip_id" => [0u32]).unwrap().lazy();
+        let info = AccessibilityInfo::from_frames(stops, trips).unwrap();
+
+        assert_eq!(info.stop(StopId(1)), WheelchairSupport::StepFree);
+    }
+}
@@ -1,24 +1,60 @@
-use crate::journey::Journey;
-use crate::transfers::TransferError;
-use chrono::{DateTime, TimeDelta, Utc};
-use common::types::StopId;
-use hashbrown::HashSet;
+#[cfg(feature = "preprocessing")]
+use crate::accessibility::{AccessibilityInfo, AccessibilitySummary};
+#[cfg(feature = "preprocessing")]
+use crate::bikes::BikeCarriage;
+use crate::journey::{pareto_optimal, Journey, JourneyFilter, Leg};
+use crate::transfers::{TransferError, TransferProvider};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+#[cfg(feature = "preprocessing")]
+use common::types::config::{Compression, RoutingConfig};
+use common::types::errors::ErrorCode;
+#[cfg(feature = "preprocessing")]
+use common::types::id_interner::OriginalIds;
+use common::types::{StopId, TripId};
+#[cfg(feature = "preprocessing")]
+use common::util::logging::ProgressSink;
+use common::util::speed::Speed;
+use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "preprocessing")]
 use polars::prelude::LazyFrame;
 use std::fmt;
 use std::fmt::{Debug, Display};
+use std::hash::Hash;
+#[cfg(feature = "preprocessing")]
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 
 pub trait RoutingAlgorithm {}
 
+#[cfg(feature = "preprocessing")]
 pub trait PreprocessInit: RoutingAlgorithm + Sized {
-    fn preprocess(input: PreprocessingInput, save_to_disk: bool) -> PreprocessingResult<Self>;
+    /// Reports the progress of long-running steps to `progress`
+    fn preprocess(
+        input: PreprocessingInput,
+        config: &RoutingConfig,
+        save_to_disk: bool,
+        progress: &dyn ProgressSink,
+    ) -> PreprocessingResult<Self>;
 }
 
 
+/// Loads an algorithm from the artifacts of an earlier preprocessing, see [crate::artifacts]
+#[cfg(feature = "preprocessing")]
 pub trait FromDiskInit: RoutingAlgorithm + Sized {
-    fn load_from_disk() -> PreprocessingResult<Self>;
+    /// Reads what [SaveToDisk::save_to_disk] wrote to `dir`, next to the stops written by
+    /// [crate::artifacts::write_stops]
+    fn load_from_disk(dir: &Path) -> PreprocessingResult<Self>;
+}
+
+#[cfg(feature = "preprocessing")]
+pub trait SaveToDisk: RoutingAlgorithm {
+    /// Writes everything but the stops that is needed to answer queries to `dir`
+    fn save_to_disk(&self, dir: &Path, compression: Compression) -> PreprocessingResult<()>;
 }
 
 
+#[cfg(feature = "preprocessing")]
 #[derive(Clone)]
 pub struct PreprocessingInput {
     // corresponds to calendar.txt in GTFS
@@ -26,10 +62,25 @@ pub struct PreprocessingInput {
     pub stops: LazyFrame,
     pub trips: LazyFrame,
     pub stop_times: LazyFrame,
+    // Maps every stop to the station it belongs to (columns "stop_id", "station_id"). Platforms of
+    // the same parent station share a station_id, stops without a parent are a station of their own.
+    pub stations: LazyFrame,
+    // Maps every trip to its template trip and the offset of this run to the template (columns
+    // "trip_id", "template_trip_id", "run_offset"). Regular trips are their own template with an
+    // offset of zero, runs of frequency-based trips share the template of their frequency.
+    pub trip_runs: LazyFrame,
+    // Minimum times of changing between stops as given by transfers.txt of the datasets (columns
+    // "from_stop_id", "to_stop_id", "duration"). Transfers without a duration are not possible.
+    // May be empty without any columns.
+    pub transfers: LazyFrame,
+    // The identifiers of stops and trips in their source datasets
+    pub original_ids: Arc<OriginalIds>,
 }
 
+#[cfg(feature = "preprocessing")]
 pub type PreprocessingResult<T> = Result<T, PreprocessingError>;
 
+#[cfg(feature = "preprocessing")]
 #[derive(thiserror::Error, Debug)]
 pub enum PreprocessingError {
     Polars(#[from] polars::error::PolarsError),
@@ -38,8 +89,14 @@ pub enum PreprocessingError {
     GeoArrow(#[from] geoarrow::error::GeoArrowError),
     Arrow(#[from] arrow_schema::ArrowError),
     BuildLines(#[from] common::util::geoarrow_lines::Error),
+    Json(#[from] serde_json::Error),
+    Osm(#[from] crate::transfers::osm::OsmError),
+    Router(#[from] crate::transfers::external::RouterError),
+    // The artifacts of an earlier preprocessing are of another format version
+    IncompatibleArtifacts(u32),
 }
 
+#[cfg(feature = "preprocessing")]
 impl Display for PreprocessingError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let err: &dyn Display = match self {
@@ -49,6 +106,14 @@ impl Display for PreprocessingError {
             PreprocessingError::GeoArrow(err) => err,
             PreprocessingError::Arrow(err) => err,
             PreprocessingError::BuildLines(err) => err,
+            PreprocessingError::Json(err) => err,
+            PreprocessingError::Osm(err) => err,
+            PreprocessingError::Router(err) => err,
+            PreprocessingError::IncompatibleArtifacts(version) => return write!(
+                f,
+                "Artifacts of format version {} can't be loaded, preprocess again to get version {}",
+                version, crate::artifacts::FORMAT_VERSION,
+            ),
         };
         write!(f, "{}", err)
     }
@@ -83,28 +148,204 @@ impl QueryTargetCardinality for Multiple<'static> {}
 
 impl QueryTargetCardinality for All {}
 
+#[derive(Clone)]
 pub struct EarliestArrival {
     pub(crate) earliest_departure: DateTime<Utc>,
     pub(crate) start: StopId,
+    // Trips that are treated as not running, e.g. to preview a planned closure of their line
+    pub(crate) suspended_trips: HashSet<TripId>,
+    // Stops where no ride is boarded or alighted and no walk starts or ends, e.g. ones that aren't
+    // step-free. Rides still pass them.
+    pub(crate) closed_stops: HashSet<StopId>,
+    // Transfers are cycled at this speed instead of walked
+    pub(crate) cycling_speed: Option<Speed>,
+    pub(crate) max_transfers: Option<usize>,
+    // Left at least between arriving at a stop and boarding there, except at the start
+    pub(crate) min_transfer_buffer: TimeDelta,
+    // Left on top of the buffer to board trips of modes with a transfer slack, except at the start
+    pub(crate) transfer_slack: Arc<HashMap<TripId, TimeDelta>>,
+    // How the start and target are left and reached, only [crate::park_and_ride::ParkAndRide]
+    // drives or cycles
+    pub(crate) access_mode: AccessMode,
+    pub(crate) egress_mode: AccessMode,
 }
 
-pub struct LatestDeparture {
-    pub(crate) latest_arrival: DateTime<Utc>,
-    pub(crate) start: StopId,
+impl EarliestArrival {
+    pub fn new(start: StopId, earliest_departure: DateTime<Utc>) -> Self {
+        Self {
+            earliest_departure,
+            start,
+            suspended_trips: HashSet::new(),
+            closed_stops: HashSet::new(),
+            cycling_speed: None,
+            max_transfers: None,
+            min_transfer_buffer: TimeDelta::zero(),
+            transfer_slack: Arc::default(),
+            access_mode: AccessMode::Walk,
+            egress_mode: AccessMode::Walk,
+        }
+    }
+
+    /// Applies the constraints that the traveller chose
+    pub fn with_options(mut self, options: &QueryOptions) -> Self {
+        self.max_transfers = options.max_transfers;
+        self.min_transfer_buffer = options.min_transfer_buffer;
+        self.transfer_slack = options.transfer_slack.clone();
+        self.access_mode = options.access_mode;
+        self.egress_mode = options.egress_mode;
+        self.suspending(options.avoided_trips.iter().copied())
+            .closing(options.avoided_stops.iter().copied())
+    }
+
+    /// Masks the trips during the search, without changing the preprocessed data
+    pub fn suspending(mut self, trips: impl IntoIterator<Item = TripId>) -> Self {
+        self.suspended_trips.extend(trips);
+        self
+    }
+
+    /// Neither boards, alights nor walks at the stops during the search
+    pub fn closing(mut self, stops: impl IntoIterator<Item = StopId>) -> Self {
+        self.closed_stops.extend(stops);
+        self
+    }
+
+    /// Only rides trips and uses stops that are known to be step-free
+    #[cfg(feature = "preprocessing")]
+    pub fn step_free(self, accessibility: &AccessibilityInfo) -> Self {
+        self.suspending(accessibility.inaccessible_trips())
+            .closing(accessibility.inaccessible_stops())
+    }
+
+    /// Only boards trips that allow bikes and cycles between stops at `speed` instead of walking,
+    /// which reaches stops that are further away
+    #[cfg(feature = "preprocessing")]
+    pub fn with_bike(mut self, bikes: &BikeCarriage, speed: Speed) -> Self {
+        self.cycling_speed = Some(speed);
+        self.suspending(bikes.trips_without_bikes())
+    }
+
+    // The transfers of the search, which are the ones of the algorithm unless they are cycled
+    pub(crate) fn transfers(&self, walking: &(dyn TransferProvider + Send + Sync)) -> Option<Box<dyn TransferProvider + Send + Sync>> {
+        self.cycling_speed.and_then(|speed| walking.by_bike(speed))
+    }
+
+    // When a trip can be boarded at a stop that was reached at `arrival`
+    pub(crate) fn ready_to_board(&self, stop: StopId, arrival: DateTime<Utc>) -> DateTime<Utc> {
+        if stop == self.start {
+            return arrival;
+        }
+        arrival.checked_add_signed(self.min_transfer_buffer).unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    // Whether the trip departing at `departure` can be boarded at a stop that was reached at
+    // `arrival`, leaving the transfer slack of its mode on top of the buffer
+    pub(crate) fn can_board(&self, trip: TripId, stop: StopId, arrival: DateTime<Utc>, departure: DateTime<Utc>) -> bool {
+        let slack = match stop == self.start {
+            true => TimeDelta::zero(),
+            false => self.transfer_slack.get(&trip).copied().unwrap_or_default(),
+        };
+        !self.suspended_trips.contains(&trip)
+            && self.ready_to_board(stop, arrival).checked_add_signed(slack).is_some_and(|ready| ready <= departure)
+    }
+
+    // Whether some trips need more time to be boarded than [EarliestArrival::ready_to_board]
+    pub(crate) fn has_transfer_slack(&self) -> bool {
+        !self.transfer_slack.is_empty()
+    }
+
+    /// Whether the journey only rides trips and uses stops that the search may use, for journeys
+    /// that weren't found by a search, e.g. the rides of a timetable lookup
+    pub fn allows(&self, journey: &Journey) -> bool {
+        let allowed_stop = |stop: &StopId| !self.closed_stops.contains(stop);
+        journey.legs().all(|leg| match leg {
+            Leg::Ride { trip, boarding_stop, alight_stop, .. } => {
+                !self.suspended_trips.contains(trip) && allowed_stop(boarding_stop) && allowed_stop(alight_stop)
+            }
+            Leg::Transfer { start, end, .. } => allowed_stop(start) && allowed_stop(end),
+        })
+    }
+
+    // Algorithms that don't search by the number of rides can't look for journeys with fewer
+    // transfers, so they reject the ones they found instead
+    #[cfg(feature = "preprocessing")]
+    pub(crate) fn permits(&self, journey: &Journey) -> bool {
+        self.max_transfers.is_none_or(|max_transfers| journey.transfers() <= max_transfers)
+    }
+}
+
+/// Constraints of a query that travellers choose, e.g. to get around a disrupted line. They are
+/// accepted by all planners and applied to the search with [EarliestArrival::with_options].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QueryOptions {
+    // Journeys with more changes between rides are not considered
+    pub max_transfers: Option<usize>,
+    // Stops that are neither boarded, alighted nor walked to, rides still pass them
+    pub avoided_stops: HashSet<StopId>,
+    // Trips that are not ridden, e.g. all trips of a line or an agency
+    pub avoided_trips: HashSet<TripId>,
+    // Left at least between arriving at a stop and boarding another trip there
+    pub min_transfer_buffer: TimeDelta,
+    // Left on top of the buffer to board these trips, which is the transfer slack of their mode,
+    // see [crate::cost::CostInfo::transfer_slack]
+    pub transfer_slack: Arc<HashMap<TripId, TimeDelta>>,
+    // Driving or cycling to a P+R stop at the start and from one at the target, which only
+    // [crate::park_and_ride::ParkAndRide] plans. All other planners walk.
+    pub access_mode: AccessMode,
+    pub egress_mode: AccessMode,
 }
 
+/// How travellers get from the start to the first ride and from the last ride to the target
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessMode {
+    // Walks, which is what all planners assume
+    #[default]
+    Walk,
+    // Cycles to or from a P+R stop, where the bike is parked
+    Bike,
+    // Drives to or from a P+R stop, where the car is parked
+    Car,
+}
+
+impl FromStr for AccessMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "walk" => Ok(AccessMode::Walk),
+            "bike" => Ok(AccessMode::Bike),
+            "car" => Ok(AccessMode::Car),
+            _ => Err(format!("expected walk, bike or car, got {}", value)),
+        }
+    }
+}
+
+impl Display for AccessMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self {
+            AccessMode::Walk => "walk",
+            AccessMode::Bike => "bike",
+            AccessMode::Car => "car",
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+
 pub struct Range {
     pub(crate) earliest_departure: DateTime<Utc>,
     pub(crate) range: TimeDelta,
     pub(crate) start: StopId,
+    pub(crate) options: QueryOptions,
 }
 
 impl Range {
-    fn from_absolute(earliest: DateTime<Utc>, latest: DateTime<Utc>, start: StopId) -> Self {
+    fn from_absolute(earliest: DateTime<Utc>, latest: DateTime<Utc>, start: StopId, options: QueryOptions) -> Self {
         Self {
             earliest_departure: earliest,
             range: latest - earliest,
             start,
+            options,
         }
     }
 }
@@ -115,16 +356,228 @@ pub struct EarliestArrivalOutput {
     pub(crate) journey: Journey,
 }
 
-#[derive(Debug)]
+impl EarliestArrivalOutput {
+    pub fn journey(&self) -> &Journey {
+        &self.journey
+    }
+
+    #[cfg(feature = "preprocessing")]
+    pub fn accessibility(&self, info: &AccessibilityInfo) -> AccessibilitySummary {
+        info.summarize(&self.journey)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct RangeOutput {
     pub(crate) journeys: HashSet<Journey>,
 }
 
+impl RangeOutput {
+    /// Deduplicates and limits the journeys of this result. `line_of` decides which trips count as
+    /// the same line, e.g. all runs of a frequency-based trip by using their template.
+    pub fn filtered_journeys<L, F>(self, filter: &JourneyFilter, line_of: F) -> Vec<Journey>
+    where
+        L: Eq + Hash,
+        F: Fn(&TripId) -> L,
+    {
+        filter.apply(self.journeys, line_of)
+    }
+}
+
+
+/// The journeys that are Pareto-optimal with respect to arrival and number of rides, sorted by
+/// the number of rides. A journey with more rides is only included if it arrives earlier.
+#[derive(Debug)]
+pub struct ParetoOutput {
+    pub(crate) journeys: Vec<Journey>,
+}
+
+impl ParetoOutput {
+    pub fn journeys(&self) -> &[Journey] {
+        &self.journeys
+    }
+}
+
+/// The journey with the earliest arrival from one stop to another when departing at or after
+/// `departure`. This is the stable entry point for using the crate as a library, it is available
+/// for every algorithm that answers [SingleEarliestArrival] queries.
+pub trait JourneyPlanner: RoutingAlgorithm {
+    fn query_ea(&self, from: StopId, to: StopId, departure: DateTime<Utc>) -> QueryResult<Journey> {
+        self.query_ea_suspending(from, to, departure, HashSet::new())
+    }
+
+    /// Like [JourneyPlanner::query_ea], as if the suspended trips didn't run
+    fn query_ea_suspending(
+        &self,
+        from: StopId,
+        to: StopId,
+        departure: DateTime<Utc>,
+        suspended_trips: HashSet<TripId>,
+    ) -> QueryResult<Journey>;
+
+    /// Like [JourneyPlanner::query_ea_suspending], only riding trips and using stops that are
+    /// known to be step-free. The start and target have to be step-free too.
+    #[cfg(feature = "preprocessing")]
+    fn query_ea_step_free(
+        &self,
+        from: StopId,
+        to: StopId,
+        departure: DateTime<Utc>,
+        suspended_trips: HashSet<TripId>,
+        accessibility: &AccessibilityInfo,
+    ) -> QueryResult<Journey> {
+        self.query_ea_with(EarliestArrival::new(from, departure).suspending(suspended_trips).step_free(accessibility), to)
+    }
+
+    /// Like [JourneyPlanner::query_ea], with all options of the input, e.g. taking a bike along or
+    /// the [QueryOptions] of the traveller
+    fn query_ea_with(&self, input: EarliestArrival, to: StopId) -> QueryResult<Journey>;
+}
+
+impl<A: SingleEarliestArrival> JourneyPlanner for A {
+    fn query_ea_suspending(
+        &self,
+        from: StopId,
+        to: StopId,
+        departure: DateTime<Utc>,
+        suspended_trips: HashSet<TripId>,
+    ) -> QueryResult<Journey> {
+        self.query_ea_with(EarliestArrival::new(from, departure).suspending(suspended_trips), to)
+    }
+
+    fn query_ea_with(&self, input: EarliestArrival, to: StopId) -> QueryResult<Journey> {
+        SingleEarliestArrival::query_ea(self, input, Single { target: to })
+            .map(|output| output.journey)
+    }
+}
+
+/// All Pareto-optimal journeys from one stop to another that depart between `earliest_departure`
+/// and `latest_departure`, sorted by departure (see [pareto_optimal]). This is what departure
+/// planners show, it is available for every algorithm that answers [AllRange] queries.
+pub trait ProfilePlanner: RoutingAlgorithm {
+    fn query_profile(
+        &self,
+        from: StopId,
+        to: StopId,
+        earliest_departure: DateTime<Utc>,
+        latest_departure: DateTime<Utc>,
+    ) -> QueryResult<Vec<Journey>> {
+        self.query_profile_with(from, to, earliest_departure, latest_departure, &QueryOptions::default())
+    }
+
+    /// Like [ProfilePlanner::query_profile], only with journeys that meet the options
+    fn query_profile_with(
+        &self,
+        from: StopId,
+        to: StopId,
+        earliest_departure: DateTime<Utc>,
+        latest_departure: DateTime<Utc>,
+        options: &QueryOptions,
+    ) -> QueryResult<Vec<Journey>>;
+}
+
+impl<A: AllRange> ProfilePlanner for A {
+    fn query_profile_with(
+        &self,
+        from: StopId,
+        to: StopId,
+        earliest_departure: DateTime<Utc>,
+        latest_departure: DateTime<Utc>,
+        options: &QueryOptions,
+    ) -> QueryResult<Vec<Journey>> {
+        let range = Range::from_absolute(earliest_departure, latest_departure, from, options.clone());
+        let RangeOutput { journeys } = self.query_range_all(range)?;
+        let profile = pareto_optimal(journeys.into_iter()
+            .filter(|journey| *journey.arrival_stop() == to)
+            .filter(|journey| journey.departure().is_some_and(|departure| departure >= earliest_departure)));
+
+        if profile.is_empty() {
+            return Err(QueryError::NoRouteFound);
+        }
+        Ok(profile)
+    }
+}
+
+/// The journeys from one stop to another departing at or after `departure` that arrive earliest
+/// with each number of rides, sorted by the number of rides (see [ParetoOutput]). This is what
+/// travellers who prefer fewer transfers over an earlier arrival need, it is available for every
+/// algorithm that answers [SinglePareto] queries.
+pub trait ParetoPlanner: RoutingAlgorithm {
+    fn query_pareto_set(&self, from: StopId, to: StopId, departure: DateTime<Utc>) -> QueryResult<Vec<Journey>> {
+        self.query_pareto_set_with(EarliestArrival::new(from, departure), to)
+    }
+
+    /// Like [ParetoPlanner::query_pareto_set], with all options of the input
+    fn query_pareto_set_with(&self, input: EarliestArrival, to: StopId) -> QueryResult<Vec<Journey>>;
+}
+
+impl<A: SinglePareto> ParetoPlanner for A {
+    fn query_pareto_set_with(&self, input: EarliestArrival, to: StopId) -> QueryResult<Vec<Journey>> {
+        self.query_pareto(input, Single { target: to })
+            .map(|output| output.journeys)
+    }
+}
+
+/// The journeys with the earliest arrival from one stop to each of the targets, or to all stops,
+/// computed in a single search instead of one per target. This is what accessibility analyses
+/// need, it is available for every algorithm that answers [MultiEarliestArrival] and
+/// [AllEarliestArrival] queries. Stops that can't be reached are left out.
+pub trait OneToManyPlanner: RoutingAlgorithm {
+    fn query_ea_many(&self, from: StopId, targets: &[StopId], departure: DateTime<Utc>) -> QueryResult<HashMap<StopId, Journey>> {
+        self.query_ea_many_with(EarliestArrival::new(from, departure), targets)
+    }
+
+    fn query_ea_to_all(&self, from: StopId, departure: DateTime<Utc>) -> QueryResult<HashMap<StopId, Journey>> {
+        self.query_ea_to_all_with(EarliestArrival::new(from, departure))
+    }
+
+    /// Like [OneToManyPlanner::query_ea_many], with all options of the input
+    fn query_ea_many_with(&self, input: EarliestArrival, targets: &[StopId]) -> QueryResult<HashMap<StopId, Journey>>;
+
+    /// Like [OneToManyPlanner::query_ea_to_all], with all options of the input
+    fn query_ea_to_all_with(&self, input: EarliestArrival) -> QueryResult<HashMap<StopId, Journey>>;
+}
+
+impl<A: MultiEarliestArrival + AllEarliestArrival> OneToManyPlanner for A {
+    fn query_ea_many_with(&self, input: EarliestArrival, targets: &[StopId]) -> QueryResult<HashMap<StopId, Journey>> {
+        let targets = targets.to_vec();
+        let outputs = self.query_ea_multi(input, Multiple { targets: &targets })?;
+        Ok(by_arrival_stop(outputs))
+    }
+
+    fn query_ea_to_all_with(&self, input: EarliestArrival) -> QueryResult<HashMap<StopId, Journey>> {
+        let outputs = match self.query_ea_all(input) {
+            // Nothing is reachable from the start
+            Err(QueryError::NoRouteFound) => vec![],
+            outputs => outputs?,
+        };
+        Ok(by_arrival_stop(outputs))
+    }
+}
+
+fn by_arrival_stop(outputs: Vec<EarliestArrivalOutput>) -> HashMap<StopId, Journey> {
+    outputs.into_iter()
+        .map(|EarliestArrivalOutput { journey }| (*journey.arrival_stop(), journey))
+        .collect()
+}
 
 pub trait SingleEarliestArrival: RoutingAlgorithm {
     fn query_ea(&self, input: EarliestArrival, cardinality: Single) -> QueryResult<EarliestArrivalOutput>;
 }
 
+// Shared by several users, e.g. the shadow mode and the profiles of the server
+impl<A: RoutingAlgorithm + ?Sized> RoutingAlgorithm for Arc<A> {}
+
+impl<A: SingleEarliestArrival + ?Sized> SingleEarliestArrival for Arc<A> {
+    fn query_ea(&self, input: EarliestArrival, cardinality: Single) -> QueryResult<EarliestArrivalOutput> {
+        self.as_ref().query_ea(input, cardinality)
+    }
+}
+
+pub trait SinglePareto: RoutingAlgorithm {
+    fn query_pareto(&self, input: EarliestArrival, cardinality: Single) -> QueryResult<ParetoOutput>;
+}
+
 pub trait SingleRange: RoutingAlgorithm {
     fn query_range(&self, input: Range, cardinality: Single) -> QueryResult<RangeOutput>;
 }
@@ -153,19 +606,39 @@ Result<Vec<O>, QueryError>;
 
 #[derive(thiserror::Error, Debug)]
 pub enum QueryError {
+    #[cfg(feature = "preprocessing")]
     Polars(#[from] polars::error::PolarsError),
     NoRouteFound,
     TransferError(#[from] TransferError),
+    StopNotFound(StopId),
+    // The date of the query is outside the calendar of the datasets
+    DateOutOfRange(NaiveDate),
+}
+
+impl QueryError {
+    /// The code that is reported to clients for this error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            #[cfg(feature = "preprocessing")]
+            QueryError::Polars(_) => ErrorCode::Internal,
+            QueryError::TransferError(_) => ErrorCode::Internal,
+            QueryError::NoRouteFound => ErrorCode::NoRouteFound,
+            QueryError::StopNotFound(_) => ErrorCode::StopNotFound,
+            QueryError::DateOutOfRange(_) => ErrorCode::DateOutOfRange,
+        }
+    }
 }
 
 impl Display for QueryError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let err: &dyn Display = match self {
-            QueryError::Polars(err) => err,
-            QueryError::NoRouteFound => &"No route found",
-            QueryError::TransferError(err) => err
-        };
-        write!(f, "{}", err)
+        match self {
+            #[cfg(feature = "preprocessing")]
+            QueryError::Polars(err) => write!(f, "{}", err),
+            QueryError::NoRouteFound => write!(f, "No route found"),
+            QueryError::TransferError(err) => write!(f, "{}", err),
+            QueryError::StopNotFound(stop) => write!(f, "Unknown stop {}", stop),
+            QueryError::DateOutOfRange(date) => write!(f, "No timetable data for {}", date),
+        }
     }
 }
 
@@ -173,4 +646,175 @@ impl From<Journey> for EarliestArrivalOutput {
     fn from(journey: Journey) -> Self {
         Self { journey }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csa::ConnectionScanAlgorithm;
+    use crate::direct_connections::DirectConnections;
+    use crate::raptor::RaptorAlgorithm;
+    use crate::tests::duration;
+    use common::util::speed::CYCLING_SPEED;
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    #[test]
+    fn test_one_to_many() {
+        // Trip 0 runs from stop 0 to 1 at 100s to 500s, trip 1 from stop 1 to 2 at 1000s to 1500s
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input).unwrap()).unwrap();
+
+        let journeys = raptor.query_ea_many(StopId(0), &[StopId(1), StopId(2)], DateTime::UNIX_EPOCH).unwrap();
+        assert_eq!(journeys.len(), 2);
+        assert_eq!(journeys[&StopId(2)].arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // Stop 0 can't be reached from stop 2
+        assert!(raptor.query_ea_many(StopId(2), &[StopId(0)], DateTime::UNIX_EPOCH).unwrap().is_empty());
+        assert!(matches!(
+            raptor.query_ea_many(StopId(0), &[StopId(9)], DateTime::UNIX_EPOCH),
+            Err(QueryError::StopNotFound(StopId(9))),
+        ));
+
+        let all = raptor.query_ea_to_all(StopId(1), DateTime::UNIX_EPOCH).unwrap();
+        assert_eq!(all.keys().collect::<Vec<_>>(), [&StopId(2)]);
+    }
+
+    #[test]
+    fn test_range_profile() {
+        // Trips 0 and 1 run from stop 0 to 1, departing at 100s and 2000s, trip 2 runs from stop 1
+        // to 2 at 3000s to 3500s
+        let input = PreprocessingInput {
+            trips: df!["trip_id" => [0u32, 1, 2], "service_id" => [0u32, 0, 0]].unwrap().lazy(),
+            stop_times: df![
+                "trip_id" => [0u32, 0, 1, 1, 2, 2],
+                "stop_id" => [0u32, 1, 0, 1, 1, 2],
+                "arrival_time" => [100, 500, 2_000, 2_400, 3_000, 3_500].map(duration),
+                "departure_time" => [100, 500, 2_000, 2_400, 3_000, 3_500].map(duration),
+                "stop_sequence" => [0u32, 1, 0, 1, 0, 1],
+            ].unwrap().lazy(),
+            trip_runs: df![
+                "trip_id" => [0u32, 1, 2],
+                "template_trip_id" => [0u32, 1, 2],
+                "run_offset" => [0; 3].map(duration),
+            ].unwrap().lazy(),
+            ..crate::tests::case_2::generate_preprocessing_input().unwrap()
+        };
+        let raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input).unwrap()).unwrap();
+
+        let RangeOutput { journeys } = raptor.query_range_all(Range {
+            earliest_departure: DateTime::UNIX_EPOCH,
+            range: TimeDelta::seconds(3_000),
+            start: StopId(0),
+            options: Default::default(),
+        }).unwrap();
+
+        // Both trips to stop 1 are part of the profile, but only the later one gets to stop 2 in
+        // time for trip 2
+        let mut departures = journeys.iter()
+            .map(|journey| (*journey.arrival_stop(), journey.departure().unwrap().timestamp()))
+            .collect::<Vec<_>>();
+        departures.sort();
+        assert_eq!(departures, [(StopId(1), 100), (StopId(1), 2_000), (StopId(2), 2_000)]);
+    }
+
+    // Trips 0 and 1 run from stop 0 over stop 1 to stop 2, departing at 100s and 1000s
+    fn two_runs_over_three_stops() -> PreprocessingInput {
+        PreprocessingInput {
+            trips: df!["trip_id" => [0u32, 1], "service_id" => [0u32, 0]].unwrap().lazy(),
+            stop_times: df![
+                "trip_id" => [0u32, 0, 0, 1, 1, 1],
+                "stop_id" => [0u32, 1, 2, 0, 1, 2],
+                "arrival_time" => [100, 300, 500, 1_000, 1_200, 1_500].map(duration),
+                "departure_time" => [100, 300, 500, 1_000, 1_200, 1_500].map(duration),
+                "stop_sequence" => [0u32, 1, 2, 0, 1, 2],
+            ].unwrap().lazy(),
+            trip_runs: df![
+                "trip_id" => [0u32, 1],
+                "template_trip_id" => [0u32, 1],
+                "run_offset" => [0; 2].map(duration),
+            ].unwrap().lazy(),
+            ..crate::tests::case_2::generate_preprocessing_input().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_query_ea_step_free() {
+        let input = two_runs_over_three_stops();
+        let raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input).unwrap()).unwrap();
+        let accessibility = AccessibilityInfo::from_frames(
+            df!["stop_id" => [0u32, 1, 2], "wheelchair_boarding" => [1u32, 2, 1]].unwrap().lazy(),
+            df!["trip_id" => [0u32, 1], "wheelchair_accessible" => [0u32, 1]].unwrap().lazy(),
+        ).unwrap();
+
+        let journey = JourneyPlanner::query_ea(&raptor, StopId(0), StopId(2), DateTime::UNIX_EPOCH).unwrap();
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(500, 0));
+
+        // Trip 1 passes the inaccessible stop 1
+        let journey = raptor.query_ea_step_free(StopId(0), StopId(2), DateTime::UNIX_EPOCH, HashSet::new(), &accessibility).unwrap();
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+        assert_eq!(accessibility.summarize(&journey), AccessibilitySummary::StepFree);
+
+        assert!(matches!(
+            raptor.query_ea_step_free(StopId(1), StopId(2), DateTime::UNIX_EPOCH, HashSet::new(), &accessibility),
+            Err(QueryError::NoRouteFound),
+        ));
+    }
+
+    #[test]
+    fn test_query_ea_with_bike() {
+        let input = two_runs_over_three_stops();
+        let raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input).unwrap()).unwrap();
+        let bikes = BikeCarriage::from_frame(df!["trip_id" => [0u32, 1], "bikes_allowed" => [2u32, 1]].unwrap().lazy()).unwrap();
+
+        let input = EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH).with_bike(&bikes, CYCLING_SPEED);
+        let journey = raptor.query_ea_with(input, StopId(2)).unwrap();
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+    }
+
+    #[test]
+    fn test_query_options() {
+        // Trip 0 runs from stop 0 to 1 at 100s to 500s, trip 1 from stop 1 to 2 at 1000s to 1500s
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let direct_connections = DirectConnections::try_from(input.clone()).unwrap();
+        let raptor = RaptorAlgorithm::preprocess(input.clone(), direct_connections.clone()).unwrap();
+        let csa = ConnectionScanAlgorithm::preprocess(input, &direct_connections).unwrap();
+        let query = |options: QueryOptions| EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH).with_options(&options);
+
+        let options = [
+            QueryOptions { max_transfers: Some(0), ..Default::default() },
+            QueryOptions { avoided_stops: HashSet::from([StopId(1)]), ..Default::default() },
+            QueryOptions { avoided_trips: HashSet::from([TripId(1)]), ..Default::default() },
+            QueryOptions { min_transfer_buffer: TimeDelta::seconds(600), ..Default::default() },
+            // The transfer slack of trip 1 is left on top of the buffer
+            QueryOptions {
+                min_transfer_buffer: TimeDelta::seconds(400),
+                transfer_slack: Arc::new(HashMap::from([(TripId(1), TimeDelta::seconds(200))])),
+                ..Default::default()
+            },
+        ];
+        for options in options {
+            assert!(matches!(raptor.query_ea_with(query(options.clone()), StopId(2)), Err(QueryError::NoRouteFound)), "{options:?}");
+            assert!(csa.query_ea_many_with(query(options.clone()), &[StopId(2)]).unwrap().is_empty(), "{options:?}");
+            assert!(raptor.query_profile_with(StopId(0), StopId(2), DateTime::UNIX_EPOCH, DateTime::UNIX_EPOCH, &options).is_err());
+            assert!(raptor.query_pareto_set_with(query(options.clone()), StopId(2)).is_err(), "{options:?}");
+        }
+
+        let options = QueryOptions { max_transfers: Some(1), min_transfer_buffer: TimeDelta::seconds(400), ..Default::default() };
+        let journey = raptor.query_ea_with(query(options.clone()), StopId(2)).unwrap();
+        assert_eq!((journey.arrival(), journey.transfers()), (DateTime::from_timestamp(1_500, 0), 1));
+        assert_eq!(raptor.query_pareto_set_with(query(options.clone()), StopId(2)).unwrap(), vec![journey]);
+        assert_eq!(csa.query_ea_to_all_with(query(options)).unwrap()[&StopId(2)].arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // Trip 0 is boarded at the start, which needs no slack
+        let options = QueryOptions { transfer_slack: Arc::new(HashMap::from([(TripId(0), TimeDelta::seconds(600))])), ..Default::default() };
+        assert_eq!(raptor.query_ea_with(query(options.clone()), StopId(2)).unwrap().arrival(), DateTime::from_timestamp(1_500, 0));
+        assert_eq!(csa.query_ea_to_all_with(query(options)).unwrap()[&StopId(2)].arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // Journeys that weren't searched are checked against the same options
+        let journey = raptor.query_ea_with(query(QueryOptions::default()), StopId(2)).unwrap();
+        assert!(query(QueryOptions::default()).allows(&journey));
+        assert!(!query(QueryOptions { avoided_stops: HashSet::from([StopId(1)]), ..Default::default() }).allows(&journey));
+        assert!(!query(QueryOptions { avoided_trips: HashSet::from([TripId(1)]), ..Default::default() }).allows(&journey));
+    }
+}
@@ -0,0 +1,279 @@
+//! Preprocessing results on disk, so that a process can serve queries without preprocessing the
+//! datasets again. Tables are stored as parquet, the few values besides them as JSON. Every
+//! directory of artifacts has a manifest of what it contains, which is written last, so that an
+//! interrupted save is never loaded.
+//!
+//! Without compression, tables are stored in the Arrow IPC format instead, which is memory-mapped
+//! when loading. Loading then takes about as long as opening the files, the tables are paged in on
+//! first access and shared by all processes that serve the same artifacts. Lookup tables that
+//! aren't data frames, like the ones of RAPTOR, are still built from the mapped tables.
+//!
+//! The tables of the simplified timetable, which serving reads besides the engine (e.g. the names
+//! of the stops and the routes of the trips), are copied into the directory as well, so that the
+//! artifacts can be served without the working directory they were preprocessed in.
+
+use crate::algorithm::{PreprocessingError, PreprocessingResult};
+use crate::transfers::closure::ClosedTransferProvider;
+use crate::transfers::crow_fly::CrowFlyTransferProvider;
+use crate::transfers::footpaths::FootpathTransferProvider;
+use crate::transfers::timetable::{self, TimetableTransferProvider};
+use crate::transfers;
+use crate::transfers::TransferProvider;
+use common::types::config::{Compression, RoutingMode, TransferConfig, TransferSource};
+use common::util::df::{read_df_from_file, read_df_memory_mapped, write_df_to_file_compressed, FileType};
+use polars::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+/// Version of the layout of the artifacts. Artifacts of another version are rejected instead of
+/// being misread, they have to be preprocessed again.
+pub const FORMAT_VERSION: u32 = 5;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const TRANSFERS_FILE: &str = "transfers.json";
+const TIMETABLE_DIR: &str = "timetable";
+const ROUTER_FOOTPATHS_TABLE: &str = "router_footpaths";
+const OSM_FOOTPATHS_TABLE: &str = "osm_footpaths";
+const CLOSED_FOOTPATHS_TABLE: &str = "closed_footpaths";
+const PARQUET_EXTENSION: &str = "parquet";
+const IPC_EXTENSION: &str = "arrow";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub version: u32,
+    // Which engine was saved
+    pub mode: RoutingMode,
+    // The files of the timetable that were copied by [write_timetable]
+    pub timetable: Vec<String>,
+}
+
+impl Manifest {
+    pub fn new(mode: RoutingMode, timetable: Vec<String>) -> Self {
+        Self { version: FORMAT_VERSION, mode, timetable }
+    }
+
+    pub fn write(&self, dir: &Path) -> PreprocessingResult<()> {
+        write_json(dir, MANIFEST_FILE, self)
+    }
+
+    /// Removes the manifest of `dir`, if there is one, before other artifacts are written to it.
+    /// An interrupted save into the directory of an earlier one then isn't loaded either.
+    pub fn remove(dir: &Path) -> PreprocessingResult<()> {
+        match fs::remove_file(dir.join(MANIFEST_FILE)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads the manifest of `dir`, if its artifacts were written in the current format
+    pub fn read(dir: &Path) -> PreprocessingResult<Self> {
+        let manifest: Self = read_json(dir, MANIFEST_FILE)?;
+        if manifest.version != FORMAT_VERSION {
+            return Err(PreprocessingError::IncompatibleArtifacts(manifest.version));
+        }
+        Ok(manifest)
+    }
+}
+
+/// Writes the ids and coordinates of the stops, which all algorithms need for walks after loading,
+/// together with how the walks were estimated during preprocessing and the minimum transfer times
+/// of the timetable. Walks along the ways of an OSM extract or of a router are computed again and
+/// written as a table, so that serving needs neither of them. Responses of routers are cached, so
+/// they aren't requested again. The walks of all sources are closed here as well, so that loading
+/// doesn't close them again.
+pub fn write_stops(
+    dir: &Path,
+    stops: LazyFrame,
+    timetable_transfers: LazyFrame,
+    transfers: &TransferConfig,
+    compression: Compression,
+) -> PreprocessingResult<()> {
+    let stops = stops.select([col("stop_id"), col("lat"), col("lon")]).collect()?;
+    if let Some(router) = transfers.router.as_ref().filter(|_| transfers.uses(TransferSource::Router)) {
+        FootpathTransferProvider::from_router(router, stops.clone().lazy(), transfers)?
+            .write(dir, ROUTER_FOOTPATHS_TABLE, compression)?;
+    }
+    if let Some(extract) = transfers.osm_extract.as_ref().filter(|_| transfers.uses(TransferSource::OsmExtract)) {
+        FootpathTransferProvider::from_osm(extract, stops.clone().lazy(), transfers)?
+            .write(dir, OSM_FOOTPATHS_TABLE, compression)?;
+    }
+    timetable::write(dir, timetable_transfers, compression)?;
+    write_table(dir, "stops", stops.clone(), compression)?;
+    write_json(dir, TRANSFERS_FILE, transfers)?;
+
+    if let (chained, true) = chain(dir, transfers)? {
+        transfers::close(chained, transfers, stops.lazy())?.write(dir, CLOSED_FOOTPATHS_TABLE, compression)?;
+    }
+    Ok(())
+}
+
+/// Copies the tables in `tables` to the timetable directory of `dir` and returns their file names
+/// for the manifest
+pub fn write_timetable(dir: &Path, tables: &Path) -> PreprocessingResult<Vec<String>> {
+    let timetable = dir.join(TIMETABLE_DIR);
+    fs::create_dir_all(&timetable)?;
+    let mut names = vec![];
+    for entry in fs::read_dir(tables)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == PARQUET_EXTENSION) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            fs::copy(&path, timetable.join(&name))?;
+            names.push(name);
+        }
+    }
+    names.sort_unstable();
+    Ok(names)
+}
+
+/// The directory of the tables copied by [write_timetable], once all that the manifest lists are
+/// there
+pub fn read_timetable(dir: &Path) -> PreprocessingResult<PathBuf> {
+    let timetable = dir.join(TIMETABLE_DIR);
+    if let Some(missing) = Manifest::read(dir)?.timetable.iter().find(|name| !timetable.join(name).exists()) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("The table {} of the timetable is missing in {}", missing, timetable.display()),
+        ).into());
+    }
+    Ok(timetable)
+}
+
+/// The stops written by [write_stops]
+pub fn read_stops(dir: &Path) -> PreprocessingResult<LazyFrame> {
+    Ok(read_table(dir, "stops")?.lazy())
+}
+
+/// Walks between the stops written by [write_stops], as configured when they were written. Queries
+/// have to walk like preprocessing did, so the config of the serving process doesn't matter.
+pub fn read_transfers(dir: &Path) -> PreprocessingResult<Box<dyn TransferProvider + Send + Sync>> {
+    let config: TransferConfig = if dir.join(TRANSFERS_FILE).exists() {
+        read_json(dir, TRANSFERS_FILE)?
+    } else {
+        TransferConfig::default()
+    };
+    Ok(match chain(dir, &config)? {
+        (chained, true) => Box::new(ClosedTransferProvider::read(dir, CLOSED_FOOTPATHS_TABLE, chained)?),
+        (chained, false) => chained,
+    })
+}
+
+// The walks of the sources of the config, before they are closed
+fn chain(dir: &Path, config: &TransferConfig) -> PreprocessingResult<(Box<dyn TransferProvider + Send + Sync>, bool)> {
+    let stops = read_stops(dir)?;
+    transfers::chain(config, |source| Ok(match source {
+        TransferSource::Timetable => TimetableTransferProvider::from_frame(timetable::read(dir)?)?
+            .map(|provider| Box::new(provider) as Box<dyn TransferProvider + Send + Sync>),
+        TransferSource::Router => Some(Box::new(FootpathTransferProvider::read(dir, ROUTER_FOOTPATHS_TABLE, stops.clone(), config)?)),
+        TransferSource::OsmExtract => Some(Box::new(FootpathTransferProvider::read(dir, OSM_FOOTPATHS_TABLE, stops.clone(), config)?)),
+        TransferSource::CrowFly => Some(Box::new(CrowFlyTransferProvider::from_stops(stops.clone())?.with_config(config))),
+    }))
+}
+
+pub(crate) fn write_table(dir: &Path, name: &str, table: DataFrame, compression: Compression) -> PolarsResult<()> {
+    let [parquet, ipc] = [PARQUET_EXTENSION, IPC_EXTENSION].map(|extension| dir.join(format!("{name}.{extension}")));
+    // A table of an earlier save with another compression would be read instead
+    let (path, format, stale) = match compression {
+        Compression::None => (ipc, FileType::IPC, parquet),
+        _ => (parquet, FileType::PARQUET, ipc),
+    };
+    if stale.exists() {
+        fs::remove_file(stale)?;
+    }
+    write_df_to_file_compressed(path, format, table, compression)
+}
+
+pub(crate) fn read_table(dir: &Path, name: &str) -> PolarsResult<DataFrame> {
+    let ipc = dir.join(format!("{name}.{IPC_EXTENSION}"));
+    if ipc.exists() {
+        return read_df_memory_mapped(ipc);
+    }
+    read_df_from_file(dir.join(format!("{name}.{PARQUET_EXTENSION}")), FileType::PARQUET)
+}
+
+pub(crate) fn write_json(dir: &Path, name: &str, value: &impl Serialize) -> PreprocessingResult<()> {
+    fs::create_dir_all(dir)?;
+    let writer = BufWriter::new(File::create(dir.join(name))?);
+    Ok(serde_json::to_writer_pretty(writer, value)?)
+}
+
+pub(crate) fn read_json<T: DeserializeOwned>(dir: &Path, name: &str) -> PreprocessingResult<T> {
+    let reader = BufReader::new(File::open(dir.join(name))?);
+    Ok(serde_json::from_reader(reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::StopId;
+
+    #[test]
+    fn test_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = Manifest::new(RoutingMode::TimetableLookup, vec![]);
+        manifest.write(dir.path()).unwrap();
+        assert_eq!(Manifest::read(dir.path()).unwrap(), manifest);
+
+        Manifest { version: FORMAT_VERSION + 1, ..manifest }.write(dir.path()).unwrap();
+        assert!(matches!(
+            Manifest::read(dir.path()),
+            Err(PreprocessingError::IncompatibleArtifacts(version)) if version == FORMAT_VERSION + 1,
+        ));
+
+        // Removing it twice is fine, the second time there's nothing to remove
+        Manifest::remove(dir.path()).unwrap();
+        Manifest::remove(dir.path()).unwrap();
+        assert!(Manifest::read(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_timetable() {
+        let (dir, tables) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let stops = df!("stop_id" => [0u32, 1], "stop_name" => ["A", "B"]).unwrap();
+        write_df_to_file_compressed(tables.path().join("stops.parquet"), FileType::PARQUET, stops.clone(), Compression::default()).unwrap();
+        fs::write(tables.path().join("notes.txt"), "not a table").unwrap();
+
+        let timetable = write_timetable(dir.path(), tables.path()).unwrap();
+        assert_eq!(timetable, ["stops.parquet"]);
+        Manifest::new(RoutingMode::Journeys, timetable).write(dir.path()).unwrap();
+        let copied = read_timetable(dir.path()).unwrap();
+        assert_eq!(read_df_from_file(copied.join("stops.parquet"), FileType::PARQUET).unwrap(), stops);
+
+        // Artifacts whose tables went missing aren't served
+        fs::remove_file(copied.join("stops.parquet")).unwrap();
+        assert!(read_timetable(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_tables() {
+        let dir = tempfile::tempdir().unwrap();
+        let table = df!("stop_id" => [0u32, 1, 2]).unwrap();
+
+        write_table(dir.path(), "stops", table.clone(), Compression::None).unwrap();
+        assert!(dir.path().join("stops.arrow").exists());
+        assert_eq!(read_table(dir.path(), "stops").unwrap(), table);
+
+        // Saving again with compression replaces the mapped table
+        let compressed = df!("stop_id" => [3u32]).unwrap();
+        write_table(dir.path(), "stops", compressed.clone(), Compression::default()).unwrap();
+        assert!(!dir.path().join("stops.arrow").exists());
+        assert_eq!(read_table(dir.path(), "stops").unwrap(), compressed);
+    }
+
+    #[test]
+    fn test_transfers() {
+        let dir = tempfile::tempdir().unwrap();
+        // About 2.5 km apart
+        let stops = df!("stop_id" => [0u32, 1], "lat" => [48.0f32, 48.0], "lon" => [9.0f32, 9.0225]).unwrap();
+        write_table(dir.path(), "stops", stops.clone(), Compression::default()).unwrap();
+        // Artifacts of older versions walk by the defaults
+        assert!(read_transfers(dir.path()).unwrap().duration(StopId(0), StopId(1)).is_err());
+
+        let transfers = TransferConfig { max_walking_minutes: 30, ..Default::default() };
+        write_stops(dir.path(), stops.lazy(), LazyFrame::default(), &transfers, Compression::default()).unwrap();
+        assert!(read_transfers(dir.path()).unwrap().duration(StopId(0), StopId(1)).is_ok());
+    }
+}
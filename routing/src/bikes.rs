@@ -0,0 +1,66 @@
+use common::types::TripId;
+use hashbrown::HashSet;
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::{col, LazyFrame};
+
+/// The trips that bikes can be taken on, as given by `bikes_allowed` of trips.txt. Trips without
+/// information are treated like trips that don't allow bikes.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BikeCarriage {
+    allowed: HashSet<TripId>,
+    // Every trip of the table, so that the others can be masked during a search
+    trips: HashSet<TripId>,
+}
+
+impl BikeCarriage {
+    pub fn from_frame(trips: LazyFrame) -> Result<Self, PolarsError> {
+        // Tables without the column (e.g. from older preprocessing runs) allow no bikes at all
+        let has_column = trips.clone().collect_schema()?.contains("bikes_allowed");
+        let columns = if has_column { vec![col("trip_id"), col("bikes_allowed")] } else { vec![col("trip_id")] };
+        let frame = trips.select(columns).collect()?;
+
+        let ids = frame.column("trip_id")?.u32()?;
+        let trips = ids.iter().flatten().map(TripId).collect();
+        let allowed = if has_column {
+            izip!(ids, frame.column("bikes_allowed")?.u32()?)
+                .filter_map(|(id, allowed)| (allowed? == 1).then_some(TripId(id?)))
+                .collect()
+        } else {
+            HashSet::new()
+        };
+
+        Ok(Self { allowed, trips })
+    }
+
+    pub fn allows_bikes(&self, trip: TripId) -> bool {
+        self.allowed.contains(&trip)
+    }
+
+    /// Trips that aren't known to allow bikes
+    pub fn trips_without_bikes(&self) -> HashSet<TripId> {
+        self.trips.difference(&self.allowed).copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    #[test]
+    fn test_from_frame() {
+        let trips = df!(
+            "trip_id"       => [0u32, 1, 2],
+            "bikes_allowed" => [Some(1u32), Some(2), None],
+        ).unwrap().lazy();
+        let bikes = BikeCarriage::from_frame(trips).unwrap();
+        assert!(bikes.allows_bikes(TripId(0)));
+        assert!(!bikes.allows_bikes(TripId(2)));
+        assert_eq!(bikes.trips_without_bikes(), HashSet::from([TripId(1), TripId(2)]));
+
+        let without_column = BikeCarriage::from_frame(df!("trip_id" => [0u32]).unwrap().lazy()).unwrap();
+        assert_eq!(without_column.trips_without_bikes(), HashSet::from([TripId(0)]));
+    }
+}
@@ -0,0 +1,184 @@
+use crate::journey::{Journey, Leg};
+use chrono::TimeDelta;
+use common::types::{StopId, TripId};
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::{col, DataType, LazyFrame};
+
+/// How far ahead a demand-responsive service has to be booked, as given by `booking_type` of
+/// booking_rules.txt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BookingType {
+    RealTime,
+    SameDay,
+    PriorDays,
+}
+
+impl TryFrom<u32> for BookingType {
+    type Error = ();
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(BookingType::RealTime),
+            1 => Ok(BookingType::SameDay),
+            2 => Ok(BookingType::PriorDays),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Annotation of a leg that can only be taken after booking it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookingNote {
+    pub booking_type: BookingType,
+    pub prior_notice: Option<TimeDelta>,
+    // Message of the dataset, e.g. "Book at least one hour ahead"
+    pub message: Option<String>,
+    pub phone_number: Option<String>,
+    pub url: Option<String>,
+}
+
+impl BookingNote {
+    /// A message for the traveller. The message of the dataset is preferred, otherwise it is built
+    /// from the contact information, e.g. "Call +49 711 123 to book at least 60 min ahead".
+    pub fn text(&self) -> String {
+        if let Some(message) = &self.message {
+            return message.clone();
+        }
+
+        let how = match (&self.phone_number, &self.url) {
+            (Some(phone_number), _) => format!("Call {phone_number} to book"),
+            (None, Some(url)) => format!("Book at {url}"),
+            (None, None) => "Book".to_owned(),
+        };
+
+        match (self.booking_type, self.prior_notice) {
+            (_, Some(notice)) => format!("{how} at least {} min ahead", notice.num_minutes()),
+            (BookingType::PriorDays, None) => format!("{how} at least one day ahead"),
+            _ => format!("{how} before departure"),
+        }
+    }
+}
+
+/// Lookup of the booking rules for boarding and alighting at stops of demand-responsive trips.
+/// Built from the `booking_notes` table of the simplification step (columns "trip_id", "stop_id",
+/// "kind", "booking_type", "prior_notice_duration_min", "message", "phone_number", "url").
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookingNotes {
+    pickups: HashMap<(TripId, StopId), BookingNote>,
+    drop_offs: HashMap<(TripId, StopId), BookingNote>,
+}
+
+impl BookingNotes {
+    pub fn from_frame(notes: LazyFrame) -> Result<Self, PolarsError> {
+        let notes = notes
+            .select([
+                col("trip_id"),
+                col("stop_id"),
+                col("kind"),
+                col("booking_type"),
+                col("prior_notice_duration_min").cast(DataType::UInt32),
+                col("message"),
+                col("phone_number"),
+                col("url"),
+            ])
+            .collect()?;
+
+        let mut result = Self::default();
+        let rows = izip!(
+            notes.column("trip_id")?.u32()?,
+            notes.column("stop_id")?.u32()?,
+            notes.column("kind")?.str()?,
+            notes.column("booking_type")?.u32()?,
+            notes.column("prior_notice_duration_min")?.u32()?,
+            notes.column("message")?.str()?,
+            notes.column("phone_number")?.str()?,
+            notes.column("url")?.str()?,
+        );
+
+        for (trip_id, stop_id, kind, booking_type, prior_notice, message, phone_number, url) in rows {
+            let (Some(trip_id), Some(stop_id), Some(Ok(booking_type))) =
+                (trip_id, stop_id, booking_type.map(BookingType::try_from)) else { continue };
+
+            let note = BookingNote {
+                booking_type,
+                prior_notice: prior_notice.map(|minutes| TimeDelta::minutes(minutes as i64)),
+                message: message.map(str::to_owned),
+                phone_number: phone_number.map(str::to_owned),
+                url: url.map(str::to_owned),
+            };
+
+            let notes = match kind {
+                Some("pickup") => &mut result.pickups,
+                Some("drop_off") => &mut result.drop_offs,
+                _ => continue,
+            };
+            notes.insert((TripId(trip_id), StopId(stop_id)), note);
+        }
+
+        Ok(result)
+    }
+
+    /// The bookings needed to board and alight a ride. Transfers never need to be booked.
+    pub fn for_leg(&self, leg: &Leg) -> Vec<&BookingNote> {
+        match leg {
+            Leg::Ride { trip, boarding_stop, alight_stop, .. } => [
+                self.pickups.get(&(*trip, *boarding_stop)),
+                self.drop_offs.get(&(*trip, *alight_stop)),
+            ].into_iter().flatten().collect(),
+            Leg::Transfer { .. } => vec![],
+        }
+    }
+
+    /// The bookings needed for a journey, together with the index of the leg they belong to
+    pub fn for_journey(&self, journey: &Journey) -> Vec<(usize, &BookingNote)> {
+        journey.legs()
+            .enumerate()
+            .flat_map(|(index, leg)| self.for_leg(leg).into_iter().map(move |note| (index, note)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    #[test]
+    fn test_booking_notes() {
+        let notes = df!(
+            "trip_id"                   => [0u32, 0],
+            "stop_id"                   => [1u32, 2],
+            "kind"                      => ["pickup", "drop_off"],
+            "booking_type"              => [1u32, 0],
+            "prior_notice_duration_min" => [Some(60u32), None],
+            "message"                   => [None, Some("Tell the driver where to stop")],
+            "phone_number"              => [Some("+49 711 123"), None],
+            "url"                       => [None::<&str>, None],
+        ).unwrap().lazy();
+        let notes = BookingNotes::from_frame(notes).unwrap();
+
+        let start = DateTime::<Utc>::UNIX_EPOCH;
+        let journey = Journey::from(vec![
+            Leg::Transfer { start: StopId(0), end: StopId(1), duration: TimeDelta::minutes(5) },
+            Leg::Ride {
+                trip: TripId(0),
+                boarding_stop: StopId(1),
+                alight_stop: StopId(2),
+                boarding_time: start,
+                alight_time: start + TimeDelta::minutes(20),
+            },
+        ]);
+
+        let texts: Vec<(usize, String)> = notes.for_journey(&journey).into_iter()
+            .map(|(index, note)| (index, note.text()))
+            .collect();
+        assert_eq!(texts, [
+            (1, "Call +49 711 123 to book at least 60 min ahead".to_owned()),
+            (1, "Tell the driver where to stop".to_owned()),
+        ]);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::algorithm::{PreprocessingInput, PreprocessingResult};
+use crate::artifacts;
+use crate::tp::day_types::{day_types, DayType};
+use chrono::NaiveDate;
+use common::types::config::Compression;
+use common::types::TripId;
+use hashbrown::{HashMap, HashSet};
+use itertools::izip;
+use polars::df;
+use polars::prelude::*;
+use std::path::Path;
+
+const SERVICES_TABLE: &str = "calendar_services";
+const TRIPS_TABLE: &str = "calendar_trips";
+
+/// The dates that the services of the timetable run on, and the service of every trip. Engines are
+/// built over the trips of all services, so a query for a date leaves out the trips that don't run
+/// on it. Exceptions of single dates (calendar_dates.txt) aren't imported, so the calendar only
+/// knows the weekdays and periods of the services.
+#[derive(Debug, Clone, Default)]
+pub struct ServiceCalendar {
+    // The services as imported from calendar.txt, which the day types are derived from
+    services: DataFrame,
+    day_types: Vec<DayType>,
+    trip_services: HashMap<TripId, u32>,
+}
+
+impl ServiceCalendar {
+    pub fn new(input: &PreprocessingInput) -> PreprocessingResult<Self> {
+        let trips = input.trips.clone().select([col("trip_id"), col("service_id")]).collect()?;
+        Self::from_frames(input.services.clone().collect()?, &trips)
+    }
+
+    fn from_frames(services: DataFrame, trips: &DataFrame) -> PreprocessingResult<Self> {
+        let trip_services = izip!(trips.column("trip_id")?.u32()?, trips.column("service_id")?.u32()?)
+            .filter_map(|(trip, service)| Some((TripId(trip?), service?)))
+            .collect();
+        Ok(Self { day_types: day_types(services.clone().lazy())?, services, trip_services })
+    }
+
+    /// Sorted ids of the services that run on the date, none outside of the calendar
+    pub fn services_on(&self, date: NaiveDate) -> &[u32] {
+        self.day_types.iter()
+            .find(|day_type| day_type.dates.binary_search(&date).is_ok())
+            .map_or(&[], |day_type| day_type.service_ids.as_slice())
+    }
+
+    /// The first date on which the most services run, none if no service runs at all
+    pub fn busiest_date(&self) -> Option<NaiveDate> {
+        self.day_types.iter()
+            .filter(|day_type| !day_type.dates.is_empty())
+            .max_by_key(|day_type| (day_type.service_ids.len(), std::cmp::Reverse(day_type.dates[0])))
+            .map(|day_type| day_type.dates[0])
+    }
+
+    /// Whether the date is between the first and the last date of the calendar, even if no service
+    /// runs on it
+    pub fn covers(&self, date: NaiveDate) -> bool {
+        let dates = self.day_types.iter().flat_map(|day_type| day_type.dates.first().zip(day_type.dates.last()));
+        dates.clone().any(|(first, _)| *first <= date) && dates.clone().any(|(_, last)| date <= *last)
+    }
+
+    /// Whether the trip runs on the date. Trips without a known service never run.
+    pub fn runs_on(&self, trip: TripId, date: NaiveDate) -> bool {
+        self.trip_services.get(&trip)
+            .is_some_and(|service| self.services_on(date).binary_search(service).is_ok())
+    }
+
+    /// The trips that run on the date
+    pub fn trips_running(&self, date: NaiveDate) -> impl Iterator<Item = TripId> + '_ {
+        let services = self.services_on(date);
+        self.trip_services.iter()
+            .filter(move |(_, service)| services.binary_search(service).is_ok())
+            .map(|(trip, _)| *trip)
+    }
+
+    /// The trips that don't run on the date, which a query for the date suspends
+    pub fn trips_not_running(&self, date: NaiveDate) -> HashSet<TripId> {
+        let services = self.services_on(date);
+        self.trip_services.iter()
+            .filter(|(_, service)| services.binary_search(service).is_err())
+            .map(|(trip, _)| *trip)
+            .collect()
+    }
+
+    /// Writes the services and the service of every trip to the artifacts in `dir`
+    pub fn save(&self, dir: &Path, compression: Compression) -> PreprocessingResult<()> {
+        artifacts::write_table(dir, SERVICES_TABLE, self.services.clone(), compression)?;
+        let (trips, services): (Vec<u32>, Vec<u32>) = self.trip_services.iter()
+            .map(|(trip, service)| (trip.0, *service))
+            .unzip();
+        artifacts::write_table(dir, TRIPS_TABLE, df!("trip_id" => trips, "service_id" => services)?, compression)?;
+        Ok(())
+    }
+
+    /// The calendar written by [ServiceCalendar::save]
+    pub fn load(dir: &Path) -> PreprocessingResult<Self> {
+        Self::from_frames(artifacts::read_table(dir, SERVICES_TABLE)?, &artifacts::read_table(dir, TRIPS_TABLE)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calendar() {
+        let date = |day| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+        // Service 0 runs on weekdays, service 1 on weekends, both in the first week of 2024
+        let services = df!(
+            "service_id" => [0u32, 1],
+            "monday"     => [true, false],
+            "tuesday"    => [true, false],
+            "wednesday"  => [true, false],
+            "thursday"   => [true, false],
+            "friday"     => [true, false],
+            "saturday"   => [false, true],
+            "sunday"     => [false, true],
+            "start_date" => [date(1); 2],
+            "end_date"   => [date(7); 2],
+        ).unwrap();
+        let trips = df!("trip_id" => [0u32, 1, 2], "service_id" => [0u32, 1, 0]).unwrap();
+        let calendar = ServiceCalendar::from_frames(services, &trips).unwrap();
+
+        // 2024-01-01 is a monday, 2024-01-06 a saturday
+        assert_eq!(calendar.services_on(date(1)), [0]);
+        assert!(calendar.runs_on(TripId(0), date(1)));
+        assert!(!calendar.runs_on(TripId(1), date(1)));
+        assert_eq!(calendar.trips_not_running(date(6)), HashSet::from([TripId(0), TripId(2)]));
+        // Nothing runs after the calendar ends
+        assert_eq!(calendar.trips_not_running(date(8)).len(), 3);
+        assert!(calendar.covers(date(1)) && calendar.covers(date(7)));
+        assert!(!calendar.covers(date(8)));
+        // Both day types have a single service, so the first date wins
+        assert_eq!(calendar.busiest_date(), Some(date(1)));
+    }
+}
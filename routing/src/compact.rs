@@ -0,0 +1,200 @@
+//! A single file with everything that [RaptorAlgorithm] needs to answer the queries of a day,
+//! for clients that can't read the tables of the artifacts. It is gzipped JSON, so it is read
+//! without polars, e.g. by this crate compiled to wasm32-unknown-unknown without its
+//! `preprocessing` feature for routing in a browser or offline on a phone.
+//!
+//! Walks are saved as the durations that the transfers of preprocessing computed, see
+//! [TableTransferProvider]. The stops come with their ids in the datasets, names and coordinates,
+//! so that clients can look up the stops of a query without the rest of the timetable.
+
+use crate::raptor::{LookupTables, RaptorAlgorithm, StopMapping, StopsByLineMap, Timetable, TripAtStopTimeMap, TripsByLineAndStopMap};
+use crate::transfers::table::TableTransferProvider;
+use chrono::{DateTime, Duration, Utc};
+use common::types::{LineId, StopId, TripId};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fmt::Display;
+use std::io::{Read, Write};
+
+/// Version of the layout of [CompactTimetable], files of other versions are rejected
+pub const FORMAT_VERSION: u32 = 2;
+
+// (line_id, stop_id, [(departure, trip_id)]) sorted by departure
+type TripsAtStopRow = (u32, u32, Vec<(i64, u32)>);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompactStop {
+    pub id: u32,
+    // The id in its dataset, prefixed by the id of the dataset
+    pub original_id: String,
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// The lookup tables of [RaptorAlgorithm] as plain rows. Times are milliseconds since the start of
+/// the service day, like the times of the algorithm. The lines at each stop follow from the stops
+/// of the lines, so they aren't in the file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompactTimetable {
+    pub version: u32,
+    pub stops: Vec<CompactStop>,
+    stop_mapping: Vec<u32>,
+    // (line_id, [(stop_id, visit_idx)]) in the order of the stops along the line
+    stops_by_line: Vec<(u32, Vec<(u32, u32)>)>,
+    // (trip_id, stop_id, visit_idx, time)
+    departures: Vec<(u32, u32, u32, i64)>,
+    arrivals: Vec<(u32, u32, u32, i64)>,
+    trips_by_line_and_stop: Vec<TripsAtStopRow>,
+    // (start, end, duration)
+    walks: Vec<(u32, u32, i64)>,
+}
+
+impl CompactTimetable {
+    /// The tables of `raptor` with the walks of its transfers between all of its stops
+    pub fn new(raptor: &RaptorAlgorithm, stops: Vec<CompactStop>) -> Self {
+        let walks = TableTransferProvider::tabulate(raptor.transfer_provider.as_ref(), raptor.stop_mapping.0.sources().iter().copied());
+        let tables = raptor.timetable.tables();
+        Self {
+            version: FORMAT_VERSION,
+            stops,
+            stop_mapping: raptor.stop_mapping.0.sources().iter().map(|stop| stop.0).collect(),
+            stops_by_line: tables.stops_by_line.iter()
+                .map(|(line, stops)| (line.0, stops.iter().map(|(stop, visit_idx)| (stop.0, *visit_idx)).collect()))
+                .collect(),
+            departures: times_to_rows(&tables.departures),
+            arrivals: times_to_rows(&tables.arrivals),
+            trips_by_line_and_stop: tables.trips_by_line_and_stop.iter()
+                .map(|((line, stop), trips)| {
+                    (line.0, stop.0, trips.iter().map(|(departure, trip)| (departure.timestamp_millis(), trip.0)).collect())
+                })
+                .collect(),
+            walks: walks.walks().map(|(start, end, duration)| (start.0, end.0, duration.num_milliseconds())).collect(),
+        }
+    }
+
+    pub fn read(reader: impl Read) -> Result<Self, CompactError> {
+        let timetable: Self = serde_json::from_reader(GzDecoder::new(reader))?;
+        if timetable.version != FORMAT_VERSION {
+            return Err(CompactError::IncompatibleVersion(timetable.version));
+        }
+        Ok(timetable)
+    }
+
+    pub fn write(&self, writer: impl Write) -> Result<(), CompactError> {
+        let mut encoder = GzEncoder::new(writer, flate2::Compression::default());
+        serde_json::to_writer(&mut encoder, self)?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+impl TryFrom<CompactTimetable> for RaptorAlgorithm {
+    type Error = CompactError;
+
+    fn try_from(timetable: CompactTimetable) -> Result<Self, Self::Error> {
+        let mut trips_by_line_and_stop = TripsByLineAndStopMap::new();
+        for (line, stop, trips) in timetable.trips_by_line_and_stop {
+            let trips = trips.into_iter()
+                .map(|(departure, trip)| Ok((timestamp(departure)?, TripId(trip))))
+                .collect::<Result<_, CompactError>>()?;
+            trips_by_line_and_stop.insert((LineId(line), StopId(stop)), trips);
+        }
+
+        let mut walks: HashMap<StopId, Vec<(StopId, Duration)>> = HashMap::new();
+        for (start, end, duration) in timetable.walks {
+            walks.entry(StopId(start)).or_default().push((StopId(end), Duration::milliseconds(duration)));
+        }
+
+        Ok(Self {
+            stop_mapping: StopMapping(timetable.stop_mapping.into_iter().map(StopId).collect()),
+            timetable: Timetable::from(LookupTables {
+                stops_by_line: timetable.stops_by_line.into_iter()
+                    .map(|(line, stops)| (LineId(line), stops.into_iter().map(|(stop, visit_idx)| (StopId(stop), visit_idx)).collect()))
+                    .collect::<StopsByLineMap>(),
+                departures: times_from_rows(timetable.departures)?,
+                arrivals: times_from_rows(timetable.arrivals)?,
+                trips_by_line_and_stop,
+            }),
+            transfer_provider: Box::new(TableTransferProvider::from_walks(walks)),
+        })
+    }
+}
+
+fn times_to_rows(times: &TripAtStopTimeMap) -> Vec<(u32, u32, u32, i64)> {
+    times.iter()
+        .map(|((trip, stop, visit_idx), time)| (trip.0, stop.0, *visit_idx, time.timestamp_millis()))
+        .collect()
+}
+
+fn times_from_rows(rows: Vec<(u32, u32, u32, i64)>) -> Result<TripAtStopTimeMap, CompactError> {
+    rows.into_iter()
+        .map(|(trip, stop, visit_idx, time)| Ok(((TripId(trip), StopId(stop), visit_idx), timestamp(time)?)))
+        .collect()
+}
+
+fn timestamp(millis: i64) -> Result<DateTime<Utc>, CompactError> {
+    DateTime::from_timestamp_millis(millis).ok_or(CompactError::InvalidTime(millis))
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CompactError {
+    IO(#[from] std::io::Error),
+    Json(#[from] serde_json::Error),
+    // The file is of another format version
+    IncompatibleVersion(u32),
+    InvalidTime(i64),
+}
+
+impl Display for CompactError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompactError::IO(err) => write!(f, "{}", err),
+            CompactError::Json(err) => write!(f, "{}", err),
+            CompactError::IncompatibleVersion(version) => write!(
+                f,
+                "Compact timetables of format version {} can't be loaded, export again to get version {}",
+                version, FORMAT_VERSION,
+            ),
+            CompactError::InvalidTime(millis) => write!(f, "Invalid time {}", millis),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::JourneyPlanner;
+    use crate::direct_connections::DirectConnections;
+
+    #[test]
+    fn test_write_and_read() {
+        let input = crate::tests::case_3::generate_preprocessing_input().unwrap();
+        let raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input).unwrap()).unwrap();
+        let stops = vec![CompactStop { id: 0, original_id: "d:0".to_string(), name: "A".to_string(), lat: 48.0, lon: 9.0 }];
+        let timetable = CompactTimetable::new(&raptor, stops);
+
+        let mut file = vec![];
+        timetable.write(&mut file).unwrap();
+        let read = CompactTimetable::read(file.as_slice()).unwrap();
+        assert_eq!(read, timetable);
+
+        let loaded = RaptorAlgorithm::try_from(read).unwrap();
+        assert_eq!(loaded.stop_mapping.0, raptor.stop_mapping.0);
+        assert_eq!(loaded.timetable, raptor.timetable);
+
+        for (from, to) in [(0, 3), (3, 0), (1, 2)] {
+            let [expected, actual] = [&raptor, &loaded]
+                .map(|raptor| raptor.query_ea(StopId(from), StopId(to), DateTime::<Utc>::UNIX_EPOCH).ok().map(|journey| journey.arrival()));
+            assert_eq!(expected, actual);
+        }
+
+        let outdated = CompactTimetable { version: FORMAT_VERSION + 1, ..timetable };
+        let mut file = vec![];
+        outdated.write(&mut file).unwrap();
+        assert!(matches!(CompactTimetable::read(file.as_slice()), Err(CompactError::IncompatibleVersion(_))));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::journey::{Journey, Leg};
+use chrono::TimeDelta;
+use common::types::mode::ModeRegistry;
+use common::types::{StopId, TripId};
+use geo::{Distance, Haversine, Point};
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::{col, DataType, LazyFrame};
+use std::collections::BTreeMap;
+
+// Rides of trips without a known mode are counted under this mode id
+pub const UNKNOWN_MODE: &str = "unknown";
+
+/// What a journey is made of, so that analytics and ranking experiments don't have to recompute
+/// it from the legs
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CostBreakdown {
+    // Time in vehicles by the id of their mode
+    pub in_vehicle: BTreeMap<String, TimeDelta>,
+    pub walk_time: TimeDelta,
+    // Straight-line distance of all walks in meters
+    pub walk_distance: f32,
+    // Time at stops between arriving and departing again. Walks at the start of a journey end
+    // right when the first vehicle departs, so there is no wait before it.
+    pub wait: TimeDelta,
+    pub transfers: u32,
+    // Time that rankings add on top of the travel time, by the name of the penalty. So far this is
+    // only the transfer slack of the modes changed to.
+    pub penalties: BTreeMap<String, TimeDelta>,
+}
+
+impl CostBreakdown {
+    pub fn total_in_vehicle(&self) -> TimeDelta {
+        self.in_vehicle.values().copied().sum()
+    }
+
+    pub fn total_penalty(&self) -> TimeDelta {
+        self.penalties.values().copied().sum()
+    }
+}
+
+/// Lookup of the modes of trips and the locations of stops, which the legs of journeys don't
+/// contain. Built from the `stops` and `trips` tables of the preprocessing input.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostInfo {
+    stops: HashMap<StopId, Point<f32>>,
+    // Mode id and transfer slack of every trip with a known mode
+    trips: HashMap<TripId, (String, TimeDelta)>,
+}
+
+impl CostInfo {
+    pub fn from_frames(stops: LazyFrame, trips: LazyFrame, modes: &ModeRegistry) -> Result<Self, PolarsError> {
+        let stops = stops
+            .select([col("stop_id"), col("lat"), col("lon")])
+            .collect()?;
+        let stops = izip!(
+            stops.column("stop_id")?.u32()?,
+            stops.column("lat")?.f32()?,
+            stops.column("lon")?.f32()?,
+        )
+            .filter_map(|(stop_id, lat, lon)| Some((StopId(stop_id?), Point::new(lon?, lat?))))
+            .collect();
+
+        // Trips of older preprocessing runs have no route type
+        if !trips.clone().collect_schema()?.contains("route_type") {
+            return Ok(Self { stops, trips: HashMap::new() });
+        }
+
+        let trips = trips
+            .select([col("trip_id"), col("route_type").cast(DataType::UInt32)])
+            .collect()?;
+        let trips = izip!(trips.column("trip_id")?.u32()?, trips.column("route_type")?.u32()?)
+            .filter_map(|(trip_id, route_type)| {
+                let mode = modes.mode_of(route_type?)?;
+                let slack = TimeDelta::minutes(mode.transfer_slack_minutes as i64);
+                Some((TripId(trip_id?), (mode.id.clone(), slack)))
+            })
+            .collect();
+
+        Ok(Self { stops, trips })
+    }
+
+    /// The transfer slack of every trip whose mode has one, which queries leave to board it, see
+    /// [crate::algorithm::QueryOptions::transfer_slack]
+    pub fn transfer_slack(&self) -> HashMap<TripId, TimeDelta> {
+        self.trips.iter()
+            .filter(|(_, (_, slack))| *slack > TimeDelta::zero())
+            .map(|(trip, (_, slack))| (*trip, *slack))
+            .collect()
+    }
+
+    pub fn breakdown(&self, journey: &Journey) -> CostBreakdown {
+        let mut breakdown = CostBreakdown::default();
+        let mut time = journey.departure();
+        let mut rides: u32 = 0;
+
+        for leg in journey.legs() {
+            match leg {
+                Leg::Ride { trip, boarding_time, alight_time, .. } => {
+                    let (mode, slack) = self.trips.get(trip)
+                        .map(|(mode, slack)| (mode.as_str(), *slack))
+                        .unwrap_or((UNKNOWN_MODE, TimeDelta::zero()));
+
+                    *breakdown.in_vehicle.entry(mode.to_string()).or_default() += *alight_time - *boarding_time;
+                    if let Some(time) = time {
+                        breakdown.wait += *boarding_time - time;
+                    }
+                    if rides > 0 && slack > TimeDelta::zero() {
+                        *breakdown.penalties.entry("transfer_slack".to_string()).or_default() += slack;
+                    }
+
+                    rides += 1;
+                    time = Some(*alight_time);
+                }
+                Leg::Transfer { start, end, duration } => {
+                    breakdown.walk_time += *duration;
+                    breakdown.walk_distance += match (self.stops.get(start), self.stops.get(end)) {
+                        (Some(start), Some(end)) => Haversine::distance(*start, *end),
+                        _ => 0.0,
+                    };
+                    time = time.map(|time| time + *duration);
+                }
+            }
+        }
+
+        breakdown.transfers = rides.saturating_sub(1);
+        breakdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use common::types::mode::Mode;
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    #[test]
+    fn test_breakdown() {
+        let stops = df!(
+            "stop_id" => [0u32, 1, 2, 3],
+            // Stops 1 and 2 are ~110m apart
+            "lat"     => [48.0f32, 48.1, 48.101, 48.2],
+            "lon"     => [9.0f32, 9.0, 9.0, 9.0],
+        ).unwrap().lazy();
+        // A tram and a train of a mode with two minutes of transfer slack
+        let trips = df!(
+            "trip_id"    => [0u32, 1],
+            "route_type" => [0u32, 2],
+        ).unwrap().lazy();
+        let modes = ModeRegistry::new(&[Mode {
+            id: "rail".into(),
+            name: "Train".into(),
+            icon: None,
+            category: None,
+            route_types: vec![2],
+            max_speed: 300.0,
+            transfer_slack_minutes: 2,
+        }]);
+        let info = CostInfo::from_frames(stops, trips, &modes).unwrap();
+
+        let at = |minute: i64| DateTime::<Utc>::UNIX_EPOCH + TimeDelta::minutes(minute);
+        let journey = Journey::from(vec![
+            Leg::Ride { trip: TripId(0), boarding_stop: StopId(0), alight_stop: StopId(1), boarding_time: at(0), alight_time: at(10) },
+            Leg::Transfer { start: StopId(1), end: StopId(2), duration: TimeDelta::minutes(2) },
+            Leg::Ride { trip: TripId(1), boarding_stop: StopId(2), alight_stop: StopId(3), boarding_time: at(15), alight_time: at(30) },
+        ]);
+
+        let breakdown = info.breakdown(&journey);
+        assert_eq!(breakdown.in_vehicle, BTreeMap::from([
+            ("tram".to_string(), TimeDelta::minutes(10)),
+            ("rail".to_string(), TimeDelta::minutes(15)),
+        ]));
+        assert_eq!(breakdown.total_in_vehicle(), TimeDelta::minutes(25));
+        assert_eq!(breakdown.walk_time, TimeDelta::minutes(2));
+        assert!((breakdown.walk_distance - 111.0).abs() < 5.0);
+        assert_eq!(breakdown.wait, TimeDelta::minutes(3));
+        assert_eq!(breakdown.transfers, 1);
+        assert_eq!(breakdown.total_penalty(), TimeDelta::minutes(2));
+        assert_eq!(info.transfer_slack(), HashMap::from([(TripId(1), TimeDelta::minutes(2))]));
+
+        // Trips without a mode
+        let breakdown = CostInfo::default().breakdown(&journey);
+        assert_eq!(breakdown.in_vehicle.keys().collect::<Vec<_>>(), [UNKNOWN_MODE]);
+        assert!(breakdown.penalties.is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use crate::algorithm::{FromDiskInit, PreprocessingError, PreprocessingResult, SaveToDisk};
+use crate::artifacts;
+use crate::csa::{Connection, ConnectionScanAlgorithm};
+use chrono::{DateTime, Utc};
+use common::types::config::Compression;
+use common::types::{StopId, TripId};
+use itertools::izip;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// The values of the algorithm besides its connections
+#[derive(Serialize, Deserialize)]
+struct Summary {
+    num_stops: usize,
+    num_trips: usize,
+}
+
+// The connections are written in their order, so they don't have to be sorted again when loading
+impl SaveToDisk for ConnectionScanAlgorithm {
+    fn save_to_disk(&self, dir: &Path, compression: Compression) -> PreprocessingResult<()> {
+        let dir = dir.join("csa");
+        let connections = df!(
+            "trip_id" => self.connections.iter().map(|connection| connection.trip.0).collect::<Vec<_>>(),
+            "from" => self.connections.iter().map(|connection| connection.from.0).collect::<Vec<_>>(),
+            "to" => self.connections.iter().map(|connection| connection.to.0).collect::<Vec<_>>(),
+            "departure" => self.connections.iter().map(|connection| connection.departure.timestamp_millis()).collect::<Vec<_>>(),
+            "arrival" => self.connections.iter().map(|connection| connection.arrival.timestamp_millis()).collect::<Vec<_>>(),
+        )?;
+        artifacts::write_table(&dir, "connections", connections, compression)?;
+        artifacts::write_json(&dir, "summary.json", &Summary { num_stops: self.num_stops, num_trips: self.num_trips })
+    }
+}
+
+impl FromDiskInit for ConnectionScanAlgorithm {
+    fn load_from_disk(dir: &Path) -> PreprocessingResult<Self> {
+        let transfer_provider = artifacts::read_transfers(dir)?;
+        let dir = dir.join("csa");
+        let Summary { num_stops, num_trips } = artifacts::read_json(&dir, "summary.json")?;
+
+        let table = artifacts::read_table(&dir, "connections")?;
+        let connections = izip!(
+            table.column("trip_id")?.u32()?.into_no_null_iter(),
+            table.column("from")?.u32()?.into_no_null_iter(),
+            table.column("to")?.u32()?.into_no_null_iter(),
+            table.column("departure")?.i64()?.into_no_null_iter(),
+            table.column("arrival")?.i64()?.into_no_null_iter(),
+        )
+            .map(|(trip, from, to, departure, arrival)| Ok(Connection {
+                trip: TripId(trip),
+                from: StopId(from),
+                to: StopId(to),
+                departure: timestamp(departure)?,
+                arrival: timestamp(arrival)?,
+            }))
+            .collect::<PreprocessingResult<Vec<_>>>()?;
+
+        Ok(Self { connections, num_stops, num_trips, transfer_provider })
+    }
+}
+
+fn timestamp(millis: i64) -> PreprocessingResult<DateTime<Utc>> {
+    DateTime::from_timestamp_millis(millis)
+        .ok_or_else(|| PreprocessingError::Polars(PolarsError::ComputeError(format!("Invalid time {millis}").into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::{FromDiskInit, JourneyPlanner, SaveToDisk};
+    use crate::artifacts;
+    use crate::csa::ConnectionScanAlgorithm;
+    use crate::direct_connections::DirectConnections;
+    use chrono::{DateTime, Utc};
+    use common::types::config::{Compression, TransferConfig};
+    use common::types::StopId;
+
+    #[test]
+    fn test_save_and_load() {
+        let input = crate::tests::case_3::generate_preprocessing_input().unwrap();
+        let csa = ConnectionScanAlgorithm::preprocess(input.clone(), &DirectConnections::try_from(input.clone()).unwrap()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        artifacts::write_stops(dir.path(), input.stops, input.transfers, &TransferConfig::default(), Compression::default()).unwrap();
+        csa.save_to_disk(dir.path(), Compression::default()).unwrap();
+
+        let loaded = ConnectionScanAlgorithm::load_from_disk(dir.path()).unwrap();
+        assert_eq!(loaded.connections, csa.connections);
+        assert_eq!((loaded.num_stops, loaded.num_trips), (csa.num_stops, csa.num_trips));
+
+        for (from, to) in [(0, 3), (1, 3), (3, 0)] {
+            let [expected, actual] = [&csa, &loaded]
+                .map(|csa| csa.query_ea(StopId(from), StopId(to), DateTime::<Utc>::UNIX_EPOCH).ok().map(|journey| journey.arrival()));
+            assert_eq!(expected, actual);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::algorithm::{PreprocessInit, PreprocessingInput, PreprocessingResult, RoutingAlgorithm, SaveToDisk};
+use crate::direct_connections::DirectConnections;
+use crate::transfers::TransferProvider;
+use chrono::{DateTime, Utc};
+use common::types::config::{RoutingConfig, TransferConfig};
+use common::types::{StopId, TripId};
+use common::util::logging::ProgressSink;
+use common::util::paths;
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::col;
+
+mod artifacts;
+mod routing;
+
+/// A ride of a trip from one stop to the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Connection {
+    pub(crate) trip: TripId,
+    pub(crate) from: StopId,
+    pub(crate) to: StopId,
+    pub(crate) departure: DateTime<Utc>,
+    pub(crate) arrival: DateTime<Utc>,
+}
+
+/// Connection Scan Algorithm (https://arxiv.org/abs/1703.05997). All rides between two
+/// consecutive stops of a trip are kept in a single array sorted by departure, which a query scans
+/// once from its departure on. Without lookup tables of lines, it is simpler than RAPTOR and
+/// often faster on small feeds, which also makes it an oracle for the results of other algorithms.
+pub struct ConnectionScanAlgorithm {
+    // Sorted by departure, then by arrival
+    pub(crate) connections: Vec<Connection>,
+    // Stop ids are dense, so stops are looked up by their id
+    pub(crate) num_stops: usize,
+    // Trip ids are dense as well, so the trips that were entered are kept by their id
+    pub(crate) num_trips: usize,
+    pub(crate) transfer_provider: Box<dyn TransferProvider + Send + Sync>,
+}
+
+impl RoutingAlgorithm for ConnectionScanAlgorithm {}
+
+impl PreprocessInit for ConnectionScanAlgorithm {
+    fn preprocess(
+        input: PreprocessingInput,
+        config: &RoutingConfig,
+        save_to_disk: bool,
+        progress: &dyn ProgressSink,
+    ) -> PreprocessingResult<Self> {
+        let task = progress.start("Sorting the connections of all trips", 2);
+        let direct_connections = DirectConnections::try_from(input.clone())?;
+        task.inc(1);
+        let algorithm = Self::preprocess_with_transfers(input, &direct_connections, &config.transfers)?;
+        task.inc(1);
+
+        if save_to_disk {
+            algorithm.save_to_disk(&paths::preprocessing_dir(), config.compression)?;
+        }
+        Ok(algorithm)
+    }
+}
+
+impl ConnectionScanAlgorithm {
+    /// Walks between stops by the defaults of [TransferConfig]
+    pub fn preprocess(input: PreprocessingInput, direct_connections: &DirectConnections) -> PreprocessingResult<Self> {
+        Self::preprocess_with_transfers(input, direct_connections, &TransferConfig::default())
+    }
+
+    pub fn preprocess_with_transfers(
+        input: PreprocessingInput,
+        direct_connections: &DirectConnections,
+        transfers: &TransferConfig,
+    ) -> PreprocessingResult<Self> {
+        let num_stops = input.stops.clone()
+            .select([col("stop_id").max()])
+            .collect()?
+            .column("stop_id")?.u32()?
+            .get(0)
+            .map_or(0, |max| max as usize + 1);
+
+        let connections = connections(direct_connections)?;
+        let num_trips = connections.iter().map(|connection| connection.trip.0 as usize + 1).max().unwrap_or(0);
+
+        Ok(Self {
+            connections,
+            num_stops,
+            num_trips,
+            transfer_provider: crate::transfers::walking(&input, transfers)?,
+        })
+    }
+}
+
+// The rides between consecutive stops of all trips. Stops without times (that are not timepoints)
+// are skipped, so that the trip rides from the stop before them to the stop after them.
+fn connections(direct_connections: &DirectConnections) -> Result<Vec<Connection>, PolarsError> {
+    let stop_times = direct_connections.stop_times()
+        .sort(["trip_id", "stop_sequence"], Default::default())
+        .collect()?;
+
+    let rows = izip!(
+        stop_times.column("trip_id")?.u32()?,
+        stop_times.column("stop_id")?.u32()?,
+        stop_times.column("arrival_time")?.i64()?,
+        stop_times.column("departure_time")?.i64()?,
+    );
+
+    let mut connections = vec![];
+    // Trip, stop and departure of the last stop with a departure
+    let mut previous: Option<(u32, u32, i64)> = None;
+    for (trip, stop, arrival, departure) in rows {
+        let (Some(trip), Some(stop)) = (trip, stop) else { continue };
+
+        if let (Some((previous_trip, from, departure)), Some(arrival)) = (previous, arrival) {
+            if previous_trip == trip {
+                if let (Some(departure), Some(arrival)) = (DateTime::from_timestamp_millis(departure), DateTime::from_timestamp_millis(arrival)) {
+                    connections.push(Connection { trip: TripId(trip), from: StopId(from), to: StopId(stop), departure, arrival });
+                }
+            }
+        }
+
+        if let Some(departure) = departure {
+            previous = Some((trip, stop, departure));
+        }
+    }
+
+    connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+    Ok(connections)
+}
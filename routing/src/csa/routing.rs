@@ -0,0 +1,299 @@
+use crate::algorithm::{AllEarliestArrival, EarliestArrival, EarliestArrivalOutput, MultiEarliestArrival, MultiQueryResult, Multiple, QueryError, QueryResult, Single, SingleEarliestArrival};
+use crate::csa::ConnectionScanAlgorithm;
+use crate::journey::{Journey, Leg};
+use crate::transfers::{TransferError, TransferProvider};
+use chrono::{DateTime, Utc};
+use common::types::StopId;
+use hashbrown::HashSet;
+
+// How a stop is reached
+#[derive(Debug, Clone, Copy)]
+enum Label {
+    // Riding a trip from the first to the last of these connections
+    Ride { enter: usize, exit: usize },
+    // Walking from the start or from a stop that was reached by a ride
+    Transfer { start: StopId },
+}
+
+struct CsaState {
+    // Earliest arrival at every stop, by a ride or a transfer
+    arrivals: Vec<DateTime<Utc>>,
+    labels: Vec<Option<Label>>,
+    // Earliest arrival at every stop by a ride, and the departure at the start. Like in RAPTOR,
+    // only these arrivals are continued by a transfer, so journeys never walk twice in a row.
+    ride_arrivals: Vec<DateTime<Utc>>,
+    ride_labels: Vec<Option<Label>>,
+    // The first connection of every trip that could be boarded, by trip id
+    entered_trips: Vec<Option<usize>>,
+}
+
+impl CsaState {
+    fn new(num_stops: usize, num_trips: usize, start: StopId, departure: DateTime<Utc>) -> Self {
+        let mut state = Self {
+            arrivals: vec![DateTime::<Utc>::MAX_UTC; num_stops],
+            labels: vec![None; num_stops],
+            ride_arrivals: vec![DateTime::<Utc>::MAX_UTC; num_stops],
+            ride_labels: vec![None; num_stops],
+            entered_trips: vec![None; num_trips],
+        };
+        state.arrivals[start.0 as usize] = departure;
+        state.ride_arrivals[start.0 as usize] = departure;
+        state
+    }
+}
+
+impl ConnectionScanAlgorithm {
+    fn validate(&self, stops: impl IntoIterator<Item = StopId>) -> QueryResult<()> {
+        match stops.into_iter().find(|stop| stop.0 as usize >= self.num_stops) {
+            Some(unknown) => Err(QueryError::StopNotFound(unknown)),
+            None => Ok(()),
+        }
+    }
+
+    // Scans the connections departing at or after the departure. Connections departing after the
+    // arrival at the target can't improve it, so the scan stops there if there is a target.
+    fn scan(&self, input: &EarliestArrival, target: Option<StopId>) -> QueryResult<CsaState> {
+        let EarliestArrival { start, earliest_departure: departure, suspended_trips: suspended, closed_stops: closed, .. } = input;
+        let cycling = input.transfers(self.transfer_provider.as_ref());
+        let transfer_provider = cycling.as_deref().unwrap_or(self.transfer_provider.as_ref());
+        let mut state = CsaState::new(self.num_stops, self.num_trips, *start, *departure);
+        let first = self.connections.partition_point(|connection| connection.departure < *departure);
+        // Walks from the start to the stops of the first ride
+        Self::walk(&mut state, transfer_provider, closed, *start, *departure);
+
+        for (idx, connection) in self.connections.iter().enumerate().skip(first) {
+            if target.is_some_and(|target| state.arrivals[target.0 as usize] <= connection.departure) {
+                break;
+            }
+            if suspended.contains(&connection.trip) {
+                continue;
+            }
+
+            let enter = match state.entered_trips[connection.trip.0 as usize] {
+                Some(enter) => enter,
+                None if input.can_board(connection.trip, connection.from, state.arrivals[connection.from.0 as usize], connection.departure)
+                    && !closed.contains(&connection.from) => {
+                    state.entered_trips[connection.trip.0 as usize] = Some(idx);
+                    idx
+                }
+                None => continue,
+            };
+            // The trip passes closed stops without anyone alighting
+            if closed.contains(&connection.to) {
+                continue;
+            }
+
+            let to = connection.to.0 as usize;
+            if connection.arrival >= state.ride_arrivals[to] {
+                continue;
+            }
+            let label = Label::Ride { enter, exit: idx };
+            state.ride_arrivals[to] = connection.arrival;
+            state.ride_labels[to] = Some(label);
+            if connection.arrival < state.arrivals[to] {
+                state.arrivals[to] = connection.arrival;
+                state.labels[to] = Some(label);
+            }
+
+            Self::walk(&mut state, transfer_provider, closed, connection.to, connection.arrival);
+        }
+
+        Ok(state)
+    }
+
+    // Walks from the stop, where the ride arrived (or the journey starts) at the time
+    fn walk(
+        state: &mut CsaState,
+        transfer_provider: &(dyn TransferProvider + Send + Sync),
+        closed: &HashSet<StopId>,
+        stop: StopId,
+        time: DateTime<Utc>,
+    ) {
+        for end in transfer_provider.transfers_from(&stop).into_iter().filter(|end| !closed.contains(end)) {
+            let duration = match transfer_provider.duration(stop, end) {
+                Ok(duration) => duration,
+                Err(TransferError::OutOfReach) => continue,
+                Err(TransferError::StopNotFound) => unreachable!("We only queried stops returned in provided transfer stops"),
+            };
+            let arrival = time + duration;
+            if arrival < state.arrivals[end.0 as usize] {
+                state.arrivals[end.0 as usize] = arrival;
+                state.labels[end.0 as usize] = Some(Label::Transfer { start: stop });
+            }
+        }
+    }
+
+    fn backtrace(&self, state: &CsaState, target: StopId) -> QueryResult<Journey> {
+        let mut legs = vec![];
+        let mut stop = target;
+        let mut label = state.labels[target.0 as usize];
+
+        while let Some(current) = label {
+            match current {
+                Label::Ride { enter, exit } => {
+                    let (enter, exit) = (&self.connections[enter], &self.connections[exit]);
+                    legs.push(Leg::Ride {
+                        trip: exit.trip,
+                        boarding_stop: enter.from,
+                        alight_stop: exit.to,
+                        boarding_time: enter.departure,
+                        alight_time: exit.arrival,
+                    });
+                    stop = enter.from;
+                    label = state.labels[stop.0 as usize];
+                }
+                Label::Transfer { start } => {
+                    let duration = state.arrivals[stop.0 as usize] - state.ride_arrivals[start.0 as usize];
+                    legs.push(Leg::Transfer { start, end: stop, duration });
+                    stop = start;
+                    label = state.ride_labels[stop.0 as usize];
+                }
+            }
+        }
+
+        if legs.is_empty() {
+            return Err(QueryError::NoRouteFound);
+        }
+        legs.reverse();
+        Ok(Journey::from(legs))
+    }
+}
+
+impl SingleEarliestArrival for ConnectionScanAlgorithm {
+    fn query_ea(&self, input: EarliestArrival, Single { target }: Single) -> QueryResult<EarliestArrivalOutput> {
+        self.validate([input.start, target])?;
+        let state = self.scan(&input, Some(target))?;
+        let journey = self.backtrace(&state, target)?;
+        if !input.permits(&journey) {
+            return Err(QueryError::NoRouteFound);
+        }
+        Ok(EarliestArrivalOutput { journey })
+    }
+}
+
+impl MultiEarliestArrival for ConnectionScanAlgorithm {
+    /// A single scan for all targets, targets that can't be reached are left out
+    fn query_ea_multi(&self, input: EarliestArrival, Multiple { targets }: Multiple) -> MultiQueryResult<EarliestArrivalOutput> {
+        self.validate([input.start].into_iter().chain(targets.iter().copied()))?;
+        let state = self.scan(&input, None)?;
+        let result = targets.iter()
+            .filter_map(|target| self.backtrace(&state, *target).ok())
+            .filter(|journey| input.permits(journey))
+            .map(|journey| EarliestArrivalOutput { journey })
+            .collect();
+        Ok(result)
+    }
+}
+
+impl AllEarliestArrival for ConnectionScanAlgorithm {
+    fn query_ea_all(&self, input: EarliestArrival) -> MultiQueryResult<EarliestArrivalOutput> {
+        self.validate([input.start])?;
+        let state = self.scan(&input, None)?;
+        let result = (0..self.num_stops as u32)
+            .map(StopId)
+            .filter_map(|stop| self.backtrace(&state, stop).ok())
+            .filter(|journey| input.permits(journey))
+            .map(|journey| EarliestArrivalOutput { journey })
+            .collect();
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::{JourneyPlanner, PreprocessInit, PreprocessingInput};
+    use crate::csa::Connection;
+    use crate::raptor::RaptorAlgorithm;
+    use crate::tests::{case_1, case_2, case_3};
+    use crate::transfers::fixed_time::FixedTimeTransferProvider;
+    use chrono::Duration;
+    use common::types::TripId;
+    use common::util::logging::NoProgress;
+    use itertools::Itertools;
+    use ndarray::array;
+
+    // CSA and RAPTOR both find the earliest arrival, although not always by the same journey
+    fn assert_same_arrivals(input: PreprocessingInput) {
+        let csa = <ConnectionScanAlgorithm as PreprocessInit>::preprocess(input.clone(), &Default::default(), false, &NoProgress).unwrap();
+        let raptor = <RaptorAlgorithm as PreprocessInit>::preprocess(input, &Default::default(), false, &NoProgress).unwrap();
+
+        let stops = (0..csa.num_stops as u32).map(StopId).collect_vec();
+        for (start, target) in stops.iter().cartesian_product(&stops).filter(|(start, target)| start != target) {
+            let csa_arrival = JourneyPlanner::query_ea(&csa, *start, *target, DateTime::UNIX_EPOCH).ok().and_then(|journey| journey.arrival());
+            let raptor_arrival = JourneyPlanner::query_ea(&raptor, *start, *target, DateTime::UNIX_EPOCH).ok().and_then(|journey| journey.arrival());
+            assert_eq!(csa_arrival, raptor_arrival, "Arrivals from {start:?} to {target:?} differ");
+        }
+    }
+
+    #[test]
+    fn test_same_arrivals_as_raptor() {
+        assert_same_arrivals(case_1::generate_preprocessing_input().unwrap());
+        assert_same_arrivals(case_2::generate_preprocessing_input().unwrap());
+        assert_same_arrivals(case_3::generate_preprocessing_input().unwrap());
+    }
+
+    #[test]
+    fn test_query_ea() {
+        // Trip 0 runs from stop 0 to 1 at 100s to 500s, trip 1 from stop 1 to 2 at 1000s to 1500s
+        let csa = <ConnectionScanAlgorithm as PreprocessInit>::preprocess(case_2::generate_preprocessing_input().unwrap(), &Default::default(), false, &NoProgress).unwrap();
+
+        let journey = JourneyPlanner::query_ea(&csa, StopId(0), StopId(2), DateTime::UNIX_EPOCH).unwrap();
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+        assert_eq!(journey.legs().filter(|leg| matches!(leg, Leg::Ride { .. })).count(), 2);
+
+        // Stop 0 can't be reached from stop 2, and stop 9 doesn't exist
+        assert!(matches!(JourneyPlanner::query_ea(&csa, StopId(2), StopId(0), DateTime::UNIX_EPOCH), Err(QueryError::NoRouteFound)));
+        assert!(matches!(JourneyPlanner::query_ea(&csa, StopId(0), StopId(9), DateTime::UNIX_EPOCH), Err(QueryError::StopNotFound(StopId(9)))));
+
+        // Without trip 0, stop 2 can't be reached either
+        let suspended = hashbrown::HashSet::from([TripId(0)]);
+        assert!(csa.query_ea_suspending(StopId(0), StopId(2), DateTime::UNIX_EPOCH, suspended).is_err());
+        // Neither if trip 0 can't be left at stop 1
+        let closed = EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH).closing([StopId(1)]);
+        assert!(SingleEarliestArrival::query_ea(&csa, closed, Single { target: StopId(2) }).is_err());
+
+        let all = csa.query_ea_all(EarliestArrival::new(StopId(1), DateTime::UNIX_EPOCH)).unwrap();
+        assert_eq!(all.len(), 1);
+    }
+
+    #[test]
+    fn test_walk_from_start() {
+        // Trip 0 runs from stop 1 to 2 at 100s to 500s, stop 0 is a walk of 10s away from stop 1 and
+        // a day away from stop 2
+        let csa = ConnectionScanAlgorithm {
+            connections: vec![Connection {
+                trip: TripId(0),
+                from: StopId(1),
+                to: StopId(2),
+                departure: DateTime::from_timestamp(100, 0).unwrap(),
+                arrival: DateTime::from_timestamp(500, 0).unwrap(),
+            }],
+            num_stops: 3,
+            num_trips: 1,
+            transfer_provider: Box::new(FixedTimeTransferProvider {
+                duration_matrix: array![
+                    [Duration::zero(),      Duration::seconds(10), Duration::days(1)],
+                    [Duration::seconds(10), Duration::zero(),      Duration::days(1)],
+                    [Duration::days(1),     Duration::days(1),     Duration::zero() ],
+                ]
+            }),
+        };
+
+        let journey = JourneyPlanner::query_ea(&csa, StopId(0), StopId(2), DateTime::UNIX_EPOCH).unwrap();
+        assert_eq!(journey, Journey::from(vec![
+            Leg::Transfer { start: StopId(0), end: StopId(1), duration: Duration::seconds(10) },
+            Leg::Ride {
+                trip: TripId(0),
+                boarding_stop: StopId(1),
+                alight_stop: StopId(2),
+                boarding_time: DateTime::from_timestamp(100, 0).unwrap(),
+                alight_time: DateTime::from_timestamp(500, 0).unwrap(),
+            },
+        ]));
+
+        // Leaving 91s later, the walk to stop 1 arrives after the trip departed
+        let late = JourneyPlanner::query_ea(&csa, StopId(0), StopId(2), DateTime::from_timestamp(91, 0).unwrap()).unwrap();
+        assert_eq!(late.legs().filter(|leg| matches!(leg, Leg::Ride { .. })).count(), 0);
+    }
+}
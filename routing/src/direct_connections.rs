@@ -1,13 +1,18 @@
 use geoarrow::table::Table;
-use itertools::Itertools;
+use itertools::{izip, Itertools};
 use polars::frame::DataFrame;
 use polars::prelude::*;
 use polars::series::IntoSeries;
 
 use crate::algorithm::{PreprocessingError, PreprocessingInput};
-use common::types::StopId;
+use crate::artifacts;
+use crate::journey::Leg;
+use chrono::{DateTime, Utc};
+use common::types::config::Compression;
+use common::types::{LineId, StopId, TripId};
 use common::util::df;
 use common::util::geoarrow_lines::build_geoarrow_lines;
+use std::path::Path;
 
 /// In the transfer patterns paper, lines are represented like this:
 ///
@@ -181,27 +186,101 @@ impl DirectConnections {
         Ok(earliest)
     }
 
-    pub fn to_geoarrow_lines(
+    // Stop times of all trips with the times in milliseconds
+    pub(crate) fn stop_times(&self) -> LazyFrame {
+        self.expanded_lines.clone().lazy()
+            .select([
+                col("line_id"),
+                col("trip_id"),
+                col("stop_id"),
+                col("stop_sequence"),
+                col("arrival_time").cast(DataType::Int64),
+                col("departure_time").cast(DataType::Int64),
+            ])
+    }
+
+    /// The [DirectConnections::stop_times] at a single stop
+    pub(crate) fn stop_times_at(&self, stop: StopId) -> LazyFrame {
+        self.stop_times().filter(col("stop_id").eq(lit(stop.0)))
+    }
+
+    /// Rides from one stop to the other that depart at or after the earliest departure, the
+    /// earliest arrival first
+    pub(crate) fn rides(
         &self,
-        stops_df: LazyFrame,
-    ) -> Result<Table, common::util::geoarrow_lines::Error> {
+        from: StopId,
+        to: StopId,
+        earliest_departure: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Leg>, PolarsError> {
+        Self::rides_between(self.stop_times_at(from), self.stop_times_at(to), from, to, earliest_departure, limit)
+    }
+
+    /// Like [DirectConnections::rides], with the stop times at both stops already at hand
+    pub(crate) fn rides_between(
+        stop_times_at_from: LazyFrame,
+        stop_times_at_to: LazyFrame,
+        from: StopId,
+        to: StopId,
+        earliest_departure: DateTime<Utc>,
+        limit: usize,
+    ) -> Result<Vec<Leg>, PolarsError> {
+        let boardings = stop_times_at_from
+            .filter(col("departure_time").gt_eq(lit(earliest_departure.timestamp_millis())))
+            .select([col("trip_id"), col("stop_sequence").alias("boarding_sequence"), col("departure_time").alias("boarding_time")]);
+        let alightings = stop_times_at_to
+            .select([col("trip_id"), col("stop_sequence").alias("alight_sequence"), col("arrival_time").alias("alight_time")]);
+
+        // Trips that serve a stop more than once are boarded as late as possible
+        let rides = boardings
+            .join(alightings, [col("trip_id")], [col("trip_id")], JoinArgs::new(JoinType::Inner))
+            .filter(col("boarding_sequence").lt(col("alight_sequence")))
+            .sort(["alight_time", "boarding_time"], SortMultipleOptions::default().with_order_descending_multi([false, true]))
+            .unique_stable(Some(vec!["trip_id".into()]), UniqueKeepStrategy::First)
+            .limit(limit as IdxSize)
+            .collect()?;
+
+        let rides = izip!(
+            rides.column("trip_id")?.u32()?,
+            rides.column("boarding_time")?.i64()?,
+            rides.column("alight_time")?.i64()?,
+        )
+            .filter_map(|(trip, boarding_time, alight_time)| Some(Leg::Ride {
+                trip: TripId(trip?),
+                boarding_stop: from,
+                alight_stop: to,
+                boarding_time: DateTime::from_timestamp_millis(boarding_time?)?,
+                alight_time: DateTime::from_timestamp_millis(alight_time?)?,
+            }))
+            .collect();
+
+        Ok(rides)
+    }
+
+    /// The stops of every line in the order they are passed
+    pub fn stop_chains(&self) -> Result<Vec<(LineId, Vec<StopId>)>, PolarsError> {
         let stop_chains = self.line_progressions.clone().lazy()
             .sort(["stop_sequence"], SortMultipleOptions::default())
             .group_by([col("line_id")])
             .agg([col("stop_id")])
-            .select([col("stop_id")])
+            .sort(["line_id"], SortMultipleOptions::default())
             .collect()?;
 
-        let [stop_chains] = stop_chains.get_columns()
-        else { unreachable!("we only selected a single column") };
-
-        let stop_chains: Vec<Vec<StopId>> = stop_chains.list()?.into_iter()
-            .map(|l| {
-                let l = l.unwrap();
-                let l = l.u32().unwrap();
-                l.into_iter().map(|id| StopId::from(id.unwrap())).collect_vec()
+        let stop_chains = stop_chains.column("line_id")?.u32()?.iter()
+            .zip(stop_chains.column("stop_id")?.list()?)
+            .filter_map(|(line, stops)| {
+                let stops = stops?.u32().ok()?.into_iter().flatten().map(StopId::from).collect_vec();
+                Some((LineId(line?), stops))
             })
             .collect_vec();
+        Ok(stop_chains)
+    }
+
+    pub fn to_geoarrow_lines(
+        &self,
+        stops_df: LazyFrame,
+    ) -> Result<Table, common::util::geoarrow_lines::Error> {
+        let stop_chains = self.stop_chains()?.into_iter().map(|(_, stops)| stops).collect_vec();
 
         let table = build_geoarrow_lines(
             stop_chains,
@@ -210,14 +289,44 @@ impl DirectConnections {
 
         Ok(table)
     }
+
+    /// Writes the tables to the subdirectory "direct_connections" of `dir`
+    pub fn save(&self, dir: &Path, compression: Compression) -> PolarsResult<()> {
+        let dir = dir.join("direct_connections");
+        artifacts::write_table(&dir, "expanded_lines", self.expanded_lines.clone(), compression)?;
+        artifacts::write_table(&dir, "line_progressions", self.line_progressions.clone(), compression)?;
+        artifacts::write_table(&dir, "stop_incidence", self.stop_incidence.clone(), compression)
+    }
+
+    /// The tables written by [DirectConnections::save]
+    pub fn load(dir: &Path) -> PolarsResult<Self> {
+        let dir = dir.join("direct_connections");
+        Ok(Self {
+            expanded_lines: artifacts::read_table(&dir, "expanded_lines")?,
+            line_progressions: artifacts::read_table(&dir, "line_progressions")?,
+            stop_incidence: artifacts::read_table(&dir, "stop_incidence")?,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::tests::case_1::generate_preprocessing_input;
+    use crate::tests::duration;
     use polars::datatypes::AnyValue::List;
 
+    #[test]
+    fn test_save_and_load() {
+        let direct_connections = DirectConnections::try_from(generate_preprocessing_input().unwrap()).unwrap();
+        // Without compression, the tables are memory-mapped
+        for compression in [Compression::default(), Compression::None] {
+            let dir = tempfile::tempdir().unwrap();
+            direct_connections.save(dir.path(), compression).unwrap();
+            assert_eq!(DirectConnections::load(dir.path()).unwrap(), direct_connections);
+        }
+    }
+
     #[test]
     fn test_case_1() {
         let input = generate_preprocessing_input().unwrap();
@@ -232,8 +341,8 @@ mod tests {
                 "line_id" => [0u32, 0],
                 "trip_id" => [0u32, 0],
                 "stop_id" => [0u32, 1],
-                "arrival" => [AnyValue::Duration(100, TimeUnit::Milliseconds), AnyValue::Duration(500, TimeUnit::Milliseconds)],
-                "departure" => [AnyValue::Duration(100, TimeUnit::Milliseconds), AnyValue::Duration(500, TimeUnit::Milliseconds)],
+                "arrival_time" => [duration(100), duration(500)],
+                "departure_time" => [duration(100), duration(500)],
                 "stop_sequence" => [0u32, 1],
             ].unwrap(),
             stop_incidence: df![
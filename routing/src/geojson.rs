@@ -0,0 +1,244 @@
+//! GeoJSON of journeys and of the network, e.g. to show them on a map or load them into a GIS.
+//! Every leg of a journey is a feature with the line from where it starts to where it ends, over
+//! the stops that rides pass. The network has a point for every stop and a line for every line of
+//! the timetable along its stops.
+//!
+//! Times are in the local time of the day of the query, like the times of `drino query`.
+
+use crate::algorithm::AccessMode;
+use crate::direct_connections::DirectConnections;
+use crate::itinerary::{Itinerary, ItineraryLeg};
+use crate::journey::{Journey, Leg};
+use crate::park_and_ride::AccessLeg;
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use common::types::id_interner::OriginalIds;
+use common::types::{StopId, TripId};
+use ::geojson::{Feature, FeatureCollection, Geometry, JsonObject, JsonValue, Value};
+use polars::error::PolarsError;
+
+/// Latitude and longitude of a stop, either of which may be missing in the dataset
+pub type StopCoordinates = (Option<f32>, Option<f32>);
+
+/// Media type of GeoJSON, which clients of the server accept to get it instead of plain JSON
+pub const MEDIA_TYPE: &str = "application/geo+json";
+
+/// A leg of a journey as a feature. Rides pass their intermediate stops if they are known, see
+/// [Itinerary], and go straight from boarding to alighting otherwise.
+#[derive(Debug, Clone)]
+pub enum GeoLeg {
+    Ride {
+        trip: TripId,
+        from: StopId,
+        to: StopId,
+        departure: DateTime<Utc>,
+        arrival: DateTime<Utc>,
+        intermediate_stops: Vec<StopId>,
+    },
+    // Walks between rides, and the drives or rides to and from P+R stops
+    Transfer { mode: AccessMode, from: StopId, to: StopId, duration: Duration },
+}
+
+impl From<&Leg> for GeoLeg {
+    fn from(leg: &Leg) -> Self {
+        match leg {
+            Leg::Ride { trip, boarding_stop, alight_stop, boarding_time, alight_time } => GeoLeg::Ride {
+                trip: *trip,
+                from: *boarding_stop,
+                to: *alight_stop,
+                departure: *boarding_time,
+                arrival: *alight_time,
+                intermediate_stops: vec![],
+            },
+            Leg::Transfer { start, end, duration } => GeoLeg::Transfer { mode: AccessMode::Walk, from: *start, to: *end, duration: *duration },
+        }
+    }
+}
+
+impl From<&ItineraryLeg> for GeoLeg {
+    fn from(leg: &ItineraryLeg) -> Self {
+        match leg {
+            ItineraryLeg::Ride { trip, boarding_stop, alight_stop, departure, arrival, intermediate_stops, .. } => GeoLeg::Ride {
+                trip: *trip,
+                from: *boarding_stop,
+                to: *alight_stop,
+                departure: *departure,
+                arrival: *arrival,
+                intermediate_stops: intermediate_stops.iter().map(|call| call.stop).collect(),
+            },
+            ItineraryLeg::Transfer { start, end, duration } => GeoLeg::Transfer { mode: AccessMode::Walk, from: *start, to: *end, duration: *duration },
+        }
+    }
+}
+
+impl From<&AccessLeg> for GeoLeg {
+    fn from(leg: &AccessLeg) -> Self {
+        GeoLeg::Transfer { mode: leg.mode, from: leg.start, to: leg.end, duration: leg.duration }
+    }
+}
+
+/// What features tell about stops and trips, which the routing data doesn't have
+pub struct GeoFeatures<'a> {
+    original_ids: &'a OriginalIds,
+    names: &'a [String],
+    // (lat, lon) by the id of the stop
+    coordinates: &'a [StopCoordinates],
+}
+
+impl<'a> GeoFeatures<'a> {
+    /// The names and coordinates (lat, lon) are those of the stops by their id, like the rows of
+    /// the simplified stops
+    pub fn new(original_ids: &'a OriginalIds, names: &'a [String], coordinates: &'a [StopCoordinates]) -> Self {
+        Self { original_ids, names, coordinates }
+    }
+
+    /// The legs of a journey on `day`, with its departure and arrival as members of the collection
+    pub fn journey(&self, journey: &Journey, day: NaiveDate) -> FeatureCollection {
+        let mut collection = self.legs(journey.legs().map(GeoLeg::from), day);
+        collection.foreign_members = Some(self.journey_members(journey.departure(), journey.arrival(), day));
+        collection
+    }
+
+    /// The legs of an itinerary, whose rides pass their intermediate stops
+    pub fn itinerary(&self, itinerary: &Itinerary, day: NaiveDate) -> FeatureCollection {
+        self.legs(itinerary.legs.iter().map(GeoLeg::from), day)
+    }
+
+    /// A feature per leg in the order they are travelled, with the number of the leg
+    pub fn legs(&self, legs: impl IntoIterator<Item = GeoLeg>, day: NaiveDate) -> FeatureCollection {
+        let features = legs.into_iter().enumerate()
+            .map(|(idx, leg)| self.leg(idx, &leg, day))
+            .collect();
+        FeatureCollection { bbox: None, features, foreign_members: None }
+    }
+
+    /// Every stop as a point and every line of the direct connections along its stops
+    pub fn network(&self, direct_connections: &DirectConnections) -> Result<FeatureCollection, PolarsError> {
+        let stops = (0..self.coordinates.len() as u32)
+            .filter_map(|stop| {
+                let stop = StopId(stop);
+                let geometry = Geometry::new(Value::Point(self.position(stop)?));
+                Some(feature(geometry, self.stop_properties(stop)))
+            });
+        let lines = direct_connections.stop_chains()?.into_iter()
+            .filter_map(|(line, stops)| {
+                let mut properties = JsonObject::new();
+                properties.insert("type".into(), "line".into());
+                properties.insert("line_id".into(), line.0.into());
+                properties.insert("stops".into(), stops.len().into());
+                Some(feature(self.line_string(stops)?, properties))
+            });
+        Ok(FeatureCollection { bbox: None, features: stops.chain(lines).collect(), foreign_members: None })
+    }
+
+    /// Departure and arrival of a journey in local time, for the members of its collection
+    pub fn journey_members(&self, departure: Option<DateTime<Utc>>, arrival: Option<DateTime<Utc>>, day: NaiveDate) -> JsonObject {
+        let mut members = JsonObject::new();
+        members.insert("departure".into(), departure.map(|time| local_time(time, day)).into());
+        members.insert("arrival".into(), arrival.map(|time| local_time(time, day)).into());
+        members
+    }
+
+    fn leg(&self, idx: usize, leg: &GeoLeg, day: NaiveDate) -> Feature {
+        let mut properties = JsonObject::new();
+        properties.insert("leg".into(), idx.into());
+        let (from, to, stops) = match leg {
+            GeoLeg::Ride { trip, from, to, departure, arrival, intermediate_stops } => {
+                properties.insert("type".into(), "ride".into());
+                properties.insert("trip_id".into(), self.original_ids.trip(*trip).into());
+                properties.insert("departure".into(), local_time(*departure, day).into());
+                properties.insert("arrival".into(), local_time(*arrival, day).into());
+                properties.insert("duration_seconds".into(), (*arrival - *departure).num_seconds().into());
+                let stops = [*from].into_iter().chain(intermediate_stops.iter().copied()).chain([*to]).collect();
+                (from, to, stops)
+            }
+            GeoLeg::Transfer { mode, from, to, duration } => {
+                properties.insert("type".into(), mode.to_string().into());
+                properties.insert("duration_seconds".into(), duration.num_seconds().into());
+                (from, to, vec![*from, *to])
+            }
+        };
+        properties.insert("from_stop_id".into(), self.original_ids.stop(*from).into());
+        properties.insert("from_name".into(), self.name(*from));
+        properties.insert("to_stop_id".into(), self.original_ids.stop(*to).into());
+        properties.insert("to_name".into(), self.name(*to));
+
+        Feature {
+            bbox: None,
+            geometry: self.line_string(stops),
+            id: None,
+            properties: Some(properties),
+            foreign_members: None,
+        }
+    }
+
+    fn stop_properties(&self, stop: StopId) -> JsonObject {
+        let mut properties = JsonObject::new();
+        properties.insert("type".into(), "stop".into());
+        properties.insert("stop_id".into(), self.original_ids.stop(stop).into());
+        properties.insert("name".into(), self.name(stop));
+        properties
+    }
+
+    // Stops without coordinates are left out, lines with less than two positions have none
+    fn line_string(&self, stops: Vec<StopId>) -> Option<Geometry> {
+        let positions: Vec<Vec<f64>> = stops.into_iter().filter_map(|stop| self.position(stop)).collect();
+        (positions.len() >= 2).then(|| Geometry::new(Value::LineString(positions)))
+    }
+
+    // GeoJSON positions are [lon, lat]
+    fn position(&self, stop: StopId) -> Option<Vec<f64>> {
+        let (lat, lon) = self.coordinates.get(stop.0 as usize)?;
+        Some(vec![(*lon)? as f64, (*lat)? as f64])
+    }
+
+    fn name(&self, stop: StopId) -> JsonValue {
+        self.names.get(stop.0 as usize).map(String::as_str).into()
+    }
+}
+
+fn feature(geometry: Geometry, properties: JsonObject) -> Feature {
+    Feature { bbox: None, geometry: Some(geometry), id: None, properties: Some(properties), foreign_members: None }
+}
+
+// Times of the timetable are relative to the start of the service day, e.g. 2024-05-01T08:03:00
+fn local_time(time: DateTime<Utc>, day: NaiveDate) -> String {
+    (day.and_time(NaiveTime::MIN) + (time - DateTime::<Utc>::UNIX_EPOCH)).format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::id_interner::IdInterner;
+
+    #[test]
+    fn test_legs() {
+        let original_ids = OriginalIds {
+            stops: IdInterner::from_originals(["d:a", "d:b", "d:c"]),
+            trips: IdInterner::from_originals(["d:1"]),
+            stable_trips: IdInterner::default(),
+        };
+        let names = ["A".to_string(), "B".to_string(), "C".to_string()];
+        // C has no coordinates
+        let features = GeoFeatures::new(&original_ids, &names, &[(Some(48.0), Some(9.0)), (Some(48.5), Some(9.5)), (None, None)]);
+        let at = |minutes: i64| DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minutes);
+        let legs = [
+            GeoLeg::Ride { trip: TripId(0), from: StopId(0), to: StopId(1), departure: at(480), arrival: at(490), intermediate_stops: vec![] },
+            GeoLeg::Transfer { mode: AccessMode::Walk, from: StopId(1), to: StopId(2), duration: Duration::minutes(3) },
+        ];
+
+        let collection = features.legs(legs, NaiveDate::from_ymd_opt(2024, 5, 1).unwrap());
+        assert_eq!(collection.features.len(), 2);
+        let ride = &collection.features[0];
+        assert_eq!(ride.geometry, Some(Geometry::new(Value::LineString(vec![vec![9.0, 48.0], vec![9.5, 48.5]]))));
+        assert_eq!(ride.property("type"), Some(&"ride".into()));
+        assert_eq!(ride.property("trip_id"), Some(&"d:1".into()));
+        assert_eq!(ride.property("departure"), Some(&"2024-05-01T08:00:00".into()));
+        assert_eq!(ride.property("from_name"), Some(&"A".into()));
+
+        let walk = &collection.features[1];
+        assert_eq!(walk.geometry, None);
+        assert_eq!(walk.property("type"), Some(&"walk".into()));
+        assert_eq!(walk.property("duration_seconds"), Some(&180.into()));
+        assert_eq!(walk.property("to_stop_id"), Some(&"d:c".into()));
+    }
+}
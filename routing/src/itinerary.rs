@@ -0,0 +1,194 @@
+use crate::algorithm::{QueryError, QueryResult};
+use crate::direct_connections::DirectConnections;
+use crate::journey::{Journey, Leg};
+use chrono::{DateTime, Duration, Utc};
+use common::types::{LineId, StopId, TripId};
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::prelude::*;
+
+/// A stop that a ride passes between boarding and alighting. Times may be missing for stops that
+/// are not timepoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StopCall {
+    pub stop: StopId,
+    pub arrival: Option<DateTime<Utc>>,
+    pub departure: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ItineraryLeg {
+    Ride {
+        trip: TripId,
+        line: LineId,
+        boarding_stop: StopId,
+        alight_stop: StopId,
+        departure: DateTime<Utc>,
+        arrival: DateTime<Utc>,
+        // In the order they are passed, without the boarding and alight stop
+        intermediate_stops: Vec<StopCall>,
+    },
+    Transfer { start: StopId, end: StopId, duration: Duration },
+}
+
+/// A journey with everything clients show about its legs. Routing algorithms only keep the
+/// boarding and alight stop of rides, the rest is looked up in the timetable afterwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Itinerary {
+    pub legs: Vec<ItineraryLeg>,
+}
+
+// A stop of a trip in the order of the trip
+pub(crate) struct TripStop {
+    pub(crate) line: LineId,
+    // The stop_sequence of the stop time
+    pub(crate) sequence: u32,
+    pub(crate) call: StopCall,
+}
+
+impl Itinerary {
+    /// Adds the lines and intermediate stops of the rides of the journey. Fails with
+    /// [QueryError::NoRouteFound] if a ride is not part of the timetable of the direct connections.
+    pub fn reconstruct(journey: &Journey, direct_connections: &DirectConnections) -> QueryResult<Itinerary> {
+        let trips_by_id = trip_stops(journey, direct_connections)?;
+
+        let legs = journey.legs()
+            .map(|leg| match leg {
+                Leg::Ride { trip, boarding_stop, alight_stop, boarding_time, alight_time } => {
+                    let stops = trips_by_id.get(trip).ok_or(QueryError::NoRouteFound)?;
+
+                    // Trips that visit a stop more than once are boarded at the visit that departs
+                    // at the time of the ride
+                    let boarding = stops.iter()
+                        .position(|stop| stop.call.stop == *boarding_stop && stop.call.departure == Some(*boarding_time))
+                        .or_else(|| stops.iter().position(|stop| stop.call.stop == *boarding_stop))
+                        .ok_or(QueryError::NoRouteFound)?;
+                    let alight = stops.iter().skip(boarding + 1)
+                        .position(|stop| stop.call.stop == *alight_stop)
+                        .map(|offset| boarding + 1 + offset)
+                        .ok_or(QueryError::NoRouteFound)?;
+
+                    Ok(ItineraryLeg::Ride {
+                        trip: *trip,
+                        line: stops[boarding].line,
+                        boarding_stop: *boarding_stop,
+                        alight_stop: *alight_stop,
+                        departure: *boarding_time,
+                        arrival: *alight_time,
+                        intermediate_stops: stops[boarding + 1..alight].iter().map(|stop| stop.call.clone()).collect(),
+                    })
+                }
+                Leg::Transfer { start, end, duration } => Ok(ItineraryLeg::Transfer {
+                    start: *start,
+                    end: *end,
+                    duration: *duration,
+                }),
+            })
+            .collect::<QueryResult<Vec<_>>>()?;
+
+        Ok(Itinerary { legs })
+    }
+}
+
+// The stops of all trips the journey rides on, sorted by their sequence
+pub(crate) fn trip_stops(journey: &Journey, direct_connections: &DirectConnections) -> QueryResult<HashMap<TripId, Vec<TripStop>>> {
+    let trips = journey.legs().filter_map(|leg| match leg {
+        Leg::Ride { trip, .. } => Some(*trip),
+        Leg::Transfer { .. } => None,
+    });
+    stops_of_trips(trips, direct_connections)
+}
+
+// The stops of the trips in their order
+pub(crate) fn stops_of_trips(trips: impl IntoIterator<Item = TripId>, direct_connections: &DirectConnections) -> QueryResult<HashMap<TripId, Vec<TripStop>>> {
+    let trip_ids: Vec<u32> = trips.into_iter().map(|trip| trip.0).collect();
+    let trip_ids = DataFrame::new(vec![
+        Column::new("trip_id".into(), trip_ids),
+    ]).expect("A single column is always a valid frame").lazy();
+
+    let stop_times = direct_connections.stop_times()
+        .semi_join(trip_ids, col("trip_id"), col("trip_id"))
+        .sort(["trip_id", "stop_sequence"], Default::default())
+        .collect()?;
+
+    let mut trips_by_id: HashMap<TripId, Vec<TripStop>> = HashMap::new();
+    let rows = izip!(
+        stop_times.column("trip_id")?.u32()?,
+        stop_times.column("line_id")?.u32()?,
+        stop_times.column("stop_id")?.u32()?,
+        stop_times.column("stop_sequence")?.u32()?,
+        stop_times.column("arrival_time")?.i64()?,
+        stop_times.column("departure_time")?.i64()?,
+    );
+    for (trip, line, stop, sequence, arrival, departure) in rows {
+        let (Some(trip), Some(line), Some(stop)) = (trip, line, stop) else { continue };
+
+        trips_by_id.entry(TripId(trip)).or_default().push(TripStop {
+            line: LineId(line),
+            sequence: sequence.unwrap_or_default(),
+            call: StopCall {
+                stop: StopId(stop),
+                arrival: arrival.and_then(DateTime::from_timestamp_millis),
+                departure: departure.and_then(DateTime::from_timestamp_millis),
+            },
+        });
+    }
+
+    Ok(trips_by_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::PreprocessingInput;
+    use polars::df;
+
+    #[test]
+    fn test_reconstruct() {
+        // A single trip from stop 0 over 1 to 2 instead of the two trips of case 2
+        let minutes = |minutes: [i64; 3]| Series::new("".into(), minutes.map(|minute| minute * 60_000))
+            .cast(&DataType::Duration(TimeUnit::Milliseconds))
+            .unwrap();
+        let input = PreprocessingInput {
+            stop_times: df![
+                "trip_id" => [0u32, 0, 0],
+                "stop_id" => [0u32, 1, 2],
+                "arrival_time" => minutes([0, 10, 20]),
+                "departure_time" => minutes([0, 11, 20]),
+                "stop_sequence" => [0u32, 1, 2],
+            ].unwrap().lazy(),
+            ..crate::tests::case_2::generate_preprocessing_input().unwrap()
+        };
+        let direct_connections = DirectConnections::try_from(input).unwrap();
+
+        let at = |minute: i64| DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minute);
+        let journey = Journey::from(vec![Leg::Ride {
+            trip: TripId(0),
+            boarding_stop: StopId(0),
+            alight_stop: StopId(2),
+            boarding_time: at(0),
+            alight_time: at(20),
+        }]);
+
+        let itinerary = Itinerary::reconstruct(&journey, &direct_connections).unwrap();
+        assert_eq!(itinerary.legs, vec![ItineraryLeg::Ride {
+            trip: TripId(0),
+            line: LineId(0),
+            boarding_stop: StopId(0),
+            alight_stop: StopId(2),
+            departure: at(0),
+            arrival: at(20),
+            intermediate_stops: vec![StopCall { stop: StopId(1), arrival: Some(at(10)), departure: Some(at(11)) }],
+        }]);
+
+        // Trip 1 is not in the timetable
+        let unknown = Journey::from(vec![Leg::Ride {
+            trip: TripId(1),
+            boarding_stop: StopId(0),
+            alight_stop: StopId(2),
+            boarding_time: at(0),
+            alight_time: at(20),
+        }]);
+        assert!(Itinerary::reconstruct(&unknown, &direct_connections).is_err());
+    }
+}
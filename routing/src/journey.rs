@@ -2,6 +2,7 @@ use chrono::{DateTime, Duration, TimeDelta, Utc};
 use common::types::{StopId, TripId};
 use itertools::Itertools;
 use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
 use std::slice::Iter;
 
 #[derive(Clone, Eq, PartialEq, Hash)]
@@ -95,15 +96,24 @@ impl Journey {
         Self { legs }
     }
 
-    pub(crate) fn legs(&self) -> Iter<Leg> {
+    pub fn legs(&self) -> Iter<Leg> {
         self.legs.iter()
     }
 
+    pub fn rides(&self) -> usize {
+        self.legs.iter().filter(|leg| matches!(leg, Leg::Ride { .. })).count()
+    }
+
+    // Changes from one ride to the next, walking between them or not
+    pub fn transfers(&self) -> usize {
+        self.rides().saturating_sub(1)
+    }
+
     // Return the time at which this journey will start
     // This is done by summing up all transfer durations before the first fixed departure (aka a
     // ride). The transfer durations will then be subtracted from that first departure date-time.
     // If the Journey only consists of transfers, then None will be returned.
-    pub(crate) fn departure(&self) -> Option<DateTime<Utc>> {
+    pub fn departure(&self) -> Option<DateTime<Utc>> {
         let first_ride = self.legs.iter().find(|leg| matches!(leg, Leg::Ride { .. }));
 
         if let Some(first_ride) = first_ride {
@@ -130,7 +140,7 @@ impl Journey {
     // This is done by summing up all transfer durations from back to front, until we hit a ride.
     // The transfer durations will then be added to the arrival date-time of the last ride.
     // If the Journey only consists of transfers, then None will be returned.
-    pub(crate) fn arrival(&self) -> Option<DateTime<Utc>> {
+    pub fn arrival(&self) -> Option<DateTime<Utc>> {
         let legs_reversed = self.legs.iter().rev();
 
         let last_ride = legs_reversed.clone().find(|leg| matches!(leg, Leg::Ride { .. }));
@@ -179,12 +189,12 @@ impl Journey {
         }
     }
 
-    pub(crate) fn departure_stop(&self) -> &StopId {
+    pub fn departure_stop(&self) -> &StopId {
         let first_leg = self.legs.first().expect("Journey must have at least one leg");
         first_leg.start()
     }
 
-    pub(crate) fn arrival_stop(&self) -> &StopId {
+    pub fn arrival_stop(&self) -> &StopId {
         let last_leg = self.legs.last().expect("Journey must have at least one leg");
         last_leg.end()
     }
@@ -194,4 +204,162 @@ impl From<Vec<Leg>> for Journey {
     fn from(legs: Vec<Leg>) -> Self {
         Self::new(legs)
     }
-}
\ No newline at end of file
+}
+/// Post-processing of the journeys found by a query, so that clients don't receive lots of
+/// trivially different journeys.
+#[derive(Clone, Debug)]
+pub struct JourneyFilter {
+    // Journeys that take the same lines between the same stops and depart within this window are
+    // collapsed into one
+    pub dedup_window: TimeDelta,
+    pub max_results: Option<usize>,
+}
+
+impl Default for JourneyFilter {
+    fn default() -> Self {
+        Self {
+            dedup_window: TimeDelta::minutes(5),
+            max_results: None,
+        }
+    }
+}
+
+// A ride is identified by its line instead of its trip, a transfer by its start and end
+#[derive(Clone, Eq, PartialEq, Hash)]
+enum LegSignature<L> {
+    Ride { line: L, boarding_stop: StopId, alight_stop: StopId },
+    Transfer { start: StopId, end: StopId },
+}
+
+impl JourneyFilter {
+    /// Collapses journeys that only differ by the run boarded on the same line, keeping the one
+    /// that arrives first (or departs last, if they arrive at the same time). The result is sorted
+    /// by departure and capped at `max_results`.
+    pub fn apply<L, F>(&self, journeys: impl IntoIterator<Item = Journey>, line_of: F) -> Vec<Journey>
+    where
+        L: Eq + Hash,
+        F: Fn(&TripId) -> L,
+    {
+        let groups = journeys.into_iter()
+            .into_group_map_by(|journey| {
+                journey.legs().map(|leg| match leg {
+                    Leg::Ride { trip, boarding_stop, alight_stop, .. } => LegSignature::Ride {
+                        line: line_of(trip),
+                        boarding_stop: *boarding_stop,
+                        alight_stop: *alight_stop,
+                    },
+                    Leg::Transfer { start, end, .. } => LegSignature::Transfer { start: *start, end: *end },
+                }).collect_vec()
+            });
+
+        let mut result = groups.into_values()
+            .flat_map(|group| {
+                let mut kept: Vec<(DateTime<Utc>, Journey)> = vec![];
+
+                for journey in group.into_iter().sorted_by_key(|journey| journey.departure()) {
+                    let departure = journey.departure().unwrap_or(DateTime::<Utc>::MIN_UTC);
+
+                    match kept.last_mut() {
+                        // Within the window of the first journey of the current cluster
+                        Some((cluster_start, best)) if departure - *cluster_start <= self.dedup_window => {
+                            if (journey.arrival(), std::cmp::Reverse(journey.departure()))
+                                < (best.arrival(), std::cmp::Reverse(best.departure())) {
+                                *best = journey;
+                            }
+                        }
+                        _ => kept.push((departure, journey)),
+                    }
+                }
+
+                kept.into_iter().map(|(_, journey)| journey)
+            })
+            .sorted_by_key(|journey| (journey.departure(), journey.arrival()))
+            .collect_vec();
+
+        if let Some(max_results) = self.max_results {
+            result.truncate(max_results);
+        }
+
+        result
+    }
+}
+
+/// Only keeps the Pareto-optimal journeys of a profile: those that no other journey beats by
+/// departing later, arriving earlier or taking fewer rides without being worse in another of them.
+/// Of journeys that are equal in all three, only one is kept. Journeys that only walk have no
+/// departure and are left out. The result is sorted by departure.
+pub fn pareto_optimal(journeys: impl IntoIterator<Item = Journey>) -> Vec<Journey> {
+    let criteria = |journey: &Journey| Some((
+        journey.departure()?,
+        journey.arrival()?,
+        journey.rides(),
+    ));
+
+    let candidates = journeys.into_iter()
+        .filter_map(|journey| Some((criteria(&journey)?, journey)))
+        .sorted_by_key(|(criteria, _)| *criteria)
+        .dedup_by(|(a, _), (b, _)| a == b)
+        .collect_vec();
+
+    candidates.iter()
+        .filter(|((departure, arrival, rides), _)| !candidates.iter().any(|(other, _)| {
+            let (other_departure, other_arrival, other_rides) = *other;
+            other != &(*departure, *arrival, *rides)
+                && other_departure >= *departure && other_arrival <= *arrival && other_rides <= *rides
+        }))
+        .map(|(_, journey)| journey.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ride(trip: u32, departure_minute: i64) -> Journey {
+        let start = DateTime::<Utc>::from_timestamp(0, 0).unwrap() + TimeDelta::minutes(departure_minute);
+        Journey::from(vec![Leg::Ride {
+            trip: TripId(trip),
+            boarding_stop: StopId(0),
+            alight_stop: StopId(1),
+            boarding_time: start,
+            alight_time: start + TimeDelta::minutes(30),
+        }])
+    }
+
+    #[test]
+    fn test_dedup_runs_of_same_line() {
+        // Trips 0 to 2 are runs of line 0, trip 3 is on line 1
+        let line_of = |trip: &TripId| if trip.0 < 3 { 0 } else { 1 };
+        let journeys = vec![ride(0, 0), ride(1, 3), ride(2, 20), ride(3, 1)];
+
+        let filtered = JourneyFilter::default().apply(journeys.clone(), line_of);
+        assert_eq!(filtered, vec![ride(0, 0), ride(3, 1), ride(2, 20)]);
+
+        let filter = JourneyFilter { max_results: Some(2), ..Default::default() };
+        assert_eq!(filter.apply(journeys, line_of), vec![ride(0, 0), ride(3, 1)]);
+    }
+
+    #[test]
+    fn test_pareto_optimal() {
+        let start = DateTime::<Utc>::from_timestamp(0, 0).unwrap();
+        let journey = |legs: &[(u32, i64, i64)]| Journey::from(legs.iter().enumerate()
+            .map(|(index, (trip, departure_minute, arrival_minute))| Leg::Ride {
+                trip: TripId(*trip),
+                boarding_stop: StopId(index as u32),
+                alight_stop: StopId(index as u32 + 1),
+                boarding_time: start + TimeDelta::minutes(*departure_minute),
+                alight_time: start + TimeDelta::minutes(*arrival_minute),
+            })
+            .collect_vec());
+
+        let early = journey(&[(0, 0, 30)]);
+        // Departs later and arrives earlier than the first ride, but needs a transfer
+        let with_transfer = journey(&[(1, 5, 10), (2, 12, 25)]);
+        // Departs at the same time as the first ride, but arrives later
+        let slow = journey(&[(3, 0, 40)]);
+        let late = journey(&[(4, 20, 50)]);
+
+        let optimal = pareto_optimal([late.clone(), slow, with_transfer.clone(), early.clone(), early.clone()]);
+        assert_eq!(optimal, vec![early, with_transfer, late]);
+    }
+}
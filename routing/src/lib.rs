@@ -1,9 +1,51 @@
 pub mod raptor;
+#[cfg(feature = "preprocessing")]
+pub mod mcraptor;
+#[cfg(feature = "preprocessing")]
+pub mod csa;
+#[cfg(feature = "preprocessing")]
 pub mod stp;
+#[cfg(feature = "preprocessing")]
 pub mod tp;
 pub mod transfers;
 pub mod algorithm;
+#[cfg(feature = "preprocessing")]
+pub mod artifacts;
+pub mod compact;
+#[cfg(feature = "preprocessing")]
+pub mod realtime;
+#[cfg(feature = "preprocessing")]
 pub mod direct_connections;
-mod journey;
-mod algorithms;
-#[cfg(test)] mod tests;
\ No newline at end of file
+#[cfg(feature = "preprocessing")]
+pub mod stations;
+#[cfg(feature = "preprocessing")]
+pub mod trip_runs;
+pub mod shadow;
+#[cfg(feature = "preprocessing")]
+pub mod accessibility;
+#[cfg(feature = "preprocessing")]
+pub mod bikes;
+#[cfg(feature = "preprocessing")]
+pub mod park_and_ride;
+#[cfg(feature = "preprocessing")]
+pub mod booking;
+#[cfg(feature = "preprocessing")]
+pub mod stop_index;
+#[cfg(feature = "preprocessing")]
+pub mod transfer_feasibility;
+#[cfg(feature = "preprocessing")]
+pub mod monitoring;
+#[cfg(feature = "preprocessing")]
+pub mod quality;
+#[cfg(feature = "preprocessing")]
+pub mod timetable;
+#[cfg(feature = "preprocessing")]
+pub mod calendar;
+pub mod journey;
+#[cfg(feature = "preprocessing")]
+pub mod itinerary;
+#[cfg(feature = "preprocessing")]
+pub mod cost;
+#[cfg(feature = "preprocessing")]
+pub mod geojson;
+#[cfg(all(test, feature = "preprocessing"))] mod tests;
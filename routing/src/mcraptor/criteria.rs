@@ -0,0 +1,76 @@
+use chrono::TimeDelta;
+use common::types::{StopId, TripId};
+use geo::{Distance, Haversine, Point};
+use hashbrown::{HashMap, HashSet};
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::{col, LazyFrame};
+
+/// An additional criterion of McRAPTOR. The costs of the legs of a journey add up, and lower costs
+/// are better. Costs must not be negative, since labels are pruned by the ones that reached the
+/// target already.
+pub trait Criterion: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn ride_cost(&self, _trip: TripId, _boarding_stop: StopId, _alight_stop: StopId) -> f64 {
+        0.0
+    }
+
+    fn transfer_cost(&self, _start: StopId, _end: StopId, _duration: TimeDelta) -> f64 {
+        0.0
+    }
+}
+
+/// Straight-line distance of the walks between stops in meters
+pub struct WalkingDistance {
+    stops: HashMap<StopId, Point<f32>>,
+}
+
+impl WalkingDistance {
+    pub fn from_stops(stops: LazyFrame) -> Result<Self, PolarsError> {
+        let stops = stops.select([col("stop_id"), col("lat"), col("lon")]).collect()?;
+        let stops = izip!(
+            stops.column("stop_id")?.u32()?,
+            stops.column("lat")?.f32()?,
+            stops.column("lon")?.f32()?,
+        )
+            .filter_map(|(stop_id, lat, lon)| Some((StopId(stop_id?), Point::new(lon?, lat?))))
+            .collect();
+        Ok(Self { stops })
+    }
+}
+
+impl Criterion for WalkingDistance {
+    fn name(&self) -> &str {
+        "walking_distance"
+    }
+
+    fn transfer_cost(&self, start: StopId, end: StopId, _: TimeDelta) -> f64 {
+        match (self.stops.get(&start), self.stops.get(&end)) {
+            (Some(start), Some(end)) => Haversine::distance(*start, *end) as f64,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Counts the rides on some trips, e.g. on trips that don't allow bikes for journeys with a bike
+pub struct TripPenalty {
+    name: String,
+    trips: HashSet<TripId>,
+}
+
+impl TripPenalty {
+    pub fn new(name: &str, trips: impl IntoIterator<Item = TripId>) -> Self {
+        Self { name: name.to_string(), trips: trips.into_iter().collect() }
+    }
+}
+
+impl Criterion for TripPenalty {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn ride_cost(&self, trip: TripId, _: StopId, _: StopId) -> f64 {
+        if self.trips.contains(&trip) { 1.0 } else { 0.0 }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::algorithm::{PreprocessInit, PreprocessingInput, PreprocessingResult, RoutingAlgorithm};
+use crate::journey::Journey;
+use crate::mcraptor::criteria::Criterion;
+use crate::raptor::RaptorAlgorithm;
+use common::types::config::RoutingConfig;
+use common::util::logging::ProgressSink;
+
+pub mod criteria;
+mod routing;
+
+/// McRAPTOR, the multi-criteria variant of RAPTOR (see section 4 of
+/// https://www.microsoft.com/en-us/research/wp-content/uploads/2012/01/raptor_alenex.pdf).
+/// Stops keep bags of labels instead of a single arrival, so that the result contains every
+/// journey that is not dominated in arrival, number of rides and the costs of all criteria.
+pub struct McRaptorAlgorithm {
+    // The lookup tables of lines and stops are the same as for RAPTOR
+    pub(crate) raptor: RaptorAlgorithm,
+    pub(crate) criteria: Vec<Box<dyn Criterion>>,
+}
+
+/// A journey of a multi-criteria query with its cost for each criterion, in the order of the
+/// criteria of the algorithm
+#[derive(Debug, Clone, PartialEq)]
+pub struct CriteriaJourney {
+    pub journey: Journey,
+    pub costs: Vec<f64>,
+}
+
+impl RoutingAlgorithm for McRaptorAlgorithm {}
+
+impl PreprocessInit for McRaptorAlgorithm {
+    fn preprocess(
+        input: PreprocessingInput,
+        config: &RoutingConfig,
+        save_to_disk: bool,
+        progress: &dyn ProgressSink,
+    ) -> PreprocessingResult<Self> {
+        Ok(Self::from(<RaptorAlgorithm as PreprocessInit>::preprocess(input, config, save_to_disk, progress)?))
+    }
+}
+
+impl From<RaptorAlgorithm> for McRaptorAlgorithm {
+    /// Without criteria, the result is Pareto-optimal in arrival and number of rides
+    fn from(raptor: RaptorAlgorithm) -> Self {
+        Self { raptor, criteria: vec![] }
+    }
+}
+
+impl McRaptorAlgorithm {
+    pub fn with_criterion(mut self, criterion: impl Criterion + 'static) -> Self {
+        self.criteria.push(Box::new(criterion));
+        self
+    }
+
+    pub fn criteria(&self) -> impl Iterator<Item = &str> {
+        self.criteria.iter().map(|criterion| criterion.name())
+    }
+}
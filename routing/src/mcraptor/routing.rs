@@ -0,0 +1,302 @@
+use crate::algorithm::{EarliestArrival, ParetoOutput, QueryError, QueryResult, Single, SinglePareto};
+use crate::journey::{Journey, Leg};
+use crate::mcraptor::{CriteriaJourney, McRaptorAlgorithm};
+use crate::raptor::{LocalStopId, TripIdx};
+use crate::transfers::TransferError;
+use chrono::{DateTime, Utc};
+use hashbrown::HashSet;
+use itertools::Itertools;
+
+// How a stop is reached. Labels are kept in an arena, so that they can refer to the label of the
+// stop before their leg.
+#[derive(Debug, Clone)]
+struct Label {
+    arrival: DateTime<Utc>,
+    rides: u32,
+    costs: Vec<f64>,
+    // Index of the label this label continues, together with the leg from its stop. The label of
+    // the start has neither.
+    parent: Option<(usize, Leg)>,
+}
+
+impl Label {
+    fn dominates(&self, other: &Label) -> bool {
+        self.arrival <= other.arrival
+            && self.rides <= other.rides
+            && self.costs.iter().zip(&other.costs).all(|(cost, other)| cost <= other)
+    }
+}
+
+// A label that boarded a trip while scanning a line
+struct RouteLabel {
+    // Index of the trip on the line that is scanned
+    trip: TripIdx,
+    boarding_stop: LocalStopId,
+    boarding_time: DateTime<Utc>,
+    // Index of the label that boarded
+    label: usize,
+}
+
+// The labels of all rounds, and the bags of labels that are not dominated
+struct McRaptorState {
+    labels: Vec<Label>,
+    // Labels that reached a stop in a round, by round and local stop id
+    round_bags: Vec<Vec<Vec<usize>>>,
+    // Labels of all rounds that are not dominated, called B* in the paper
+    best_bags: Vec<Vec<usize>>,
+}
+
+impl McRaptorState {
+    fn new(num_stops: usize, start: LocalStopId, departure: DateTime<Utc>, num_criteria: usize) -> Self {
+        let mut state = Self {
+            labels: vec![Label { arrival: departure, rides: 0, costs: vec![0.0; num_criteria], parent: None }],
+            round_bags: vec![vec![vec![]; num_stops]],
+            best_bags: vec![vec![]; num_stops],
+        };
+        state.round_bags[0][start.0 as usize].push(0);
+        state.best_bags[start.0 as usize].push(0);
+        state
+    }
+
+    fn new_round(&mut self) {
+        self.round_bags.push(vec![vec![]; self.best_bags.len()]);
+    }
+
+    // Adds the label to the stop in the current round, unless a label of the stop or the target
+    // dominates it. Labels of the stop that it dominates are removed from the best bag.
+    fn add(&mut self, stop: LocalStopId, target: LocalStopId, label: Label) -> bool {
+        let dominated_by = |bag: &[usize]| bag.iter().any(|other| self.labels[*other].dominates(&label));
+        if dominated_by(&self.best_bags[stop.0 as usize]) || dominated_by(&self.best_bags[target.0 as usize]) {
+            return false;
+        }
+
+        let idx = self.labels.len();
+        let best_bag = &mut self.best_bags[stop.0 as usize];
+        best_bag.retain(|other| !label.dominates(&self.labels[*other]));
+        best_bag.push(idx);
+        self.round_bags.last_mut().expect("There is always a round").get_mut(stop.0 as usize)
+            .expect("Bags are initialized for all stops")
+            .push(idx);
+        self.labels.push(label);
+        true
+    }
+
+    fn journey(&self, label: usize) -> Journey {
+        let mut legs = vec![];
+        let mut label = &self.labels[label];
+        while let Some((parent, leg)) = &label.parent {
+            legs.push(leg.clone());
+            label = &self.labels[*parent];
+        }
+        legs.reverse();
+        Journey::from(legs)
+    }
+}
+
+impl McRaptorAlgorithm {
+    /// The journeys to the target that are Pareto-optimal in arrival, number of rides and the
+    /// costs of all criteria, sorted by the number of rides and arrival
+    pub fn query_criteria(&self, input: EarliestArrival, Single { target }: Single) -> QueryResult<Vec<CriteriaJourney>> {
+        let raptor = &self.raptor;
+        if let Some(unknown) = [input.start, target].iter().find(|stop| !raptor.stop_mapping.0.contains(stop)) {
+            return Err(QueryError::StopNotFound(*unknown));
+        }
+        let start = raptor.stop_mapping.translate_to_local(input.start);
+        let local_target = raptor.stop_mapping.translate_to_local(target);
+        let global = |stop: LocalStopId| raptor.stop_mapping.translate_to_global(stop);
+        let is_closed = |stop: &LocalStopId| !input.closed_stops.is_empty() && input.closed_stops.contains(&global(*stop));
+        let cycling = input.transfers(raptor.transfer_provider.as_ref());
+        let transfer_provider = cycling.as_deref().unwrap_or(raptor.transfer_provider.as_ref());
+
+        let mut state = McRaptorState::new(raptor.num_stops(), start, input.earliest_departure, self.criteria.len());
+        let mut marked_stops: HashSet<LocalStopId> = HashSet::from([start]);
+
+        while !marked_stops.is_empty() {
+            // Every round after the first one adds a transfer
+            if input.max_transfers.is_some_and(|max_transfers| state.round_bags.len() - 1 > max_transfers) {
+                break;
+            }
+            state.new_round();
+            let k = state.round_bags.len() - 1;
+            let queue = raptor.build_queue(&marked_stops);
+            marked_stops.clear();
+
+            // Scan lines, first alighting the labels that boarded before, then boarding the
+            // labels of the previous round
+            for (line, first) in queue.iter() {
+                let (line, timetable) = (*line, &raptor.timetable);
+                let mut route_bag: Vec<RouteLabel> = vec![];
+
+                for (position, (b_stop, _)) in timetable.stops(line).iter().enumerate().skip(*first) {
+                    // Trips pass closed stops without anyone boarding or alighting
+                    if is_closed(b_stop) {
+                        continue;
+                    }
+                    for route_label in &route_bag {
+                        let Some(arrival) = timetable.arrival(line, route_label.trip, position) else { continue };
+                        let trip = timetable.trip(line, route_label.trip);
+                        let leg = Leg::Ride {
+                            trip,
+                            boarding_stop: global(route_label.boarding_stop),
+                            alight_stop: global(*b_stop),
+                            boarding_time: route_label.boarding_time,
+                            alight_time: arrival,
+                        };
+                        let boarded = &state.labels[route_label.label];
+                        let costs = self.criteria.iter().zip(&boarded.costs)
+                            .map(|(criterion, cost)| cost + criterion.ride_cost(trip, global(route_label.boarding_stop), global(*b_stop)))
+                            .collect();
+                        let label = Label { arrival, rides: boarded.rides + 1, costs, parent: Some((route_label.label, leg)) };
+
+                        if state.add(*b_stop, local_target, label) {
+                            marked_stops.insert(*b_stop);
+                        }
+                    }
+
+                    for label in &state.round_bags[k - 1][b_stop.0 as usize] {
+                        let arrival = state.labels[*label].arrival;
+                        let ready = input.ready_to_board(global(*b_stop), arrival);
+                        let boardable = |trip, departure| input.can_board(trip, global(*b_stop), arrival, departure);
+                        let Some(trip) = timetable.earliest_trip(line, position, ready, boardable) else { continue };
+                        let Some(departure) = timetable.departure(line, trip, position) else { continue };
+
+                        // Of the labels that boarded at this stop, an earlier trip is at least as
+                        // good if the label is
+                        let dominated = route_bag.iter()
+                            .filter(|other| other.boarding_stop == *b_stop && other.boarding_time <= departure)
+                            .any(|other| {
+                                let (other, label) = (&state.labels[other.label], &state.labels[*label]);
+                                other.rides <= label.rides && other.costs.iter().zip(&label.costs).all(|(other, cost)| other <= cost)
+                            });
+                        if !dominated {
+                            route_bag.push(RouteLabel { trip, boarding_stop: *b_stop, boarding_time: departure, label: *label });
+                        }
+                    }
+                }
+            }
+
+            // Scan the transfers from the stops that were reached by a ride in this round
+            for start in marked_stops.clone() {
+                for parent in state.round_bags[k][start.0 as usize].clone() {
+                    let label = &state.labels[parent];
+                    if !matches!(label.parent, Some((_, Leg::Ride { .. }))) {
+                        continue;
+                    }
+                    let (arrival, rides) = (label.arrival, label.rides);
+
+                    for end in transfer_provider.transfers_from(&start).into_iter().filter(|end| !is_closed(end)) {
+                        let duration = match transfer_provider.duration(start, end) {
+                            Ok(duration) => duration,
+                            Err(TransferError::OutOfReach) => continue,
+                            Err(TransferError::StopNotFound) => unreachable!("We only queried stops returned in provided transfer stops"),
+                        };
+                        let costs = self.criteria.iter().zip(&state.labels[parent].costs)
+                            .map(|(criterion, cost)| cost + criterion.transfer_cost(global(start), global(end), duration))
+                            .collect();
+                        let leg = Leg::Transfer { start: global(start), end: global(end), duration };
+                        let label = Label { arrival: arrival + duration, rides, costs, parent: Some((parent, leg)) };
+
+                        if state.add(end, local_target, label) {
+                            marked_stops.insert(end);
+                        }
+                    }
+                }
+            }
+        }
+
+        let journeys = state.best_bags[local_target.0 as usize].iter()
+            .filter(|label| state.labels[**label].parent.is_some())
+            .sorted_by_key(|label| (state.labels[**label].rides, state.labels[**label].arrival))
+            .map(|label| CriteriaJourney { journey: state.journey(*label), costs: state.labels[*label].costs.clone() })
+            .collect_vec();
+
+        if journeys.is_empty() {
+            return Err(QueryError::NoRouteFound);
+        }
+        Ok(journeys)
+    }
+}
+
+impl SinglePareto for McRaptorAlgorithm {
+    fn query_pareto(&self, input: EarliestArrival, cardinality: Single) -> QueryResult<ParetoOutput> {
+        let journeys = self.query_criteria(input, cardinality)?
+            .into_iter()
+            .map(|CriteriaJourney { journey, .. }| journey)
+            .collect();
+        Ok(ParetoOutput { journeys })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::{PreprocessInit, PreprocessingInput};
+    use crate::mcraptor::criteria::TripPenalty;
+    use crate::tests::duration;
+    use common::types::{StopId, TripId};
+    use common::util::logging::NoProgress;
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    // Trip 0 runs directly from stop 0 to 2 and arrives at 1000s. Changing from trip 1 to trip 2
+    // at stop 1 arrives later, at 1500s.
+    fn mc_raptor() -> McRaptorAlgorithm {
+        let input = PreprocessingInput {
+            trips: df!["trip_id" => [0u32, 1, 2], "service_id" => [0u32, 0, 0]].unwrap().lazy(),
+            stop_times: df![
+                "trip_id" => [0u32, 0, 1, 1, 2, 2],
+                "stop_id" => [0u32, 2, 0, 1, 1, 2],
+                "arrival_time" => [100, 1_000, 100, 500, 600, 1_500].map(duration),
+                "departure_time" => [100, 1_000, 100, 500, 600, 1_500].map(duration),
+                "stop_sequence" => [0u32, 1, 0, 1, 0, 1],
+            ].unwrap().lazy(),
+            trip_runs: df![
+                "trip_id" => [0u32, 1, 2],
+                "template_trip_id" => [0u32, 1, 2],
+                "run_offset" => [0; 3].map(duration),
+            ].unwrap().lazy(),
+            ..crate::tests::case_2::generate_preprocessing_input().unwrap()
+        };
+        McRaptorAlgorithm::preprocess(input, &Default::default(), false, &NoProgress).unwrap()
+    }
+
+    fn trips(journey: &Journey) -> Vec<TripId> {
+        journey.legs()
+            .filter_map(|leg| match leg {
+                Leg::Ride { trip, .. } => Some(*trip),
+                Leg::Transfer { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_without_criteria() {
+        // The direct trip is faster with fewer rides
+        let journeys = mc_raptor()
+            .query_criteria(EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH), Single { target: StopId(2) })
+            .unwrap();
+        assert_eq!(journeys.len(), 1);
+        assert_eq!(trips(&journeys[0].journey), [TripId(0)]);
+        assert!(journeys[0].costs.is_empty());
+    }
+
+    #[test]
+    fn test_trip_penalty() {
+        // Trip 0 doesn't take bikes, so changing is the only journey without a penalty
+        let mc_raptor = mc_raptor().with_criterion(TripPenalty::new("no_bikes", [TripId(0)]));
+        assert_eq!(mc_raptor.criteria().collect_vec(), ["no_bikes"]);
+
+        let journeys = mc_raptor
+            .query_criteria(EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH), Single { target: StopId(2) })
+            .unwrap();
+        assert_eq!(journeys.iter().map(|journey| trips(&journey.journey)).collect_vec(), [
+            vec![TripId(0)],
+            vec![TripId(1), TripId(2)],
+        ]);
+        assert_eq!(journeys.iter().map(|journey| journey.costs[0]).collect_vec(), [1.0, 0.0]);
+
+        // Without trip 1, only the direct trip is left
+        let input = EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH).suspending([TripId(1)]);
+        assert_eq!(mc_raptor.query_criteria(input, Single { target: StopId(2) }).unwrap().len(), 1);
+    }
+}
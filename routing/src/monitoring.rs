@@ -0,0 +1,344 @@
+//! Journeys that clients subscribe to with a webhook, which is called when the trip updates of the
+//! feeds change one of its rides
+
+use crate::algorithm::QueryResult;
+use crate::direct_connections::DirectConnections;
+use crate::itinerary::stops_of_trips;
+use crate::journey::{Journey, Leg};
+use crate::realtime::journeys::realtime_times;
+use crate::realtime::TripUpdatesFeed;
+use chrono::{DateTime, TimeDelta, Utc};
+use common::types::id_interner::OriginalIds;
+use common::types::{StopId, TripId};
+use hashbrown::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+// Of 64-bit FNV-1a
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// Stable identifier of a journey, derived from its legs. The same journey always gets the same
+/// id, also in other builds and processes, so clients can subscribe to a journey they received
+/// earlier. It is written as 16 hexadecimal digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct JourneyId(pub u64);
+
+impl From<&Journey> for JourneyId {
+    // The hasher of the standard library may change between releases, so the legs are hashed with
+    // FNV-1a in a layout of their own
+    fn from(journey: &Journey) -> Self {
+        let mut hash = FNV_OFFSET_BASIS;
+        let mut write = |bytes: &[u8]| for byte in bytes {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        };
+        for leg in journey.legs() {
+            match leg {
+                Leg::Ride { trip, boarding_stop, alight_stop, boarding_time, alight_time } => {
+                    write(&[0]);
+                    write(&trip.0.to_le_bytes());
+                    write(&boarding_stop.0.to_le_bytes());
+                    write(&alight_stop.0.to_le_bytes());
+                    write(&boarding_time.timestamp_millis().to_le_bytes());
+                    write(&alight_time.timestamp_millis().to_le_bytes());
+                }
+                Leg::Transfer { start, end, duration } => {
+                    write(&[1]);
+                    write(&start.0.to_le_bytes());
+                    write(&end.0.to_le_bytes());
+                    write(&duration.num_milliseconds().to_le_bytes());
+                }
+            }
+        }
+        JourneyId(hash)
+    }
+}
+
+impl Display for JourneyId {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for JourneyId {
+    type Err = ParseIntError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(id, 16).map(JourneyId)
+    }
+}
+
+/// A realtime change of a trip, see [TripChange::from_feeds]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TripChange {
+    // The trip arrives at and departs from a stop later (or earlier, if negative) than scheduled
+    Delay { trip: TripId, stop: StopId, delay: TimeDelta },
+    Cancellation { trip: TripId },
+    // The trip serves another platform instead of the scheduled one
+    PlatformChange { trip: TripId, stop: StopId, new_stop: StopId },
+}
+
+impl TripChange {
+    /// The changes that the latest messages of the feeds make to `trips`. Delays propagate along a
+    /// trip like in [crate::realtime::journeys::evaluate], with the same conversion of absolute
+    /// times. A stop time update with a stop sequence and another stop than the scheduled one
+    /// moves the trip to that stop.
+    pub fn from_feeds(
+        trips: &HashSet<TripId>,
+        feeds: &[TripUpdatesFeed],
+        original_ids: &OriginalIds,
+        direct_connections: &DirectConnections,
+        service_day_start: DateTime<Utc>,
+    ) -> QueryResult<Vec<TripChange>> {
+        let updates = feeds.iter()
+            .flat_map(|feed| feed.updates.iter().map(|update| (feed.dataset_id.as_str(), update)))
+            .filter_map(|(dataset_id, update)| {
+                let trip = original_ids.find_trip(&format!("{dataset_id}:{}", update.trip_id))?;
+                trips.contains(&trip).then_some((trip, dataset_id, update))
+            })
+            .collect::<Vec<_>>();
+        let stops_of_trips = stops_of_trips(updates.iter().map(|(trip, _, _)| *trip), direct_connections)?;
+        let offset = service_day_start - DateTime::UNIX_EPOCH;
+
+        let mut changes = vec![];
+        for (trip, dataset_id, update) in updates {
+            if update.cancelled {
+                changes.push(TripChange::Cancellation { trip });
+                continue;
+            }
+            let Some(stops) = stops_of_trips.get(&trip) else { continue };
+            let times = realtime_times(stops, dataset_id, update, original_ids, offset);
+            for (stop, times) in stops.iter().zip(times) {
+                let Some((arrival, departure)) = times else { continue };
+                let delay = departure.zip(stop.call.departure)
+                    .or(arrival.zip(stop.call.arrival))
+                    .map(|(expected, scheduled)| expected - scheduled);
+                if let Some(delay) = delay.filter(|delay| !delay.is_zero()) {
+                    changes.push(TripChange::Delay { trip, stop: stop.call.stop, delay });
+                }
+            }
+            for stop_time_update in &update.stop_time_updates {
+                let (Some(sequence), Some(stop_id)) = (stop_time_update.stop_sequence, &stop_time_update.stop_id) else { continue };
+                let Some(new_stop) = original_ids.stops.get(&format!("{dataset_id}:{stop_id}")) else { continue };
+                if let Some(scheduled) = stops.iter().find(|stop| stop.sequence == sequence).filter(|stop| stop.call.stop != new_stop) {
+                    changes.push(TripChange::PlatformChange { trip, stop: scheduled.call.stop, new_stop });
+                }
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// How a change affects a leg of a subscribed journey
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Alert {
+    Delayed { leg: usize, stop: StopId, delay: TimeDelta },
+    Cancelled { leg: usize },
+    PlatformChanged { leg: usize, stop: StopId, new_stop: StopId },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscription {
+    pub journey: Journey,
+    // Called with the alerts whenever a refresh affects the journey
+    pub webhook_url: String,
+    // Smaller delays are not reported
+    pub delay_threshold: TimeDelta,
+}
+
+impl Subscription {
+    /// Alerts for all changes that affect a ride of the journey. Delays only matter at the stops
+    /// where the journey boards or alights the trip.
+    pub fn alerts(&self, changes: &[TripChange]) -> Vec<Alert> {
+        let rides = self.journey.legs()
+            .enumerate()
+            .filter_map(|(index, leg)| match leg {
+                Leg::Ride { trip, boarding_stop, alight_stop, .. } => Some((index, *trip, [*boarding_stop, *alight_stop])),
+                Leg::Transfer { .. } => None,
+            })
+            .collect::<Vec<_>>();
+
+        changes.iter()
+            .flat_map(|change| rides.iter().filter_map(move |(leg, trip, stops)| match change {
+                TripChange::Delay { trip: changed, stop, delay }
+                if changed == trip && stops.contains(stop) && delay.abs() >= self.delay_threshold => {
+                    Some(Alert::Delayed { leg: *leg, stop: *stop, delay: *delay })
+                }
+                TripChange::Cancellation { trip: changed } if changed == trip => {
+                    Some(Alert::Cancelled { leg: *leg })
+                }
+                TripChange::PlatformChange { trip: changed, stop, new_stop } if changed == trip && stops.contains(stop) => {
+                    Some(Alert::PlatformChanged { leg: *leg, stop: *stop, new_stop: *new_stop })
+                }
+                _ => None,
+            }))
+            .collect()
+    }
+}
+
+/// Webhook callback for a subscribed journey that is affected by a refresh
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification<'a> {
+    pub journey_id: JourneyId,
+    pub webhook_url: &'a str,
+    pub alerts: Vec<Alert>,
+}
+
+/// All journeys that clients monitor for realtime changes
+#[derive(Debug, Clone, Default)]
+pub struct Subscriptions {
+    subscriptions: HashMap<JourneyId, Vec<Subscription>>,
+}
+
+impl Subscriptions {
+    pub fn subscribe(&mut self, subscription: Subscription) -> JourneyId {
+        let journey_id = JourneyId::from(&subscription.journey);
+        self.subscriptions.entry(journey_id).or_default().push(subscription);
+        journey_id
+    }
+
+    /// Removes all subscriptions of a journey with the webhook and returns whether there were any
+    pub fn unsubscribe(&mut self, journey_id: JourneyId, webhook_url: &str) -> bool {
+        let Some(subscriptions) = self.subscriptions.get_mut(&journey_id) else { return false };
+        let num_before = subscriptions.len();
+        subscriptions.retain(|subscription| subscription.webhook_url != webhook_url);
+        let removed = subscriptions.len() < num_before;
+
+        if subscriptions.is_empty() {
+            self.subscriptions.remove(&journey_id);
+        }
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscriptions.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    /// The trips that the subscribed journeys ride, whose changes [Self::evaluate] needs
+    pub fn trips(&self) -> HashSet<TripId> {
+        self.subscriptions.values().flatten()
+            .flat_map(|subscription| subscription.journey.legs())
+            .filter_map(|leg| match leg {
+                Leg::Ride { trip, .. } => Some(*trip),
+                Leg::Transfer { .. } => None,
+            })
+            .collect()
+    }
+
+    /// The callbacks to send after a refresh with the given changes, ordered by journey id
+    pub fn evaluate(&self, changes: &[TripChange]) -> Vec<Notification> {
+        let mut notifications = self.subscriptions.iter()
+            .flat_map(|(journey_id, subscriptions)| subscriptions.iter().map(move |subscription| (journey_id, subscription)))
+            .filter_map(|(journey_id, subscription)| {
+                let alerts = subscription.alerts(changes);
+                (!alerts.is_empty()).then_some(Notification {
+                    journey_id: *journey_id,
+                    webhook_url: subscription.webhook_url.as_str(),
+                    alerts,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        notifications.sort_by(|a, b| a.journey_id.cmp(&b.journey_id).then(a.webhook_url.cmp(b.webhook_url)));
+        notifications
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::{StopTimeEvent, StopTimeUpdate, TripUpdate};
+    use common::types::id_interner::IdInterner;
+
+    fn ride(trip: u32, boarding_stop: u32, alight_stop: u32) -> Leg {
+        let start = DateTime::<Utc>::UNIX_EPOCH;
+        Leg::Ride {
+            trip: TripId(trip),
+            boarding_stop: StopId(boarding_stop),
+            alight_stop: StopId(alight_stop),
+            boarding_time: start,
+            alight_time: start + TimeDelta::minutes(10),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let journey = Journey::from(vec![
+            ride(0, 0, 1),
+            Leg::Transfer { start: StopId(1), end: StopId(2), duration: TimeDelta::minutes(3) },
+            ride(1, 2, 3),
+        ]);
+
+        let mut subscriptions = Subscriptions::default();
+        let journey_id = subscriptions.subscribe(Subscription {
+            journey: journey.clone(),
+            webhook_url: "https://example.com/hook".into(),
+            delay_threshold: TimeDelta::minutes(5),
+        });
+        assert_eq!(journey_id, JourneyId::from(&journey));
+        assert_eq!(journey_id.to_string().parse::<JourneyId>().unwrap(), journey_id);
+        assert_eq!(subscriptions.trips(), HashSet::from([TripId(0), TripId(1)]));
+
+        let notifications = subscriptions.evaluate(&[
+            // Too small, at a stop the journey doesn't use and of another trip
+            TripChange::Delay { trip: TripId(0), stop: StopId(1), delay: TimeDelta::minutes(2) },
+            TripChange::Delay { trip: TripId(1), stop: StopId(9), delay: TimeDelta::minutes(20) },
+            TripChange::Cancellation { trip: TripId(5) },
+            TripChange::Delay { trip: TripId(1), stop: StopId(3), delay: TimeDelta::minutes(8) },
+            TripChange::PlatformChange { trip: TripId(0), stop: StopId(1), new_stop: StopId(4) },
+        ]);
+        assert_eq!(notifications.len(), 1);
+        assert_eq!(notifications[0].alerts, [
+            Alert::Delayed { leg: 2, stop: StopId(3), delay: TimeDelta::minutes(8) },
+            Alert::PlatformChanged { leg: 0, stop: StopId(1), new_stop: StopId(4) },
+        ]);
+
+        assert!(subscriptions.evaluate(&[TripChange::Cancellation { trip: TripId(5) }]).is_empty());
+
+        assert!(subscriptions.unsubscribe(journey_id, "https://example.com/hook"));
+        assert!(subscriptions.is_empty());
+    }
+
+    #[test]
+    fn test_from_feeds() {
+        // Trip 0 from stop 0 to 1 at 100s to 500s, trip 1 from stop 1 to 2 at 1000s to 1500s
+        let direct_connections = DirectConnections::try_from(crate::tests::case_2::generate_preprocessing_input().unwrap()).unwrap();
+        let original_ids = OriginalIds {
+            stops: IdInterner::from_originals(["d:0", "d:1", "d:2"]),
+            trips: IdInterner::from_originals(["d:0", "d:1"]),
+            ..Default::default()
+        };
+        let late = Some(StopTimeEvent { delay: Some(120), time: None });
+        let updates = vec![
+            TripUpdate { trip_id: "0".into(), cancelled: true, ..Default::default() },
+            TripUpdate {
+                trip_id: "1".into(),
+                stop_time_updates: vec![
+                    // Leaves from stop 0 instead of 1, two minutes late
+                    StopTimeUpdate { stop_sequence: Some(0), stop_id: Some("0".into()), arrival: late.clone(), departure: late, ..Default::default() },
+                ],
+                ..Default::default()
+            },
+        ];
+        let feeds = [TripUpdatesFeed { dataset_id: "d".into(), updates }];
+        let from_feeds = |trips: &[u32]| {
+            let trips = trips.iter().copied().map(TripId).collect();
+            TripChange::from_feeds(&trips, &feeds, &original_ids, &direct_connections, DateTime::UNIX_EPOCH).unwrap()
+        };
+
+        assert_eq!(from_feeds(&[0]), [TripChange::Cancellation { trip: TripId(0) }]);
+        assert_eq!(from_feeds(&[1]), [
+            TripChange::Delay { trip: TripId(1), stop: StopId(1), delay: TimeDelta::minutes(2) },
+            TripChange::Delay { trip: TripId(1), stop: StopId(2), delay: TimeDelta::minutes(2) },
+            TripChange::PlatformChange { trip: TripId(1), stop: StopId(1), new_stop: StopId(0) },
+        ]);
+        assert!(from_feeds(&[]).is_empty());
+    }
+}
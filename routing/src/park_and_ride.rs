@@ -0,0 +1,229 @@
+use crate::algorithm::{EarliestArrival, JourneyPlanner, PreprocessingResult, QueryError, QueryResult};
+pub use crate::algorithm::AccessMode;
+use crate::journey::Journey;
+use crate::transfers::osm;
+use chrono::{DateTime, Duration, Utc};
+use common::types::config::ParkAndRideConfig;
+use common::types::id_interner::OriginalIds;
+use common::types::StopId;
+use common::util::haversine::PointColumns;
+use common::util::speed::{Speed, CYCLING_SPEED};
+use geo::{Distance, Haversine, Point};
+use hashbrown::{HashMap, HashSet};
+use itertools::izip;
+use log::warn;
+use polars::prelude::{col, LazyFrame};
+
+/// A drive or ride between the start or target of a query and a P+R stop
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLeg {
+    pub mode: AccessMode,
+    pub start: StopId,
+    pub end: StopId,
+    pub duration: Duration,
+}
+
+/// A journey by public transport with the drives or rides to and from it. Walking at either end
+/// leaves out the leg there.
+#[derive(Debug, Clone)]
+pub struct ParkAndRideJourney {
+    pub access: Option<AccessLeg>,
+    pub journey: Journey,
+    pub egress: Option<AccessLeg>,
+}
+
+impl ParkAndRideJourney {
+    /// When the traveller leaves the start, before the access leg
+    pub fn departure(&self) -> Option<DateTime<Utc>> {
+        Some(self.journey.departure()? - self.access_duration())
+    }
+
+    /// When the traveller reaches the target, after the egress leg
+    pub fn arrival(&self) -> Option<DateTime<Utc>> {
+        Some(self.journey.arrival()? + self.egress_duration())
+    }
+
+    fn access_duration(&self) -> Duration {
+        self.access.as_ref().map_or(Duration::zero(), |leg| leg.duration)
+    }
+
+    fn egress_duration(&self) -> Duration {
+        self.egress.as_ref().map_or(Duration::zero(), |leg| leg.duration)
+    }
+}
+
+/// Stops where travellers may park a car or a bike and continue by public transport, as listed in
+/// the config or near the sites of an OpenStreetMap extract that are tagged with `park_ride`.
+/// Drives and rides to them take the straight line.
+pub struct ParkAndRide {
+    stops: HashSet<StopId>,
+    // Longitude and latitude of every stop
+    coords: HashMap<StopId, Point<f64>>,
+    driving_speed: Speed,
+    max_duration: Duration,
+    max_stops: usize,
+}
+
+impl ParkAndRide {
+    pub fn from_config(config: &ParkAndRideConfig, stops: LazyFrame, original_ids: &OriginalIds) -> PreprocessingResult<Self> {
+        let frame = stops.select([col("stop_id"), col("lat"), col("lon")]).collect()?;
+        let coords: HashMap<StopId, Point<f64>> = izip!(
+            frame.column("stop_id")?.u32()?,
+            frame.column("lat")?.f32()?,
+            frame.column("lon")?.f32()?,
+        )
+            .filter_map(|(id, lat, lon)| Some((StopId(id?), Point::new(lon? as f64, lat? as f64))))
+            .collect();
+
+        let mut marked: HashSet<StopId> = config.stops.iter()
+            .filter_map(|stop| {
+                let id = original_ids.stops.get(stop);
+                if id.is_none() {
+                    warn!(target: "preprocessing", "Ignoring unknown P+R stop {stop}");
+                }
+                id
+            })
+            .collect();
+        if let Some(extract) = &config.osm_extract {
+            let sites = osm::park_and_ride_sites(extract)?;
+            let (stop_ids, points): (Vec<StopId>, PointColumns) = coords.iter().map(|(stop, coord)| (*stop, *coord)).unzip();
+            for site in sites {
+                marked.extend(points.within(site, config.radius_meters as f64).into_iter().map(|idx| stop_ids[idx]));
+            }
+        }
+
+        Ok(Self {
+            stops: marked,
+            coords,
+            driving_speed: config.driving_speed(),
+            max_duration: config.max_access_duration(),
+            max_stops: config.max_stops,
+        })
+    }
+
+    pub fn is_park_and_ride(&self, stop: StopId) -> bool {
+        self.stops.contains(&stop)
+    }
+
+    /// The journey with the earliest arrival that gets to the first ride and from the last one by
+    /// the access and egress modes of the input, see [EarliestArrival::with_options]. The nearest
+    /// P+R stops at both ends are tried, as are the start and target themselves, so the journey is
+    /// never later than the one of walking.
+    pub fn query_ea(&self, planner: &impl JourneyPlanner, input: EarliestArrival, to: StopId) -> QueryResult<ParkAndRideJourney> {
+        let access = self.reached(input.start, input.access_mode, &input.closed_stops);
+        let egress = self.reached(to, input.egress_mode, &input.closed_stops);
+
+        let mut best: Option<(DateTime<Utc>, ParkAndRideJourney)> = None;
+        for (boarding_stop, access_duration) in &access {
+            for (alight_stop, egress_duration) in &egress {
+                if boarding_stop == alight_stop {
+                    continue;
+                }
+                let search = EarliestArrival {
+                    start: *boarding_stop,
+                    earliest_departure: input.earliest_departure + *access_duration,
+                    ..input.clone()
+                };
+                let departure = search.earliest_departure;
+                let journey = match planner.query_ea_with(search, *alight_stop) {
+                    Ok(journey) => journey,
+                    Err(QueryError::NoRouteFound) => continue,
+                    Err(err) => return Err(err),
+                };
+                let Some(arrival) = journey.arrival_when_starting_at(departure).map(|arrival| arrival + *egress_duration) else { continue };
+                if best.as_ref().is_some_and(|(best, _)| *best <= arrival) {
+                    continue;
+                }
+
+                let leg = |mode: AccessMode, start: StopId, end: StopId, duration: Duration| {
+                    (start != end).then_some(AccessLeg { mode, start, end, duration })
+                };
+                best = Some((arrival, ParkAndRideJourney {
+                    access: leg(input.access_mode, input.start, *boarding_stop, *access_duration),
+                    journey,
+                    egress: leg(input.egress_mode, *alight_stop, to, *egress_duration),
+                }));
+            }
+        }
+        best.map(|(_, journey)| journey).ok_or(QueryError::NoRouteFound)
+    }
+
+    // The stop itself and the nearest open P+R stops that `mode` reaches from it in time, with
+    // the duration of getting there. Drives and rides are symmetric, so the same stops reach it.
+    fn reached(&self, stop: StopId, mode: AccessMode, closed_stops: &HashSet<StopId>) -> Vec<(StopId, Duration)> {
+        let speed = match mode {
+            AccessMode::Walk => return vec![(stop, Duration::zero())],
+            AccessMode::Bike => CYCLING_SPEED,
+            AccessMode::Car => self.driving_speed,
+        };
+        let Some(coord) = self.coords.get(&stop) else { return vec![(stop, Duration::zero())] };
+
+        let mut reached: Vec<(StopId, Duration)> = self.stops.iter()
+            .filter(|other| **other != stop && !closed_stops.contains(*other))
+            .filter_map(|other| Some((*other, speed.time_to_travel_distance(Haversine::distance(*coord, *self.coords.get(other)?) as f32))))
+            .filter(|(_, duration)| *duration <= self.max_duration)
+            .collect();
+        reached.sort_unstable_by_key(|(other, duration)| (*duration, *other));
+        reached.truncate(self.max_stops);
+        reached.insert(0, (stop, Duration::zero()));
+        reached
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::{PreprocessInit, QueryOptions};
+    use crate::csa::ConnectionScanAlgorithm;
+    use crate::tests::case_2;
+    use common::types::id_interner::IdInterner;
+    use common::util::logging::NoProgress;
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    #[test]
+    fn test_query_ea() {
+        // Trip 0 runs from stop 0 to 1 at 100s to 500s, trip 1 from stop 1 to 2 at 1000s to 1500s.
+        // Stop 1 is a P+R stop about 3 km from stop 0, stop 2 is far away.
+        let mut input = case_2::generate_preprocessing_input().unwrap();
+        input.stops = df!(
+            "stop_id" => [0u32, 1, 2],
+            "lat" => [48.0f32, 48.027, 45.0],
+            "lon" => [9.0f32, 9.0, 9.0],
+        ).unwrap().lazy();
+        input.original_ids = std::sync::Arc::new(OriginalIds {
+            stops: IdInterner::from_originals(["a:0", "a:1", "a:2"]),
+            ..Default::default()
+        });
+        let config = ParkAndRideConfig { stops: vec!["a:1".to_string(), "a:9".to_string()], ..Default::default() };
+        let park_and_ride = ParkAndRide::from_config(&config, input.stops.clone(), &input.original_ids).unwrap();
+        assert!(park_and_ride.is_park_and_ride(StopId(1)));
+        assert!(!park_and_ride.is_park_and_ride(StopId(0)));
+        let csa = <ConnectionScanAlgorithm as PreprocessInit>::preprocess(input, &Default::default(), false, &NoProgress).unwrap();
+
+        // Walking misses trip 0
+        let departure = DateTime::from_timestamp(200, 0).unwrap();
+        let walking = EarliestArrival::new(StopId(0), departure);
+        assert!(matches!(park_and_ride.query_ea(&csa, walking, StopId(2)), Err(QueryError::NoRouteFound)));
+
+        // Driving to stop 1 takes about 6 minutes, which is in time for trip 1
+        let options = QueryOptions { access_mode: AccessMode::Car, ..Default::default() };
+        let driving = EarliestArrival::new(StopId(0), departure).with_options(&options);
+        let journey = park_and_ride.query_ea(&csa, driving.clone(), StopId(2)).unwrap();
+        let access = journey.access.clone().unwrap();
+        assert_eq!((access.mode, access.start, access.end), (AccessMode::Car, StopId(0), StopId(1)));
+        assert!((Duration::minutes(5)..Duration::minutes(7)).contains(&access.duration), "{}", access.duration);
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+        assert!(journey.egress.is_none());
+
+        // Unless the P+R stop is closed
+        assert!(park_and_ride.query_ea(&csa, driving.closing([StopId(1)]), StopId(2)).is_err());
+    }
+
+    #[test]
+    fn test_access_mode() {
+        assert_eq!("car".parse::<AccessMode>().unwrap(), AccessMode::Car);
+        assert_eq!(AccessMode::Bike.to_string(), "bike");
+        assert!("train".parse::<AccessMode>().is_err());
+    }
+}
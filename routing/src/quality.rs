@@ -0,0 +1,189 @@
+use crate::algorithm::{JourneyPlanner, PreprocessingInput, PreprocessingResult};
+use crate::journey::Leg;
+use crate::tp::day_types::day_types;
+use chrono::{DateTime, TimeDelta, Utc};
+use common::types::id_interner::OriginalIds;
+use common::types::{StopId, TripId};
+use hashbrown::{HashMap, HashSet};
+use itertools::{izip, Itertools};
+use polars::prelude::*;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+// Number of origin-destination pairs the mean number of transfers is computed for
+pub const OD_SAMPLE_SIZE: usize = 50;
+// Journeys of the sample depart at 08:00 of the timetable day
+const SAMPLE_DEPARTURE: TimeDelta = TimeDelta::hours(8);
+
+/// Mean number of trips running on each date that has any service
+pub fn trips_per_day(input: &PreprocessingInput) -> PreprocessingResult<f64> {
+    let trips_per_service = input.trips.clone()
+        .group_by([col("service_id")])
+        .agg([len().cast(DataType::UInt32).alias("num_trips")])
+        .collect()?;
+    let trips_per_service: HashMap<u32, u32> = izip!(
+        trips_per_service.column("service_id")?.u32()?,
+        trips_per_service.column("num_trips")?.u32()?,
+    )
+        .filter_map(|(service_id, num_trips)| Some((service_id?, num_trips?)))
+        .collect();
+
+    let day_types = day_types(input.services.clone())?;
+    let num_dates: usize = day_types.iter().map(|day_type| day_type.dates.len()).sum();
+    let num_trips: u64 = day_types.iter()
+        .map(|day_type| {
+            let trips: u64 = day_type.service_ids.iter()
+                .map(|service_id| *trips_per_service.get(service_id).unwrap_or(&0) as u64)
+                .sum();
+            trips * day_type.dates.len() as u64
+        })
+        .sum();
+
+    Ok(match num_dates {
+        0 => 0.0,
+        num_dates => num_trips as f64 / num_dates as f64,
+    })
+}
+
+/// Origin-destination pairs of original stop ids, spread evenly over all stops. The pairs only
+/// depend on the set of stops, not on the order their ids were assigned in.
+pub fn od_sample(original_ids: &OriginalIds, size: usize) -> Vec<(String, String)> {
+    let stops: Vec<&str> = (0..original_ids.stops.len() as u32)
+        .filter_map(|id| original_ids.stop(StopId(id)))
+        .sorted()
+        .dedup()
+        .collect();
+    if stops.len() < 2 {
+        return vec![];
+    }
+
+    let size = size.min(stops.len());
+    (0..size)
+        .map(|index| {
+            let origin = index * stops.len() / size;
+            let destination = (origin + stops.len() / 2) % stops.len();
+            (stops[origin].to_string(), stops[destination].to_string())
+        })
+        .collect()
+}
+
+/// The sample stored at `path` (columns "from_stop", "to_stop"), so that every build is measured
+/// on the same pairs. It is created on the first build. Pairs with a stop that doesn't exist
+/// anymore are left out.
+pub fn read_or_create_od_sample(path: &Path, original_ids: &OriginalIds) -> PreprocessingResult<Vec<(StopId, StopId)>> {
+    let sample = match std::fs::read_to_string(path) {
+        Ok(sample) => sample.lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(','))
+            .map(|(from, to)| (from.to_string(), to.to_string()))
+            .collect(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let sample = od_sample(original_ids, OD_SAMPLE_SIZE);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut file = File::create(path)?;
+            writeln!(file, "from_stop,to_stop")?;
+            for (from, to) in &sample {
+                writeln!(file, "{from},{to}")?;
+            }
+            sample
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok(sample.iter()
+        .filter_map(|(from, to)| Some((original_ids.stops.get(from)?, original_ids.stops.get(to)?)))
+        .collect())
+}
+
+/// Mean number of transfers between the rides of the optimal journeys between the pairs, without
+/// the trips that don't run on the date of the sample. Pairs without a journey don't count, `None`
+/// if no pair has one.
+pub fn mean_transfers(planner: &impl JourneyPlanner, pairs: &[(StopId, StopId)], not_running: &HashSet<TripId>) -> Option<f64> {
+    let departure = DateTime::<Utc>::UNIX_EPOCH + SAMPLE_DEPARTURE;
+    let transfers: Vec<usize> = pairs.iter()
+        .filter_map(|(from, to)| planner.query_ea_suspending(*from, *to, departure, not_running.clone()).ok())
+        .map(|journey| journey.legs().filter(|leg| matches!(leg, Leg::Ride { .. })).count())
+        .filter(|rides| *rides > 0)
+        .map(|rides| rides - 1)
+        .collect();
+
+    match transfers.len() {
+        0 => None,
+        num_journeys => Some(transfers.iter().sum::<usize>() as f64 / num_journeys as f64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use common::types::id_interner::IdInterner;
+    use polars::df;
+
+    #[test]
+    fn test_trips_per_day() {
+        let date = |day| NaiveDate::from_ymd_opt(2024, 1, day).unwrap();
+        // Service 0 runs on every day of the first week, service 1 only on its saturday. Service 2
+        // has no trips.
+        let services = df!(
+            "service_id" => [0u32, 1, 2],
+            "monday"     => [true, false, true],
+            "tuesday"    => [true, false, true],
+            "wednesday"  => [true, false, true],
+            "thursday"   => [true, false, true],
+            "friday"     => [true, false, true],
+            "saturday"   => [true; 3],
+            "sunday"     => [true, false, true],
+            "start_date" => [date(1); 3],
+            "end_date"   => [date(7); 3],
+        ).unwrap().lazy();
+        let input = PreprocessingInput {
+            services,
+            stops: DataFrame::empty().lazy(),
+            trips: df!(
+                "trip_id"    => [0u32, 1, 2],
+                "service_id" => [0u32, 0, 1],
+            ).unwrap().lazy(),
+            stop_times: DataFrame::empty().lazy(),
+            stations: DataFrame::empty().lazy(),
+            trip_runs: DataFrame::empty().lazy(),
+            transfers: Default::default(),
+            original_ids: Default::default(),
+        };
+
+        // Two trips on six days and three on saturday
+        assert_eq!(trips_per_day(&input).unwrap(), 15.0 / 7.0);
+    }
+
+    #[test]
+    fn test_read_or_create_od_sample() {
+        let original_ids = OriginalIds {
+            stops: IdInterner::from_originals(["d", "c", "b", "a"]),
+            trips: Default::default(),
+            stable_trips: Default::default(),
+        };
+        assert_eq!(od_sample(&original_ids, 2), [
+            ("a".to_string(), "c".to_string()),
+            ("c".to_string(), "a".to_string()),
+        ]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("od_sample.csv");
+        let sample = read_or_create_od_sample(&path, &original_ids).unwrap();
+        assert_eq!(sample.len(), 4);
+
+        // Stop "a" is gone in the next build, the remaining pairs stay the same
+        let original_ids = OriginalIds {
+            stops: IdInterner::from_originals(["b", "c", "d", "e"]),
+            trips: Default::default(),
+            stable_trips: Default::default(),
+        };
+        assert_eq!(read_or_create_od_sample(&path, &original_ids).unwrap(), [
+            (StopId(0), StopId(2)),
+            (StopId(2), StopId(0)),
+        ]);
+    }
+}
@@ -0,0 +1,144 @@
+use crate::algorithm::{FromDiskInit, PreprocessingError, PreprocessingResult, SaveToDisk};
+use crate::artifacts;
+use crate::raptor::{
+    LookupTables, RaptorAlgorithm, StopMapping, StopsByLineMap, Timetable, TripAtStopTimeMap, TripsByLineAndStopMap,
+};
+use chrono::DateTime;
+use common::types::config::Compression;
+use common::types::{LineId, StopId, TripId};
+use itertools::izip;
+use polars::prelude::*;
+use std::path::Path;
+
+// The lookup tables are written as maps, one row per entry. Rows of the same line keep their
+// order, which is the order of the stops along the line and of the trips by departure. The lines
+// at each stop aren't written, they follow from the stops of the lines.
+impl SaveToDisk for RaptorAlgorithm {
+    fn save_to_disk(&self, dir: &Path, compression: Compression) -> PreprocessingResult<()> {
+        let dir = dir.join("raptor");
+        let write = |name: &str, table: DataFrame| artifacts::write_table(&dir, name, table, compression);
+
+        write("stop_mapping", df!("stop_id" => self.stop_mapping.0.sources().iter().map(|stop| stop.0).collect::<Vec<_>>())?)?;
+
+        let tables = self.timetable.tables();
+        let (mut line_ids, mut stop_ids, mut visit_idxs) = (vec![], vec![], vec![]);
+        for (line, stops) in &tables.stops_by_line {
+            for (stop, visit_idx) in stops {
+                line_ids.push(line.0);
+                stop_ids.push(stop.0);
+                visit_idxs.push(*visit_idx);
+            }
+        }
+        write("stops_by_line", df!("line_id" => line_ids, "stop_id" => stop_ids, "visit_idx" => visit_idxs)?)?;
+
+        write("departures", times_to_frame(&tables.departures)?)?;
+        write("arrivals", times_to_frame(&tables.arrivals)?)?;
+
+        let (mut line_ids, mut stop_ids, mut departures, mut trip_ids) = (vec![], vec![], vec![], vec![]);
+        for ((line, stop), trips) in &tables.trips_by_line_and_stop {
+            for (departure, trip) in trips {
+                line_ids.push(line.0);
+                stop_ids.push(stop.0);
+                departures.push(departure.timestamp_millis());
+                trip_ids.push(trip.0);
+            }
+        }
+        write("trips_by_line_and_stop", df!(
+            "line_id" => line_ids, "stop_id" => stop_ids, "departure" => departures, "trip_id" => trip_ids,
+        )?)?;
+
+        Ok(())
+    }
+}
+
+impl FromDiskInit for RaptorAlgorithm {
+    fn load_from_disk(dir: &Path) -> PreprocessingResult<Self> {
+        let transfer_provider = artifacts::read_transfers(dir)?;
+        let dir = dir.join("raptor");
+        let read = |name: &str| artifacts::read_table(&dir, name);
+
+        let stop_mapping = read("stop_mapping")?;
+        let stop_mapping = StopMapping(stop_mapping.column("stop_id")?.u32()?.into_no_null_iter().map(StopId).collect());
+
+        let mut stops_by_line = StopsByLineMap::new();
+        let table = read("stops_by_line")?;
+        for (line, stop, visit_idx) in izip!(u32s(&table, "line_id")?, u32s(&table, "stop_id")?, u32s(&table, "visit_idx")?) {
+            stops_by_line.entry(LineId(line)).or_default().push((StopId(stop), visit_idx));
+        }
+
+        let mut trips_by_line_and_stop = TripsByLineAndStopMap::new();
+        let table = read("trips_by_line_and_stop")?;
+        let departures = table.column("departure")?.i64()?.into_no_null_iter();
+        for (line, stop, departure, trip) in izip!(u32s(&table, "line_id")?, u32s(&table, "stop_id")?, departures, u32s(&table, "trip_id")?) {
+            trips_by_line_and_stop.entry((LineId(line), StopId(stop))).or_default()
+                .push((timestamp(departure)?, TripId(trip)));
+        }
+
+        Ok(Self {
+            stop_mapping,
+            timetable: Timetable::from(LookupTables {
+                stops_by_line,
+                departures: times_from_frame(&read("departures")?)?,
+                arrivals: times_from_frame(&read("arrivals")?)?,
+                trips_by_line_and_stop,
+            }),
+            transfer_provider,
+        })
+    }
+}
+
+fn times_to_frame(times: &TripAtStopTimeMap) -> PolarsResult<DataFrame> {
+    let (mut trip_ids, mut stop_ids, mut visit_idxs, mut times_ms) = (vec![], vec![], vec![], vec![]);
+    for ((trip, stop, visit_idx), time) in times {
+        trip_ids.push(trip.0);
+        stop_ids.push(stop.0);
+        visit_idxs.push(*visit_idx);
+        times_ms.push(time.timestamp_millis());
+    }
+    df!("trip_id" => trip_ids, "stop_id" => stop_ids, "visit_idx" => visit_idxs, "time" => times_ms)
+}
+
+fn times_from_frame(table: &DataFrame) -> PreprocessingResult<TripAtStopTimeMap> {
+    izip!(u32s(table, "trip_id")?, u32s(table, "stop_id")?, u32s(table, "visit_idx")?, table.column("time")?.i64()?.into_no_null_iter())
+        .map(|(trip, stop, visit_idx, time)| Ok(((TripId(trip), StopId(stop), visit_idx), timestamp(time)?)))
+        .collect()
+}
+
+fn u32s<'a>(table: &'a DataFrame, column: &str) -> PolarsResult<impl Iterator<Item = u32> + 'a> {
+    Ok(table.column(column)?.u32()?.into_no_null_iter())
+}
+
+fn timestamp(millis: i64) -> PreprocessingResult<DateTime<chrono::Utc>> {
+    DateTime::from_timestamp_millis(millis)
+        .ok_or_else(|| PreprocessingError::Polars(PolarsError::ComputeError(format!("Invalid time {millis}").into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::{FromDiskInit, JourneyPlanner, SaveToDisk};
+    use crate::artifacts;
+    use crate::direct_connections::DirectConnections;
+    use crate::raptor::RaptorAlgorithm;
+    use chrono::{DateTime, Utc};
+    use common::types::config::{Compression, TransferConfig};
+    use common::types::StopId;
+
+    #[test]
+    fn test_save_and_load() {
+        let input = crate::tests::case_3::generate_preprocessing_input().unwrap();
+        let raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input.clone()).unwrap()).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        artifacts::write_stops(dir.path(), input.stops, input.transfers, &TransferConfig::default(), Compression::default()).unwrap();
+        raptor.save_to_disk(dir.path(), Compression::default()).unwrap();
+
+        let loaded = RaptorAlgorithm::load_from_disk(dir.path()).unwrap();
+        assert_eq!(loaded.stop_mapping.0, raptor.stop_mapping.0);
+        assert_eq!(loaded.timetable, raptor.timetable);
+
+        for (from, to) in [(0, 3), (3, 0), (1, 2)] {
+            let [expected, actual] = [&raptor, &loaded]
+                .map(|raptor| raptor.query_ea(StopId(from), StopId(to), DateTime::<Utc>::UNIX_EPOCH).ok().map(|journey| journey.arrival()));
+            assert_eq!(expected, actual);
+        }
+    }
+}
@@ -2,16 +2,22 @@ use crate::algorithm::{QueryResult, RoutingAlgorithm};
 use crate::journey::Journey;
 use crate::transfers::TransferProvider;
 use chrono::{DateTime, Duration, Utc};
-use common::types::{LineId, SeqNum, StopId, TripId};
-use hashbrown::{HashMap, HashSet};
+use common::types::dense_ids::DenseIds;
+use common::types::{LineId, StopId, TripId};
+use hashbrown::HashMap;
 
+#[cfg(feature = "preprocessing")]
+mod artifacts;
+#[cfg(feature = "preprocessing")]
 mod preprocessing;
 mod routing;
 mod state;
+#[cfg(all(test, feature = "preprocessing"))]
 mod tests;
+mod timetable;
 
-type GlobalStopId = StopId;
-type LocalStopId = StopId;
+pub(crate) type GlobalStopId = StopId;
+pub(crate) type LocalStopId = StopId;
 
 /// <(trip_id, stop_id, visit_idx), time>
 /// the visit_idx is there, since a trip could visit the same stop multiple times (think round trips)
@@ -20,23 +26,13 @@ pub type TripsByLineAndStopMap =
     HashMap<(LineId, LocalStopId), Vec<(DateTime<Utc>, TripId)>>;
 
 pub type StopsByLineMap = HashMap<LineId, Vec<(LocalStopId, u32)>>;
-pub type LinesByStopMap = HashMap<LocalStopId, HashSet<(LineId, SeqNum)>>;
+
+pub(crate) use timetable::{LineIdx, LookupTables, Timetable, TripIdx};
 
 pub struct RaptorAlgorithm {
     pub(crate) stop_mapping: StopMapping,
 
-    /// <line_id, [stop_id, visit_idx]>
-    pub(crate) stops_by_line: StopsByLineMap,
-    pub(crate) lines_by_stops: LinesByStopMap,
-
-    // <(trip_id, stop_id, visit_idx), departure_time>
-    pub(crate) departures: TripAtStopTimeMap,
-    // <(trip_id, stop_id, visit_idx), arrival_time>
-    pub(crate) arrivals: TripAtStopTimeMap,
-
-    // Vec has to be sorted from earliest to latest
-    // DateTime is departure
-    pub(crate) trips_by_line_and_stop: TripsByLineAndStopMap,
+    pub(crate) timetable: Timetable,
 
     pub(crate) transfer_provider: Box<dyn TransferProvider + Send + Sync>,
 }
@@ -56,22 +52,25 @@ impl RaptorAlgorithm {
 }
 
 /// In order to simplify lookup of data, the passed stop IDs will be transformed to local stop
-/// ids that start at zero and assign a new stop ID to each stop continuously. The index of
-/// stop_mapping is the local stop ID, the value at that index is the global stop ID.
+/// ids that start at zero and assign a new stop ID to each stop continuously. Both directions of
+/// the translation are a lookup in a vector.
 #[derive(Debug)]
-pub(crate) struct StopMapping(pub(crate) Vec<GlobalStopId>);
+pub(crate) struct StopMapping(pub(crate) DenseIds<GlobalStopId>);
 
 impl StopMapping {
     /// Translates a local stop ID into a global stop ID
-    fn translate_to_global(&self, local_stop_id: LocalStopId) -> GlobalStopId {
-        self.0[local_stop_id.0 as usize]
+    pub(crate) fn translate_to_global(&self, local_stop_id: LocalStopId) -> GlobalStopId {
+        self.0.source(local_stop_id)
     }
 
     /// Translates a global stop ID into a local stop ID
-    // TODO: Return the result, maybe use a separate hash map to speed up the lookup?
-    fn translate_to_local(&self, global_stop_id: GlobalStopId) -> LocalStopId {
-        let idx = self.0.iter().position(|stop_id| stop_id == &global_stop_id);
+    pub(crate) fn translate_to_local(&self, global_stop_id: GlobalStopId) -> LocalStopId {
+        self.0.dense(global_stop_id).unwrap()
+    }
 
-        StopId(idx.unwrap() as u32)
+    /// The local stop ID of a global stop ID, if the stop is in the timetable
+    #[cfg(feature = "preprocessing")]
+    pub(crate) fn find_local(&self, global_stop_id: GlobalStopId) -> Option<LocalStopId> {
+        self.0.dense(global_stop_id)
     }
 }
@@ -1,13 +1,17 @@
 use crate::algorithm::{
-    PreprocessInit, PreprocessingError, PreprocessingInput, PreprocessingResult,
+    PreprocessInit, PreprocessingError, PreprocessingInput, PreprocessingResult, SaveToDisk,
 };
 use crate::direct_connections::DirectConnections;
 use crate::raptor::{
-    GlobalStopId, LinesByStopMap, RaptorAlgorithm, StopMapping, StopsByLineMap, TripAtStopTimeMap,
+    GlobalStopId, LookupTables, RaptorAlgorithm, StopMapping, StopsByLineMap, Timetable, TripAtStopTimeMap,
     TripsByLineAndStopMap,
 };
-use crate::transfers::crow_fly::CrowFlyTransferProvider;
+use crate::transfers::TransferProvider;
 use chrono::DateTime;
+use common::types::config::{RoutingConfig, TransferConfig};
+use common::types::dense_ids::DenseIds;
+use common::util::logging::ProgressSink;
+use common::util::paths;
 use common::types::{LineId, StopId, TripId};
 use common::util::time::INFINITY;
 use itertools::{izip, Itertools};
@@ -18,27 +22,55 @@ use std::ops::{BitAnd, BitOr};
 impl PreprocessInit for RaptorAlgorithm {
     fn preprocess(
         input: PreprocessingInput,
+        config: &RoutingConfig,
         save_to_disk: bool,
+        progress: &dyn ProgressSink,
     ) -> PreprocessingResult<RaptorAlgorithm> {
+        // Lines, walking transfers and the lookup tables are each built in a single pass
+        let task = progress.start("Building lines, walking transfers and lookup tables of stops", 3);
+        let direct_connections = DirectConnections::try_from(input.clone())?;
+        task.inc(1);
+        let transfer_provider = crate::transfers::walking(&input, &config.transfers)?;
+        task.inc(1);
+        let algorithm = Self::preprocess_with_provider(input, direct_connections, transfer_provider)?;
+        task.inc(1);
+
         if save_to_disk {
-            unimplemented!()
+            algorithm.save_to_disk(&paths::preprocessing_dir(), config.compression)?;
         }
-
-        let direct_connections = DirectConnections::try_from(input.clone())?;
-        Self::preprocess(input, direct_connections)
+        Ok(algorithm)
     }
 }
 
 impl RaptorAlgorithm {
+    /// Walks between stops by the defaults of [TransferConfig]
     pub fn preprocess(
+        input: PreprocessingInput,
+        direct_connections: DirectConnections,
+    ) -> PreprocessingResult<RaptorAlgorithm> {
+        Self::preprocess_with_transfers(input, direct_connections, &TransferConfig::default())
+    }
+
+    pub fn preprocess_with_transfers(
+        input: PreprocessingInput,
+        direct_connections: DirectConnections,
+        transfers: &TransferConfig,
+    ) -> PreprocessingResult<RaptorAlgorithm> {
+        let transfer_provider = crate::transfers::walking(&input, transfers)?;
+        Self::preprocess_with_provider(input, direct_connections, transfer_provider)
+    }
+
+    /// Walks with a provider that was already built, e.g. one that is shared with other algorithms
+    pub fn preprocess_with_provider(
         PreprocessingInput { stops, .. }: PreprocessingInput,
         DirectConnections {
             expanded_lines,
             line_progressions,
             ..
         }: DirectConnections,
+        transfer_provider: Box<dyn TransferProvider + Send + Sync>,
     ) -> PreprocessingResult<RaptorAlgorithm> {
-        let stops_vec: Vec<GlobalStopId> = stops.clone()
+        let stops_vec: DenseIds<GlobalStopId> = stops.clone()
             .select(&[col("stop_id")]).collect()?
             .column("stop_id")?.u32()?
             .to_vec().into_iter()
@@ -47,47 +79,35 @@ impl RaptorAlgorithm {
 
         let stop_mapping = StopMapping(stops_vec);
 
-        let (stops_by_line, lines_by_stops) = {
+        let stops_by_line = {
             let mut stops_by_line = hashbrown::HashMap::default();
-            let mut lines_by_stops = hashbrown::HashMap::default();
 
-            let [line_ids, global_stop_ids, sequence_numbers] = line_progressions.get_columns()
+            let [line_ids, global_stop_ids, _sequence_numbers] = line_progressions.get_columns()
             else {
                 return Err(PreprocessingError::Polars(PolarsError::ColumnNotFound(
                     "".into(),
                 )));
             };
 
-            let [line_ids, global_stop_ids, sequence_numbers] = [
-                line_ids.u32()?,
-                global_stop_ids.u32()?,
-                sequence_numbers.u32()?,
-            ];
+            let [line_ids, global_stop_ids] = [line_ids.u32()?, global_stop_ids.u32()?];
 
-            for (line_id, global_stop_id, seq_num) in
-                izip!(line_ids, global_stop_ids, sequence_numbers)
-            {
+            for (line_id, global_stop_id) in izip!(line_ids, global_stop_ids) {
                 let line_id = line_id.unwrap().into();
                 let global_stop_id = global_stop_id.unwrap().into();
                 let local_stop_id = stop_mapping.translate_to_local(global_stop_id);
-                let seq_num = seq_num.unwrap().into();
 
                 let stops_by_line_entry = stops_by_line.entry(line_id).or_insert(vec![]);
                 let visit_idx = stops_by_line_entry.iter()
                     .filter(|(stop_id, _)| stop_id == &local_stop_id)
                     .count() as u32;
                 stops_by_line_entry.push((local_stop_id, visit_idx));
-
-                lines_by_stops.entry(local_stop_id).or_insert(hashbrown::HashSet::new())
-                    .insert((line_id, seq_num));
             }
 
-            Ok::<(StopsByLineMap, LinesByStopMap), PreprocessingError>((
-                stops_by_line,
-                lines_by_stops,
-            ))
+            Ok::<StopsByLineMap, PreprocessingError>(stops_by_line)
         }?;
-        debug_assert!(stop_mapping.0.len() == lines_by_stops.len());
+        debug_assert!(
+            stop_mapping.0.len() == stops_by_line.values().flatten().map(|(stop, _)| stop).unique().count()
+        );
 
         let lines = expanded_lines.clone().select([
             "line_id",
@@ -269,12 +289,13 @@ impl RaptorAlgorithm {
 
         Ok(Self {
             stop_mapping,
-            stops_by_line,
-            lines_by_stops,
-            arrivals,
-            departures,
-            trips_by_line_and_stop,
-            transfer_provider: Box::new(CrowFlyTransferProvider::from_stops(stops)?),
+            timetable: Timetable::from(LookupTables {
+                stops_by_line,
+                arrivals,
+                departures,
+                trips_by_line_and_stop,
+            }),
+            transfer_provider,
         })
     }
 }
@@ -287,6 +308,7 @@ mod tests {
     use polars::prelude::*;
 
     use super::*;
+    use common::util::logging::NoProgress;
 
     #[test]
     fn test_preprocessing() {
@@ -315,13 +337,23 @@ mod tests {
                 "departure_time" => departure_times.clone(),
                 "stop_sequence"  => &[0u32, 1, 2, 3, 4, 5, 6, 7, 8, 10, 11, 12, 13, 14, 15]
             ).unwrap().lazy(),
+            stations: df!(
+                "stop_id"    => &[0u32, 1, 2, 3, 4, 5],
+                "station_id" => &[0u32, 1, 2, 3, 4, 5],
+            ).unwrap().lazy(),
+            trip_runs: df!(
+                "trip_id"          => &[0u32, 1, 2, 3],
+                "template_trip_id" => &[0u32, 1, 2, 3],
+            ).unwrap().lazy(),
+            transfers: Default::default(),
+            original_ids: Default::default(),
         };
 
         let preprocessing_out =
-            <RaptorAlgorithm as PreprocessInit>::preprocess(preprocessing_in, false).unwrap();
+            <RaptorAlgorithm as PreprocessInit>::preprocess(preprocessing_in, &RoutingConfig::default(), false, &NoProgress).unwrap();
 
         assert!(list_eq(
-            &preprocessing_out.stop_mapping.0,
+            &preprocessing_out.stop_mapping.0.sources().to_vec(),
             &vec![0u32, 1, 2, 3, 4, 5].into_iter().map(|x| StopId(x)).collect())
         );
         // TODO: Test all of preprocessing_out
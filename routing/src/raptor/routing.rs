@@ -1,89 +1,65 @@
-use crate::algorithm::*;
+use crate::algorithm::{AllEarliestArrival, AllRange, EarliestArrival, EarliestArrivalOutput, MultiEarliestArrival, MultiQueryResult, Multiple, QueryError, ParetoOutput, QueryResult, Range, RangeOutput, Single, SingleEarliestArrival, SinglePareto, SingleRange};
 use crate::journey::Journey;
 use crate::raptor::state::RaptorState;
-use crate::raptor::{LocalStopId, RaptorAlgorithm};
-use crate::transfers::TransferError;
-use chrono::{DateTime, Duration, TimeDelta, Utc};
-use common::types::{LineId, SeqNum, StopId, TripId};
+use crate::raptor::{LineIdx, LocalStopId, RaptorAlgorithm, TripIdx};
+use crate::transfers::{TransferError, TransferProvider};
+use chrono::{DateTime, TimeDelta, Utc};
+use common::types::StopId;
 use common::util::time::INFINITY;
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
+use std::iter::once;
 
 impl RaptorAlgorithm {
-    /// Selects the earliest trip of a line, that departs at `stop` after a given time
-    fn earliest_trip(&self, line: LineId, stop: StopId, after: DateTime<Utc>) -> Option<TripId> {
-        self.trips_by_line_and_stop
-            .get(&(line, stop))
-            .and_then(|trips| {
-                trips.iter().find_map(|(departure, trip)| {
-                    if *departure >= after {
-                        Some(*trip)
-                    } else { None }
-                })
-            })
-    }
-
-    fn build_queue(&self, marked_stops: &HashSet<LocalStopId>) -> HashSet<(LineId, (LocalStopId, u32))> {
-        let mut queue: HashSet<(LineId, (LocalStopId, u32))> = HashSet::new();
-
-        for stop_a in marked_stops {
-            if let Some(lines_serving_stop) = self.lines_by_stops.get(stop_a) {
-                // foreach line serving marked_stop (stop a)
-                for (line, seq_num_a) in lines_serving_stop {
-                    let other_stops = self.stops_by_line.get(line)
-                        .unwrap_or_else(|| panic!(
-                            "Line {line:?} is in lines_by_stops, so it must also be in stops_by_line."
-                        ));
-                    // for any stop b that is also on the line
-                    for (seq_num_b, (stop_b, visit_idx)) in other_stops.iter().enumerate() {
-                        let seq_num_b = SeqNum(seq_num_b as u32);
-                        // if other_stop comes after marked_stop on that line
-                        if queue.contains(&(*line, (*stop_b, *visit_idx))) && seq_num_a < &seq_num_b {
-                            queue.remove(&(*line, (*stop_b, *visit_idx)));
-                            queue.insert((*line, (*stop_b, *visit_idx)));
-                        } else {
-                            queue.insert((*line, (*stop_b, *visit_idx)));
-                        }
-                    }
-                }
+    /// The lines to scan in a round, each from the first stop on it that is marked
+    pub(crate) fn build_queue(&self, marked_stops: &HashSet<LocalStopId>) -> HashMap<LineIdx, usize> {
+        let mut queue: HashMap<LineIdx, usize> = HashMap::new();
+
+        for stop in marked_stops {
+            // foreach line serving marked_stop, keep the earlier of the stops
+            for (line, position) in self.timetable.lines_at(*stop) {
+                let position = *position as usize;
+                queue.entry(*line)
+                    .and_modify(|first| *first = (*first).min(position))
+                    .or_insert(position);
             }
         }
 
         queue
     }
 
-    fn stops_on_line_after(&self, line: &LineId, stop: &LocalStopId, visit_idx: &u32) -> impl Iterator<Item=&(LocalStopId, u32)> {
-        // Get all stops on that line that comes after stop_id (including stop_id)
-        let stops_on_line = self.stops_by_line.get(line).unwrap();
-        let a_stop_idx_on_line = stops_on_line.iter()
-            .position(|x| x == &(*stop, *visit_idx))
-            .unwrap_or_else(|| panic!( // used instead of expect for performance
-                "Expected Stop with ID {stop:?} to be on line {line:?}. But this line has only these stops: {stops_on_line:?}"
-            ));
-        let stops_on_line_after = stops_on_line.iter().skip(a_stop_idx_on_line);
-
-        #[cfg(debug_assertions)] {
-            // stop_id itself is first in line of the stops
-            debug_assert!(
-                stops_on_line_after.clone().collect_vec()[0].0 == *stop,
-                "Line {line:?} does not include stop {stop:?} as a stop after {stop:?}",
-            );
-        }
-
-        stops_on_line_after
+    fn run(&self, start: LocalStopId, input: &EarliestArrival) -> QueryResult<RaptorState> {
+        let mut state = RaptorState::init(self.num_stops(), start, input.earliest_departure, &self.stop_mapping);
+        self.run_rounds(&mut state, start, input)?;
+        Ok(state)
     }
 
-    fn run(
+    // The rounds of a single departure from the start. Labels that are already in the state (of
+    // later departures of a range query) prune journeys that don't arrive earlier. The suspended
+    // trips, closed stops, transfers and other options are the ones of the input, its departure is
+    // ignored.
+    fn run_rounds(
         &self,
+        state: &mut RaptorState,
         start: LocalStopId,
-        departure: DateTime<Utc>,
-    ) -> QueryResult<RaptorState> {
-        let mut state = RaptorState::init(self.num_stops(), start, departure, &self.stop_mapping);
+        input: &EarliestArrival,
+    ) -> QueryResult<()> {
         let mut marked_stops: HashSet<LocalStopId> = HashSet::from([start]);
+        let closed = &input.closed_stops;
+        let is_closed = |stop: &LocalStopId| !closed.is_empty() && closed.contains(&self.stop_mapping.translate_to_global(*stop));
+        let cycling = input.transfers(self.transfer_provider.as_ref());
+        let transfer_provider = cycling.as_deref().unwrap_or(self.transfer_provider.as_ref());
+
+        // Walks from the start to the stops of the first ride
+        self.scan_transfers(state, &mut marked_stops, transfer_provider, &is_closed)?;
 
         // Increase the number of legs per round
         // foreach k <- 1,2,... do
         while !marked_stops.is_empty() {
+            // Every round after the first one adds a transfer
+            if input.max_transfers.is_some_and(|max_transfers| state.k > max_transfers) {
+                break;
+            }
             // increment k and set up this round
             state.new_round();
             debug_assert!(state.k > 0, "k starts at 1");
@@ -101,47 +77,52 @@ impl RaptorAlgorithm {
 
             // SECOND STAGE: Scan lines
             // Process each line (called "route" in the original paper).
-            for (line, (a_stop, a_visit_idx)) in queue.iter() {
-                // Option<(stop_id, visit_idx)>
-                let mut boarding: Option<(StopId, u32)> = None;
-                let mut trip: Option<TripId> = None;
+            for (line, first) in queue.iter() {
+                let (line, stops) = (*line, self.timetable.stops(*line));
+                // Option<(trip, position of the boarding stop on the line)>
+                let mut boarding: Option<(TripIdx, usize)> = None;
 
-                for (b_stop, b_visit_idx) in self.stops_on_line_after(line, a_stop, a_visit_idx) {
+                for (position, (b_stop, _)) in stops.iter().enumerate().skip(*first) {
                     // TODO: Fix funky date problems
                     // if t != ⊥ and ...
-                    if let Some(trip) = trip {
-                        let b_arrival = self.arrivals.get(&(trip, *b_stop, *b_visit_idx))
-                            .unwrap_or(&INFINITY); // TODO: Why is this not an error?
+                    if let Some((trip, boarding_position)) = boarding {
+                        let b_arrival = self.timetable.arrival(line, trip, position)
+                            .unwrap_or(INFINITY); // TODO: Why is this not an error?
                         let best_b_arrival = state.best_arrival(b_stop);
 
                         // taking the trip to b it is faster than not taking it
                         // ...and arr(t, pᵢ) < τ*(pᵢ)
-                        if b_arrival < best_b_arrival {
-                            let (boarding_stop, boarding_visit_idx) = boarding.expect("Boarding stop must not be None");
-                            let boarding_departure = self.departures.get(&(trip, boarding_stop, boarding_visit_idx))
+                        if &b_arrival < best_b_arrival && !is_closed(b_stop) {
+                            let (boarding_stop, _) = stops[boarding_position];
+                            let trip_id = self.timetable.trip(line, trip);
+                            let boarding_departure = self.timetable.departure(line, trip, boarding_position)
                                 .unwrap_or_else(|| panic!(
-                                    "Expected departure for stop {a_stop:?} (visit {boarding_visit_idx}) to exist on trip {trip:?}"
+                                    "Expected departure for stop {boarding_stop:?} (position {boarding_position}) to exist on trip {trip_id:?}"
                                 ));
 
-                            state.set_ride(boarding_stop, *b_stop, *boarding_departure, *b_arrival, trip);
+                            state.set_ride(boarding_stop, *b_stop, boarding_departure, b_arrival, trip_id);
                             marked_stops.insert(*b_stop);
                         }
                     }
 
-                    let b_departure = trip.and_then(|trip| {
-                        self.departures.get(&(trip, *b_stop, *b_visit_idx))
-                    }).unwrap_or(&INFINITY);
+                    let b_departure = boarding
+                        .and_then(|(trip, _)| self.timetable.departure(line, trip, position))
+                        .unwrap_or(INFINITY);
 
                     let prev_b_arrival = state.previous_tau(b_stop);
 
                     // Initialize trip if its None. Also execute when we can catch an earlier trip
                     // of the same line at stop b.
-                    if prev_b_arrival <= b_departure {
-                        let next_trip = self.earliest_trip(*line, *b_stop, *prev_b_arrival);
-
-                        if next_trip.is_some() {
-                            trip = next_trip;
-                            boarding = Some((*b_stop, *b_visit_idx));
+                    let global_stop = self.stop_mapping.translate_to_global(*b_stop);
+                    let ready = input.ready_to_board(global_stop, *prev_b_arrival);
+                    if ready <= b_departure && !is_closed(b_stop) {
+                        // Trips of modes with a transfer slack may have to be boarded later than
+                        // the trip that is ridden already, which is then kept
+                        let next_trip = self.timetable
+                            .earliest_trip(line, position, ready, |trip, departure| input.can_board(trip, global_stop, *prev_b_arrival, departure))
+                            .filter(|next_trip| self.timetable.departure(line, *next_trip, position).is_some_and(|departure| departure <= b_departure));
+                        if let Some(next_trip) = next_trip {
+                            boarding = Some((next_trip, position));
                         }
                     }
                 }
@@ -150,97 +131,99 @@ impl RaptorAlgorithm {
             // THIRD STAGE: Scan transfers
             // Look at individual station-to-station transfers (like footpaths) and update
             // best_arrival when walking to a stop is faster than taking transit
-            let transfer_provider = &self.transfer_provider;
             // foreach marked stop p
-            for start in marked_stops.clone() {
-                // foreach footpath (p, p') ∈ F
-                for end in transfer_provider.transfers_from(&start) {
-                    // This is the maximum amount of time a transfer will have to take in order to
-                    // be faster
-                    let max_duration = *state.tau(&end).unwrap_or(&INFINITY) - *state.tau(&start)
-                        .expect("transfer start was in marked_stops, so it must have a tau value set");
-
-                    // This if-clause checks if there is any chance this transfer is faster.
-                    // For this approximation, we use a lower bound duration that is cheaper to
-                    // calculate than an actual route and duration (at least for large distances)
-                    let lower_bound_duration = transfer_provider.lower_bound_duration(start, end);
-                    match lower_bound_duration {
-                        Ok(lower_bound_duration) => {
-                            if lower_bound_duration < max_duration {
-                                // Since we found a candidate, calculate the actual, precise duration it
-                                // will take.
-                                let actual_duration = transfer_provider.duration(start, end)?;
-                                debug_assert!(
-                                    actual_duration >= lower_bound_duration,
-                                    "Actual duration must be greater than the lower bound."
-                                );
-
-                                if actual_duration < max_duration {
-                                    state.set_transfer(start, end, actual_duration);
-                                }
-                            }
-                        },
-                        Err(e) => {
-                            match e {
-                                TransferError::OutOfReach => {},
-                                TransferError::StopNotFound => unreachable!("We only queried stops returned in provided transfer stops"),
+            self.scan_transfers(state, &mut marked_stops, transfer_provider, &is_closed)?;
+        }
+
+        Ok(())
+    }
+
+    // Walks from the marked stops, marking the stops that they reach
+    fn scan_transfers(
+        &self,
+        state: &mut RaptorState,
+        marked_stops: &mut HashSet<LocalStopId>,
+        transfer_provider: &(dyn TransferProvider + Send + Sync),
+        is_closed: &dyn Fn(&LocalStopId) -> bool,
+    ) -> QueryResult<()> {
+        for start in marked_stops.clone() {
+            // foreach footpath (p, p') ∈ F
+            for end in transfer_provider.transfers_from(&start) {
+                if is_closed(&end) {
+                    continue;
+                }
+                // This is the maximum amount of time a transfer will have to take in order to
+                // be faster. In a single run τ*(p') equals τₖ(p'), in range queries it may come
+                // from a later departure.
+                let max_duration = *state.best_arrival(&end) - *state.tau(&start)
+                    .expect("transfer start was in marked_stops, so it must have a tau value set");
+
+                // This if-clause checks if there is any chance this transfer is faster.
+                // For this approximation, we use a lower bound duration that is cheaper to
+                // calculate than an actual route and duration (at least for large distances)
+                let lower_bound_duration = transfer_provider.lower_bound_duration(start, end);
+                match lower_bound_duration {
+                    Ok(lower_bound_duration) => {
+                        if lower_bound_duration < max_duration {
+                            // Since we found a candidate, calculate the actual, precise duration it
+                            // will take.
+                            let actual_duration = transfer_provider.duration(start, end)?;
+                            debug_assert!(
+                                actual_duration >= lower_bound_duration,
+                                "Actual duration must be greater than the lower bound."
+                            );
+
+                            if actual_duration < max_duration {
+                                state.set_transfer(start, end, actual_duration);
                             }
                         }
+                    },
+                    Err(e) => {
+                        match e {
+                            TransferError::OutOfReach => {},
+                            TransferError::StopNotFound => unreachable!("We only queried stops returned in provided transfer stops"),
+                        }
                     }
-
-                    // mark p'
-                    marked_stops.insert(end);
                 }
+
+                // mark p'
+                marked_stops.insert(end);
             }
         }
-
-        Ok(state)
+        Ok(())
     }
 
+    /// rRAPTOR: runs the rounds for every departure at the start within the range, the latest
+    /// first. The labels are kept from one departure to the next earlier one, so only stops that
+    /// are reached earlier than by departing later are scanned again and the journeys form a
+    /// profile: none of them departs earlier without arriving earlier.
     fn run_range(
         &self,
-        start: StopId,
-        earliest_departure: DateTime<Utc>,
+        start: LocalStopId,
+        input: &EarliestArrival,
         range: TimeDelta,
     ) -> QueryResult<RangeOutput> {
+        let earliest_departure = input.earliest_departure;
         let last_departure = earliest_departure + range;
+        let departures = self.departures_at(start, input, earliest_departure, last_departure)?;
+        let Some(latest) = departures.first() else {
+            return Err(QueryError::NoRouteFound);
+        };
 
         // List of all journeys to all targets in the given time range
         let mut journeys = HashSet::new();
-
-        let mut departure = earliest_departure;
-        while departure <= last_departure {
-            let res_after_departure = self.run(start, departure);
-
-            match res_after_departure {
-                // There is a valid output of the earliest arrival query
-                Ok(state) => {
-                    let new_journeys = self.backtrace_all(state, departure)
-                        .unwrap_or_default()
-                        .into_iter()
-                        .filter(|j| { j.departure().unwrap_or(departure) <= last_departure });
-
-                    journeys.extend(new_journeys.clone());
-
-                    let earliest_departure = new_journeys
-                        .filter_map(|journey| journey.departure())
-                        .min();
-
-                    if let Some(earliest_departure) = earliest_departure {
-                        departure = earliest_departure + Duration::seconds(1);
-                    } else {
-                        // There is no earliest departure, so there is no departure at all
-                        // after this point in time
-                        break;
-                    }
-                }
-                // There were no (more) trips found: stop searching
-                Err(QueryError::NoRouteFound) => break,
-                // Propagate errors up to the caller
-                Err(other_err) => {
-                    return Err(other_err);
-                }
-            }
+        let mut state = RaptorState::init(self.num_stops(), start, *latest, &self.stop_mapping);
+
+        for departure in departures {
+            state.restart(start, departure);
+            let best_arrivals = state.best_arrivals.clone();
+            self.run_rounds(&mut state, start, input)?;
+
+            // Stops that were not reached earlier keep the journey of a later departure
+            let improved = self.local_stop_ids()
+                .filter(|stop| state.best_arrival(stop) < &best_arrivals[stop.0 as usize])
+                .map(|stop| self.stop_mapping.translate_to_global(stop));
+            journeys.extend(improved.filter_map(|stop| state.backtrace(stop, departure).ok()));
         }
 
         // Check if any journeys were found at all
@@ -251,9 +234,41 @@ impl RaptorAlgorithm {
         Ok(RangeOutput { journeys })
     }
 
+    // The distinct times within the range at which to leave the stop to catch a trip, the latest
+    // first. These are the departures at the stop itself and at the stops that are reachable on
+    // foot from it, less the walk to them, so that starts without trips of their own (like the
+    // last stop of a line) are not left out.
+    fn departures_at(
+        &self,
+        stop: LocalStopId,
+        input: &EarliestArrival,
+        earliest: DateTime<Utc>,
+        latest: DateTime<Utc>,
+    ) -> QueryResult<Vec<DateTime<Utc>>> {
+        let cycling = input.transfers(self.transfer_provider.as_ref());
+        let transfer_provider = cycling.as_deref().unwrap_or(self.transfer_provider.as_ref());
+        let mut walks = vec![(stop, TimeDelta::zero())];
+        for end in transfer_provider.transfers_from(&stop) {
+            match transfer_provider.duration(stop, end) {
+                Ok(duration) => walks.push((end, duration)),
+                Err(TransferError::OutOfReach) => {}
+                Err(TransferError::StopNotFound) => unreachable!("We only queried stops returned in provided transfer stops"),
+            }
+        }
+
+        Ok(walks.into_iter()
+            .flat_map(|(stop, walk)| self.timetable.lines_at(stop).iter()
+                .flat_map(|(line, position)| self.timetable.boardings(*line, *position as usize))
+                .map(move |(departure, _)| *departure - walk))
+            .filter(|departure| (earliest..=latest).contains(departure))
+            .sorted_by(|a, b| b.cmp(a))
+            .dedup()
+            .collect())
+    }
+
     fn backtrace_all(&self, state: RaptorState, departure: DateTime<Utc>) -> QueryResult<Vec<Journey>> {
         let journeys = self.local_stop_ids()
-            .map(|stop| state.backtrace(stop, departure))
+            .map(|stop| state.backtrace(self.stop_mapping.translate_to_global(stop), departure))
             .filter_map(|res| res.ok())
             .collect_vec();
 
@@ -265,12 +280,49 @@ impl RaptorAlgorithm {
     }
 }
 
-impl AllEarliestArrival for RaptorAlgorithm {
-    fn query_ea_all(&self, EarliestArrival { start, earliest_departure }: EarliestArrival) -> MultiQueryResult<EarliestArrivalOutput> {
-        let start = self.stop_mapping.translate_to_local(start);
+impl RaptorAlgorithm {
+    // Runs the rounds from the start of a query to its targets, whose labels are kept by their
+    // global id
+    fn run_to(&self, input: &EarliestArrival, targets: &[StopId]) -> QueryResult<RaptorState> {
+        if let Some(unknown) = once(&input.start).chain(targets).find(|stop| !self.stop_mapping.0.contains(stop)) {
+            return Err(QueryError::StopNotFound(*unknown));
+        }
+        self.run(self.stop_mapping.translate_to_local(input.start), input)
+    }
+}
+
+impl SingleEarliestArrival for RaptorAlgorithm {
+    fn query_ea(&self, input: EarliestArrival, Single { target }: Single) -> QueryResult<EarliestArrivalOutput> {
+        let res_state = self.run_to(&input, &[target])?;
+        let journey = res_state.backtrace(target, input.earliest_departure)?;
+        Ok(EarliestArrivalOutput { journey })
+    }
+}
 
-        let res_state = self.run(start, earliest_departure)?;
-        let journeys = self.backtrace_all(res_state, earliest_departure)?;
+impl SinglePareto for RaptorAlgorithm {
+    fn query_pareto(&self, input: EarliestArrival, Single { target }: Single) -> QueryResult<ParetoOutput> {
+        let res_state = self.run_to(&input, &[target])?;
+        let journeys = res_state.backtrace_pareto(target, input.earliest_departure)?;
+        Ok(ParetoOutput { journeys })
+    }
+}
+
+impl MultiEarliestArrival for RaptorAlgorithm {
+    /// A single run for all targets, targets that can't be reached are left out
+    fn query_ea_multi(&self, input: EarliestArrival, Multiple { targets }: Multiple) -> MultiQueryResult<EarliestArrivalOutput> {
+        let res_state = self.run_to(&input, targets)?;
+        let result = targets.iter()
+            .filter_map(|target| res_state.backtrace(*target, input.earliest_departure).ok())
+            .map(|journey| EarliestArrivalOutput { journey })
+            .collect();
+        Ok(result)
+    }
+}
+
+impl AllEarliestArrival for RaptorAlgorithm {
+    fn query_ea_all(&self, input: EarliestArrival) -> MultiQueryResult<EarliestArrivalOutput> {
+        let res_state = self.run_to(&input, &[])?;
+        let journeys = self.backtrace_all(res_state, input.earliest_departure)?;
         let result = journeys.into_iter()
             .map(|journey| EarliestArrivalOutput { journey })
             .collect();
@@ -279,10 +331,27 @@ impl AllEarliestArrival for RaptorAlgorithm {
 }
 
 impl AllRange for RaptorAlgorithm {
-    fn query_range_all(&self, Range { earliest_departure, range, start }: Range) -> QueryResult<RangeOutput> {
+    fn query_range_all(&self, Range { earliest_departure, range, start, options }: Range) -> QueryResult<RangeOutput> {
+        if let Some(unknown) = once(&start).find(|stop| !self.stop_mapping.0.contains(stop)) {
+            return Err(QueryError::StopNotFound(*unknown));
+        }
+        let input = EarliestArrival::new(start, earliest_departure).with_options(&options);
         let start = self.stop_mapping.translate_to_local(start);
 
-        self.run_range(start, earliest_departure, range)
+        self.run_range(start, &input, range)
+    }
+}
+
+impl SingleRange for RaptorAlgorithm {
+    /// The profile of all targets, of which only the journeys to this one are kept
+    fn query_range(&self, range: Range, Single { target }: Single) -> QueryResult<RangeOutput> {
+        let journeys: HashSet<Journey> = self.query_range_all(range)?.journeys.into_iter()
+            .filter(|journey| *journey.arrival_stop() == target)
+            .collect();
+        match journeys.is_empty() {
+            true => Err(QueryError::NoRouteFound),
+            false => Ok(RangeOutput { journeys }),
+        }
     }
 }
 
@@ -295,6 +364,8 @@ mod tests {
     use crate::raptor::tests::generate_case_4;
     use crate::raptor::StopMapping;
     use crate::transfers::fixed_time::FixedTimeTransferProvider;
+    use chrono::Duration;
+    use common::types::dense_ids::DenseIds;
     use common::util::duration;
     use hashbrown::{HashMap, HashSet};
     use ndarray::array;
@@ -304,22 +375,20 @@ mod tests {
     fn case1() -> RaptorAlgorithm {
         RaptorAlgorithm {
             stop_mapping: StopMapping(vec![0, 1].into_iter().map(|x| StopId(x)).collect()),
-            stops_by_line: HashMap::from([
-                (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)])
-            ]),
-            lines_by_stops: HashMap::from([
-                (StopId(0), HashSet::from([(LineId(0), SeqNum(0))])),
-                (StopId(1), HashSet::from([(LineId(0), SeqNum(1))])),
-            ]),
-            arrivals: HashMap::from([
-                ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap())
-            ]),
-            departures: HashMap::from([
-                ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap())
-            ]),
-            trips_by_line_and_stop: HashMap::from([
-                ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
-            ]),
+            timetable: Timetable::from(LookupTables {
+                stops_by_line: HashMap::from([
+                    (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)])
+                ]),
+                arrivals: HashMap::from([
+                    ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap())
+                ]),
+                departures: HashMap::from([
+                    ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap())
+                ]),
+                trips_by_line_and_stop: HashMap::from([
+                    ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
+                ]),
+            }),
             transfer_provider: Box::new(FixedTimeTransferProvider {
                 duration_matrix: array![
                     [Duration::zero(), Duration::max_value(),],
@@ -343,27 +412,24 @@ mod tests {
     fn test_earliest_trip_function() {
         let raptor = RaptorAlgorithm {
             stop_mapping: StopMapping(vec![0, 1, 2].into_iter().map(|x| StopId(x)).collect()),
-            stops_by_line: HashMap::from([
-                (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)]),
-                (LineId(1), vec![(StopId(1), 0), (StopId(2), 0)]),
-            ]),
-            lines_by_stops: HashMap::from([
-                (StopId(0), HashSet::from([(LineId(0), SeqNum(0))])),
-                (StopId(1), HashSet::from([(LineId(0), SeqNum(1)), (LineId(1), SeqNum(0))])),
-                (StopId(2), HashSet::from([(LineId(1), SeqNum(1))])),
-            ]),
-            departures: HashMap::from([
-                ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap()),
-                ((TripId(1), StopId(1), 0), DateTime::<Utc>::from_timestamp(1000, 0).unwrap()),
-            ]),
-            arrivals: HashMap::from([
-                ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap()),
-                ((TripId(1), StopId(2), 0), DateTime::<Utc>::from_timestamp(1500, 0).unwrap()),
-            ]),
-            trips_by_line_and_stop: HashMap::from([
-                ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
-                ((LineId(1), StopId(1)), vec![(DateTime::<Utc>::from_timestamp(1000, 0).unwrap(), TripId(1))]),
-            ]),
+            timetable: Timetable::from(LookupTables {
+                stops_by_line: HashMap::from([
+                    (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)]),
+                    (LineId(1), vec![(StopId(1), 0), (StopId(2), 0)]),
+                ]),
+                departures: HashMap::from([
+                    ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap()),
+                    ((TripId(1), StopId(1), 0), DateTime::<Utc>::from_timestamp(1000, 0).unwrap()),
+                ]),
+                arrivals: HashMap::from([
+                    ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap()),
+                    ((TripId(1), StopId(2), 0), DateTime::<Utc>::from_timestamp(1500, 0).unwrap()),
+                ]),
+                trips_by_line_and_stop: HashMap::from([
+                    ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
+                    ((LineId(1), StopId(1)), vec![(DateTime::<Utc>::from_timestamp(1000, 0).unwrap(), TripId(1))]),
+                ]),
+            }),
             transfer_provider: Box::new(FixedTimeTransferProvider {
                 duration_matrix: array![
                     [Duration::zero(), duration::INFINITY, duration::INFINITY,],
@@ -372,28 +438,39 @@ mod tests {
                 ]
             }),
         };
+        let earliest_trip = |line: LineId, stop: StopId, after: DateTime<Utc>, suspended: &HashSet<TripId>| -> Option<TripId> {
+            let line = raptor.timetable.line(line)?;
+            let position = raptor.timetable.stops(line).iter().position(|(other, _)| *other == stop)?;
+            raptor.timetable.earliest_trip(line, position, after, |trip, _| !suspended.contains(&trip)).map(|trip| raptor.timetable.trip(line, trip))
+        };
 
         assert_eq!(
-            raptor.earliest_trip(LineId(0), StopId(0), DateTime::<Utc>::from_timestamp(0, 0).unwrap()),
+            earliest_trip(LineId(0), StopId(0), DateTime::<Utc>::from_timestamp(0, 0).unwrap(), &HashSet::new()),
             Some(TripId(0))
         );
         assert_eq!(
-            raptor.earliest_trip(LineId(0), StopId(0), DateTime::<Utc>::from_timestamp(100, 0).unwrap()),
+            earliest_trip(LineId(0), StopId(0), DateTime::<Utc>::from_timestamp(100, 0).unwrap(), &HashSet::new()),
             Some(TripId(0))
         );
         assert_eq!(
-            raptor.earliest_trip(LineId(0), StopId(0), DateTime::<Utc>::from_timestamp(100, 1).unwrap()),
+            earliest_trip(LineId(0), StopId(0), DateTime::<Utc>::from_timestamp(100, 1).unwrap(), &HashSet::new()),
+            None
+        );
+
+        // The only trip of line 0 is suspended
+        assert_eq!(
+            earliest_trip(LineId(0), StopId(0), DateTime::<Utc>::from_timestamp(0, 0).unwrap(), &HashSet::from([TripId(0)])),
             None
         );
 
         // Stop 2 is not served by Line 0
         assert_eq!(
-            raptor.earliest_trip(LineId(0), StopId(2), DateTime::<Utc>::from_timestamp(0, 1).unwrap()),
+            earliest_trip(LineId(0), StopId(2), DateTime::<Utc>::from_timestamp(0, 1).unwrap(), &HashSet::new()),
             None
         );
         // Stop 2 is the terminus of Line 1, so there is no trip departing from there at any time
         assert_eq!(
-            raptor.earliest_trip(LineId(1), StopId(2), DateTime::<Utc>::from_timestamp(0, 1).unwrap()),
+            earliest_trip(LineId(1), StopId(2), DateTime::<Utc>::from_timestamp(0, 1).unwrap(), &HashSet::new()),
             None
         );
     }
@@ -403,7 +480,7 @@ mod tests {
         let dep0 = DateTime::<Utc>::from_timestamp(0, 0).unwrap();
 
         let raptor = generate_case_4();
-        let res = raptor.run(StopId(0), None, dep0).unwrap();
+        let res = raptor.run(StopId(0), &EarliestArrival::new(StopId(0), dep0)).unwrap();
 
         // The k value that is reached after finding a way to all other stops
         // It's 3 since going to 1 or 4 takes two legs, going to 2 or 3 just takes one leg, and we
@@ -434,7 +511,7 @@ mod tests {
 
         for i in 0u32..3 {
             let stop_id = StopId(i);
-            let res_single = raptor.run(StopId(0), Some(StopId(4)), dep0).expect("expected this to work");
+            let res_single = raptor.run_to(&EarliestArrival::new(StopId(0), dep0), &[StopId(4)]).expect("expected this to work");
             assert_eq!(
                 res.best_arrivals[i as usize], res_single.best_arrivals[i as usize],
                 "Best arrivals were different between one to one and one to all for StopId(0) to {stop_id:?}"
@@ -472,7 +549,7 @@ mod tests {
                     ])
                 )
             ]),
-            stop_mapping: &StopMapping(vec![StopId(0), StopId(1)])
+            stop_mapping: &StopMapping(DenseIds::new([StopId(0), StopId(1)]))
         };
 
         let res = case1().backtrace_all(state, DateTime::UNIX_EPOCH).unwrap();
@@ -486,21 +563,21 @@ mod tests {
 
         // Query a too short range starting from 0
         let res = raptor.query_range(
-            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(98), start: StopId(0) },
+            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(98), start: StopId(0), options: Default::default() },
             Single { target: StopId(1) },
         );
         assert!(matches!(res, Err(QueryError::NoRouteFound)));
 
         // Query a longer range starting from 0
         let res = raptor.query_range(
-            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(101), start: StopId(0) },
+            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(101), start: StopId(0), options: Default::default() },
             Single { target: StopId(1) },
         ).unwrap();
         assert_eq!(res.journeys, HashSet::from([Journey::from(vec![case1_journey0_leg0()])]));
 
         // query later, after missing the only connection there is
         let res = raptor.query_range(
-            Range { earliest_departure: DateTime::<Utc>::from_timestamp(300, 0).unwrap(), range: Duration::weeks(42), start: StopId(0) },
+            Range { earliest_departure: DateTime::<Utc>::from_timestamp(300, 0).unwrap(), range: Duration::weeks(42), start: StopId(0), options: Default::default() },
             Single { target: StopId(1) },
         );
         assert!(matches!(res, Err(QueryError::NoRouteFound)));
@@ -512,20 +589,26 @@ mod tests {
 
         // Query a too short range starting from 0
         let res = raptor.query_range_all(
-            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(98), start: StopId(0) },
+            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(98), start: StopId(0), options: Default::default() },
         );
         assert!(matches!(res, Err(QueryError::NoRouteFound)));
 
         // Query a longer range starting from 0
         let res = raptor.query_range_all(
-            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(101), start: StopId(0) },
+            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(101), start: StopId(0), options: Default::default() },
         ).unwrap();
         assert_eq!(res.journeys, HashSet::from([Journey::from( vec![case1_journey0_leg0()] )]));
 
         // query later, after missing the only connection there is
         let res = raptor.query_range_all(
-            Range { earliest_departure: DateTime::<Utc>::from_timestamp(300, 0).unwrap(), range: Duration::weeks(42), start: StopId(0) },
+            Range { earliest_departure: DateTime::<Utc>::from_timestamp(300, 0).unwrap(), range: Duration::weeks(42), start: StopId(0), options: Default::default() },
         );
         assert!(matches!(res, Err(QueryError::NoRouteFound)));
+
+        // Stop 2 is not in the timetable
+        let res = raptor.query_range_all(
+            Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(101), start: StopId(2), options: Default::default() },
+        );
+        assert!(matches!(res, Err(QueryError::StopNotFound(StopId(2)))));
     }
 }
\ No newline at end of file
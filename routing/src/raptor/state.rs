@@ -1,5 +1,6 @@
 use crate::algorithm::QueryError::NoRouteFound;
 use crate::journey::Leg;
+use itertools::Itertools;
 
 use super::*;
 
@@ -52,7 +53,24 @@ impl <'a> RaptorState<'a> {
         // Set earliest arrival time with the current num_legs to the same value as for previous
         // number of legs (so where it was num_legs - 1).
         // This acts as an upper bound for the arrival time.
-        self.k_arrivals.push(self.k_arrivals.last().unwrap().clone());
+        if self.k < self.k_arrivals.len() {
+            // The round was already run for a later departure, whose arrivals are upper bounds too
+            let (previous, current) = self.k_arrivals.split_at_mut(self.k);
+            current[0].iter_mut()
+                .zip(&previous[self.k - 1])
+                .for_each(|(arrival, previous)| *arrival = (*arrival).min(*previous));
+        } else {
+            self.k_arrivals.push(self.k_arrivals[self.k - 1].clone());
+        }
+    }
+
+    /// Starts the rounds over for an earlier departure of a range query. The arrivals of later
+    /// departures are kept, since journeys that depart earlier have to arrive earlier to be better.
+    pub fn restart(&mut self, start: LocalStopId, departure: DateTime<Utc>) {
+        let start_idx = start.0 as usize;
+        self.k = 0;
+        self.k_arrivals[0][start_idx] = self.k_arrivals[0][start_idx].min(departure);
+        self.best_arrivals[start_idx] = self.best_arrivals[start_idx].min(departure);
     }
 
     // τ_k(stop)
@@ -166,14 +184,48 @@ impl <'a> RaptorState<'a> {
             }
         }
 
-        // TODO: Return pareto-set of k's versus duration
-        // Determine the fastest route by calculating the final arrival time at the destination
+        // Determine the fastest route (see backtrace_pareto for the trade-off with the number of
+        // rides) by calculating the final arrival time at the destination
         let fastest_journey = journeys.into_iter()
             .min_by_key(|journey| journey.arrival_when_starting_at(departure));
 
         fastest_journey.ok_or(NoRouteFound)
     }
 
+    /// The journeys to the target that are Pareto-optimal with respect to arrival and number of
+    /// rides: each one arrives earlier than all journeys with fewer rides. Sorted by the number of
+    /// rides, so the last one arrives first.
+    pub fn backtrace_pareto(&self, target: GlobalStopId, departure: DateTime<Utc>) -> QueryResult<Vec<Journey>> {
+        let ks_until_target = self.connection_index.get(&target).ok_or(NoRouteFound)?.keys();
+
+        let journeys = ks_until_target
+            .filter_map(|k| self.extract_journey(*k, target))
+            .filter_map(|journey| {
+                let rides = journey.legs().filter(|leg| matches!(leg, Leg::Ride { .. })).count();
+                Some((rides, journey.arrival_when_starting_at(departure)?, journey))
+            })
+            .sorted_by_key(|(rides, arrival, _)| (*rides, *arrival));
+
+        let mut pareto_set: Vec<(DateTime<Utc>, Journey)> = vec![];
+        for (_, arrival, journey) in journeys {
+            if pareto_set.last().is_none_or(|(best_arrival, _)| arrival < *best_arrival) {
+                pareto_set.push((arrival, journey));
+            }
+        }
+
+        if pareto_set.is_empty() {
+            return Err(NoRouteFound);
+        }
+        Ok(pareto_set.into_iter().map(|(_, journey)| journey).collect())
+    }
+
+    // The leg of the latest round up to k that reached the stop. A stop that wasn't improved in
+    // round k keeps its arrival of an earlier round.
+    fn leg_until_round(&self, stop: GlobalStopId, k: usize) -> Option<(usize, &Leg)> {
+        let legs = self.connection_index.get(&stop)?;
+        (0..=k).rev().find_map(|round| Some((round, legs.get(&round)?)))
+    }
+
     fn extract_journey(&self, k: usize, target: GlobalStopId) -> Option<Journey> {
         let mut legs: Vec<Leg> = vec![];
 
@@ -184,7 +236,8 @@ impl <'a> RaptorState<'a> {
         // time will be used to figure out whether a journey is actually feasible
         let mut time = None;
 
-        while let Some(Some(leg)) = self.connection_index.get(&curr_dest).map(|x| x.get(&k)) {
+        while let Some((round, leg)) = self.leg_until_round(curr_dest, k) {
+            k = round;
             match leg {
                 Leg::Ride { alight_time: arrival, boarding_time: departure, .. } => {
                     // Only decrement k if the leg was a ride, since RAPTOR's rounds don't count
@@ -231,7 +284,7 @@ mod tests {
     #[test]
     fn test_init() {
         let departure = DateTime::from_str("2042-06-24T12:00:00Z").unwrap();
-        let stop_mapping = StopMapping(vec![StopId(0), StopId(1), StopId(2), StopId(3)]);
+        let stop_mapping = StopMapping(DenseIds::new([StopId(0), StopId(1), StopId(2), StopId(3)]));
         let mut res = RaptorState::init(4, StopId(2), departure, &stop_mapping);
 
         assert_eq!(res.k, 0);
@@ -255,7 +308,7 @@ mod tests {
     #[test]
     fn test_new_round() {
         let departure = DateTime::from_str("2042-06-24T12:00:00Z").unwrap();
-        let stop_mapping = StopMapping(vec![StopId(42), StopId(31)]);
+        let stop_mapping = StopMapping(DenseIds::new([StopId(42), StopId(31)]));
         let mut state = RaptorState::init(2, StopId(0), departure, &stop_mapping);
 
         assert_eq!(state.tau(&StopId(0)), Some(&departure));
@@ -272,4 +325,38 @@ mod tests {
             state.previous_tau(&StopId(1))
         );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_backtrace_pareto() {
+        let at = |minute: i64| DateTime::<Utc>::UNIX_EPOCH + Duration::minutes(minute);
+        let ride = |trip: u32, from: u32, to: u32, departure: i64, arrival: i64| Leg::Ride {
+            trip: TripId(trip),
+            boarding_stop: StopId(from),
+            alight_stop: StopId(to),
+            boarding_time: at(departure),
+            alight_time: at(arrival),
+        };
+
+        // A direct ride to stop 2 arrives at 60 in round 1, changing at stop 1 arrives at 40 in
+        // round 2. The ride of round 3 also changes at stop 1 (reached in round 1), but arrives
+        // later with as many rides.
+        let stop_mapping = StopMapping(DenseIds::new([StopId(0), StopId(1), StopId(2)]));
+        let mut state = RaptorState::init(3, StopId(0), at(0), &stop_mapping);
+        state.connection_index = HashMap::from([
+            (StopId(1), HashMap::from([(1, ride(1, 0, 1, 0, 10))])),
+            (StopId(2), HashMap::from([
+                (1, ride(0, 0, 2, 0, 60)),
+                (2, ride(2, 1, 2, 20, 40)),
+                (3, ride(3, 1, 2, 30, 45)),
+            ])),
+        ]);
+
+        let journeys = state.backtrace_pareto(StopId(2), at(0)).unwrap();
+        assert_eq!(journeys, vec![
+            Journey::from(vec![ride(0, 0, 2, 0, 60)]),
+            Journey::from(vec![ride(1, 0, 1, 0, 10), ride(2, 1, 2, 20, 40)]),
+        ]);
+
+        assert!(state.backtrace_pareto(StopId(1), at(20)).is_err());
+    }
+}
@@ -1,12 +1,11 @@
-use crate::raptor::{RaptorAlgorithm, StopMapping};
+use crate::raptor::{LookupTables, RaptorAlgorithm, StopMapping, Timetable};
 use crate::transfers::fixed_time::FixedTimeTransferProvider;
 use chrono::{DateTime, Duration, Utc};
-use common::types::{LineId, SeqNum, StopId, TripId};
+use common::types::{LineId, StopId, TripId};
 use common::util::duration::INFINITY;
-use hashbrown::{HashMap, HashSet};
+use hashbrown::HashMap;
 use ndarray::array;
 
-#[cfg(test)]
 #[allow(clippy::inconsistent_digit_grouping)]
 /// Test case 4 has some specialties:
 /// - Stop 3 and 4 are quite close together, so walking between them is feasible
@@ -34,70 +33,65 @@ pub(crate) fn generate_case_4() -> RaptorAlgorithm {
 
     RaptorAlgorithm {
         stop_mapping: StopMapping(vec![0, 1, 2, 3, 4].into_iter().map(StopId).collect()),
-        stops_by_line: HashMap::from([
-            // Line 100: 0 --> 2 --> 3
-            (LineId(100), vec![(StopId(0), 0), (StopId(2), 0), (StopId(3), 0)]),
-            // Line 101: 1 <-- 2 <-- 3
-            (LineId(101), vec![(StopId(3), 0), (StopId(2), 0), (StopId(1), 0)]),
-            // Line 120: 1 --> 2 --> 4
-            (LineId(120), vec![(StopId(1), 0), (StopId(2), 0), (StopId(4), 0)]),
-            // Line 130 ("express line"): 0 --> 3
-            (LineId(130), vec![(StopId(0), 0), (StopId(3), 0)]),
-        ]),
-        lines_by_stops: HashMap::from([
-            (StopId(0), HashSet::from([(LineId(100), SeqNum(0)), (LineId(130), SeqNum(0))])),
-            (StopId(1), HashSet::from([(LineId(101), SeqNum(2)), (LineId(120), SeqNum(0))])),
-            (StopId(2), HashSet::from([(LineId(100), SeqNum(1)), (LineId(101), SeqNum(1)), (LineId(120), SeqNum(1))])),
-            (StopId(3), HashSet::from([(LineId(100), SeqNum(2)), (LineId(101), SeqNum(0)), (LineId(130), SeqNum(1))])),
-            (StopId(4), HashSet::from([(LineId(120), SeqNum(2))])),
-        ]),
-        departures: HashMap::from([
-            // Line 100
-            ((TripId(100_1), StopId(0), 0), dep20),
-            ((TripId(100_1), StopId(2), 0), dep110),
-            ((TripId(100_2), StopId(0), 0), dep220),
-            ((TripId(100_2), StopId(2), 0), dep310),
-            // Line 101
-            ((TripId(101_1), StopId(3), 0), dep20),
-            ((TripId(101_1), StopId(2), 0), dep110),
-            ((TripId(101_2), StopId(3), 0), dep220),
-            ((TripId(101_2), StopId(2), 0), dep310),
-            // Line 120
-            ((TripId(120_1), StopId(1), 0), dep0),
-            ((TripId(120_1), StopId(2), 0), dep90),
-            ((TripId(120_2), StopId(1), 0), dep400),
-            ((TripId(120_2), StopId(2), 0), dep490),
-            // Line 130
-            ((TripId(130_1), StopId(0), 0), dep0),
-        ]),
-        arrivals: HashMap::from([
-            // Line 100
-            ((TripId(100_1), StopId(2), 0), arr100),
-            ((TripId(100_1), StopId(3), 0), arr300),
-            ((TripId(100_2), StopId(2), 0), arr150),
-            ((TripId(100_2), StopId(3), 0), arr350),
-            // Line 101
-            ((TripId(101_1), StopId(2), 0), arr100),
-            ((TripId(101_1), StopId(1), 0), arr150),
-            ((TripId(101_2), StopId(2), 0), arr300),
-            ((TripId(101_2), StopId(1), 0), arr350),
-            // Line 120
-            ((TripId(120_1), StopId(2), 0), arr80),
-            ((TripId(120_1), StopId(4), 0), arr300),
-            ((TripId(120_2), StopId(2), 0), arr480),
-            ((TripId(120_2), StopId(4), 0), arr700),
-            // Line 130
-            ((TripId(130_1), StopId(3), 0), arr250),
-        ]),
-        trips_by_line_and_stop: HashMap::from([
-            ((LineId(100), StopId(0)), vec![(dep20, TripId(100_1)), (dep220, TripId(100_2))]),
-            ((LineId(100), StopId(2)), vec![(dep110, TripId(100_1)), (dep310, TripId(100_2))]),
-            ((LineId(101), StopId(3)), vec![(dep20, TripId(101_1)), (dep220, TripId(101_2))]),
-            ((LineId(101), StopId(2)), vec![(dep110, TripId(101_1)), (dep310, TripId(101_2))]),
-            ((LineId(120), StopId(1)), vec![(dep0, TripId(120_1)), (dep400, TripId(120_2))]),
-            ((LineId(120), StopId(2)), vec![(dep90, TripId(120_1)), (dep490, TripId(120_2))]),
-            ((LineId(130), StopId(0)), vec![(dep0, TripId(130_1))]),
-        ]),
+        timetable: Timetable::from(LookupTables {
+            stops_by_line: HashMap::from([
+                // Line 100: 0 --> 2 --> 3
+                (LineId(100), vec![(StopId(0), 0), (StopId(2), 0), (StopId(3), 0)]),
+                // Line 101: 1 <-- 2 <-- 3
+                (LineId(101), vec![(StopId(3), 0), (StopId(2), 0), (StopId(1), 0)]),
+                // Line 120: 1 --> 2 --> 4
+                (LineId(120), vec![(StopId(1), 0), (StopId(2), 0), (StopId(4), 0)]),
+                // Line 130 ("express line"): 0 --> 3
+                (LineId(130), vec![(StopId(0), 0), (StopId(3), 0)]),
+            ]),
+            departures: HashMap::from([
+                // Line 100
+                ((TripId(100_1), StopId(0), 0), dep20),
+                ((TripId(100_1), StopId(2), 0), dep110),
+                ((TripId(100_2), StopId(0), 0), dep220),
+                ((TripId(100_2), StopId(2), 0), dep310),
+                // Line 101
+                ((TripId(101_1), StopId(3), 0), dep20),
+                ((TripId(101_1), StopId(2), 0), dep110),
+                ((TripId(101_2), StopId(3), 0), dep220),
+                ((TripId(101_2), StopId(2), 0), dep310),
+                // Line 120
+                ((TripId(120_1), StopId(1), 0), dep0),
+                ((TripId(120_1), StopId(2), 0), dep90),
+                ((TripId(120_2), StopId(1), 0), dep400),
+                ((TripId(120_2), StopId(2), 0), dep490),
+                // Line 130
+                ((TripId(130_1), StopId(0), 0), dep0),
+            ]),
+            arrivals: HashMap::from([
+                // Line 100
+                ((TripId(100_1), StopId(2), 0), arr100),
+                ((TripId(100_1), StopId(3), 0), arr300),
+                ((TripId(100_2), StopId(2), 0), arr150),
+                ((TripId(100_2), StopId(3), 0), arr350),
+                // Line 101
+                ((TripId(101_1), StopId(2), 0), arr100),
+                ((TripId(101_1), StopId(1), 0), arr150),
+                ((TripId(101_2), StopId(2), 0), arr300),
+                ((TripId(101_2), StopId(1), 0), arr350),
+                // Line 120
+                ((TripId(120_1), StopId(2), 0), arr80),
+                ((TripId(120_1), StopId(4), 0), arr300),
+                ((TripId(120_2), StopId(2), 0), arr480),
+                ((TripId(120_2), StopId(4), 0), arr700),
+                // Line 130
+                ((TripId(130_1), StopId(3), 0), arr250),
+            ]),
+            trips_by_line_and_stop: HashMap::from([
+                ((LineId(100), StopId(0)), vec![(dep20, TripId(100_1)), (dep220, TripId(100_2))]),
+                ((LineId(100), StopId(2)), vec![(dep110, TripId(100_1)), (dep310, TripId(100_2))]),
+                ((LineId(101), StopId(3)), vec![(dep20, TripId(101_1)), (dep220, TripId(101_2))]),
+                ((LineId(101), StopId(2)), vec![(dep110, TripId(101_1)), (dep310, TripId(101_2))]),
+                ((LineId(120), StopId(1)), vec![(dep0, TripId(120_1)), (dep400, TripId(120_2))]),
+                ((LineId(120), StopId(2)), vec![(dep90, TripId(120_1)), (dep490, TripId(120_2))]),
+                ((LineId(130), StopId(0)), vec![(dep0, TripId(130_1))]),
+            ]),
+        }),
         transfer_provider: Box::new(FixedTimeTransferProvider {
             duration_matrix: array![
                 [Duration::zero(), INFINITY, INFINITY,  INFINITY, INFINITY],
@@ -116,7 +110,8 @@ macro_rules! earliest_arrival_tests {
         use chrono::{DateTime, Utc};
         
         use crate::algorithm::{ EarliestArrival, Single, SingleEarliestArrival};
-        use common::types::{LineId, SeqNum, StopId, TripId};
+        use crate::raptor::{LookupTables, Timetable};
+        use common::types::{LineId, StopId, TripId};
         
         ///  0 ---Ride--> 1
         #[tokio::test]
@@ -124,22 +119,20 @@ macro_rules! earliest_arrival_tests {
             // todo: let algorithm = <$t>::preprocess(todo!(), todo!()).unwrap();
             let raptor = RaptorAlgorithm {
                 stop_mapping: StopMapping(vec![0, 1].into_iter().map(|x| StopId(x)).collect()),
-                stops_by_line: HashMap::from([
-                    (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)])
-                ]),
-                lines_by_stops: HashMap::from([
-                    (StopId(0), HashSet::from([(LineId(0), SeqNum(0))])),
-                    (StopId(1), HashSet::from([(LineId(0), SeqNum(1))])),
-                ]),
-                arrivals: HashMap::from([
-                    ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap())
-                ]),
-                departures: HashMap::from([
-                    ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap())
-                ]),
-                trips_by_line_and_stop: HashMap::from([
-                    ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
-                ]),
+                timetable: Timetable::from(LookupTables {
+                    stops_by_line: HashMap::from([
+                        (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)])
+                    ]),
+                    arrivals: HashMap::from([
+                        ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap())
+                    ]),
+                    departures: HashMap::from([
+                        ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap())
+                    ]),
+                    trips_by_line_and_stop: HashMap::from([
+                        ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
+                    ]),
+                }),
                 transfer_provider: Box::new(FixedTimeTransferProvider {
                     duration_matrix: array![
                         [Duration::zero(), Duration::max_value(),],
@@ -149,7 +142,7 @@ macro_rules! earliest_arrival_tests {
             };
 
             let res = raptor.query_ea(
-                EarliestArrival { start: StopId(0), earliest_departure: DateTime::UNIX_EPOCH },
+                EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH),
                 Single { target: StopId(1) },
             ).unwrap();
 
@@ -164,7 +157,7 @@ macro_rules! earliest_arrival_tests {
 
             // query a little later (missed the only connection there is)
             let res = raptor.query_ea(
-                EarliestArrival { start: StopId(0), earliest_departure: DateTime::<Utc>::from_timestamp(300, 0).unwrap() },
+                EarliestArrival::new(StopId(0), DateTime::<Utc>::from_timestamp(300, 0).unwrap()),
                 Single { target: StopId(1) },
             );
 
@@ -176,27 +169,24 @@ macro_rules! earliest_arrival_tests {
         async fn test_query_earliest_2() {
             let raptor = RaptorAlgorithm {
                 stop_mapping: StopMapping(vec![0, 1, 2].into_iter().map(|x| StopId(x)).collect()),
-                stops_by_line: HashMap::from([
-                    (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)]),
-                    (LineId(1), vec![(StopId(1), 0), (StopId(2), 0)]),
-                ]),
-                lines_by_stops: HashMap::from([
-                    (StopId(0), HashSet::from([(LineId(0), SeqNum(0))])),
-                    (StopId(1), HashSet::from([(LineId(0), SeqNum(1)), (LineId(1), SeqNum(0))])),
-                    (StopId(2), HashSet::from([(LineId(1), SeqNum(1))])),
-                ]),
-                departures: HashMap::from([
-                    ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap()),
-                    ((TripId(1), StopId(1), 0), DateTime::<Utc>::from_timestamp(1000, 0).unwrap()),
-                ]),
-                arrivals: HashMap::from([
-                    ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap()),
-                    ((TripId(1), StopId(2), 0), DateTime::<Utc>::from_timestamp(1500, 0).unwrap()),
-                ]),
-                trips_by_line_and_stop: HashMap::from([
-                    ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
-                    ((LineId(1), StopId(1)), vec![(DateTime::<Utc>::from_timestamp(1000, 0).unwrap(), TripId(1))]),
-                ]),
+                timetable: Timetable::from(LookupTables {
+                    stops_by_line: HashMap::from([
+                        (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)]),
+                        (LineId(1), vec![(StopId(1), 0), (StopId(2), 0)]),
+                    ]),
+                    departures: HashMap::from([
+                        ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap()),
+                        ((TripId(1), StopId(1), 0), DateTime::<Utc>::from_timestamp(1000, 0).unwrap()),
+                    ]),
+                    arrivals: HashMap::from([
+                        ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap()),
+                        ((TripId(1), StopId(2), 0), DateTime::<Utc>::from_timestamp(1500, 0).unwrap()),
+                    ]),
+                    trips_by_line_and_stop: HashMap::from([
+                        ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
+                        ((LineId(1), StopId(1)), vec![(DateTime::<Utc>::from_timestamp(1000, 0).unwrap(), TripId(1))]),
+                    ]),
+                }),
                 transfer_provider: Box::new(FixedTimeTransferProvider {
                     duration_matrix: array![
                         [Duration::zero(),   duration::INFINITY, duration::INFINITY],
@@ -207,7 +197,7 @@ macro_rules! earliest_arrival_tests {
             };
 
             let res = raptor.query_ea(
-                EarliestArrival { start: StopId(0), earliest_departure: DateTime::UNIX_EPOCH },
+                EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH),
                 Single { target: StopId(2) },
             ).unwrap();
 
@@ -238,28 +228,24 @@ macro_rules! earliest_arrival_tests {
             
             let raptor = RaptorAlgorithm {
                 stop_mapping: StopMapping(vec![0, 1, 2, 3].into_iter().map(|x| StopId(x)).collect()),
-                stops_by_line: HashMap::from([
-                    (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)]),
-                    (LineId(1), vec![(StopId(2), 0), (StopId(3), 0)]),
-                ]),
-                lines_by_stops: HashMap::from([
-                    (StopId(0), HashSet::from([(LineId(0), SeqNum(0))])),
-                    (StopId(1), HashSet::from([(LineId(0), SeqNum(1))])),
-                    (StopId(2), HashSet::from([(LineId(1), SeqNum(0))])),
-                    (StopId(3), HashSet::from([(LineId(1), SeqNum(1))])),
-                ]),
-                departures: HashMap::from([
-                    ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap()),
-                    ((TripId(1), StopId(2), 0), DateTime::<Utc>::from_timestamp(1000, 0).unwrap()),
-                ]),
-                arrivals: HashMap::from([
-                    ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap()),
-                    ((TripId(1), StopId(3), 0), DateTime::<Utc>::from_timestamp(1500, 0).unwrap()),
-                ]),
-                trips_by_line_and_stop: HashMap::from([
-                    ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
-                    ((LineId(1), StopId(2)), vec![(DateTime::<Utc>::from_timestamp(1000, 0).unwrap(), TripId(1))]),
-                ]),
+                timetable: Timetable::from(LookupTables {
+                    stops_by_line: HashMap::from([
+                        (LineId(0), vec![(StopId(0), 0), (StopId(1), 0)]),
+                        (LineId(1), vec![(StopId(2), 0), (StopId(3), 0)]),
+                    ]),
+                    departures: HashMap::from([
+                        ((TripId(0), StopId(0), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap()),
+                        ((TripId(1), StopId(2), 0), DateTime::<Utc>::from_timestamp(1000, 0).unwrap()),
+                    ]),
+                    arrivals: HashMap::from([
+                        ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap()),
+                        ((TripId(1), StopId(3), 0), DateTime::<Utc>::from_timestamp(1500, 0).unwrap()),
+                    ]),
+                    trips_by_line_and_stop: HashMap::from([
+                        ((LineId(0), StopId(0)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
+                        ((LineId(1), StopId(2)), vec![(DateTime::<Utc>::from_timestamp(1000, 0).unwrap(), TripId(1))]),
+                    ]),
+                }),
                 transfer_provider: Box::new(FixedTimeTransferProvider {
                     duration_matrix: array![
                         [Duration::zero(),   duration::INFINITY, duration::INFINITY, duration::INFINITY],
@@ -271,7 +257,7 @@ macro_rules! earliest_arrival_tests {
             };
 
             let res = raptor.query_ea(
-                EarliestArrival { start: StopId(0), earliest_departure: DateTime::UNIX_EPOCH },
+                EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH),
                 Single { target: StopId(3) },
             ).unwrap();
 
@@ -326,7 +312,7 @@ macro_rules! earliest_arrival_tests {
             // 0 ---Ride(130_1)--> 3 ---Transfer--> 4
             // Takes 250s + 410s = 660s
             let res = raptor.query_ea(
-                EarliestArrival { start: StopId(0), earliest_departure: dep0 },
+                EarliestArrival::new(StopId(0), dep0),
                 Single { target: StopId(4) },
             ).unwrap();
 
@@ -355,7 +341,7 @@ macro_rules! earliest_arrival_tests {
             // of 700s:
             // 0@20s   ---Ride(100_1)-->   3@300s   ---Transfer-->   4@710s
             let res = raptor.query_ea(
-                EarliestArrival { start: StopId(0), earliest_departure: DateTime::<Utc>::from_timestamp(1, 0).unwrap() },
+                EarliestArrival::new(StopId(0), DateTime::<Utc>::from_timestamp(1, 0).unwrap()),
                 Single { target: StopId(4) },
             ).unwrap();
 
@@ -374,8 +360,90 @@ macro_rules! earliest_arrival_tests {
                     },
                 ])
             );
-            
+
             // TODO: More cases
         }
+
+        ///   0 ---Transfer--> 1 ---Ride--> 2
+        #[tokio::test]
+        async fn test_query_earliest_walk_from_start() {
+            let raptor = walk_from_start();
+
+            let res = raptor.query_ea(
+                EarliestArrival::new(StopId(0), DateTime::UNIX_EPOCH),
+                Single { target: StopId(2) },
+            ).unwrap();
+
+            assert_eq!(res.journey, Journey::from(vec![
+                Leg::Transfer {
+                    start: StopId(0),
+                    end: StopId(1),
+                    duration: Duration::seconds(10),
+                },
+                Leg::Ride {
+                    boarding_stop: StopId(1),
+                    alight_stop: StopId(2),
+                    boarding_time: DateTime::<Utc>::from_timestamp(100, 0).unwrap(),
+                    alight_time: DateTime::<Utc>::from_timestamp(500, 0).unwrap(),
+                    trip: TripId(0),
+                },
+            ]));
+
+            // Leaving 91s later, the walk arrives after the trip departed
+            let res = raptor.query_ea(
+                EarliestArrival::new(StopId(0), DateTime::<Utc>::from_timestamp(91, 0).unwrap()),
+                Single { target: StopId(2) },
+            );
+            assert!(matches!(res, Err(QueryError::NoRouteFound)));
+        }
+
+        /// Stop 0 has no trips of its own, so the range is searched from the times at which to leave
+        /// it to catch the trips of stop 1
+        #[tokio::test]
+        async fn test_query_range_walk_from_start() {
+            let raptor = walk_from_start();
+
+            let res = raptor.query_range(
+                Range { earliest_departure: DateTime::UNIX_EPOCH, range: Duration::seconds(90), start: StopId(0), options: Default::default() },
+                Single { target: StopId(2) },
+            ).unwrap();
+            assert_eq!(res.journeys.len(), 1);
+            assert!(res.journeys.iter().all(|journey| journey.arrival() == DateTime::from_timestamp(500, 0)));
+
+            // The last time to leave stop 0 is 90s
+            let res = raptor.query_range(
+                Range { earliest_departure: DateTime::<Utc>::from_timestamp(91, 0).unwrap(), range: Duration::seconds(1000), start: StopId(0), options: Default::default() },
+                Single { target: StopId(2) },
+            );
+            assert!(matches!(res, Err(QueryError::NoRouteFound)));
+        }
+
+        // Trip 0 runs from stop 1 to 2 at 100s to 500s, stop 0 is a walk of 10s away from stop 1
+        fn walk_from_start() -> RaptorAlgorithm {
+            RaptorAlgorithm {
+                stop_mapping: StopMapping(vec![0, 1, 2].into_iter().map(|x| StopId(x)).collect()),
+                timetable: Timetable::from(LookupTables {
+                    stops_by_line: HashMap::from([
+                        (LineId(0), vec![(StopId(1), 0), (StopId(2), 0)]),
+                    ]),
+                    departures: HashMap::from([
+                        ((TripId(0), StopId(1), 0), DateTime::<Utc>::from_timestamp(100, 0).unwrap()),
+                    ]),
+                    arrivals: HashMap::from([
+                        ((TripId(0), StopId(2), 0), DateTime::<Utc>::from_timestamp(500, 0).unwrap()),
+                    ]),
+                    trips_by_line_and_stop: HashMap::from([
+                        ((LineId(0), StopId(1)), vec![(DateTime::<Utc>::from_timestamp(100, 0).unwrap(), TripId(0))]),
+                    ]),
+                }),
+                transfer_provider: Box::new(FixedTimeTransferProvider {
+                    duration_matrix: array![
+                        [Duration::zero(),      Duration::seconds(10), duration::INFINITY],
+                        [Duration::seconds(10), Duration::zero(),      duration::INFINITY],
+                        [duration::INFINITY,    duration::INFINITY,    Duration::zero()  ],
+                    ]
+                }),
+            }
+        }
     };
 }
\ No newline at end of file
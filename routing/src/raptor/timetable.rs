@@ -0,0 +1,319 @@
+use crate::raptor::{LocalStopId, StopsByLineMap, TripAtStopTimeMap, TripsByLineAndStopMap};
+use chrono::{DateTime, Utc};
+use common::types::dense_ids::DenseIds;
+use common::types::{LineId, TripId};
+use common::util::time::INFINITY;
+use hashbrown::HashMap;
+use itertools::Itertools;
+use std::ops::Range;
+
+/// Index of a line in [Timetable]
+pub(crate) type LineIdx = u32;
+/// Index of a trip among the trips of its line
+pub(crate) type TripIdx = u32;
+
+/// The lookup tables of [RaptorAlgorithm](crate::raptor::RaptorAlgorithm) as maps, which is how
+/// they are built, saved and exported. Queries use them as a [Timetable].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct LookupTables {
+    /// <line_id, [stop_id, visit_idx]>
+    pub(crate) stops_by_line: StopsByLineMap,
+
+    // <(trip_id, stop_id, visit_idx), departure_time>
+    pub(crate) departures: TripAtStopTimeMap,
+    // <(trip_id, stop_id, visit_idx), arrival_time>
+    pub(crate) arrivals: TripAtStopTimeMap,
+
+    // Vec has to be sorted from earliest to latest
+    // DateTime is departure
+    pub(crate) trips_by_line_and_stop: TripsByLineAndStopMap,
+}
+
+/// The lines, trips and stop times in flat arrays, laid out like the routes of the RAPTOR paper.
+/// The stops and the trips of a line are next to each other, with the index of the first of every
+/// line in `*_start`. The times of a line are a matrix of its trips by its stops, so that scanning
+/// a trip along the line reads consecutive times. Lines, trips and the stops on a line are
+/// referred to by their index, so nothing is hashed while routing.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Timetable {
+    // Sorted, the dense id of a line is its LineIdx
+    line_ids: DenseIds<LineId>,
+    // <(stop_id, visit_idx)> of all lines, in the order of the stops along each line
+    line_stops: Vec<(LocalStopId, u32)>,
+    line_stops_start: Vec<u32>,
+    // Trips of all lines, sorted by id within a line
+    line_trips: Vec<TripId>,
+    line_trips_start: Vec<u32>,
+
+    // One row of times per trip of a line, with a column per stop of the line. Times that don't
+    // exist, e.g. the departure at the last stop, are INFINITY.
+    times_start: Vec<u32>,
+    departures: Vec<DateTime<Utc>>,
+    arrivals: Vec<DateTime<Utc>>,
+
+    // <(departure_time, trip)> at each stop of `line_stops`, sorted from earliest to latest. Trips
+    // that can't be boarded anymore depart at INFINITY.
+    boardings: Vec<(DateTime<Utc>, TripIdx)>,
+    boardings_start: Vec<u32>,
+
+    // <(line, position of the stop on the line)> of the lines at each local stop
+    stop_lines: Vec<(LineIdx, u32)>,
+    stop_lines_start: Vec<u32>,
+}
+
+impl Timetable {
+    pub(crate) fn lines(&self) -> Range<LineIdx> {
+        0..self.line_ids.len() as LineIdx
+    }
+
+    /// Index of a line, if the line is in the timetable
+    #[cfg(test)]
+    pub(crate) fn line(&self, line: LineId) -> Option<LineIdx> {
+        self.line_ids.dense(line).map(LineIdx::from)
+    }
+
+    /// [(stop_id, visit_idx)] of a line, in the order of the stops along the line
+    pub(crate) fn stops(&self, line: LineIdx) -> &[(LocalStopId, u32)] {
+        &self.line_stops[slice(&self.line_stops_start, line as usize)]
+    }
+
+    pub(crate) fn trips(&self, line: LineIdx) -> &[TripId] {
+        &self.line_trips[slice(&self.line_trips_start, line as usize)]
+    }
+
+    pub(crate) fn trip(&self, line: LineIdx, trip: TripIdx) -> TripId {
+        self.trips(line)[trip as usize]
+    }
+
+    /// [(line, position of the stop on the line)] of the lines that serve a stop
+    pub(crate) fn lines_at(&self, stop: LocalStopId) -> &[(LineIdx, u32)] {
+        if stop.0 as usize + 1 >= self.stop_lines_start.len() {
+            return &[];
+        }
+        &self.stop_lines[slice(&self.stop_lines_start, stop.0 as usize)]
+    }
+
+    /// The trips that depart at the stop at `position` on a line, from the earliest to the latest
+    pub(crate) fn boardings(&self, line: LineIdx, position: usize) -> &[(DateTime<Utc>, TripIdx)] {
+        &self.boardings[slice(&self.boardings_start, self.line_stops_start[line as usize] as usize + position)]
+    }
+
+    /// Selects the earliest trip of a line that departs at the stop at `position` after a given
+    /// time and that `boardable` accepts by its id and departure, e.g. one that isn't suspended
+    pub(crate) fn earliest_trip(
+        &self,
+        line: LineIdx,
+        position: usize,
+        after: DateTime<Utc>,
+        boardable: impl Fn(TripId, DateTime<Utc>) -> bool,
+    ) -> Option<TripIdx> {
+        let boardings = self.boardings(line, position);
+        boardings[boardings.partition_point(|(departure, _)| *departure < after)..].iter()
+            .take_while(|(departure, _)| *departure != INFINITY)
+            .find(|(departure, trip)| boardable(self.trip(line, *trip), *departure))
+            .map(|(_, trip)| *trip)
+    }
+
+    pub(crate) fn arrival(&self, line: LineIdx, trip: TripIdx, position: usize) -> Option<DateTime<Utc>> {
+        Some(self.arrivals[self.time_idx(line, trip, position)]).filter(|arrival| *arrival != INFINITY)
+    }
+
+    pub(crate) fn departure(&self, line: LineIdx, trip: TripIdx, position: usize) -> Option<DateTime<Utc>> {
+        Some(self.departures[self.time_idx(line, trip, position)]).filter(|departure| *departure != INFINITY)
+    }
+
+    #[cfg(feature = "preprocessing")]
+    pub(crate) fn set_arrival(&mut self, line: LineIdx, trip: TripIdx, position: usize, arrival: Option<DateTime<Utc>>) {
+        let idx = self.time_idx(line, trip, position);
+        self.arrivals[idx] = arrival.unwrap_or(INFINITY);
+    }
+
+    /// Also moves the trip among the boardings at the stop. Without a departure, the trip can't be
+    /// boarded there.
+    #[cfg(feature = "preprocessing")]
+    pub(crate) fn set_departure(&mut self, line: LineIdx, trip: TripIdx, position: usize, departure: Option<DateTime<Utc>>) {
+        let idx = self.time_idx(line, trip, position);
+        self.departures[idx] = departure.unwrap_or(INFINITY);
+
+        let range = slice(&self.boardings_start, self.line_stops_start[line as usize] as usize + position);
+        let boardings = &mut self.boardings[range];
+        if let Some(boarding) = boardings.iter_mut().find(|(_, other)| *other == trip) {
+            boarding.0 = departure.unwrap_or(INFINITY);
+            boardings.sort_unstable();
+        }
+    }
+
+    fn time_idx(&self, line: LineIdx, trip: TripIdx, position: usize) -> usize {
+        self.times_start[line as usize] as usize + trip as usize * self.stops(line).len() + position
+    }
+
+    /// The timetable as maps again. Times and boardings that don't exist anymore are left out.
+    pub(crate) fn tables(&self) -> LookupTables {
+        let mut tables = LookupTables::default();
+        for line in self.lines() {
+            let line_id = self.line_ids.sources()[line as usize];
+            let stops = self.stops(line);
+            tables.stops_by_line.insert(line_id, stops.to_vec());
+
+            for (trip, trip_id) in self.trips(line).iter().enumerate() {
+                for (position, (stop, visit_idx)) in stops.iter().enumerate() {
+                    let key = (*trip_id, *stop, *visit_idx);
+                    if let Some(arrival) = self.arrival(line, trip as TripIdx, position) {
+                        tables.arrivals.insert(key, arrival);
+                    }
+                    if let Some(departure) = self.departure(line, trip as TripIdx, position) {
+                        tables.departures.insert(key, departure);
+                    }
+                }
+            }
+
+            for (position, (stop, _)) in stops.iter().enumerate() {
+                let boardings = self.boardings(line, position).iter()
+                    .filter(|(departure, _)| *departure != INFINITY)
+                    .map(|(departure, trip)| (*departure, self.trip(line, *trip)))
+                    .collect_vec();
+                if !boardings.is_empty() {
+                    tables.trips_by_line_and_stop.entry((line_id, *stop)).or_default().extend(boardings);
+                }
+            }
+        }
+        tables.trips_by_line_and_stop.values_mut().for_each(|trips| trips.sort_unstable());
+        tables
+    }
+}
+
+impl From<LookupTables> for Timetable {
+    /// The trips of a line are those that can be boarded on it. Departures in
+    /// `trips_by_line_and_stop` belong to the visit of the stop that the trip departs at then.
+    fn from(LookupTables { stops_by_line, departures, arrivals, trips_by_line_and_stop }: LookupTables) -> Self {
+        let mut trips_of_lines: HashMap<LineId, Vec<TripId>> = HashMap::new();
+        for ((line, _), trips) in &trips_by_line_and_stop {
+            trips_of_lines.entry(*line).or_default().extend(trips.iter().map(|(_, trip)| *trip));
+        }
+
+        let line_ids = stops_by_line.keys().copied().sorted().collect_vec();
+        let mut timetable = Self {
+            line_ids: DenseIds::default(),
+            line_stops: vec![],
+            line_stops_start: vec![0],
+            line_trips: vec![],
+            line_trips_start: vec![0],
+            times_start: vec![],
+            departures: vec![],
+            arrivals: vec![],
+            boardings: vec![],
+            boardings_start: vec![0],
+            stop_lines: vec![],
+            stop_lines_start: vec![0],
+        };
+
+        for line in line_ids {
+            let stops = &stops_by_line[&line];
+            let trips = trips_of_lines.remove(&line).unwrap_or_default().into_iter().sorted().dedup().collect_vec();
+
+            timetable.times_start.push(timetable.arrivals.len() as u32);
+            for trip in &trips {
+                for (stop, visit_idx) in stops {
+                    let key = (*trip, *stop, *visit_idx);
+                    timetable.arrivals.push(arrivals.get(&key).copied().unwrap_or(INFINITY));
+                    timetable.departures.push(departures.get(&key).copied().unwrap_or(INFINITY));
+                }
+            }
+
+            for (stop, visit_idx) in stops {
+                let boardings = trips_by_line_and_stop.get(&(line, *stop)).into_iter().flatten()
+                    .filter(|(departure, trip)| departures.get(&(*trip, *stop, *visit_idx)) == Some(departure))
+                    .map(|(departure, trip)| (*departure, trips.binary_search(trip).unwrap() as TripIdx))
+                    .sorted_unstable();
+                timetable.boardings.extend(boardings);
+                timetable.boardings_start.push(timetable.boardings.len() as u32);
+            }
+
+            timetable.line_ids.push(line);
+            timetable.line_stops.extend_from_slice(stops);
+            timetable.line_stops_start.push(timetable.line_stops.len() as u32);
+            timetable.line_trips.extend(trips);
+            timetable.line_trips_start.push(timetable.line_trips.len() as u32);
+        }
+
+        let num_stops = timetable.line_stops.iter().map(|(stop, _)| stop.0 as usize + 1).max().unwrap_or_default();
+        let mut lines_at_stops = vec![vec![]; num_stops];
+        for line in timetable.lines() {
+            for (position, (stop, _)) in timetable.stops(line).iter().enumerate() {
+                lines_at_stops[stop.0 as usize].push((line, position as u32));
+            }
+        }
+        for lines in lines_at_stops {
+            timetable.stop_lines.extend(lines);
+            timetable.stop_lines_start.push(timetable.stop_lines.len() as u32);
+        }
+
+        timetable
+    }
+}
+
+// The range of the entries of the `idx`th line or stop in a flat array
+fn slice(starts: &[u32], idx: usize) -> Range<usize> {
+    starts[idx] as usize..starts[idx + 1] as usize
+}
+
+#[cfg(test)]
+// Trip ids of the test cases are the id of their line followed by their number
+#[allow(clippy::inconsistent_digit_grouping)]
+mod tests {
+    use super::*;
+    use crate::raptor::tests::generate_case_4;
+    use common::types::StopId;
+
+    #[test]
+    fn test_layout() {
+        let timetable = &generate_case_4().timetable;
+        let line = timetable.line(LineId(120)).unwrap();
+        assert_eq!(timetable.stops(line), [(StopId(1), 0), (StopId(2), 0), (StopId(4), 0)]);
+        assert_eq!(timetable.trips(line), [TripId(120_1), TripId(120_2)]);
+        assert_eq!(timetable.line(LineId(42)), None);
+
+        // Stop 2 is the second stop of lines 100, 101 and 120
+        let lines_at_2 = timetable.lines_at(StopId(2)).iter()
+            .map(|(line, position)| (timetable.line_ids.sources()[*line as usize], *position))
+            .collect_vec();
+        assert_eq!(lines_at_2, [(LineId(100), 1), (LineId(101), 1), (LineId(120), 1)]);
+        assert!(timetable.lines_at(StopId(5)).is_empty());
+
+        let trip = timetable.earliest_trip(line, 1, DateTime::from_timestamp(91, 0).unwrap(), |_, _| true).unwrap();
+        assert_eq!(timetable.trip(line, trip), TripId(120_2));
+        assert_eq!(timetable.departure(line, trip, 1), DateTime::from_timestamp(490, 0));
+        assert_eq!(timetable.arrival(line, trip, 2), DateTime::from_timestamp(700, 0));
+        // The trips end at stop 4
+        assert_eq!(timetable.departure(line, trip, 2), None);
+        assert_eq!(timetable.earliest_trip(line, 2, DateTime::UNIX_EPOCH, |_, _| true), None);
+    }
+
+    #[test]
+    fn test_tables() {
+        let timetable = generate_case_4().timetable;
+        let tables = timetable.tables();
+        assert_eq!(tables.stops_by_line.len(), 4);
+        assert_eq!(tables.arrivals.len(), 13);
+        assert_eq!(Timetable::from(tables), timetable);
+    }
+
+    #[test]
+    #[cfg(feature = "preprocessing")]
+    fn test_set_departure() {
+        let mut timetable = generate_case_4().timetable;
+        let line = timetable.line(LineId(100)).unwrap();
+        let after = DateTime::UNIX_EPOCH;
+        let first = timetable.earliest_trip(line, 0, after, |_, _| true).unwrap();
+        assert_eq!(timetable.trip(line, first), TripId(100_1));
+
+        // Without a departure, the later trip is the earliest to board
+        timetable.set_departure(line, first, 0, None);
+        let second = timetable.earliest_trip(line, 0, after, |_, _| true).unwrap();
+        assert_eq!(timetable.trip(line, second), TripId(100_2));
+        assert!(!timetable.tables().departures.contains_key(&(TripId(100_1), StopId(0), 0)));
+
+        timetable.set_departure(line, first, 0, DateTime::from_timestamp(20, 0));
+        assert_eq!(timetable, generate_case_4().timetable);
+    }
+}
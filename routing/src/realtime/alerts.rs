@@ -0,0 +1,229 @@
+use crate::itinerary::{Itinerary, ItineraryLeg};
+use chrono::{DateTime, TimeDelta, Utc};
+use common::types::id_interner::OriginalIds;
+use common::types::{StopId, TripId};
+use hashbrown::{HashMap, HashSet};
+use std::iter::once;
+
+/// A service alert, as in https://gtfs.org/documentation/realtime/reference/#message-alert
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Alert {
+    pub id: String,
+    // The alert is active during any of them, or always if there are none
+    pub active_periods: Vec<ActivePeriod>,
+    pub informed_entities: Vec<InformedEntity>,
+    pub header: TranslatedText,
+    pub description: TranslatedText,
+    pub url: TranslatedText,
+}
+
+/// Seconds since the UNIX epoch, open if missing
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ActivePeriod {
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+/// What an alert is about. Ids are the ones of the dataset of the feed. Entities that are only
+/// given by their agency or route type don't affect any journey.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InformedEntity {
+    pub route_id: Option<String>,
+    pub trip_id: Option<String>,
+    pub stop_id: Option<String>,
+}
+
+/// Translations of a text by their BCP-47 language code. A translation without language is in the
+/// language of the feed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranslatedText(pub Vec<(Option<String>, String)>);
+
+impl TranslatedText {
+    /// The translation to `language`, otherwise the one without language, otherwise the first one
+    pub fn get(&self, language: Option<&str>) -> Option<&str> {
+        let translation = |wanted: Option<&str>| self.0.iter()
+            .find(|(language, _)| language.as_deref().map(|language| language.to_lowercase()) == wanted.map(str::to_lowercase));
+        language.and_then(|language| translation(Some(language)))
+            .or_else(|| translation(None))
+            .or(self.0.first())
+            .map(|(_, text)| text.as_str())
+    }
+}
+
+/// The latest message of the GTFS-RT alerts feed of a dataset
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AlertsFeed {
+    pub dataset_id: String,
+    pub alerts: Vec<Alert>,
+}
+
+// An informed entity with the ids of the timetable. All given parts have to match. An entity whose
+// route, trip or stop isn't in the timetable can't match anything.
+#[derive(Debug, Default)]
+struct Selector {
+    route: Option<String>,
+    trip: Option<TripId>,
+    stop: Option<StopId>,
+}
+
+/// The alerts of all feeds, looked up by what a leg rides and passes
+pub struct Alerts {
+    alerts: Vec<(Alert, Vec<Selector>)>,
+    // Route ids prefixed with the id of their dataset, like the original ids
+    route_of_trips: HashMap<TripId, String>,
+}
+
+impl Alerts {
+    pub fn new(feeds: Vec<AlertsFeed>, original_ids: &OriginalIds, route_of_trips: HashMap<TripId, String>) -> Self {
+        let mut alerts = vec![];
+        for AlertsFeed { dataset_id, alerts: feed } in feeds {
+            for alert in feed {
+                let selectors = alert.informed_entities.iter().filter_map(|entity| {
+                    let route = entity.route_id.as_ref().map(|route| format!("{dataset_id}:{route}"));
+                    let trip = match &entity.trip_id {
+                        Some(trip) => Some(original_ids.find_trip(&format!("{dataset_id}:{trip}"))?),
+                        None => None,
+                    };
+                    let stop = match &entity.stop_id {
+                        Some(stop) => Some(original_ids.stops.get(&format!("{dataset_id}:{stop}"))?),
+                        None => None,
+                    };
+                    let selector = Selector { route, trip, stop };
+                    (selector.route.is_some() || selector.trip.is_some() || selector.stop.is_some()).then_some(selector)
+                }).collect::<Vec<_>>();
+                if !selectors.is_empty() {
+                    alerts.push((alert, selectors));
+                }
+            }
+        }
+        Self { alerts, route_of_trips }
+    }
+
+    /// The alerts of every leg of the itinerary that are active while the leg is travelled. Rides
+    /// are affected by alerts of their trip and route and of the stops they board, pass or alight
+    /// at, transfers by alerts of their stops. Times of the itinerary are relative to the start of
+    /// the service day.
+    pub fn for_itinerary(&self, itinerary: &Itinerary, service_day_start: DateTime<Utc>) -> Vec<Vec<&Alert>> {
+        let absolute = |time: DateTime<Utc>| service_day_start + (time - DateTime::UNIX_EPOCH);
+        // Transfers before the first ride end when it departs
+        let walk_before_first_ride: TimeDelta = itinerary.legs.iter()
+            .map_while(|leg| match leg {
+                ItineraryLeg::Transfer { duration, .. } => Some(*duration),
+                ItineraryLeg::Ride { .. } => None,
+            })
+            .sum();
+        let mut time = itinerary.legs.iter()
+            .find_map(|leg| match leg {
+                ItineraryLeg::Ride { departure, .. } => Some(*departure - walk_before_first_ride),
+                ItineraryLeg::Transfer { .. } => None,
+            })
+            .unwrap_or(DateTime::UNIX_EPOCH);
+        itinerary.legs.iter()
+            .map(|leg| match leg {
+                ItineraryLeg::Ride { trip, boarding_stop, alight_stop, departure, arrival, intermediate_stops, .. } => {
+                    time = *arrival;
+                    let stops = once(*boarding_stop).chain(intermediate_stops.iter().map(|call| call.stop)).chain(once(*alight_stop)).collect();
+                    self.matching(Some(*trip), &stops, absolute(*departure), absolute(*arrival))
+                }
+                ItineraryLeg::Transfer { start, end, duration } => {
+                    let start_time = absolute(time);
+                    time += *duration;
+                    self.matching(None, &HashSet::from([*start, *end]), start_time, start_time + *duration)
+                }
+            })
+            .collect()
+    }
+
+    fn matching(&self, trip: Option<TripId>, stops: &HashSet<StopId>, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<&Alert> {
+        let route = trip.and_then(|trip| self.route_of_trips.get(&trip));
+        let matches = |selector: &Selector| {
+            selector.trip.is_none_or(|selected| Some(selected) == trip)
+                && selector.route.as_ref().is_none_or(|selected| Some(selected) == route)
+                && selector.stop.is_none_or(|selected| stops.contains(&selected))
+        };
+        self.alerts.iter()
+            .filter(|(alert, selectors)| is_active(alert, from, to) && selectors.iter().any(matches))
+            .map(|(alert, _)| alert)
+            .collect()
+    }
+}
+
+fn is_active(alert: &Alert, from: DateTime<Utc>, to: DateTime<Utc>) -> bool {
+    let seconds = |time: DateTime<Utc>| (time - DateTime::UNIX_EPOCH).num_seconds();
+    alert.active_periods.is_empty() || alert.active_periods.iter().any(|period| {
+        period.start.is_none_or(|start| start <= seconds(to)) && period.end.is_none_or(|end| seconds(from) <= end)
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::itinerary::StopCall;
+    use common::types::id_interner::IdInterner;
+    use common::types::LineId;
+
+    fn alert(id: &str, entity: InformedEntity, active_periods: Vec<ActivePeriod>) -> Alert {
+        Alert { id: id.to_string(), informed_entities: vec![entity], active_periods, ..Default::default() }
+    }
+
+    #[test]
+    fn test_for_itinerary() {
+        let original_ids = OriginalIds {
+            stops: IdInterner::from_originals(["d:a", "d:b", "d:c", "d:e"]),
+            trips: IdInterner::from_originals(["d:1", "d:2"]),
+            ..Default::default()
+        };
+        let route = |route: &str| InformedEntity { route_id: Some(route.to_string()), ..Default::default() };
+        let stop = |stop: &str| InformedEntity { stop_id: Some(stop.to_string()), ..Default::default() };
+        let feed = AlertsFeed {
+            dataset_id: "d".to_string(),
+            alerts: vec![
+                alert("route", route("U1"), vec![]),
+                // Passed during the ride
+                alert("passed", stop("b"), vec![]),
+                // Active after the ride
+                alert("later", route("U1"), vec![ActivePeriod { start: Some(86_400 + 2_000), end: None }]),
+                alert("walk", stop("e"), vec![ActivePeriod { start: Some(86_400 + 1_100), end: Some(86_400 + 1_200) }]),
+                // Unknown ids don't match anything
+                alert("unknown", InformedEntity { trip_id: Some("9".to_string()), ..Default::default() }, vec![]),
+            ],
+        };
+        let alerts = Alerts::new(vec![feed], &original_ids, HashMap::from([(TripId(0), "d:U1".to_string())]));
+
+        let time = |seconds| DateTime::from_timestamp(seconds, 0).unwrap();
+        let itinerary = Itinerary {
+            legs: vec![
+                ItineraryLeg::Ride {
+                    trip: TripId(0),
+                    line: LineId(0),
+                    boarding_stop: StopId(0),
+                    alight_stop: StopId(2),
+                    departure: time(500),
+                    arrival: time(1_000),
+                    intermediate_stops: vec![StopCall { stop: StopId(1), arrival: None, departure: None }],
+                },
+                ItineraryLeg::Transfer { start: StopId(2), end: StopId(3), duration: TimeDelta::seconds(300) },
+            ],
+        };
+
+        // The itinerary runs on the second day after the epoch
+        let ids = alerts.for_itinerary(&itinerary, time(86_400)).iter()
+            .map(|leg| leg.iter().map(|alert| alert.id.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [vec!["route", "passed"], vec!["walk"]]);
+    }
+
+    #[test]
+    fn test_translated_text() {
+        let text = TranslatedText(vec![
+            (Some("de".to_string()), "Umleitung".to_string()),
+            (None, "Detour".to_string()),
+        ]);
+        assert_eq!(text.get(Some("DE")), Some("Umleitung"));
+        assert_eq!(text.get(Some("fr")), Some("Detour"));
+        assert_eq!(text.get(None), Some("Detour"));
+        assert_eq!(TranslatedText(vec![(Some("de".to_string()), "Umleitung".to_string())]).get(None), Some("Umleitung"));
+        assert_eq!(TranslatedText::default().get(None), None);
+    }
+}
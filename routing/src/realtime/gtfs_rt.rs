@@ -0,0 +1,404 @@
+//! Decodes the parts of GTFS-RT feeds (https://gtfs.org/documentation/realtime/proto/) that the
+//! routing uses. Fields that aren't needed are skipped, like the vehicle of a trip update, the
+//! odometer of a vehicle or the cause and effect of an alert.
+
+use crate::realtime::protobuf::{DecodeError, Fields};
+use crate::realtime::alerts::{ActivePeriod, Alert, InformedEntity, TranslatedText};
+use crate::realtime::vehicles::{VehiclePosition, VehicleStatus};
+use crate::realtime::{StopTimeEvent, StopTimeUpdate, TripUpdate};
+
+// FeedMessage
+const ENTITY: u32 = 2;
+// FeedEntity
+const ENTITY_ID: u32 = 1;
+const IS_DELETED: u32 = 2;
+const TRIP_UPDATE: u32 = 3;
+const VEHICLE: u32 = 4;
+const ALERT: u32 = 5;
+// TripUpdate
+const TRIP: u32 = 1;
+const STOP_TIME_UPDATE: u32 = 2;
+const TRIP_DELAY: u32 = 5;
+// TripDescriptor
+const TRIP_ID: u32 = 1;
+const TRIP_SCHEDULE_RELATIONSHIP: u32 = 4;
+const CANCELED: u64 = 3;
+const DELETED: u64 = 7;
+// StopTimeUpdate
+const STOP_SEQUENCE: u32 = 1;
+const ARRIVAL: u32 = 2;
+const DEPARTURE: u32 = 3;
+const STOP_ID: u32 = 4;
+const STOP_SCHEDULE_RELATIONSHIP: u32 = 5;
+const SKIPPED: u64 = 1;
+// StopTimeEvent
+const EVENT_DELAY: u32 = 1;
+const EVENT_TIME: u32 = 2;
+// VehiclePosition
+const VEHICLE_TRIP: u32 = 1;
+const POSITION: u32 = 2;
+const CURRENT_STATUS: u32 = 4;
+const VEHICLE_TIMESTAMP: u32 = 5;
+const VEHICLE_STOP_ID: u32 = 7;
+const VEHICLE_DESCRIPTOR: u32 = 8;
+// Position
+const LATITUDE: u32 = 1;
+const LONGITUDE: u32 = 2;
+const BEARING: u32 = 3;
+const SPEED: u32 = 5;
+// VehicleDescriptor
+const VEHICLE_ID: u32 = 1;
+const VEHICLE_LABEL: u32 = 2;
+// Alert
+const ACTIVE_PERIOD: u32 = 1;
+const INFORMED_ENTITY: u32 = 5;
+const URL: u32 = 8;
+const HEADER_TEXT: u32 = 10;
+const DESCRIPTION_TEXT: u32 = 11;
+// TimeRange
+const PERIOD_START: u32 = 1;
+const PERIOD_END: u32 = 2;
+// EntitySelector
+const SELECTOR_ROUTE_ID: u32 = 2;
+const SELECTOR_TRIP: u32 = 4;
+const SELECTOR_STOP_ID: u32 = 5;
+// TranslatedString
+const TRANSLATION: u32 = 1;
+const TRANSLATION_TEXT: u32 = 1;
+const TRANSLATION_LANGUAGE: u32 = 2;
+
+/// The trip updates of a feed message. Deleted entities and trip updates without a trip id, e.g. of
+/// trips that are only given by their route and start time, are left out.
+pub fn decode_trip_updates(message: &[u8]) -> Result<Vec<TripUpdate>, DecodeError> {
+    let mut trip_updates = vec![];
+    for (_, trip_update) in entities(message, TRIP_UPDATE)? {
+        trip_updates.extend(decode_trip_update(trip_update)?);
+    }
+    Ok(trip_updates)
+}
+
+/// The alerts of a feed message, with the ids of their entities
+pub fn decode_alerts(message: &[u8]) -> Result<Vec<Alert>, DecodeError> {
+    entities(message, ALERT)?.into_iter()
+        .map(|(id, alert)| decode_alert(id, alert))
+        .collect()
+}
+
+/// The vehicle positions of a feed message. Vehicles without a position are left out.
+pub fn decode_vehicle_positions(message: &[u8]) -> Result<Vec<VehiclePosition>, DecodeError> {
+    let mut positions = vec![];
+    for (_, vehicle) in entities(message, VEHICLE)? {
+        positions.extend(decode_vehicle_position(vehicle)?);
+    }
+    Ok(positions)
+}
+
+// Id and the message in `field` of the entities that aren't deleted and have the field
+fn entities(message: &[u8], field: u32) -> Result<Vec<(String, &[u8])>, DecodeError> {
+    let mut entities = vec![];
+    for entity in Fields::new(message) {
+        let (number, value) = entity?;
+        if number != ENTITY {
+            continue;
+        }
+
+        let (mut id, mut is_deleted, mut content) = (String::new(), false, None);
+        for entity_field in Fields::new(value.as_bytes()?) {
+            match entity_field? {
+                (ENTITY_ID, value) => id = value.as_string()?,
+                (IS_DELETED, value) => is_deleted = value.as_u64()? != 0,
+                (number, value) if number == field => content = Some(value.as_bytes()?),
+                _ => {}
+            }
+        }
+        if let (false, Some(content)) = (is_deleted, content) {
+            entities.push((id, content));
+        }
+    }
+    Ok(entities)
+}
+
+fn decode_trip_update(message: &[u8]) -> Result<Option<TripUpdate>, DecodeError> {
+    let (mut trip_id, mut cancelled, mut delay, mut stop_time_updates) = (None, false, None, vec![]);
+    for field in Fields::new(message) {
+        match field? {
+            (TRIP, value) => for field in Fields::new(value.as_bytes()?) {
+                match field? {
+                    (TRIP_ID, value) => trip_id = Some(value.as_string()?),
+                    (TRIP_SCHEDULE_RELATIONSHIP, value) => cancelled = matches!(value.as_u64()?, CANCELED | DELETED),
+                    _ => {}
+                }
+            },
+            (STOP_TIME_UPDATE, value) => stop_time_updates.push(decode_stop_time_update(value.as_bytes()?)?),
+            (TRIP_DELAY, value) => delay = Some(value.as_i64()? as i32),
+            _ => {}
+        }
+    }
+    Ok(trip_id.map(|trip_id| TripUpdate { trip_id, cancelled, delay, stop_time_updates }))
+}
+
+fn decode_stop_time_update(message: &[u8]) -> Result<StopTimeUpdate, DecodeError> {
+    let mut update = StopTimeUpdate::default();
+    for field in Fields::new(message) {
+        match field? {
+            (STOP_SEQUENCE, value) => update.stop_sequence = Some(value.as_u64()? as u32),
+            (ARRIVAL, value) => update.arrival = Some(decode_stop_time_event(value.as_bytes()?)?),
+            (DEPARTURE, value) => update.departure = Some(decode_stop_time_event(value.as_bytes()?)?),
+            (STOP_ID, value) => update.stop_id = Some(value.as_string()?),
+            (STOP_SCHEDULE_RELATIONSHIP, value) => update.skipped = value.as_u64()? == SKIPPED,
+            _ => {}
+        }
+    }
+    Ok(update)
+}
+
+fn decode_stop_time_event(message: &[u8]) -> Result<StopTimeEvent, DecodeError> {
+    let mut event = StopTimeEvent::default();
+    for field in Fields::new(message) {
+        match field? {
+            (EVENT_DELAY, value) => event.delay = Some(value.as_i64()? as i32),
+            (EVENT_TIME, value) => event.time = Some(value.as_i64()?),
+            _ => {}
+        }
+    }
+    Ok(event)
+}
+
+fn decode_vehicle_position(message: &[u8]) -> Result<Option<VehiclePosition>, DecodeError> {
+    let (mut vehicle, mut has_position) = (VehiclePosition::default(), false);
+    for field in Fields::new(message) {
+        match field? {
+            (VEHICLE_TRIP, value) => for field in Fields::new(value.as_bytes()?) {
+                if let (TRIP_ID, value) = field? {
+                    vehicle.trip_id = Some(value.as_string()?);
+                }
+            },
+            (POSITION, value) => {
+                has_position = true;
+                for field in Fields::new(value.as_bytes()?) {
+                    match field? {
+                        (LATITUDE, value) => vehicle.lat = value.as_f32()?,
+                        (LONGITUDE, value) => vehicle.lon = value.as_f32()?,
+                        (BEARING, value) => vehicle.bearing = Some(value.as_f32()?),
+                        (SPEED, value) => vehicle.speed = Some(value.as_f32()?),
+                        _ => {}
+                    }
+                }
+            }
+            (CURRENT_STATUS, value) => vehicle.status = match value.as_u64()? {
+                0 => Some(VehicleStatus::IncomingAt),
+                1 => Some(VehicleStatus::StoppedAt),
+                2 => Some(VehicleStatus::InTransitTo),
+                _ => None,
+            },
+            (VEHICLE_TIMESTAMP, value) => vehicle.timestamp = Some(value.as_i64()?),
+            (VEHICLE_STOP_ID, value) => vehicle.stop_id = Some(value.as_string()?),
+            (VEHICLE_DESCRIPTOR, value) => for field in Fields::new(value.as_bytes()?) {
+                match field? {
+                    (VEHICLE_ID, value) => vehicle.vehicle_id = Some(value.as_string()?),
+                    (VEHICLE_LABEL, value) => vehicle.label = Some(value.as_string()?),
+                    _ => {}
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(has_position.then_some(vehicle))
+}
+
+fn decode_alert(id: String, message: &[u8]) -> Result<Alert, DecodeError> {
+    let mut alert = Alert { id, ..Default::default() };
+    for field in Fields::new(message) {
+        match field? {
+            (ACTIVE_PERIOD, value) => {
+                let mut period = ActivePeriod::default();
+                for field in Fields::new(value.as_bytes()?) {
+                    match field? {
+                        (PERIOD_START, value) => period.start = Some(value.as_i64()?),
+                        (PERIOD_END, value) => period.end = Some(value.as_i64()?),
+                        _ => {}
+                    }
+                }
+                alert.active_periods.push(period);
+            }
+            (INFORMED_ENTITY, value) => {
+                let mut entity = InformedEntity::default();
+                for field in Fields::new(value.as_bytes()?) {
+                    match field? {
+                        (SELECTOR_ROUTE_ID, value) => entity.route_id = Some(value.as_string()?),
+                        (SELECTOR_TRIP, value) => for field in Fields::new(value.as_bytes()?) {
+                            if let (TRIP_ID, value) = field? {
+                                entity.trip_id = Some(value.as_string()?);
+                            }
+                        },
+                        (SELECTOR_STOP_ID, value) => entity.stop_id = Some(value.as_string()?),
+                        _ => {}
+                    }
+                }
+                alert.informed_entities.push(entity);
+            }
+            (URL, value) => alert.url = decode_translated_text(value.as_bytes()?)?,
+            (HEADER_TEXT, value) => alert.header = decode_translated_text(value.as_bytes()?)?,
+            (DESCRIPTION_TEXT, value) => alert.description = decode_translated_text(value.as_bytes()?)?,
+            _ => {}
+        }
+    }
+    Ok(alert)
+}
+
+fn decode_translated_text(message: &[u8]) -> Result<TranslatedText, DecodeError> {
+    let mut translations = vec![];
+    for field in Fields::new(message) {
+        let (TRANSLATION, value) = field? else { continue };
+        let (mut language, mut text) = (None, String::new());
+        for field in Fields::new(value.as_bytes()?) {
+            match field? {
+                (TRANSLATION_TEXT, value) => text = value.as_string()?,
+                (TRANSLATION_LANGUAGE, value) => language = Some(value.as_string()?),
+                _ => {}
+            }
+        }
+        translations.push((language, text));
+    }
+    Ok(TranslatedText(translations))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::protobuf::encode;
+    use itertools::Itertools;
+
+    fn encode_trip_update(update: &TripUpdate) -> Vec<u8> {
+        let mut trip = encode::bytes(TRIP_ID, update.trip_id.as_bytes());
+        if update.cancelled {
+            trip.extend(encode::int(TRIP_SCHEDULE_RELATIONSHIP, CANCELED as i64));
+        }
+        let event = |event: &StopTimeEvent| [
+            event.delay.map(|delay| encode::int(EVENT_DELAY, delay as i64)).unwrap_or_default(),
+            event.time.map(|time| encode::int(EVENT_TIME, time)).unwrap_or_default(),
+        ].concat();
+
+        let mut message = encode::bytes(TRIP, &trip);
+        for stop_time_update in &update.stop_time_updates {
+            let encoded = [
+                stop_time_update.stop_sequence.map(|seq| encode::int(STOP_SEQUENCE, seq as i64)).unwrap_or_default(),
+                stop_time_update.arrival.as_ref().map(|arrival| encode::bytes(ARRIVAL, &event(arrival))).unwrap_or_default(),
+                stop_time_update.departure.as_ref().map(|departure| encode::bytes(DEPARTURE, &event(departure))).unwrap_or_default(),
+                stop_time_update.stop_id.as_ref().map(|stop| encode::bytes(STOP_ID, stop.as_bytes())).unwrap_or_default(),
+                if stop_time_update.skipped { encode::int(STOP_SCHEDULE_RELATIONSHIP, SKIPPED as i64) } else { vec![] },
+            ].concat();
+            message.extend(encode::bytes(STOP_TIME_UPDATE, &encoded));
+        }
+        if let Some(delay) = update.delay {
+            message.extend(encode::int(TRIP_DELAY, delay as i64));
+        }
+        message
+    }
+
+    /// A feed message with a header and one entity per trip update
+    fn encode_feed(updates: &[TripUpdate]) -> Vec<u8> {
+        let header = encode::bytes(1, &encode::bytes(1, b"2.0"));
+        let entities = updates.iter().enumerate().map(|(idx, update)| encode::bytes(ENTITY, &[
+            encode::bytes(ENTITY_ID, idx.to_string().as_bytes()),
+            encode::bytes(TRIP_UPDATE, &encode_trip_update(update)),
+        ].concat()));
+        [header].into_iter().chain(entities).concat()
+    }
+
+    #[test]
+    fn test_decode_trip_updates() {
+        let updates = vec![
+            TripUpdate {
+                trip_id: "4711".to_string(),
+                cancelled: false,
+                delay: None,
+                stop_time_updates: vec![
+                    StopTimeUpdate {
+                        stop_id: Some("a".to_string()),
+                        arrival: Some(StopTimeEvent { delay: Some(-60), time: None }),
+                        departure: Some(StopTimeEvent { delay: None, time: Some(1_700_000_000) }),
+                        ..Default::default()
+                    },
+                    StopTimeUpdate { stop_sequence: Some(3), skipped: true, ..Default::default() },
+                ],
+            },
+            TripUpdate { trip_id: "4712".to_string(), cancelled: true, delay: Some(300), stop_time_updates: vec![] },
+        ];
+        assert_eq!(decode_trip_updates(&encode_feed(&updates)).unwrap(), updates);
+
+        // Deleted entities are no updates
+        let deleted = encode::bytes(ENTITY, &[
+            encode::int(IS_DELETED, 1),
+            encode::bytes(TRIP_UPDATE, &encode_trip_update(&updates[0])),
+        ].concat());
+        assert!(decode_trip_updates(&deleted).unwrap().is_empty());
+
+        assert_eq!(decode_trip_updates(&[0x12, 0x05, 0x0a]), Err(DecodeError::UnexpectedEnd));
+    }
+
+    #[test]
+    fn test_decode_vehicle_positions() {
+        let vehicle = [
+            encode::bytes(VEHICLE_TRIP, &encode::bytes(TRIP_ID, b"4711")),
+            encode::bytes(POSITION, &[encode::float(LATITUDE, 48.75), encode::float(LONGITUDE, 9.25), encode::float(BEARING, 90.0)].concat()),
+            encode::int(CURRENT_STATUS, 1),
+            encode::int(VEHICLE_TIMESTAMP, 1_700_000_000),
+            encode::bytes(VEHICLE_STOP_ID, b"a"),
+            encode::bytes(VEHICLE_DESCRIPTOR, &[encode::bytes(VEHICLE_ID, b"v1"), encode::bytes(VEHICLE_LABEL, b"S1")].concat()),
+        ].concat();
+        let without_position = encode::bytes(VEHICLE_TRIP, &encode::bytes(TRIP_ID, b"4712"));
+        let message = [vehicle, without_position].map(|vehicle| encode::bytes(ENTITY, &encode::bytes(VEHICLE, &vehicle))).concat();
+
+        assert_eq!(decode_vehicle_positions(&message).unwrap(), vec![VehiclePosition {
+            trip_id: Some("4711".to_string()),
+            vehicle_id: Some("v1".to_string()),
+            label: Some("S1".to_string()),
+            lat: 48.75,
+            lon: 9.25,
+            bearing: Some(90.0),
+            speed: None,
+            stop_id: Some("a".to_string()),
+            status: Some(VehicleStatus::StoppedAt),
+            timestamp: Some(1_700_000_000),
+        }]);
+    }
+
+    #[test]
+    fn test_decode_alerts() {
+        let translation = |text: &str, language: &str| encode::bytes(TRANSLATION, &[
+            encode::bytes(TRANSLATION_TEXT, text.as_bytes()),
+            encode::bytes(TRANSLATION_LANGUAGE, language.as_bytes()),
+        ].concat());
+        let alert = [
+            encode::bytes(ACTIVE_PERIOD, &[encode::int(PERIOD_START, 1_000), encode::int(PERIOD_END, 2_000)].concat()),
+            encode::bytes(INFORMED_ENTITY, &encode::bytes(SELECTOR_ROUTE_ID, b"U1")),
+            encode::bytes(INFORMED_ENTITY, &[
+                encode::bytes(SELECTOR_TRIP, &encode::bytes(TRIP_ID, b"4711")),
+                encode::bytes(SELECTOR_STOP_ID, b"a"),
+            ].concat()),
+            encode::bytes(HEADER_TEXT, &[translation("Umleitung", "de"), translation("Detour", "en")].concat()),
+            // Cause
+            encode::int(6, 3),
+        ].concat();
+        let message = [
+            encode::bytes(ENTITY, &[encode::bytes(ENTITY_ID, b"detour"), encode::bytes(ALERT, &alert)].concat()),
+            // Entities without an alert are left out
+            encode_feed(&[TripUpdate { trip_id: "4711".to_string(), ..Default::default() }]),
+        ].concat();
+
+        assert_eq!(decode_alerts(&message).unwrap(), vec![Alert {
+            id: "detour".to_string(),
+            active_periods: vec![ActivePeriod { start: Some(1_000), end: Some(2_000) }],
+            informed_entities: vec![
+                InformedEntity { route_id: Some("U1".to_string()), ..Default::default() },
+                InformedEntity { trip_id: Some("4711".to_string()), stop_id: Some("a".to_string()), ..Default::default() },
+            ],
+            header: TranslatedText(vec![
+                (Some("de".to_string()), "Umleitung".to_string()),
+                (Some("en".to_string()), "Detour".to_string()),
+            ]),
+            ..Default::default()
+        }]);
+    }
+}
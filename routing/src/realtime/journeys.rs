@@ -0,0 +1,272 @@
+//! Journeys that were planned on the schedule, re-evaluated with the trip updates of the feeds. The
+//! rides keep their trips, so a journey that can't be travelled anymore is reported as such instead
+//! of being planned again.
+
+use super::{StopTimeUpdate, TripUpdate, TripUpdatesFeed};
+use crate::algorithm::{QueryError, QueryResult};
+use crate::direct_connections::DirectConnections;
+use crate::itinerary::{trip_stops, TripStop};
+use crate::journey::{Journey, Leg};
+use chrono::{DateTime, TimeDelta, Utc};
+use common::types::id_interner::OriginalIds;
+use common::types::TripId;
+use hashbrown::HashMap;
+
+/// What the trip updates say about a leg
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LegStatus {
+    Scheduled,
+    Delayed,
+    Cancelled,
+    // The trip doesn't stop where the ride boards or alights
+    StopSkipped,
+    // The ride departs before the legs before it arrive
+    ConnectionMissed,
+}
+
+/// The expected times of a leg. Walks before the first ride end when it departs, journeys that
+/// only walk have no times.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegUpdate {
+    pub status: LegStatus,
+    pub departure: Option<DateTime<Utc>>,
+    pub arrival: Option<DateTime<Utc>>,
+    // When the ride leaves each stop between boarding and alighting, or arrives at the last one.
+    // None at stops that are skipped, empty for walks.
+    pub intermediate_stops: Vec<Option<DateTime<Utc>>>,
+}
+
+/// A journey in the realtime timetable, with an update for each of its legs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JourneyUpdate {
+    pub legs: Vec<LegUpdate>,
+}
+
+impl JourneyUpdate {
+    /// Whether every ride can still be taken, if late
+    pub fn is_feasible(&self) -> bool {
+        self.legs.iter().all(|leg| matches!(leg.status, LegStatus::Scheduled | LegStatus::Delayed))
+    }
+
+    pub fn arrival(&self) -> Option<DateTime<Utc>> {
+        self.legs.iter().rev().find_map(|leg| leg.arrival)
+    }
+}
+
+/// Re-evaluates the rides of a journey with the latest messages of the feeds. Delays propagate
+/// along a trip like in [super::RealtimeTimetable], so the stops of the trips are looked up in the
+/// direct connections. Times are relative to the start of the service day, absolute times of the
+/// feeds are converted with `service_day_start`. Fails with [QueryError::NoRouteFound] if a ride is
+/// not part of the timetable.
+pub fn evaluate(
+    journey: &Journey,
+    feeds: &[TripUpdatesFeed],
+    original_ids: &OriginalIds,
+    direct_connections: &DirectConnections,
+    service_day_start: DateTime<Utc>,
+) -> QueryResult<JourneyUpdate> {
+    let trips = trip_stops(journey, direct_connections)?;
+    // A trip that is in the message twice gets the later update
+    let updates: HashMap<TripId, (&str, &TripUpdate)> = feeds.iter()
+        .flat_map(|feed| feed.updates.iter().map(|update| (feed.dataset_id.as_str(), update)))
+        .filter_map(|(dataset_id, update)| {
+            let trip = original_ids.find_trip(&format!("{dataset_id}:{}", update.trip_id))?;
+            trips.contains_key(&trip).then_some((trip, (dataset_id, update)))
+        })
+        .collect();
+    let offset = service_day_start - DateTime::UNIX_EPOCH;
+
+    // When the legs so far arrive
+    let mut time: Option<DateTime<Utc>> = None;
+    let mut legs = journey.legs()
+        .map(|leg| match leg {
+            Leg::Ride { trip, boarding_stop, alight_stop, boarding_time, .. } => {
+                let stops = trips.get(trip).ok_or(QueryError::NoRouteFound)?;
+                let boarding = stops.iter()
+                    .position(|stop| stop.call.stop == *boarding_stop && stop.call.departure == Some(*boarding_time))
+                    .or_else(|| stops.iter().position(|stop| stop.call.stop == *boarding_stop))
+                    .ok_or(QueryError::NoRouteFound)?;
+                let alight = stops.iter().skip(boarding + 1)
+                    .position(|stop| stop.call.stop == *alight_stop)
+                    .map(|offset| boarding + 1 + offset)
+                    .ok_or(QueryError::NoRouteFound)?;
+
+                let update = match updates.get(trip) {
+                    Some((dataset_id, update)) => realtime_times(stops, dataset_id, update, original_ids, offset),
+                    None => stops.iter().map(|stop| Some((stop.call.arrival, stop.call.departure))).collect(),
+                };
+                let (departure, arrival) = (update[boarding].map(|(_, departure)| departure), update[alight].map(|(arrival, _)| arrival));
+                let scheduled = (stops[boarding].call.departure, stops[alight].call.arrival);
+
+                let status = match (departure, arrival) {
+                    _ if updates.get(trip).is_some_and(|(_, update)| update.cancelled) => LegStatus::Cancelled,
+                    (None, _) | (_, None) => LegStatus::StopSkipped,
+                    (Some(departure), _) if time.zip(departure).is_some_and(|(time, departure)| departure < time) => {
+                        LegStatus::ConnectionMissed
+                    }
+                    (Some(departure), Some(arrival)) if (departure, arrival) == scheduled => LegStatus::Scheduled,
+                    _ => LegStatus::Delayed,
+                };
+                // Later legs are evaluated as if the ride was taken
+                let ((departure, arrival), intermediate_stops) = match status {
+                    LegStatus::Cancelled | LegStatus::StopSkipped => (
+                        scheduled,
+                        stops[boarding + 1..alight].iter().map(|stop| stop.call.departure.or(stop.call.arrival)).collect(),
+                    ),
+                    _ => (
+                        (departure.flatten(), arrival.flatten()),
+                        update[boarding + 1..alight].iter().map(|times| times.and_then(|(arrival, departure)| departure.or(arrival))).collect(),
+                    ),
+                };
+                time = arrival.or(time);
+                Ok(LegUpdate { status, departure, arrival, intermediate_stops })
+            }
+            Leg::Transfer { duration, .. } => {
+                let departure = time;
+                time = time.map(|time| time + *duration);
+                Ok(LegUpdate { status: LegStatus::Scheduled, departure, arrival: time, intermediate_stops: vec![] })
+            }
+        })
+        .collect::<QueryResult<Vec<LegUpdate>>>()?;
+
+    // Walks before the first ride have to end when it departs
+    let first_ride = legs.iter().position(|leg| leg.departure.is_some()).unwrap_or_default();
+    let mut time = legs.get(first_ride).and_then(|leg| leg.departure);
+    for (leg, walk) in legs[..first_ride].iter_mut().zip(journey.legs()).rev() {
+        let Leg::Transfer { duration, .. } = walk else { continue };
+        leg.arrival = time;
+        time = time.map(|time| time - *duration);
+        leg.departure = time;
+    }
+    Ok(JourneyUpdate { legs })
+}
+
+// The expected arrival and departure at a stop, either of which the stop may not have
+pub(crate) type ExpectedTimes = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+// The expected arrival and departure at each stop of the trip, none at stops that are skipped
+pub(crate) fn realtime_times(
+    stops: &[TripStop],
+    dataset_id: &str,
+    update: &TripUpdate,
+    original_ids: &OriginalIds,
+    service_day_start: TimeDelta,
+) -> Vec<Option<ExpectedTimes>> {
+    // Updates are matched by their stop sequence if they have one, since the stop may have been
+    // moved to another platform
+    let mut stop_time_updates = update.stop_time_updates.iter()
+        .filter_map(|stop_time_update| {
+            let stop = stop_time_update.stop_id.as_ref()
+                .and_then(|stop_id| original_ids.stops.get(&format!("{dataset_id}:{stop_id}")));
+            (stop.is_some() || stop_time_update.stop_sequence.is_some()).then_some((stop, stop_time_update))
+        })
+        .peekable();
+    let mut delay = TimeDelta::seconds(update.delay.unwrap_or_default() as i64);
+
+    stops.iter()
+        .map(|stop| {
+            let (scheduled_arrival, scheduled_departure) = (stop.call.arrival, stop.call.departure);
+            let stop_time_update: Option<&StopTimeUpdate> = stop_time_updates
+                .next_if(|(update_stop, update)| match update.stop_sequence {
+                    Some(sequence) => sequence == stop.sequence,
+                    None => *update_stop == Some(stop.call.stop),
+                })
+                .map(|(_, update)| update);
+            if stop_time_update.is_some_and(|update| update.skipped) {
+                return None;
+            }
+
+            if let Some((event, scheduled)) = stop_time_update.and_then(|update| update.arrival.as_ref()).zip(scheduled_arrival) {
+                delay = event.delay_after(scheduled, service_day_start).unwrap_or(delay);
+            }
+            let arrival = scheduled_arrival.map(|arrival| arrival + delay);
+            if let Some((event, scheduled)) = stop_time_update.and_then(|update| update.departure.as_ref()).zip(scheduled_departure) {
+                delay = event.delay_after(scheduled, service_day_start).unwrap_or(delay);
+            }
+            // A trip can't leave before it arrived
+            let departure = scheduled_departure.map(|departure| (departure + delay).max(arrival.unwrap_or(departure + delay)));
+            Some((arrival, departure))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::StopTimeEvent;
+    use common::types::id_interner::IdInterner;
+    use common::types::StopId;
+
+    // A walk of a minute to stop 0, trip 0 from stop 0 to 1 at 100s to 500s, then trip 1 from stop
+    // 1 to 2 at 1000s to 1500s
+    fn journey() -> (Journey, DirectConnections, OriginalIds) {
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let at = |seconds: i64| DateTime::from_timestamp(seconds, 0).unwrap();
+        let journey = Journey::from(vec![
+            Leg::Transfer { start: StopId(2), end: StopId(0), duration: TimeDelta::minutes(1) },
+            Leg::Ride { trip: TripId(0), boarding_stop: StopId(0), alight_stop: StopId(1), boarding_time: at(100), alight_time: at(500) },
+            Leg::Ride { trip: TripId(1), boarding_stop: StopId(1), alight_stop: StopId(2), boarding_time: at(1_000), alight_time: at(1_500) },
+        ]);
+        let original_ids = OriginalIds {
+            stops: IdInterner::from_originals(["d:0", "d:1", "d:2"]),
+            trips: IdInterner::from_originals(["d:0", "d:1"]),
+            ..Default::default()
+        };
+        (journey, DirectConnections::try_from(input).unwrap(), original_ids)
+    }
+
+    fn feed(updates: Vec<TripUpdate>) -> [TripUpdatesFeed; 1] {
+        [TripUpdatesFeed { dataset_id: "d".to_string(), updates }]
+    }
+
+    fn delayed(trip: &str, stop: &str, delay: i32) -> TripUpdate {
+        let event = Some(StopTimeEvent { delay: Some(delay), time: None });
+        TripUpdate {
+            trip_id: trip.to_string(),
+            stop_time_updates: vec![StopTimeUpdate { stop_id: Some(stop.to_string()), arrival: event.clone(), departure: event, ..Default::default() }],
+            ..Default::default()
+        }
+    }
+
+    fn statuses(update: &JourneyUpdate) -> Vec<LegStatus> {
+        update.legs.iter().map(|leg| leg.status).collect()
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let (journey, direct_connections, original_ids) = journey();
+        let day = DateTime::<Utc>::UNIX_EPOCH;
+        let evaluate = |updates| evaluate(&journey, &feed(updates), &original_ids, &direct_connections, day).unwrap();
+
+        let scheduled = evaluate(vec![]);
+        assert_eq!(statuses(&scheduled), vec![LegStatus::Scheduled; 3]);
+        assert_eq!((scheduled.legs[0].departure, scheduled.legs[0].arrival), (DateTime::from_timestamp(40, 0), DateTime::from_timestamp(100, 0)));
+        assert_eq!(scheduled.arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // Trip 1 leaves two minutes late, and arrives as late
+        let late = evaluate(vec![delayed("1", "1", 120)]);
+        assert!(late.is_feasible());
+        assert_eq!(statuses(&late), vec![LegStatus::Scheduled, LegStatus::Scheduled, LegStatus::Delayed]);
+        assert_eq!(late.arrival(), DateTime::from_timestamp(1_620, 0));
+
+        // Trip 0 arrives after trip 1 has left
+        let missed = evaluate(vec![delayed("0", "1", 600)]);
+        assert!(!missed.is_feasible());
+        assert_eq!(statuses(&missed), vec![LegStatus::Scheduled, LegStatus::Delayed, LegStatus::ConnectionMissed]);
+
+        let cancelled = evaluate(vec![TripUpdate { trip_id: "1".to_string(), cancelled: true, ..Default::default() }]);
+        assert_eq!(cancelled.legs[2], LegUpdate {
+            status: LegStatus::Cancelled,
+            departure: DateTime::from_timestamp(1_000, 0),
+            arrival: DateTime::from_timestamp(1_500, 0),
+            intermediate_stops: vec![],
+        });
+
+        let skipped = TripUpdate {
+            trip_id: "0".to_string(),
+            stop_time_updates: vec![StopTimeUpdate { stop_id: Some("1".to_string()), skipped: true, ..Default::default() }],
+            ..Default::default()
+        };
+        assert_eq!(statuses(&evaluate(vec![skipped]))[1], LegStatus::StopSkipped);
+    }
+}
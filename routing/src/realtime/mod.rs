@@ -0,0 +1,294 @@
+//! Realtime updates of the timetable from GTFS-RT feeds. The trip updates of a feed patch the
+//! arrivals and departures that RAPTOR searches, so queries see delays, cancelled trips and
+//! skipped stops. Service alerts are attached to the legs of itineraries they affect, and vehicle
+//! positions are looked up by the trip a leg rides.
+
+pub mod alerts;
+pub mod gtfs_rt;
+pub mod journeys;
+pub(crate) mod protobuf;
+pub mod vehicles;
+
+use crate::raptor::{LineIdx, LocalStopId, RaptorAlgorithm, Timetable, TripIdx};
+use chrono::{DateTime, TimeDelta, Utc};
+use common::types::dense_ids::DenseIds;
+use common::types::id_interner::OriginalIds;
+use common::types::TripId;
+use hashbrown::HashSet;
+use itertools::{Either, Itertools};
+
+pub use protobuf::DecodeError;
+
+/// The realtime state of a trip, as in https://gtfs.org/documentation/realtime/reference/#message-tripupdate
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TripUpdate {
+    // Id of the trip in its dataset
+    pub trip_id: String,
+    pub cancelled: bool,
+    // Delay in seconds of the stops before the first stop time update
+    pub delay: Option<i32>,
+    // In the order of the stops of the trip
+    pub stop_time_updates: Vec<StopTimeUpdate>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StopTimeUpdate {
+    pub stop_sequence: Option<u32>,
+    // Id of the stop in the dataset of the trip
+    pub stop_id: Option<String>,
+    pub arrival: Option<StopTimeEvent>,
+    pub departure: Option<StopTimeEvent>,
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StopTimeEvent {
+    // Seconds after the scheduled time
+    pub delay: Option<i32>,
+    // Seconds since the UNIX epoch
+    pub time: Option<i64>,
+}
+
+impl StopTimeEvent {
+    // How much later than `scheduled` the event is, if it says so. Absolute times are converted
+    // with the offset of the start of the service day from the UNIX epoch.
+    fn delay_after(&self, scheduled: DateTime<Utc>, service_day_start: TimeDelta) -> Option<TimeDelta> {
+        match (self.delay, self.time) {
+            (Some(delay), _) => Some(TimeDelta::seconds(delay as i64)),
+            (None, Some(time)) => Some(TimeDelta::seconds(time) - service_day_start - (scheduled - DateTime::UNIX_EPOCH)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The latest message of the GTFS-RT feed of a dataset
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TripUpdatesFeed {
+    pub dataset_id: String,
+    pub updates: Vec<TripUpdate>,
+}
+
+/// How the feeds were applied
+#[derive(Debug, Default, PartialEq)]
+pub struct AppliedTripUpdates {
+    pub updated: usize,
+    pub cancelled: usize,
+    // Trips that aren't in the timetable, e.g. because they don't run on the service day
+    pub unknown_trips: usize,
+    // Stop time updates without a stop id or with a stop the trip doesn't serve. The stop sequence
+    // of GTFS isn't kept in the timetable, so it can't be used to match them.
+    pub unmatched_stops: usize,
+}
+
+/// The scheduled timetable of a [RaptorAlgorithm] and the trips that differ from it. A feed message
+/// has the complete realtime state of the trips it mentions, so the updates of the messages replace
+/// those applied before instead of adding up. The times of itineraries, which are
+/// reconstructed from the direct connections, stay the scheduled ones.
+pub struct RealtimeTimetable {
+    scheduled: Timetable,
+    // Trips of the timetable, the dense id of a trip is its index in `trips`
+    trip_ids: DenseIds<TripId>,
+    // <(line, index of the trip on the line)> by the dense id of the trip
+    trips: Vec<(LineIdx, TripIdx)>,
+    // Trips whose times in the algorithm aren't the scheduled ones
+    updated_trips: HashSet<(LineIdx, TripIdx)>,
+}
+
+impl RealtimeTimetable {
+    pub fn new(raptor: &RaptorAlgorithm) -> Self {
+        let timetable = &raptor.timetable;
+        let (trip_ids, trips): (Vec<TripId>, _) = timetable.lines()
+            .flat_map(|line| timetable.trips(line).iter().enumerate().map(move |(trip, id)| (*id, (line, trip as TripIdx))))
+            .unzip();
+        Self {
+            scheduled: timetable.clone(),
+            trip_ids: DenseIds::new(trip_ids),
+            trips,
+            updated_trips: HashSet::new(),
+        }
+    }
+
+    /// Applies the latest messages of all feeds to the timetable of `raptor`, which must be the one
+    /// the timetable was created from. Absolute times of the feeds are converted with the start of
+    /// the service day, which is at the UNIX epoch in the timetable.
+    pub fn apply(
+        &mut self,
+        raptor: &mut RaptorAlgorithm,
+        feeds: &[TripUpdatesFeed],
+        original_ids: &OriginalIds,
+        service_day_start: DateTime<Utc>,
+    ) -> AppliedTripUpdates {
+        for trip in std::mem::take(&mut self.updated_trips) {
+            self.restore(raptor, trip);
+        }
+
+        let mut applied = AppliedTripUpdates::default();
+        let updates = feeds.iter().flat_map(|feed| feed.updates.iter().map(|update| (feed.dataset_id.as_str(), update)));
+        for (dataset_id, update) in updates {
+            let Some(trip) = original_ids.find_trip(&format!("{dataset_id}:{}", update.trip_id))
+                .and_then(|trip| self.trip_ids.dense(trip))
+                .map(|trip| self.trips[trip.0 as usize]) else {
+                applied.unknown_trips += 1;
+                continue;
+            };
+            // A trip that is in the message twice gets the later update
+            if !self.updated_trips.insert(trip) {
+                self.restore(raptor, trip);
+            }
+
+            if update.cancelled {
+                let (line, trip) = trip;
+                for position in 0..self.scheduled.stops(line).len() {
+                    raptor.timetable.set_departure(line, trip, position, None);
+                }
+                applied.cancelled += 1;
+            } else {
+                let (matched, unmatched): (Vec<_>, Vec<_>) = update.stop_time_updates.iter()
+                    .partition_map(|stop_time_update| match stop_time_update.stop_id.as_ref()
+                        .and_then(|stop| original_ids.stops.get(&format!("{dataset_id}:{stop}")))
+                        .and_then(|stop| raptor.stop_mapping.find_local(stop)) {
+                        Some(stop) => Either::Left((stop, stop_time_update)),
+                        None => Either::Right(stop_time_update),
+                    });
+                let delay = TimeDelta::seconds(update.delay.unwrap_or_default() as i64);
+                applied.unmatched_stops += unmatched.len()
+                    + self.apply_stop_time_updates(raptor, trip, delay, matched, service_day_start - DateTime::UNIX_EPOCH);
+                applied.updated += 1;
+            }
+        }
+        applied
+    }
+
+    // Delays propagate along the trip until the next stop with an update. Returns the number of
+    // stop time updates that aren't at a stop of the trip or not in the order of its stops.
+    fn apply_stop_time_updates(
+        &self,
+        raptor: &mut RaptorAlgorithm,
+        (line, trip): (LineIdx, TripIdx),
+        mut delay: TimeDelta,
+        stop_time_updates: Vec<(LocalStopId, &StopTimeUpdate)>,
+        service_day_start: TimeDelta,
+    ) -> usize {
+        let mut stop_time_updates = stop_time_updates.into_iter().peekable();
+        let to_delay = |event: &StopTimeEvent, scheduled: DateTime<Utc>| event.delay_after(scheduled, service_day_start);
+
+        let mut times = vec![];
+        for (position, (stop, _)) in self.scheduled.stops(line).iter().enumerate() {
+            let scheduled_arrival = self.scheduled.arrival(line, trip, position);
+            let scheduled_departure = self.scheduled.departure(line, trip, position);
+            let stop_time_update = stop_time_updates.next_if(|(update_stop, _)| update_stop == stop).map(|(_, update)| update);
+
+            // Stops that are skipped can't be boarded or alighted at
+            if stop_time_update.is_some_and(|update| update.skipped) {
+                times.push((position, None, None));
+                continue;
+            }
+            if let Some((event, scheduled)) = stop_time_update.and_then(|update| update.arrival.as_ref()).zip(scheduled_arrival) {
+                delay = to_delay(event, scheduled).unwrap_or(delay);
+            }
+            let arrival = scheduled_arrival.map(|arrival| arrival + delay);
+            if let Some((event, scheduled)) = stop_time_update.and_then(|update| update.departure.as_ref()).zip(scheduled_departure) {
+                delay = to_delay(event, scheduled).unwrap_or(delay);
+            }
+            // A trip can't leave before it arrived
+            let departure = scheduled_departure.map(|departure| (departure + delay).max(arrival.unwrap_or(departure + delay)));
+            times.push((position, arrival, departure));
+        }
+
+        for (position, arrival, departure) in times {
+            raptor.timetable.set_arrival(line, trip, position, arrival);
+            raptor.timetable.set_departure(line, trip, position, departure);
+        }
+
+        stop_time_updates.count()
+    }
+
+    fn restore(&self, raptor: &mut RaptorAlgorithm, (line, trip): (LineIdx, TripIdx)) {
+        for position in 0..self.scheduled.stops(line).len() {
+            raptor.timetable.set_arrival(line, trip, position, self.scheduled.arrival(line, trip, position));
+            raptor.timetable.set_departure(line, trip, position, self.scheduled.departure(line, trip, position));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::JourneyPlanner;
+    use crate::direct_connections::DirectConnections;
+    use common::types::id_interner::IdInterner;
+    use common::types::StopId;
+
+    fn feed(updates: Vec<TripUpdate>) -> [TripUpdatesFeed; 1] {
+        [TripUpdatesFeed { dataset_id: "d".to_string(), updates }]
+    }
+
+    fn stop_time_update(stop: &str, delay: i32) -> StopTimeUpdate {
+        StopTimeUpdate {
+            stop_id: Some(stop.to_string()),
+            arrival: Some(StopTimeEvent { delay: Some(delay), time: None }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        // Trip 0 runs from stop 0 to 1 at 100s to 500s, trip 1 from stop 1 to 2 at 1000s to 1500s
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let mut raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input).unwrap()).unwrap();
+        let original_ids = OriginalIds {
+            stops: IdInterner::from_originals(["d:0", "d:1", "d:2"]),
+            trips: IdInterner::from_originals(["d:0", "d:1"]),
+            ..Default::default()
+        };
+        let mut timetable = RealtimeTimetable::new(&raptor);
+        let day = DateTime::<Utc>::UNIX_EPOCH;
+        let query = |raptor: &RaptorAlgorithm| raptor.query_ea(StopId(0), StopId(2), day).ok().and_then(|journey| journey.arrival());
+
+        // Delayed by 10 minutes, trip 0 arrives after trip 1 has left
+        let delayed = TripUpdate { trip_id: "0".to_string(), stop_time_updates: vec![stop_time_update("1", 600)], ..Default::default() };
+        let unknown = TripUpdate { trip_id: "9".to_string(), ..Default::default() };
+        let applied = timetable.apply(&mut raptor, &feed(vec![delayed, unknown]), &original_ids, day);
+        assert_eq!(applied, AppliedTripUpdates { updated: 1, unknown_trips: 1, ..Default::default() });
+        assert_eq!(query(&raptor), None);
+
+        // The next message replaces the delay, trip 1 runs 2 minutes late
+        let absolute = StopTimeUpdate {
+            stop_id: Some("2".to_string()),
+            arrival: Some(StopTimeEvent { delay: None, time: Some(1_620) }),
+            ..Default::default()
+        };
+        let delayed = TripUpdate { trip_id: "1".to_string(), stop_time_updates: vec![absolute], ..Default::default() };
+        timetable.apply(&mut raptor, &feed(vec![delayed]), &original_ids, day);
+        assert_eq!(query(&raptor), DateTime::from_timestamp(1_620, 0));
+
+        let cancelled = TripUpdate { trip_id: "1".to_string(), cancelled: true, ..Default::default() };
+        assert_eq!(timetable.apply(&mut raptor, &feed(vec![cancelled]), &original_ids, day).cancelled, 1);
+        assert_eq!(query(&raptor), None);
+
+        // Without updates, the schedule is back
+        timetable.apply(&mut raptor, &[], &original_ids, day);
+        assert_eq!(query(&raptor), DateTime::from_timestamp(1_500, 0));
+        assert_eq!(raptor.timetable, timetable.scheduled);
+    }
+
+    #[test]
+    fn test_skipped_stop() {
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let mut raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input).unwrap()).unwrap();
+        let original_ids = OriginalIds {
+            stops: IdInterner::from_originals(["d:0", "d:1", "d:2"]),
+            trips: IdInterner::from_originals(["d:0", "d:1"]),
+            ..Default::default()
+        };
+        let mut timetable = RealtimeTimetable::new(&raptor);
+
+        // Trip 1 can't be boarded at stop 1 anymore, and an update by stop sequence can't be matched
+        let skipped = StopTimeUpdate { stop_id: Some("1".to_string()), skipped: true, ..Default::default() };
+        let by_sequence = StopTimeUpdate { stop_sequence: Some(2), ..Default::default() };
+        let update = TripUpdate { trip_id: "1".to_string(), stop_time_updates: vec![skipped, by_sequence], ..Default::default() };
+        let day = DateTime::<Utc>::UNIX_EPOCH;
+        assert_eq!(timetable.apply(&mut raptor, &feed(vec![update]), &original_ids, day).unmatched_stops, 1);
+        assert!(raptor.query_ea(StopId(0), StopId(2), day).is_err());
+    }
+}
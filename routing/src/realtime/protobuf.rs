@@ -0,0 +1,185 @@
+use std::fmt;
+use std::fmt::Display;
+
+/// The parts of the protocol buffers wire format (https://protobuf.dev/programming-guides/encoding/)
+/// that GTFS-RT needs, so that feeds can be decoded without generated code
+pub(crate) struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+pub(crate) enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+impl<'a> Fields<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (byte, rest) = self.bytes.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+            self.bytes = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::InvalidVarint)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    // Field number and value
+    type Item = Result<(u32, Value<'a>), DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let field = (|| {
+            let key = self.varint()?;
+            let value = match key & 0x7 {
+                0 => Value::Varint(self.varint()?),
+                1 => Value::Fixed64(u64::from_le_bytes(self.take(8)?.try_into().unwrap())),
+                2 => {
+                    let len = self.varint()? as usize;
+                    Value::Bytes(self.take(len)?)
+                }
+                5 => Value::Fixed32(u32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+                wire_type => return Err(DecodeError::InvalidWireType(wire_type as u8)),
+            };
+            Ok(((key >> 3) as u32, value))
+        })();
+        // A broken message can't be read any further
+        if field.is_err() {
+            self.bytes = &[];
+        }
+        Some(field)
+    }
+}
+
+impl<'a> Value<'a> {
+    pub(crate) fn as_u64(&self) -> Result<u64, DecodeError> {
+        match self {
+            Value::Varint(value) | Value::Fixed64(value) => Ok(*value),
+            Value::Fixed32(value) => Ok(*value as u64),
+            Value::Bytes(_) => Err(DecodeError::UnexpectedType),
+        }
+    }
+
+    // Negative int32 and int64 values are sign-extended to 64 bits
+    pub(crate) fn as_i64(&self) -> Result<i64, DecodeError> {
+        Ok(self.as_u64()? as i64)
+    }
+
+    pub(crate) fn as_f32(&self) -> Result<f32, DecodeError> {
+        match self {
+            Value::Fixed32(value) => Ok(f32::from_bits(*value)),
+            _ => Err(DecodeError::UnexpectedType),
+        }
+    }
+
+    pub(crate) fn as_bytes(&self) -> Result<&'a [u8], DecodeError> {
+        match self {
+            Value::Bytes(bytes) => Ok(bytes),
+            _ => Err(DecodeError::UnexpectedType),
+        }
+    }
+
+    pub(crate) fn as_string(&self) -> Result<String, DecodeError> {
+        String::from_utf8(self.as_bytes()?.to_vec()).map_err(|_| DecodeError::InvalidString)
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    InvalidVarint,
+    InvalidWireType(u8),
+    UnexpectedType,
+    InvalidString,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "Message ends within a field"),
+            DecodeError::InvalidVarint => write!(f, "Integer is longer than 64 bits"),
+            DecodeError::InvalidWireType(wire_type) => write!(f, "Unknown wire type {}", wire_type),
+            DecodeError::UnexpectedType => write!(f, "Field has an unexpected type"),
+            DecodeError::InvalidString => write!(f, "String is not valid UTF-8"),
+        }
+    }
+}
+
+/// Encodes messages for tests, the counterpart of [Fields]
+#[cfg(test)]
+pub(crate) mod encode {
+    pub(crate) fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    pub(crate) fn int(field: u32, value: i64) -> Vec<u8> {
+        [varint((field as u64) << 3), varint(value as u64)].concat()
+    }
+
+    pub(crate) fn bytes(field: u32, value: &[u8]) -> Vec<u8> {
+        [varint(((field as u64) << 3) | 2), varint(value.len() as u64), value.to_vec()].concat()
+    }
+
+    pub(crate) fn float(field: u32, value: f32) -> Vec<u8> {
+        [varint(((field as u64) << 3) | 5), value.to_le_bytes().to_vec()].concat()
+    }
+
+    pub(crate) fn zigzag(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    pub(crate) fn packed(field: u32, values: &[u64]) -> Vec<u8> {
+        bytes(field, &values.iter().flat_map(|value| varint(*value)).collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields() {
+        let message = [encode::int(1, 150), encode::int(2, -5), encode::bytes(3, b"drino"), encode::float(4, 48.5)].concat();
+        let fields: Vec<(u32, Value)> = Fields::new(&message).collect::<Result<_, _>>().unwrap();
+
+        assert_eq!(fields.len(), 4);
+        assert_eq!((fields[0].0, fields[0].1.as_u64()), (1, Ok(150)));
+        assert_eq!((fields[1].0, fields[1].1.as_i64()), (2, Ok(-5)));
+        assert_eq!((fields[2].0, fields[2].1.as_string()), (3, Ok("drino".to_string())));
+        assert_eq!((fields[3].0, fields[3].1.as_f32()), (4, Ok(48.5)));
+
+        // The string is cut off
+        let truncated = &message[..message.len() - 1];
+        assert_eq!(Fields::new(truncated).last().unwrap().err(), Some(DecodeError::UnexpectedEnd));
+    }
+}
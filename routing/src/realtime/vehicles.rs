@@ -0,0 +1,128 @@
+use crate::itinerary::{Itinerary, ItineraryLeg};
+use common::types::id_interner::OriginalIds;
+use common::types::TripId;
+use hashbrown::HashMap;
+use serde::Serialize;
+
+/// Where a vehicle is, as in https://gtfs.org/documentation/realtime/reference/#message-vehicleposition
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct VehiclePosition {
+    // Id of the trip in its dataset, if the vehicle serves one
+    pub trip_id: Option<String>,
+    pub vehicle_id: Option<String>,
+    // What is shown to riders, e.g. the number of a train
+    pub label: Option<String>,
+    pub lat: f32,
+    pub lon: f32,
+    // Degrees clockwise from north
+    pub bearing: Option<f32>,
+    // Meters per second
+    pub speed: Option<f32>,
+    // Id of the stop the vehicle is at or approaching, in the dataset of the trip
+    pub stop_id: Option<String>,
+    pub status: Option<VehicleStatus>,
+    // Seconds since the UNIX epoch when the position was measured
+    pub timestamp: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum VehicleStatus {
+    IncomingAt,
+    StoppedAt,
+    InTransitTo,
+}
+
+/// The latest message of the GTFS-RT vehicle positions feed of a dataset
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VehiclePositionsFeed {
+    pub dataset_id: String,
+    pub positions: Vec<VehiclePosition>,
+}
+
+/// The vehicles of all feeds by the trip of the timetable that they serve
+#[derive(Debug, Default)]
+pub struct VehiclePositions {
+    by_trip: HashMap<TripId, VehiclePosition>,
+    // Vehicles without a trip or with a trip that isn't in the timetable
+    pub unmatched: usize,
+}
+
+impl VehiclePositions {
+    pub fn new(feeds: Vec<VehiclePositionsFeed>, original_ids: &OriginalIds) -> Self {
+        let mut vehicles = Self::default();
+        for VehiclePositionsFeed { dataset_id, positions } in feeds {
+            for position in positions {
+                let Some(trip) = position.trip_id.as_ref()
+                    .and_then(|trip| original_ids.find_trip(&format!("{dataset_id}:{trip}"))) else {
+                    vehicles.unmatched += 1;
+                    continue;
+                };
+                // If several vehicles report the same trip, e.g. the parts of a coupled train, the
+                // latest report wins
+                match vehicles.by_trip.get(&trip) {
+                    Some(other) if other.timestamp > position.timestamp => {}
+                    _ => {
+                        vehicles.by_trip.insert(trip, position);
+                    }
+                }
+            }
+        }
+        vehicles
+    }
+
+    pub fn of_trip(&self, trip: TripId) -> Option<&VehiclePosition> {
+        self.by_trip.get(&trip)
+    }
+
+    /// The vehicle serving each ride of the itinerary, `None` for transfers and rides of trips
+    /// without a reported position
+    pub fn for_itinerary(&self, itinerary: &Itinerary) -> Vec<Option<&VehiclePosition>> {
+        itinerary.legs.iter()
+            .map(|leg| match leg {
+                ItineraryLeg::Ride { trip, .. } => self.of_trip(*trip),
+                ItineraryLeg::Transfer { .. } => None,
+            })
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_trip.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_trip.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::types::id_interner::IdInterner;
+
+    #[test]
+    fn test_vehicle_positions() {
+        let original_ids = OriginalIds { trips: IdInterner::from_originals(["d:1", "d:2"]), ..Default::default() };
+        let position = |trip: Option<&str>, label: &str, timestamp: i64| VehiclePosition {
+            trip_id: trip.map(str::to_string),
+            label: Some(label.to_string()),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        };
+        let feed = VehiclePositionsFeed {
+            dataset_id: "d".to_string(),
+            positions: vec![
+                position(Some("1"), "later", 200),
+                position(Some("1"), "earlier", 100),
+                position(Some("9"), "unknown trip", 100),
+                position(None, "depot", 100),
+            ],
+        };
+
+        let vehicles = VehiclePositions::new(vec![feed], &original_ids);
+        assert_eq!(vehicles.len(), 1);
+        assert_eq!(vehicles.unmatched, 2);
+        assert_eq!(vehicles.of_trip(TripId(0)).and_then(|vehicle| vehicle.label.as_deref()), Some("later"));
+        assert_eq!(vehicles.of_trip(TripId(1)), None);
+    }
+}
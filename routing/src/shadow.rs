@@ -0,0 +1,165 @@
+use crate::algorithm::{EarliestArrival, EarliestArrivalOutput, QueryError, QueryResult, RoutingAlgorithm, Single, SingleEarliestArrival};
+use log::warn;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Runs a sampled share of all queries against a reference algorithm as well and logs whenever
+/// the results differ. Only the result of the primary algorithm is returned, so this can be used
+/// in production to continuously monitor e.g. transfer patterns against plain RAPTOR. The primary
+/// algorithm is lent for every query by [ShadowMode::shadowing], the reference is owned.
+pub struct ShadowMode<Reference> {
+    reference: Reference,
+    // Share of queries between 0 and 1 that are also run against the reference
+    sample_rate: f64,
+    num_queries: AtomicU64,
+    num_compared: AtomicU64,
+    num_discrepancies: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShadowStats {
+    pub num_queries: u64,
+    pub num_compared: u64,
+    pub num_discrepancies: u64,
+}
+
+impl<Reference> ShadowMode<Reference> {
+    pub fn new(reference: Reference, sample_rate: f64) -> Self {
+        Self {
+            reference,
+            sample_rate: sample_rate.clamp(0.0, 1.0),
+            num_queries: AtomicU64::new(0),
+            num_compared: AtomicU64::new(0),
+            num_discrepancies: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> ShadowStats {
+        ShadowStats {
+            num_queries: self.num_queries.load(Ordering::Relaxed),
+            num_compared: self.num_compared.load(Ordering::Relaxed),
+            num_discrepancies: self.num_discrepancies.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The primary algorithm, whose queries are counted and sampled by this shadow mode
+    pub fn shadowing<'a, Primary>(&'a self, primary: &'a Primary) -> Shadowed<'a, Primary, Reference> {
+        Shadowed { primary, shadow: self }
+    }
+
+    // Sampling is deterministic: The n-th query is sampled whenever the number of samples that
+    // should have been taken so far increases. This evenly spreads exactly `sample_rate` of all
+    // queries.
+    fn should_sample(&self) -> bool {
+        let n = self.num_queries.fetch_add(1, Ordering::Relaxed);
+        let before = (n as f64 * self.sample_rate).floor();
+        let after = ((n + 1) as f64 * self.sample_rate).floor();
+        after > before
+    }
+}
+
+/// A primary algorithm that is compared against the reference of a [ShadowMode]
+pub struct Shadowed<'a, Primary, Reference> {
+    primary: &'a Primary,
+    shadow: &'a ShadowMode<Reference>,
+}
+
+impl<Primary: RoutingAlgorithm, Reference: RoutingAlgorithm> RoutingAlgorithm for Shadowed<'_, Primary, Reference> {}
+
+impl<Primary, Reference> SingleEarliestArrival for Shadowed<'_, Primary, Reference>
+where
+    Primary: SingleEarliestArrival,
+    Reference: SingleEarliestArrival,
+{
+    fn query_ea(&self, input: EarliestArrival, cardinality: Single) -> QueryResult<EarliestArrivalOutput> {
+        let shadow = self.shadow;
+        if !shadow.should_sample() {
+            return self.primary.query_ea(input, cardinality);
+        }
+
+        let result = self.primary.query_ea(input.clone(), cardinality.clone());
+        let reference_result = shadow.reference.query_ea(input.clone(), cardinality.clone());
+        shadow.num_compared.fetch_add(1, Ordering::Relaxed);
+
+        // Journeys might differ while still being equally good, so only compare the arrival
+        let arrival = result.as_ref().map(|output| output.journey.arrival());
+        let reference_arrival = reference_result.as_ref().map(|output| output.journey.arrival());
+
+        let matches = match (&arrival, &reference_arrival) {
+            (Ok(arrival), Ok(reference_arrival)) => arrival == reference_arrival,
+            (Err(QueryError::NoRouteFound), Err(QueryError::NoRouteFound)) => true,
+            _ => false,
+        };
+
+        if !matches {
+            shadow.num_discrepancies.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                target: "shadow",
+                "Discrepancy for query from {} to {} departing at {}: arrival {:?}, reference arrival {:?}",
+                input.start, cardinality.target, input.earliest_departure,
+                arrival.map_err(|err| err.to_string()),
+                reference_arrival.map_err(|err| err.to_string()),
+            );
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journey::{Journey, Leg};
+    use chrono::{DateTime, TimeDelta, Utc};
+    use common::types::{StopId, TripId};
+
+    // Always returns a single ride arriving after the given duration
+    struct FixedDuration(Option<TimeDelta>);
+
+    impl RoutingAlgorithm for FixedDuration {}
+
+    impl SingleEarliestArrival for FixedDuration {
+        fn query_ea(&self, input: EarliestArrival, cardinality: Single) -> QueryResult<EarliestArrivalOutput> {
+            let duration = self.0.ok_or(QueryError::NoRouteFound)?;
+            Ok(Journey::from(vec![Leg::Ride {
+                trip: TripId(0),
+                boarding_stop: input.start,
+                alight_stop: cardinality.target,
+                boarding_time: input.earliest_departure,
+                alight_time: input.earliest_departure + duration,
+            }]).into())
+        }
+    }
+
+    fn query<A: SingleEarliestArrival>(algorithm: &A) -> QueryResult<EarliestArrivalOutput> {
+        algorithm.query_ea(
+            EarliestArrival::new(StopId(0), DateTime::<Utc>::UNIX_EPOCH),
+            Single { target: StopId(1) },
+        )
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let shadow = ShadowMode::new(FixedDuration(None), 0.25);
+        (0..100).for_each(|_| { let _ = query(&shadow.shadowing(&FixedDuration(None))); });
+
+        assert_eq!(shadow.stats(), ShadowStats { num_queries: 100, num_compared: 25, num_discrepancies: 0 });
+    }
+
+    #[test]
+    fn test_discrepancies() {
+        let shadow = ShadowMode::new(FixedDuration(Some(TimeDelta::minutes(15))), 1.0);
+        let result = query(&shadow.shadowing(&FixedDuration(Some(TimeDelta::minutes(20))))).unwrap();
+
+        // The result of the primary algorithm is returned, even if it is worse
+        assert_eq!(result.journey.arrival(), Some(DateTime::<Utc>::UNIX_EPOCH + TimeDelta::minutes(20)));
+        assert_eq!(shadow.stats().num_discrepancies, 1);
+
+        let shadow = ShadowMode::new(FixedDuration(Some(TimeDelta::minutes(15))), 1.0);
+        assert!(query(&shadow.shadowing(&FixedDuration(None))).is_err());
+        assert_eq!(shadow.stats().num_discrepancies, 1);
+
+        let shadow = ShadowMode::new(FixedDuration(Some(TimeDelta::minutes(15))), 0.0);
+        let _ = query(&shadow.shadowing(&FixedDuration(None)));
+        assert_eq!(shadow.stats().num_compared, 0);
+    }
+}
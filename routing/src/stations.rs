@@ -0,0 +1,94 @@
+use common::types::{StationId, StopId};
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::{col, LazyFrame};
+
+/// Lookup of which stops belong to the same logical station (e.g. all platforms of a train
+/// station). Built from the `stations` table of the preprocessing input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StationHierarchy {
+    // Index is the stop id, value is the station this stop belongs to
+    station_by_stop: Vec<StationId>,
+    stops_by_station: HashMap<StationId, Vec<StopId>>,
+}
+
+impl StationHierarchy {
+    pub fn from_stations(stations: LazyFrame) -> Result<Self, PolarsError> {
+        let stations = stations
+            .select([col("stop_id"), col("station_id")])
+            .sort(["stop_id"], Default::default())
+            .collect()?;
+
+        let stop_ids = stations.column("stop_id")?.u32()?;
+        let station_ids = stations.column("station_id")?.u32()?;
+
+        let mut station_by_stop = Vec::with_capacity(stop_ids.len());
+        let mut stops_by_station: HashMap<StationId, Vec<StopId>> = HashMap::new();
+
+        for (stop_id, station_id) in izip!(stop_ids, station_ids) {
+            let stop_id = StopId(stop_id.expect("stop_id must not be null"));
+            let station_id = StationId(station_id.expect("station_id must not be null"));
+
+            debug_assert!(
+                stop_id.0 as usize == station_by_stop.len(),
+                "Stop ids must be continuous, but {stop_id} was found at position {}",
+                station_by_stop.len()
+            );
+
+            station_by_stop.push(station_id);
+            stops_by_station.entry(station_id).or_default().push(stop_id);
+        }
+
+        Ok(Self { station_by_stop, stops_by_station })
+    }
+
+    /// The station a stop belongs to
+    pub fn station_of(&self, stop: StopId) -> Option<StationId> {
+        self.station_by_stop.get(stop.0 as usize).copied()
+    }
+
+    /// All stops (platforms) of a station
+    pub fn stops_of(&self, station: StationId) -> &[StopId] {
+        self.stops_by_station.get(&station)
+            .map(|stops| stops.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Whether two stops are part of the same station and can therefore be treated as one stop
+    pub fn same_station(&self, a: StopId, b: StopId) -> bool {
+        match (self.station_of(a), self.station_of(b)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    pub fn num_stations(&self) -> usize {
+        self.stops_by_station.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    #[test]
+    fn test_station_hierarchy() {
+        let stations = df!(
+            "stop_id"    => &[2u32, 0, 1, 3],
+            "station_id" => &[1u32, 0, 0, 2],
+        ).unwrap().lazy();
+
+        let hierarchy = StationHierarchy::from_stations(stations).unwrap();
+
+        assert_eq!(hierarchy.num_stations(), 3);
+        assert_eq!(hierarchy.station_of(StopId(1)), Some(StationId(0)));
+        assert_eq!(hierarchy.station_of(StopId(4)), None);
+        assert_eq!(hierarchy.stops_of(StationId(0)), &[StopId(0), StopId(1)]);
+        assert_eq!(hierarchy.stops_of(StationId(42)), &[] as &[StopId]);
+        assert!(hierarchy.same_station(StopId(0), StopId(1)));
+        assert!(!hierarchy.same_station(StopId(1), StopId(2)));
+    }
+}
@@ -0,0 +1,230 @@
+use geo::{Distance, Haversine, Point};
+use hashbrown::{HashMap, HashSet};
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::{col, LazyFrame};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Size of a grid cell of the spatial index in degrees (~1km in latitude)
+const CELL_SIZE: f32 = 0.01;
+
+/// Stops are identified by their original ids, since the numeric ids are reassigned whenever a new
+/// version of the data is simplified
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StopKey {
+    pub dataset_id: Arc<str>,
+    pub stop_id: Arc<str>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedStop {
+    pub name: Option<String>,
+    pub lat: f32,
+    pub lon: f32,
+}
+
+/// Number of stops that changed in the index when swapping in a new version of the data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndexDiff {
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+/// Search index for stops by name and by location. Built from the simplified `stops` table
+/// (columns "dataset_id", "stop_id_in_dataset", "stop_name", "lat", "lon").
+///
+/// When a new version of the data is swapped in, [StopIndex::update] only touches the stops that
+/// were added, removed or changed, so that swapping country-scale datasets does not pause serving
+/// while the whole index is rebuilt.
+#[derive(Debug, Clone, Default)]
+pub struct StopIndex {
+    stops: HashMap<StopKey, IndexedStop>,
+    cells: HashMap<(i32, i32), HashSet<StopKey>>,
+    // Lowercase names, to search for stops by prefix
+    names: BTreeMap<String, HashSet<StopKey>>,
+}
+
+impl StopIndex {
+    pub fn from_stops(stops: LazyFrame) -> Result<Self, PolarsError> {
+        let mut index = Self::default();
+        index.update(stops)?;
+        Ok(index)
+    }
+
+    /// Replaces the indexed stops with `stops` and returns what changed
+    pub fn update(&mut self, stops: LazyFrame) -> Result<IndexDiff, PolarsError> {
+        let mut new_stops = collect_stops(stops)?;
+        let mut diff = IndexDiff::default();
+
+        let old_keys = self.stops.keys().cloned().collect::<Vec<_>>();
+        for key in old_keys {
+            match new_stops.remove(&key) {
+                None => {
+                    self.remove(&key);
+                    diff.removed += 1;
+                }
+                Some(stop) if self.stops.get(&key) != Some(&stop) => {
+                    self.remove(&key);
+                    self.insert(key, stop);
+                    diff.changed += 1;
+                }
+                // Unchanged stops stay where they are
+                Some(_) => {}
+            }
+        }
+
+        // Stops that are left were not indexed before
+        diff.added = new_stops.len();
+        new_stops.into_iter().for_each(|(key, stop)| self.insert(key, stop));
+
+        Ok(diff)
+    }
+
+    pub fn get(&self, key: &StopKey) -> Option<&IndexedStop> {
+        self.stops.get(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stops.is_empty()
+    }
+
+    /// All stops whose name starts with `prefix`, ignoring case, ordered by name
+    pub fn search_name(&self, prefix: &str) -> Vec<&StopKey> {
+        let prefix = prefix.to_lowercase();
+        self.names.range(prefix.clone()..)
+            .take_while(|(name, _)| name.starts_with(&prefix))
+            .flat_map(|(_, keys)| {
+                let mut keys = keys.iter().collect::<Vec<_>>();
+                keys.sort();
+                keys
+            })
+            .collect()
+    }
+
+    /// All stops within `radius` meters of a location, the closest first
+    pub fn nearby(&self, lat: f32, lon: f32, radius: f32) -> Vec<&StopKey> {
+        let center = Point::new(lon, lat);
+        // Longitude degrees get shorter towards the poles, so more cells have to be searched there
+        let lat_cells = (radius / 111_000.0 / CELL_SIZE).ceil() as i32;
+        let lon_cells = (radius / (111_000.0 * lat.to_radians().cos().max(0.01)) / CELL_SIZE).ceil() as i32;
+        let (cell_lat, cell_lon) = cell(lat, lon);
+
+        let mut found = (-lat_cells..=lat_cells)
+            .flat_map(|d_lat| (-lon_cells..=lon_cells).map(move |d_lon| (cell_lat + d_lat, cell_lon + d_lon)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .filter_map(|key| {
+                let stop = &self.stops[key];
+                let distance = Haversine::distance(center, Point::new(stop.lon, stop.lat));
+                (distance <= radius).then_some((distance, key))
+            })
+            .collect::<Vec<_>>();
+
+        found.sort_by(|(a, a_key), (b, b_key)| a.total_cmp(b).then(a_key.cmp(b_key)));
+        found.into_iter().map(|(_, key)| key).collect()
+    }
+
+    fn insert(&mut self, key: StopKey, stop: IndexedStop) {
+        self.cells.entry(cell(stop.lat, stop.lon)).or_default().insert(key.clone());
+        if let Some(name) = &stop.name {
+            self.names.entry(name.to_lowercase()).or_default().insert(key.clone());
+        }
+        self.stops.insert(key, stop);
+    }
+
+    fn remove(&mut self, key: &StopKey) {
+        let Some(stop) = self.stops.remove(key) else { return };
+
+        let cell = cell(stop.lat, stop.lon);
+        if let Some(keys) = self.cells.get_mut(&cell) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.cells.remove(&cell);
+            }
+        }
+
+        if let Some(name) = stop.name.map(|name| name.to_lowercase()) {
+            if let Some(keys) = self.names.get_mut(&name) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.names.remove(&name);
+                }
+            }
+        }
+    }
+}
+
+fn cell(lat: f32, lon: f32) -> (i32, i32) {
+    ((lat / CELL_SIZE).floor() as i32, (lon / CELL_SIZE).floor() as i32)
+}
+
+fn collect_stops(stops: LazyFrame) -> Result<HashMap<StopKey, IndexedStop>, PolarsError> {
+    let stops = stops
+        .select([col("dataset_id"), col("stop_id_in_dataset"), col("stop_name"), col("lat"), col("lon")])
+        .collect()?;
+
+    let dataset_ids = stops.column("dataset_id")?.str()?;
+    let stop_ids = stops.column("stop_id_in_dataset")?.str()?;
+    let names = stops.column("stop_name")?.str()?;
+    let lats = stops.column("lat")?.f32()?;
+    let lons = stops.column("lon")?.f32()?;
+
+    Ok(izip!(dataset_ids, stop_ids, names, lats, lons)
+        .filter_map(|(dataset_id, stop_id, name, lat, lon)| {
+            let key = StopKey { dataset_id: dataset_id?.into(), stop_id: stop_id?.into() };
+            Some((key, IndexedStop { name: name.map(str::to_owned), lat: lat?, lon: lon? }))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    fn stops(ids: &[&str], names: &[&str], lats: &[f32]) -> LazyFrame {
+        df!(
+            "dataset_id"         => vec!["d"; ids.len()],
+            "stop_id_in_dataset" => ids,
+            "stop_name"          => names,
+            "lat"                => lats,
+            "lon"                => vec![9.0f32; ids.len()],
+        ).unwrap().lazy()
+    }
+
+    fn key(stop_id: &str) -> StopKey {
+        StopKey { dataset_id: "d".into(), stop_id: stop_id.into() }
+    }
+
+    #[test]
+    fn test_update() {
+        let mut index = StopIndex::from_stops(stops(
+            &["a", "b", "c"],
+            &["Hauptbahnhof", "Hauptstraße", "Marktplatz"],
+            &[48.0, 48.001, 48.1],
+        )).unwrap();
+
+        assert_eq!(index.search_name("haupt"), [&key("a"), &key("b")]);
+        assert_eq!(index.nearby(48.0, 9.0, 500.0), [&key("a"), &key("b")]);
+
+        // b is moved and renamed, c is removed and d is added
+        let diff = index.update(stops(
+            &["a", "b", "d"],
+            &["Hauptbahnhof", "Schlossplatz", "Hauptfriedhof"],
+            &[48.0, 48.1, 48.002],
+        )).unwrap();
+
+        assert_eq!(diff, IndexDiff { added: 1, removed: 1, changed: 1 });
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.search_name("haupt"), [&key("a"), &key("d")]);
+        assert_eq!(index.nearby(48.1, 9.0, 500.0), [&key("b")]);
+        assert!(index.get(&key("c")).is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::algorithm::{FromDiskInit, PreprocessingResult, SaveToDisk};
+use crate::artifacts;
+use crate::direct_connections::DirectConnections;
+use crate::stp::ScalableTransferPatternsAlgorithm;
+use crate::tp::transfer_pattern_ds::table::{patterns_to_frame, PatternsByStart, TransferPatternsTable};
+use common::types::config::Compression;
+use common::types::StopId;
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// The values of the algorithm besides its tables
+#[derive(Serialize, Deserialize)]
+struct Summary {
+    num_excluded_journeys: u64,
+}
+
+impl SaveToDisk for ScalableTransferPatternsAlgorithm {
+    fn save_to_disk(&self, dir: &Path, compression: Compression) -> PreprocessingResult<()> {
+        self.direct_connections.save(dir, compression)?;
+        let dir = dir.join("stp");
+
+        let (stop_ids, cluster_ids): (Vec<u32>, Vec<u32>) = self.clusters.iter().map(|(stop, cluster)| (stop.0, *cluster)).unzip();
+        artifacts::write_table(&dir, "clusters", df!("stop_id" => stop_ids, "cluster_id" => cluster_ids)?, compression)?;
+        let (stop_ids, cluster_ids): (Vec<u32>, Vec<u32>) = self.border_stops.iter()
+            .flat_map(|(cluster, stops)| stops.iter().map(|stop| (stop.0, *cluster)))
+            .unzip();
+        artifacts::write_table(&dir, "border_stops", df!("stop_id" => stop_ids, "cluster_id" => cluster_ids)?, compression)?;
+
+        artifacts::write_table(&dir, "local_patterns", by_start_to_frame(&self.local_patterns)?, compression)?;
+        artifacts::write_table(&dir, "long_distance_patterns", by_start_to_frame(&self.long_distance_patterns)?, compression)?;
+        artifacts::write_json(&dir, "summary.json", &Summary { num_excluded_journeys: self.num_excluded_journeys })
+    }
+}
+
+impl FromDiskInit for ScalableTransferPatternsAlgorithm {
+    fn load_from_disk(dir: &Path) -> PreprocessingResult<Self> {
+        let transfer_provider = artifacts::read_transfers(dir)?.into();
+        let direct_connections = DirectConnections::load(dir)?;
+        let dir = dir.join("stp");
+        let Summary { num_excluded_journeys } = artifacts::read_json(&dir, "summary.json")?;
+
+        let clusters = stops_with_clusters(&artifacts::read_table(&dir, "clusters")?)?.collect();
+        let mut border_stops: HashMap<u32, Vec<StopId>> = HashMap::new();
+        for (stop, cluster) in stops_with_clusters(&artifacts::read_table(&dir, "border_stops")?)? {
+            border_stops.entry(cluster).or_default().push(stop);
+        }
+        border_stops.values_mut().for_each(|stops| stops.sort());
+
+        let read_patterns = |name: &str| Ok::<PatternsByStart, PolarsError>(
+            TransferPatternsTable::from_frame(&artifacts::read_table(&dir, name)?)?.by_start()
+        );
+
+        Ok(Self {
+            direct_connections,
+            clusters,
+            border_stops,
+            local_patterns: read_patterns("local_patterns")?,
+            long_distance_patterns: read_patterns("long_distance_patterns")?,
+            transfer_provider,
+            num_excluded_journeys,
+        })
+    }
+}
+
+fn by_start_to_frame(patterns: &PatternsByStart) -> PolarsResult<DataFrame> {
+    patterns_to_frame(patterns.iter()
+        .flat_map(|(start, patterns)| patterns.iter().map(move |(stops, target)| (start, stops.as_slice(), target))))
+}
+
+// columns: "stop_id", "cluster_id"
+fn stops_with_clusters(table: &DataFrame) -> PolarsResult<impl Iterator<Item = (StopId, u32)> + '_> {
+    Ok(izip!(table.column("stop_id")?.u32()?.into_no_null_iter(), table.column("cluster_id")?.u32()?.into_no_null_iter())
+        .map(|(stop, cluster)| (StopId(stop), cluster)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::{FromDiskInit, SaveToDisk};
+    use crate::artifacts;
+    use crate::direct_connections::DirectConnections;
+    use crate::stp::ScalableTransferPatternsAlgorithm;
+    use crate::transfers::crow_fly::CrowFlyTransferProvider;
+    use common::types::config::{Compression, TransferConfig};
+    use common::types::StopId;
+    use hashbrown::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_save_and_load() {
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let algorithm = ScalableTransferPatternsAlgorithm {
+            direct_connections: DirectConnections::try_from(input.clone()).unwrap(),
+            clusters: HashMap::from([(StopId(0), 0), (StopId(1), 0), (StopId(2), 1)]),
+            border_stops: HashMap::from([(0, vec![StopId(1)]), (1, vec![StopId(2)])]),
+            local_patterns: HashMap::from([(StopId(0), vec![(vec![], StopId(1))])]),
+            long_distance_patterns: HashMap::from([(StopId(1), vec![(vec![], StopId(2))])]),
+            transfer_provider: Arc::new(CrowFlyTransferProvider::from_stops(input.stops.clone()).unwrap()),
+            num_excluded_journeys: 3,
+        };
+        let dir = tempfile::tempdir().unwrap();
+        artifacts::write_stops(dir.path(), input.stops, input.transfers, &TransferConfig::default(), Compression::default()).unwrap();
+        algorithm.save_to_disk(dir.path(), Compression::default()).unwrap();
+
+        let loaded = ScalableTransferPatternsAlgorithm::load_from_disk(dir.path()).unwrap();
+        assert_eq!(loaded.clusters, algorithm.clusters);
+        assert_eq!(loaded.border_stops, algorithm.border_stops);
+        assert_eq!(loaded.local_patterns, algorithm.local_patterns);
+        assert_eq!(loaded.long_distance_patterns, algorithm.long_distance_patterns);
+        assert_eq!(loaded.num_excluded_journeys, 3);
+    }
+}
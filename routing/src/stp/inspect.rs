@@ -0,0 +1,98 @@
+use crate::algorithm::{QueryError, QueryResult};
+use crate::stp::{PatternKind, ScalableTransferPatternsAlgorithm, TransferPattern};
+use crate::tp::transfer_pattern_ds::table::PatternsByStart;
+use common::types::StopId;
+
+impl ScalableTransferPatternsAlgorithm {
+    /// The cluster that a stop belongs to
+    pub fn cluster(&self, stop: StopId) -> Option<u32> {
+        self.clusters.get(&stop).copied()
+    }
+
+    /// All stored patterns, local ones first and each by start, transfers and target
+    pub fn transfer_patterns(&self) -> Vec<TransferPattern<'_>> {
+        let mut patterns: Vec<TransferPattern> = [(PatternKind::Local, &self.local_patterns), (PatternKind::LongDistance, &self.long_distance_patterns)]
+            .into_iter()
+            .flat_map(|(kind, patterns)| patterns.iter().flat_map(move |(start, patterns)| {
+                patterns.iter().map(move |(transfers, target)| TransferPattern { kind, start: *start, transfers, target: *target })
+            }))
+            .collect();
+        patterns.sort_unstable();
+        patterns
+    }
+
+    /// The patterns that the query graph between two stops is stitched from, in the order they are
+    /// added to it. Within a cluster, these are the local patterns between the stops. Otherwise,
+    /// they are the local patterns from the start to the border stops of its cluster, the
+    /// long-distance patterns between the border stops of both clusters and the local patterns
+    /// from the border stops of the target's cluster to the target.
+    pub fn patterns_between(&self, start: StopId, target: StopId) -> QueryResult<Vec<TransferPattern<'_>>> {
+        let start_cluster = self.clusters.get(&start).ok_or(QueryError::StopNotFound(start))?;
+        let target_cluster = self.clusters.get(&target).ok_or(QueryError::StopNotFound(target))?;
+
+        let mut patterns = vec![];
+        if start_cluster == target_cluster {
+            // Journeys that leave the cluster in between aren't considered
+            add_patterns(&mut patterns, PatternKind::Local, &self.local_patterns, start, &[target]);
+            return Ok(patterns);
+        }
+
+        let start_borders = self.border_stops.get(start_cluster).map(Vec::as_slice).unwrap_or_default();
+        let target_borders = self.border_stops.get(target_cluster).map(Vec::as_slice).unwrap_or_default();
+        add_patterns(&mut patterns, PatternKind::Local, &self.local_patterns, start, start_borders);
+        for border in start_borders {
+            add_patterns(&mut patterns, PatternKind::LongDistance, &self.long_distance_patterns, *border, target_borders);
+        }
+        for border in target_borders {
+            add_patterns(&mut patterns, PatternKind::Local, &self.local_patterns, *border, &[target]);
+        }
+        Ok(patterns)
+    }
+}
+
+// Adds the patterns from `start` to any of `targets`. A stop is trivially connected to itself, e.g.
+// if the start is a border stop.
+fn add_patterns<'a>(
+    patterns: &mut Vec<TransferPattern<'a>>,
+    kind: PatternKind,
+    by_start: &'a PatternsByStart,
+    start: StopId,
+    targets: &[StopId],
+) {
+    for (transfers, target) in by_start.get(&start).into_iter().flatten() {
+        if targets.contains(target) {
+            patterns.push(TransferPattern { kind, start, transfers, target: *target });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::direct_connections::DirectConnections;
+    use crate::stp::{PatternKind, ScalableTransferPatternsAlgorithm, TransferPattern};
+    use crate::transfers::crow_fly::CrowFlyTransferProvider;
+    use common::types::StopId;
+    use hashbrown::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_patterns_between() {
+        // Stops 0 and 1 are in cluster 0 with border stop 1, stop 2 is in cluster 1
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let algorithm = ScalableTransferPatternsAlgorithm {
+            direct_connections: DirectConnections::try_from(input.clone()).unwrap(),
+            clusters: HashMap::from([(StopId(0), 0), (StopId(1), 0), (StopId(2), 1)]),
+            border_stops: HashMap::from([(0, vec![StopId(1)]), (1, vec![StopId(2)])]),
+            local_patterns: HashMap::from([(StopId(0), vec![(vec![], StopId(1))])]),
+            long_distance_patterns: HashMap::from([(StopId(1), vec![(vec![], StopId(2))])]),
+            transfer_provider: Arc::new(CrowFlyTransferProvider::from_stops(input.stops).unwrap()),
+            num_excluded_journeys: 0,
+        };
+
+        let local = TransferPattern { kind: PatternKind::Local, start: StopId(0), transfers: &[], target: StopId(1) };
+        let long_distance = TransferPattern { kind: PatternKind::LongDistance, start: StopId(1), transfers: &[], target: StopId(2) };
+        assert_eq!(algorithm.patterns_between(StopId(0), StopId(2)).unwrap(), [local.clone(), long_distance.clone()]);
+        assert_eq!(algorithm.patterns_between(StopId(0), StopId(1)).unwrap(), [local.clone()]);
+        assert_eq!(algorithm.transfer_patterns(), [local, long_distance]);
+    }
+}
@@ -1,7 +1,66 @@
+mod artifacts;
+mod inspect;
 pub(crate) mod preprocessing;
+mod query;
 
-use crate::algorithm::RoutingAlgorithm;
+use crate::algorithm::{PreprocessingInput, PreprocessingResult, RoutingAlgorithm};
+use crate::direct_connections::DirectConnections;
+use crate::raptor::RaptorAlgorithm;
+use crate::tp::transfer_pattern_ds::table::PatternsByStart;
+use crate::transfers::SharedTransferProvider;
+use common::types::StopId;
+use hashbrown::HashMap;
+use std::sync::Arc;
 
-pub struct ScalableTransferPatternsAlgorithm;
+/// Scalable transfer patterns (https://ad.informatik.uni-freiburg.de/files/transferpatterns.pdf,
+/// extended by https://arxiv.org/abs/1607.01299). Stops are partitioned into clusters, local
+/// transfer patterns only connect the stops within a cluster and long-distance transfer patterns
+/// only connect the border stops of different clusters. Queries between clusters stitch them
+/// together, so no pattern between two stops of different clusters is ever stored.
+pub struct ScalableTransferPatternsAlgorithm {
+    // Of the whole network, so that patterns of all clusters can be followed
+    pub(crate) direct_connections: DirectConnections,
+    // The cluster of every stop
+    pub(crate) clusters: HashMap<StopId, u32>,
+    // Stops at which trips enter or leave a cluster, by cluster
+    pub(crate) border_stops: HashMap<u32, Vec<StopId>>,
+    pub(crate) local_patterns: PatternsByStart,
+    pub(crate) long_distance_patterns: PatternsByStart,
+    pub(crate) transfer_provider: SharedTransferProvider,
+    // Number of optimal journeys that weren't stored, since they have more legs than allowed
+    pub(crate) num_excluded_journeys: u64,
+}
 
-impl RoutingAlgorithm for ScalableTransferPatternsAlgorithm {}
\ No newline at end of file
+impl RoutingAlgorithm for ScalableTransferPatternsAlgorithm {}
+
+/// Which table of [ScalableTransferPatternsAlgorithm] a transfer pattern is stored in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PatternKind {
+    // Between stops of the same cluster
+    Local,
+    // Between border stops of different clusters
+    LongDistance,
+}
+
+/// A stored transfer pattern: the stops at which optimal journeys from its start to its target
+/// change between rides and walks. A pattern without transfers is a single ride.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TransferPattern<'a> {
+    pub kind: PatternKind,
+    pub start: StopId,
+    pub transfers: &'a [StopId],
+    pub target: StopId,
+}
+
+impl ScalableTransferPatternsAlgorithm {
+    /// The timetable that journeys are reconstructed from, e.g. with [crate::itinerary::Itinerary]
+    pub fn timetable(&self) -> &DirectConnections {
+        &self.direct_connections
+    }
+
+    /// Plain RAPTOR on the same timetable and walks, which answers the queries that transfer
+    /// patterns don't, e.g. profiles. `input` is the one that this was preprocessed from.
+    pub fn raptor(&self, input: PreprocessingInput) -> PreprocessingResult<RaptorAlgorithm> {
+        RaptorAlgorithm::preprocess_with_provider(input, self.direct_connections.clone(), Box::new(Arc::clone(&self.transfer_provider)))
+    }
+}
@@ -0,0 +1,71 @@
+use polars::frame::{DataFrame, UniqueKeepStrategy};
+use polars::prelude::*;
+
+/// Stops at which a trip enters or leaves their cluster, called border stations in section 3.1 of
+/// "Scalable Transfer Patterns". Journeys between clusters always pass one of them, so the global
+/// transfer patterns only need to connect border stops.
+///
+/// Returns the columns "stop_id" and "cluster_id" of the border stops, sorted by stop id.
+pub fn border_stops(
+    // columns: "stop_id", "cluster_id"
+    stop_ids_with_clusters: &DataFrame,
+    stop_times: &LazyFrame,
+) -> PolarsResult<DataFrame> {
+    let clusters = stop_ids_with_clusters.clone().lazy();
+
+    // Stop sequences may have gaps, so the next stop of a trip is the next one in its order
+    let rides = stop_times.clone()
+        .select([col("trip_id"), col("stop_sequence"), col("stop_id")])
+        .sort(["trip_id", "stop_sequence"], Default::default())
+        .with_column(col("stop_id").shift(lit(-1)).over([col("trip_id")]).alias("next_stop_id"))
+        .drop_nulls(Some(vec![col("next_stop_id")]))
+        .join(clusters.clone(), [col("stop_id")], [col("stop_id")], JoinArgs::new(JoinType::Inner))
+        .join(
+            clusters.clone().select([col("stop_id").alias("next_stop_id"), col("cluster_id").alias("next_cluster_id")]),
+            [col("next_stop_id")],
+            [col("next_stop_id")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .filter(col("cluster_id").neq(col("next_cluster_id")));
+
+    // Both ends of a ride between two clusters are border stops
+    let border_stop_ids = concat(
+        [
+            rides.clone().select([col("stop_id")]),
+            rides.select([col("next_stop_id").alias("stop_id")]),
+        ],
+        UnionArgs::default(),
+    )?;
+
+    clusters
+        .semi_join(border_stop_ids, col("stop_id"), col("stop_id"))
+        .unique(None, UniqueKeepStrategy::Any)
+        .sort(["stop_id"], Default::default())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_border_stops() {
+        let stop_ids_with_clusters = df!(
+            "stop_id"    => [0u32, 1, 2, 3, 4],
+            "cluster_id" => [0u32, 0, 1, 1, 1],
+        ).unwrap();
+        // Trip 0 runs from stop 0 to 1 and crosses into cluster 1 at stop 2. Trip 1 stays in
+        // cluster 1, even though its stop sequence has a gap.
+        let stop_times = df!(
+            "trip_id"       => [0u32, 0, 0, 1, 1],
+            "stop_sequence" => [0u32, 1, 2, 0, 5],
+            "stop_id"       => [0u32, 1, 2, 3, 4],
+        ).unwrap().lazy();
+
+        let border_stops = border_stops(&stop_ids_with_clusters, &stop_times).unwrap();
+        assert_eq!(border_stops, df!(
+            "stop_id"    => [1u32, 2],
+            "cluster_id" => [0u32, 1],
+        ).unwrap());
+    }
+}
@@ -7,7 +7,7 @@ pub fn filter_for_cluster(
     // columns: "stop_id", "cluster_id"
     stop_ids_with_cluster_ids: &DataFrame,
     PreprocessingInput {
-        stops, stop_times, trips, services
+        stops, stop_times, trips, services, stations, trip_runs, transfers, original_ids
     }: &PreprocessingInput,
 ) -> Result<PreprocessingInput, PreprocessingError> {
     let stop_ids_in_this_cluster = stop_ids_with_cluster_ids.clone().lazy()
@@ -44,6 +44,14 @@ pub fn filter_for_cluster(
         .unique(None, UniqueKeepStrategy::Any);
 
     let trips = trips.clone()
+        .semi_join(
+            trip_ids_in_this_cluster.clone(),
+            col("trip_id"),
+            col("trip_id"),
+        );
+
+    // Runs keep referencing their template, even if the template itself is not in this cluster
+    let trip_runs = trip_runs.clone()
         .semi_join(
             trip_ids_in_this_cluster,
             col("trip_id"),
@@ -61,11 +69,24 @@ pub fn filter_for_cluster(
             col("service_id"),
         );
     
+    // Only keep the station assignments of stops in this cluster
+    let stations = stations.clone()
+        .semi_join(
+            stop_ids_in_this_cluster.clone().lazy(),
+            col("stop_id"),
+            col("stop_id"),
+        );
+
     let preprocessing_input = PreprocessingInput {
         services: services.clone(),
         stops: stops.clone().lazy(),
         trips,
         stop_times,
+        stations,
+        trip_runs,
+        // Stops keep their ids, so the transfers between them stay the same
+        transfers: transfers.clone(),
+        original_ids: original_ids.clone(),
     };
     
     Ok(preprocessing_input)
@@ -76,8 +97,8 @@ pub fn filter_for_cluster(
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_filter_for_cluster() {
+    // Stops 2 and 3 of cluster 1 are served by trips 4, 5 and 6
+    fn filter_cluster_1() -> PreprocessingInput {
         let stop_ids_with_clusters = df!(
             "stop_id"    => &[0u32, 1, 2, 3, 4, 5],
             "cluster_id" => &[0u32, 0, 1, 1, 2, 2],
@@ -96,34 +117,48 @@ mod tests {
         let services = df!(
             "service_id" => [0u32, 1, 2, 3, 4, 5, 6, 7, 8],
         ).unwrap().lazy();
+        let stations = df!(
+            "stop_id"    => &[0u32, 1, 2, 3, 4, 5],
+            "station_id" => &[0u32, 0, 1, 2, 3, 3],
+        ).unwrap().lazy();
+        let trip_runs = df!(
+            "trip_id"          => [0u32, 1, 2, 3, 4, 5, 6, 7, 8],
+            "template_trip_id" => [0u32, 0, 0, 3, 3, 5, 6, 7, 8],
+        ).unwrap().lazy();
+
+        filter_for_cluster(
+            1,
+            &stop_ids_with_clusters,
+            &PreprocessingInput { stops, stop_times, trips, services, stations, trip_runs, transfers: Default::default(), original_ids: Default::default() },
+        ).unwrap()
+    }
 
+    #[test]
+    fn test_filter_for_cluster() {
         let PreprocessingInput {
             stops: filtered_stops,
             stop_times: filtered_stop_times,
             trips: filtered_trips,
             services: filtered_services,
-        } = filter_for_cluster(
-            1,
-            &stop_ids_with_clusters,
-            &PreprocessingInput { stops, stop_times, trips, services },
-        ).unwrap();
+            ..
+        } = filter_cluster_1();
 
         let filtered_stops_ids = filtered_stops.collect().unwrap()
             .column("stop_id").unwrap()
             .u32().unwrap()
             .to_vec();
         assert_eq!(filtered_stops_ids.len(), 2);
-        // The new stop ids are continuous starting from 0
-        assert!(filtered_stops_ids.contains(&Some(0)));
-        assert!(filtered_stops_ids.contains(&Some(1)));
+        // The stops keep their ids
+        assert!(filtered_stops_ids.contains(&Some(2)));
+        assert!(filtered_stops_ids.contains(&Some(3)));
 
         let filtered_stop_times_station_ids = filtered_stop_times.clone().collect().unwrap()
             .column("stop_id").unwrap()
             .u32().unwrap()
             .to_vec();
         assert_eq!(filtered_stop_times_station_ids.len(), 3);
-        assert!(filtered_stop_times_station_ids.contains(&Some(0)));
-        assert!(filtered_stop_times_station_ids.contains(&Some(1)));
+        assert!(filtered_stop_times_station_ids.contains(&Some(2)));
+        assert!(filtered_stop_times_station_ids.contains(&Some(3)));
         let filtered_stop_times_trip_ids = filtered_stop_times.collect().unwrap()
             .column("trip_id").unwrap()
             .u32().unwrap()
@@ -151,4 +186,27 @@ mod tests {
         assert!(filtered_service_ids.contains(&Some(5)));
         assert!(filtered_service_ids.contains(&Some(6)));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_filter_stations() {
+        let PreprocessingInput { stations, .. } = filter_cluster_1();
+
+        let filtered_station_ids = stations.collect().unwrap()
+            .column("station_id").unwrap()
+            .u32().unwrap()
+            .to_vec();
+        assert_eq!(filtered_station_ids, vec![Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn test_filter_trip_runs() {
+        let PreprocessingInput { trip_runs, .. } = filter_cluster_1();
+
+        // Trip 4 runs from template 3, which has no stop times in the cluster
+        let filtered_template_ids = trip_runs.collect().unwrap()
+            .column("template_trip_id").unwrap()
+            .u32().unwrap()
+            .to_vec();
+        assert_eq!(filtered_template_ids, vec![Some(3), Some(5), Some(6)]);
+    }
+}
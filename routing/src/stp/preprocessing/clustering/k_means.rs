@@ -1,24 +1,35 @@
+use crate::algorithm::PreprocessingError;
 use linfa::prelude::{Fit, Predict};
 use linfa::DatasetBase;
 use linfa_clustering::KMeans;
 use polars::frame::DataFrame;
-use polars::prelude::{col, Float32Type, IndexOrder, LazyFrame, Literal};
+use polars::prelude::{col, Column, DataType, Float32Type, IndexOrder, LazyFrame, Literal};
 use polars::series::Series;
 use std::fmt;
 use std::fmt::Display;
 
-const NUM_CLUSTERS: u32 = 8;
-
+/// Partitions the stops into `num_clusters` clusters by their coordinates. With fewer stops than
+/// clusters, every stop gets a cluster of its own. Returns the columns "stop_id" and "cluster_id"
+/// together with the actual number of clusters.
 pub fn cluster(
     stops: &LazyFrame,
+    num_clusters: u32,
 ) -> Result<(DataFrame, u32), KmeansClusterError> {
     let stops_array = stops.clone()
         .select([ col("lat"), col("lon")])
         .collect()?
         .to_ndarray::<Float32Type>(IndexOrder::default())?;
+    let num_clusters = num_clusters.min(stops_array.nrows() as u32);
+    if num_clusters == 0 {
+        let empty = DataFrame::new(vec![
+            Column::new_empty("stop_id".into(), &DataType::UInt32),
+            Column::new_empty("cluster_id".into(), &DataType::UInt32),
+        ])?;
+        return Ok((empty, 0));
+    }
     let stops_data = DatasetBase::from(stops_array.as_standard_layout().clone());
 
-    let k_means_model = KMeans::params(NUM_CLUSTERS as usize)
+    let k_means_model = KMeans::params(num_clusters as usize)
         .fit(&stops_data)?;
     let result = k_means_model.predict(stops_array);
 
@@ -32,7 +43,7 @@ pub fn cluster(
         .with_column(cluster_id_series.lit())
         .collect()?;
 
-    Ok((stop_ids_with_clusters, NUM_CLUSTERS))
+    Ok((stop_ids_with_clusters, num_clusters))
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -41,6 +52,15 @@ pub enum KmeansClusterError {
     KMeans(#[from] linfa_clustering::KMeansError),
 }
 
+impl From<KmeansClusterError> for PreprocessingError {
+    fn from(err: KmeansClusterError) -> Self {
+        match err {
+            KmeansClusterError::Polars(err) => PreprocessingError::Polars(err),
+            KmeansClusterError::KMeans(err) => PreprocessingError::KMeans(err),
+        }
+    }
+}
+
 impl Display for KmeansClusterError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let err: &dyn Display = match self {
@@ -49,4 +69,28 @@ impl Display for KmeansClusterError {
         };
         write!(f, "{}", err)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    #[test]
+    fn test_fewer_stops_than_clusters() {
+        let stops = df!(
+            "stop_id" => [0u32, 1, 2],
+            "lat"     => [48.0f32, 49.0, 50.0],
+            "lon"     => [9.0f32, 9.0, 9.0],
+        ).unwrap().lazy();
+
+        let (clusters, num_clusters) = cluster(&stops, 8).unwrap();
+        assert_eq!(num_clusters, 3);
+        let mut cluster_ids: Vec<_> = clusters.column("cluster_id").unwrap().u32().unwrap().into_no_null_iter().collect();
+        cluster_ids.sort();
+        assert_eq!(cluster_ids, [0, 1, 2]);
+
+        let (clusters, num_clusters) = cluster(&stops.clone().limit(0), 8).unwrap();
+        assert_eq!((clusters.height(), num_clusters), (0, 0));
+    }
+}
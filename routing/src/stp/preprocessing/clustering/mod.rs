@@ -1,6 +1,7 @@
 pub mod merging;
 pub mod k_means;
 pub mod filter_for_cluster;
+pub mod border_stops;
 pub use filter_for_cluster::filter_for_cluster as filter_for_cluster;
 pub mod dbscan;
 pub mod gmm;
@@ -2,61 +2,144 @@ use crate::algorithm::{
     PreprocessInit, PreprocessingError, PreprocessingInput, PreprocessingResult,
 };
 use crate::direct_connections::DirectConnections;
+use crate::stp::preprocessing::clustering::border_stops::border_stops;
 use crate::stp::preprocessing::clustering::filter_for_cluster;
 use crate::stp::preprocessing::clustering::k_means::cluster;
+use crate::stp::preprocessing::long_distance;
 use crate::stp::ScalableTransferPatternsAlgorithm;
-use crate::tp::transfer_pattern_ds::table::TransferPatternsTable;
+use crate::tp::transfer_pattern_ds::table::{patterns_to_frame, SpillingPatterns, TransferPatternsTable};
+use crate::tp::init::transfer_patterns_from;
 use crate::tp::TransferPatternsAlgorithm;
+use crate::transfers;
+use crate::transfers::SharedTransferProvider;
 use arrow_array::UInt32Array;
 use arrow_schema::{DataType, Field};
-use common::util::df::{write_df_to_file, write_geoarrow_to_file, FileType};
+use common::types::config::{Compression, RoutingConfig};
+use common::util::df::{write_df_to_file, write_df_to_file_compressed, write_geoarrow_to_file, FileType};
 use common::util::geoarrow_lines::build_geoarrow_lines;
-use common::util::logging::{run_with_pb, run_with_spinner};
+use common::util::logging::{run_with_spinner, ProgressSink};
+use common::types::StopId;
+use common::util::spill::Spill;
+use common::util::{checkpoint, paths};
+use hashbrown::{HashMap, HashSet};
+use log::info;
 use polars::frame::DataFrame;
-use polars::prelude::IntoLazy;
+use polars::df;
+use polars::prelude::{lit, IntoLazy, LazyFrame};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 impl PreprocessInit for ScalableTransferPatternsAlgorithm {
-    fn preprocess(input: PreprocessingInput, save_to_disk: bool) -> PreprocessingResult<Self> {
-        let (stop_ids_with_clusters, num_clusters) =
-            run_with_spinner("preprocessing", "Clustering stops", || {
-                let (stop_ids_with_clusters, num_clusters) =
-                    cluster(&input.stops).expect("Clustering failed");
+    fn preprocess(
+        input: PreprocessingInput,
+        config: &RoutingConfig,
+        save_to_disk: bool,
+        progress: &dyn ProgressSink,
+    ) -> PreprocessingResult<Self> {
+        let clustering = match read_clustering_checkpoint()? {
+            Some(clustering) => {
+                info!(target: "preprocessing", "Resuming with the {} clusters of an earlier run", clustering.1);
+                clustering
+            }
+            None => run_with_spinner("preprocessing", "Clustering stops", || {
+                let (stop_ids_with_clusters, num_clusters) = cluster(&input.stops, config.num_clusters)?;
 
-            let stops_clustered = input.stops.clone()
-                .left_join(stop_ids_with_clusters.clone().lazy(), "stop_id", "stop_id")
-                .collect()?;
+                let stops_clustered = input.stops.clone()
+                    .left_join(stop_ids_with_clusters.clone().lazy(), "stop_id", "stop_id")
+                    .collect()?;
 
                 // TODO: Switch to parquet
                 write_df_to_file(
-                    "data/tmp/stp/stops_clustered.csv".into(),
+                    paths::tmp_dir().join("stp").join("stops_clustered.csv"),
                     FileType::CSV,
                     stops_clustered,
                 )?;
 
-                Ok::<(DataFrame, u32), PreprocessingError>((stop_ids_with_clusters, num_clusters))
-            })?;
-
-        let message = format!("Calculating local transfers for {num_clusters} clusters");
-        run_with_pb("preprocessing", message.as_str(), num_clusters as u64, true, |pb| {
-            // Currently not parallelized, since individual clusters could take very different amounts
-            // of time and RAM usage is lower when only looking at a single cluster at a time.
-            // Therefore, we parallelize within one cluster.
-            for cluster_id in 0..num_clusters {
-                let cluster_result =
-                    Self::process_cluster(cluster_id, &stop_ids_with_clusters, &input)?;
-                if save_to_disk {
-                    Self::save_cluster(cluster_id, cluster_result)?;
-                }
+                // Journeys between clusters pass the stops where trips cross into another cluster
+                let border_stops = border_stops(&stop_ids_with_clusters, &input.stop_times)?;
+                info!(
+                    target: "preprocessing",
+                    "Partitioned {} stops into {} clusters with {} border stops",
+                    stop_ids_with_clusters.height(), num_clusters, border_stops.height(),
+                );
+                write_df_to_file(
+                    paths::tmp_dir().join("stp").join("border_stops.parquet"),
+                    FileType::PARQUET,
+                    border_stops.clone(),
+                )?;
 
-                pb.inc(1);
+                let clustering = (stop_ids_with_clusters, num_clusters, border_stops);
+                write_clustering_checkpoint(&clustering)?;
+                Ok::<(DataFrame, u32, DataFrame), PreprocessingError>(clustering)
+            })?,
+        };
+        let (stop_ids_with_clusters, num_clusters, border_stops) = clustering;
+        let clusters = clusters_by_stop(&stop_ids_with_clusters)?;
+        let border_stops = border_stops_by_cluster(&border_stops)?;
+
+        // Walks are built once for all stops, since clusters and border stops walk the same ways
+        let walking: SharedTransferProvider = transfers::walking(&input, &config.transfers)?.into();
+
+        let spill = Spill::in_work_dir(config.memory_budget);
+        let mut local_patterns = SpillingPatterns::new(spill.clone());
+        let mut num_excluded_journeys = 0;
+        let task = progress.start(&format!("Calculating local transfers for {num_clusters} clusters"), num_clusters as u64);
+        // Currently not parallelized, since individual clusters could take very different amounts
+        // of time and RAM usage is lower when only looking at a single cluster at a time.
+        // Therefore, we parallelize within one cluster.
+        for cluster_id in 0..num_clusters {
+            // Clusters that an earlier run completed only need their direct connections again
+            let ((tp_table, direct_connections), num_excluded) = match read_cluster_checkpoint(cluster_id)? {
+                Some((tp_table, num_excluded)) => {
+                    let cluster_input = filter_for_cluster(cluster_id, &stop_ids_with_clusters, &input)?;
+                    ((tp_table, DirectConnections::try_from(cluster_input)?), num_excluded)
+                }
+                None => {
+                    let result = Self::process_cluster(cluster_id, &stop_ids_with_clusters, &input, config, &walking, &spill, progress)?;
+                    write_cluster_checkpoint(cluster_id, &result.0.0, result.1)?;
+                    result
+                }
+            };
+            num_excluded_journeys += num_excluded;
+            if save_to_disk {
+                Self::save_cluster(cluster_id, direct_connections, config.compression)?;
             }
+            local_patterns.add(tp_table)?;
 
-            Ok::<(), PreprocessingError>(())
-        })?;
+            task.inc(1);
+        }
+        drop(task);
+        let local_patterns = local_patterns.into_table()?;
+
+        // The paper searches a reduced network for long-distance patterns, here the range queries
+        // just start at border stops only
+        let is_border_stop: HashSet<StopId> = border_stops.values().flatten().copied().collect();
+        let (long_distance_patterns, num_excluded) = transfer_patterns_from(
+            &input, config, &walking, &spill, |stop| is_border_stop.contains(stop), "Calculating long-distance transfers between border stops", progress,
+        )?;
+        num_excluded_journeys += num_excluded;
+        let long_distance_patterns = long_distance::long_distance_patterns(long_distance_patterns, &clusters, &border_stops);
+        info!(
+            target: "preprocessing",
+            "Calculated {} local and {} long-distance transfer patterns",
+            local_patterns.0.len(), long_distance_patterns.0.len(),
+        );
+
+        info!(
+            target: "preprocessing",
+            "Excluded {} optimal journeys with more than {} legs from transfer patterns",
+            num_excluded_journeys, config.max_legs,
+        );
 
-        // TODO
-        Ok(Self {})
+        Ok(Self {
+            direct_connections: DirectConnections::try_from(input.clone())?,
+            clusters,
+            border_stops,
+            local_patterns: local_patterns.by_start(),
+            long_distance_patterns: long_distance_patterns.by_start(),
+            transfer_provider: walking,
+            num_excluded_journeys,
+        })
     }
 }
 
@@ -65,28 +148,32 @@ impl ScalableTransferPatternsAlgorithm {
         cluster_id: u32,
         stop_ids_with_clusters: &DataFrame,
         overall_input: &PreprocessingInput,
-    ) -> Result<(TransferPatternsTable, DirectConnections), PreprocessingError> {
+        config: &RoutingConfig,
+        walking: &SharedTransferProvider,
+        spill: &Spill,
+        progress: &dyn ProgressSink,
+    ) -> Result<((TransferPatternsTable, DirectConnections), u64), PreprocessingError> {
         let input = filter_for_cluster(cluster_id, stop_ids_with_clusters, overall_input)?;
 
         write_df_to_file(
-            format!("./data/tmp/stp/clusters/{cluster_id}/stops.parquet").into(),
+            cluster_dir(cluster_id).join("stops.parquet"),
             FileType::PARQUET,
             input.stops.clone().collect()?,
         )?;
         write_df_to_file(
-            format!("./data/tmp/stp/clusters/{cluster_id}/trips.parquet").into(),
+            cluster_dir(cluster_id).join("trips.parquet"),
             FileType::PARQUET,
             input.trips.clone().collect()?,
         )?;
         write_df_to_file(
-            format!("./data/tmp/stp/clusters/{cluster_id}/stop_times.parquet").into(),
+            cluster_dir(cluster_id).join("stop_times.parquet"),
             FileType::PARQUET,
             input.stop_times.clone().collect()?,
         )?;
 
-        let result = TransferPatternsAlgorithm::preprocess(input.clone(), false)?;
+        let result = TransferPatternsAlgorithm::preprocess_with_provider(input.clone(), config, Arc::clone(walking), spill, progress)?;
 
-        let TransferPatternsAlgorithm { transfer_patterns, direct_connections } = result;
+        let TransferPatternsAlgorithm { transfer_patterns, direct_connections, num_excluded_journeys, .. } = result;
 
         // Build transfer patterns visualization
         {
@@ -110,7 +197,7 @@ impl ScalableTransferPatternsAlgorithm {
             table.append_column(target_field.into(), vec![Arc::new(target_id_array)])?;
 
             write_geoarrow_to_file(
-                format!("./data/tmp/stp/clusters/{cluster_id}/transfer_patterns.arrow").into(),
+                cluster_dir(cluster_id).join("transfer_patterns.arrow"),
                 FileType::IPC,
                 table,
             )?;
@@ -121,18 +208,19 @@ impl ScalableTransferPatternsAlgorithm {
             let table = direct_connections.to_geoarrow_lines(input.stops)?;
 
             write_geoarrow_to_file(
-                format!("./data/tmp/stp/clusters/{cluster_id}/lines_geo.arrow").into(),
+                cluster_dir(cluster_id).join("lines_geo.arrow"),
                 FileType::IPC,
                 table,
             )?;
         }
 
-        Ok((transfer_patterns, direct_connections))
+        Ok(((transfer_patterns, direct_connections), num_excluded_journeys))
     }
 
     fn save_cluster(
         cluster_id: u32,
-        (tp_table, direct_connections): (TransferPatternsTable, DirectConnections),
+        direct_connections: DirectConnections,
+        compression: Compression,
     ) -> Result<(), PreprocessingError> {
         // TODO: Switch to IPC as data format
 
@@ -142,18 +230,96 @@ impl ScalableTransferPatternsAlgorithm {
             tp_table.0
         )?; */
 
-        write_df_to_file(
-            format!("./data/preprocessing/stp/direct_connections/stop_incidence/cluster_id={cluster_id}/data.parquet").into(),
+        write_df_to_file_compressed(
+            paths::preprocessing_dir()
+                .join("stp").join("direct_connections").join("stop_incidence")
+                .join(format!("cluster_id={cluster_id}")).join("data.parquet"),
             FileType::PARQUET,
-            direct_connections.stop_incidence
+            direct_connections.stop_incidence,
+            compression,
         )?;
 
-        write_df_to_file(
-            format!("./data/preprocessing/stp/direct_connections/expanded_lines/cluster_id={cluster_id}/data.parquet").into(),
+        write_df_to_file_compressed(
+            paths::preprocessing_dir()
+                .join("stp").join("direct_connections").join("expanded_lines")
+                .join(format!("cluster_id={cluster_id}")).join("data.parquet"),
             FileType::PARQUET,
-            direct_connections.expanded_lines
+            direct_connections.expanded_lines,
+            compression,
         )?;
 
         Ok(())
     }
 }
+
+// columns: "stop_id", "cluster_id"
+fn clusters_by_stop(stop_ids_with_clusters: &DataFrame) -> Result<HashMap<StopId, u32>, PreprocessingError> {
+    Ok(stop_ids_with_clusters.column("stop_id")?.u32()?.iter()
+        .zip(stop_ids_with_clusters.column("cluster_id")?.u32()?.iter())
+        .filter_map(|(stop, cluster)| Some((StopId(stop?), cluster?)))
+        .collect())
+}
+
+// columns: "stop_id", "cluster_id"
+fn border_stops_by_cluster(border_stops: &DataFrame) -> Result<HashMap<u32, Vec<StopId>>, PreprocessingError> {
+    let mut by_cluster: HashMap<u32, Vec<StopId>> = HashMap::new();
+    for (stop, cluster) in clusters_by_stop(border_stops)? {
+        by_cluster.entry(cluster).or_default().push(stop);
+    }
+    by_cluster.values_mut().for_each(|stops| stops.sort());
+    Ok(by_cluster)
+}
+
+/// Directory for the intermediate results of a single cluster
+fn cluster_dir(cluster_id: u32) -> PathBuf {
+    paths::tmp_dir().join("stp").join("clusters").join(cluster_id.to_string())
+}
+
+// Directory for the clusters and the patterns of every completed cluster, if checkpoints are
+// enabled. Clustering isn't deterministic, so the patterns are only valid with these clusters.
+fn checkpoint_dir() -> Option<PathBuf> {
+    checkpoint::dir().map(|dir| dir.join("stp"))
+}
+
+fn write_clustering_checkpoint((stop_ids_with_clusters, num_clusters, border_stops): &(DataFrame, u32, DataFrame)) -> PreprocessingResult<()> {
+    let Some(dir) = checkpoint_dir() else { return Ok(()) };
+    write_df_to_file(dir.join("border_stops.parquet"), FileType::PARQUET, border_stops.clone())?;
+    // Written last, since it marks the clustering as complete
+    let clusters = stop_ids_with_clusters.clone().lazy()
+        .with_column(lit(*num_clusters).alias("num_clusters"))
+        .collect()?;
+    write_df_to_file(dir.join("clusters.parquet"), FileType::PARQUET, clusters)?;
+    Ok(())
+}
+
+// The clusters and border stops of an earlier run with the same config
+fn read_clustering_checkpoint() -> PreprocessingResult<Option<(DataFrame, u32, DataFrame)>> {
+    let Some(dir) = checkpoint_dir().filter(|dir| dir.join("clusters.parquet").exists()) else { return Ok(None) };
+    let clusters = LazyFrame::scan_parquet(dir.join("clusters.parquet"), Default::default())?.collect()?;
+    let num_clusters = clusters.column("num_clusters")?.u32()?.get(0).unwrap_or(0);
+    let stop_ids_with_clusters = clusters.select(["stop_id", "cluster_id"])?;
+    let border_stops = LazyFrame::scan_parquet(dir.join("border_stops.parquet"), Default::default())?.collect()?;
+    Ok(Some((stop_ids_with_clusters, num_clusters, border_stops)))
+}
+
+fn write_cluster_checkpoint(cluster_id: u32, tp_table: &TransferPatternsTable, num_excluded_journeys: u64) -> PreprocessingResult<()> {
+    let Some(dir) = checkpoint_dir() else { return Ok(()) };
+    let dir = dir.join(format!("cluster_id={cluster_id}"));
+    let patterns = patterns_to_frame(tp_table.0.iter().map(|(start, stops, target)| (start, stops.as_slice(), target)))?;
+    write_df_to_file(dir.join("transfer_patterns.parquet"), FileType::PARQUET, patterns)?;
+    // Written last, since it marks the cluster as complete
+    write_df_to_file(dir.join("excluded.parquet"), FileType::PARQUET, df!("num_excluded_journeys" => [num_excluded_journeys])?)?;
+    Ok(())
+}
+
+// The patterns and the number of excluded journeys of a cluster that an earlier run completed
+fn read_cluster_checkpoint(cluster_id: u32) -> PreprocessingResult<Option<(TransferPatternsTable, u64)>> {
+    let Some(dir) = checkpoint_dir().map(|dir| dir.join(format!("cluster_id={cluster_id}"))) else { return Ok(None) };
+    if !dir.join("excluded.parquet").exists() {
+        return Ok(None);
+    }
+    let excluded = LazyFrame::scan_parquet(dir.join("excluded.parquet"), Default::default())?.collect()?;
+    let num_excluded_journeys = excluded.column("num_excluded_journeys")?.u64()?.get(0).unwrap_or(0);
+    let patterns = LazyFrame::scan_parquet(dir.join("transfer_patterns.parquet"), Default::default())?.collect()?;
+    Ok(Some((TransferPatternsTable::from_frame(&patterns)?, num_excluded_journeys)))
+}
@@ -0,0 +1,46 @@
+use crate::tp::transfer_pattern_ds::table::TransferPatternsTable;
+use common::types::StopId;
+use hashbrown::HashMap;
+
+/// Of the patterns from border stops, only keeps the ones to border stops of another cluster.
+/// Patterns within a cluster are covered by its local patterns, and patterns to stops that aren't
+/// border stops by the local patterns of their cluster.
+pub(crate) fn long_distance_patterns(
+    patterns: TransferPatternsTable,
+    clusters: &HashMap<StopId, u32>,
+    border_stops: &HashMap<u32, Vec<StopId>>,
+) -> TransferPatternsTable {
+    let is_border_stop = |stop: &StopId| clusters.get(stop)
+        .and_then(|cluster| border_stops.get(cluster))
+        .is_some_and(|border_stops| border_stops.contains(stop));
+
+    TransferPatternsTable(patterns.0.into_iter()
+        .filter(|(start, _, target)| is_border_stop(start) && is_border_stop(target) && clusters.get(start) != clusters.get(target))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashbrown::HashSet;
+
+    #[test]
+    fn test_long_distance_patterns() {
+        // Stops 0 and 1 are in cluster 0, stops 2 and 3 in cluster 1. Stops 1 and 2 are border stops.
+        let clusters = HashMap::from([(StopId(0), 0), (StopId(1), 0), (StopId(2), 1), (StopId(3), 1)]);
+        let border_stops = HashMap::from([(0, vec![StopId(1)]), (1, vec![StopId(2)])]);
+        let patterns = TransferPatternsTable(HashSet::from([
+            (StopId(1), vec![], StopId(2)),
+            // To a stop that isn't a border stop, within a cluster and from a stop that isn't a
+            // border stop
+            (StopId(1), vec![StopId(2)], StopId(3)),
+            (StopId(1), vec![], StopId(0)),
+            (StopId(0), vec![StopId(1)], StopId(2)),
+        ]));
+
+        assert_eq!(
+            long_distance_patterns(patterns, &clusters, &border_stops),
+            TransferPatternsTable(HashSet::from([(StopId(1), vec![], StopId(2))])),
+        );
+    }
+}
@@ -1,2 +1,3 @@
 pub mod clustering;
-mod init;
\ No newline at end of file
+mod init;
+mod long_distance;
@@ -0,0 +1,85 @@
+use crate::algorithm::{EarliestArrival, EarliestArrivalOutput, QueryResult, Single, SingleEarliestArrival};
+use crate::stp::ScalableTransferPatternsAlgorithm;
+use crate::tp::query::earliest_arrival;
+use crate::tp::transfer_pattern_ds::query_graph::QueryGraph;
+use common::types::StopId;
+
+impl SingleEarliestArrival for ScalableTransferPatternsAlgorithm {
+    /// Stitches the local and long-distance patterns between the stops into a query graph and
+    /// searches it for the earliest arrival
+    fn query_ea(&self, input: EarliestArrival, Single { target }: Single) -> QueryResult<EarliestArrivalOutput> {
+        let graph = self.query_graph(input.start, target)?;
+        let journey = earliest_arrival(&graph, &self.direct_connections, self.transfer_provider.as_ref(), input, target)?;
+        Ok(EarliestArrivalOutput { journey })
+    }
+}
+
+impl ScalableTransferPatternsAlgorithm {
+    fn query_graph(&self, start: StopId, target: StopId) -> QueryResult<QueryGraph> {
+        let mut graph = QueryGraph::default();
+        for pattern in self.patterns_between(start, target)? {
+            graph.add_pattern(pattern.start, pattern.transfers, pattern.target);
+        }
+        Ok(graph)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::{JourneyPlanner, QueryError};
+    use crate::direct_connections::DirectConnections;
+    use crate::stp::ScalableTransferPatternsAlgorithm;
+    use crate::transfers::crow_fly::CrowFlyTransferProvider;
+    use chrono::{DateTime, Utc};
+    use common::types::StopId;
+    use hashbrown::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_query_ea() {
+        // Trip 0 runs from stop 0 to 1 at 100s to 500s, trip 1 from stop 1 to 2 at 1000s to 1500s.
+        // Stops 0 and 1 are in cluster 0, stop 2 in cluster 1, and trip 1 crosses between them.
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let algorithm = ScalableTransferPatternsAlgorithm {
+            direct_connections: DirectConnections::try_from(input.clone()).unwrap(),
+            clusters: HashMap::from([(StopId(0), 0), (StopId(1), 0), (StopId(2), 1)]),
+            border_stops: HashMap::from([(0, vec![StopId(1)]), (1, vec![StopId(2)])]),
+            local_patterns: HashMap::from([(StopId(0), vec![(vec![], StopId(1))])]),
+            long_distance_patterns: HashMap::from([(StopId(1), vec![(vec![], StopId(2))])]),
+            transfer_provider: Arc::new(CrowFlyTransferProvider::from_stops(input.stops).unwrap()),
+            num_excluded_journeys: 0,
+        };
+
+        let journey = algorithm.query_ea(StopId(0), StopId(2), DateTime::<Utc>::UNIX_EPOCH).unwrap();
+        assert_eq!(journey.legs().count(), 2);
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // Within a cluster, only the local patterns are used
+        let journey = algorithm.query_ea(StopId(0), StopId(1), DateTime::<Utc>::UNIX_EPOCH).unwrap();
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(500, 0));
+
+        assert!(matches!(
+            algorithm.query_ea(StopId(0), StopId(9), DateTime::<Utc>::UNIX_EPOCH),
+            Err(QueryError::StopNotFound(StopId(9))),
+        ));
+    }
+
+    #[test]
+    fn test_raptor() {
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let algorithm = ScalableTransferPatternsAlgorithm {
+            direct_connections: DirectConnections::try_from(input.clone()).unwrap(),
+            clusters: HashMap::from([(StopId(0), 0), (StopId(1), 0), (StopId(2), 1)]),
+            border_stops: HashMap::from([(0, vec![StopId(1)]), (1, vec![StopId(2)])]),
+            local_patterns: HashMap::from([(StopId(0), vec![(vec![], StopId(1))])]),
+            long_distance_patterns: HashMap::from([(StopId(1), vec![(vec![], StopId(2))])]),
+            transfer_provider: Arc::new(CrowFlyTransferProvider::from_stops(input.stops.clone()).unwrap()),
+            num_excluded_journeys: 0,
+        };
+
+        // Plain RAPTOR on the same timetable and walks finds the same journey without patterns
+        let raptor = algorithm.raptor(input).unwrap();
+        let journey = raptor.query_ea(StopId(0), StopId(2), DateTime::<Utc>::UNIX_EPOCH).unwrap();
+        assert_eq!(journey, algorithm.query_ea(StopId(0), StopId(2), DateTime::<Utc>::UNIX_EPOCH).unwrap());
+    }
+}
@@ -43,6 +43,17 @@ pub(crate) mod case_1 {
                 "departure_time" => [duration(100), duration(500)],
                 "stop_sequence" => [0u32, 1],
             ]?.lazy(),
+            stations: df![
+                "stop_id" => [0u32, 1],
+                "station_id" => [0u32, 1],
+            ]?.lazy(),
+            trip_runs: df![
+                "trip_id" => [0u32],
+                "template_trip_id" => [0u32],
+                "run_offset" => [duration(0)],
+            ]?.lazy(),
+            transfers: Default::default(),
+            original_ids: Default::default(),
         })
     }
 }
@@ -85,6 +96,17 @@ pub(crate) mod case_2 {
                 "departure_time" => [duration(100), duration(500), duration(1_000), duration(1_500)],
                 "stop_sequence" => [0u32, 1, 0, 1],
             ]?.lazy(),
+            stations: df![
+                "stop_id" => [0u32, 1, 2],
+                "station_id" => [0u32, 1, 2],
+            ]?.lazy(),
+            trip_runs: df![
+                "trip_id" => [0u32, 1],
+                "template_trip_id" => [0u32, 1],
+                "run_offset" => [duration(0), duration(0)],
+            ]?.lazy(),
+            transfers: Default::default(),
+            original_ids: Default::default(),
         })
     }
 }
@@ -128,11 +150,22 @@ pub(crate) mod case_3 {
                 "departure_time" => [duration(100), duration(500), duration(1_000), duration(1_500)],
                 "stop_sequence" => [0u32, 1, 0, 1],
             ]?.lazy(),
+            stations: df![
+                "stop_id" => [0u32, 1, 2, 3],
+                "station_id" => [0u32, 1, 2, 3],
+            ]?.lazy(),
+            trip_runs: df![
+                "trip_id" => [0u32, 1],
+                "template_trip_id" => [0u32, 1],
+                "run_offset" => [duration(0), duration(0)],
+            ]?.lazy(),
+            transfers: Default::default(),
+            original_ids: Default::default(),
         })
     }
 }
 
 /// Helper function for generating arrival and departure times more concisely
-fn duration<'a>(seconds: i64) -> AnyValue<'a> {
+pub(crate) fn duration<'a>(seconds: i64) -> AnyValue<'a> {
     AnyValue::Duration(seconds * 1_000, TimeUnit::Milliseconds)
 }
\ No newline at end of file
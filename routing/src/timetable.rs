@@ -0,0 +1,262 @@
+use crate::algorithm::{
+    FromDiskInit, PreprocessInit, PreprocessingInput, PreprocessingResult, QueryResult, RoutingAlgorithm, SaveToDisk,
+};
+use crate::calendar::ServiceCalendar;
+use crate::direct_connections::DirectConnections;
+use crate::journey::Journey;
+use chrono::{DateTime, NaiveDate, Utc};
+use common::types::config::{Compression, RoutingConfig};
+use common::util::logging::ProgressSink;
+use common::types::{LineId, StopId, TripId};
+use dashmap::DashMap;
+use itertools::izip;
+use polars::prelude::*;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Stops whose stop times are cached at most, besides the warmed up ones. The least recently
+// looked up ones are evicted first.
+const STOP_CACHE_CAPACITY: usize = 4096;
+// Last use of stop times that are never evicted
+const PINNED: u64 = u64::MAX;
+
+/// A vehicle leaving a stop, as shown on a departure board
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Departure {
+    pub trip: TripId,
+    pub line: LineId,
+    pub departure: DateTime<Utc>,
+    // Last stop of the trip
+    pub destination: StopId,
+}
+
+/// Answers queries that don't need transfers straight from the direct connections, without the
+/// preprocessing of a routing algorithm. Like elsewhere, times are relative to the start of the
+/// service day. Only the trips that run on the date of a query are looked up.
+pub struct TimetableLookup {
+    direct_connections: DirectConnections,
+    calendar: ServiceCalendar,
+    // The stop times at stops that were looked up recently or warmed up, so that only the first
+    // lookup of a stop has to go through the stop times of all trips
+    stop_times_by_stop: DashMap<StopId, CachedStopTimes>,
+    cache_capacity: usize,
+    // Counts the lookups, which orders the cached stop times by their last use
+    lookups: AtomicU64,
+}
+
+struct CachedStopTimes {
+    stop_times: DataFrame,
+    // Number of lookups before the last use, [PINNED] for warmed up stops
+    last_used: AtomicU64,
+}
+
+impl RoutingAlgorithm for TimetableLookup {}
+
+impl PreprocessInit for TimetableLookup {
+    fn preprocess(input: PreprocessingInput, _: &RoutingConfig, _: bool, _: &dyn ProgressSink) -> PreprocessingResult<Self> {
+        let calendar = ServiceCalendar::new(&input)?;
+        Ok(Self::new(DirectConnections::try_from(input)?, calendar))
+    }
+}
+
+impl SaveToDisk for TimetableLookup {
+    fn save_to_disk(&self, dir: &Path, compression: Compression) -> PreprocessingResult<()> {
+        self.calendar.save(dir, compression)?;
+        Ok(self.direct_connections.save(dir, compression)?)
+    }
+}
+
+impl FromDiskInit for TimetableLookup {
+    fn load_from_disk(dir: &Path) -> PreprocessingResult<Self> {
+        Ok(Self::new(DirectConnections::load(dir)?, ServiceCalendar::load(dir)?))
+    }
+}
+
+impl TimetableLookup {
+    pub fn new(direct_connections: DirectConnections, calendar: ServiceCalendar) -> Self {
+        Self {
+            direct_connections,
+            calendar,
+            stop_times_by_stop: DashMap::new(),
+            cache_capacity: STOP_CACHE_CAPACITY,
+            lookups: AtomicU64::new(0),
+        }
+    }
+
+    /// The timetable that the lookups answer from
+    pub fn timetable(&self) -> &DirectConnections {
+        &self.direct_connections
+    }
+
+    /// Caches the lookups of the stops, e.g. of the busiest stations before serving. Unlike the
+    /// stops of other lookups, they stay cached.
+    pub fn warm_up(&self, stops: &[StopId]) -> QueryResult<()> {
+        stops.iter().try_for_each(|stop| {
+            let stop_times = self.direct_connections.stop_times_at(*stop).collect()?;
+            self.stop_times_by_stop.insert(*stop, CachedStopTimes { stop_times, last_used: AtomicU64::new(PINNED) });
+            Ok(())
+        })
+    }
+
+    fn stop_times_at(&self, stop: StopId) -> QueryResult<LazyFrame> {
+        let lookup = self.lookups.fetch_add(1, Ordering::Relaxed);
+        if let Some(cached) = self.stop_times_by_stop.get(&stop) {
+            if cached.last_used.load(Ordering::Relaxed) != PINNED {
+                cached.last_used.store(lookup, Ordering::Relaxed);
+            }
+            return Ok(cached.stop_times.clone().lazy());
+        }
+
+        let stop_times = self.direct_connections.stop_times_at(stop).collect()?;
+        if self.stop_times_by_stop.len() >= self.cache_capacity {
+            self.evict_least_recently_used();
+        }
+        self.stop_times_by_stop.insert(stop, CachedStopTimes { stop_times: stop_times.clone(), last_used: AtomicU64::new(lookup) });
+        Ok(stop_times.lazy())
+    }
+
+    // Lookups of other stops may be cached in the meantime, so the cache can hold a few more stops
+    // than its capacity while it is busy
+    fn evict_least_recently_used(&self) {
+        let least_recently_used = self.stop_times_by_stop.iter()
+            .map(|entry| (entry.last_used.load(Ordering::Relaxed), *entry.key()))
+            .filter(|(last_used, _)| *last_used != PINNED)
+            .min();
+        if let Some((_, stop)) = least_recently_used {
+            self.stop_times_by_stop.remove(&stop);
+        }
+    }
+
+    // Only the stop times of the trips that run on the date
+    fn running_on(&self, stop_times: LazyFrame, date: NaiveDate) -> LazyFrame {
+        let trips: Vec<u32> = self.calendar.trips_running(date).map(|trip| trip.0).collect();
+        let trips = DataFrame::new(vec![Column::new("trip_id".into(), trips)])
+            .expect("A single column is always a valid frame");
+        stop_times.semi_join(trips.lazy(), col("trip_id"), col("trip_id"))
+    }
+
+    /// Rides from one stop to the other without a transfer on the service day `date` that depart
+    /// at or after the earliest departure, the earliest arrival first. Empty if no trip that runs
+    /// on the date serves both stops in this order.
+    pub fn direct_connections(
+        &self,
+        from: StopId,
+        to: StopId,
+        date: NaiveDate,
+        earliest_departure: DateTime<Utc>,
+        limit: usize,
+    ) -> QueryResult<Vec<Journey>> {
+        let rides = DirectConnections::rides_between(
+            self.running_on(self.stop_times_at(from)?, date),
+            self.stop_times_at(to)?,
+            from, to, earliest_departure, limit,
+        )?;
+        Ok(rides.into_iter().map(|ride| Journey::from(vec![ride])).collect())
+    }
+
+    /// The next departures at a stop on the service day `date`, at or after the earliest departure.
+    /// Trips that end at the stop don't depart from it.
+    pub fn departures(&self, stop: StopId, date: NaiveDate, earliest_departure: DateTime<Utc>, limit: usize) -> QueryResult<Vec<Departure>> {
+        let destinations = self.direct_connections.stop_times()
+            .filter(col("stop_sequence").eq(col("stop_sequence").max().over([col("trip_id")])))
+            .select([col("trip_id"), col("stop_id").alias("destination"), col("stop_sequence").alias("last_sequence")]);
+
+        let departures = self.running_on(self.stop_times_at(stop)?, date)
+            .filter(col("departure_time").gt_eq(lit(earliest_departure.timestamp_millis())))
+            .join(destinations, [col("trip_id")], [col("trip_id")], JoinArgs::new(JoinType::Inner))
+            .filter(col("stop_sequence").lt(col("last_sequence")))
+            .sort(["departure_time", "trip_id"], Default::default())
+            .limit(limit as IdxSize)
+            .collect()?;
+
+        let departures = izip!(
+            departures.column("trip_id")?.u32()?,
+            departures.column("line_id")?.u32()?,
+            departures.column("departure_time")?.i64()?,
+            departures.column("destination")?.u32()?,
+        )
+            .filter_map(|(trip, line, departure, destination)| Some(Departure {
+                trip: TripId(trip?),
+                line: LineId(line?),
+                departure: DateTime::from_timestamp_millis(departure?)?,
+                destination: StopId(destination?),
+            }))
+            .collect();
+
+        Ok(departures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::util::logging::NoProgress;
+
+    // Case 2: trip 0 runs from stop 0 to 1, trip 1 from stop 1 to 2, both on every day until 2070
+    fn lookup() -> TimetableLookup {
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        TimetableLookup::preprocess(input, &Default::default(), false, &NoProgress).unwrap()
+    }
+
+    fn date() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, 1).unwrap()
+    }
+
+    #[test]
+    fn test_direct_connections() {
+        let lookup = lookup();
+        let start = DateTime::<Utc>::UNIX_EPOCH;
+
+        let journeys = lookup.direct_connections(StopId(1), StopId(2), date(), start, 5).unwrap();
+        assert_eq!(journeys.len(), 1);
+        assert_eq!(journeys[0].departure(), DateTime::from_timestamp(1_000, 0));
+        assert_eq!(journeys[0].arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // Trip 0 has already left, and there is no trip from stop 0 to stop 2
+        assert!(lookup.direct_connections(StopId(0), StopId(1), date(), start + chrono::TimeDelta::seconds(200), 5).unwrap().is_empty());
+        assert!(lookup.direct_connections(StopId(0), StopId(2), date(), start, 5).unwrap().is_empty());
+
+        // No trip runs after the calendar ended
+        let after_calendar = NaiveDate::from_ymd_opt(2071, 1, 1).unwrap();
+        assert!(lookup.direct_connections(StopId(1), StopId(2), after_calendar, start, 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_departures() {
+        let lookup = lookup();
+
+        let departures = lookup.departures(StopId(1), date(), DateTime::<Utc>::UNIX_EPOCH, 5).unwrap();
+        assert_eq!(departures.len(), 1);
+        assert_eq!((departures[0].trip, departures[0].destination), (TripId(1), StopId(2)));
+
+        assert!(lookup.departures(StopId(2), date(), DateTime::<Utc>::UNIX_EPOCH, 5).unwrap().is_empty());
+        assert!(lookup.departures(StopId(1), NaiveDate::from_ymd_opt(2071, 1, 1).unwrap(), DateTime::<Utc>::UNIX_EPOCH, 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_warm_up() {
+        let lookup = lookup();
+
+        lookup.warm_up(&[StopId(1), StopId(2)]).unwrap();
+        assert_eq!(lookup.stop_times_by_stop.len(), 2);
+        // Stop 1 is served by both trips, stop 2 only by trip 1
+        assert_eq!(lookup.stop_times_by_stop.get(&StopId(1)).unwrap().stop_times.height(), 2);
+
+        // Cached lookups give the same results
+        let journeys = lookup.direct_connections(StopId(1), StopId(2), date(), DateTime::<Utc>::UNIX_EPOCH, 5).unwrap();
+        assert_eq!(journeys.len(), 1);
+    }
+
+    #[test]
+    fn test_cache_capacity() {
+        let lookup = TimetableLookup { cache_capacity: 1, ..lookup() };
+        lookup.warm_up(&[StopId(0)]).unwrap();
+
+        // The warmed up stop stays cached, the others replace the least recently used one
+        lookup.departures(StopId(1), date(), DateTime::<Utc>::UNIX_EPOCH, 5).unwrap();
+        lookup.departures(StopId(2), date(), DateTime::<Utc>::UNIX_EPOCH, 5).unwrap();
+        let mut cached: Vec<StopId> = lookup.stop_times_by_stop.iter().map(|entry| *entry.key()).collect();
+        cached.sort();
+        assert_eq!(cached, [StopId(0), StopId(2)]);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::algorithm::{FromDiskInit, PreprocessingResult, SaveToDisk};
+use crate::artifacts;
+use crate::direct_connections::DirectConnections;
+use crate::tp::transfer_pattern_ds::table::{patterns_to_frame, TransferPatternsTable};
+use crate::tp::TransferPatternsAlgorithm;
+use common::types::config::Compression;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// The values of the algorithm besides its tables
+#[derive(Serialize, Deserialize)]
+struct Summary {
+    num_excluded_journeys: u64,
+}
+
+impl SaveToDisk for TransferPatternsAlgorithm {
+    fn save_to_disk(&self, dir: &Path, compression: Compression) -> PreprocessingResult<()> {
+        self.direct_connections.save(dir, compression)?;
+        let dir = dir.join("tp");
+        let patterns = self.transfer_patterns.0.iter().map(|(start, stops, target)| (start, stops.as_slice(), target));
+        artifacts::write_table(&dir, "transfer_patterns", patterns_to_frame(patterns)?, compression)?;
+        artifacts::write_json(&dir, "summary.json", &Summary { num_excluded_journeys: self.num_excluded_journeys })
+    }
+}
+
+impl FromDiskInit for TransferPatternsAlgorithm {
+    fn load_from_disk(dir: &Path) -> PreprocessingResult<Self> {
+        let transfer_provider = artifacts::read_transfers(dir)?;
+        let direct_connections = DirectConnections::load(dir)?;
+        let dir = dir.join("tp");
+        let Summary { num_excluded_journeys } = artifacts::read_json(&dir, "summary.json")?;
+
+        Ok(Self {
+            direct_connections,
+            transfer_patterns: TransferPatternsTable::from_frame(&artifacts::read_table(&dir, "transfer_patterns")?)?,
+            transfer_provider,
+            num_excluded_journeys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::{FromDiskInit, JourneyPlanner, PreprocessInit, SaveToDisk};
+    use crate::artifacts;
+    use crate::tp::TransferPatternsAlgorithm;
+    use chrono::{DateTime, Utc};
+    use common::types::config::{Compression, TransferConfig};
+    use common::types::StopId;
+    use common::util::logging::NoProgress;
+
+    #[test]
+    fn test_save_and_load() {
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let algorithm = <TransferPatternsAlgorithm as PreprocessInit>::preprocess(input.clone(), &Default::default(), false, &NoProgress).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        artifacts::write_stops(dir.path(), input.stops, input.transfers, &TransferConfig::default(), Compression::default()).unwrap();
+        algorithm.save_to_disk(dir.path(), Compression::default()).unwrap();
+
+        let loaded = TransferPatternsAlgorithm::load_from_disk(dir.path()).unwrap();
+        assert_eq!(loaded.transfer_patterns, algorithm.transfer_patterns);
+        assert_eq!(loaded.direct_connections, algorithm.direct_connections);
+        let journey = loaded.query_ea(StopId(0), StopId(2), DateTime::<Utc>::UNIX_EPOCH).unwrap();
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+    }
+}
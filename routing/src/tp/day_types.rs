@@ -0,0 +1,254 @@
+use crate::algorithm::{PreprocessingInput, PreprocessingResult};
+use chrono::{Datelike, NaiveDate, TimeDelta, Weekday};
+use itertools::{izip, Itertools};
+use polars::prelude::*;
+
+const WEEKDAYS: [(Weekday, &str); 7] = [
+    (Weekday::Mon, "monday"),
+    (Weekday::Tue, "tuesday"),
+    (Weekday::Wed, "wednesday"),
+    (Weekday::Thu, "thursday"),
+    (Weekday::Fri, "friday"),
+    (Weekday::Sat, "saturday"),
+    (Weekday::Sun, "sunday"),
+];
+
+/// Dates on which exactly the same services run, e.g. all Mondays to Fridays during school terms.
+/// Transfer patterns only have to be computed once for all of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DayType {
+    // Weekdays of the dates, in the order of the week
+    pub weekdays: Vec<Weekday>,
+    // Sorted ids of the services that run on these dates
+    pub service_ids: Vec<u32>,
+    // Sorted dates this day type represents
+    pub dates: Vec<NaiveDate>,
+}
+
+// A service with the days since 1970-01-01 it runs between and its weekday flags starting with
+// monday
+struct ServiceDays {
+    service_id: u32,
+    start: i32,
+    end: i32,
+    weekdays: [bool; 7],
+}
+
+/// Groups the dates of the calendar by their services. Dates without any service are left out.
+///
+/// The services that run only change at the start and end dates of services, so the calendar is
+/// split into periods between these dates and only the weekdays of each period are compared,
+/// instead of every single date.
+pub(crate) fn day_types(services: LazyFrame) -> PreprocessingResult<Vec<DayType>> {
+    let services = services
+        .select([
+            vec![
+                col("service_id"),
+                col("start_date").cast(DataType::Int32),
+                col("end_date").cast(DataType::Int32),
+            ],
+            WEEKDAYS.iter().map(|(_, column)| col(*column)).collect(),
+        ].concat())
+        .sort(["service_id"], Default::default())
+        .collect()?;
+
+    let weekday_columns = WEEKDAYS.iter()
+        .map(|(_, column)| services.column(column)?.bool().cloned())
+        .collect::<Result<Vec<_>, _>>()?;
+    let services: Vec<ServiceDays> = izip!(
+        services.column("service_id")?.u32()?,
+        services.column("start_date")?.i32()?,
+        services.column("end_date")?.i32()?,
+    )
+        .enumerate()
+        .filter_map(|(row, (service_id, start, end))| Some(ServiceDays {
+            service_id: service_id?,
+            start: start?,
+            end: end?,
+            weekdays: std::array::from_fn(|weekday| weekday_columns[weekday].get(row) == Some(true)),
+        }))
+        .collect();
+
+    // Each period starts at one of these days and lasts until the next one
+    let boundaries: Vec<i32> = services.iter()
+        .flat_map(|service| [service.start, service.end + 1])
+        .sorted()
+        .dedup()
+        .collect();
+
+    let mut day_types: Vec<DayType> = vec![];
+    for (period_start, period_end) in boundaries.into_iter().tuple_windows() {
+        let running: Vec<&ServiceDays> = services.iter()
+            .filter(|service| service.start <= period_start && period_end <= service.end + 1)
+            .collect();
+
+        for (index, (weekday, _)) in WEEKDAYS.iter().enumerate() {
+            let service_ids: Vec<u32> = running.iter()
+                .filter(|service| service.weekdays[index])
+                .map(|service| service.service_id)
+                .collect();
+            if service_ids.is_empty() {
+                continue;
+            }
+
+            let dates = (period_start..period_end)
+                .map(from_days_since_epoch)
+                .filter(|date| date.weekday() == *weekday);
+
+            match day_types.iter_mut().find(|day_type| day_type.service_ids == service_ids) {
+                Some(day_type) => day_type.dates.extend(dates),
+                None => day_types.push(DayType { weekdays: vec![], service_ids, dates: dates.collect() }),
+            }
+        }
+    }
+
+    // Periods that are shorter than a week might not contain a date of every weekday
+    day_types.retain(|day_type| !day_type.dates.is_empty());
+    for day_type in &mut day_types {
+        day_type.dates.sort();
+        day_type.weekdays = WEEKDAYS.iter()
+            .map(|(weekday, _)| *weekday)
+            .filter(|weekday| day_type.dates.iter().any(|date| date.weekday() == *weekday))
+            .collect();
+    }
+
+    Ok(day_types)
+}
+
+fn from_days_since_epoch(days: i32) -> NaiveDate {
+    // The default date is 1970-01-01
+    NaiveDate::default() + TimeDelta::days(days as i64)
+}
+
+/// Only keeps the trips of the services of a day type, together with their stop times and runs
+pub(crate) fn filter_for_day_type(
+    day_type: &DayType,
+    PreprocessingInput {
+        stops, stop_times, trips, services, stations, trip_runs, transfers, original_ids
+    }: &PreprocessingInput,
+) -> PreprocessingInput {
+    let service_ids = DataFrame::new(vec![
+        Column::new("service_id".into(), &day_type.service_ids),
+    ]).expect("A single column is always a valid frame").lazy();
+
+    let trips = trips.clone()
+        .semi_join(service_ids.clone(), col("service_id"), col("service_id"));
+    let trip_ids = trips.clone().select([col("trip_id")]);
+
+    PreprocessingInput {
+        services: services.clone().semi_join(service_ids, col("service_id"), col("service_id")),
+        stops: stops.clone(),
+        stop_times: stop_times.clone().semi_join(trip_ids.clone(), col("trip_id"), col("trip_id")),
+        trip_runs: trip_runs.clone().semi_join(trip_ids, col("trip_id"), col("trip_id")),
+        trips,
+        stations: stations.clone(),
+        transfers: transfers.clone(),
+        original_ids: original_ids.clone(),
+    }
+}
+
+impl PreprocessingInput {
+    /// Only keeps the trips that run on the date. Times of trips are relative to the start of
+    /// their service day, so trips of the previous day that run past midnight are not included.
+    pub fn running_on(&self, date: NaiveDate) -> PreprocessingResult<PreprocessingInput> {
+        let day_type = day_types(self.services.clone())?
+            .into_iter()
+            .find(|day_type| day_type.dates.binary_search(&date).is_ok())
+            .unwrap_or(DayType { weekdays: vec![date.weekday()], service_ids: vec![], dates: vec![date] });
+
+        Ok(filter_for_day_type(&day_type, self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap()
+    }
+
+    // All dates from the first to the last day of january 2024 that are one of the weekdays
+    fn dates(first: u32, last: u32, weekdays: &[Weekday]) -> Vec<NaiveDate> {
+        (first..=last).map(date).filter(|date| weekdays.contains(&date.weekday())).collect()
+    }
+
+    #[test]
+    fn test_day_types() {
+        // Service 0 runs on weekdays, 1 every day and 2 only on saturdays. Nothing runs on sundays
+        // except for service 1. All of them run for two weeks, starting on monday.
+        let services = df!(
+            "service_id" => [2u32, 0, 1],
+            "monday"     => [false, true, true],
+            "tuesday"    => [false, true, true],
+            "wednesday"  => [false, true, true],
+            "thursday"   => [false, true, true],
+            "friday"     => [false, true, true],
+            "saturday"   => [true, false, true],
+            "sunday"     => [false, false, true],
+            "start_date" => [date(1); 3],
+            "end_date"   => [date(14); 3],
+        ).unwrap().lazy();
+
+        let workdays = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+        assert_eq!(day_types(services).unwrap(), [
+            DayType { weekdays: workdays.to_vec(), service_ids: vec![0, 1], dates: dates(1, 14, &workdays) },
+            DayType { weekdays: vec![Weekday::Sat], service_ids: vec![1, 2], dates: vec![date(6), date(13)] },
+            DayType { weekdays: vec![Weekday::Sun], service_ids: vec![1], dates: vec![date(7), date(14)] },
+        ]);
+    }
+
+    #[test]
+    fn test_day_types_of_periods() {
+        // Service 0 runs on workdays during school, which ends after the second week, service 1 on
+        // workdays during the holidays. Service 2 runs on workdays in both, but only from the
+        // 10th.
+        let services = df!(
+            "service_id" => [0u32, 1, 2],
+            "monday"     => [true; 3],
+            "tuesday"    => [true; 3],
+            "wednesday"  => [true; 3],
+            "thursday"   => [true; 3],
+            "friday"     => [true; 3],
+            "saturday"   => [false; 3],
+            "sunday"     => [false; 3],
+            "start_date" => [date(1), date(15), date(10)],
+            "end_date"   => [date(14), date(28), date(28)],
+        ).unwrap().lazy();
+
+        let workdays = [Weekday::Mon, Weekday::Tue, Weekday::Wed, Weekday::Thu, Weekday::Fri];
+        let day_types = day_types(services).unwrap();
+        let summary: Vec<(Vec<u32>, Vec<NaiveDate>)> = day_types.into_iter()
+            .map(|day_type| (day_type.service_ids, day_type.dates))
+            .collect();
+        assert_eq!(summary, [
+            (vec![0], dates(1, 9, &workdays)),
+            (vec![0, 2], dates(10, 14, &workdays)),
+            (vec![1, 2], dates(15, 28, &workdays)),
+        ]);
+    }
+
+    #[test]
+    fn test_filter_for_day_type() {
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let day_type = DayType { weekdays: vec![Weekday::Mon], service_ids: vec![], dates: vec![] };
+
+        let filtered = filter_for_day_type(&day_type, &input);
+        assert_eq!(filtered.trips.collect().unwrap().height(), 0);
+        assert_eq!(filtered.stop_times.collect().unwrap().height(), 0);
+        assert_eq!(filtered.stops.collect().unwrap().height(), 3);
+    }
+
+    #[test]
+    fn test_running_on() {
+        // The only service runs every day until 2070
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+
+        let running = input.running_on(date(1)).unwrap();
+        assert_eq!(running.trips.collect().unwrap().height(), 2);
+
+        let running = input.running_on(NaiveDate::from_ymd_opt(2080, 1, 1).unwrap()).unwrap();
+        assert_eq!(running.trips.collect().unwrap().height(), 0);
+    }
+}
@@ -1,91 +1,229 @@
-use crate::algorithm::{AllRange, PreprocessInit, PreprocessingInput, PreprocessingResult, Range};
+#[cfg(debug_assertions)]
+use crate::algorithm::RangeOutput;
+use crate::algorithm::{AllRange, PreprocessInit, PreprocessingInput, PreprocessingResult, Range, SaveToDisk};
 use crate::direct_connections::DirectConnections;
 use crate::raptor::RaptorAlgorithm;
+use crate::tp::day_types::{day_types, filter_for_day_type};
+#[cfg(debug_assertions)]
 use crate::tp::transfer_pattern_ds::graph::TransferPatternsGraphs;
-use crate::tp::transfer_pattern_ds::table::TransferPatternsTable;
+use crate::tp::transfer_pattern_ds::sharded::{Contention, ShardedTransferPatterns};
+use crate::tp::transfer_pattern_ds::table::{SpillingPatterns, TransferPatternsTable};
 use crate::tp::TransferPatternsAlgorithm;
+use crate::transfers;
+use crate::transfers::SharedTransferProvider;
 use async_trait::async_trait;
 use chrono::{DateTime, Duration};
-use common::util::logging::run_with_pb;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
-use std::sync::{Arc, Mutex};
+use common::types::StopId;
+use common::types::config::RoutingConfig;
+use common::util::logging::ProgressSink;
+use common::util::paths;
+use common::util::spill::Spill;
+use log::debug;
+use polars::prelude::col;
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::sync::Arc;
+#[cfg(debug_assertions)]
+use std::sync::mpsc;
+#[cfg(debug_assertions)]
+use std::thread;
+
+// Stop times of a service day can be after midnight (e.g. 25:30), so departures of two days are
+// considered
+const SERVICE_DAY_RANGE: Duration = Duration::days(2);
 
 #[async_trait]
 impl PreprocessInit for TransferPatternsAlgorithm {
-    fn preprocess(input: PreprocessingInput, save_to_disk: bool) -> PreprocessingResult<Self> {
+    fn preprocess(
+        input: PreprocessingInput,
+        config: &RoutingConfig,
+        save_to_disk: bool,
+        progress: &dyn ProgressSink,
+    ) -> PreprocessingResult<Self> {
+        let walking = transfers::walking(&input, &config.transfers)?.into();
+        let spill = Spill::in_work_dir(config.memory_budget);
+        let algorithm = Self::preprocess_with_provider(input, config, walking, &spill, progress)?;
         if save_to_disk {
-            unimplemented!()
+            algorithm.save_to_disk(&paths::preprocessing_dir(), config.compression)?;
         }
+        Ok(algorithm)
+    }
+}
 
+impl TransferPatternsAlgorithm {
+    /// Walks with a provider that was already built, e.g. for the stops of all clusters
+    pub(crate) fn preprocess_with_provider(
+        input: PreprocessingInput,
+        config: &RoutingConfig,
+        walking: SharedTransferProvider,
+        spill: &Spill,
+        progress: &dyn ProgressSink,
+    ) -> PreprocessingResult<Self> {
         let direct_connections = DirectConnections::try_from(input.clone())?;
-        let raptor = Arc::new(RaptorAlgorithm::preprocess(input.clone(), direct_connections.clone())?);
-
-        let tp_table = Arc::new(Mutex::new(TransferPatternsTable::new()));
-
-        // Also keep a graph representation when in debugging mode. This is useful for checking the
-        // validity of what we build.
-        #[allow(unused_variables)] // for the regular compiler, where this is not used at all
-        let tp_graph = Arc::new(Mutex::new(TransferPatternsGraphs::new(raptor.stop_mapping.0.clone())));
-
-        let total = raptor.num_stops() as u64;
-        run_with_pb("preprocessing", "Calculating local transfers in a single cluster", total, false, |pb| {
-            raptor.stop_mapping.0.par_iter()
-                .map(|stop| {
-                    Arc::clone(&raptor).query_range_all(Range {
-                        earliest_departure: DateTime::from_timestamp_millis(0).unwrap(),
-                        start: *stop,
-                        range: Duration::weeks(1),
-                    })
+        let (transfer_patterns, num_excluded_journeys) = transfer_patterns_from(
+            &input, config, &walking, spill, |_| true, "Calculating local transfers in a single cluster", progress,
+        )?;
+
+        Ok(Self {
+            direct_connections,
+            transfer_patterns,
+            transfer_provider: Box::new(walking),
+            num_excluded_journeys,
+        })
+    }
+}
+
+/// The transfer patterns of the optimal journeys from every stop for which `is_start` holds to all
+/// other stops on any day, together with the number of optimal journeys that were excluded since
+/// they have more than the allowed number of legs
+pub(crate) fn transfer_patterns_from(
+    input: &PreprocessingInput,
+    config: &RoutingConfig,
+    walking: &SharedTransferProvider,
+    // Where the patterns of the day types go once the memory budget is exceeded
+    spill: &Spill,
+    is_start: impl Fn(&StopId) -> bool + Sync,
+    task_message: &str,
+    progress: &dyn ProgressSink,
+) -> PreprocessingResult<(TransferPatternsTable, u64)> {
+    // Dates with the same services have the same transfer patterns, so they are only computed
+    // once for each distinct day type
+    let day_types = day_types(input.services.clone())?;
+    debug!(
+        target: "preprocessing",
+        "Calculating transfer patterns for {} distinct day types of {} dates",
+        day_types.len(), day_types.iter().map(|day_type| day_type.dates.len()).sum::<usize>(),
+    );
+
+    // Day types share most of their patterns, but over the memory budget, those computed so far
+    // are written to disk before the next day type
+    let mut tp_table = SpillingPatterns::new(spill.clone());
+    let mut num_excluded_journeys = 0;
+    let mut contention = Contention::default();
+
+    // Filtering by day type keeps all stops, so every day type has the same stops
+    let stops: Vec<StopId> = input.stops.clone()
+        .select([col("stop_id")]).collect()?
+        .column("stop_id")?.u32()?
+        .into_iter().flatten().map(StopId)
+        .collect();
+
+    // Also keep a graph representation when in debugging mode. This is useful for checking the
+    // validity of what we build. The graphs are built by a single writer that the range queries
+    // send their results to.
+    #[cfg(debug_assertions)]
+    let (graph_sender, graph_writer) = {
+        let (sender, receiver) = mpsc::channel::<RangeOutput>();
+        let mut tp_graph = TransferPatternsGraphs::new(stops.clone());
+        let writer = thread::spawn(move || {
+            receiver.into_iter().for_each(|range_out| tp_graph.add(range_out));
+            tp_graph
+        });
+        (sender, writer)
+    };
+
+    // Range queries from hubs take much longer than from other stops, so by default the starts are
+    // single tasks that idle threads can steal
+    let starts_per_task = config.starts_per_task.map_or(1, |starts| starts.get() as usize);
+    let num_starts = stops.iter().filter(|stop| is_start(stop)).count();
+    let task = progress.start(task_message, (num_starts * day_types.len()) as u64);
+    for day_type in &day_types {
+        let day_input = filter_for_day_type(day_type, input);
+        let day_connections = DirectConnections::try_from(day_input.clone())?;
+        let raptor = Arc::new(RaptorAlgorithm::preprocess_with_provider(day_input, day_connections, Box::new(Arc::clone(walking)))?);
+
+        // Every thread collects the patterns of its range queries in a table of its own, which is
+        // merged into the shards of the day type once the thread is done with its starts. Threads
+        // only wait for each other while inserting into the same shard.
+        let day_table = ShardedTransferPatterns::new(rayon::current_num_threads() * 4);
+        let starts = starts_by_workload(&raptor, &is_start);
+        let day_excluded: u64 = starts.par_iter()
+            .with_max_len(starts_per_task)
+            .with_min_len(starts_per_task)
+            .map(|stop| {
+                Arc::clone(&raptor).query_range_all(Range {
+                    earliest_departure: DateTime::from_timestamp_millis(0).unwrap(),
+                    start: *stop,
+                    range: SERVICE_DAY_RANGE,
+                    options: Default::default(),
                 })
-                .filter_map(|result| result.ok())
-                .map(|range_out| {
-                    // Also build the graph version in debug
-                    #[cfg(debug_assertions)] {
-                        let tp_graph = Arc::clone(&tp_graph);
-                        // Add this chunk to our existing transfer patterns graph
-                        let mut tp_graph = tp_graph.lock().unwrap();
-                        tp_graph.add(range_out.clone());
-                        drop(tp_graph);
+            })
+            .filter_map(|result| result.ok())
+            .fold(
+                || (TransferPatternsTable::new(), 0),
+                |(mut table, mut num_excluded), range_out| {
+                    #[cfg(debug_assertions)]
+                    graph_sender.send(range_out.clone()).expect("Writer of the transfer pattern graphs stopped");
+
+                    if let Ok(excluded) = table.add(range_out, config.max_legs) {
+                        num_excluded += excluded;
                     }
+                    task.inc(1);
+                    (table, num_excluded)
+                },
+            )
+            .map(|(table, num_excluded)| {
+                day_table.merge(table);
+                num_excluded
+            })
+            .sum();
 
-                    // Add the collected results to the table of transfer patterns
-                    let tp_table = Arc::clone(&tp_table);
-                    let mut tp_table = tp_table.lock().unwrap();
-                    let res = tp_table.add(range_out);
-                    drop(tp_table);
+        let day_contention = day_table.contention();
+        contention.acquisitions += day_contention.acquisitions;
+        contention.contended += day_contention.contended;
+        tp_table.add(day_table.into_table())?;
+        num_excluded_journeys += day_excluded;
+    }
+    drop(task);
 
-                    res
-                })
-                .for_each(|_| {
-                    pb.inc(1);
-                });
-        });
+    #[cfg(debug_assertions)] {
+        drop(graph_sender);
+        let tp_graph = graph_writer.join().expect("Writer of the transfer pattern graphs panicked");
+        // Check that graphs are acyclic. Expensive to compute, so only do that in debug.
+        tp_graph.validate();
+    }
 
+    debug!(
+        target: "preprocessing",
+        "Threads waited for a shard of the transfer patterns in {} of {} merges ({:.1}%)",
+        contention.contended, contention.acquisitions, contention.rate() * 100.0,
+    );
 
-        #[cfg(debug_assertions)] {
-            let tp_graph = Arc::try_unwrap(tp_graph)
-                .expect("Lock is still owned by others").into_inner().unwrap();
-            // Check that graphs are acyclic. Expensive to compute, so only do that in debug.
-            tp_graph.validate();
-        }
+    debug!(
+        target: "preprocessing",
+        "Excluded {} optimal journeys with more than {} legs from transfer patterns",
+        num_excluded_journeys, config.max_legs,
+    );
 
-        let tp_table = Arc::try_unwrap(tp_table)
-            .expect("Lock is still owned by others").into_inner().unwrap();
+    Ok((tp_table.into_table()?, num_excluded_journeys))
+}
 
-        Ok(Self {
-            direct_connections,
-            transfer_patterns: tp_table,
+// The start stops, with those that are expected to take the longest first, so that the short ones
+// fill the gaps at the end. The number of trips departing at a stop is a rough estimate of how
+// long its range query takes.
+fn starts_by_workload(raptor: &RaptorAlgorithm, is_start: impl Fn(&StopId) -> bool) -> Vec<StopId> {
+    let timetable = &raptor.timetable;
+    raptor.stop_mapping.0.sources().iter().enumerate()
+        .filter(|(_, stop)| is_start(stop))
+        .map(|(local, stop)| {
+            let num_trips: usize = timetable.lines_at(StopId(local as u32)).iter()
+                .map(|(line, _)| timetable.trips(*line).len())
+                .sum();
+            (num_trips, *stop)
         })
-    }
+        .sorted_by_key(|(num_trips, stop)| (Reverse(*num_trips), *stop))
+        .map(|(_, stop)| stop)
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
-    use log::LevelFilter;
     use super::*;
     use crate::tests::*;
+    use crate::tp::transfer_pattern_ds::table::TransferPatternsTable;
     use common::types::StopId;
-    use common::util::logging;
+    use common::util::logging::NoProgress;
 
     #[test]
     fn test_case1() {
@@ -116,23 +254,32 @@ mod tests {
             TransferPatternsTable(hashbrown::HashSet::from([
                 (StopId(0), vec![], StopId(1)),
                 (StopId(1), vec![], StopId(2)),
+                (StopId(2), vec![], StopId(1)),
                 (StopId(2), vec![], StopId(3)),
                 (StopId(0), vec![StopId(1)], StopId(2)),
                 (StopId(1), vec![StopId(2)], StopId(3)),
-                (StopId(0), vec![StopId(1), StopId(2)], StopId(3)),
+                // There's no (0, [1, 2], 3): trip 0 arrives at 1 after 500s and the walk of about
+                // 700s to 2 misses trip 1, which departs there after 1000s
             ]))
         );
     }
 
+    #[test]
+    fn test_starts_per_task() {
+        let input = case_3::generate_preprocessing_input().unwrap();
+        let config = RoutingConfig { starts_per_task: std::num::NonZeroU32::new(2), ..Default::default() };
+
+        let chunked = TransferPatternsAlgorithm::preprocess(input.clone(), &config, false, &NoProgress).unwrap();
+        let single = TransferPatternsAlgorithm::preprocess(input, &RoutingConfig::default(), false, &NoProgress).unwrap();
+        assert_eq!(chunked.transfer_patterns, single.transfer_patterns);
+    }
+
     /// Helper for executing the test on a specific problem instance
     fn test_single_case(
         input: PreprocessingInput,
         expected_patterns: TransferPatternsTable,
     ) {
-        // We need to initialize logging, because the preprocessing function uses a progress bar
-        logging::init(LevelFilter::Debug);
-
-        let actual_patterns = TransferPatternsAlgorithm::preprocess(input.clone(), false)
+        let actual_patterns = TransferPatternsAlgorithm::preprocess(input.clone(), &RoutingConfig::default(), false, &NoProgress)
             .unwrap().transfer_patterns;
 
         assert_eq!(expected_patterns, actual_patterns);
@@ -1,15 +1,23 @@
 use crate::algorithm::RoutingAlgorithm;
 use crate::direct_connections::DirectConnections;
 use crate::tp::transfer_pattern_ds::table::TransferPatternsTable;
+use crate::transfers::TransferProvider;
 
 /// https://ad.informatik.uni-freiburg.de/files/transferpatterns.pdf
 
-mod init;
+mod artifacts;
+pub(crate) mod day_types;
+pub(crate) mod init;
+pub(crate) mod query;
 pub(crate) mod transfer_pattern_ds;
 
-pub(crate) struct TransferPatternsAlgorithm {
-    pub direct_connections: DirectConnections,
-    pub transfer_patterns: TransferPatternsTable,
+pub struct TransferPatternsAlgorithm {
+    pub(crate) direct_connections: DirectConnections,
+    pub(crate) transfer_patterns: TransferPatternsTable,
+    // Walks between the stops of a transfer pattern that aren't connected by a ride
+    pub(crate) transfer_provider: Box<dyn TransferProvider + Send + Sync>,
+    // Number of optimal journeys that weren't stored, since they have more legs than allowed
+    pub(crate) num_excluded_journeys: u64,
 }
 
 impl RoutingAlgorithm for TransferPatternsAlgorithm {}
\ No newline at end of file
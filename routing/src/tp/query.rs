@@ -0,0 +1,251 @@
+use crate::algorithm::{EarliestArrival, EarliestArrivalOutput, QueryError, QueryResult, Single, SingleEarliestArrival};
+use crate::direct_connections::DirectConnections;
+use crate::journey::{Journey, Leg};
+use crate::tp::transfer_pattern_ds::query_graph::QueryGraph;
+use crate::tp::TransferPatternsAlgorithm;
+use crate::transfers::TransferProvider;
+use chrono::{DateTime, Utc};
+use common::types::StopId;
+use hashbrown::HashMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+impl SingleEarliestArrival for TransferPatternsAlgorithm {
+    /// Builds the query graph of the transfer patterns between the stops and searches it for the
+    /// earliest arrival
+    fn query_ea(&self, input: EarliestArrival, Single { target }: Single) -> QueryResult<EarliestArrivalOutput> {
+        let graph = QueryGraph::from_patterns(&self.transfer_patterns, input.start, target);
+        let journey = earliest_arrival(&graph, &self.direct_connections, self.transfer_provider.as_ref(), input, target)?;
+        Ok(EarliestArrivalOutput { journey })
+    }
+}
+
+/// Time-dependent Dijkstra on the query graph, taking the earliest ride or a walk along every
+/// edge. Rides are looked up in `direct_connections`, so the graph may combine the patterns of
+/// several tables as long as they refer to the same stops. Walks are cycled if the input takes a
+/// bike along, but only along the edges of the patterns, which were computed for walking. If the
+/// input limits the transfers, stops are labelled by their arrival and the number of rides that
+/// reached them, so that a journey with fewer rides is still found when the fastest one has too
+/// many.
+pub(crate) fn earliest_arrival(
+    graph: &QueryGraph,
+    direct_connections: &DirectConnections,
+    transfer_provider: &(dyn TransferProvider + Send + Sync),
+    input: EarliestArrival,
+    target: StopId,
+) -> QueryResult<Journey> {
+    let cycling = input.transfers(transfer_provider);
+    let transfer_provider = cycling.as_deref().unwrap_or(transfer_provider);
+    let EarliestArrival { start, earliest_departure, ref closed_stops, max_transfers, .. } = input;
+    if graph.is_empty() {
+        return Err(QueryError::NoRouteFound);
+    }
+    // Without a limit, the rides aren't counted, so that every stop has a single label
+    let max_rides = max_transfers.map(|max_transfers| max_transfers + 1);
+    let rides_after = |rides: usize, leg: &Leg| match (max_rides, leg) {
+        (Some(_), Leg::Ride { .. }) => rides + 1,
+        _ => rides,
+    };
+
+    // Earliest arrival at every settled or queued stop by a number of rides, with the leg that
+    // reached it
+    let mut arrivals: HashMap<Label, (DateTime<Utc>, Option<Leg>)> = HashMap::from([((start, 0), (earliest_departure, None))]);
+    let mut queue = BinaryHeap::from([Reverse((earliest_departure, start, 0))]);
+    // Whether an arrival at the stop is no earlier than one with at most as many rides
+    let dominated = |arrivals: &HashMap<Label, (DateTime<Utc>, Option<Leg>)>, (stop, rides): Label, arrival: DateTime<Utc>| {
+        (0..=rides).any(|fewer| arrivals.get(&(stop, fewer)).is_some_and(|(best, _)| *best <= arrival))
+    };
+
+    while let Some(Reverse((time, stop, rides))) = queue.pop() {
+        if stop == target {
+            return Ok(backtrace(&arrivals, start, (target, rides), rides_after));
+        }
+        // Stops are queued again when they are reached earlier
+        if arrivals.get(&(stop, rides)).is_some_and(|(arrival, _)| *arrival < time) {
+            continue;
+        }
+
+        // Closed stops are never reached, so this only keeps rides from leaving a closed start
+        if closed_stops.contains(&stop) {
+            continue;
+        }
+        for next in graph.successors(stop) {
+            if closed_stops.contains(next) {
+                continue;
+            }
+            for (arrival, leg) in legs(direct_connections, transfer_provider, stop, *next, time, &input)? {
+                let next_rides = rides_after(rides, &leg);
+                if max_rides.is_some_and(|max_rides| next_rides > max_rides) || dominated(&arrivals, (*next, next_rides), arrival) {
+                    continue;
+                }
+                arrivals.insert((*next, next_rides), (arrival, Some(leg)));
+                queue.push(Reverse((arrival, *next, next_rides)));
+            }
+        }
+    }
+
+    Err(QueryError::NoRouteFound)
+}
+
+// A stop and the number of rides that reached it, which is always 0 if the rides aren't limited
+type Label = (StopId, usize);
+
+// The earliest ride of a trip that the search may board at `time` and the walk, either of which
+// may be missing. The walk is kept even if it is slower, since it doesn't add a ride.
+fn legs(
+    direct_connections: &DirectConnections,
+    transfer_provider: &(dyn TransferProvider + Send + Sync),
+    from: StopId,
+    to: StopId,
+    time: DateTime<Utc>,
+    input: &EarliestArrival,
+) -> QueryResult<impl Iterator<Item = (DateTime<Utc>, Leg)>> {
+    // Enough rides that one is left if all suspended trips are among them. Any number of rides may
+    // leave too little time for the transfer slack of their mode.
+    let limit = match input.has_transfer_slack() {
+        true => usize::MAX,
+        false => input.suspended_trips.len() + 1,
+    };
+    let ride = direct_connections.rides(from, to, input.ready_to_board(from, time), limit)?.into_iter()
+        .find_map(|ride| match ride {
+            Leg::Ride { trip, boarding_time, alight_time, .. } if input.can_board(trip, from, time, boarding_time) => Some((alight_time, ride)),
+            _ => None,
+        });
+    let walk = transfer_provider.duration(from, to).ok()
+        .map(|duration| (time + duration, Leg::Transfer { start: from, end: to, duration }));
+
+    Ok([ride, walk].into_iter().flatten())
+}
+
+// Follows the legs that reached the labels back from the target
+fn backtrace(
+    arrivals: &HashMap<Label, (DateTime<Utc>, Option<Leg>)>,
+    start: StopId,
+    target: Label,
+    rides_after: impl Fn(usize, &Leg) -> usize,
+) -> Journey {
+    let mut legs = vec![];
+    let (mut stop, mut rides) = target;
+    while stop != start {
+        let Some((_, Some(leg))) = arrivals.get(&(stop, rides)) else { unreachable!("Every reached stop but the start has a leg") };
+        stop = *leg.start();
+        // The leg added a ride if it was counted
+        rides -= rides_after(0, leg);
+        legs.push(leg.clone());
+    }
+    legs.reverse();
+    Journey::from(legs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::algorithm::{EarliestArrival, JourneyPlanner, PreprocessingInput, QueryOptions};
+    use crate::direct_connections::DirectConnections;
+    use crate::tests::duration;
+    use crate::tp::transfer_pattern_ds::table::TransferPatternsTable;
+    use crate::tp::TransferPatternsAlgorithm;
+    use crate::transfers::crow_fly::CrowFlyTransferProvider;
+    use chrono::{DateTime, Utc};
+    use common::types::{StopId, TripId};
+    use polars::df;
+    use polars::prelude::IntoLazy;
+
+    fn algorithm(input: PreprocessingInput, patterns: &[(u32, &[u32], u32)]) -> TransferPatternsAlgorithm {
+        let patterns = patterns.iter()
+            .map(|(start, intermediates, target)| (StopId(*start), intermediates.iter().copied().map(StopId).collect(), StopId(*target)))
+            .collect();
+        TransferPatternsAlgorithm {
+            direct_connections: DirectConnections::try_from(input.clone()).unwrap(),
+            transfer_patterns: TransferPatternsTable(patterns),
+            transfer_provider: Box::new(CrowFlyTransferProvider::from_stops(input.stops).unwrap()),
+            num_excluded_journeys: 0,
+        }
+    }
+
+    #[test]
+    fn test_query_ea() {
+        // Trip 0 runs from stop 0 to 1 at 100s to 500s, trip 1 from stop 1 to 2 at 1000s to 1500s
+        let input = crate::tests::case_2::generate_preprocessing_input().unwrap();
+        let algorithm = algorithm(input, &[(0, &[], 1), (1, &[], 2), (0, &[1], 2)]);
+
+        let journey = algorithm.query_ea(StopId(0), StopId(2), DateTime::<Utc>::UNIX_EPOCH).unwrap();
+        assert_eq!(journey.legs().count(), 2);
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // The only trip from stop 0 has left
+        assert!(algorithm.query_ea(StopId(0), StopId(2), DateTime::<Utc>::from_timestamp(200, 0).unwrap()).is_err());
+
+        // Stop 2 can't be reached while trip 1 is suspended
+        let suspended = hashbrown::HashSet::from([TripId(1)]);
+        assert!(algorithm.query_ea_suspending(StopId(0), StopId(2), DateTime::<Utc>::UNIX_EPOCH, suspended).is_err());
+    }
+
+    #[test]
+    fn test_query_graph() {
+        // Trip 0 runs directly from stop 0 to 2 and arrives at 1000s. Changing from trip 1 to trip 2
+        // at stop 1 arrives later, at 1500s.
+        let input = PreprocessingInput {
+            trips: df!["trip_id" => [0u32, 1, 2], "service_id" => [0u32, 0, 0]].unwrap().lazy(),
+            stop_times: df![
+                "trip_id" => [0u32, 0, 1, 1, 2, 2],
+                "stop_id" => [0u32, 2, 0, 1, 1, 2],
+                "arrival_time" => [100, 1_000, 100, 500, 600, 1_500].map(duration),
+                "departure_time" => [100, 1_000, 100, 500, 600, 1_500].map(duration),
+                "stop_sequence" => [0u32, 1, 0, 1, 0, 1],
+            ].unwrap().lazy(),
+            trip_runs: df![
+                "trip_id" => [0u32, 1, 2],
+                "template_trip_id" => [0u32, 1, 2],
+                "run_offset" => [0; 3].map(duration),
+            ].unwrap().lazy(),
+            ..crate::tests::case_2::generate_preprocessing_input().unwrap()
+        };
+        let algorithm = algorithm(input, &[(0, &[], 2), (0, &[1], 2)]);
+
+        let journey = algorithm.query_ea(StopId(0), StopId(2), DateTime::<Utc>::UNIX_EPOCH).unwrap();
+        assert_eq!(journey.legs().count(), 1);
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_000, 0));
+
+        // Without the direct trip, the other pattern is the only one left
+        let suspended = hashbrown::HashSet::from([TripId(0)]);
+        let journey = algorithm.query_ea_suspending(StopId(0), StopId(2), DateTime::<Utc>::UNIX_EPOCH, suspended).unwrap();
+        assert_eq!(journey.legs().count(), 2);
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // There is no pattern in the other direction
+        assert!(algorithm.query_ea(StopId(2), StopId(0), DateTime::<Utc>::UNIX_EPOCH).is_err());
+    }
+
+    #[test]
+    fn test_max_transfers() {
+        // Changing from trip 1 to trip 2 at stop 1 arrives at 1500s, before trip 0 runs directly
+        // from stop 0 to 2 and arrives at 2000s
+        let input = PreprocessingInput {
+            trips: df!["trip_id" => [0u32, 1, 2], "service_id" => [0u32, 0, 0]].unwrap().lazy(),
+            stop_times: df![
+                "trip_id" => [0u32, 0, 1, 1, 2, 2],
+                "stop_id" => [0u32, 2, 0, 1, 1, 2],
+                "arrival_time" => [100, 2_000, 100, 500, 600, 1_500].map(duration),
+                "departure_time" => [100, 2_000, 100, 500, 600, 1_500].map(duration),
+                "stop_sequence" => [0u32, 1, 0, 1, 0, 1],
+            ].unwrap().lazy(),
+            trip_runs: df![
+                "trip_id" => [0u32, 1, 2],
+                "template_trip_id" => [0u32, 1, 2],
+                "run_offset" => [0; 3].map(duration),
+            ].unwrap().lazy(),
+            ..crate::tests::case_2::generate_preprocessing_input().unwrap()
+        };
+        let algorithm = algorithm(input, &[(0, &[], 2), (0, &[1], 2)]);
+        let query = |max_transfers| EarliestArrival::new(StopId(0), DateTime::<Utc>::UNIX_EPOCH)
+            .with_options(&QueryOptions { max_transfers, ..Default::default() });
+
+        let journey = algorithm.query_ea_with(query(None), StopId(2)).unwrap();
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(1_500, 0));
+
+        // Without transfers, the direct trip is found instead of rejecting the faster journey
+        let journey = algorithm.query_ea_with(query(Some(0)), StopId(2)).unwrap();
+        assert_eq!(journey.legs().count(), 1);
+        assert_eq!(journey.arrival(), DateTime::from_timestamp(2_000, 0));
+    }
+}
@@ -1,2 +1,4 @@
 pub mod graph;
-pub(crate) mod table;
\ No newline at end of file
+pub(crate) mod table;
+pub(crate) mod sharded;
+pub(crate) mod query_graph;
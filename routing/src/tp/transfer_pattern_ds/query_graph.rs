@@ -0,0 +1,75 @@
+use crate::tp::transfer_pattern_ds::table::TransferPatternsTable;
+use common::types::StopId;
+use hashbrown::HashMap;
+use itertools::Itertools;
+use std::iter::once;
+
+/// The transfer patterns between two stops merged into a single graph (section 3.3 of
+/// https://ad.informatik.uni-freiburg.de/files/transferpatterns.pdf). Nodes are stops, an edge
+/// means that some pattern goes directly from one stop to the other, by a ride or a walk. Patterns
+/// that share a part of their stops share the edges of that part, so every direct connection is
+/// only evaluated once per query.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct QueryGraph {
+    // Successors of every stop, in the order they were first seen
+    edges: HashMap<StopId, Vec<StopId>>,
+}
+
+impl QueryGraph {
+    pub(crate) fn from_patterns(table: &TransferPatternsTable, start: StopId, target: StopId) -> Self {
+        let mut graph = Self::default();
+        let patterns = table.0.iter()
+            .filter(|(from, _, to)| *from == start && *to == target)
+            // The table has no order, but queries should be reproducible
+            .sorted_by(|(_, a, _), (_, b, _)| a.cmp(b));
+
+        for (_, intermediates, _) in patterns {
+            graph.add_pattern(start, intermediates, target);
+        }
+        graph
+    }
+
+    /// Adds the edges between the consecutive stops of a pattern
+    pub(crate) fn add_pattern(&mut self, start: StopId, intermediates: &[StopId], target: StopId) {
+        for (from, to) in once(&start).chain(intermediates).chain(once(&target)).tuple_windows() {
+            let successors = self.edges.entry(*from).or_default();
+            if !successors.contains(to) {
+                successors.push(*to);
+            }
+        }
+    }
+
+    pub(crate) fn successors(&self, stop: StopId) -> &[StopId] {
+        self.edges.get(&stop).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.edges.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashbrown::HashSet;
+
+    #[test]
+    fn test_from_patterns() {
+        let table = TransferPatternsTable(HashSet::from([
+            (StopId(0), vec![], StopId(3)),
+            (StopId(0), vec![StopId(1)], StopId(3)),
+            (StopId(0), vec![StopId(1), StopId(2)], StopId(3)),
+            // Patterns to other targets are not part of the graph
+            (StopId(0), vec![], StopId(2)),
+        ]));
+
+        let graph = QueryGraph::from_patterns(&table, StopId(0), StopId(3));
+        // The edge from stop 0 to 1 is shared by two patterns
+        assert_eq!(graph.successors(StopId(0)), [StopId(3), StopId(1)]);
+        assert_eq!(graph.successors(StopId(1)), [StopId(3), StopId(2)]);
+        assert_eq!(graph.successors(StopId(2)), [StopId(3)]);
+        assert!(graph.successors(StopId(3)).is_empty());
+
+        assert!(QueryGraph::from_patterns(&table, StopId(3), StopId(0)).is_empty());
+    }
+}
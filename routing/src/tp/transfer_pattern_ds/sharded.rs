@@ -0,0 +1,116 @@
+use crate::tp::transfer_pattern_ds::table::TransferPatternsTable;
+use common::types::StopId;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, TryLockError};
+
+/// How often writers had to wait for the lock of a shard
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Contention {
+    pub(crate) acquisitions: u64,
+    // Acquisitions of a lock that was held by another writer
+    pub(crate) contended: u64,
+}
+
+impl Contention {
+    pub(crate) fn rate(&self) -> f64 {
+        if self.acquisitions == 0 {
+            return 0.0;
+        }
+        self.contended as f64 / self.acquisitions as f64
+    }
+}
+
+/// Transfer patterns split into shards by the hash of their start stop, so that threads that merge
+/// the patterns of different start stops rarely wait for each other
+#[derive(Debug)]
+pub(crate) struct ShardedTransferPatterns {
+    shards: Vec<Mutex<TransferPatternsTable>>,
+    hasher: RandomState,
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+}
+
+impl ShardedTransferPatterns {
+    pub(crate) fn new(num_shards: usize) -> Self {
+        Self {
+            shards: (0..num_shards.max(1)).map(|_| Mutex::new(TransferPatternsTable::new())).collect(),
+            hasher: RandomState::new(),
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+        }
+    }
+
+    /// Like [TransferPatternsTable::merge], for the table that a thread collected. The patterns
+    /// of a range query all start at the same stop, so a table of few range queries only locks a
+    /// few shards, and only for inserting its patterns.
+    pub(crate) fn merge(&self, table: TransferPatternsTable) {
+        let mut by_shard: Vec<TransferPatternsTable> = (0..self.shards.len()).map(|_| TransferPatternsTable::new()).collect();
+        for pattern in table.0 {
+            by_shard[self.shard_of(&pattern.0)].0.insert(pattern);
+        }
+
+        for (shard, patterns) in by_shard.into_iter().enumerate().filter(|(_, patterns)| !patterns.0.is_empty()) {
+            self.lock(shard).merge(patterns);
+        }
+    }
+
+    pub(crate) fn contention(&self) -> Contention {
+        Contention {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Merges the shards once all writers are done
+    pub(crate) fn into_table(self) -> TransferPatternsTable {
+        let patterns = self.shards.into_iter()
+            .flat_map(|shard| shard.into_inner().unwrap_or_else(|err| err.into_inner()).0)
+            .collect();
+        TransferPatternsTable(patterns)
+    }
+
+    fn shard_of(&self, stop: &StopId) -> usize {
+        (self.hasher.hash_one(stop) % self.shards.len() as u64) as usize
+    }
+
+    fn lock(&self, shard: usize) -> std::sync::MutexGuard<'_, TransferPatternsTable> {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        match self.shards[shard].try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                self.contended.fetch_add(1, Ordering::Relaxed);
+                self.shards[shard].lock().unwrap_or_else(|err| err.into_inner())
+            }
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hashbrown::HashSet;
+    use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+    #[test]
+    fn test_parallel_merge() {
+        let patterns = ShardedTransferPatterns::new(4);
+
+        // Every start stop gets a direct pattern and one over the next stop
+        (0..100u32).into_par_iter().for_each(|start| {
+            patterns.merge(TransferPatternsTable(HashSet::from([
+                (StopId(start), vec![], StopId(start + 2)),
+                (StopId(start), vec![StopId(start + 1)], StopId(start + 2)),
+            ])));
+        });
+
+        let contention = patterns.contention();
+        assert_eq!(contention.acquisitions, 100);
+        assert!(contention.contended <= contention.acquisitions);
+
+        let table = patterns.into_table();
+        assert_eq!(table.0.len(), 200);
+        assert!(table.0.contains(&(StopId(7), vec![StopId(8)], StopId(9))));
+    }
+}
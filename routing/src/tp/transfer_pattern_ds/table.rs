@@ -1,7 +1,12 @@
 use crate::algorithm::{PreprocessingResult, RangeOutput};
-use crate::journey::Journey;
+use crate::journey::{Journey, Leg};
 use common::types::StopId;
-use hashbrown::HashSet;
+use common::util::df::{write_df_to_file, FileType};
+use common::util::spill::Spill;
+use hashbrown::{HashMap, HashSet};
+use itertools::izip;
+use polars::prelude::*;
+use std::path::PathBuf;
 
 /// columns:
 /// - "start" (stop id)
@@ -61,11 +66,20 @@ impl TransferPatternsTable {
         Self(HashSet::new())
     }
 
-    pub(crate) fn add(&mut self, result: RangeOutput) -> PreprocessingResult<()> {
+    /// Adds the transfer patterns of all journeys with at most `max_legs` rides and returns the
+    /// number of excluded journeys
+    pub(crate) fn add(&mut self, result: RangeOutput, max_legs: u32) -> PreprocessingResult<u64> {
+        let mut num_excluded = 0;
         for journey in result.journeys {
+            let num_rides = journey.legs().filter(|leg| matches!(leg, Leg::Ride { .. })).count();
+            if num_rides > max_legs as usize {
+                num_excluded += 1;
+                continue;
+            }
+
             self.add_journey(journey)?;
         }
-        Ok(())
+        Ok(num_excluded)
     }
 
     pub(crate) fn add_journey(&mut self, journey: Journey) -> PreprocessingResult<()> {
@@ -81,4 +95,196 @@ impl TransferPatternsTable {
         
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Adds the patterns of another table, e.g. the one of another thread
+    pub(crate) fn merge(&mut self, mut other: Self) {
+        // Inserting the patterns of the smaller table into the larger one rehashes fewer of them
+        if self.0.len() < other.0.len() {
+            std::mem::swap(self, &mut other);
+        }
+        self.0.extend(other.0);
+    }
+
+    /// The intermediates and target of every pattern by its start, so that the patterns of a
+    /// query don't need a scan of the whole table
+    pub(crate) fn by_start(self) -> PatternsByStart {
+        let mut by_start = PatternsByStart::new();
+        for (start, intermediates, target) in self.0 {
+            by_start.entry(start).or_default().push((intermediates, target));
+        }
+        // Patterns are added to query graphs in this order, which keeps queries reproducible
+        by_start.values_mut().for_each(|patterns| patterns.sort());
+        by_start
+    }
+
+    /// The patterns written by [patterns_to_frame]
+    pub(crate) fn from_frame(frame: &DataFrame) -> PolarsResult<Self> {
+        // Tables without patterns were written with lists of an unknown type before
+        if frame.height() == 0 {
+            return Ok(Self::new());
+        }
+        let starts = frame.column("start")?.u32()?;
+        let intermediates = frame.column("intermediates")?.list()?;
+        let targets = frame.column("target")?.u32()?;
+
+        let mut patterns = HashSet::new();
+        for (start, stops, target) in izip!(starts, intermediates, targets) {
+            let (Some(start), Some(stops), Some(target)) = (start, stops, target) else { continue };
+            let stops = stops.u32()?.into_no_null_iter().map(StopId).collect();
+            patterns.insert((StopId(start), stops, StopId(target)));
+        }
+        Ok(Self(patterns))
+    }
+}
+
+// Rows of a spilled file that are read at once when the patterns are merged again
+const SPILLED_ROWS_PER_BATCH: u32 = 100_000;
+
+/// Patterns that are collected from many parts, e.g. from all day types or clusters. Whenever the
+/// memory budget is exceeded, the patterns collected so far are written to a temporary file, which
+/// only holds them once instead of with the overhead of the hash set.
+pub(crate) struct SpillingPatterns {
+    table: TransferPatternsTable,
+    files: Vec<PathBuf>,
+    spill: Spill,
+}
+
+impl SpillingPatterns {
+    pub(crate) fn new(spill: Spill) -> Self {
+        Self { table: TransferPatternsTable::new(), files: vec![], spill }
+    }
+
+    pub(crate) fn add(&mut self, table: TransferPatternsTable) -> PolarsResult<()> {
+        self.table.merge(table);
+        if self.spill.exceeded() && !self.table.0.is_empty() {
+            let path = self.spill.file("transfer_patterns");
+            let frame = patterns_to_frame(self.table.0.iter().map(|(start, stops, target)| (start, stops.as_slice(), target)))?;
+            self.table = TransferPatternsTable::new();
+            write_df_to_file(path.clone(), FileType::PARQUET, frame)?;
+            self.files.push(path);
+        }
+        Ok(())
+    }
+
+    /// All patterns, with those of the temporary files read again. Patterns of different parts
+    /// can be the same, so they are only deduplicated here. The files are read in batches that
+    /// are merged one after the other, so that only the merged patterns are held as a whole.
+    pub(crate) fn into_table(self) -> PolarsResult<TransferPatternsTable> {
+        let mut table = self.table;
+        for path in self.files {
+            let patterns = LazyFrame::scan_parquet(&path, Default::default())?;
+            let num_rows = patterns.clone().select([len()]).collect()?
+                .column("len")?.idx()?.get(0).unwrap_or(0);
+            for offset in (0..num_rows).step_by(SPILLED_ROWS_PER_BATCH as usize) {
+                let batch = patterns.clone().slice(offset as i64, SPILLED_ROWS_PER_BATCH).collect()?;
+                table.0.extend(TransferPatternsTable::from_frame(&batch)?.0);
+            }
+            // Files that are left behind are removed with the others after preprocessing
+            let _ = std::fs::remove_file(path);
+        }
+        Ok(table)
+    }
+}
+
+pub(crate) type PatternsByStart = HashMap<StopId, Vec<(Vec<StopId>, StopId)>>;
+
+/// The patterns with the columns "start", "intermediates" (list of stop ids) and "target"
+pub(crate) fn patterns_to_frame<'a>(patterns: impl IntoIterator<Item = (&'a StopId, &'a [StopId], &'a StopId)>) -> PolarsResult<DataFrame> {
+    let (mut starts, mut intermediates, mut targets) = (vec![], vec![], vec![]);
+    for (start, stops, target) in patterns {
+        starts.push(start.0);
+        intermediates.push(Series::new(PlSmallStr::EMPTY, stops.iter().map(|stop| stop.0).collect::<Vec<_>>()));
+        targets.push(target.0);
+    }
+    let mut frame = df!("start" => starts, "intermediates" => intermediates, "target" => targets)?;
+    // Without any pattern, the type of the lists can't be inferred
+    frame.with_column(frame.column("intermediates")?.cast(&DataType::List(Box::new(DataType::UInt32)))?)?;
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, TimeDelta, Utc};
+    use common::types::TripId;
+
+    fn ride(trip: u32, boarding_stop: u32, alight_stop: u32) -> Leg {
+        let boarding_time = DateTime::<Utc>::UNIX_EPOCH + TimeDelta::minutes(10 * trip as i64);
+        Leg::Ride {
+            trip: TripId(trip),
+            boarding_stop: StopId(boarding_stop),
+            alight_stop: StopId(alight_stop),
+            boarding_time,
+            alight_time: boarding_time + TimeDelta::minutes(5),
+        }
+    }
+
+    #[test]
+    fn test_max_legs() {
+        let result = RangeOutput {
+            journeys: HashSet::from([
+                Journey::from(vec![ride(0, 0, 1), ride(1, 1, 2)]),
+                // Needs three rides
+                Journey::from(vec![ride(0, 0, 1), ride(1, 1, 2), ride(2, 2, 3)]),
+            ]),
+        };
+
+        let mut table = TransferPatternsTable::new();
+        let num_excluded = table.add(result, 2).unwrap();
+
+        assert_eq!(num_excluded, 1);
+        assert_eq!(table, TransferPatternsTable(HashSet::from([(StopId(0), vec![StopId(1)], StopId(2))])));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut table = TransferPatternsTable(HashSet::from([(StopId(0), vec![], StopId(1))]));
+        table.merge(TransferPatternsTable(HashSet::from([
+            (StopId(0), vec![], StopId(1)),
+            (StopId(0), vec![StopId(1)], StopId(2)),
+        ])));
+
+        assert_eq!(table, TransferPatternsTable(HashSet::from([
+            (StopId(0), vec![], StopId(1)),
+            (StopId(0), vec![StopId(1)], StopId(2)),
+        ])));
+    }
+
+    #[test]
+    fn test_frame() {
+        let table = TransferPatternsTable(HashSet::from([
+            (StopId(0), vec![], StopId(2)),
+            (StopId(0), vec![StopId(1)], StopId(2)),
+            (StopId(3), vec![StopId(1), StopId(0)], StopId(2)),
+        ]));
+        let frame = patterns_to_frame(table.0.iter().map(|(start, stops, target)| (start, stops.as_slice(), target))).unwrap();
+        assert_eq!(TransferPatternsTable::from_frame(&frame).unwrap(), table);
+
+        let frame = patterns_to_frame([]).unwrap();
+        assert_eq!(frame.column("intermediates").unwrap().dtype(), &DataType::List(Box::new(DataType::UInt32)));
+        assert_eq!(TransferPatternsTable::from_frame(&frame).unwrap(), TransferPatternsTable::new());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_spilling() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Every part is written to a file of its own when any memory is too much
+        let spill = Spill::new(Some(common::util::size::ByteSize(0)), dir.path().to_path_buf());
+        let mut patterns = SpillingPatterns::new(spill);
+        patterns.add(TransferPatternsTable(HashSet::from([(StopId(0), vec![], StopId(1))]))).unwrap();
+        patterns.add(TransferPatternsTable(HashSet::from([
+            (StopId(0), vec![], StopId(1)),
+            (StopId(0), vec![StopId(1)], StopId(2)),
+        ]))).unwrap();
+        assert_eq!(patterns.files.len(), 2);
+
+        let files = patterns.files.clone();
+        assert_eq!(patterns.into_table().unwrap(), TransferPatternsTable(HashSet::from([
+            (StopId(0), vec![], StopId(1)),
+            (StopId(0), vec![StopId(1)], StopId(2)),
+        ])));
+        assert!(files.iter().all(|file| !file.exists()));
+    }
+}
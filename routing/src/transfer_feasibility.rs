@@ -0,0 +1,233 @@
+use crate::algorithm::PreprocessingInput;
+use crate::stations::StationHierarchy;
+use chrono::TimeDelta;
+use common::types::{StationId, StopId, TripId};
+use common::util::speed::MAX_WALKING_SPEED;
+use geo::{Distance, Haversine, Point};
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::frame::DataFrame;
+use polars::prelude::{col, Column, DataType};
+
+// Number of stations with the most stop times that are checked
+const NUM_HUBS: usize = 10;
+// Only departures within this time after an arrival are a scheduled connection
+const CONNECTION_WINDOW: TimeDelta = TimeDelta::minutes(30);
+
+/// Scheduled connections between arriving and departing trips at a single station
+#[derive(Debug, Clone, PartialEq)]
+pub struct HubFeasibility {
+    pub station: StationId,
+    // Number of stop times at the station
+    pub stop_times: u32,
+    pub connections: u32,
+    // Connections that leave at least the minimum transfer time plus the walk between platforms
+    pub feasible: u32,
+}
+
+impl HubFeasibility {
+    pub fn feasible_share(&self) -> f64 {
+        match self.connections {
+            0 => 1.0,
+            connections => self.feasible as f64 / connections as f64,
+        }
+    }
+}
+
+/// How many of the scheduled connections at the busiest stations can be made with a minimum
+/// transfer time. A low share hints at a minimum transfer time that is too long for the
+/// timetable, a share close to 1 at one that might be too short.
+///
+/// Connections are compared by their time of day, regardless of the days their trips run on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferFeasibilityReport {
+    pub min_transfer_time: TimeDelta,
+    // Ordered by the number of stop times, the busiest station first
+    pub hubs: Vec<HubFeasibility>,
+}
+
+// A vehicle arriving at or departing from a platform, with the time in milliseconds
+type StopEvent = (i64, TripId, StopId);
+
+impl TransferFeasibilityReport {
+    pub fn new(input: &PreprocessingInput, min_transfer_time: TimeDelta) -> Result<Self, PolarsError> {
+        Self::with_slack(input, min_transfer_time, &HashMap::new())
+    }
+
+    /// Like [TransferFeasibilityReport::new], with additional time to change to some trips, e.g.
+    /// the transfer slack of their mode
+    pub fn with_slack(
+        input: &PreprocessingInput,
+        min_transfer_time: TimeDelta,
+        slack: &HashMap<TripId, TimeDelta>,
+    ) -> Result<Self, PolarsError> {
+        let stations = StationHierarchy::from_stations(input.stations.clone())?;
+
+        let stops = input.stops.clone()
+            .select([col("stop_id"), col("lat"), col("lon")])
+            .collect()?;
+        let locations: HashMap<StopId, Point<f32>> = izip!(
+            stops.column("stop_id")?.u32()?,
+            stops.column("lat")?.f32()?,
+            stops.column("lon")?.f32()?,
+        )
+            .filter_map(|(stop_id, lat, lon)| Some((StopId(stop_id?), Point::new(lon?, lat?))))
+            .collect();
+
+        // Nobody arrives at the first stop of a trip and nobody departs from its last one
+        let stop_times = input.stop_times.clone()
+            .select([
+                col("trip_id"),
+                col("stop_id"),
+                col("arrival_time").cast(DataType::Int64),
+                col("departure_time").cast(DataType::Int64),
+                col("stop_sequence").eq(col("stop_sequence").min().over([col("trip_id")])).alias("first"),
+                col("stop_sequence").eq(col("stop_sequence").max().over([col("trip_id")])).alias("last"),
+            ])
+            .collect()?;
+
+        let mut stop_time_counts: HashMap<StationId, u32> = HashMap::new();
+        let mut arrivals: HashMap<StationId, Vec<StopEvent>> = HashMap::new();
+        let mut departures: HashMap<StationId, Vec<StopEvent>> = HashMap::new();
+
+        let rows = izip!(
+            stop_times.column("trip_id")?.u32()?,
+            stop_times.column("stop_id")?.u32()?,
+            stop_times.column("arrival_time")?.i64()?,
+            stop_times.column("departure_time")?.i64()?,
+            stop_times.column("first")?.bool()?,
+            stop_times.column("last")?.bool()?,
+        );
+        for (trip, stop, arrival, departure, first, last) in rows {
+            let (Some(trip), Some(stop)) = (trip, stop) else { continue };
+            let (trip, stop) = (TripId(trip), StopId(stop));
+            let Some(station) = stations.station_of(stop) else { continue };
+
+            *stop_time_counts.entry(station).or_default() += 1;
+            if let (Some(arrival), Some(false)) = (arrival, first) {
+                arrivals.entry(station).or_default().push((arrival, trip, stop));
+            }
+            if let (Some(departure), Some(false)) = (departure, last) {
+                departures.entry(station).or_default().push((departure, trip, stop));
+            }
+        }
+
+        let mut hubs = stop_time_counts.into_iter().collect::<Vec<_>>();
+        hubs.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        hubs.truncate(NUM_HUBS);
+
+        let walking_time = |from: StopId, to: StopId| match (locations.get(&from), locations.get(&to)) {
+            _ if from == to => TimeDelta::zero(),
+            (Some(from), Some(to)) => MAX_WALKING_SPEED.time_to_travel_distance(Haversine::distance(*from, *to)),
+            _ => TimeDelta::zero(),
+        };
+
+        let hubs = hubs.into_iter()
+            .map(|(station, stop_times)| {
+                let arrivals = arrivals.remove(&station).unwrap_or_default();
+                let mut departures = departures.remove(&station).unwrap_or_default();
+                departures.sort_by_key(|(departure, ..)| *departure);
+
+                let mut connections = 0;
+                let mut feasible = 0;
+                for (arrival, arriving_trip, arrival_stop) in arrivals {
+                    let start = departures.partition_point(|(departure, ..)| *departure < arrival);
+                    let end = departures.partition_point(|(departure, ..)| *departure <= arrival + CONNECTION_WINDOW.num_milliseconds());
+
+                    // Staying on the same trip is not a transfer
+                    for (departure, departing_trip, departure_stop) in departures[start..end].iter()
+                        .filter(|(_, departing_trip, _)| *departing_trip != arriving_trip) {
+                        let needed = min_transfer_time
+                            + slack.get(departing_trip).copied().unwrap_or_default()
+                            + walking_time(arrival_stop, *departure_stop);
+                        connections += 1;
+                        if departure - arrival >= needed.num_milliseconds() {
+                            feasible += 1;
+                        }
+                    }
+                }
+
+                HubFeasibility { station, stop_times, connections, feasible }
+            })
+            .collect();
+
+        Ok(Self { min_transfer_time, hubs })
+    }
+
+    pub fn connections(&self) -> u32 {
+        self.hubs.iter().map(|hub| hub.connections).sum()
+    }
+
+    pub fn feasible(&self) -> u32 {
+        self.hubs.iter().map(|hub| hub.feasible).sum()
+    }
+
+    /// One row per hub (columns "station_id", "stop_times", "connections", "feasible",
+    /// "feasible_share")
+    pub fn to_frame(&self) -> Result<DataFrame, PolarsError> {
+        DataFrame::new(vec![
+            Column::new("station_id".into(), self.hubs.iter().map(|hub| hub.station.0).collect::<Vec<_>>()),
+            Column::new("stop_times".into(), self.hubs.iter().map(|hub| hub.stop_times).collect::<Vec<_>>()),
+            Column::new("connections".into(), self.hubs.iter().map(|hub| hub.connections).collect::<Vec<_>>()),
+            Column::new("feasible".into(), self.hubs.iter().map(|hub| hub.feasible).collect::<Vec<_>>()),
+            Column::new("feasible_share".into(), self.hubs.iter().map(HubFeasibility::feasible_share).collect::<Vec<_>>()),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use polars::prelude::{IntoLazy, TimeUnit};
+
+    #[test]
+    fn test_feasibility() {
+        let minutes = |values: &[i64]| values.iter().map(|minutes| minutes * 60_000).collect::<Vec<_>>();
+        // Stops 0 and 1 are platforms of the same station ~110m apart, stop 2 is somewhere else.
+        // Trip 0 arrives at platform 0 at 10 min, trip 1 departs there at 11 min, trip 2 departs
+        // at platform 1 at 14 min.
+        let input = PreprocessingInput {
+            services: DataFrame::empty().lazy(),
+            stops: df!(
+                "stop_id" => [0u32, 1, 2],
+                "lat"     => [48.0f32, 48.001, 48.1],
+                "lon"     => [9.0f32, 9.0, 9.0],
+            ).unwrap().lazy(),
+            trips: DataFrame::empty().lazy(),
+            stop_times: df!(
+                "trip_id"        => [0u32, 0, 1, 1, 2, 2],
+                "stop_id"        => [2u32, 0, 0, 2, 1, 2],
+                "stop_sequence"  => [1u32, 2, 1, 2, 1, 2],
+                "arrival_time"   => minutes(&[0, 10, 11, 20, 14, 25]),
+                "departure_time" => minutes(&[0, 10, 11, 20, 14, 25]),
+            ).unwrap().lazy().with_columns([
+                col("arrival_time").cast(DataType::Duration(TimeUnit::Milliseconds)),
+                col("departure_time").cast(DataType::Duration(TimeUnit::Milliseconds)),
+            ]),
+            stations: df!(
+                "stop_id"    => [0u32, 1, 2],
+                "station_id" => [0u32, 0, 1],
+            ).unwrap().lazy(),
+            trip_runs: DataFrame::empty().lazy(),
+            transfers: Default::default(),
+            original_ids: Default::default(),
+        };
+
+        let report = TransferFeasibilityReport::new(&input, TimeDelta::minutes(2)).unwrap();
+        let hub = &report.hubs[0];
+        assert_eq!(hub.station, StationId(0));
+        // One minute to trip 1 is too short, four minutes to walk to the other platform are enough
+        assert_eq!((hub.connections, hub.feasible), (2, 1));
+
+        let report = TransferFeasibilityReport::new(&input, TimeDelta::zero()).unwrap();
+        assert_eq!(report.hubs[0].feasible, 2);
+        assert_eq!(report.to_frame().unwrap().height(), 2);
+
+        // Two minutes of slack to trip 2 make the walk to the other platform too long
+        let slack = HashMap::from([(TripId(2), TimeDelta::minutes(2))]);
+        let report = TransferFeasibilityReport::with_slack(&input, TimeDelta::minutes(2), &slack).unwrap();
+        assert_eq!(report.hubs[0].feasible, 0);
+    }
+}
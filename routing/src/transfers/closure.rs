@@ -0,0 +1,231 @@
+use crate::algorithm::PreprocessingResult;
+use crate::artifacts;
+use crate::journey::Leg;
+use crate::transfers::{TransferError, TransferProvider};
+use chrono::Duration;
+use common::types::config::Compression;
+use common::types::StopId;
+use common::util::speed::Speed;
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::prelude::*;
+use rayon::prelude::*;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The transfers of another provider, closed under walking several of them one after another. A
+/// transfer that is faster over another stop takes the faster way, and stops that are only
+/// reached over other stops get a transfer of their own, as long as it is at most as long as the
+/// longest one. Walks then obey the triangle inequality, which RAPTOR relies on, since it never
+/// walks twice in a row.
+pub struct ClosedTransferProvider {
+    // Shortest walks to all stops within reach, sorted by stop
+    footpaths: Arc<HashMap<StopId, Vec<(StopId, Duration)>>>,
+    inner: Box<dyn TransferProvider + Send + Sync>,
+}
+
+/// How the closure changed the transfers of the provider
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClosureStats {
+    // Transfers that are known by the provider
+    pub num_direct: usize,
+    // Transfers between stops that the provider doesn't connect
+    pub num_added: usize,
+    // Transfers of the provider that are faster over other stops
+    pub num_shortened: usize,
+}
+
+impl TransferProvider for ClosedTransferProvider {
+    // Closed walks are the shortest ones, so they are their own lower bound
+    fn lower_bound_duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        self.duration(start, end)
+    }
+
+    fn duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        let Some(footpaths) = self.footpaths.get(&start) else { return Err(TransferError::StopNotFound) };
+        footpaths.binary_search_by_key(&end, |(end, _)| *end)
+            .map(|idx| footpaths[idx].1)
+            .map_err(|_| TransferError::OutOfReach)
+    }
+
+    fn transfers_from(&self, start: &StopId) -> Vec<StopId> {
+        self.footpaths.get(start).into_iter().flatten().map(|(end, _)| *end).collect()
+    }
+
+    fn transfers_between(&self, start: StopId, end: StopId) -> Result<Vec<Leg>, TransferError> {
+        Ok(vec![
+            Leg::Transfer { start, end, duration: self.duration(start, end)? }
+        ])
+    }
+
+    // Cycling takes the ways of the provider, which are not closed
+    fn by_bike(&self, speed: Speed) -> Option<Box<dyn TransferProvider + Send + Sync>> {
+        self.inner.by_bike(speed)
+    }
+}
+
+impl ClosedTransferProvider {
+    /// Closes the transfers of `inner` between `stops`, none of which take longer than
+    /// `max_duration`
+    pub fn close(inner: Box<dyn TransferProvider + Send + Sync>, stops: &[StopId], max_duration: Duration) -> (Self, ClosureStats) {
+        let direct: HashMap<StopId, Vec<(StopId, Duration)>> = stops.par_iter()
+            .map(|start| {
+                let transfers = inner.transfers_from(start).into_iter()
+                    .filter_map(|end| inner.duration(*start, end).ok().map(|duration| (end, duration)))
+                    .filter(|(_, duration)| *duration <= max_duration)
+                    .collect();
+                (*start, transfers)
+            })
+            .collect();
+
+        let closed = stops.par_iter()
+            .map(|start| {
+                let mut footpaths: Vec<(StopId, Duration)> = shortest_walks(&direct, *start, max_duration).into_iter().collect();
+                footpaths.sort_unstable_by_key(|(end, _)| *end);
+
+                let mut direct_of_start = direct.get(start).cloned().unwrap_or_default();
+                direct_of_start.sort_unstable_by_key(|(end, _)| *end);
+                let mut stats = ClosureStats { num_direct: direct_of_start.len(), ..Default::default() };
+                for (end, duration) in &footpaths {
+                    match direct_of_start.binary_search_by_key(end, |(end, _)| *end) {
+                        Ok(idx) if direct_of_start[idx].1 > *duration => stats.num_shortened += 1,
+                        Ok(_) => {}
+                        Err(_) => stats.num_added += 1,
+                    }
+                }
+                (*start, footpaths, stats)
+            })
+            .collect::<Vec<_>>();
+
+        let mut stats = ClosureStats::default();
+        let mut footpaths = HashMap::with_capacity(closed.len());
+        for (start, closed, closed_stats) in closed {
+            stats.num_direct += closed_stats.num_direct;
+            stats.num_added += closed_stats.num_added;
+            stats.num_shortened += closed_stats.num_shortened;
+            footpaths.insert(start, closed);
+        }
+        (Self { footpaths: Arc::new(footpaths), inner }, stats)
+    }
+
+    /// Writes the closed walks as the table `name` next to the stops of
+    /// [artifacts::write_stops], one row per walk. Stops without walks have a row without an end.
+    pub fn write(&self, dir: &Path, name: &str, compression: Compression) -> PreprocessingResult<()> {
+        let (mut starts, mut ends, mut durations) = (vec![], vec![], vec![]);
+        for (start, footpaths) in self.footpaths.iter() {
+            if footpaths.is_empty() {
+                starts.push(start.0);
+                ends.push(None);
+                durations.push(None);
+            }
+            for (end, duration) in footpaths {
+                starts.push(start.0);
+                ends.push(Some(end.0));
+                durations.push(Some(duration.num_milliseconds()));
+            }
+        }
+        let table = df!(
+            "start" => starts,
+            "end" => ends,
+            "duration_ms" => durations,
+        )?;
+        Ok(artifacts::write_table(dir, name, table, compression)?)
+    }
+
+    /// Reads the walks that [Self::write] wrote, which close those of `inner`
+    pub fn read(dir: &Path, name: &str, inner: Box<dyn TransferProvider + Send + Sync>) -> PreprocessingResult<Self> {
+        let table = artifacts::read_table(dir, name)?;
+        let mut footpaths: HashMap<StopId, Vec<(StopId, Duration)>> = HashMap::new();
+        for (start, end, duration) in izip!(
+            table.column("start")?.u32()?,
+            table.column("end")?.u32()?,
+            table.column("duration_ms")?.i64()?,
+        ) {
+            let Some(start) = start.map(StopId) else { continue };
+            let footpaths = footpaths.entry(start).or_default();
+            if let Some((end, duration)) = end.zip(duration) {
+                footpaths.push((StopId(end), Duration::milliseconds(duration)));
+            }
+        }
+        footpaths.values_mut().for_each(|footpaths| footpaths.sort_unstable_by_key(|(end, _)| *end));
+        Ok(Self { footpaths: Arc::new(footpaths), inner })
+    }
+}
+
+// Durations of the fastest walks from the start to all other stops over any number of
+// transfers, up to the maximum duration
+fn shortest_walks(direct: &HashMap<StopId, Vec<(StopId, Duration)>>, start: StopId, max_duration: Duration) -> HashMap<StopId, Duration> {
+    let mut durations: HashMap<StopId, Duration> = HashMap::from([(start, Duration::zero())]);
+    let mut queue = BinaryHeap::from([Reverse((Duration::zero(), start))]);
+    while let Some(Reverse((duration, stop))) = queue.pop() {
+        if durations.get(&stop).is_some_and(|known| *known < duration) {
+            continue;
+        }
+        for (end, walk) in direct.get(&stop).into_iter().flatten() {
+            let arrival = duration + *walk;
+            if arrival <= max_duration && durations.get(end).is_none_or(|known| arrival < *known) {
+                durations.insert(*end, arrival);
+                queue.push(Reverse((arrival, *end)));
+            }
+        }
+    }
+    durations.remove(&start);
+    durations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfers::fixed_time::FixedTimeTransferProvider;
+
+    #[test]
+    fn test_close() {
+        // Walking from 0 to 2 directly is slower than over 1, 3 is only reached over 2
+        let minutes = |minutes: i64| Duration::minutes(minutes);
+        let unreachable = minutes(100);
+        let inner = FixedTimeTransferProvider {
+            duration_matrix: ndarray::array![
+                [minutes(0), minutes(2), minutes(10), unreachable],
+                [minutes(2), minutes(0), minutes(3), unreachable],
+                [minutes(10), minutes(3), minutes(0), minutes(4)],
+                [unreachable, unreachable, minutes(4), minutes(0)],
+            ],
+        };
+        let stops = (0..4).map(StopId).collect::<Vec<_>>();
+
+        let (closed, stats) = ClosedTransferProvider::close(Box::new(inner), &stops, minutes(15));
+        assert_eq!(closed.duration(StopId(0), StopId(2)).unwrap(), minutes(5));
+        assert_eq!(closed.duration(StopId(0), StopId(3)).unwrap(), minutes(9));
+        assert_eq!(closed.transfers_from(&StopId(3)), vec![StopId(0), StopId(1), StopId(2)]);
+        assert_eq!(stats, ClosureStats { num_direct: 8, num_added: 4, num_shortened: 2 });
+
+        // Walks over other stops are limited like the direct ones
+        let inner = FixedTimeTransferProvider { duration_matrix: ndarray::Array2::from_elem((4, 4), minutes(5)) };
+        let (closed, stats) = ClosedTransferProvider::close(Box::new(inner), &stops, minutes(5));
+        assert_eq!(closed.duration(StopId(0), StopId(3)).unwrap(), minutes(5));
+        assert_eq!(stats, ClosureStats { num_direct: 12, num_added: 0, num_shortened: 0 });
+    }
+
+    #[test]
+    fn test_write() {
+        let minutes = |minutes: i64| Duration::minutes(minutes);
+        let inner = || FixedTimeTransferProvider {
+            duration_matrix: ndarray::array![
+                [minutes(0), minutes(2), minutes(100)],
+                [minutes(2), minutes(0), minutes(100)],
+                [minutes(100), minutes(100), minutes(0)],
+            ],
+        };
+        let stops = (0..3).map(StopId).collect::<Vec<_>>();
+        let (closed, _) = ClosedTransferProvider::close(Box::new(inner()), &stops, minutes(15));
+
+        let dir = tempfile::tempdir().unwrap();
+        closed.write(dir.path(), "closed_footpaths", Compression::default()).unwrap();
+        let read = ClosedTransferProvider::read(dir.path(), "closed_footpaths", Box::new(inner())).unwrap();
+        assert_eq!(read.duration(StopId(0), StopId(1)).unwrap(), minutes(2));
+        // Stops without walks are still known
+        assert!(matches!(read.duration(StopId(2), StopId(0)), Err(TransferError::OutOfReach)));
+    }
+}
@@ -0,0 +1,97 @@
+use crate::journey::Leg;
+use crate::transfers::{TransferError, TransferProvider};
+use chrono::Duration;
+use common::types::StopId;
+use common::util::speed::Speed;
+use itertools::Itertools;
+
+/// An ordered chain of transfer providers, e.g. the transfers of the timetable, then the walks of an
+/// OSM extract and the straight line for all other stops. Every pair of stops is answered by the
+/// first provider that knows both of them, so a provider that says a transfer is out of reach
+/// hides the ones after it.
+pub struct CompositeTransferProvider {
+    providers: Vec<Box<dyn TransferProvider + Send + Sync>>,
+}
+
+impl CompositeTransferProvider {
+    pub fn new(providers: Vec<Box<dyn TransferProvider + Send + Sync>>) -> Self {
+        Self { providers }
+    }
+
+    // The answer of the first provider that knows both stops
+    fn first(&self, answer: impl Fn(&dyn TransferProvider) -> Result<Duration, TransferError>) -> Result<Duration, TransferError> {
+        self.providers.iter()
+            .map(|provider| answer(provider.as_ref()))
+            .find(|result| !matches!(result, Err(TransferError::StopNotFound)))
+            .unwrap_or(Err(TransferError::StopNotFound))
+    }
+}
+
+impl TransferProvider for CompositeTransferProvider {
+    fn lower_bound_duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        self.first(|provider| provider.lower_bound_duration(start, end))
+    }
+
+    fn duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        self.first(|provider| provider.duration(start, end))
+    }
+
+    // Stops that a provider would reach are left out if an earlier one knows better
+    fn transfers_from(&self, start: &StopId) -> Vec<StopId> {
+        self.providers.iter()
+            .flat_map(|provider| provider.transfers_from(start))
+            .unique()
+            .filter(|end| self.duration(*start, *end).is_ok())
+            .collect()
+    }
+
+    fn transfers_between(&self, start: StopId, end: StopId) -> Result<Vec<Leg>, TransferError> {
+        Ok(vec![
+            Leg::Transfer { start, end, duration: self.duration(start, end)? }
+        ])
+    }
+
+    // Providers without a bike variant are left out of the chain
+    fn by_bike(&self, speed: Speed) -> Option<Box<dyn TransferProvider + Send + Sync>> {
+        let providers = self.providers.iter().filter_map(|provider| provider.by_bike(speed)).collect_vec();
+        (!providers.is_empty()).then(|| Box::new(Self::new(providers)) as Box<dyn TransferProvider + Send + Sync>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfers::crow_fly::CrowFlyTransferProvider;
+    use crate::transfers::fixed_time::FixedTimeTransferProvider;
+    use crate::transfers::timetable::TimetableTransferProvider;
+    use common::util::speed::CYCLING_SPEED;
+    use geo::Coord;
+    use polars::prelude::{col, df, DataType, IntoLazy, TimeUnit};
+
+    #[test]
+    fn test_chain() {
+        // The timetable knows two transfers from stop 0, one of which is not possible
+        let timetable = TimetableTransferProvider::from_frame(df!(
+            "from_stop_id" => [0u32, 0],
+            "to_stop_id"   => [1u32, 2],
+            "duration"     => [Some(120_000i64), None],
+        ).unwrap().lazy().with_column(col("duration").cast(DataType::Duration(TimeUnit::Milliseconds)))).unwrap().unwrap();
+        // Walking takes five minutes between all three stops
+        let walking = FixedTimeTransferProvider {
+            duration_matrix: ndarray::Array2::from_elem((3, 3), Duration::minutes(5)),
+        };
+        let provider = CompositeTransferProvider::new(vec![Box::new(timetable), Box::new(walking)]);
+
+        assert_eq!(provider.duration(StopId(0), StopId(1)).unwrap(), Duration::minutes(2));
+        assert!(matches!(provider.duration(StopId(0), StopId(2)), Err(TransferError::OutOfReach)));
+        assert_eq!(provider.duration(StopId(1), StopId(0)).unwrap(), Duration::minutes(5));
+        assert_eq!(provider.transfers_from(&StopId(0)), vec![StopId(1)]);
+        assert_eq!(provider.transfers_from(&StopId(1)), vec![StopId(0), StopId(2)]);
+
+        // Neither of them cycles
+        assert!(provider.by_bike(CYCLING_SPEED).is_none());
+        let crow_fly = CrowFlyTransferProvider::from(vec![Coord { x: 48.0, y: 9.0 }, Coord { x: 48.0, y: 9.0225 }]);
+        let provider = CompositeTransferProvider::new(vec![Box::new(crow_fly)]);
+        assert!(provider.by_bike(CYCLING_SPEED).unwrap().duration(StopId(0), StopId(1)).is_ok());
+    }
+}
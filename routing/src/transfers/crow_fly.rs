@@ -1,34 +1,55 @@
 use crate::journey::Leg;
 use crate::transfers::{TransferError, TransferProvider};
 use chrono::Duration;
+use common::types::config::TransferConfig;
 use common::types::StopId;
-use common::util::speed::{Speed, MAX_WALKING_DURATION, MAX_WALKING_SPEED};
-use geo::{Coord, Distance, Haversine, Point};
+use common::util::haversine::{PointColumns, EARTH_RADIUS};
+use common::util::speed::Speed;
+use geo::{Coord, Point};
 use itertools::Itertools;
 use polars::error::PolarsError;
 use polars::prelude::{col, LazyFrame};
+use rstar::primitives::GeomWithData;
+use rstar::RTree;
+use std::sync::Arc;
+
+// Stops as points on a sphere, whose straight distances grow with their distances on the surface
+type StopPoint = GeomWithData<[f64; 3], StopId>;
 
 /// A pretty stupid transfer provider, that calculates the duration by measuring the distance
 /// between stops and then going that distance in a straight line.
 /// It basically always underestimates how long it takes.
+///
+/// Only stops within the maximum duration and distance of the config are transfers from a stop, so
+/// stops farther apart aren't connected at all, not even by a very long walk.
 #[derive(Clone)]
 pub struct CrowFlyTransferProvider {
-    stop_coords: Vec<Coord<f32>>,
+    // By the index of the stop
+    points: Arc<PointColumns>,
+    // Stops within reach are found in logarithmic time instead of by measuring the distance to all
+    // of them
+    tree: Arc<RTree<StopPoint>>,
     speed: Speed,
     max_duration: Duration,
+    max_distance: Option<f32>,
+    // Added to the time of travelling the distance
+    penalty: Duration,
 }
 
 impl TransferProvider for CrowFlyTransferProvider {
     fn lower_bound_duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
-        let Some(start) = self.stop_coords.get(start.0 as usize) else { return Err(TransferError::StopNotFound); };
-        let Some(end) = self.stop_coords.get(end.0 as usize) else { return Err(TransferError::StopNotFound); };
-
-        let distance_meters = Haversine::distance(Point::from(*start), Point::from(*end));
+        let Some(distance_meters) = self.points.distance_between(start.0 as usize, end.0 as usize) else {
+            return Err(TransferError::StopNotFound);
+        };
+        let distance_meters = distance_meters as f32;
+        if self.max_distance.is_some_and(|max_distance| distance_meters > max_distance) {
+            return Err(TransferError::OutOfReach);
+        }
         
         let time = self.speed.time_to_travel_distance(distance_meters);
         
         if time <= self.max_duration {
-            Ok(time)
+            Ok(time + self.penalty)
         } else {
             Err(TransferError::OutOfReach)
         }
@@ -39,11 +60,19 @@ impl TransferProvider for CrowFlyTransferProvider {
     }
 
     fn transfers_from(&self, start: &StopId) -> Vec<StopId> {
-        (0u32..self.stop_coords.len() as u32)
-            // Return as Stop Ids, not as u32
-            .map(StopId)
+        let Some(vector) = self.points.vector(start.0 as usize) else { return vec![] };
+        // With a meter to spare for rounding
+        let reach = self.max_distance.map_or(f32::MAX, |max| max)
+            .min(self.speed.distance_in(self.max_duration)) as f64 + 1.0;
+        // The chord of the arc of that length bounds the candidates, whose distances are then
+        // measured on the surface
+        let chord = 2.0 * EARTH_RADIUS * (reach.min(EARTH_RADIUS * std::f64::consts::PI) / (2.0 * EARTH_RADIUS)).sin();
+        let candidates = self.tree.locate_within_distance(on_sphere(vector), chord * chord)
+            .map(|stop| stop.data.0 as usize);
+        self.points.within_among(start.0 as usize, candidates, reach).into_iter()
+            .map(|end| StopId(end as u32))
             // Don't return the starting station itself
-            .filter(|x| x != start)
+            .filter(|end| end != start)
             .collect()
     }
 
@@ -52,17 +81,49 @@ impl TransferProvider for CrowFlyTransferProvider {
             Leg::Transfer { start, end, duration: self.duration(start, end)? }
         ])
     }
+
+    // Cycling is limited to as long as walking, so it reaches stops that are further away. The
+    // distance limit is one of walking.
+    fn by_bike(&self, speed: Speed) -> Option<Box<dyn TransferProvider + Send + Sync>> {
+        Some(Box::new(Self { max_distance: None, ..self.clone().with_speed(speed) }))
+    }
 }
 
 impl From<Vec<Coord<f32>>> for CrowFlyTransferProvider {
     fn from(stop_coords: Vec<Coord<f32>>) -> Self {
-        Self {
-            stop_coords, speed: MAX_WALKING_SPEED, max_duration: MAX_WALKING_DURATION
-        }
+        // Read like [geo::Haversine] reads the coordinates
+        let points: PointColumns = stop_coords.iter().map(|coord| Point::new(coord.x as f64, coord.y as f64)).collect();
+        let stop_points = (0..points.len())
+            .filter_map(|idx| Some(StopPoint::new(on_sphere(points.vector(idx)?), StopId(idx as u32))))
+            .collect();
+        let tree = Arc::new(RTree::bulk_load(stop_points));
+        Self { points: Arc::new(points), tree, speed: Speed(0.0), max_duration: Duration::zero(), max_distance: None, penalty: Duration::zero() }
+            .with_config(&TransferConfig::default())
     }
 }
 
+// The vector on the unit sphere on a sphere of the size of the earth
+fn on_sphere(vector: [f64; 3]) -> [f64; 3] {
+    vector.map(|axis| EARTH_RADIUS * axis)
+}
+
 impl CrowFlyTransferProvider {
+    /// Travels the straight line at another speed than walking, e.g. by bike
+    pub fn with_speed(self, speed: Speed) -> Self {
+        Self { speed, ..self }
+    }
+
+    /// Walks as configured instead of by the defaults of [TransferConfig]
+    pub fn with_config(self, config: &TransferConfig) -> Self {
+        Self {
+            speed: config.walking_speed(),
+            max_duration: config.max_duration(),
+            max_distance: config.max_distance_meters,
+            penalty: config.penalty(),
+            ..self
+        }
+    }
+
     pub fn from_stops(stops_frame: LazyFrame) -> Result<Self, PolarsError> {
         let stop_lats = stops_frame.clone()
             .select(&[col("lat")])
@@ -0,0 +1,255 @@
+//! Walks between stops computed by a routing engine with an HTTP API. Only stops within the walking
+//! limits in a straight line are asked for, in table requests of several sources and targets. The
+//! resulting matrix is cached by the router, the stops and the limits, so that later preprocessing
+//! runs of the same timetable don't send any requests.
+
+use crate::algorithm::PreprocessingError;
+use crate::artifacts;
+use chrono::Duration;
+use common::types::config::{Compression, ExternalRouter, RouterEngine, TransferConfig};
+use common::types::StopId;
+use common::util::haversine::PointColumns;
+use geo::Point;
+use hashbrown::{HashMap, HashSet};
+use itertools::{izip, Itertools};
+use log::debug;
+use polars::prelude::*;
+use rstar::primitives::GeomWithData;
+use rstar::{RTree, AABB};
+use serde::Deserialize;
+use std::fmt;
+use std::fmt::Display;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
+
+// Meters of one degree of latitude
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Durations of the walks from every stop to the stops within the limits of `config`, without the
+/// penalty. Pairs that the router can't connect are left out.
+pub fn footpaths(
+    router: &ExternalRouter,
+    stops: &[(StopId, Point<f64>)],
+    config: &TransferConfig,
+    cache_dir: &Path,
+) -> Result<HashMap<StopId, Vec<(StopId, Duration)>>, PreprocessingError> {
+    let cache = cache_dir.join("transfers");
+    let table_name = format!("walks-{:016x}", cache_key(router, stops, config));
+    let mut footpaths: HashMap<StopId, Vec<(StopId, Duration)>> = HashMap::new();
+    if let Ok(table) = artifacts::read_table(&cache, &table_name) {
+        debug!(target: "preprocessing", "Using cached walks of {} from {}", router.url, cache.display());
+        for (start, end, duration) in izip!(table.column("start")?.u32()?, table.column("end")?.u32()?, table.column("duration_ms")?.i64()?) {
+            if let (Some(start), Some(end), Some(duration)) = (start, end, duration) {
+                footpaths.entry(StopId(start)).or_default().push((StopId(end), Duration::milliseconds(duration)));
+            }
+        }
+        return Ok(footpaths);
+    }
+
+    let mut num_requests = 0;
+    for (sources, targets) in batches(stops, config, router.max_locations) {
+        let candidates: HashSet<(StopId, StopId)> = sources.iter()
+            .flat_map(|(source, _, reachable)| reachable.iter().map(|target| (*source, *target)))
+            .collect();
+        let coords = sources.iter().map(|(_, coord, _)| *coord).chain(targets.iter().map(|(_, coord)| *coord)).collect_vec();
+        let matrix = request(router, &coords, sources.len(), config)?;
+        num_requests += 1;
+
+        for ((source, _, _), row) in sources.iter().zip(matrix) {
+            for ((target, _), seconds) in targets.iter().zip(row) {
+                let Some(seconds) = seconds.filter(|_| candidates.contains(&(*source, *target))) else { continue };
+                let duration = Duration::milliseconds((seconds * 1_000.0) as i64);
+                if duration <= config.max_duration() {
+                    footpaths.entry(*source).or_default().push((*target, duration));
+                }
+            }
+        }
+    }
+    debug!(target: "preprocessing", "Requested the walks between {} stops from {} in {} requests", stops.len(), router.url, num_requests);
+
+    let rows = footpaths.iter().flat_map(|(start, ends)| ends.iter().map(|(end, duration)| (start.0, end.0, duration.num_milliseconds())));
+    let (starts, ends, durations): (Vec<u32>, Vec<u32>, Vec<i64>) = itertools::multiunzip(rows);
+    let table = df!("start" => starts, "end" => ends, "duration_ms" => durations)?;
+    std::fs::create_dir_all(&cache)?;
+    artifacts::write_table(&cache, &table_name, table, Compression::default())?;
+    Ok(footpaths)
+}
+
+// A source with its coordinates and the stops it may reach in a straight line
+type Source = (StopId, Point<f64>, Vec<StopId>);
+// Sources and targets of a single request
+type Batch = (Vec<Source>, Vec<(StopId, Point<f64>)>);
+
+// Requests of nearby sources, together with all targets that any of them may reach. Both are
+// limited so that a request has at most `max_locations` locations.
+fn batches(stops: &[(StopId, Point<f64>)], config: &TransferConfig, max_locations: usize) -> Vec<Batch> {
+    let max_distance = config.max_distance_meters.map_or(f32::MAX, |max| max)
+        .min(config.walking_speed().distance_in(config.max_duration())) as f64;
+    // By the index of the stop in `stops`
+    let points: PointColumns = stops.iter().map(|(_, coord)| *coord).collect();
+    let tree = RTree::bulk_load(stops.iter().enumerate().map(|(idx, (_, coord))| GeomWithData::new([coord.x(), coord.y()], idx)).collect());
+
+    let sources: Vec<Source> = stops.iter().enumerate()
+        // Nearby stops are in the same cell of about a kilometer, so they share many targets
+        .sorted_by_key(|(_, (_, coord))| ((coord.y() * 100.0).floor() as i64, (coord.x() * 100.0).floor() as i64))
+        .filter_map(|(idx, (stop, coord))| {
+            let lat_radius = max_distance / METERS_PER_DEGREE;
+            let lon_radius = lat_radius / coord.y().to_radians().cos().max(0.01);
+            let envelope = AABB::from_corners([coord.x() - lon_radius, coord.y() - lat_radius], [coord.x() + lon_radius, coord.y() + lat_radius]);
+            let candidates = tree.locate_in_envelope(&envelope).map(|target| target.data);
+            let reachable = points.within_among(idx, candidates, max_distance).into_iter()
+                .map(|target| stops[target].0)
+                .filter(|target| target != stop)
+                .collect_vec();
+            (!reachable.is_empty()).then_some((*stop, *coord, reachable))
+        })
+        .collect();
+
+    let coord_of_stop: HashMap<StopId, Point<f64>> = stops.iter().copied().collect();
+    let sources_per_request = (max_locations / 4).max(1);
+    let mut batches = vec![];
+    for sources in &sources.into_iter().chunks(sources_per_request) {
+        let sources = sources.collect_vec();
+        let targets = sources.iter().flat_map(|(_, _, reachable)| reachable).unique().collect_vec();
+        let targets_per_request = max_locations.saturating_sub(sources.len()).max(1);
+        for targets in targets.chunks(targets_per_request) {
+            let targets = targets.iter().map(|target| (**target, coord_of_stop[*target])).collect();
+            batches.push((sources.clone(), targets));
+        }
+    }
+    batches
+}
+
+// Seconds from each of the first `num_sources` coordinates to each of the others
+fn request(router: &ExternalRouter, coords: &[Point<f64>], num_sources: usize, config: &TransferConfig) -> Result<Vec<Vec<Option<f64>>>, RouterError> {
+    let client = reqwest::blocking::Client::new();
+    let url = router.url.trim_end_matches('/');
+    match router.engine {
+        RouterEngine::Osrm => {
+            let sources = (0..num_sources).join(";");
+            let destinations = (num_sources..coords.len()).join(";");
+            let coords = coords.iter().map(|coord| format!("{:.6},{:.6}", coord.x(), coord.y())).join(";");
+            let table: OsrmTable = client.get(format!("{url}/table/v1/foot/{coords}"))
+                .query(&[("sources", sources), ("destinations", destinations), ("annotations", "duration".into())])
+                .send()?
+                .json()?;
+            match table.durations {
+                Some(durations) if table.code == "Ok" => Ok(durations),
+                _ => Err(RouterError::Rejected(table.message.unwrap_or(table.code))),
+            }
+        }
+        RouterEngine::Valhalla => {
+            let location = |coord: &Point<f64>| serde_json::json!({ "lat": coord.y(), "lon": coord.x() });
+            let body = serde_json::json!({
+                "sources": coords[..num_sources].iter().map(location).collect_vec(),
+                "targets": coords[num_sources..].iter().map(location).collect_vec(),
+                "costing": "pedestrian",
+                "costing_options": { "pedestrian": { "walking_speed": config.walking_speed } },
+            });
+            let matrix: ValhallaMatrix = client.post(format!("{url}/sources_to_targets"))
+                .json(&body)
+                .send()?
+                .error_for_status()?
+                .json()?;
+            Ok(matrix.sources_to_targets.into_iter()
+                .map(|row| row.into_iter().map(|cell| cell.time).collect())
+                .collect())
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct OsrmTable {
+    code: String,
+    message: Option<String>,
+    durations: Option<Vec<Vec<Option<f64>>>>,
+}
+
+#[derive(Deserialize)]
+struct ValhallaMatrix {
+    sources_to_targets: Vec<Vec<ValhallaCell>>,
+}
+
+#[derive(Deserialize)]
+struct ValhallaCell {
+    time: Option<f64>,
+}
+
+// Another router, other stops or other limits need other walks
+fn cache_key(router: &ExternalRouter, stops: &[(StopId, Point<f64>)], config: &TransferConfig) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (router.engine, &router.url).hash(&mut hasher);
+    for (stop, coord) in stops {
+        (stop.0, coord.x().to_bits(), coord.y().to_bits()).hash(&mut hasher);
+    }
+    (config.walking_speed.to_bits(), config.max_walking_minutes, config.max_distance_meters.map(f32::to_bits)).hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum RouterError {
+    Reqwest(#[from] reqwest::Error),
+    // The router answered, but without walks
+    Rejected(String),
+}
+
+impl Display for RouterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouterError::Reqwest(err) => write!(f, "Request to the router failed: {}", err),
+            RouterError::Rejected(message) => write!(f, "Router rejected the request: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_footpaths() {
+        // Answers every table request with a minute between all sources and destinations
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut num_requests = 0;
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream).read_line(&mut request_line).unwrap();
+                if request_line.contains("/shutdown") {
+                    return num_requests;
+                }
+                let count = |param: &str| request_line.split(&format!("{param}=")).nth(1).unwrap()
+                    .split(['&', ' ']).next().unwrap()
+                    .split("%3B").count();
+                let row = vec!["60.0"; count("destinations")].join(",");
+                let body = format!(r#"{{"code":"Ok","durations":[{}]}}"#, vec![format!("[{row}]"); count("sources")].join(","));
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+                num_requests += 1;
+            }
+            num_requests
+        });
+
+        // Three stops within a few hundred meters and one too far away
+        let stops = [(0, 48.0, 9.0), (1, 48.001, 9.0), (2, 48.002, 9.0), (3, 48.5, 9.0)]
+            .map(|(id, lat, lon)| (StopId(id), Point::new(lon, lat)));
+        let router = ExternalRouter { engine: RouterEngine::Osrm, url: url.clone(), max_locations: 3 };
+        let cache_dir = tempfile::tempdir().unwrap();
+        let config = TransferConfig::default();
+
+        let walks = footpaths(&router, &stops, &config, cache_dir.path()).unwrap();
+        assert_eq!(walks[&StopId(0)].len(), 2);
+        assert!(walks[&StopId(1)].contains(&(StopId(2), Duration::minutes(1))));
+        assert!(!walks.contains_key(&StopId(3)));
+
+        // The second time, the walks are read from the cache
+        reqwest::blocking::get(format!("{url}/shutdown")).ok();
+        let num_requests = server.join().unwrap();
+        assert!(num_requests > 1, "{num_requests}");
+        let cached = footpaths(&router, &stops, &config, cache_dir.path()).unwrap();
+        assert_eq!(cached[&StopId(0)].iter().sorted().collect_vec(), walks[&StopId(0)].iter().sorted().collect_vec());
+    }
+}
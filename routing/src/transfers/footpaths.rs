@@ -0,0 +1,248 @@
+use crate::algorithm::PreprocessingResult;
+use crate::artifacts;
+use crate::journey::Leg;
+use crate::transfers::crow_fly::CrowFlyTransferProvider;
+use crate::transfers::external;
+use crate::transfers::osm::WalkNetwork;
+use crate::transfers::{TransferError, TransferProvider};
+use chrono::Duration;
+use common::types::config::{Compression, ExternalRouter, TransferConfig};
+use common::types::StopId;
+use common::util::paths;
+use common::util::speed::Speed;
+use geo::{Distance, Haversine, Point};
+use hashbrown::{HashMap, HashSet};
+use itertools::izip;
+use polars::prelude::*;
+use rayon::prelude::*;
+use rstar::primitives::GeomWithData;
+use rstar::RTree;
+use std::path::Path;
+use std::sync::Arc;
+
+// Stops further from the closest node of a way aren't connected to the network, e.g. since the
+// extract doesn't cover them
+const MAX_SNAP_DISTANCE: f32 = 100.0;
+
+/// Walks between stops along the footways, streets and platforms of an OpenStreetMap extract or as
+/// computed by an [external] routing engine. The walks are computed once during preprocessing, up
+/// to the walking limits of [TransferConfig]. Stops that aren't close to any way walk in a straight
+/// line like [CrowFlyTransferProvider].
+#[derive(Clone)]
+pub struct FootpathTransferProvider {
+    // Reachable stops with the duration of walking there, sorted by stop
+    footpaths: Arc<HashMap<StopId, Vec<(StopId, Duration)>>>,
+    off_network: Arc<HashSet<StopId>>,
+    crow_fly: CrowFlyTransferProvider,
+}
+
+impl TransferProvider for FootpathTransferProvider {
+    // Footpaths are exact, so they are their own lower bound
+    fn lower_bound_duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        if self.off_network.contains(&start) || self.off_network.contains(&end) {
+            return self.crow_fly.lower_bound_duration(start, end);
+        }
+        let Some(footpaths) = self.footpaths.get(&start) else { return Err(TransferError::StopNotFound) };
+        footpaths.binary_search_by_key(&end, |(end, _)| *end)
+            .map(|idx| footpaths[idx].1)
+            .map_err(|_| TransferError::OutOfReach)
+    }
+
+    fn duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        self.lower_bound_duration(start, end)
+    }
+
+    fn transfers_from(&self, start: &StopId) -> Vec<StopId> {
+        if self.off_network.contains(start) {
+            return self.crow_fly.transfers_from(start);
+        }
+        let footpaths = self.footpaths.get(start).into_iter().flatten().map(|(end, _)| *end);
+        footpaths.chain(self.off_network.iter().copied().filter(|end| end != start)).collect()
+    }
+
+    fn transfers_between(&self, start: StopId, end: StopId) -> Result<Vec<Leg>, TransferError> {
+        Ok(vec![
+            Leg::Transfer { start, end, duration: self.duration(start, end)? }
+        ])
+    }
+
+    // Bikes aren't bound to footways, they still go the straight line
+    fn by_bike(&self, speed: Speed) -> Option<Box<dyn TransferProvider + Send + Sync>> {
+        self.crow_fly.by_bike(speed)
+    }
+}
+
+impl FootpathTransferProvider {
+    /// Requests the walks between all stops that are within the limits of `config` from the router
+    pub fn from_router(router: &ExternalRouter, stops: LazyFrame, config: &TransferConfig) -> PreprocessingResult<Self> {
+        let footpaths = external::footpaths(router, &stop_coords(stops.clone())?, config, &paths::cache_dir())?;
+        Self::new(footpaths, HashSet::new(), stops, config)
+    }
+
+    // Footpaths are sorted here, stops without any get an empty list
+    fn new(
+        mut footpaths: HashMap<StopId, Vec<(StopId, Duration)>>,
+        off_network: HashSet<StopId>,
+        stops: LazyFrame,
+        config: &TransferConfig,
+    ) -> PreprocessingResult<Self> {
+        footpaths.values_mut().for_each(|footpaths| footpaths.sort_unstable_by_key(|(end, _)| *end));
+        let frame = stops.clone().select([col("stop_id")]).collect()?;
+        for stop in frame.column("stop_id")?.u32()?.into_iter().flatten().map(StopId) {
+            if !off_network.contains(&stop) {
+                footpaths.entry(stop).or_default();
+            }
+        }
+
+        Ok(Self {
+            footpaths: Arc::new(footpaths),
+            off_network: Arc::new(off_network),
+            crow_fly: CrowFlyTransferProvider::from_stops(stops)?.with_config(config),
+        })
+    }
+
+    /// Computes the walks between all stops that are within the limits of `config` along the ways
+    /// of the extract
+    pub fn from_osm(extract: &Path, stops: LazyFrame, config: &TransferConfig) -> PreprocessingResult<Self> {
+        let network = WalkNetwork::read(extract)?;
+        let coords = stop_coords(stops.clone())?;
+
+        // Nearest neighbours by degrees are close enough to the ones by meters for snapping
+        let tree = RTree::bulk_load(
+            network.nodes.iter().enumerate().map(|(idx, node)| GeomWithData::new([node.x(), node.y()], idx)).collect(),
+        );
+        let mut off_network = HashSet::new();
+        let mut stops_at_node: HashMap<usize, Vec<(StopId, f32)>> = HashMap::new();
+        for (stop, coord) in &coords {
+            let snapped = tree.nearest_neighbor(&[coord.x(), coord.y()])
+                .map(|node| (node.data, Haversine::distance(*coord, network.nodes[node.data]) as f32))
+                .filter(|(_, distance)| *distance <= MAX_SNAP_DISTANCE);
+            match snapped {
+                Some((node, distance)) => stops_at_node.entry(node).or_default().push((*stop, distance)),
+                None => _ = off_network.insert(*stop),
+            }
+        }
+
+        let speed = config.walking_speed();
+        let max_distance = config.max_distance_meters.map_or(f32::MAX, |max| max)
+            .min(speed.distance_in(config.max_duration()));
+        let stops_at_node = &stops_at_node;
+        let footpaths = stops_at_node.par_iter()
+            .flat_map_iter(|(node, stops)| {
+                let distances = network.distances_from(*node, max_distance);
+                stops.iter().map(move |(start, start_distance)| {
+                    let mut footpaths: Vec<(StopId, Duration)> = distances.iter()
+                        .flat_map(|(end_node, distance)| stops_at_node.get(end_node).into_iter().flatten()
+                            .map(move |(end, end_distance)| (*end, start_distance + distance + end_distance)))
+                        .filter(|(end, distance)| end != start && *distance <= max_distance)
+                        .map(|(end, distance)| (end, speed.time_to_travel_distance(distance) + config.penalty()))
+                        .collect();
+                    footpaths.sort_unstable_by_key(|(end, _)| *end);
+                    (*start, footpaths)
+                }).collect::<Vec<_>>()
+            })
+            .collect();
+
+        Self::new(footpaths, off_network, stops, config)
+    }
+
+    /// Writes the footpaths as the table `name` next to the stops of [artifacts::write_stops], one
+    /// row per walk. Stops off the network have a row without an end.
+    pub fn write(&self, dir: &Path, name: &str, compression: Compression) -> PreprocessingResult<()> {
+        let rows = self.footpaths.iter()
+            .flat_map(|(start, footpaths)| footpaths.iter().map(|(end, duration)| (*start, Some(*end), Some(*duration))))
+            .chain(self.off_network.iter().map(|stop| (*stop, None, None)));
+        let (mut starts, mut ends, mut durations) = (vec![], vec![], vec![]);
+        for (start, end, duration) in rows {
+            starts.push(start.0);
+            ends.push(end.map(|end: StopId| end.0));
+            durations.push(duration.map(|duration: Duration| duration.num_milliseconds()));
+        }
+        let table = df!(
+            "start" => starts,
+            "end" => ends,
+            "duration_ms" => durations,
+        )?;
+        Ok(artifacts::write_table(dir, name, table, compression)?)
+    }
+
+    /// Reads the footpaths that [Self::write] wrote, walking off the network as configured
+    pub fn read(dir: &Path, name: &str, stops: LazyFrame, config: &TransferConfig) -> PreprocessingResult<Self> {
+        let table = artifacts::read_table(dir, name)?;
+        let mut footpaths: HashMap<StopId, Vec<(StopId, Duration)>> = HashMap::new();
+        let mut off_network = HashSet::new();
+        for (start, end, duration) in izip!(
+            table.column("start")?.u32()?,
+            table.column("end")?.u32()?,
+            table.column("duration_ms")?.i64()?,
+        ) {
+            let Some(start) = start.map(StopId) else { continue };
+            match end.zip(duration) {
+                Some((end, duration)) => footpaths.entry(start).or_default().push((StopId(end), Duration::milliseconds(duration))),
+                None => _ = off_network.insert(start),
+            }
+        }
+        Self::new(footpaths, off_network, stops, config)
+    }
+}
+
+/// Ids and coordinates of the stops, as longitude and latitude
+pub(crate) fn stop_coords(stops: LazyFrame) -> PolarsResult<Vec<(StopId, Point<f64>)>> {
+    let frame = stops.select([col("stop_id"), col("lat"), col("lon")]).collect()?;
+    let coords = izip!(
+        frame.column("stop_id")?.u32()?,
+        frame.column("lat")?.f32()?,
+        frame.column("lon")?.f32()?,
+    )
+        .filter_map(|(id, lat, lon)| Some((StopId(id?), Point::new(lon? as f64, lat? as f64))))
+        .collect();
+    Ok(coords)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfers::osm::encode;
+    use std::io::Write;
+
+    #[test]
+    fn test_footpaths() {
+        // Stops 0 and 1 are on both sides of a river, which a footway crosses on a bridge 500 m
+        // further, stop 2 is far off any way. A motorway would be a shortcut.
+        let extract = encode::extract(
+            &[
+                (&[("highway", "footway")], &[1, 2, 3, 4]),
+                (&[("highway", "motorway")], &[1, 4]),
+            ],
+            &[(1, 48.0, 9.0), (2, 48.0045, 9.0), (3, 48.0045, 9.001), (4, 48.0, 9.001)],
+        );
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&extract).unwrap();
+        let stops = df!(
+            "stop_id" => [0u32, 1, 2],
+            "lat" => [48.0f32, 48.0, 48.1],
+            "lon" => [9.0f32, 9.001, 9.0],
+        ).unwrap().lazy();
+
+        let config = TransferConfig { penalty_seconds: 60, ..Default::default() };
+        let provider = FootpathTransferProvider::from_osm(file.path(), stops.clone(), &config).unwrap();
+        // Around 1075 m over the bridge instead of 75 m in a straight line
+        let duration = provider.duration(StopId(0), StopId(1)).unwrap();
+        let expected = config.walking_speed().time_to_travel_distance(1_075.0) + config.penalty();
+        assert!((duration - expected).abs() < Duration::seconds(5), "{duration}");
+        assert_eq!(provider.transfers_from(&StopId(0)), vec![StopId(1), StopId(2)]);
+        assert!(matches!(provider.duration(StopId(0), StopId(2)), Err(TransferError::OutOfReach)));
+
+        // A bridge longer than the walk
+        let config = TransferConfig { max_walking_minutes: 5, ..Default::default() };
+        let provider = FootpathTransferProvider::from_osm(file.path(), stops.clone(), &config).unwrap();
+        assert!(matches!(provider.duration(StopId(0), StopId(1)), Err(TransferError::OutOfReach)));
+
+        let dir = tempfile::tempdir().unwrap();
+        artifacts::write_table(dir.path(), "stops", stops.clone().collect().unwrap(), Compression::default()).unwrap();
+        provider.write(dir.path(), "footpaths", Compression::default()).unwrap();
+        let read = FootpathTransferProvider::read(dir.path(), "footpaths", stops, &config).unwrap();
+        assert_eq!(read.footpaths, provider.footpaths);
+        assert_eq!(read.off_network, provider.off_network);
+    }
+}
@@ -1,13 +1,49 @@
+#[cfg(feature = "preprocessing")]
+pub mod closure;
+pub mod composite;
+#[cfg(feature = "preprocessing")]
 pub mod fixed_time;
+#[cfg(feature = "preprocessing")]
 pub mod crow_fly;
+#[cfg(feature = "preprocessing")]
+pub mod external;
+#[cfg(feature = "preprocessing")]
+pub mod footpaths;
 pub mod noop;
+#[cfg(feature = "preprocessing")]
+pub mod osm;
+pub mod table;
+#[cfg(feature = "preprocessing")]
+pub mod timetable;
 
 use std::fmt;
 use std::fmt::Display;
+use std::sync::Arc;
 
+#[cfg(feature = "preprocessing")]
+use crate::algorithm::{PreprocessingInput, PreprocessingResult};
 use crate::journey::Leg;
+#[cfg(feature = "preprocessing")]
+use crate::transfers::closure::ClosedTransferProvider;
+#[cfg(feature = "preprocessing")]
+use crate::transfers::composite::CompositeTransferProvider;
+#[cfg(feature = "preprocessing")]
+use crate::transfers::crow_fly::CrowFlyTransferProvider;
+#[cfg(feature = "preprocessing")]
+use crate::transfers::footpaths::FootpathTransferProvider;
+#[cfg(feature = "preprocessing")]
+use crate::transfers::timetable::TimetableTransferProvider;
 use chrono::Duration;
+#[cfg(feature = "preprocessing")]
+use common::types::config::{TransferConfig, TransferSource};
 use common::types::StopId;
+#[cfg(feature = "preprocessing")]
+use common::util::metrics;
+use common::util::speed::Speed;
+#[cfg(feature = "preprocessing")]
+use log::debug;
+#[cfg(feature = "preprocessing")]
+use polars::prelude::{col, LazyFrame};
 
 pub trait TransferProvider {
     fn lower_bound_duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError>;
@@ -16,6 +52,117 @@ pub trait TransferProvider {
     // All transfers that are possible from the starting station. Must not include the station itself.
     fn transfers_from(&self, start: &StopId) -> Vec<StopId>;
     fn transfers_between(&self, start: StopId, end: StopId) -> Result<Vec<Leg>, TransferError>;
+
+    /// The same transfers by bike at `speed`. Providers that can't tell cycling from walking keep
+    /// walking.
+    fn by_bike(&self, _speed: Speed) -> Option<Box<dyn TransferProvider + Send + Sync>> {
+        None
+    }
+}
+
+pub type SharedTransferProvider = Arc<dyn TransferProvider + Send + Sync>;
+
+// Shared by several algorithms, e.g. the RAPTORs of all day types while computing transfer patterns
+impl<T: TransferProvider + ?Sized> TransferProvider for Arc<T> {
+    fn lower_bound_duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        self.as_ref().lower_bound_duration(start, end)
+    }
+
+    fn duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        self.as_ref().duration(start, end)
+    }
+
+    fn transfers_from(&self, start: &StopId) -> Vec<StopId> {
+        self.as_ref().transfers_from(start)
+    }
+
+    fn transfers_between(&self, start: StopId, end: StopId) -> Result<Vec<Leg>, TransferError> {
+        self.as_ref().transfers_between(start, end)
+    }
+
+    fn by_bike(&self, speed: Speed) -> Option<Box<dyn TransferProvider + Send + Sync>> {
+        self.as_ref().by_bike(speed)
+    }
+}
+
+/// Transfers between the stops of the input by the chain of `config`: the minimum transfer times of
+/// the timetable, walks requested from the router, along the ways of the OSM extract and in a
+/// straight line
+#[cfg(feature = "preprocessing")]
+pub fn walking(input: &PreprocessingInput, config: &TransferConfig) -> PreprocessingResult<Box<dyn TransferProvider + Send + Sync>> {
+    let stops = input.stops.clone();
+    from_chain(config, stops.clone(), |source| Ok(match source {
+        TransferSource::Timetable => TimetableTransferProvider::from_frame(input.transfers.clone())?
+            .map(|provider| Box::new(provider) as Box<dyn TransferProvider + Send + Sync>),
+        TransferSource::Router => match &config.router {
+            Some(router) => Some(Box::new(FootpathTransferProvider::from_router(router, stops.clone(), config)?)),
+            None => None,
+        },
+        TransferSource::OsmExtract => match &config.osm_extract {
+            Some(extract) => Some(Box::new(FootpathTransferProvider::from_osm(extract, stops.clone(), config)?)),
+            None => None,
+        },
+        TransferSource::CrowFly => Some(Box::new(CrowFlyTransferProvider::from_stops(stops.clone())?.with_config(config))),
+    }))
+}
+
+/// A [CompositeTransferProvider] of the sources of the chain of `config` that are configured, in
+/// their order, closed by [ClosedTransferProvider]. `provider` builds the one of a source, which
+/// may still be missing, e.g. if the timetable has no transfers.
+#[cfg(feature = "preprocessing")]
+pub fn from_chain(
+    config: &TransferConfig,
+    stops: LazyFrame,
+    provider: impl FnMut(TransferSource) -> PreprocessingResult<Option<Box<dyn TransferProvider + Send + Sync>>>,
+) -> PreprocessingResult<Box<dyn TransferProvider + Send + Sync>> {
+    Ok(match chain(config, provider)? {
+        (chained, false) => chained,
+        (chained, true) => Box::new(close(chained, config, stops)?),
+    })
+}
+
+/// The sources of the chain like [from_chain] before they are closed, and whether they have to be
+#[cfg(feature = "preprocessing")]
+pub fn chain(
+    config: &TransferConfig,
+    mut provider: impl FnMut(TransferSource) -> PreprocessingResult<Option<Box<dyn TransferProvider + Send + Sync>>>,
+) -> PreprocessingResult<(Box<dyn TransferProvider + Send + Sync>, bool)> {
+    let mut providers = vec![];
+    let mut sources = vec![];
+    for source in config.chain.iter().filter(|source| config.uses(**source)) {
+        if let Some(built) = provider(*source)? {
+            providers.push(built);
+            sources.push(*source);
+        }
+    }
+    // A single provider answers everything on its own
+    let chained = match providers.len() {
+        1 => providers.remove(0),
+        _ => Box::new(CompositeTransferProvider::new(providers)),
+    };
+    // Straight lines already obey the triangle inequality
+    Ok((chained, sources != [TransferSource::CrowFly]))
+}
+
+/// Closes the transfers of a chain between the stops, up to the longest walk of `config`
+#[cfg(feature = "preprocessing")]
+pub fn close(
+    chained: Box<dyn TransferProvider + Send + Sync>,
+    config: &TransferConfig,
+    stops: LazyFrame,
+) -> PreprocessingResult<ClosedTransferProvider> {
+    let stop_ids: Vec<StopId> = stops.select([col("stop_id")]).collect()?
+        .column("stop_id")?.u32()?
+        .into_iter().flatten().map(StopId)
+        .collect();
+    let (closed, stats) = ClosedTransferProvider::close(chained, &stop_ids, config.max_duration() + config.penalty());
+    debug!(
+        target: "preprocessing",
+        "Closing {} transfers added {} over other stops and shortened {}",
+        stats.num_direct, stats.num_added, stats.num_shortened,
+    );
+    metrics::record("closure_added_transfers", stats.num_added as f64);
+    Ok(closed)
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -37,28 +184,35 @@ impl Display for TransferError {
 #[cfg(test)]
 mod tests {
     use crate::transfers::crow_fly::CrowFlyTransferProvider;
-    use common::util::speed::MAX_WALKING_SPEED;
+    use common::types::config::TransferConfig;
+    use common::util::speed::{CYCLING_SPEED, MAX_WALKING_SPEED};
     use geo::Coord;
     use super::*;
 
     #[test]
     fn test_crow_fly_provider() {
+        // About 5217 km apart, far out of reach of walking 15 minutes
         let coord_a = Coord { x: 48.0, y: 9.0 };
         let coord_b = Coord { x: 10.0, y: 42.0 };
         let provider = CrowFlyTransferProvider::from(vec![coord_a, coord_b]);
 
+        assert!(provider.transfers_from(&StopId(0)).is_empty());
+        assert!(matches!(provider.transfers_between(StopId(0), StopId(1)), Err(TransferError::OutOfReach)));
+
+        // Walking for two months reaches the other stop
+        let provider = provider.with_config(&TransferConfig { max_walking_minutes: 60 * 24 * 60, ..Default::default() });
         let transfers_from_0 = provider.transfers_from(&StopId(0));
         assert!(transfers_from_0.contains(&StopId(1)));
         let transfers_from_1 = provider.transfers_from(&StopId(1));
         assert!(transfers_from_1.contains(&StopId(0)));
-        
+
         assert_eq!(
             provider.transfers_between(StopId(0), StopId(1)).unwrap(),
             vec![
                 Leg::Transfer {
                     start: StopId(0),
                     end: StopId(1),
-                    duration: MAX_WALKING_SPEED.time_to_travel_distance(5216815f32),
+                    duration: MAX_WALKING_SPEED.time_to_travel_distance(5216814.5f32),
                 }
             ]
         );
@@ -69,9 +223,53 @@ mod tests {
                 Leg::Transfer {
                     start: StopId(1),
                     end: StopId(0),
-                    duration: MAX_WALKING_SPEED.time_to_travel_distance(5216815f32),
+                    duration: MAX_WALKING_SPEED.time_to_travel_distance(5216814.5f32),
                 }
             ]
         )
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_by_bike() {
+        // About 2.5 km apart
+        let provider = CrowFlyTransferProvider::from(vec![Coord { x: 48.0, y: 9.0 }, Coord { x: 48.0, y: 9.0225 }]);
+        assert!(matches!(provider.duration(StopId(0), StopId(1)), Err(TransferError::OutOfReach)));
+
+        let by_bike = provider.by_bike(CYCLING_SPEED).unwrap();
+        let duration = by_bike.duration(StopId(0), StopId(1)).unwrap();
+        assert!((Duration::minutes(9)..Duration::minutes(11)).contains(&duration), "{duration}");
+    }
+
+    #[test]
+    fn test_transfers_within_reach() {
+        // A row of stops every 250 m, of which 15 minutes walking reach the next six or seven
+        let coords: Vec<_> = (0..40).map(|idx| Coord { x: 48.0, y: 9.0 + idx as f32 * 0.00225 }).collect();
+        let provider = CrowFlyTransferProvider::from(coords);
+
+        let transfers = provider.transfers_from(&StopId(20));
+        let reachable = (0..40).map(StopId)
+            .filter(|end| *end != StopId(20) && provider.duration(StopId(20), *end).is_ok())
+            .collect::<Vec<_>>();
+        assert!(reachable.iter().all(|end| transfers.contains(end)), "{transfers:?}");
+        assert!(transfers.len() <= reachable.len() + 2, "{transfers:?}");
+        assert!(!transfers.contains(&StopId(0)));
+
+        // Bikes reach further in the same time
+        assert!(provider.by_bike(CYCLING_SPEED).unwrap().transfers_from(&StopId(20)).len() > transfers.len());
+    }
+
+    #[test]
+    fn test_with_config() {
+        // About 2.5 km apart
+        let coords = vec![Coord { x: 48.0, y: 9.0 }, Coord { x: 48.0, y: 9.0225 }];
+        let config = TransferConfig { max_walking_minutes: 30, penalty_seconds: 60, ..Default::default() };
+        let provider = CrowFlyTransferProvider::from(coords).with_config(&config);
+        let duration = provider.duration(StopId(0), StopId(1)).unwrap();
+        assert!((Duration::minutes(22)..Duration::minutes(24)).contains(&duration), "{duration}");
+
+        let provider = provider.with_config(&TransferConfig { max_distance_meters: Some(2000.0), ..config });
+        assert!(matches!(provider.duration(StopId(0), StopId(1)), Err(TransferError::OutOfReach)));
+        // The distance only limits walks
+        assert!(provider.by_bike(CYCLING_SPEED).unwrap().duration(StopId(0), StopId(1)).is_ok());
+    }
+}
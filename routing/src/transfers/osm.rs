@@ -0,0 +1,303 @@
+//! Reads the ways that pedestrians may use and the sites of Park & Ride from an OpenStreetMap
+//! extract in the PBF format (https://wiki.openstreetmap.org/wiki/PBF_Format) with [osmpbf].
+//! Relations and the metadata of elements are skipped.
+
+use geo::{Distance, Haversine, Point};
+use hashbrown::{HashMap, HashSet};
+use ordered_float::OrderedFloat;
+use osmpbf::{Element, ElementReader};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::fmt::Display;
+use std::path::Path;
+
+// Ways with these values of `highway` are no place for pedestrians, the others are
+const NOT_WALKABLE: [&str; 8] = [
+    "motorway", "motorway_link", "trunk", "trunk_link", "construction", "proposed", "raceway", "bus_guideway",
+];
+
+/// The ways that pedestrians may use, as a graph of the nodes they pass
+#[derive(Debug, Default)]
+pub struct WalkNetwork {
+    // Longitude and latitude of every node
+    pub nodes: Vec<Point<f64>>,
+    // Neighbours of every node, with the distance to them in meters
+    edges: Vec<Vec<(usize, f32)>>,
+}
+
+impl WalkNetwork {
+    /// Reads the extract twice, first for the walkable ways and then for the nodes that they pass,
+    /// so that only those nodes are kept
+    pub fn read(path: &Path) -> Result<Self, OsmError> {
+        let mut ways = vec![];
+        ElementReader::from_path(path)?.for_each(|element| {
+            if let Element::Way(way) = element {
+                ways.extend(tagged_way(&way, is_walkable));
+            }
+        })?;
+
+        let needed: HashSet<i64> = ways.iter().flatten().copied().collect();
+        let coords = node_coords(path, &needed)?;
+
+        Ok(Self::from_ways(ways, &coords))
+    }
+
+    // Nodes that aren't in the extract, e.g. because the way was cut at its border, end the way
+    fn from_ways(ways: Vec<Vec<i64>>, coords: &HashMap<i64, Point<f64>>) -> Self {
+        let mut network = Self::default();
+        let mut idx_of_node = HashMap::new();
+        let mut idx = |network: &mut Self, node: i64| -> Option<usize> {
+            let coord = coords.get(&node)?;
+            Some(*idx_of_node.entry(node).or_insert_with(|| {
+                network.nodes.push(*coord);
+                network.edges.push(vec![]);
+                network.nodes.len() - 1
+            }))
+        };
+
+        for way in ways {
+            for pair in way.windows(2) {
+                let (Some(a), Some(b)) = (idx(&mut network, pair[0]), idx(&mut network, pair[1])) else { continue };
+                let distance = Haversine::distance(network.nodes[a], network.nodes[b]) as f32;
+                network.edges[a].push((b, distance));
+                network.edges[b].push((a, distance));
+            }
+        }
+        network
+    }
+
+    /// The distance in meters along the ways from a node to all nodes within `max_distance`
+    pub fn distances_from(&self, start: usize, max_distance: f32) -> HashMap<usize, f32> {
+        let mut distances = HashMap::from([(start, 0f32)]);
+        let mut queue = BinaryHeap::from([Reverse((OrderedFloat(0f32), start))]);
+
+        while let Some(Reverse((OrderedFloat(distance), node))) = queue.pop() {
+            // Nodes are queued again when they are reached on a shorter path
+            if distances.get(&node).is_some_and(|best| *best < distance) {
+                continue;
+            }
+            for (next, length) in &self.edges[node] {
+                let next_distance = distance + length;
+                if next_distance <= max_distance && distances.get(next).is_none_or(|best| next_distance < *best) {
+                    distances.insert(*next, next_distance);
+                    queue.push(Reverse((OrderedFloat(next_distance), *next)));
+                }
+            }
+        }
+        distances
+    }
+}
+
+/// Where cars can be parked to continue by public transport, i.e. nodes and ways tagged with
+/// `park_ride`. Ways, which are usually the area of the car park, are located at the average of
+/// their nodes.
+pub fn park_and_ride_sites(path: &Path) -> Result<Vec<Point<f64>>, OsmError> {
+    let (mut sites, mut ways) = (vec![], vec![]);
+    ElementReader::from_path(path)?.for_each(|element| match element {
+        Element::Node(node) => if is_park_and_ride(&node.tags().collect::<Vec<_>>()) {
+            sites.push(Point::new(node.lon(), node.lat()));
+        },
+        Element::DenseNode(node) => if is_park_and_ride(&node.tags().collect::<Vec<_>>()) {
+            sites.push(Point::new(node.lon(), node.lat()));
+        },
+        Element::Way(way) => ways.extend(tagged_way(&way, is_park_and_ride)),
+        Element::Relation(_) => {}
+    })?;
+
+    let needed: HashSet<i64> = ways.iter().flatten().copied().collect();
+    let coords = match needed.is_empty() {
+        true => HashMap::new(),
+        false => node_coords(path, &needed)?,
+    };
+
+    // Nodes outside of the extract are left out, ways without any are dropped. Areas end at their
+    // first node, which only counts once.
+    for way in ways {
+        let open = match way.as_slice() {
+            [first, .., last] if first == last => &way[..way.len() - 1],
+            open => open,
+        };
+        let nodes: Vec<Point<f64>> = open.iter().filter_map(|node| coords.get(node).copied()).collect();
+        if !nodes.is_empty() {
+            let sum = nodes.iter().fold(Point::new(0.0, 0.0), |sum, node| sum + *node);
+            sites.push(sum / nodes.len() as f64);
+        }
+    }
+    Ok(sites)
+}
+
+fn is_park_and_ride(tags: &[(&str, &str)]) -> bool {
+    tags.iter().any(|(key, val)| *key == "park_ride" && *val != "no")
+}
+
+// The longitude and latitude of the nodes of the extract that are needed
+fn node_coords(path: &Path, needed: &HashSet<i64>) -> Result<HashMap<i64, Point<f64>>, OsmError> {
+    let mut coords = HashMap::new();
+    ElementReader::from_path(path)?.for_each(|element| match element {
+        Element::Node(node) if needed.contains(&node.id()) => _ = coords.insert(node.id(), Point::new(node.lon(), node.lat())),
+        Element::DenseNode(node) if needed.contains(&node.id()) => _ = coords.insert(node.id(), Point::new(node.lon(), node.lat())),
+        _ => {}
+    })?;
+    Ok(coords)
+}
+
+// The nodes of the way if its tags are accepted
+fn tagged_way(way: &osmpbf::Way, accept: impl Fn(&[(&str, &str)]) -> bool) -> Option<Vec<i64>> {
+    accept(&way.tags().collect::<Vec<_>>()).then(|| way.refs().collect())
+}
+
+fn is_walkable(tags: &[(&str, &str)]) -> bool {
+    let tag = |key: &str| tags.iter().find(|(other, _)| *other == key).map(|(_, val)| *val);
+    match tag("foot") {
+        Some("no" | "private" | "use_sidepath") => return false,
+        Some("yes" | "designated" | "permissive") => return true,
+        _ => {}
+    }
+    if matches!(tag("access"), Some("no" | "private")) {
+        return false;
+    }
+    match tag("highway") {
+        Some(highway) => !NOT_WALKABLE.contains(&highway),
+        None => tag("railway") == Some("platform") || tag("public_transport") == Some("platform"),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum OsmError {
+    Pbf(#[from] osmpbf::Error),
+}
+
+impl Display for OsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OsmError::Pbf(err) => write!(f, "Invalid OSM extract: {}", err),
+        }
+    }
+}
+
+/// Writes extracts for tests, with a single uncompressed block
+#[cfg(test)]
+pub(crate) mod encode {
+    use crate::realtime::protobuf::encode::{bytes, int, packed, zigzag};
+
+    // Keys and values of the tags of a way or node
+    pub(crate) type Tags<'a> = &'a [(&'a str, &'a str)];
+    // The tags and nodes of a way
+    pub(crate) type Way<'a> = (Tags<'a>, &'a [i64]);
+
+    // Ways are given by their tags and nodes, nodes by their id, latitude and longitude
+    pub(crate) fn extract(ways: &[Way], nodes: &[(i64, f64, f64)]) -> Vec<u8> {
+        extract_with_node_tags(ways, nodes, &[])
+    }
+
+    // Like [extract], with tags of some of the nodes by their id
+    pub(crate) fn extract_with_node_tags(
+        ways: &[Way],
+        nodes: &[(i64, f64, f64)],
+        node_tags: &[(i64, Tags)],
+    ) -> Vec<u8> {
+        let mut strings = vec![String::new()];
+        let mut string = |value: &str| match strings.iter().position(|other| other == value) {
+            Some(idx) => idx as u64,
+            None => {
+                strings.push(value.to_string());
+                strings.len() as u64 - 1
+            }
+        };
+        let delta = |values: &mut dyn Iterator<Item = i64>| {
+            let mut last = 0;
+            values.map(|value| {
+                let delta = zigzag(value - last);
+                last = value;
+                delta
+            }).collect::<Vec<_>>()
+        };
+
+        let ways: Vec<u8> = ways.iter()
+            .enumerate()
+            // Ways need an id, which is otherwise of no interest
+            .flat_map(|(id, (tags, nodes))| bytes(3, &[
+                int(1, id as i64),
+                packed(2, &tags.iter().map(|(key, _)| string(key)).collect::<Vec<_>>()),
+                packed(3, &tags.iter().map(|(_, val)| string(val)).collect::<Vec<_>>()),
+                packed(8, &delta(&mut nodes.iter().copied())),
+            ].concat()))
+            .collect();
+        let keys_vals: Vec<u64> = nodes.iter()
+            .flat_map(|(id, _, _)| {
+                let tags = node_tags.iter().filter(|(node, _)| node == id).flat_map(|(_, tags)| tags.iter());
+                tags.flat_map(|(key, val)| [string(key), string(val)]).chain([0]).collect::<Vec<_>>()
+            })
+            .collect();
+        // The default granularity of 100 nanodegrees
+        let dense = bytes(2, &[
+            packed(1, &delta(&mut nodes.iter().map(|(id, _, _)| *id))),
+            packed(8, &delta(&mut nodes.iter().map(|(_, lat, _)| (lat * 1e7).round() as i64))),
+            packed(9, &delta(&mut nodes.iter().map(|(_, _, lon)| (lon * 1e7).round() as i64))),
+            if node_tags.is_empty() { vec![] } else { packed(10, &keys_vals) },
+        ].concat());
+
+        let string_table: Vec<u8> = strings.iter().flat_map(|value| bytes(1, value.as_bytes())).collect();
+        let block = [bytes(1, &string_table), bytes(2, &dense), bytes(2, &ways)].concat();
+        let blob = bytes(1, &block);
+        let header = [bytes(1, b"OSMData"), int(3, blob.len() as i64)].concat();
+        [(header.len() as u32).to_be_bytes().to_vec(), header, blob].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_read() {
+        // A footway over nodes 1, 2 and 3, a motorway from 3 to 4 and a path that pedestrians may
+        // not use from 1 to 5
+        let extract = encode::extract(
+            &[
+                (&[("highway", "footway")], &[1, 2, 3]),
+                (&[("highway", "motorway")], &[3, 4]),
+                (&[("highway", "path"), ("foot", "no")], &[1, 5]),
+            ],
+            &[(1, 48.0, 9.0), (2, 48.001, 9.0), (3, 48.002, 9.0), (4, 48.003, 9.0), (5, 47.999, 9.0)],
+        );
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&extract).unwrap();
+
+        let network = WalkNetwork::read(file.path()).unwrap();
+        assert_eq!(network.nodes.len(), 3);
+        assert!((network.nodes[2].y() - 48.002).abs() < 1e-6);
+
+        let distances = network.distances_from(0, 1_000.0);
+        assert!((distances[&2] - 222.4).abs() < 1.0, "{}", distances[&2]);
+        assert_eq!(network.distances_from(0, 150.0).len(), 2);
+    }
+
+    #[test]
+    fn test_park_and_ride_sites() {
+        // A car park of nodes 1 to 4 and a single node, next to a regular car park and a node
+        // that is explicitly no site
+        let extract = encode::extract_with_node_tags(
+            &[
+                (&[("amenity", "parking"), ("park_ride", "yes")], &[1, 2, 3, 4, 1]),
+                (&[("amenity", "parking")], &[5, 6, 7]),
+            ],
+            &[
+                (1, 48.0, 9.0), (2, 48.0, 9.002), (3, 48.002, 9.002), (4, 48.002, 9.0),
+                (5, 48.1, 9.0), (6, 48.1, 9.001), (7, 48.101, 9.0),
+                (8, 48.2, 9.0), (9, 48.3, 9.0),
+            ],
+            &[(8, &[("amenity", "parking"), ("park_ride", "bus")]), (9, &[("park_ride", "no")])],
+        );
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&extract).unwrap();
+
+        let sites = park_and_ride_sites(file.path()).unwrap();
+        assert_eq!(sites.len(), 2);
+        assert!((sites[0].y() - 48.2).abs() < 1e-6);
+        assert!((sites[1].y() - 48.001).abs() < 1e-6, "{:?}", sites[1]);
+        assert!((sites[1].x() - 9.001).abs() < 1e-6, "{:?}", sites[1]);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::journey::Leg;
+use crate::transfers::{TransferError, TransferProvider};
+use chrono::Duration;
+use common::types::StopId;
+use hashbrown::HashMap;
+
+/// Walks that another provider computed before, as a table of durations between stops. This is
+/// what queries walk when the provider itself isn't available, e.g. on a [crate::compact]
+/// timetable. Only walks are tabulated, so there is no bike variant.
+#[derive(Clone, Default)]
+pub struct TableTransferProvider {
+    // Walks from every stop, sorted by stop
+    walks: HashMap<StopId, Vec<(StopId, Duration)>>,
+}
+
+impl TransferProvider for TableTransferProvider {
+    fn lower_bound_duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        self.duration(start, end)
+    }
+
+    fn duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        let Some(walks) = self.walks.get(&start) else { return Err(TransferError::StopNotFound) };
+        walks.binary_search_by_key(&end, |(end, _)| *end)
+            .map(|idx| walks[idx].1)
+            .map_err(|_| TransferError::OutOfReach)
+    }
+
+    fn transfers_from(&self, start: &StopId) -> Vec<StopId> {
+        self.walks.get(start).into_iter().flatten().map(|(end, _)| *end).collect()
+    }
+
+    fn transfers_between(&self, start: StopId, end: StopId) -> Result<Vec<Leg>, TransferError> {
+        Ok(vec![
+            Leg::Transfer { start, end, duration: self.duration(start, end)? }
+        ])
+    }
+}
+
+impl TableTransferProvider {
+    /// The walks of `provider` from each of the stops to all stops it reaches
+    pub fn tabulate(provider: &dyn TransferProvider, stops: impl IntoIterator<Item = StopId>) -> Self {
+        let walks = stops.into_iter()
+            .map(|start| {
+                let walks = provider.transfers_from(&start).into_iter()
+                    .filter(|end| *end != start)
+                    .filter_map(|end| Some((end, provider.duration(start, end).ok()?)))
+                    .collect();
+                (start, walks)
+            })
+            .collect();
+        Self::from_walks(walks)
+    }
+
+    pub fn from_walks(mut walks: HashMap<StopId, Vec<(StopId, Duration)>>) -> Self {
+        walks.values_mut().for_each(|walks| walks.sort_unstable_by_key(|(end, _)| *end));
+        Self { walks }
+    }
+
+    /// Each walk as its start, end and duration
+    pub fn walks(&self) -> impl Iterator<Item = (StopId, StopId, Duration)> + '_ {
+        self.walks.iter()
+            .flat_map(|(start, walks)| walks.iter().map(|(end, duration)| (*start, *end, *duration)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfers::noop::NoOpTransferProvider;
+
+    #[test]
+    fn test_table_transfers() {
+        let walks = HashMap::from([(StopId(0), vec![(StopId(2), Duration::minutes(3)), (StopId(1), Duration::minutes(2))])]);
+        let provider = TableTransferProvider::from_walks(walks);
+        assert_eq!(provider.duration(StopId(0), StopId(1)).unwrap(), Duration::minutes(2));
+        assert!(matches!(provider.duration(StopId(0), StopId(3)), Err(TransferError::OutOfReach)));
+        assert!(matches!(provider.duration(StopId(1), StopId(0)), Err(TransferError::StopNotFound)));
+        assert_eq!(provider.transfers_from(&StopId(0)), vec![StopId(1), StopId(2)]);
+
+        let tabulated = TableTransferProvider::tabulate(&provider, [StopId(0), StopId(1)]);
+        assert_eq!(tabulated.transfers_from(&StopId(0)), provider.transfers_from(&StopId(0)));
+        assert_eq!(tabulated.walks().count(), 2);
+        assert_eq!(TableTransferProvider::tabulate(&NoOpTransferProvider, [StopId(0)]).walks().count(), 0);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::algorithm::PreprocessingResult;
+use crate::artifacts;
+use crate::journey::Leg;
+use crate::transfers::{TransferError, TransferProvider};
+use chrono::Duration;
+use common::types::config::Compression;
+use common::types::StopId;
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+// A curated transfer to a stop, without a duration if it is not possible
+type Curated = (StopId, Option<Duration>);
+
+/// Minimum times of changing between stops as the agencies give them in transfers.txt. It only
+/// knows the transfers of the timetable, so it is the first of a [CompositeTransferProvider] whose
+/// other providers estimate walks between all other stops. The times are the ones of walking, so
+/// there is no bike variant.
+///
+/// [CompositeTransferProvider]: crate::transfers::composite::CompositeTransferProvider
+#[derive(Clone)]
+pub struct TimetableTransferProvider {
+    // Curated transfers from every stop, sorted by stop
+    transfers: Arc<HashMap<StopId, Vec<Curated>>>,
+}
+
+impl TransferProvider for TimetableTransferProvider {
+    fn lower_bound_duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        self.duration(start, end)
+    }
+
+    // Transfers that are marked as not possible are out of reach, the ones without a time are
+    // not known
+    fn duration(&self, start: StopId, end: StopId) -> Result<Duration, TransferError> {
+        let Some(transfers) = self.transfers.get(&start) else { return Err(TransferError::StopNotFound) };
+        match transfers.binary_search_by_key(&end, |(end, _)| *end) {
+            Ok(idx) => transfers[idx].1.ok_or(TransferError::OutOfReach),
+            Err(_) => Err(TransferError::StopNotFound),
+        }
+    }
+
+    fn transfers_from(&self, start: &StopId) -> Vec<StopId> {
+        self.transfers.get(start).into_iter().flatten()
+            .filter(|(_, duration)| duration.is_some())
+            .map(|(end, _)| *end)
+            .collect()
+    }
+
+    fn transfers_between(&self, start: StopId, end: StopId) -> Result<Vec<Leg>, TransferError> {
+        Ok(vec![
+            Leg::Transfer { start, end, duration: self.duration(start, end)? }
+        ])
+    }
+}
+
+impl TimetableTransferProvider {
+    /// The transfers of a timetable, as in [crate::algorithm::PreprocessingInput::transfers], if
+    /// it has any
+    pub fn from_frame(transfers: LazyFrame) -> PreprocessingResult<Option<Self>> {
+        let frame = transfers.collect()?;
+        // Timetables of imports from before transfers.txt was read have no columns at all
+        if frame.width() == 0 || frame.height() == 0 {
+            return Ok(None);
+        }
+        let durations = frame.column("duration")?.cast(&DataType::Int64)?;
+        let rows = izip!(
+            frame.column("from_stop_id")?.u32()?,
+            frame.column("to_stop_id")?.u32()?,
+            durations.i64()?,
+        );
+
+        let mut transfers: HashMap<StopId, Vec<Curated>> = HashMap::new();
+        for (start, end, duration) in rows {
+            let (Some(start), Some(end)) = (start, end) else { continue };
+            transfers.entry(StopId(start)).or_default().push((StopId(end), duration.map(Duration::milliseconds)));
+        }
+        transfers.values_mut().for_each(|transfers| transfers.sort_unstable_by_key(|(end, _)| *end));
+
+        Ok(Some(Self { transfers: Arc::new(transfers) }))
+    }
+}
+
+/// Writes the transfers of a timetable next to the stops of [artifacts::write_stops]
+pub fn write(dir: &Path, transfers: LazyFrame, compression: Compression) -> PreprocessingResult<()> {
+    let frame = transfers.collect()?;
+    if frame.width() == 0 {
+        return Ok(());
+    }
+    let table = frame.lazy()
+        .select([
+            col("from_stop_id"),
+            col("to_stop_id"),
+            col("duration").cast(DataType::Int64).alias("duration_ms"),
+        ])
+        .collect()?;
+    Ok(artifacts::write_table(dir, "timetable_transfers", table, compression)?)
+}
+
+/// Reads the transfers that [write] wrote in the columns of
+/// [crate::algorithm::PreprocessingInput::transfers], which are empty if there are none
+pub fn read(dir: &Path) -> PreprocessingResult<LazyFrame> {
+    let Ok(table) = artifacts::read_table(dir, "timetable_transfers") else { return Ok(LazyFrame::default()) };
+    Ok(table.lazy().select([
+        col("from_stop_id"),
+        col("to_stop_id"),
+        col("duration_ms").cast(DataType::Duration(TimeUnit::Milliseconds)).alias("duration"),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timetable_transfers() {
+        let transfers = df!(
+            "from_stop_id" => [0u32, 0],
+            "to_stop_id"   => [1u32, 2],
+            "duration"     => [Some(120_000i64), None],
+        ).unwrap().lazy().with_column(col("duration").cast(DataType::Duration(TimeUnit::Milliseconds)));
+
+        let provider = TimetableTransferProvider::from_frame(transfers.clone()).unwrap().unwrap();
+        assert_eq!(provider.duration(StopId(0), StopId(1)).unwrap(), Duration::minutes(2));
+        assert!(matches!(provider.duration(StopId(0), StopId(2)), Err(TransferError::OutOfReach)));
+        assert!(matches!(provider.duration(StopId(1), StopId(0)), Err(TransferError::StopNotFound)));
+        assert_eq!(provider.transfers_from(&StopId(0)), vec![StopId(1)]);
+        assert!(TimetableTransferProvider::from_frame(LazyFrame::default()).unwrap().is_none());
+
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), transfers.clone(), Compression::default()).unwrap();
+        assert!(read(dir.path()).unwrap().collect().unwrap().equals_missing(&transfers.collect().unwrap()));
+        assert_eq!(read(tempfile::tempdir().unwrap().path()).unwrap().collect().unwrap().width(), 0);
+    }
+}
@@ -0,0 +1,90 @@
+use chrono::TimeDelta;
+use common::types::TripId;
+use hashbrown::HashMap;
+use itertools::izip;
+use polars::error::PolarsError;
+use polars::prelude::{col, LazyFrame};
+
+/// A single run of a trip. Frequency-based trips are expanded into one trip per run during
+/// simplification, all of them pointing to the same template.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TripRun {
+    pub template: TripId,
+    // Offset of this run to the times of the template. Exact to the millisecond.
+    pub offset: TimeDelta,
+}
+
+/// Lookup of the template and offset of every trip. Built from the `trip_runs` table of the
+/// preprocessing input.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TripRuns(HashMap<TripId, TripRun>);
+
+impl TripRuns {
+    pub fn from_trip_runs(trip_runs: LazyFrame) -> Result<Self, PolarsError> {
+        let trip_runs = trip_runs
+            .select([col("trip_id"), col("template_trip_id"), col("run_offset")])
+            .collect()?;
+
+        let trip_ids = trip_runs.column("trip_id")?.u32()?;
+        let template_ids = trip_runs.column("template_trip_id")?.u32()?;
+        let offsets = trip_runs.column("run_offset")?.duration()?;
+
+        let runs = izip!(trip_ids, template_ids, offsets.iter())
+            .map(|(trip_id, template_id, offset)| {
+                let trip_id = TripId(trip_id.expect("trip_id must not be null"));
+                let run = TripRun {
+                    template: TripId(template_id.expect("template_trip_id must not be null")),
+                    offset: TimeDelta::milliseconds(offset.unwrap_or_default()),
+                };
+                (trip_id, run)
+            })
+            .collect();
+
+        Ok(Self(runs))
+    }
+
+    /// Template and offset of a trip. Trips without an entry are their own template.
+    pub fn run_of(&self, trip: TripId) -> TripRun {
+        self.0.get(&trip).copied().unwrap_or(TripRun {
+            template: trip,
+            offset: TimeDelta::zero(),
+        })
+    }
+
+    /// Turns a time of the template into the actual time of the given run
+    pub fn time_of_run(&self, trip: TripId, template_time: TimeDelta) -> TimeDelta {
+        template_time + self.run_of(trip).offset
+    }
+
+    /// Whether two trips are runs of the same template
+    pub fn same_template(&self, a: TripId, b: TripId) -> bool {
+        self.run_of(a).template == self.run_of(b).template
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+    use polars::prelude::{AnyValue, IntoLazy, TimeUnit};
+
+    #[test]
+    fn test_trip_runs() {
+        let trip_runs = df!(
+            "trip_id"          => &[0u32, 1, 2, 3],
+            "template_trip_id" => &[0u32, 1, 1, 1],
+            "run_offset"       => &[0i64, 0, 600_000, 1_200_000].map(|ms| AnyValue::Duration(ms, TimeUnit::Milliseconds)),
+        ).unwrap().lazy();
+
+        let trip_runs = TripRuns::from_trip_runs(trip_runs).unwrap();
+
+        assert_eq!(trip_runs.run_of(TripId(3)), TripRun { template: TripId(1), offset: TimeDelta::minutes(20) });
+        assert_eq!(trip_runs.run_of(TripId(42)), TripRun { template: TripId(42), offset: TimeDelta::zero() });
+        assert_eq!(
+            trip_runs.time_of_run(TripId(2), TimeDelta::hours(8) + TimeDelta::milliseconds(1)),
+            TimeDelta::hours(8) + TimeDelta::minutes(10) + TimeDelta::milliseconds(1)
+        );
+        assert!(trip_runs.same_template(TripId(1), TripId(3)));
+        assert!(!trip_runs.same_template(TripId(0), TripId(1)));
+    }
+}
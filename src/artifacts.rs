@@ -0,0 +1,49 @@
+use crate::{DrinoError, Engine, ALGORITHM};
+use common::types::config::{Compression, RoutingMode, TransferConfig};
+use routing::algorithm::{FromDiskInit, PreprocessingInput, SaveToDisk};
+use routing::artifacts::{self, Manifest};
+use routing::timetable::TimetableLookup;
+use std::path::{Path, PathBuf};
+
+/// Writes the engine to `dir`, together with the stops, the config of walks between them and a
+/// copy of the simplified timetable in `tables`. The manifest of an earlier save is removed first
+/// and the new one comes last, so that a directory is only loaded once the engine was saved
+/// completely.
+pub fn save(
+    engine: &Engine,
+    input: PreprocessingInput,
+    tables: &Path,
+    transfers: &TransferConfig,
+    dir: &Path,
+    compression: Compression,
+) -> Result<(), DrinoError> {
+    Manifest::remove(dir)?;
+    artifacts::write_stops(dir, input.stops, input.transfers, transfers, compression)?;
+    let timetable = artifacts::write_timetable(dir, tables)?;
+    let mode = match engine {
+        Engine::Journeys(algorithm) => {
+            algorithm.save_to_disk(dir, compression)?;
+            RoutingMode::Journeys
+        }
+        Engine::TimetableLookup(lookup) => {
+            lookup.save_to_disk(dir, compression)?;
+            RoutingMode::TimetableLookup
+        }
+    };
+    Manifest::new(mode, timetable).write(dir)?;
+    Ok(())
+}
+
+/// Loads the engine that [save] wrote to `dir`
+pub fn load(dir: &Path) -> Result<Engine, DrinoError> {
+    let engine = match Manifest::read(dir)?.mode {
+        RoutingMode::Journeys => Engine::Journeys(ALGORITHM::load_from_disk(dir)?),
+        RoutingMode::TimetableLookup => Engine::TimetableLookup(TimetableLookup::load_from_disk(dir)?),
+    };
+    Ok(engine)
+}
+
+/// The directory of the simplified timetable that [save] copied to `dir`
+pub fn timetable(dir: &Path) -> Result<PathBuf, DrinoError> {
+    Ok(artifacts::read_timetable(dir)?)
+}
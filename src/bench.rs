@@ -0,0 +1,199 @@
+use crate::{artifacts, DrinoError, Engine};
+use chrono::{DateTime, TimeDelta, Utc};
+use common::types::config::{RoutingConfig, TransferConfig};
+use common::types::errors::ErrorCode;
+use common::types::StopId;
+use common::util::logging::NoProgress;
+use common::util::paths;
+use data_harvester::step5_simplify::read_simplified;
+use routing::algorithm::{JourneyPlanner, PreprocessInit, PreprocessingInput, QueryError};
+use routing::calendar::ServiceCalendar;
+use routing::direct_connections::DirectConnections;
+use routing::quality;
+use routing::raptor::RaptorAlgorithm;
+use routing::stp::ScalableTransferPatternsAlgorithm;
+use routing::tp::TransferPatternsAlgorithm;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Queries depart between 05:00 and 23:00 of the timetable day, when most trips run
+const FIRST_DEPARTURE_MINUTE: u64 = 5 * 60;
+const LAST_DEPARTURE_MINUTE: u64 = 23 * 60;
+
+/// Times the steps of preprocessing that the routing data is built from on the simplified
+/// timetable of the working directory, `iterations` times each, then answers `num_queries` random
+/// queries with the results of `preprocess` in `dir`, on the date on which the most services run.
+/// The same seed asks the same queries, so that the timings of two releases on the same data can
+/// be compared. Formatted for the terminal.
+pub fn bench(dir: &Path, num_queries: usize, iterations: usize, seed: u64) -> Result<String, DrinoError> {
+    let mut out = String::new();
+    let input = read_simplified(paths::work_dir())?;
+    let num_stops = input.original_ids.stops.len();
+    let date = ServiceCalendar::new(&input)?.busiest_date().unwrap_or_default();
+
+    writeln!(out, "Preprocessing ({} runs each)", iterations).unwrap();
+    for (name, timings) in preprocessing_benchmarks(&input, iterations)? {
+        writeln!(out, "  {:<22} {}", name, timings.summary()).unwrap();
+    }
+
+    let engine = artifacts::load(dir)?;
+    let queries = random_queries(num_stops, num_queries, seed);
+    let mut timings = Timings::default();
+    let mut num_journeys = 0;
+    // Errors other than missing journeys don't end the benchmark, e.g. random stops that an
+    // algorithm has no patterns of
+    let mut errors: HashMap<ErrorCode, usize> = HashMap::new();
+    let start = Instant::now();
+    for (from, to, departure) in queries {
+        let query_start = Instant::now();
+        let found = match &engine {
+            Engine::Journeys(algorithm) => algorithm.query_ea(from, to, departure).map(|_| true),
+            Engine::TimetableLookup(lookup) => lookup.direct_connections(from, to, date, departure, 1).map(|journeys| !journeys.is_empty()),
+        };
+        timings.0.push(query_start.elapsed());
+        match found {
+            Ok(found) => num_journeys += found as usize,
+            // Random pairs of stops are often not connected on the timetable day
+            Err(QueryError::NoRouteFound) => {}
+            Err(err) => *errors.entry(err.code()).or_default() += 1,
+        }
+    }
+    let elapsed = start.elapsed();
+
+    writeln!(out, "Queries ({} between random stops, seed {}, {} with a journey)", num_queries, seed, num_journeys).unwrap();
+    writeln!(out, "  {:<22} {}", "latency", timings.summary()).unwrap();
+    let seconds = elapsed.as_secs_f64();
+    let throughput = if seconds > 0.0 { num_queries as f64 / seconds } else { 0.0 };
+    writeln!(out, "  {:<22} {:.1} queries/s on a single thread", "throughput", throughput).unwrap();
+    if !errors.is_empty() {
+        let mut errors: Vec<_> = errors.into_iter().collect();
+        errors.sort_by_key(|(code, count)| (std::cmp::Reverse(*count), code.title()));
+        let errors: Vec<String> = errors.iter().map(|(code, count)| format!("{} {}", count, code)).collect();
+        writeln!(out, "  {:<22} {}", "errors", errors.join(", ")).unwrap();
+    }
+    Ok(out)
+}
+
+// The steps that every routing mode runs and the transfer patterns that journeys are planned
+// with, on the same input and with the default config, so that the timings don't depend on it
+fn preprocessing_benchmarks(input: &PreprocessingInput, iterations: usize) -> Result<Vec<(&'static str, Timings)>, DrinoError> {
+    let direct_connections = DirectConnections::try_from(input.clone())?;
+    let config = RoutingConfig::default();
+
+    Ok(vec![
+        ("direct connections", time(iterations, || {
+            DirectConnections::try_from(input.clone())?;
+            Ok(())
+        })?),
+        ("raptor with walks", time(iterations, || {
+            RaptorAlgorithm::preprocess_with_transfers(input.clone(), direct_connections.clone(), &TransferConfig::default())?;
+            Ok(())
+        })?),
+        ("transfer patterns", time(iterations, || {
+            TransferPatternsAlgorithm::preprocess(input.clone(), &config, false, &NoProgress)?;
+            Ok(())
+        })?),
+        ("scalable tp", time(iterations, || {
+            ScalableTransferPatternsAlgorithm::preprocess(input.clone(), &config, false, &NoProgress)?;
+            Ok(())
+        })?),
+        ("day types", time(iterations, || {
+            quality::trips_per_day(input)?;
+            Ok(())
+        })?),
+    ])
+}
+
+// How long each of the `iterations` runs of `step` took
+fn time(iterations: usize, mut step: impl FnMut() -> Result<(), DrinoError>) -> Result<Timings, DrinoError> {
+    let mut timings = Timings::default();
+    for _ in 0..iterations {
+        let start = Instant::now();
+        step()?;
+        timings.0.push(start.elapsed());
+    }
+    Ok(timings)
+}
+
+// Start, target and departure of the queries, the same ones for the same seed and stops
+fn random_queries(num_stops: usize, num_queries: usize, seed: u64) -> Vec<(StopId, StopId, DateTime<Utc>)> {
+    if num_stops == 0 {
+        return vec![];
+    }
+    let mut random = SplitMix64(seed);
+    (0..num_queries)
+        .map(|_| {
+            let from = StopId(random.below(num_stops as u64) as u32);
+            let to = StopId(random.below(num_stops as u64) as u32);
+            let minute = FIRST_DEPARTURE_MINUTE + random.below(LAST_DEPARTURE_MINUTE - FIRST_DEPARTURE_MINUTE);
+            (from, to, DateTime::<Utc>::UNIX_EPOCH + TimeDelta::minutes(minute as i64))
+        })
+        .collect()
+}
+
+// Small generator of random numbers whose sequence is fixed by the seed, across platforms and
+// releases. See https://prng.di.unimi.it/splitmix64.c
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Slightly biased towards small numbers for bounds that don't divide 2^64, which doesn't
+    // matter for picking stops
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+#[derive(Default)]
+struct Timings(Vec<Duration>);
+
+impl Timings {
+    // The duration that the share `p` (from 0 to 1) of the runs took at most, by the nearest rank
+    fn percentile(&self, p: f64) -> Duration {
+        let mut sorted = self.0.clone();
+        sorted.sort();
+        match sorted.len() {
+            0 => Duration::ZERO,
+            len => sorted[((p * len as f64).ceil() as usize).clamp(1, len) - 1],
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "p50 {:>10.3?}  p90 {:>10.3?}  p99 {:>10.3?}  max {:>10.3?}",
+            self.percentile(0.5), self.percentile(0.9), self.percentile(0.99), self.percentile(1.0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_queries() {
+        let queries = random_queries(10, 100, 42);
+        assert_eq!(queries, random_queries(10, 100, 42));
+        assert_ne!(queries, random_queries(10, 100, 43));
+        assert!(queries.iter().all(|(from, to, _)| from.0 < 10 && to.0 < 10));
+        assert!(random_queries(0, 100, 42).is_empty());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let timings = Timings((1..=100).rev().map(Duration::from_millis).collect());
+        assert_eq!(timings.percentile(0.5), Duration::from_millis(50));
+        assert_eq!(timings.percentile(0.99), Duration::from_millis(99));
+        assert_eq!(timings.percentile(1.0), Duration::from_millis(100));
+        assert_eq!(Timings::default().percentile(0.5), Duration::ZERO);
+    }
+}
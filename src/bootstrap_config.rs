@@ -1,22 +1,322 @@
-use log::LevelFilter;
-use clap::Parser;
+use tracing::level_filters::LevelFilter;
+use clap::{Args, Parser};
+use common::util::paths;
+use common::util::speed::CYCLING_SPEED;
+use chrono::{NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+use routing::park_and_ride::AccessMode;
+use std::path::PathBuf;
 
+/// Options shared by all commands. They can be given before or after the command.
 #[derive(Parser, Clone)]
 #[command(version, about)]
 pub struct BootstrapConfig {
-    #[clap(short('c'), long("config"), env("DRINO_CONFIG"), default_value_os = "config.yaml")]
+    /// Config file in YAML (.yml, .yaml), JSON (.json) or TOML (.toml), by its extension
+    #[clap(short('c'), long("config"), env("DRINO_CONFIG"), default_value_os = "config.yaml", global = true)]
     pub config_file: String,
-    #[clap(short('l'), long("log-level"), env("DRINO_LOG_LEVEL"), default_value_t, value_enum)]
+    #[clap(short('l'), long("log-level"), env("DRINO_LOG_LEVEL"), default_value_t, value_enum, global = true)]
     pub log_level: LogLevel,
+    /// Directory for datasets, temporary files and preprocessing results. Defaults to the data
+    /// directory of the platform (e.g. ~/.local/share/drino on Linux).
+    #[clap(short('w'), long("work-dir"), env("DRINO_WORK_DIR"), global = true)]
+    pub work_dir: Option<PathBuf>,
+    /// Records how long the steps of the run took, per dataset, as folded stacks to this file,
+    /// e.g. for `inferno-flamegraph`
+    #[clap(long("flamegraph"), env("DRINO_FLAMEGRAPH"), global = true)]
+    pub flamegraph: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+/// Tasks of drino
+#[derive(clap::Subcommand, Clone)]
+pub enum Command {
+    /// Preprocesses the datasets of the config and writes the results to a directory, without
+    /// serving them
+    Preprocess {
+        /// Directory for the artifacts, e.g. on a volume that is shared with the serving instances
+        #[clap(short('o'), long("out"))]
+        out: PathBuf,
+        #[command(flatten)]
+        validation: ValidationArgs,
+        /// Only checks the config, asks the servers of the datasets for their sizes and prints the
+        /// steps that would run, e.g. to try the config of a new deployment
+        #[clap(long("dry-run"))]
+        dry_run: bool,
+        /// Continues after the last step that an earlier run with the same config completed, e.g.
+        /// after it crashed while computing transfer patterns, instead of starting with the download
+        #[clap(long("resume"), conflicts_with("dry_run"))]
+        resume: bool,
+    },
+    /// Fetches, imports and validates the datasets of the config and writes their validation
+    /// reports, without merging or preprocessing them
+    Validate {
+        #[command(flatten)]
+        validation: ValidationArgs,
+    },
+    /// Works with the config file without running anything
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Serves routing requests over HTTP, see `--bind`. With `--artifacts`, the results of an
+    /// earlier `preprocess` are loaded, otherwise the datasets of the config are preprocessed first.
+    Serve {
+        #[clap(long("artifacts"), env("DRINO_ARTIFACTS"))]
+        artifacts: Option<PathBuf>,
+        #[command(flatten)]
+        server: ServerArgs,
+        #[command(flatten)]
+        validation: ValidationArgs,
+    },
+    /// Copies the parts of the preprocessed data in the working directory that are relevant to a
+    /// region into a new working directory
+    CropPreprocessed {
+        /// GeoJSON file with the polygons of the region
+        #[clap(long("polygon"))]
+        polygon: PathBuf,
+        /// Working directory of the cropped instance
+        #[clap(short('o'), long("out"))]
+        out: PathBuf,
+    },
+    /// Finds the journey with the earliest arrival between two stops in the preprocessed data of
+    /// the working directory
+    Query {
+        /// Name or id of the start, e.g. "Hauptbahnhof" or "vvs:de:08111:6118"
+        #[clap(long("from"))]
+        from: String,
+        /// Name or id of the target
+        #[clap(long("to"))]
+        to: String,
+        /// Earliest departure in the local time of the timetable, e.g. 2024-05-01T08:00:00
+        #[clap(long("at"))]
+        at: NaiveDateTime,
+        /// Routes that are treated as not running, to preview the impact of a closure, e.g.
+        /// "vvs:20-1". Can be given multiple times.
+        #[clap(long("suspend-route"))]
+        suspended_routes: Vec<String>,
+        // Boxed, since the options are much larger than the other commands
+        #[command(flatten)]
+        options: Box<JourneyOptions>,
+        #[command(flatten)]
+        realtime: RealtimeArgs,
+        /// How the journey is printed: as text, or as GeoJSON with a feature for every leg
+        #[clap(long("format"), default_value_t, value_enum)]
+        format: OutputFormat,
+    },
+    /// Writes the preprocessed data in other formats
+    Export {
+        #[command(subcommand)]
+        export: ExportCommand,
+    },
+    /// Prints the transfer patterns that a query between two stops is stitched from, e.g. to find
+    /// out why it returns a strange journey
+    Patterns {
+        /// Directory of the results of `preprocess`
+        #[clap(long("artifacts"), env("DRINO_ARTIFACTS"))]
+        artifacts: PathBuf,
+        /// Name or id of the start, like in `query`
+        #[clap(long("from"))]
+        from: String,
+        /// Name or id of the target
+        #[clap(long("to"))]
+        to: String,
+    },
+    /// Times the steps of preprocessing on the timetable of the working directory and random
+    /// queries on the results of `preprocess`, so that releases can be compared on the same data
+    Bench {
+        /// Directory of the results of `preprocess`, which have to be preprocessed from the
+        /// working directory
+        #[clap(long("artifacts"), env("DRINO_ARTIFACTS"))]
+        artifacts: PathBuf,
+        /// Number of queries between random stops
+        #[clap(long("queries"), default_value_t = 1000)]
+        queries: usize,
+        /// How often every step of preprocessing is timed
+        #[clap(long("iterations"), default_value_t = 5)]
+        iterations: usize,
+        /// Picks the stops and departures of the queries, the same seed asks the same queries
+        #[clap(long("seed"), default_value_t = 0)]
+        seed: u64,
+    },
+}
+
+/// Tasks of `drino config`
+#[derive(clap::Subcommand, Clone)]
+pub enum ConfigCommand {
+    /// Reads the config like the other commands and reports all problems, e.g. unknown keys or
+    /// dataset groups that don't exist
+    Check,
+}
+
+/// Formats that `drino export` writes
+#[derive(clap::Subcommand, Clone)]
+pub enum ExportCommand {
+    /// Writes the timetable of a day in the preprocessed data of the working directory as a single
+    /// compact file, which RAPTOR loads without polars, e.g. in a browser
+    Compact {
+        /// Day of the timetable, e.g. 2024-05-01
+        #[clap(long("date"))]
+        date: NaiveDate,
+        /// File of the timetable, gzipped JSON
+        #[clap(short('o'), long("out"))]
+        out: PathBuf,
+    },
+    /// Writes the stops and lines of the preprocessed data in the working directory as GeoJSON,
+    /// e.g. to check the network on a map
+    Network {
+        /// File of the network
+        #[clap(short('o'), long("out"))]
+        out: PathBuf,
+    },
+    /// Writes the transfer patterns of the results of `preprocess` with the ids and names of their
+    /// stops in the working directory, e.g. to look for patterns that shouldn't exist
+    Patterns {
+        /// Directory of the results of `preprocess`
+        #[clap(long("artifacts"), env("DRINO_ARTIFACTS"))]
+        artifacts: PathBuf,
+        /// File of the patterns
+        #[clap(short('o'), long("out"))]
+        out: PathBuf,
+        #[clap(long("format"), default_value_t, value_enum)]
+        format: TableFormat,
+    },
+    /// Writes the merged and simplified timetable in the working directory as a GTFS zip, e.g. to
+    /// feed it into other tools
+    Gtfs {
+        /// File of the feed
+        #[clap(short('o'), long("out"))]
+        out: PathBuf,
+    },
+}
+
+/// Addresses and time zone of `drino serve`
+#[derive(Args, Clone)]
+pub struct ServerArgs {
+    /// Address that the query server listens on
+    #[clap(long("bind"), env("DRINO_BIND"), default_value = "127.0.0.1:8080")]
+    pub bind: String,
+    /// Address that the gRPC service listens on, which isn't served without it
+    #[clap(long("grpc-bind"), env("DRINO_GRPC_BIND"))]
+    pub grpc_bind: Option<String>,
+    /// Time zone of the timetable, which the OTP API, the gRPC service and the trip updates of
+    /// followed journeys convert their absolute times from, e.g. "Europe/Berlin"
+    #[clap(long("timezone"), env("DRINO_TIMEZONE"), default_value = "UTC")]
+    pub timezone: Tz,
+}
+
+/// Reports of the commands that validate the datasets of the config
+#[derive(Args, Clone)]
+pub struct ValidationArgs {
+    /// Additionally write the validation reports of datasets as HTML
+    #[clap(long("html-validation-report"), env("DRINO_HTML_VALIDATION_REPORT"))]
+    pub html_validation_report: bool,
+}
+
+/// Needs of the traveller that restrict the journeys of a query
+#[derive(Args, Clone)]
+pub struct JourneyOptions {
+    /// Only rides trips and uses stops that are known to be step-free. If there is no such
+    /// journey, one that may require assistance is shown with a warning.
+    #[clap(long("wheelchair"))]
+    pub wheelchair: bool,
+    /// Takes a bike along, so only trips that allow bikes are boarded and transfers are cycled
+    #[clap(long("bike"))]
+    pub bike: bool,
+    /// Cycling speed in km/h for transfers with a bike
+    #[clap(long("cycling-speed"), default_value_t = CYCLING_SPEED.0)]
+    pub cycling_speed: f64,
+    /// Changes between rides that a journey has at most
+    #[clap(long("max-transfers"))]
+    pub max_transfers: Option<usize>,
+    /// Stops that are neither boarded, alighted nor walked to, given like the start. Can be given
+    /// multiple times.
+    #[clap(long("avoid-stop"))]
+    pub avoided_stops: Vec<String>,
+    /// Routes whose trips are not ridden, given like the suspended ones. Unlike suspended routes,
+    /// the journey isn't compared to one with them. Can be given multiple times.
+    #[clap(long("avoid-route"))]
+    pub avoided_routes: Vec<String>,
+    /// Agencies whose trips are not ridden, by their id prefixed with the dataset id, e.g.
+    /// "vvs:1". Can be given multiple times.
+    #[clap(long("avoid-agency"))]
+    pub avoided_agencies: Vec<String>,
+    /// Minutes that are left at least between arriving at a stop and boarding another trip
+    #[clap(long("min-transfer-buffer"), default_value_t = 0)]
+    pub min_transfer_buffer: u32,
+    /// How the journey gets to the first ride: walk, or bike or car to a P+R stop of the config
+    #[clap(long("access-mode"), default_value = "walk")]
+    pub access_mode: AccessMode,
+    /// How the journey gets from the last ride to the target, like the access mode
+    #[clap(long("egress-mode"), default_value = "walk")]
+    pub egress_mode: AccessMode,
+}
+
+impl JourneyOptions {
+    /// Whether the journey drives or cycles to or from a P+R stop, which needs the config
+    pub fn uses_park_and_ride(&self) -> bool {
+        self.access_mode != AccessMode::Walk || self.egress_mode != AccessMode::Walk
+    }
+}
+
+/// GTFS-RT feeds that a query takes into account
+#[derive(Args, Clone)]
+pub struct RealtimeArgs {
+    /// Trip updates of a dataset, whose delays and cancellations are applied before the search,
+    /// e.g. "vvs=https://example.org/trip-updates.pb" or "vvs=trip-updates.pb". Can be given
+    /// multiple times.
+    #[clap(long("trip-updates"), value_parser = parse_feed)]
+    pub trip_updates: Vec<(String, String)>,
+    /// Service alerts of a dataset, which are shown below the legs they affect. Given like the trip
+    /// updates.
+    #[clap(long("alerts"), value_parser = parse_feed)]
+    pub alerts: Vec<(String, String)>,
+    /// Vehicle positions of a dataset, which are shown below the rides of the vehicles. Given like
+    /// the trip updates.
+    #[clap(long("vehicle-positions"), value_parser = parse_feed)]
+    pub vehicle_positions: Vec<(String, String)>,
+    /// Time zone of the timetable, which the absolute times of the feeds are converted to, e.g.
+    /// "Europe/Berlin"
+    #[clap(long("timezone"), default_value = "UTC")]
+    pub timezone: Tz,
+    /// Preferred language of the texts of alerts, e.g. "de"
+    #[clap(long("language"))]
+    pub language: Option<String>,
+}
+
+// Dataset id and source of a GTFS-RT feed, given as "<dataset>=<url or path>"
+fn parse_feed(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((dataset_id, src)) if !dataset_id.is_empty() && !src.is_empty() => Ok((dataset_id.to_string(), src.to_string())),
+        _ => Err("expected <dataset>=<url or path>".to_string()),
+    }
 }
 
 impl BootstrapConfig {
     pub fn read() -> Self {
         BootstrapConfig::parse()
     }
+
+    pub fn work_dir(&self) -> PathBuf {
+        self.work_dir.clone().unwrap_or_else(paths::default_work_dir)
+    }
 }
 
 
+#[derive(clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Geojson,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TableFormat {
+    #[default]
+    Parquet,
+    Json,
+}
+
 #[derive(clap::ValueEnum, Clone, Default)]
 pub enum LogLevel {
     Off,
@@ -31,12 +331,12 @@ pub enum LogLevel {
 impl From<LogLevel> for LevelFilter {
     fn from(value: LogLevel) -> Self {
         match value {
-            LogLevel::Off => Self::Off,
-            LogLevel::Error => Self::Error,
-            LogLevel::Warn => Self::Warn,
-            LogLevel::Info => Self::Info,
-            LogLevel::Debug => Self::Debug,
-            LogLevel::Trace => Self::Trace,
+            LogLevel::Off => Self::OFF,
+            LogLevel::Error => Self::ERROR,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Trace => Self::TRACE,
         }
     }
 }
\ No newline at end of file
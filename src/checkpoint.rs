@@ -0,0 +1,104 @@
+use crate::DrinoError;
+use common::util::checkpoint;
+use data_harvester::checkpoint::CheckpointError;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Steps of preprocessing whose results are kept, in the order they run. Transfer patterns are
+/// kept per cluster by the algorithm itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Step {
+    Import,
+    Merge,
+    Simplify,
+}
+
+impl Step {
+    fn name(&self) -> &'static str {
+        match self {
+            Step::Import => "import",
+            Step::Merge => "merge",
+            Step::Simplify => "simplify",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    // The settings of the run, a run with other settings can't reuse its results
+    settings: serde_json::Value,
+    completed: Vec<Step>,
+}
+
+/// The completed steps of the current run, kept in [checkpoint::dir]. Without checkpoints, e.g.
+/// when preprocessing before serving, nothing is written and no step is completed.
+pub(crate) struct Checkpoints {
+    dir: Option<PathBuf>,
+    manifest: Manifest,
+}
+
+impl Checkpoints {
+    /// Continues the checkpoints of an earlier run with the same settings if `resume` is set,
+    /// otherwise removes them
+    pub(crate) fn open(settings: serde_json::Value, resume: bool) -> Result<Self, DrinoError> {
+        let Some(dir) = checkpoint::dir() else {
+            return Ok(Self { dir: None, manifest: Manifest::default() });
+        };
+
+        let earlier = match (resume, std::fs::read(dir.join("manifest.json"))) {
+            (true, Ok(manifest)) => Some(serde_json::from_slice::<Manifest>(&manifest).map_err(CheckpointError::from)?),
+            (true, Err(_)) => {
+                info!(target: "preprocessing", "No earlier run to resume, starting from the beginning");
+                None
+            }
+            (false, _) => None,
+        };
+        let manifest = match earlier {
+            Some(manifest) if manifest.settings == settings => {
+                let steps: Vec<&str> = manifest.completed.iter().map(Step::name).collect();
+                info!(target: "preprocessing", "Resuming after the completed steps: {}", steps.join(", "));
+                manifest
+            }
+            earlier => {
+                if earlier.is_some() {
+                    warn!(target: "preprocessing", "The config changed since the earlier run, starting from the beginning");
+                }
+                if dir.exists() {
+                    std::fs::remove_dir_all(&dir)?;
+                }
+                Manifest { settings, completed: vec![] }
+            }
+        };
+
+        Ok(Self { dir: Some(dir), manifest })
+    }
+
+    pub(crate) fn completed(&self, step: Step) -> bool {
+        self.manifest.completed.contains(&step)
+    }
+
+    /// Directory for the results of a step, if checkpoints are kept
+    pub(crate) fn dir(&self, step: Step) -> Option<PathBuf> {
+        self.dir.as_ref().map(|dir| dir.join(step.name()))
+    }
+
+    /// Marks a step as completed, once its results are written
+    pub(crate) fn complete(&mut self, step: Step) -> Result<(), DrinoError> {
+        let Some(dir) = &self.dir else { return Ok(()) };
+        self.manifest.completed.push(step);
+        std::fs::create_dir_all(dir)?;
+        let manifest = serde_json::to_vec_pretty(&self.manifest).map_err(CheckpointError::from)?;
+        std::fs::write(dir.join("manifest.json"), manifest)?;
+        Ok(())
+    }
+
+    /// Removes the checkpoints once the run succeeded, there is nothing left to resume
+    pub(crate) fn remove(self) -> Result<(), DrinoError> {
+        match self.dir {
+            Some(dir) if dir.exists() => Ok(std::fs::remove_dir_all(dir)?),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use crate::query::stop_names;
+use crate::DrinoError;
+use chrono::NaiveDate;
+use common::types::StopId;
+use common::util::paths;
+use data_harvester::step5_simplify::read_simplified;
+use polars::prelude::{col, DataType};
+use routing::compact::{CompactStop, CompactTimetable};
+use routing::direct_connections::DirectConnections;
+use routing::raptor::RaptorAlgorithm;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Writes the timetable of `date` in the preprocessed data of the working directory as a
+/// [CompactTimetable] to `out`, with the walks of `drino query`. Returns the number of stops.
+pub fn export(date: NaiveDate, out: &Path) -> Result<usize, DrinoError> {
+    let input = read_simplified(paths::work_dir())?.running_on(date)?;
+    let names = stop_names()?;
+    let stops = input.stops.clone().select([col("stop_id"), col("lat").cast(DataType::Float64), col("lon").cast(DataType::Float64)]).collect()?;
+    let stops: Vec<CompactStop> = stops.column("stop_id")?.u32()?.into_no_null_iter()
+        .zip(stops.column("lat")?.f64()?.into_no_null_iter())
+        .zip(stops.column("lon")?.f64()?.into_no_null_iter())
+        .map(|((id, lat), lon)| CompactStop {
+            id,
+            original_id: input.original_ids.stop(StopId(id)).unwrap_or_default().to_string(),
+            name: names.get(id as usize).cloned().unwrap_or_default(),
+            lat,
+            lon,
+        })
+        .collect();
+
+    let raptor = RaptorAlgorithm::preprocess(input.clone(), DirectConnections::try_from(input)?)?;
+    let num_stops = stops.len();
+    CompactTimetable::new(&raptor, stops).write(BufWriter::new(File::create(out)?))?;
+    Ok(num_stops)
+}
@@ -1,60 +1,375 @@
 use std::fmt::Display;
-use common::types::config::Config;
+use common::types::config::{Config, ConfigHeader, Settings};
 use log::{debug, info};
-use std::fs::File;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use crate::bootstrap_config::BootstrapConfig;
 
+/// Key of the files that a config is layered on, see [layer]
+const INCLUDE: &str = "include";
+
 pub(super) fn load_config(bootstrap_config: BootstrapConfig) -> Result<Config, ConfigError> {
     let path: &Path = &Path::new(&bootstrap_config.config_file);
-    
-    let config_file = File::open(path)?;
-    let config_extension = path.extension();
-    
-    if let Some(extension) = config_extension {
-        let config: Config = match extension.to_str() { 
-            Some("yml") | Some("yaml") => {
-                serde_yml::from_reader(config_file)?
-            },
-            Some("json") => {
-                serde_json::from_reader(config_file)?
-            },
-            _ => {
-                return Err(ConfigError::UnknownFileExtension())
+
+    let config = read_config(path)?;
+
+    if let Some(option) = config.unsupported_options().into_iter().next() {
+        return Err(ConfigError::RequiresVersion2(option));
+    }
+
+    info!(target: "main", "Config read successfully from {path:?}");
+    debug!(target: "main", "Using config: {:?}", config);
+
+    Ok(config)
+}
+
+/// Reads the config at `path`. Files without includes are parsed right away, so that their
+/// diagnostics have a line. With includes, the layered files only know the path of a field.
+fn read_config(path: &Path) -> Result<Config, ConfigError> {
+    let format = ConfigFormat::of(path)?;
+    let text = fs::read_to_string(path)?;
+    if format.read_tree(&text)?.get(INCLUDE).is_none() {
+        return format.parse(&text);
+    }
+
+    let tree = read_layers(path, &mut vec![])?;
+    parse(ConfigHeader::deserialize(&tree), tree, |_| None)
+}
+
+/// Reads the file at `path` layered over the files it includes, in the order they are listed.
+/// Included paths are relative to the including file and may include files themselves. `stack`
+/// holds the files that are being read, to detect cycles.
+fn read_layers(path: &Path, stack: &mut Vec<PathBuf>) -> Result<Value, ConfigError> {
+    let in_file = |err: ConfigError| ConfigError::InFile { file: path.to_path_buf(), err: Box::new(err) };
+
+    let canonical = path.canonicalize().map_err(|err| in_file(err.into()))?;
+    if stack.contains(&canonical) {
+        return Err(ConfigError::IncludeCycle(path.to_path_buf()));
+    }
+
+    let text = fs::read_to_string(path).map_err(|err| in_file(err.into()))?;
+    let mut tree = ConfigFormat::of(path).and_then(|format| format.read_tree(&text)).map_err(in_file)?;
+    // A single file or a list of them
+    let includes: Vec<String> = match tree.as_object_mut().and_then(|tree| tree.remove(INCLUDE)) {
+        None => vec![],
+        Some(Value::String(include)) => vec![include],
+        Some(includes) => serde_json::from_value(includes).map_err(|err| in_file(ConfigError::Invalid(Diagnostic {
+            path: INCLUDE.to_string(),
+            location: None,
+            message: err.to_string(),
+        })))?,
+    };
+
+    stack.push(canonical);
+    let mut layered = Value::Object(Map::new());
+    for include in includes {
+        let included = read_layers(&path.parent().unwrap_or(Path::new("")).join(include), stack)?;
+        layer(&mut layered, included);
+    }
+    stack.pop();
+
+    layer(&mut layered, tree);
+    Ok(layered)
+}
+
+/// Puts `top` over `base`. Maps are merged key by key, so that a file only has to contain the
+/// options it changes, e.g. `routing.max_legs` of a shared base config. Everything else, including
+/// lists like `datasets`, is replaced as a whole by the top layer.
+fn layer(base: &mut Value, top: Value) {
+    match (base, top) {
+        (Value::Object(base), Value::Object(top)) => {
+            for (key, value) in top {
+                match base.get_mut(&key) {
+                    Some(base) => layer(base, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
             }
+        }
+        (base, top) => *base = top,
+    }
+}
+
+/// Formats of config files, by their extension
+#[derive(Clone, Copy)]
+enum ConfigFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl ConfigFormat {
+    fn of(path: &Path) -> Result<Self, ConfigError> {
+        let Some(extension) = path.extension() else {
+            return Err(ConfigError::MissingFileExtension());
         };
+        match extension.to_str() {
+            Some("yml") | Some("yaml") => Ok(ConfigFormat::Yaml),
+            Some("json") => Ok(ConfigFormat::Json),
+            Some("toml") => Ok(ConfigFormat::Toml),
+            _ => Err(ConfigError::UnknownFileExtension()),
+        }
+    }
+
+    fn parse(self, text: &str) -> Result<Config, ConfigError> {
+        match self {
+            ConfigFormat::Yaml => parse_yaml(text),
+            ConfigFormat::Json => parse_json(text),
+            ConfigFormat::Toml => parse_toml(text),
+        }
+    }
+
+    /// The file as a tree, which files are layered as
+    fn read_tree(self, text: &str) -> Result<Value, ConfigError> {
+        let syntax_error = |message: String, location| ConfigError::Invalid(Diagnostic { path: ".".to_string(), location, message });
+        match self {
+            ConfigFormat::Yaml => serde_yml::from_str(text).map_err(|err| syntax_error(err.to_string(), yaml_location(&err))),
+            ConfigFormat::Json => serde_json::from_str(text).map_err(|err| syntax_error(err.to_string(), json_location(&err))),
+            ConfigFormat::Toml => toml::from_str(text)
+                .map_err(|err| syntax_error(err.to_string(), err.span().map(|span| line_and_column(text, span.start)))),
+        }
+    }
+}
+
+/// Problems of a config that parses, but can't work, e.g. references to datasets that don't exist
+pub(super) fn check(config: &Config) -> Vec<String> {
+    let Settings { datasets, dataset_groups, .. } = config.settings();
+    let mut problems = vec![];
+
+    if datasets.is_empty() {
+        problems.push(ConfigError::NoDatasets().to_string());
+    }
+
+    let mut dataset_ids = HashSet::new();
+    for dataset in datasets {
+        if !dataset_ids.insert(dataset.id.as_str()) {
+            problems.push(format!("datasets: The id {} is used by several datasets", dataset.id));
+        }
+    }
+
+    let group_ids: HashSet<&str> = dataset_groups.iter().map(|group| group.id.as_str()).collect();
+    for dataset in datasets {
+        for group_id in dataset.group_ids.iter().filter(|group_id| !group_ids.contains(group_id.as_str())) {
+            problems.push(format!("datasets.{}.groups: No dataset group with the id {}", dataset.id, group_id));
+        }
+        for dataset_override in dataset.overrides.iter().filter(|dataset_override| !dataset_ids.contains(dataset_override.dataset.as_str())) {
+            problems.push(format!("datasets.{}.overrides: No dataset with the id {}", dataset.id, dataset_override.dataset));
+        }
+    }
+
+    problems
+}
 
-        info!(target: "main", "Config read successfully from {path:?}");
-        debug!(target: "main", "Using config: {:?}", config);
+/// Reads the settings of the config version in the header. Unlike deserializing [Config] right
+/// away, this keeps track of the path of the field that fails and of unknown keys, which are
+/// rejected since they are usually typos of optional fields.
+fn parse<'de, D: Deserializer<'de>>(
+    header: Result<ConfigHeader, D::Error>,
+    deserializer: D,
+    location: impl Fn(&D::Error) -> Option<(usize, usize)>,
+) -> Result<Config, ConfigError> {
+    let invalid = |path: String, err: D::Error| ConfigError::Invalid(Diagnostic {
+        path,
+        location: location(&err),
+        message: err.to_string(),
+    });
 
-        Ok(config)
-    } else {
-        Err(ConfigError::MissingFileExtension())
+    let header = header.map_err(|err| invalid("version".to_string(), err))?;
+
+    let mut unknown_keys = vec![];
+    let mut track_unknown_key = |path: serde_ignored::Path| {
+        let path = path.to_string();
+        if path != "version" {
+            unknown_keys.push(path);
+        }
+    };
+    let settings: Settings = serde_path_to_error::deserialize(
+        serde_ignored::Deserializer::new(deserializer, &mut track_unknown_key)
+    ).map_err(|err| {
+        let path = err.path().to_string();
+        invalid(path, err.into_inner())
+    })?;
+
+    if !unknown_keys.is_empty() {
+        return Err(ConfigError::UnknownKeys(unknown_keys));
+    }
+
+    Config::new(&header.version, settings).ok_or_else(|| ConfigError::UnknownVersion(header.version.to_string()))
+}
+
+fn parse_yaml(text: &str) -> Result<Config, ConfigError> {
+    let header = ConfigHeader::deserialize(serde_yml::Deserializer::from_str(text));
+    parse(header, serde_yml::Deserializer::from_str(text), yaml_location)
+}
+
+fn parse_json(text: &str) -> Result<Config, ConfigError> {
+    parse(serde_json::from_str(text), &mut serde_json::Deserializer::from_str(text), json_location)
+}
+
+fn parse_toml(text: &str) -> Result<Config, ConfigError> {
+    // TOML errors only know the bytes of the text they are about
+    let location = |err: &toml::de::Error| err.span().map(|span| line_and_column(text, span.start));
+    parse(toml::from_str(text), toml::Deserializer::new(text), location)
+}
+
+fn yaml_location(err: &serde_yml::Error) -> Option<(usize, usize)> {
+    err.location().map(|location| (location.line(), location.column()))
+}
+
+// serde_json reports 0 for errors without a position
+fn json_location(err: &serde_json::Error) -> Option<(usize, usize)> {
+    (err.line() > 0).then(|| (err.line(), err.column()))
+}
+
+// Both start at 1, like the ones of the other parsers
+fn line_and_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+/// Where and why a config can't be read
+#[derive(Debug)]
+pub struct Diagnostic {
+    // Path of the field in the config, e.g. `datasets[0].src`
+    pub path: String,
+    // Line and column in the file, if the parser knows them
+    pub location: Option<(usize, usize)>,
+    // Explanation of the parser, e.g. a missing field or a value of the wrong type
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.location {
+            Some((line, column)) => write!(f, "{} (line {}, column {}): {}", self.path, line, column, self.message),
+            None => write!(f, "{}: {}", self.path, self.message),
+        }
     }
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum ConfigError {
     IO(#[from] io::Error),
-    DeserializationYaml(#[from] serde_yml::Error),
-    DeserializationJson(#[from] serde_json::Error),
+    Invalid(Diagnostic),
+    InFile { file: PathBuf, err: Box<ConfigError> },
+    IncludeCycle(PathBuf),
+    UnknownKeys(Vec<String>),
+    UnknownVersion(String),
     MissingFileExtension(),
     UnknownFileExtension(),
-    NoDatasets()
+    NoDatasets(),
+    Inconsistent(Vec<String>),
+    RequiresVersion2(String),
 }
 
 impl Display for ConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             ConfigError::IO(err) => write!(f, "{}", err),
-            ConfigError::DeserializationYaml(err) => write!(f, "{}", err),
-            ConfigError::DeserializationJson(err) => write!(f, "{}", err),
-            ConfigError::MissingFileExtension() => write!(f, "File extension not provided. Please provide .yml, .yaml or .json in the file path."),
-            ConfigError::UnknownFileExtension() => write!(f, "File extension not recognized. Please provide .yml, .yaml or .json in the file path."),
+            ConfigError::Invalid(diagnostic) => write!(f, "{}", diagnostic),
+            ConfigError::InFile { file, err } => write!(f, "In {}: {}", file.display(), err),
+            ConfigError::IncludeCycle(file) => write!(f, "{} includes itself", file.display()),
+            ConfigError::UnknownKeys(keys) => write!(f, "Unknown keys {}. Check them for typos.", keys.join(", ")),
+            ConfigError::UnknownVersion(version) => write!(f, "Unknown version {}. Please provide `version: 1` or `version: 2`.", version),
+            ConfigError::MissingFileExtension() => write!(f, "File extension not provided. Please provide .yml, .yaml, .json or .toml in the file path."),
+            ConfigError::UnknownFileExtension() => write!(f, "File extension not recognized. Please provide .yml, .yaml, .json or .toml in the file path."),
             ConfigError::NoDatasets() => write!(f, "No datasets provided."),
+            ConfigError::Inconsistent(problems) => write!(f, "{}", problems.join("\n")),
+            ConfigError::RequiresVersion2(option) => write!(f, "{} requires `version: 2` of the config.", option),
         }?;
-        
+
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_diagnostics() {
+        let err = parse_yaml("version: 1\ndatasets:\n  - id: a\n    format: gtfs\n").unwrap_err();
+        let ConfigError::Invalid(diagnostic) = err else { panic!("{err}") };
+        assert_eq!(diagnostic.path, "datasets[0]");
+        assert!(diagnostic.message.contains("missing field `src`"), "{}", diagnostic.message);
+
+        let err = parse_yaml("version: 1\ndatasets: []\nrouting:\n  max_legs: many\n").unwrap_err();
+        let ConfigError::Invalid(diagnostic) = err else { panic!("{err}") };
+        assert_eq!(diagnostic.path, "routing.max_legs");
+        assert_eq!(diagnostic.location.map(|(line, _)| line), Some(4));
+
+        let err = parse_yaml("version: 1\ndatasets: []\nmerge:\n  stop_match_raduis: 10\n").unwrap_err();
+        assert!(matches!(&err, ConfigError::UnknownKeys(keys) if keys == &["merge.stop_match_raduis"]), "{err}");
+
+        assert!(matches!(parse_yaml("version: 3\ndatasets: []\n"), Err(ConfigError::UnknownVersion(_))));
+    }
+
+    #[test]
+    fn test_parse_versions() {
+        assert!(matches!(parse_yaml("version: 2\ndatasets: []\n"), Ok(Config::Version2(_))));
+        assert!(matches!(parse_json(r#"{"version": 1, "datasets": []}"#), Ok(Config::Version1(_))));
+        assert!(matches!(parse_toml("version = 2\ndatasets = []\n"), Ok(Config::Version2(_))));
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let config = parse_toml(
+            "version = 1\n\n[[datasets]]\nid = \"a\"\nformat = \"gtfs\"\nsrc = { path = \"a.zip\" }\n\n[routing]\nmax_legs = 3\n",
+        ).unwrap();
+        assert_eq!(config.settings().datasets[0].id, "a");
+        assert_eq!(config.settings().routing.max_legs, 3);
+
+        let err = parse_toml("version = 1\ndatasets = []\n\n[routing]\nmax_legs = \"many\"\n").unwrap_err();
+        let ConfigError::Invalid(diagnostic) = err else { panic!("{err}") };
+        assert_eq!(diagnostic.path, "routing.max_legs");
+        assert_eq!(diagnostic.location.map(|(line, _)| line), Some(5));
+    }
+
+    #[test]
+    fn test_check() {
+        let config = parse_yaml(
+            "version: 1\ndatasets:\n  - id: a\n    format: gtfs\n    groups: [g]\n    src: { path: a.zip }\n    overrides: [{ dataset: b }]\n",
+        ).unwrap();
+        assert_eq!(check(&config), vec![
+            "datasets.a.groups: No dataset group with the id g",
+            "datasets.a.overrides: No dataset with the id b",
+        ]);
+    }
+
+    #[test]
+    fn test_read_layers() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, text: &str| fs::write(dir.path().join(name), text).unwrap();
+        write("base.yaml", "version: 1\ndatasets: []\nmerge:\n  stop_match_radius: 10\n  transfer_radius: 100\nrouting:\n  max_legs: 3\n");
+        write("limits.toml", "[routing]\nmax_legs = 5\nnum_clusters = 4\n");
+        fs::create_dir(dir.path().join("regions")).unwrap();
+        write(
+            "regions/stuttgart.yaml",
+            "include: [../base.yaml, ../limits.toml]\ndatasets:\n  - { id: vvs, format: gtfs, src: { path: vvs.zip } }\nmerge:\n  transfer_radius: 50\n",
+        );
+
+        let config = read_config(&dir.path().join("regions/stuttgart.yaml")).unwrap();
+        let settings = config.settings();
+        // Lists are replaced, maps merged key by key with the later layer winning
+        assert_eq!(settings.datasets.iter().map(|dataset| dataset.id.as_str()).collect::<Vec<_>>(), vec!["vvs"]);
+        assert_eq!(settings.merge.stop_match_radius, 10.0);
+        assert_eq!(settings.merge.transfer_radius, 50.0);
+        assert_eq!(settings.routing.max_legs, 5);
+        assert_eq!(settings.routing.num_clusters, 4);
+
+        write("a.yaml", "include: b.yaml\n");
+        write("b.yaml", "include: a.yaml\n");
+        let err = read_config(&dir.path().join("a.yaml")).unwrap_err();
+        assert!(matches!(err, ConfigError::IncludeCycle(_)), "{err}");
+
+        write("typo.yaml", "include: base.yaml\nrouting:\n  max_leg: 2\n");
+        let err = read_config(&dir.path().join("typo.yaml")).unwrap_err();
+        assert!(matches!(&err, ConfigError::UnknownKeys(keys) if keys == &["routing.max_leg"]), "{err}");
+    }
+}
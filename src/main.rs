@@ -1,98 +1,211 @@
+mod artifacts;
+mod bench;
 pub mod bootstrap_config;
+mod checkpoint;
+mod compact;
 mod config;
+mod network;
+mod patterns;
+mod plan;
 mod preprocessing;
+mod query;
+mod realtime;
+mod server;
 
-use crate::config::load_config;
-use bootstrap_config::BootstrapConfig;
-use common::types::config::Config;
-use common::util::logging;
-use common::util::speed::Speed;
+use crate::config::{load_config, ConfigError};
+use bootstrap_config::{BootstrapConfig, Command, ConfigCommand, ExportCommand, ServerArgs, ValidationArgs};
+use common::types::config::{Config, Settings};
+use common::util::{logging, paths};
+use data_harvester::checkpoint::CheckpointError;
+use data_harvester::crop::{crop_preprocessed, read_region, CropError};
+use data_harvester::export_gtfs::{export_gtfs, ExportError};
+use data_harvester::realtime::RealtimeError;
 use data_harvester::step1_fetch_data::FetchError;
 use data_harvester::step2_import_data::ImportError;
 use data_harvester::step3_validate_data::ValidateError;
 use data_harvester::step4_merge_data::MergeError;
-use data_harvester::step5_simplify::SimplifyError;
+use data_harvester::step5_simplify::{read_original_ids_from, read_simplified_from, simplified_dir, SimplifyError};
 use log::{debug, error, info};
 use polars::error::PolarsError;
-use routing::algorithm::PreprocessingError;
+use routing::algorithm::{PreprocessingError, QueryError};
+use routing::compact::CompactError;
 use routing::stp::ScalableTransferPatternsAlgorithm;
+use routing::timetable::TimetableLookup;
 use std::fmt::{Display, Formatter};
-use std::thread;
-use preprocessing::preprocess;
+use std::path::PathBuf;
+use tokio::runtime::Runtime;
+use preprocessing::{preprocess, PreprocessingSettings};
 
+// Any algorithm that can be preprocessed and saved works here, e.g. routing::raptor::RaptorAlgorithm
+// without the long preprocessing of transfer patterns
 type ALGORITHM = ScalableTransferPatternsAlgorithm;
 
-// The maximum speed in km/h that any vehicle can travel
-// This must be high enough, otherwise wrong routes might be calculated
-pub const MAX_SPEED: Speed = Speed(500.0);
+/// What is served after preprocessing, depending on the routing mode of the config
+pub enum Engine {
+    Journeys(ALGORITHM),
+    TimetableLookup(TimetableLookup),
+}
 
 fn main() {
-    let _ = run().inspect_err(|err| error!("{}", err));
+    // A single runtime drives the whole pipeline, from fetching datasets to serving requests
+    let rt = Runtime::new().expect("Unable to create runtime");
+    let _ = rt.block_on(run()).inspect_err(|err| error!("{}", err));
 }
 
-fn run() -> Result<(), DrinoError> {
+async fn run() -> Result<(), DrinoError> {
     let bootstrap_config = BootstrapConfig::read();
 
-    logging::init(bootstrap_config.clone().log_level.into());
+    let _flamegraph = logging::init(bootstrap_config.clone().log_level.into(), bootstrap_config.flamegraph.as_deref());
     print_startup_message();
 
-    debug!(target: "main", "Using temporary folder at {}", std::env::temp_dir().to_str().unwrap());
+    paths::init(bootstrap_config.work_dir());
+    debug!(target: "main", "Using working directory at {}", paths::work_dir().display());
 
-    let config = load_config(bootstrap_config)?;
-    
-    info!(target: "visualization", "Launching visualization server");
-    let vis_server_config = config.clone();
-    let vis_server_thread = thread::spawn(move || {
-        let vis_server_rt = actix_web::rt::Runtime::new()
-            .expect("Could not create actix runtime");
-        let vis_server_handle = vis_server_rt.spawn(async move {
-            let vis_server = visualization::build_server(
-                vis_server_config, "./data".into(), true
-            ).await.expect("Error building visualization server");
-            
-            vis_server.await.expect("Error running visualization server");
-        });
-        
-        vis_server_rt.block_on(vis_server_handle).unwrap();
-        
-        info!(target: "visualization", "Visualization server shut down");
-    });
-
-    match config {
-        Config::Version1 { datasets, .. } => {
-            let algorithm = preprocess(datasets)?;
-
-            serve(algorithm)?;
+    match bootstrap_config.command.clone() {
+        Command::Preprocess { out, validation, dry_run, resume } => {
+            let config = load_config(bootstrap_config)?;
+            if dry_run {
+                print!("{}", plan::plan(&config, &out).await?);
+                return Ok(());
+            }
+            // A run that crashes can be resumed from its last completed step
+            common::util::checkpoint::enable();
+            let modes = config.mode_registry();
+            let Settings { datasets, merge, simplify, routing, .. } = config.into_settings();
+            let settings = PreprocessingSettings { merge: &merge, simplify: &simplify, routing: &routing, modes: &modes, html_validation_report: validation.html_validation_report };
+            let engine = preprocess(datasets, settings, resume).await?;
+            // The simplified timetable was written to the working directory during preprocessing
+            let tables = simplified_dir(paths::work_dir());
+            let input = read_simplified_from(&tables)?;
+            logging::run_with_spinner("main", "Saving preprocessing results", || {
+                artifacts::save(&engine, input, &tables, &routing.transfers, &out, routing.compression)
+            })?;
+            info!(target: "main", "Saved preprocessing results to {}", out.display());
         }
-    };
-    
-    vis_server_thread.join().expect("Visualization server thread join error");
+        Command::Validate { validation } => {
+            let config = load_config(bootstrap_config)?;
+            let modes = config.mode_registry();
+            let num_datasets = config.settings().datasets.len();
+            let skipped = preprocessing::validate(config.into_settings().datasets, &modes, validation.html_validation_report).await?;
+            if skipped > 0 {
+                return Err(DrinoError::InvalidDatasets(skipped));
+            }
+            info!(target: "main", "All {} datasets are valid, see the validation reports in {}", num_datasets, paths::datasets_dir().display());
+        }
+        Command::Config { command: ConfigCommand::Check } => {
+            let config_file = bootstrap_config.config_file.clone();
+            let problems = config::check(&load_config(bootstrap_config)?);
+            if !problems.is_empty() {
+                return Err(ConfigError::Inconsistent(problems).into());
+            }
+            info!(target: "main", "The config at {} is valid", config_file);
+        }
+        Command::Serve { artifacts, server, validation } => {
+            serve(load_config(bootstrap_config)?, artifacts, server, validation).await?;
+        }
+        Command::CropPreprocessed { polygon, out } => {
+            let region = read_region(&polygon)?;
+            let summary = crop_preprocessed(paths::work_dir(), &out, &region)?;
+            info!(
+                target: "main",
+                "Cropped preprocessed data to {} stops, {} trips and {} lines at {}",
+                summary.stops, summary.trips, summary.lines, out.display(),
+            );
+        }
+        Command::Query { from, to, at, suspended_routes, options, realtime, format } => {
+            let config = load_config(bootstrap_config)?;
+            let routing = &config.settings().routing;
+            print!("{}", query::query(&from, &to, at, &suspended_routes, &options, routing, &config.mode_registry(), &realtime, format).await?);
+        }
+        Command::Export { export } => run_export(export)?,
+        Command::Patterns { artifacts, from, to } => {
+            print!("{}", patterns::inspect(&artifacts, &from, &to)?);
+        }
+        Command::Bench { artifacts, queries, iterations, seed } => {
+            print!("{}", bench::bench(&artifacts, queries, iterations, seed)?);
+        }
+    }
 
     Ok(())
 }
 
-fn print_startup_message() {
-    info!("\n      _      _             \n   __| |_ __(_)_ __   ___  \n  / _` | '__| | '_ \\ / _ \\ \n | (_| | |  | | | | | (_) |\n  \\__,_|_|  |_|_| |_|\\___/ \n                           \n R O U T I N G   E N G I N E\n");
-}
+/// Preprocesses the datasets of the config, unless the results of an earlier `drino preprocess`
+/// are given, and serves them until the servers shut down
+async fn serve(config: Config, artifacts: Option<PathBuf>, server: ServerArgs, validation: ValidationArgs) -> Result<(), DrinoError> {
+    let modes = config.mode_registry();
+    let datasets = &config.settings().datasets;
+    let feeds = server::RealtimeFeeds {
+        trip_updates: datasets.iter()
+            .filter_map(|dataset| Some((dataset.id.clone(), dataset.trip_updates.clone()?)))
+            .collect(),
+        alerts: datasets.iter()
+            .filter_map(|dataset| Some((dataset.id.clone(), dataset.alerts.clone()?)))
+            .collect(),
+    };
+    let routing = config.settings().routing.clone();
 
-fn serve(algorithm: ALGORITHM) -> Result<(), DrinoError> {
-    let rt = actix_web::rt::Runtime::new()
-        .expect("Unable to create server runtime");
+    info!(target: "visualization", "Launching visualization server");
+    let vis_server = visualization::build_server(config.clone(), paths::work_dir().into(), true).await?;
+    let vis_server_handle = tokio::spawn(vis_server);
 
-    let server_handle = rt.spawn(async move {
-        /*HttpServer::new(move || {})
-            .bind("127.0.0.1:8080");*/
-    });
+    // The timetable of the results is served from the copy saved with them, so that they don't
+    // need the working directory they were preprocessed in
+    let (engine, tables) = match artifacts {
+        Some(dir) => {
+            let engine = logging::run_with_spinner("main", "Loading preprocessing results", || artifacts::load(&dir))?;
+            let tables = artifacts::timetable(&dir)?;
+            info!(target: "main", "Loaded preprocessing results from {}", dir.display());
+            preprocessing::warm_up(&engine, &routing.warm_up_stops, &read_original_ids_from(&tables)?)?;
+            (engine, tables)
+        }
+        None => {
+            let Settings { datasets, merge, simplify, .. } = config.into_settings();
+            let settings = PreprocessingSettings { merge: &merge, simplify: &simplify, routing: &routing, modes: &modes, html_validation_report: validation.html_validation_report };
+            let engine = preprocess(datasets, settings, false).await?;
+            (engine, simplified_dir(paths::work_dir()))
+        }
+    };
+    server::serve(engine, &tables, &server.bind, server.grpc_bind.as_deref(), modes, server.timezone, feeds, &routing).await?;
 
-    rt.block_on(server_handle).unwrap();
+    vis_server_handle.await.expect("Visualization server task join error")?;
+    info!(target: "visualization", "Visualization server shut down");
 
+    Ok(())
+}
 
-    todo!()
+fn run_export(export: ExportCommand) -> Result<(), DrinoError> {
+    match export {
+        ExportCommand::Compact { date, out } => {
+            let stops = logging::run_with_spinner("main", "Exporting the compact timetable", || compact::export(date, &out))?;
+            info!(target: "main", "Exported the timetable of {} with {} stops to {}", date, stops, out.display());
+        }
+        ExportCommand::Network { out } => {
+            let features = logging::run_with_spinner("main", "Exporting the network", || network::export(&out))?;
+            info!(target: "main", "Exported the network with {} features to {}", features, out.display());
+        }
+        ExportCommand::Patterns { artifacts, out, format } => {
+            let num_patterns = logging::run_with_spinner("main", "Exporting the transfer patterns", || patterns::export(&artifacts, &out, format))?;
+            info!(target: "main", "Exported {} transfer patterns to {}", num_patterns, out.display());
+        }
+        ExportCommand::Gtfs { out } => {
+            let summary = logging::run_with_spinner("main", "Exporting the timetable as GTFS", || export_gtfs(paths::work_dir(), &out))?;
+            info!(
+                target: "main",
+                "Exported {} agencies, {} routes, {} stops, {} trips and {} transfers to {}",
+                summary.agencies, summary.routes, summary.stops, summary.trips, summary.transfers, out.display(),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_startup_message() {
+    info!("\n      _      _             \n   __| |_ __(_)_ __   ___  \n  / _` | '__| | '_ \\ / _ \\ \n | (_| | |  | | | | | (_) |\n  \\__,_|_|  |_|_| |_|\\___/ \n                           \n R O U T I N G   E N G I N E\n");
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum DrinoError {
-    Config(#[from] config::ConfigError),
+    Config(#[from] ConfigError),
     Fetch(#[from] FetchError),
     Import(#[from] ImportError),
     Validate(#[from] ValidateError),
@@ -100,6 +213,17 @@ pub enum DrinoError {
     Simplify(#[from] SimplifyError),
     Polars(#[from] PolarsError),
     Preprocessing(#[from] PreprocessingError),
+    Crop(#[from] CropError),
+    Export(#[from] ExportError),
+    Query(#[from] QueryError),
+    Realtime(#[from] RealtimeError),
+    Compact(#[from] CompactError),
+    Checkpoint(#[from] CheckpointError),
+    UnknownStop(String),
+    UnknownRoute(String),
+    UnknownAgency(String),
+    NoTransferPatterns,
+    InvalidDatasets(usize),
     IO(#[from] std::io::Error),
 }
 
@@ -114,6 +238,17 @@ impl Display for DrinoError {
             DrinoError::Simplify(err) => err,
             DrinoError::Polars(err) => err,
             DrinoError::Preprocessing(err) => err,
+            DrinoError::Crop(err) => err,
+            DrinoError::Export(err) => err,
+            DrinoError::Query(err) => err,
+            DrinoError::Realtime(err) => err,
+            DrinoError::Compact(err) => err,
+            DrinoError::Checkpoint(err) => err,
+            DrinoError::UnknownStop(stop) => stop,
+            DrinoError::UnknownRoute(route) => route,
+            DrinoError::UnknownAgency(agency) => agency,
+            DrinoError::NoTransferPatterns => &"The results were preprocessed in the timetable lookup mode",
+            DrinoError::InvalidDatasets(skipped) => skipped,
             DrinoError::IO(err) => err,
         };
         let prefix = match self {
@@ -125,6 +260,17 @@ impl Display for DrinoError {
             DrinoError::Simplify(_) => "Error while simplifying a dataset",
             DrinoError::Polars(_) => "Error while processing dataset data",
             DrinoError::Preprocessing(_) => "Error while preprocessing data",
+            DrinoError::Crop(_) => "Error while cropping preprocessed data",
+            DrinoError::Export(_) => "Error while exporting the timetable as GTFS",
+            DrinoError::Query(_) => "Error while answering the query",
+            DrinoError::Realtime(_) => "Error while fetching a realtime feed",
+            DrinoError::Compact(_) => "Error while writing the compact timetable",
+            DrinoError::Checkpoint(_) => "Error while keeping the results of a step to resume from",
+            DrinoError::UnknownStop(_) => "No stop with this name or id",
+            DrinoError::UnknownRoute(_) => "No route with this id",
+            DrinoError::UnknownAgency(_) => "No agency with this id",
+            DrinoError::NoTransferPatterns => "No transfer patterns to inspect",
+            DrinoError::InvalidDatasets(_) => "Datasets with validation errors",
             DrinoError::IO(_) => "Error during IO",
         };
         write!(f, "{}: {}", prefix, err)
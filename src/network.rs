@@ -0,0 +1,21 @@
+use crate::query::{stop_coordinates, stop_names};
+use crate::DrinoError;
+use common::util::paths;
+use data_harvester::step5_simplify::read_simplified;
+use routing::direct_connections::DirectConnections;
+use routing::geojson::GeoFeatures;
+use std::fs;
+use std::path::Path;
+
+/// Writes the stops and lines of the whole simplified timetable in the working directory to `out`
+/// as GeoJSON, see [GeoFeatures::network]. Returns the number of features.
+pub fn export(out: &Path) -> Result<usize, DrinoError> {
+    let input = read_simplified(paths::work_dir())?;
+    let names = stop_names()?;
+    let coordinates = stop_coordinates(&input)?;
+    let direct_connections = DirectConnections::try_from(input.clone())?;
+
+    let network = GeoFeatures::new(&input.original_ids, &names, &coordinates).network(&direct_connections)?;
+    fs::write(out, network.to_string())?;
+    Ok(network.features.len())
+}
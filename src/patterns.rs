@@ -0,0 +1,130 @@
+use crate::bootstrap_config::TableFormat;
+use crate::query::stop_names;
+use crate::{artifacts, DrinoError, Engine, ALGORITHM};
+use common::types::id_interner::OriginalIds;
+use common::types::StopId;
+use common::util::df::{write_df_to_file, FileType};
+use common::util::paths;
+use data_harvester::step5_simplify::read_original_ids;
+use polars::prelude::{Column, DataFrame, NamedFrom, PlSmallStr, Series};
+use routing::stp::{PatternKind, TransferPattern};
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs::File;
+use std::path::Path;
+
+/// A transfer pattern with the original ids and names of its stops
+#[derive(Serialize)]
+struct ExportedPattern {
+    kind: &'static str,
+    start_stop_id: String,
+    start_name: String,
+    transfer_stop_ids: Vec<String>,
+    target_stop_id: String,
+    target_name: String,
+}
+
+/// Writes all transfer patterns of the results of `preprocess` in `dir` to `out`, one row per
+/// pattern. The ids and names of the stops are those of the working directory, so it has to be
+/// the one that the results were preprocessed in. Returns the number of patterns.
+pub fn export(dir: &Path, out: &Path, format: TableFormat) -> Result<usize, DrinoError> {
+    let algorithm = load(dir)?;
+    let stops = Stops::read()?;
+    let patterns: Vec<ExportedPattern> = algorithm.transfer_patterns().iter()
+        .map(|pattern| ExportedPattern {
+            kind: kind_name(pattern.kind),
+            start_stop_id: stops.id(pattern.start),
+            start_name: stops.name(pattern.start),
+            transfer_stop_ids: pattern.transfers.iter().map(|stop| stops.id(*stop)).collect(),
+            target_stop_id: stops.id(pattern.target),
+            target_name: stops.name(pattern.target),
+        })
+        .collect();
+
+    match format {
+        TableFormat::Json => serde_json::to_writer(File::create(out)?, &patterns).map_err(std::io::Error::from)?,
+        TableFormat::Parquet => {
+            let string_column = |name: &str, values: Vec<&str>| Column::new(name.into(), values);
+            let frame = DataFrame::new(vec![
+                string_column("kind", patterns.iter().map(|pattern| pattern.kind).collect()),
+                string_column("start_stop_id", patterns.iter().map(|pattern| pattern.start_stop_id.as_str()).collect()),
+                string_column("start_name", patterns.iter().map(|pattern| pattern.start_name.as_str()).collect()),
+                Column::new("transfer_stop_ids".into(), patterns.iter()
+                    .map(|pattern| Series::new(PlSmallStr::EMPTY, &pattern.transfer_stop_ids))
+                    .collect::<Vec<_>>()),
+                string_column("target_stop_id", patterns.iter().map(|pattern| pattern.target_stop_id.as_str()).collect()),
+                string_column("target_name", patterns.iter().map(|pattern| pattern.target_name.as_str()).collect()),
+            ])?;
+            write_df_to_file(out.to_path_buf(), FileType::PARQUET, frame)?;
+        }
+    }
+    Ok(patterns.len())
+}
+
+/// The transfer patterns that a query between two stops of the results in `dir` is stitched from,
+/// formatted for the terminal. Stops are looked up like in `drino query`.
+pub fn inspect(dir: &Path, from: &str, to: &str) -> Result<String, DrinoError> {
+    let algorithm = load(dir)?;
+    let stops = Stops::read()?;
+    let (start, target) = (stops.find(from)?, stops.find(to)?);
+    let patterns = algorithm.patterns_between(start, target)?;
+
+    let mut out = String::new();
+    let cluster = |stop: StopId| algorithm.cluster(stop)
+        .map(|cluster| format!("cluster {}", cluster))
+        .unwrap_or_else(|| "no cluster".to_string());
+    writeln!(out, "{} ({}) is in {}, {} ({}) in {}", stops.name(start), stops.id(start), cluster(start), stops.name(target), stops.id(target), cluster(target)).unwrap();
+    if patterns.is_empty() {
+        writeln!(out, "No transfer patterns connect them").unwrap();
+    }
+    for TransferPattern { kind, start, transfers, target } in &patterns {
+        let stops = [*start].iter().chain(transfers.iter()).chain([*target].iter())
+            .map(|stop| format!("{} ({})", stops.name(*stop), stops.id(*stop)))
+            .collect::<Vec<_>>();
+        writeln!(out, "{:<13} {}", kind_name(*kind), stops.join(" -> ")).unwrap();
+    }
+    Ok(out)
+}
+
+// Only the journeys mode has transfer patterns
+fn load(dir: &Path) -> Result<ALGORITHM, DrinoError> {
+    match artifacts::load(dir)? {
+        Engine::Journeys(algorithm) => Ok(algorithm),
+        Engine::TimetableLookup(_) => Err(DrinoError::NoTransferPatterns),
+    }
+}
+
+fn kind_name(kind: PatternKind) -> &'static str {
+    match kind {
+        PatternKind::Local => "local",
+        PatternKind::LongDistance => "long_distance",
+    }
+}
+
+// Original ids and names of the stops in the working directory, by their id
+struct Stops {
+    original_ids: OriginalIds,
+    names: Vec<String>,
+}
+
+impl Stops {
+    fn read() -> Result<Self, DrinoError> {
+        Ok(Self { original_ids: read_original_ids(paths::work_dir())?, names: stop_names()? })
+    }
+
+    fn find(&self, stop: &str) -> Result<StopId, DrinoError> {
+        self.original_ids.stops.get(stop)
+            .or_else(|| self.names.iter()
+                .position(|name| name.eq_ignore_ascii_case(stop))
+                .map(|id| StopId(id as u32)))
+            .ok_or_else(|| DrinoError::UnknownStop(stop.to_string()))
+    }
+
+    fn id(&self, stop: StopId) -> String {
+        self.original_ids.stop(stop).unwrap_or_default().to_string()
+    }
+
+    fn name(&self, stop: StopId) -> String {
+        self.names.get(stop.0 as usize).cloned().unwrap_or_default()
+    }
+}
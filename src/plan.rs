@@ -0,0 +1,110 @@
+use crate::config::{check, ConfigError};
+use crate::DrinoError;
+use common::types::config::{Config, RoutingMode, Settings, SimplifyPass};
+use common::types::dataset::{DataSource, Dataset, DatasetFilter};
+use data_harvester::step1_fetch_data::probe_dataset;
+use data_harvester::step2_import_data::estimate_rows;
+use indicatif::HumanBytes;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Describes what `drino preprocess` would do with the config, without downloading, importing or
+/// preprocessing anything. The config is checked like by `drino config check` and the server of
+/// every dataset is asked for the size of its archive. Archives that are already on disk are
+/// looked into, so that the number of rows of their files can be estimated.
+///
+/// Fails like preprocessing would if the config is inconsistent or a dataset can't be reached.
+pub async fn plan(config: &Config, out: &Path) -> Result<String, DrinoError> {
+    let problems = check(config);
+    if !problems.is_empty() {
+        return Err(ConfigError::Inconsistent(problems).into());
+    }
+
+    let Settings { datasets, merge, simplify, routing, .. } = config.settings();
+    let mut plan = String::new();
+    let mut num_downloads = 0;
+    let mut download_size = Some(0u64);
+    let mut stop_times = Some(0u64);
+
+    writeln!(plan, "Datasets:").unwrap();
+    for dataset in datasets {
+        let probe = probe_dataset(dataset).await?;
+        writeln!(plan, "  {} ({})", dataset.id, source(dataset)).unwrap();
+
+        let size = probe.size.map_or("unknown size".to_string(), |size| HumanBytes(size).to_string());
+        match &probe.local_file {
+            Some(path) => writeln!(plan, "    {}, on disk at {}", size, path.display()).unwrap(),
+            None => {
+                writeln!(plan, "    {}, to be downloaded", size).unwrap();
+                num_downloads += 1;
+                download_size = download_size.zip(probe.size).map(|(total, size)| total + size);
+            }
+        }
+        if !dataset.filter.is_empty() {
+            writeln!(plan, "    Filtered by {}", filter(&dataset.filter)).unwrap();
+        }
+
+        let Some(path) = probe.local_file else {
+            stop_times = None;
+            continue;
+        };
+        let Some(estimates) = estimate_rows(dataset, &path)? else {
+            writeln!(plan, "    GTFS RT feed, rows not estimated").unwrap();
+            continue;
+        };
+        for estimate in &estimates {
+            writeln!(plan, "    {:<20} {:>10}  ~{} rows", estimate.file, HumanBytes(estimate.size).to_string(), estimate.rows).unwrap();
+        }
+        let dataset_stop_times = estimates.iter().find(|estimate| estimate.file == "stop_times.txt").map(|estimate| estimate.rows);
+        stop_times = stop_times.zip(dataset_stop_times).map(|(total, rows)| total + rows);
+    }
+
+    writeln!(plan, "\nSteps:").unwrap();
+    let download_size = download_size.map_or(String::new(), |size| format!(" ({})", HumanBytes(size)));
+    writeln!(plan, "  1. Fetch: download {} of {} datasets{}", num_downloads, datasets.len(), download_size).unwrap();
+    let stop_times = stop_times.map_or(String::new(), |rows| format!(" with ~{} stop times", rows));
+    writeln!(plan, "  2. Import and validate {} datasets{}", datasets.len(), stop_times).unwrap();
+    writeln!(
+        plan, "  3. Merge: stops within {} m, transfers between datasets within {} m",
+        merge.stop_match_radius, merge.transfer_radius,
+    ).unwrap();
+    let passes: Vec<&str> = simplify.passes.iter().map(|pass| match pass {
+        SimplifyPass::Dedup => "dedup",
+        SimplifyPass::Prune => "prune",
+        SimplifyPass::FrequencyCompress => "frequency-compress",
+        SimplifyPass::DayReduce => "day-reduce",
+    }).collect();
+    writeln!(plan, "  4. Simplify: {}", if passes.is_empty() { "no passes".to_string() } else { passes.join(", ") }).unwrap();
+    match routing.mode {
+        RoutingMode::Journeys => writeln!(
+            plan, "  5. Preprocess transfer patterns in {} clusters, with at most {} rides per journey",
+            routing.num_clusters, routing.max_legs,
+        ).unwrap(),
+        RoutingMode::TimetableLookup => writeln!(plan, "  5. Build the timetable lookup, without transfer patterns").unwrap(),
+    }
+    writeln!(plan, "  6. Save the results to {}", out.display()).unwrap();
+
+    Ok(plan)
+}
+
+// Where a dataset comes from, without the path and query of URLs, which might contain secrets
+fn source(dataset: &Dataset) -> String {
+    match &dataset.src {
+        DataSource::URL { url, .. } => format!("{}://{}", url.scheme(), url.host_str().unwrap_or_default()),
+        DataSource::File { path } => path.clone(),
+    }
+}
+
+fn filter(filter: &DatasetFilter) -> String {
+    let mut parts = vec![];
+    if let Some(bbox) = &filter.bbox {
+        parts.push(format!("bbox {},{} - {},{}", bbox.min_lat, bbox.min_lon, bbox.max_lat, bbox.max_lon));
+    }
+    if !filter.agencies.is_empty() {
+        parts.push(format!("agencies {}", filter.agencies.join(", ")));
+    }
+    if !filter.route_types.is_empty() {
+        parts.push(format!("route types {}", filter.route_types.iter().map(u32::to_string).collect::<Vec<_>>().join(", ")));
+    }
+    parts.join("; ")
+}
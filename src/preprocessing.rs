@@ -1,125 +1,380 @@
 use std::path::PathBuf;
 use std::time::SystemTime;
+use chrono::{TimeDelta, Utc};
 use futures::{StreamExt, TryStreamExt};
-use log::{debug, error, info};
-use polars::prelude::IntoLazy;
+use log::{debug, error, info, warn};
+use hashbrown::HashMap;
+use indicatif::HumanBytes;
+use polars::prelude::{col, DataType, JoinArgs, JoinType, LazyFrame};
 use tempfile::TempPath;
-use tokio::runtime::Runtime;
+use tracing::{info_span, instrument, Instrument, Span};
+use common::types::config::{MergeConfig, RoutingConfig, RoutingMode, SimplifyConfig};
 use common::types::dataset::Dataset;
-use common::util::df::{write_geoarrow_to_file, FileType};
-use common::util::logging;
+use common::types::id_interner::OriginalIds;
+use common::types::mode::ModeRegistry;
+use common::types::{StopId, TripId};
+use common::util::df::{write_df_to_file, write_geoarrow_to_file, FileType};
+use common::util::logging::{ProgressBars, ProgressSink};
+use common::util::spill::Spill;
+use common::util::{logging, memory, metrics, paths};
+use data_harvester::checkpoint::{read_merged, read_validated, write_merged, write_validated, CheckpointError};
 use data_harvester::step1_fetch_data::fetch_dataset;
 use data_harvester::step2_import_data::{import_data, ImportStepExtra};
 use data_harvester::step3_validate_data::{validate_data, ValidateStepOutput};
 use data_harvester::step4_merge_data::merge;
-use data_harvester::step5_simplify::simplify;
+use data_harvester::step5_simplify::{read_simplified, simplify};
 use routing::algorithm::{PreprocessInit, PreprocessingError, PreprocessingInput};
+use routing::calendar::ServiceCalendar;
 use routing::direct_connections::DirectConnections;
-use crate::{DrinoError, ALGORITHM};
+use routing::quality;
+use routing::timetable::TimetableLookup;
+use routing::transfer_feasibility::TransferFeasibilityReport;
+use crate::checkpoint::{Checkpoints, Step};
+use crate::{DrinoError, Engine, ALGORITHM};
 use crate::config::ConfigError;
 
+// Maximum number of datasets that are fetched and imported at the same time
+const MAX_CONCURRENT_DATASETS: usize = 4;
+
+/// The sections of the config that the datasets are preprocessed with
+#[derive(Clone, Copy)]
+pub struct PreprocessingSettings<'a> {
+    pub merge: &'a MergeConfig,
+    pub simplify: &'a SimplifyConfig,
+    pub routing: &'a RoutingConfig,
+    pub modes: &'a ModeRegistry,
+    // Whether the validation reports are also written as HTML
+    pub html_validation_report: bool,
+}
+
 /// Wrapper for `preprocess_inner` that handles cleaning up temporary files, even if error was
-/// thrown.
-pub fn preprocess(datasets: Vec<Dataset>) -> Result<ALGORITHM, DrinoError> {
+/// thrown. With `resume`, the steps that an earlier run with the same settings completed are
+/// skipped, if checkpoints are enabled.
+pub async fn preprocess(datasets: Vec<Dataset>, settings: PreprocessingSettings<'_>, resume: bool) -> Result<Engine, DrinoError> {
+    let PreprocessingSettings { merge: merge_config, simplify: simplify_config, routing: routing_config, .. } = settings;
     let mut files_to_clean_up: Vec<PathBuf> = vec![];
+    let spill = Spill::in_work_dir(routing_config.memory_budget);
+
+    // A run that ran out of memory can be resumed with a lower budget
+    let routing_settings = RoutingConfig { memory_budget: None, ..routing_config.clone() };
+    let checked = serde_json::to_value((&datasets, merge_config, simplify_config, &routing_settings)).map_err(CheckpointError::from)?;
+    let checkpoints = Checkpoints::open(checked, resume)?;
 
-    let result = preprocess_inner(datasets, &mut files_to_clean_up);
+    let result = preprocess_inner(datasets, settings, checkpoints, &mut files_to_clean_up).await;
 
     clean_up(files_to_clean_up);
+    if let Err(err) = spill.clean_up() {
+        warn!(target: "preprocessing", "Couldn't remove the temporary files of the memory budget: {}", err);
+    }
 
     result
 }
 
-fn preprocess_inner(
+#[instrument(name = "preprocess", skip_all)]
+async fn preprocess_inner(
     datasets: Vec<Dataset>,
+    settings: PreprocessingSettings<'_>,
+    mut checkpoints: Checkpoints,
     files_to_clean_up: &mut Vec<PathBuf>,
-) -> Result<ALGORITHM, DrinoError> {
+) -> Result<Engine, DrinoError> {
+    let PreprocessingSettings { merge: merge_config, simplify: simplify_config, routing: routing_config, modes, html_validation_report } = settings;
     info!(target: "preprocessing", "Starting preprocessing");
     let preprocessing_start_time = SystemTime::now();
 
-    let preprocessing_input =
-        logging::run_with_spinner("preprocessing", "Fetching and importing datasets", || {
-            let rt = Runtime::new().unwrap();
-            rt.block_on(async {
-                if datasets.len() > 1 {
-                    todo!("Using multiple datasets is not yet supported")
-                }
-                match datasets.len() {
-                    0 => {
-                        Err(DrinoError::Config(ConfigError::NoDatasets()))
-                    }
-                    2.. => {
-                        todo!("Using multiple datasets is not yet supported")
-                    },
-                    1 => {
-                        let datasets = datasets.into_iter().take(1);
-
-                        let results = futures::stream::iter(datasets)
-                            .then(|dataset| async move {
-                                let fetch_out = fetch_dataset(dataset).await?;
-                                let import_out = import_data(fetch_out).await?;
-                                let validated = validate_data(import_out).await?;
-                                Ok::<ValidateStepOutput, DrinoError>(validated)
-                            })
-                            .inspect_err(|err| {
-                                error!("{}", err);
-                            })
-                            .collect::<Vec<Result<ValidateStepOutput, DrinoError>>>()
-                            .await
-                            .into_iter()
-                            .collect::<Result<Vec<ValidateStepOutput>, DrinoError>>()?;
-
-                        results.iter().for_each(|result| match &result.extra {
-                            ImportStepExtra::Gtfs {
-                                temporary_files, ..
-                            } => temporary_files
-                                .iter()
-                                .for_each(|f| files_to_clean_up.push(f.clone())),
-                        });
-
-                        let merged = merge(results).await?;
-                        let simplified = simplify(merged).await?;
-
-                        Ok::<PreprocessingInput, DrinoError>(simplified)
+    // Each step reports its own progress below the progress of all of them
+    let progress = ProgressBars { target: "preprocessing" };
+    let steps = progress.start("Preprocessing (fetching, merging, simplifying, building the routing data)", 4);
+
+    // Simplify writes its results to the working directory, where they are read again like by
+    // the commands that work with the preprocessed data
+    let preprocessing_input = if checkpoints.completed(Step::Simplify) {
+        steps.inc(3);
+        read_simplified(paths::work_dir())?
+    } else {
+        let merged = if checkpoints.completed(Step::Merge) {
+            steps.inc(2);
+            read_merged(&checkpoints.dir(Step::Merge).unwrap())?
+        } else {
+            let validated = if checkpoints.completed(Step::Import) {
+                read_validated(datasets, &checkpoints.dir(Step::Import).unwrap())?
+            } else {
+                let validated = fetch_and_validate(datasets.clone(), modes, html_validation_report, files_to_clean_up, &progress).await?;
+                match checkpoints.dir(Step::Import) {
+                    // Continues with the written tables, so that the imports aren't read twice
+                    Some(dir) => {
+                        write_validated(&validated, &dir)?;
+                        checkpoints.complete(Step::Import)?;
+                        read_validated(datasets, &dir)?
                     }
+                    None => validated,
                 }
-            })
-        })?;
+            };
+            steps.inc(1);
+
+            let merged = merge(validated, merge_config, &progress).await?;
+            let merged = match checkpoints.dir(Step::Merge) {
+                Some(dir) => {
+                    write_merged(&merged, &dir)?;
+                    checkpoints.complete(Step::Merge)?;
+                    read_merged(&dir)?
+                }
+                None => merged,
+            };
+            steps.inc(1);
+            merged
+        };
+
+        let preprocessing_input = simplify(merged, simplify_config).await?;
+        checkpoints.complete(Step::Simplify)?;
+        steps.inc(1);
+        preprocessing_input
+    };
+
+    // The remaining steps are CPU-bound, so keep them off the async worker threads. Blocking tasks
+    // don't inherit the current span, so it is entered again there.
+    let routing_config = routing_config.clone();
+    let modes = modes.clone();
+    let span = Span::current();
+    let preprocessing_result = tokio::task::spawn_blocking(move || span.in_scope(|| build_algorithm(preprocessing_input, &routing_config, &modes)))
+        .await
+        .expect("Preprocessing task panicked")?;
+    steps.inc(1);
+    drop(steps);
+    checkpoints.remove()?;
+
+    let elapsed = indicatif::HumanDuration(preprocessing_start_time.elapsed().unwrap());
+    info!(target: "preprocessing", "Preprocessing finished in {}", elapsed);
+
+    // Peaks close to the memory of the machine tell which step to look at before a larger feed
+    // doesn't fit anymore
+    let peaks = memory::take_peaks();
+    if !peaks.is_empty() {
+        info!(
+            target: "preprocessing",
+            "Peak memory by step: {}",
+            peaks.iter().map(|(step, bytes)| format!("{} {}", step, HumanBytes(*bytes))).collect::<Vec<_>>().join(", "),
+        );
+        peaks.iter().for_each(|(step, bytes)| metrics::record(&format!("peak_memory_{step}"), *bytes as f64));
+        append_to_quality_history()?;
+    }
+
+    Ok(preprocessing_result)
+}
+
+/// Fetches, imports and validates the datasets, without merging them. Returns the number of
+/// datasets that are skipped due to validation errors.
+pub async fn validate(
+    datasets: Vec<Dataset>,
+    modes: &ModeRegistry,
+    html_validation_report: bool,
+) -> Result<usize, DrinoError> {
+    let mut files_to_clean_up: Vec<PathBuf> = vec![];
+
+    let progress = ProgressBars { target: "validation" };
+    let result = fetch_and_validate(datasets, modes, html_validation_report, &mut files_to_clean_up, &progress).await;
+
+    clean_up(files_to_clean_up);
+
+    Ok(result?.iter().filter(|validated| validated.skip).count())
+}
+
+/// Datasets are fetched and imported concurrently, each one is validated as soon as it is
+/// imported. Results keep the order of the config, which is the order datasets are merged in.
+async fn fetch_and_validate(
+    datasets: Vec<Dataset>,
+    modes: &ModeRegistry,
+    html_validation_report: bool,
+    files_to_clean_up: &mut Vec<PathBuf>,
+    progress: &dyn ProgressSink,
+) -> Result<Vec<ValidateStepOutput>, DrinoError> {
+    if datasets.is_empty() {
+        return Err(DrinoError::Config(ConfigError::NoDatasets()));
+    }
+
+    let task = progress.start(&format!("Fetching, importing and validating {} datasets", datasets.len()), datasets.len() as u64);
+    let task = task.as_ref();
 
-    // TODO: Merge datasets (with deduplication) and frequency reduce calender times
+    let results = futures::stream::iter(datasets)
+        .map(|dataset| {
+            // Fetching, importing and validating of each dataset are recorded below its own span
+            let span = info_span!("dataset", id = %dataset.id);
+            async move {
+                let fetch_out = fetch_dataset(dataset, progress).await?;
+                let import_out = import_data(fetch_out, progress).await?;
+                let validated = validate_data(import_out, modes, html_validation_report, progress).await?;
+                task.inc(1);
+                Ok::<ValidateStepOutput, DrinoError>(validated)
+            }.instrument(span)
+        })
+        .buffered(MAX_CONCURRENT_DATASETS)
+        .inspect_err(|err| {
+            error!("{}", err);
+        })
+        .collect::<Vec<Result<ValidateStepOutput, DrinoError>>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<ValidateStepOutput>, DrinoError>>()?;
 
+    results.iter().for_each(|result| match &result.extra {
+        ImportStepExtra::Gtfs {
+            temporary_files, ..
+        } => temporary_files
+            .iter()
+            .for_each(|f| files_to_clean_up.push(f.clone())),
+    });
+
+    Ok(results)
+}
+
+#[instrument(skip_all)]
+fn build_algorithm(
+    preprocessing_input: PreprocessingInput,
+    routing_config: &RoutingConfig,
+    modes: &ModeRegistry,
+) -> Result<Engine, DrinoError> {
     // Cache important (and small) tables like stops to speed up computation
+    let spill = Spill::in_work_dir(routing_config.memory_budget);
     let cached_input = logging::run_with_spinner(
         "preprocessing",
         "Reading and caching timetable data",
         move || {
             Ok::<PreprocessingInput, DrinoError>(PreprocessingInput {
-                stops: preprocessing_input.stops.collect()?.lazy(),
-                stop_times: preprocessing_input.stop_times.collect()?.lazy(),
+                stops: spill.cache(preprocessing_input.stops, "stops")?,
+                stop_times: spill.cache(preprocessing_input.stop_times, "stop_times")?,
                 ..preprocessing_input
             })
         },
     )?;
 
     // Build visualization of lines
-    logging::run_with_spinner("visualization", "Building visualization for lines", || {
+    let direct_connections = logging::run_with_spinner("visualization", "Building visualization for lines", || {
         let direct_connections = DirectConnections::try_from(cached_input.clone())?;
         let table = direct_connections
             .to_geoarrow_lines(cached_input.stops.clone())
             .map_err(|e| PreprocessingError::BuildLines(e))?;
 
-        write_geoarrow_to_file("./data/tmp/global/lines.arrow".into(), FileType::IPC, table)
+        write_geoarrow_to_file(paths::tmp_dir().join("global").join("lines.arrow"), FileType::IPC, table)
             .map_err(|e| PreprocessingError::GeoArrow(e))?;
 
+        Ok::<DirectConnections, DrinoError>(direct_connections)
+    })?;
+
+    // Report how many connections at the busiest stations can be made, so that a minimum transfer
+    // time that doesn't fit the timetable stands out
+    logging::run_with_spinner("preprocessing", "Checking transfer feasibility at hubs", || {
+        let min_transfer_time = TimeDelta::minutes(routing_config.min_transfer_minutes as i64);
+        let report = TransferFeasibilityReport::with_slack(&cached_input, min_transfer_time, &transfer_slack(modes)?)?;
+        info!(
+            target: "preprocessing",
+            "{} of {} connections at the {} busiest stations are feasible with a minimum transfer time of {} min",
+            report.feasible(), report.connections(), report.hubs.len(), routing_config.min_transfer_minutes,
+        );
+
+        write_df_to_file(paths::tmp_dir().join("global").join("transfer_feasibility.parquet"), FileType::PARQUET, report.to_frame()?)?;
+
         Ok::<(), DrinoError>(())
     })?;
 
-    let preprocessing_result = ALGORITHM::preprocess(cached_input, true)?;
+    let original_ids = cached_input.original_ids.clone();
+    let calendar = ServiceCalendar::new(&cached_input)?;
+    let engine = match routing_config.mode {
+        RoutingMode::Journeys => Engine::Journeys(ALGORITHM::preprocess(cached_input.clone(), routing_config, true, &ProgressBars { target: "preprocessing" })?),
+        RoutingMode::TimetableLookup => {
+            info!(target: "preprocessing", "Skipping preprocessing for journey planning in timetable lookup mode");
+            Engine::TimetableLookup(TimetableLookup::new(direct_connections, calendar.clone()))
+        }
+    };
 
-    let elapsed = indicatif::HumanDuration(preprocessing_start_time.elapsed().unwrap());
-    info!(target: "preprocessing", "Preprocessing finished in {}", elapsed);
+    // Compare indicators of the data quality to earlier builds, so that a broken dataset or a bug
+    // in the pipeline stands out even if preprocessing succeeds
+    logging::run_with_spinner("preprocessing", "Comparing data quality to earlier builds", || {
+        metrics::record("trips_per_day", quality::trips_per_day(&cached_input)?);
 
-    Ok(preprocessing_result)
+        let quality_dir = paths::preprocessing_dir().join("quality");
+        // Journeys with transfers need a routing algorithm, which the timetable lookup does without
+        if let Engine::Journeys(algorithm) = &engine {
+            let od_sample = quality::read_or_create_od_sample(&quality_dir.join("od_sample.csv"), &original_ids)?;
+            // On the date with the most services, so that the sample compares across builds
+            let not_running = calendar.busiest_date().map(|date| calendar.trips_not_running(date)).unwrap_or_default();
+            if let Some(mean_transfers) = quality::mean_transfers(algorithm, &od_sample, &not_running) {
+                metrics::record("mean_transfers", mean_transfers);
+            }
+        }
+
+        append_to_quality_history()
+    })?;
+
+    warm_up(&engine, &routing_config.warm_up_stops, &original_ids)?;
+    Ok(engine)
+}
+
+/// Looks up the stops of the config before serving, so that the first queries after a deploy
+/// don't wait for it. Stops are given by their id in the source dataset prefixed with the dataset
+/// id, unknown ones are skipped.
+pub(crate) fn warm_up(engine: &Engine, stops: &[String], original_ids: &OriginalIds) -> Result<(), DrinoError> {
+    if stops.is_empty() {
+        return Ok(());
+    }
+    let Engine::TimetableLookup(lookup) = engine else {
+        warn!(target: "preprocessing", "Ignoring the stops to warm up, only the timetable lookup has caches");
+        return Ok(());
+    };
+
+    let stops: Vec<StopId> = stops.iter()
+        .filter_map(|stop| {
+            let id = original_ids.stops.get(stop);
+            if id.is_none() {
+                warn!(target: "preprocessing", "Not warming up unknown stop {stop}");
+            }
+            id
+        })
+        .collect();
+    if !stops.is_empty() {
+        logging::run_with_spinner("preprocessing", "Warming up caches of frequently queried stops", || lookup.warm_up(&stops))?;
+    }
+    Ok(())
+}
+
+/// Appends the metrics recorded since the last call to the history of earlier builds and warns
+/// about the ones that changed a lot
+fn append_to_quality_history() -> Result<(), DrinoError> {
+    let history = paths::preprocessing_dir().join("quality").join("history.csv");
+    let drifts = metrics::append_to_history(&history, Utc::now(), &metrics::take())?;
+    drifts.iter().for_each(|drift| warn!(
+        target: "preprocessing",
+        "{} changed by {:+.0}% since the previous build ({} -> {})",
+        drift.metric, drift.change() * 100.0, drift.previous, drift.current,
+    ));
+
+    Ok(())
+}
+
+// The transfer slack of the mode of every trip that has one
+fn transfer_slack(modes: &ModeRegistry) -> Result<HashMap<TripId, TimeDelta>, DrinoError> {
+    if modes.modes().iter().all(|mode| mode.transfer_slack_minutes == 0) {
+        return Ok(HashMap::new());
+    }
+
+    // Route types are only in the written tables, the routing input doesn't need them
+    let scan = |name: &str| LazyFrame::scan_parquet(paths::tmp_dir().join("simplify").join(name), Default::default());
+    let trips = scan("trips.parquet")?
+        .select([col("trip_id"), col("dataset_id"), col("route_id_in_dataset")])
+        .join(
+            scan("routes.parquet")?.select([col("dataset_id"), col("route_id_in_dataset"), col("route_type").cast(DataType::UInt32)]),
+            [col("dataset_id"), col("route_id_in_dataset")],
+            [col("dataset_id"), col("route_id_in_dataset")],
+            JoinArgs::new(JoinType::Inner),
+        )
+        .collect()?;
+
+    let slack = trips.column("trip_id")?.u32()?.iter()
+        .zip(trips.column("route_type")?.u32()?.iter())
+        .filter_map(|(trip, route_type)| {
+            let minutes = modes.mode_of(route_type?)?.transfer_slack_minutes;
+            (minutes > 0).then(|| Some((TripId(trip?), TimeDelta::minutes(minutes as i64))))?
+        })
+        .collect();
+    Ok(slack)
 }
 
 /// Cleans up files that were created during preprocessing
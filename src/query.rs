@@ -0,0 +1,519 @@
+use crate::bootstrap_config::{JourneyOptions, OutputFormat, RealtimeArgs};
+use crate::realtime::{status_name, TripUpdates};
+use crate::DrinoError;
+use chrono::{DateTime, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Utc};
+use chrono_tz::Tz;
+use common::types::config::RoutingConfig;
+use common::types::dataset::DataSource;
+use common::types::mode::ModeRegistry;
+use common::types::{StopId, TripId};
+use common::util::speed::Speed;
+use common::util::{logging, paths};
+use data_harvester::realtime::{fetch_alerts, fetch_vehicle_positions};
+use data_harvester::step5_simplify::{read_simplified_from, simplified_dir};
+use hashbrown::{HashMap, HashSet};
+use log::{info, warn};
+use polars::prelude::{col, DataType, LazyFrame};
+use routing::accessibility::{AccessibilityInfo, AccessibilitySummary};
+use routing::algorithm::QueryError;
+use routing::algorithm::{EarliestArrival, JourneyPlanner, PreprocessingInput, QueryOptions};
+use routing::bikes::BikeCarriage;
+use routing::cost::{CostBreakdown, CostInfo};
+use routing::direct_connections::DirectConnections;
+use routing::geojson::{GeoFeatures, GeoLeg, StopCoordinates};
+use routing::itinerary::{Itinerary, ItineraryLeg};
+use routing::park_and_ride::{AccessLeg, ParkAndRide, ParkAndRideJourney};
+use routing::raptor::RaptorAlgorithm;
+use routing::realtime::alerts::{ActivePeriod, Alert, Alerts, AlertsFeed};
+use routing::realtime::vehicles::{VehiclePosition, VehiclePositions, VehiclePositionsFeed};
+use routing::realtime::journeys::{evaluate, JourneyUpdate, LegStatus};
+use routing::realtime::RealtimeTimetable;
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The names of the stops in the timetable of an earlier run, by their id. They are only in the
+/// written table, the routing input doesn't need them.
+pub(crate) fn stop_names() -> Result<Vec<String>, DrinoError> {
+    Ok(LazyFrame::scan_parquet(
+        paths::tmp_dir().join("simplify").join("stops.parquet"),
+        Default::default(),
+    )?
+        .select([col("stop_name")])
+        .collect()?
+        .column("stop_name")?
+        .str()?
+        .iter()
+        .map(|name| name.unwrap_or_default().to_string())
+        .collect())
+}
+
+/// The coordinates (lat, lon) of the stops of the routing input, by their id
+pub(crate) fn stop_coordinates(input: &PreprocessingInput) -> Result<Vec<StopCoordinates>, DrinoError> {
+    let stops = input.stops.clone()
+        .sort(["stop_id"], Default::default())
+        .select([col("lat").cast(DataType::Float32), col("lon").cast(DataType::Float32)])
+        .collect()?;
+    Ok(stops.column("lat")?.f32()?.iter().zip(stops.column("lon")?.f32()?.iter()).collect())
+}
+
+/// Finds the journey with the earliest arrival in the timetable of an earlier run and formats it
+/// for the terminal. Stops are looked up by their id in the source dataset first, then by their
+/// name. If several stops share a name, the first one is used.
+///
+/// Every journey is followed by what it is made of, with the modes of routes by their route type.
+/// Trips of modes with a transfer slack are only boarded if it is left on top of the minimum
+/// transfer time.
+///
+/// The trips of suspended routes are masked during the search. The journey is then compared to
+/// the one without the suspension, so that the impact of a planned closure can be previewed.
+///
+/// Trip updates are fetched like the server polls them and applied to the timetable of the day
+/// before the search, so the journey reflects delays, cancelled trips and skipped stops. Every leg is
+/// then shown with its expected times at all of its stops and what the updates say about it, e.g.
+/// "(delayed)". Service alerts that are active while a leg is travelled are shown below it, as is the vehicle
+/// currently serving a ride.
+///
+/// Wheelchair users get a journey of step-free trips and stops. Since the data is often
+/// incomplete, they get the regular journey with a warning if there is none. With a bike, only
+/// trips that allow bikes are boarded and transfers are cycled. Stops, routes and agencies can be
+/// avoided, and the number of transfers and the time left for each of them can be limited. Every
+/// transfer takes at least the minimum transfer time of the `routing` config.
+///
+/// Journeys may also start or end with driving or cycling to a P+R stop of the `routing` config.
+///
+/// As GeoJSON, the journey is a feature collection with a line for every leg, see
+/// [routing::geojson]. Warnings and the comparison to the journey without suspensions are logged
+/// instead, so that only the journey is printed.
+#[allow(clippy::too_many_arguments)]
+pub async fn query(
+    from: &str,
+    to: &str,
+    at: NaiveDateTime,
+    suspended_routes: &[String],
+    options: &JourneyOptions,
+    routing: &RoutingConfig,
+    modes: &ModeRegistry,
+    realtime: &RealtimeArgs,
+    format: OutputFormat,
+) -> Result<String, DrinoError> {
+    let tables = simplified_dir(paths::work_dir());
+    let input = read_simplified_from(&tables)?.running_on(at.date())?;
+
+    let stop_names = stop_names()?;
+    let coordinates = match format {
+        OutputFormat::Text => vec![],
+        OutputFormat::Geojson => stop_coordinates(&input)?,
+    };
+    let geo_features = (format == OutputFormat::Geojson).then(|| GeoFeatures::new(&input.original_ids, &stop_names, &coordinates));
+
+    let find_stop = |stop: &str| input.original_ids.stops.get(stop)
+        .or_else(|| stop_names.iter()
+            .position(|name| name.eq_ignore_ascii_case(stop))
+            .map(|id| StopId(id as u32)))
+        .ok_or_else(|| DrinoError::UnknownStop(stop.to_string()));
+    let start = find_stop(from)?;
+    let target = find_stop(to)?;
+    let suspended_trips = trips_of_routes(suspended_routes, &tables)?;
+    let avoided_stops = options.avoided_stops.iter().map(|stop| find_stop(stop)).collect::<Result<_, _>>()?;
+    let cost_info = CostInfo::from_frames(input.stops.clone(), input.trips.clone(), modes)?;
+    let min_transfer_time = TimeDelta::minutes(routing.min_transfer_minutes as i64);
+    let query_options = query_options(options, avoided_stops, min_transfer_time, Arc::new(cost_info.transfer_slack()), &tables)?;
+    // Only journeys that drive or cycle need the P+R stops
+    let park_and_ride = options.uses_park_and_ride()
+        .then(|| ParkAndRide::from_config(&routing.park_and_ride, input.stops.clone(), &input.original_ids))
+        .transpose()?;
+
+    let (mut algorithm, direct_connections) = logging::run_with_spinner("query", "Building routing data for the day", || {
+        let direct_connections = DirectConnections::try_from(input.clone())?;
+        let algorithm = RaptorAlgorithm::preprocess(input.clone(), direct_connections.clone())?;
+        Ok::<(RaptorAlgorithm, DirectConnections), DrinoError>((algorithm, direct_connections))
+    })?;
+    let service_day_start = service_day_start(at, realtime.timezone);
+    let trip_updates = TripUpdates::fetch(&realtime.trip_updates).await?;
+    if !trip_updates.is_empty() {
+        apply_trip_updates(&mut algorithm, &input, &trip_updates, service_day_start);
+    }
+    let alerts = fetch_all_alerts(&input, &realtime.alerts, &tables).await?;
+    let vehicles = fetch_all_vehicle_positions(&input, &realtime.vehicle_positions).await?;
+
+    let format_journey = |park_and_ride_journey: &ParkAndRideJourney| {
+        let ParkAndRideJourney { access, journey, egress } = park_and_ride_journey;
+        let itinerary = Itinerary::reconstruct(journey, &direct_connections)?;
+        if let Some(geo_features) = &geo_features {
+            let legs = access.iter().map(GeoLeg::from)
+                .chain(itinerary.legs.iter().map(GeoLeg::from))
+                .chain(egress.iter().map(GeoLeg::from));
+            let mut collection = geo_features.legs(legs, at.date());
+            collection.foreign_members = Some(geo_features.journey_members(park_and_ride_journey.departure(), park_and_ride_journey.arrival(), at.date()));
+            return Ok(collection.to_string() + "\n");
+        }
+        let update = evaluate(journey, &trip_updates.feeds(), &input.original_ids, &direct_connections, service_day_start)?;
+        let realtime = RealtimeOfLegs {
+            update,
+            alerts: alerts.for_itinerary(&itinerary, service_day_start),
+            vehicles: vehicles.for_itinerary(&itinerary),
+            timezone: realtime.timezone,
+            language: realtime.language.as_deref(),
+        };
+        Ok::<String, DrinoError>(
+            format_access_leg(access.as_ref(), &stop_names)
+                + &format_itinerary(&itinerary, &input, &stop_names, &realtime)
+                + &format_access_leg(egress.as_ref(), &stop_names)
+                + &format_breakdown(&cost_info.breakdown(journey))
+        )
+    };
+
+    // Times of the timetable are relative to the start of the service day
+    let departure = DateTime::<Utc>::UNIX_EPOCH + (at - at.date().and_time(NaiveTime::MIN));
+    let accessibility = options.wheelchair
+        .then(|| AccessibilityInfo::from_frames(input.stops.clone(), input.trips.clone()))
+        .transpose()?;
+    let bikes = options.bike.then(|| BikeCarriage::from_frame(input.trips.clone())).transpose()?;
+    let search_input = |suspended_trips: HashSet<TripId>| {
+        let search_input = EarliestArrival::new(start, departure).with_options(&query_options).suspending(suspended_trips);
+        match &bikes {
+            Some(bikes) => search_input.with_bike(bikes, Speed(options.cycling_speed)),
+            None => search_input,
+        }
+    };
+    // Only journeys that drive or cycle try the P+R stops
+    let plan = |search_input: EarliestArrival| match &park_and_ride {
+        Some(park_and_ride) => park_and_ride.query_ea(&algorithm, search_input, target),
+        None => algorithm.query_ea_with(search_input, target)
+            .map(|journey| ParkAndRideJourney { access: None, journey, egress: None }),
+    };
+    // The journey and a warning if it isn't step-free although it should be
+    let search = |suspended_trips: HashSet<TripId>| {
+        let Some(accessibility) = &accessibility else {
+            return Ok((plan(search_input(suspended_trips))?, String::new()));
+        };
+        match plan(search_input(suspended_trips.clone()).step_free(accessibility)) {
+            Ok(journey) => Ok((journey, String::new())),
+            Err(QueryError::NoRouteFound) => {
+                warn!(target: "query", "No step-free journey found, falling back to one that may require assistance");
+                let journey = plan(search_input(suspended_trips))?;
+                let warning = match accessibility.summarize(&journey.journey) {
+                    AccessibilitySummary::AssistanceRequired => "Warning: No step-free journey found, this one requires assistance\n",
+                    _ => "Warning: No step-free journey found, this one isn't known to be step-free\n",
+                };
+                Ok((journey, warning.to_string()))
+            }
+            Err(err) => Err(err),
+        }
+    };
+
+    // Only the journey is printed as GeoJSON, the notes about it are logged
+    let note = |note: String| match format {
+        OutputFormat::Text => note,
+        OutputFormat::Geojson => {
+            if !note.is_empty() {
+                warn!(target: "query", "{}", note.trim_end());
+            }
+            String::new()
+        }
+    };
+
+    if suspended_routes.is_empty() {
+        let (journey, warning) = search(HashSet::new())?;
+        return Ok(note(warning) + &format_journey(&journey)?);
+    }
+
+    let regular = search(HashSet::new()).ok().map(|(journey, _)| journey);
+    let formatted = match search(suspended_trips) {
+        Ok((journey, warning)) => {
+            let delay = regular
+                .and_then(|regular| Some(journey.arrival()? - regular.arrival()?))
+                .map(|delay| format!("Arrives {} min later than without the suspension\n", delay.num_minutes()))
+                .unwrap_or_default();
+            note(warning) + &format_journey(&journey)? + &note(delay)
+        }
+        Err(QueryError::NoRouteFound) if regular.is_some() => {
+            let unreachable = note("Not reachable while the routes are suspended\n".to_string());
+            match &geo_features {
+                Some(geo_features) => geo_features.legs([], at.date()).to_string() + "\n",
+                None => unreachable,
+            }
+        }
+        Err(err) => return Err(err.into()),
+    };
+    Ok(formatted)
+}
+
+/// The constraints of the options for the planners, with the trips of the avoided routes and
+/// agencies looked up. Stops are looked up by the caller, since the server finds them differently.
+/// Transfers take at least `min_transfer_time`, the minimum transfer time of the config, even if
+/// the options leave less time for them. Trips of modes with a transfer slack need it on top.
+pub(crate) fn query_options(
+    options: &JourneyOptions,
+    avoided_stops: HashSet<StopId>,
+    min_transfer_time: TimeDelta,
+    transfer_slack: Arc<HashMap<TripId, TimeDelta>>,
+    tables: &Path,
+) -> Result<QueryOptions, DrinoError> {
+    Ok(QueryOptions {
+        max_transfers: options.max_transfers,
+        avoided_stops,
+        avoided_trips: trips_of_routes(&options.avoided_routes, tables)?.into_iter()
+            .chain(trips_of_agencies(&options.avoided_agencies, tables)?)
+            .collect(),
+        min_transfer_buffer: TimeDelta::minutes(options.min_transfer_buffer as i64).max(min_transfer_time),
+        transfer_slack,
+        access_mode: options.access_mode,
+        egress_mode: options.egress_mode,
+    })
+}
+
+// On days when the clocks change, midnight might not exist, but then the timetable doesn't start
+// there either
+pub(crate) fn service_day_start(at: NaiveDateTime, timezone: Tz) -> DateTime<Utc> {
+    let midnight = at.date().and_time(NaiveTime::MIN);
+    timezone.from_local_datetime(&midnight).earliest()
+        .map(|start| start.to_utc())
+        .unwrap_or_else(|| midnight.and_utc())
+}
+
+fn apply_trip_updates(
+    algorithm: &mut RaptorAlgorithm,
+    input: &PreprocessingInput,
+    trip_updates: &TripUpdates,
+    service_day_start: DateTime<Utc>,
+) {
+    let applied = RealtimeTimetable::new(algorithm).apply(algorithm, &trip_updates.feeds(), &input.original_ids, service_day_start);
+    info!(
+        target: "query",
+        "Applied {} trip updates and {} cancellations, {} trips are not running on the day and {} stop updates didn't match",
+        applied.updated, applied.cancelled, applied.unknown_trips, applied.unmatched_stops,
+    );
+}
+
+async fn fetch_all_alerts(input: &PreprocessingInput, feeds: &[(String, String)], tables: &Path) -> Result<Alerts, DrinoError> {
+    let mut alerts = vec![];
+    for (dataset_id, src) in feeds {
+        let feed = fetch_alerts(&DataSource::from_url_or_path(src)).await?;
+        alerts.push(AlertsFeed { dataset_id: dataset_id.clone(), alerts: feed });
+    }
+    let route_of_trips = if feeds.is_empty() { HashMap::new() } else { route_of_trips(tables)?.into_iter().collect() };
+    Ok(Alerts::new(alerts, &input.original_ids, route_of_trips))
+}
+
+async fn fetch_all_vehicle_positions(input: &PreprocessingInput, feeds: &[(String, String)]) -> Result<VehiclePositions, DrinoError> {
+    let mut positions = vec![];
+    for (dataset_id, src) in feeds {
+        let feed = fetch_vehicle_positions(&DataSource::from_url_or_path(src)).await?;
+        positions.push(VehiclePositionsFeed { dataset_id: dataset_id.clone(), positions: feed });
+    }
+    Ok(VehiclePositions::new(positions, &input.original_ids))
+}
+
+// All trips of the routes, which are given by their id in the source dataset prefixed with the id
+// of the dataset like the stops. The routes are read from the simplified timetable in `tables`.
+pub(crate) fn trips_of_routes(routes: &[String], tables: &Path) -> Result<HashSet<TripId>, DrinoError> {
+    if routes.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let route_of_trips = route_of_trips(tables)?;
+    if let Some(unknown) = routes.iter().find(|route| !route_of_trips.iter().any(|(_, of_trip)| of_trip == *route)) {
+        return Err(DrinoError::UnknownRoute(unknown.clone()));
+    }
+
+    let suspended_trips = route_of_trips.into_iter()
+        .filter(|(_, route)| routes.contains(route))
+        .map(|(trip, _)| trip)
+        .collect();
+    Ok(suspended_trips)
+}
+
+// All trips of the routes of the agencies, which are given like the routes
+fn trips_of_agencies(agencies: &[String], tables: &Path) -> Result<HashSet<TripId>, DrinoError> {
+    if agencies.is_empty() {
+        return Ok(HashSet::new());
+    }
+
+    let routes = LazyFrame::scan_parquet(tables.join("routes.parquet"), Default::default())?
+        .select([col("dataset_id"), col("route_id_in_dataset"), col("agency_id_in_dataset")])
+        .collect()?;
+    let agency_of_routes: Vec<(String, String)> = routes.column("dataset_id")?.str()?.iter()
+        .zip(routes.column("route_id_in_dataset")?.str()?.iter())
+        .zip(routes.column("agency_id_in_dataset")?.str()?.iter())
+        .map(|((dataset_id, route_id), agency_id)| {
+            let dataset_id = dataset_id.unwrap_or_default();
+            (format!("{}:{}", dataset_id, route_id.unwrap_or_default()), format!("{}:{}", dataset_id, agency_id.unwrap_or_default()))
+        })
+        .collect();
+    if let Some(unknown) = agencies.iter().find(|agency| !agency_of_routes.iter().any(|(_, of_route)| of_route == *agency)) {
+        return Err(DrinoError::UnknownAgency(unknown.clone()));
+    }
+
+    // Routes of the agencies may have no trips, so they aren't checked like given routes
+    let routes: HashSet<String> = agency_of_routes.into_iter()
+        .filter(|(_, agency)| agencies.contains(agency))
+        .map(|(route, _)| route)
+        .collect();
+    let trips = route_of_trips(tables)?.into_iter()
+        .filter(|(_, route)| routes.contains(route))
+        .map(|(trip, _)| trip)
+        .collect();
+    Ok(trips)
+}
+
+/// The route of every trip of the simplified timetable in `tables`, by its id in the source dataset
+/// prefixed with the id of the dataset
+pub(crate) fn route_of_trips(tables: &Path) -> Result<Vec<(TripId, String)>, DrinoError> {
+    let trips = LazyFrame::scan_parquet(tables.join("trips.parquet"), Default::default())?
+        .select([col("trip_id"), col("dataset_id"), col("route_id_in_dataset")])
+        .collect()?;
+
+    let route_of_trips = trips.column("trip_id")?.u32()?.iter()
+        .zip(trips.column("dataset_id")?.str()?.iter())
+        .zip(trips.column("route_id_in_dataset")?.str()?.iter())
+        .filter_map(|((trip, dataset_id), route_id)| Some((
+            TripId(trip?),
+            format!("{}:{}", dataset_id.unwrap_or_default(), route_id.unwrap_or_default()),
+        )))
+        .collect();
+    Ok(route_of_trips)
+}
+
+// The expected times, alerts and vehicles of every leg of an itinerary and how to show their texts
+// and times
+struct RealtimeOfLegs<'a> {
+    update: JourneyUpdate,
+    alerts: Vec<Vec<&'a Alert>>,
+    vehicles: Vec<Option<&'a VehiclePosition>>,
+    timezone: Tz,
+    language: Option<&'a str>,
+}
+
+// One line per leg with its expected times, e.g. "08:03-08:10  Hauptbahnhof -> Stadtmitte (trip
+// vvs:4711)" or "08:05-08:12  Hauptbahnhof -> Stadtmitte (trip vvs:4711, delayed)", followed by
+// the intermediate stops of rides, e.g. "      08:06  Rotebühlplatz", the vehicle of the ride, e.g.
+// "      @ S1 at 48.7840, 9.1820 (08:04)", and the alerts of the leg, e.g.
+// "      ! Construction work (until 2024-05-03 18:00)"
+fn format_itinerary(itinerary: &Itinerary, input: &PreprocessingInput, stop_names: &[String], realtime: &RealtimeOfLegs) -> String {
+    let stop_name = |stop: &StopId| stop_names.get(stop.0 as usize).map(String::as_str).unwrap_or("?");
+
+    let mut formatted = String::new();
+    for (idx, leg) in itinerary.legs.iter().enumerate() {
+        let update = realtime.update.legs.get(idx);
+        let _ = match leg {
+            ItineraryLeg::Ride { trip, boarding_stop, alight_stop, departure, arrival, intermediate_stops, .. } => {
+                let status = match update.map(|update| update.status) {
+                    None | Some(LegStatus::Scheduled) => String::new(),
+                    Some(status) => format!(", {}", status_name(status)),
+                };
+                let _ = writeln!(
+                    formatted,
+                    "{}-{}  {} -> {} (trip {}{})",
+                    format_time(update.and_then(|update| update.departure).unwrap_or(*departure)),
+                    format_time(update.and_then(|update| update.arrival).unwrap_or(*arrival)),
+                    stop_name(boarding_stop),
+                    stop_name(alight_stop),
+                    input.original_ids.trip(*trip).unwrap_or("?"),
+                    status,
+                );
+                // Skipped stops are shown without a time
+                intermediate_stops.iter().enumerate().try_for_each(|(stop_idx, call)| writeln!(
+                    formatted,
+                    "      {}  {}",
+                    match update {
+                        Some(update) => update.intermediate_stops.get(stop_idx).copied().flatten(),
+                        None => call.departure.or(call.arrival),
+                    }.map(format_time).unwrap_or_else(|| "     ".to_string()),
+                    stop_name(&call.stop),
+                ))
+            }
+            ItineraryLeg::Transfer { start, end, duration } => writeln!(
+                formatted,
+                "{:>5} min    {} -> {} (walk)",
+                duration.num_minutes(),
+                stop_name(start),
+                stop_name(end),
+            ),
+        };
+        if let Some(Some(vehicle)) = realtime.vehicles.get(idx) {
+            let _ = writeln!(
+                formatted,
+                "      @ {} at {:.4}, {:.4}{}",
+                vehicle.label.as_deref().or(vehicle.vehicle_id.as_deref()).unwrap_or("Vehicle"),
+                vehicle.lat,
+                vehicle.lon,
+                vehicle.timestamp.and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+                    .map(|time| format!(" ({})", time.with_timezone(&realtime.timezone).format("%H:%M")))
+                    .unwrap_or_default(),
+            );
+        }
+        for alert in realtime.alerts.get(idx).into_iter().flatten() {
+            let _ = writeln!(
+                formatted,
+                "      ! {}{}",
+                alert.header.get(realtime.language).unwrap_or(&alert.id),
+                format_active_periods(&alert.active_periods, realtime.timezone),
+            );
+            if let Some(description) = alert.description.get(realtime.language) {
+                let _ = writeln!(formatted, "        {}", description);
+            }
+        }
+    }
+    formatted
+}
+
+// E.g. " (from 2024-05-01 08:00 until 2024-05-03 18:00)", empty for alerts that are always active
+fn format_active_periods(periods: &[ActivePeriod], timezone: Tz) -> String {
+    let format = |seconds: i64| DateTime::from_timestamp(seconds, 0)
+        .map(|time| time.with_timezone(&timezone).format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default();
+    let periods = periods.iter()
+        .filter_map(|period| match (period.start, period.end) {
+            (Some(start), Some(end)) => Some(format!("from {} until {}", format(start), format(end))),
+            (Some(start), None) => Some(format!("from {}", format(start))),
+            (None, Some(end)) => Some(format!("until {}", format(end))),
+            (None, None) => None,
+        })
+        .collect::<Vec<_>>();
+    if periods.is_empty() {
+        return String::new();
+    }
+    format!(" ({})", periods.join(", "))
+}
+
+// A single line, e.g. "Riding 25 min (rail 15, tram 10), walking 2 min (110 m), waiting 3 min, 1 transfer"
+fn format_breakdown(breakdown: &CostBreakdown) -> String {
+    let in_vehicle = breakdown.in_vehicle.iter()
+        .map(|(mode, duration)| format!("{} {}", mode, duration.num_minutes()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let penalties = match breakdown.total_penalty().num_minutes() {
+        0 => String::new(),
+        minutes => format!(", {} min of penalties", minutes),
+    };
+
+    format!(
+        "Riding {} min ({}), walking {} min ({:.0} m), waiting {} min, {} transfer{}{}\n",
+        breakdown.total_in_vehicle().num_minutes(),
+        in_vehicle,
+        breakdown.walk_time.num_minutes(),
+        breakdown.walk_distance,
+        breakdown.wait.num_minutes(),
+        breakdown.transfers,
+        if breakdown.transfers == 1 { "" } else { "s" },
+        penalties,
+    )
+}
+
+// A single line like the walks of an itinerary, e.g. "   12 min    Home -> Park & Ride (car)",
+// empty without a leg
+fn format_access_leg(leg: Option<&AccessLeg>, stop_names: &[String]) -> String {
+    let Some(AccessLeg { mode, start, end, duration }) = leg else { return String::new() };
+    let stop_name = |stop: &StopId| stop_names.get(stop.0 as usize).map(String::as_str).unwrap_or("?");
+    format!("{:>5} min    {} -> {} ({})\n", duration.num_minutes(), stop_name(start), stop_name(end), mode)
+}
+
+// Time of day, with the number of days after the service day if the trip runs past midnight
+fn format_time(time: DateTime<Utc>) -> String {
+    match (time - DateTime::<Utc>::UNIX_EPOCH).num_days() {
+        0 => time.format("%H:%M").to_string(),
+        days => format!("{}+{}", time.format("%H:%M"), days),
+    }
+}
@@ -0,0 +1,90 @@
+//! The trip updates of the datasets, which `drino query` fetches once before its search and the
+//! server keeps polling. Both evaluate journeys with them, see [routing::realtime::journeys].
+
+use crate::DrinoError;
+use common::types::dataset::{DataSource, RealtimeFeed};
+use common::types::id_interner::OriginalIds;
+use common::types::TripId;
+use data_harvester::realtime::{fetch_trip_updates, poll_trip_updates};
+use log::debug;
+use routing::realtime::journeys::LegStatus;
+use routing::realtime::{TripUpdate, TripUpdatesFeed};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use tokio::sync::watch;
+
+/// The latest trip updates of the datasets that have a feed
+pub(crate) struct TripUpdates {
+    feeds: RwLock<HashMap<String, TripUpdatesFeed>>,
+    // Tells the sessions that a new message arrived
+    received: watch::Sender<()>,
+}
+
+impl TripUpdates {
+    pub(crate) fn new() -> Self {
+        Self { feeds: RwLock::default(), received: watch::channel(()).0 }
+    }
+
+    /// Fetches the feeds once, given by the ids of their datasets and their URLs or paths
+    pub(crate) async fn fetch(feeds: &[(String, String)]) -> Result<Self, DrinoError> {
+        let store = Self::new();
+        for (dataset_id, src) in feeds {
+            store.insert(dataset_id, fetch_trip_updates(&DataSource::from_url_or_path(src)).await?);
+        }
+        Ok(store)
+    }
+
+    /// Polls the trip updates feeds, given by the ids of their datasets
+    pub(crate) fn spawn_polling(self: &Arc<Self>, feeds: Vec<(String, RealtimeFeed)>) {
+        for (dataset_id, feed) in feeds {
+            let store = Arc::clone(self);
+            tokio::spawn(async move {
+                poll_trip_updates(&feed.src, feed.interval.into(), |updates| {
+                    store.insert(&dataset_id, updates);
+                    true
+                }).await;
+            });
+        }
+    }
+
+    // Replaces the message of the dataset received before
+    fn insert(&self, dataset_id: &str, updates: Vec<TripUpdate>) {
+        debug!(target: "realtime", "Received {} trip updates of {}", updates.len(), dataset_id);
+        self.feeds.write().unwrap().insert(dataset_id.to_string(), TripUpdatesFeed { dataset_id: dataset_id.to_string(), updates });
+        self.received.send_replace(());
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.feeds.read().unwrap().is_empty()
+    }
+
+    pub(crate) fn feeds(&self) -> Vec<TripUpdatesFeed> {
+        self.feeds.read().unwrap().values().cloned().collect()
+    }
+
+    /// Changes whenever a new message arrives
+    pub(crate) fn subscribe(&self) -> watch::Receiver<()> {
+        self.received.subscribe()
+    }
+
+    /// The trips that the feeds cancel, which planned journeys don't ride
+    pub(crate) fn cancelled_trips(&self, original_ids: &OriginalIds) -> HashSet<TripId> {
+        self.feeds.read().unwrap().values()
+            .flat_map(|feed| feed.updates.iter()
+                .filter(|update| update.cancelled)
+                .filter_map(|update| original_ids.find_trip(&format!("{}:{}", feed.dataset_id, update.trip_id))))
+            .collect()
+    }
+}
+
+/// What the trip updates say about a leg, as in the messages of followed journeys, the `status` of
+/// planned legs and the legs of `drino query`
+pub(crate) fn status_name(status: LegStatus) -> &'static str {
+    match status {
+        LegStatus::Scheduled => "scheduled",
+        LegStatus::Delayed => "delayed",
+        LegStatus::Cancelled => "cancelled",
+        LegStatus::StopSkipped => "stop-skipped",
+        LegStatus::ConnectionMissed => "connection-missed",
+    }
+}
@@ -0,0 +1,241 @@
+//! The gRPC service `drino.v1.Routing` of `proto/drino/v1/routing.proto` over HTTP/2 without TLS.
+//! Messages and the service are generated from the proto file by the build script.
+
+use super::{absolute_time, departure_in_timetable, PlanOptions, State, DEFAULT_DEPARTURE_LIMIT, DEFAULT_JOURNEY_LIMIT};
+use crate::query::service_day_start;
+use crate::bootstrap_config::JourneyOptions;
+use crate::Engine;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
+use common::util::speed::CYCLING_SPEED;
+use common::types::errors::ErrorCode;
+use common::types::StopId;
+use log::warn;
+use routing::algorithm::{AccessMode, QueryError};
+use routing::cost::CostBreakdown;
+use routing::journey::Leg;
+use routing::park_and_ride::{AccessLeg, ParkAndRideJourney};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{Code, Request, Response, Status};
+use visualization::api::problem::Problem;
+
+mod proto {
+    tonic::include_proto!("drino.v1");
+}
+
+use proto::routing_server::{Routing, RoutingServer};
+
+/// Accepts connections until the process is stopped. Clients have to speak HTTP/2 from the start.
+pub(super) async fn serve(state: Arc<State>, listener: TcpListener) {
+    let service = RoutingServer::new(RoutingService { state });
+    if let Err(err) = tonic::transport::Server::builder()
+        .add_service(service)
+        .serve_with_incoming(TcpListenerStream::new(listener))
+        .await
+    {
+        warn!(target: "server", "The gRPC service stopped: {}", err);
+    }
+}
+
+fn status(code: ErrorCode, message: impl Into<String>) -> Status {
+    let code = match code {
+        ErrorCode::NoCoverage => Code::InvalidArgument,
+        ErrorCode::DateOutOfRange => Code::OutOfRange,
+        ErrorCode::StopNotFound | ErrorCode::NoRouteFound | ErrorCode::NotFound => Code::NotFound,
+        ErrorCode::Timeout => Code::DeadlineExceeded,
+        ErrorCode::DataReloading => Code::Unavailable,
+        ErrorCode::Internal => Code::Internal,
+    };
+    Status::new(code, message)
+}
+
+fn query_status(err: QueryError) -> Status {
+    status(err.code(), err.to_string())
+}
+
+fn problem_status(problem: Problem) -> Status {
+    let message = problem.detail.unwrap_or_else(|| problem.title.to_string());
+    status(problem.code, message)
+}
+
+struct RoutingService {
+    state: Arc<State>,
+}
+
+#[tonic::async_trait]
+impl Routing for RoutingService {
+    async fn plan(&self, request: Request<proto::PlanRequest>) -> Result<Response<proto::PlanResponse>, Status> {
+        let state = self.state.clone();
+        blocking(move || plan(&state, request.into_inner())).await
+    }
+
+    async fn profile(&self, request: Request<proto::ProfileRequest>) -> Result<Response<proto::ProfileResponse>, Status> {
+        let state = self.state.clone();
+        blocking(move || profile(&state, request.into_inner())).await
+    }
+
+    async fn departures(&self, request: Request<proto::DeparturesRequest>) -> Result<Response<proto::DeparturesResponse>, Status> {
+        let state = self.state.clone();
+        blocking(move || departures(&state, request.into_inner())).await
+    }
+}
+
+// Searches block for a while, so they don't run on the tasks that serve connections
+async fn blocking<T: Send + 'static>(answer: impl FnOnce() -> Result<T, Status> + Send + 'static) -> Result<Response<T>, Status> {
+    tokio::task::spawn_blocking(answer)
+        .await
+        .map_err(|err| Status::internal(format!("The search failed: {}", err)))?
+        .map(Response::new)
+}
+
+// The local time of the timetable at a Unix time, with the start of its service day
+fn local_time(state: &State, unix_time: i64) -> Result<(NaiveDateTime, DateTime<Utc>), Status> {
+    let at = DateTime::from_timestamp(unix_time, 0)
+        .ok_or_else(|| Status::invalid_argument(format!("Invalid time {}", unix_time)))?
+        .with_timezone(&state.timezone)
+        .naive_local();
+    Ok((at, service_day_start(at, state.timezone)))
+}
+
+fn stop(state: &State, stop: &str) -> Result<StopId, Status> {
+    state.stops.find(stop).map_err(problem_status)
+}
+
+// Requests without options get the defaults of `drino query`
+fn journey_options(options: proto::QueryOptions) -> JourneyOptions {
+    JourneyOptions {
+        wheelchair: options.wheelchair,
+        bike: options.bike,
+        cycling_speed: options.cycling_speed.map_or(CYCLING_SPEED.0, f64::from),
+        max_transfers: options.max_transfers.map(|max_transfers| max_transfers as usize),
+        access_mode: access_mode(options.access_mode()),
+        egress_mode: access_mode(options.egress_mode()),
+        avoided_stops: options.avoided_stops,
+        avoided_routes: options.avoided_routes,
+        avoided_agencies: options.avoided_agencies,
+        // In seconds instead of minutes, see [plan_options]
+        min_transfer_buffer: 0,
+    }
+}
+
+fn access_mode(mode: proto::AccessMode) -> AccessMode {
+    match mode {
+        proto::AccessMode::Walk => AccessMode::Walk,
+        proto::AccessMode::Bike => AccessMode::Bike,
+        proto::AccessMode::Car => AccessMode::Car,
+    }
+}
+
+fn plan_options(state: &State, options: Option<proto::QueryOptions>) -> Result<PlanOptions, Status> {
+    let options = options.unwrap_or_default();
+    let min_transfer_buffer = TimeDelta::seconds(options.min_transfer_buffer_seconds as i64);
+    let mut plan_options = state.plan_options(&journey_options(options)).map_err(problem_status)?;
+    plan_options.query.min_transfer_buffer = min_transfer_buffer.max(state.min_transfer_time);
+    Ok(plan_options)
+}
+
+fn plan(state: &State, request: proto::PlanRequest) -> Result<proto::PlanResponse, Status> {
+    let (start, target) = (stop(state, &request.from)?, stop(state, &request.to)?);
+    let limit = request.limit.map_or(DEFAULT_JOURNEY_LIMIT, |limit| limit as usize);
+    let options = plan_options(state, request.options)?;
+    let (at, day_start) = local_time(state, request.departure_time)?;
+    let journeys = state.plan(start, target, at.date(), departure_in_timetable(at), &options, limit).map_err(query_status)?;
+    Ok(proto::PlanResponse { journeys: journeys.iter().map(|journey| encode_journey(state, journey, day_start)).collect() })
+}
+
+fn profile(state: &State, request: proto::ProfileRequest) -> Result<proto::ProfileResponse, Status> {
+    let (earliest, latest) = (request.earliest_departure_time, request.latest_departure_time);
+    if latest < earliest {
+        return Err(Status::invalid_argument("The latest departure is before the earliest"));
+    }
+    let (start, target) = (stop(state, &request.from)?, stop(state, &request.to)?);
+    let options = plan_options(state, request.options)?;
+    // The window is searched on the service day of its start
+    let (at, day_start) = local_time(state, earliest)?;
+    let earliest_departure = departure_in_timetable(at);
+    let latest_departure = earliest_departure + TimeDelta::seconds(latest - earliest);
+    let journeys = state.profile(start, target, at.date(), earliest_departure, latest_departure, &options).map_err(query_status)?;
+    Ok(proto::ProfileResponse { journeys: journeys.iter().map(|journey| encode_journey(state, journey, day_start)).collect() })
+}
+
+fn departures(state: &State, request: proto::DeparturesRequest) -> Result<proto::DeparturesResponse, Status> {
+    let Engine::TimetableLookup(lookup) = &state.engine else {
+        return Err(Status::unimplemented("Departures are only served in the timetable-lookup routing mode"));
+    };
+    let stop = stop(state, &request.stop)?;
+    let limit = request.limit.map_or(DEFAULT_DEPARTURE_LIMIT, |limit| limit as usize);
+    let (at, day_start) = local_time(state, request.departure_time)?;
+    state.check_date(at.date()).map_err(query_status)?;
+    let departures = lookup.departures(stop, at.date(), departure_in_timetable(at), limit).map_err(query_status)?;
+    Ok(proto::DeparturesResponse {
+        departures: departures.iter()
+            .map(|departure| proto::Departure {
+                trip_id: state.stops.trip_id(departure.trip),
+                departure_time: absolute_time(day_start, departure.departure).timestamp(),
+                destination: Some(encode_stop(state, departure.destination)),
+            })
+            .collect(),
+    })
+}
+
+fn encode_stop(state: &State, id: StopId) -> proto::Stop {
+    let stop = state.stops.stop(id);
+    proto::Stop { stop_id: stop.stop_id, name: stop.name, lat: stop.lat, lon: stop.lon }
+}
+
+fn encode_journey(state: &State, park_and_ride_journey: &ParkAndRideJourney, day_start: DateTime<Utc>) -> proto::Journey {
+    let ParkAndRideJourney { access, journey, egress } = park_and_ride_journey;
+    let unix_time = |time: DateTime<Utc>| absolute_time(day_start, time).timestamp();
+    let legs = journey.legs().map(|leg| match leg {
+        Leg::Ride { trip, boarding_stop, alight_stop, boarding_time, alight_time } => proto::leg::Leg::Ride(proto::Ride {
+            trip_id: state.stops.trip_id(*trip),
+            from: Some(encode_stop(state, *boarding_stop)),
+            to: Some(encode_stop(state, *alight_stop)),
+            departure_time: unix_time(*boarding_time),
+            arrival_time: unix_time(*alight_time),
+        }),
+        Leg::Transfer { start, end, duration } => proto::leg::Leg::Walk(proto::Walk {
+            from: Some(encode_stop(state, *start)),
+            to: Some(encode_stop(state, *end)),
+            duration_seconds: duration.num_seconds() as u32,
+        }),
+    });
+    let legs = access.iter().map(|access| encode_access(state, access))
+        .chain(legs)
+        .chain(egress.iter().map(|egress| encode_access(state, egress)))
+        .map(|leg| proto::Leg { leg: Some(leg) })
+        .collect();
+
+    proto::Journey {
+        departure_time: park_and_ride_journey.departure().map(unix_time),
+        arrival_time: park_and_ride_journey.arrival().map(unix_time),
+        legs,
+        cost: Some(encode_cost(&state.costs.breakdown(journey))),
+    }
+}
+
+fn encode_cost(breakdown: &CostBreakdown) -> proto::Cost {
+    proto::Cost {
+        in_vehicle_seconds: breakdown.in_vehicle.iter().map(|(mode, time)| (mode.clone(), time.num_seconds())).collect(),
+        walk_seconds: breakdown.walk_time.num_seconds(),
+        walk_meters: breakdown.walk_distance,
+        wait_seconds: breakdown.wait.num_seconds(),
+        transfers: breakdown.transfers,
+        penalty_seconds: breakdown.total_penalty().num_seconds(),
+    }
+}
+
+fn encode_access(state: &State, leg: &AccessLeg) -> proto::leg::Leg {
+    let mode = match leg.mode {
+        AccessMode::Walk => proto::AccessMode::Walk,
+        AccessMode::Bike => proto::AccessMode::Bike,
+        AccessMode::Car => proto::AccessMode::Car,
+    };
+    proto::leg::Leg::Access(proto::Access {
+        mode: mode.into(),
+        from: Some(encode_stop(state, leg.start)),
+        to: Some(encode_stop(state, leg.end)),
+        duration_seconds: leg.duration.num_seconds() as u32,
+    })
+}
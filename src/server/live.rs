@@ -0,0 +1,251 @@
+//! Journeys that clients follow over a WebSocket at `/api/v1/journeys/live`. A client sends a
+//! journey as `/api/v1/plan` returned it and gets it back re-evaluated with the trip updates of the
+//! datasets, right away and whenever a new message changes it. Each message replaces the journey
+//! followed before, see [routing::realtime::journeys] for how the legs are evaluated.
+
+use super::{format_time, query_problem, PlannedJourney, PlannedLeg, State};
+use crate::query::service_day_start;
+use crate::realtime::status_name;
+use crate::Engine;
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws;
+use actix_web::body::BodyStream;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use bytes::BytesMut;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Utc};
+use common::types::dataset::RealtimeFeed;
+use common::types::errors::ErrorCode;
+use data_harvester::realtime::poll_alerts;
+use futures::StreamExt;
+use log::debug;
+use routing::journey::{Journey, Leg};
+use routing::realtime::alerts::{Alerts, AlertsFeed};
+use routing::realtime::journeys::{evaluate, JourneyUpdate};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::mpsc;
+use visualization::api::problem::Problem;
+
+/// The latest service alerts of the datasets that have a feed, which are added to the legs of
+/// planned journeys
+pub(super) struct ServiceAlerts {
+    feeds: Mutex<HashMap<String, AlertsFeed>>,
+    // Of all feeds, rebuilt whenever one of them changes. None until the first message arrives.
+    alerts: RwLock<Option<Arc<Alerts>>>,
+}
+
+impl ServiceAlerts {
+    pub(super) fn new() -> Self {
+        Self { feeds: Mutex::default(), alerts: RwLock::default() }
+    }
+
+    /// Polls the alerts feeds, given by the ids of their datasets. Their trips, routes and stops
+    /// are looked up in the timetable of the state.
+    pub(super) fn spawn_polling(state: &Arc<State>, feeds: Vec<(String, RealtimeFeed)>) {
+        for (dataset_id, feed) in feeds {
+            let state = Arc::clone(state);
+            tokio::spawn(async move {
+                poll_alerts(&feed.src, feed.interval.into(), |alerts| {
+                    debug!(target: "realtime", "Received {} alerts of {}", alerts.len(), dataset_id);
+                    let mut feeds = state.alerts.feeds.lock().unwrap();
+                    feeds.insert(dataset_id.clone(), AlertsFeed { dataset_id: dataset_id.clone(), alerts });
+                    let alerts = Alerts::new(feeds.values().cloned().collect(), &state.stops.original_ids, state.route_of_trips.clone());
+                    *state.alerts.alerts.write().unwrap() = Some(Arc::new(alerts));
+                    true
+                }).await;
+            });
+        }
+    }
+
+    pub(super) fn current(&self) -> Option<Arc<Alerts>> {
+        self.alerts.read().unwrap().clone()
+    }
+}
+
+#[derive(Serialize)]
+struct LiveJourney {
+    // Whether every ride can still be taken
+    feasible: bool,
+    arrival: Option<String>,
+    legs: Vec<LiveLeg>,
+}
+
+#[derive(Serialize)]
+struct LiveLeg {
+    status: &'static str,
+    departure: Option<String>,
+    arrival: Option<String>,
+}
+
+// The journey of a session and what it was last pushed as
+struct Followed {
+    journey: Journey,
+    // Of the times of the journey
+    day: NaiveDate,
+    pushed: Option<JourneyUpdate>,
+}
+
+#[get("/api/v1/journeys/live")]
+pub(super) async fn follow_journey(request: HttpRequest, payload: web::Payload, state: web::Data<State>) -> actix_web::Result<HttpResponse> {
+    let mut response = ws::handshake(request.head())?;
+    let (sender, receiver) = mpsc::unbounded_channel();
+    actix_web::rt::spawn(session(payload, sender, state));
+
+    let frames = futures::stream::unfold((receiver, ws::Codec::new()), |(mut receiver, mut codec)| async move {
+        let message = receiver.recv().await?;
+        let mut frame = BytesMut::new();
+        let frame = codec.encode(message, &mut frame).map(|_| frame.freeze());
+        Some((frame, (receiver, codec)))
+    });
+    Ok(HttpResponse::from(response.body(BodyStream::new(frames))).map_into_boxed_body())
+}
+
+// Ends when the client closes the connection, or the response with the frames is dropped
+async fn session(mut payload: web::Payload, sender: mpsc::UnboundedSender<ws::Message>, state: web::Data<State>) {
+    let (mut codec, mut buffer) = (ws::Codec::new(), BytesMut::new());
+    let mut received = state.trip_updates.subscribe();
+    let mut followed: Option<Followed> = None;
+    loop {
+        tokio::select! {
+            chunk = payload.next() => {
+                let Some(Ok(chunk)) = chunk else { return };
+                buffer.extend_from_slice(&chunk);
+                while let Ok(Some(frame)) = codec.decode(&mut buffer) {
+                    let reply = match frame {
+                        ws::Frame::Text(text) => match follow(&text, &state) {
+                            Ok(journey) => {
+                                followed = Some(journey);
+                                None
+                            }
+                            Err(Rejection::Problem(problem)) => Some(problem_message(&problem)),
+                            Err(Rejection::Invalid(description)) => {
+                                let reason = ws::CloseReason { code: ws::CloseCode::Invalid, description: Some(description) };
+                                let _ = sender.send(ws::Message::Close(Some(reason)));
+                                return;
+                            }
+                        },
+                        ws::Frame::Ping(bytes) => Some(ws::Message::Pong(bytes)),
+                        ws::Frame::Close(reason) => {
+                            let _ = sender.send(ws::Message::Close(reason));
+                            return;
+                        }
+                        _ => None,
+                    };
+                    if let Some(reply) = reply {
+                        if sender.send(reply).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            Ok(()) = received.changed() => {}
+        }
+
+        let Some(followed) = followed.as_mut() else { continue };
+        let message = match reevaluate(&state, followed).await {
+            Ok(Some(update)) => live_journey_message(&update, followed.day),
+            Ok(None) => continue,
+            Err(problem) => problem_message(&problem),
+        };
+        if sender.send(message).is_err() {
+            return;
+        }
+    }
+}
+
+// Why a message isn't followed
+pub(super) enum Rejection {
+    // Answered, the connection stays open
+    Problem(Problem),
+    // Not a journey, which closes the connection
+    Invalid(String),
+}
+
+impl From<Problem> for Rejection {
+    fn from(problem: Problem) -> Self {
+        Rejection::Problem(problem)
+    }
+}
+
+fn follow(text: &[u8], state: &State) -> Result<Followed, Rejection> {
+    let planned: PlannedJourney = serde_json::from_slice(text).map_err(|err| Rejection::Invalid(err.to_string()))?;
+    let (journey, day) = journey_of(&planned, state)?;
+    Ok(Followed { journey, day, pushed: None })
+}
+
+pub(super) fn journey_of(planned: &PlannedJourney, state: &State) -> Result<(Journey, NaiveDate), Rejection> {
+    let parse = |time: &str| time.parse::<NaiveDateTime>()
+        .map_err(|err| Rejection::Invalid(format!("Invalid time {}: {}", time, err)));
+    // Times after midnight are still on the day the journey departs
+    let Some(departure) = &planned.departure else {
+        return Err(Problem::new(ErrorCode::NotFound, Some("A journey without rides has nothing to follow".into())).into());
+    };
+    let day = parse(departure)?.date();
+    let in_timetable = |time: &str| Ok::<_, Rejection>(DateTime::<Utc>::UNIX_EPOCH + (parse(time)? - day.and_time(NaiveTime::MIN)));
+
+    let legs = planned.legs.iter()
+        .map(|leg| Ok(match leg {
+            PlannedLeg::Ride(ride) => Leg::Ride {
+                trip: state.stops.original_ids.find_trip(&ride.trip_id)
+                    .ok_or_else(|| Problem::new(ErrorCode::NotFound, Some(format!("No trip with the id {}", ride.trip_id))))?,
+                boarding_stop: state.stops.find(&ride.from.stop_id)?,
+                alight_stop: state.stops.find(&ride.to.stop_id)?,
+                boarding_time: in_timetable(&ride.departure)?,
+                alight_time: in_timetable(&ride.arrival)?,
+            },
+            // Drives and rides to P+R stops aren't affected by trip updates, so they are
+            // followed like walks
+            PlannedLeg::Walk { from, to, duration_seconds, .. } | PlannedLeg::Access { from, to, duration_seconds, .. } => Leg::Transfer {
+                start: state.stops.find(&from.stop_id)?,
+                end: state.stops.find(&to.stop_id)?,
+                duration: TimeDelta::seconds(*duration_seconds),
+            },
+        }))
+        .collect::<Result<Vec<_>, Rejection>>()?;
+    Ok((Journey::from(legs), day))
+}
+
+// The journey in the latest trip updates, unless it was pushed like that before. This blocks for a
+// while, so it runs like the searches of the other endpoints.
+async fn reevaluate(state: &web::Data<State>, followed: &mut Followed) -> Result<Option<JourneyUpdate>, Problem> {
+    let (evaluating, journey, day) = (state.clone(), followed.journey.clone(), followed.day);
+    let update = web::block(move || {
+        let timetable = match &evaluating.engine {
+            Engine::Journeys(algorithm) => algorithm.timetable(),
+            Engine::TimetableLookup(lookup) => lookup.timetable(),
+        };
+        let service_day_start = service_day_start(day.and_time(NaiveTime::MIN), evaluating.timezone);
+        evaluate(&journey, &evaluating.trip_updates.feeds(), &evaluating.stops.original_ids, timetable, service_day_start)
+    })
+        .await
+        .map_err(|_| Problem::from(ErrorCode::Internal))?
+        .map_err(query_problem)?;
+
+    if followed.pushed.as_ref() == Some(&update) {
+        return Ok(None);
+    }
+    followed.pushed = Some(update.clone());
+    Ok(Some(update))
+}
+
+fn live_journey_message(update: &JourneyUpdate, day: NaiveDate) -> ws::Message {
+    let at = day.and_time(NaiveTime::MIN);
+    let legs = update.legs.iter()
+        .map(|leg| LiveLeg {
+            status: status_name(leg.status),
+            departure: leg.departure.map(|departure| format_time(departure, at)),
+            arrival: leg.arrival.map(|arrival| format_time(arrival, at)),
+        })
+        .collect();
+    let journey = LiveJourney {
+        feasible: update.is_feasible(),
+        arrival: update.arrival().map(|arrival| format_time(arrival, at)),
+        legs,
+    };
+    ws::Message::Text(serde_json::to_string(&journey).unwrap_or_default().into())
+}
+
+fn problem_message(problem: &Problem) -> ws::Message {
+    ws::Message::Text(serde_json::to_string(problem).unwrap_or_default().into())
+}
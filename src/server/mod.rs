@@ -0,0 +1,914 @@
+mod grpc;
+mod live;
+mod otp;
+mod subscriptions;
+
+use crate::bootstrap_config::JourneyOptions;
+use crate::query::{query_options, route_of_trips, service_day_start, trips_of_routes};
+use crate::realtime::{status_name, TripUpdates};
+use crate::{DrinoError, Engine, ALGORITHM};
+use actix_web::http::header;
+use actix_web::{get, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+use common::types::config::{RoutingConfig, RoutingMode};
+use common::types::dataset::RealtimeFeed;
+use common::types::errors::ErrorCode;
+use common::types::id_interner::OriginalIds;
+use common::types::mode::ModeRegistry;
+use common::types::{StopId, TripId};
+use common::util::speed::{Speed, CYCLING_SPEED};
+use data_harvester::step5_simplify::{read_original_ids_from, read_simplified_from};
+use hashbrown::{HashMap, HashSet};
+use log::info;
+use polars::prelude::{col, LazyFrame};
+use routing::accessibility::{AccessibilityInfo, AccessibilitySummary};
+use routing::algorithm::{AccessMode, EarliestArrival, ParetoPlanner, ProfilePlanner, QueryError, QueryOptions, QueryResult};
+use routing::bikes::BikeCarriage;
+use routing::booking::BookingNotes;
+use routing::calendar::ServiceCalendar;
+use routing::cost::{CostBreakdown, CostInfo};
+use routing::direct_connections::DirectConnections;
+use routing::geojson::{GeoFeatures, GeoLeg, MEDIA_TYPE};
+use routing::itinerary::{Itinerary, ItineraryLeg, StopCall};
+use routing::journey::{pareto_optimal, JourneyFilter, Leg};
+use routing::park_and_ride::{AccessLeg, ParkAndRide, ParkAndRideJourney};
+use routing::raptor::RaptorAlgorithm;
+use routing::realtime::alerts::Alert;
+use routing::realtime::journeys::evaluate;
+use routing::shadow::ShadowMode;
+use routing::stop_index::StopIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use visualization::api::problem::Problem;
+
+// Stops that a search for a name returns, unless the request asks for more or fewer
+const DEFAULT_STOP_LIMIT: usize = 20;
+// In meters around the location that a search for nearby stops is given
+const DEFAULT_STOP_RADIUS: f32 = 500.0;
+const DEFAULT_DEPARTURE_LIMIT: usize = 10;
+// Journeys that a plan returns, unless the request asks for more or fewer
+const DEFAULT_JOURNEY_LIMIT: usize = 3;
+// Profiles end after so many journeys, even if more depart in the window
+const MAX_PROFILE_JOURNEYS: usize = 100;
+
+/// The GTFS-RT feeds that the server polls, with the ids of their datasets
+pub struct RealtimeFeeds {
+    pub trip_updates: Vec<(String, RealtimeFeed)>,
+    pub alerts: Vec<(String, RealtimeFeed)>,
+}
+
+/// Answers routing requests over HTTP until the process is stopped:
+///
+/// - `GET /api/v1/health` tells whether the engine is ready, and for which queries
+/// - `GET /api/v1/stops?q=<name>` lists the stops whose name contains the text, and
+///   `GET /api/v1/stops?lat=<lat>&lon=<lon>` those near the location, the closest first
+/// - `GET /api/v1/plan?from=<stop>&to=<stop>&at=<time>` plans the journeys with the earliest
+///   arrival, each departing after the one before, as GeoJSON with a feature for every leg if the
+///   request accepts `application/geo+json`. With `until=<time>`, it plans the profile of all
+///   Pareto-optimal journeys that depart in between instead, and with `pareto=true` the journeys
+///   departing at `at` that arrive earliest with each number of rides. `suspend_routes=<routes>`
+///   plans them as if the routes were closed.
+/// - `GET /api/v1/departures?stop=<stop>&at=<time>` lists the next departures at a stop
+/// - `GET /api/v1/journeys/live` follows a planned journey over a WebSocket, see [live]
+/// - `POST /api/v1/subscriptions` calls a webhook when trip updates change a planned journey,
+///   `DELETE /api/v1/subscriptions/<journey id>` stops calling it, see [subscriptions]
+/// - `POST /otp/gtfs/v1` answers a subset of the GraphQL API of OpenTripPlanner, see [otp]
+///
+/// With `grpc_bind`, the same queries are also answered by the gRPC service of
+/// `proto/drino/v1/routing.proto` at that address, see [grpc].
+///
+/// Stops are given by their id in the source dataset prefixed with the id of the dataset, or by
+/// their name like in `drino query`. Times are in the local time of the timetable, e.g.
+/// 2024-05-01T08:00:00. Only the trips that run on the date of a query are ridden, and trips that
+/// the trip updates cancel are left out. Errors are problem details with the codes of [ErrorCode].
+///
+/// With a `shadow_sample_rate` in the routing config, plain RAPTOR plans that share of the journeys
+/// again and every arrival that differs is logged, see [ShadowMode].
+#[allow(clippy::too_many_arguments)]
+pub async fn serve(
+    engine: Engine,
+    tables: &Path,
+    bind: &str,
+    grpc_bind: Option<&str>,
+    modes: ModeRegistry,
+    timezone: Tz,
+    feeds: RealtimeFeeds,
+    routing: &RoutingConfig,
+) -> Result<(), DrinoError> {
+    // Engines only keep what they route with, the rest of the timetable is read again
+    let input = read_simplified_from(tables)?;
+    let costs = CostInfo::from_frames(input.stops.clone(), input.trips.clone(), &modes)?;
+    let raptor = match &engine {
+        Engine::Journeys(algorithm) => {
+            info!(target: "server", "Building RAPTOR for profiles");
+            Some(Arc::new(algorithm.raptor(input.clone())?))
+        }
+        Engine::TimetableLookup(_) => None,
+    };
+    let shadow = raptor.as_ref()
+        .filter(|_| routing.shadow_sample_rate > 0.0)
+        .map(|raptor| ShadowMode::new(Arc::clone(raptor), routing.shadow_sample_rate));
+    let state = web::Data::new(State {
+        engine,
+        stops: Stops::read(tables)?,
+        catalog: otp::Catalog::read(tables)?,
+        calendar: ServiceCalendar::new(&input)?,
+        accessibility: AccessibilityInfo::from_frames(input.stops.clone(), input.trips.clone())?,
+        bikes: BikeCarriage::from_frame(input.trips.clone())?,
+        bookings: BookingNotes::from_frame(LazyFrame::scan_parquet(tables.join("booking_notes.parquet"), Default::default())?)?,
+        costs: costs.clone(),
+        transfer_slack: Arc::new(costs.transfer_slack()),
+        park_and_ride: ParkAndRide::from_config(&routing.park_and_ride, input.stops.clone(), &input.original_ids)?,
+        min_transfer_time: TimeDelta::minutes(routing.min_transfer_minutes as i64),
+        route_of_trips: route_of_trips(tables)?.into_iter().collect(),
+        tables: tables.to_path_buf(),
+        modes,
+        timezone,
+        trip_updates: Arc::new(TripUpdates::new()),
+        alerts: live::ServiceAlerts::new(),
+        subscriptions: subscriptions::JourneySubscriptions::default(),
+        raptor,
+        shadow,
+    });
+    state.trip_updates.spawn_polling(feeds.trip_updates);
+    subscriptions::spawn_notifying(state.clone().into_inner());
+    live::ServiceAlerts::spawn_polling(&state.clone().into_inner(), feeds.alerts);
+    if let Some(grpc_bind) = grpc_bind {
+        let listener = tokio::net::TcpListener::bind(grpc_bind).await?;
+        info!(target: "server", "Serving gRPC at {}", grpc_bind);
+        tokio::spawn(grpc::serve(state.clone().into_inner(), listener));
+    }
+
+    let otp_schema = web::Data::new(otp::schema(state.clone().into_inner()));
+
+    info!(target: "server", "Serving queries at http://{}", bind);
+    HttpServer::new(move || {
+        App::new()
+            .app_data(state.clone())
+            .app_data(otp_schema.clone())
+            .service(health_status)
+            .service(search_stops)
+            .service(plan_journey)
+            .service(list_departures)
+            .service(live::follow_journey)
+            .service(subscriptions::subscribe)
+            .service(subscriptions::unsubscribe)
+            .service(otp::resource())
+    })
+        .bind(bind)?
+        .run()
+        .await?;
+    Ok(())
+}
+
+struct State {
+    engine: Engine,
+    stops: Stops,
+    // Agencies, routes and trips, which only the OTP API serves
+    catalog: otp::Catalog,
+    // Of the trips of the engine, which queries leave out on the dates they don't run
+    calendar: ServiceCalendar,
+    accessibility: AccessibilityInfo,
+    bikes: BikeCarriage,
+    // Of demand-responsive trips, which are added to the rides that need them
+    bookings: BookingNotes,
+    costs: CostInfo,
+    // Of the modes of the config, which queries leave to board the trips
+    transfer_slack: Arc<HashMap<TripId, TimeDelta>>,
+    park_and_ride: ParkAndRide,
+    // Of the config, which transfers take at least whatever the requests ask for
+    min_transfer_time: TimeDelta,
+    // Route ids prefixed with the id of their dataset, which alerts are given by
+    route_of_trips: HashMap<TripId, String>,
+    // Of the simplified timetable, which the routes and agencies of requests are looked up in
+    tables: PathBuf,
+    modes: ModeRegistry,
+    // Of the timetable, which absolute times of the OTP API and the feeds are converted from
+    timezone: Tz,
+    // Of the datasets with a feed, which cancel trips and which followed journeys are
+    // re-evaluated with
+    trip_updates: Arc<TripUpdates>,
+    // Of the datasets with a feed, which are added to the legs of planned journeys
+    alerts: live::ServiceAlerts,
+    // Journeys whose webhooks are called when the trip updates change them
+    subscriptions: subscriptions::JourneySubscriptions,
+    // On the timetable and walks of the engine, which answers profiles and Pareto sets. Only in
+    // the journeys routing mode.
+    raptor: Option<Arc<RaptorAlgorithm>>,
+    // Plans the sampled journeys of the config again with RAPTOR to check the engine
+    shadow: Option<ShadowMode<Arc<RaptorAlgorithm>>>,
+}
+
+// What travellers choose about their journeys, the same in all APIs as in `drino query`
+#[derive(Default)]
+struct PlanOptions {
+    query: QueryOptions,
+    // Only step-free trips and stops, unless there is no such journey
+    wheelchair: bool,
+    // Takes a bike along, cycling between stops at this speed
+    bike: Option<Speed>,
+    // Of the routes that a request suspends, which aren't ridden as if they were closed
+    suspended_trips: HashSet<TripId>,
+}
+
+impl PlanOptions {
+    // Whether the journeys walk to, from and between the stops, like all planners of the engine
+    // assume without the help of [ParkAndRide], [AccessibilityInfo] or [BikeCarriage]
+    fn only_walks(&self) -> bool {
+        !self.wheelchair && self.bike.is_none() && self.query.access_mode == AccessMode::Walk && self.query.egress_mode == AccessMode::Walk
+    }
+}
+
+impl State {
+    fn mode(&self) -> RoutingMode {
+        match self.engine {
+            Engine::Journeys(_) => RoutingMode::Journeys,
+            Engine::TimetableLookup(_) => RoutingMode::TimetableLookup,
+        }
+    }
+
+    fn timetable(&self) -> &DirectConnections {
+        match &self.engine {
+            Engine::Journeys(algorithm) => algorithm.timetable(),
+            Engine::TimetableLookup(lookup) => lookup.timetable(),
+        }
+    }
+
+    // The options of a request, with the stops, routes and agencies to avoid looked up
+    fn plan_options(&self, options: &JourneyOptions) -> Result<PlanOptions, Problem> {
+        let avoided_stops = options.avoided_stops.iter()
+            .map(|stop| self.stops.find(stop))
+            .collect::<Result<_, _>>()?;
+        let query = query_options(options, avoided_stops, self.min_transfer_time, self.transfer_slack.clone(), &self.tables).map_err(options_problem)?;
+        Ok(PlanOptions {
+            query,
+            wheelchair: options.wheelchair,
+            bike: options.bike.then_some(Speed(options.cycling_speed)),
+            suspended_trips: HashSet::new(),
+        })
+    }
+
+    // Up to `limit` journeys with the earliest arrival on the service day `date`, each departing
+    // after the one before and not just a few minutes after another of the same routes. This
+    // blocks for a while, see [web::block].
+    fn plan(
+        &self,
+        start: StopId,
+        target: StopId,
+        date: NaiveDate,
+        departure: DateTime<Utc>,
+        options: &PlanOptions,
+        limit: usize,
+    ) -> QueryResult<Vec<ParkAndRideJourney>> {
+        self.successive_journeys(start, target, date, departure, None, options, limit)
+    }
+
+    // The journeys that depart until `latest_departure` and arrive earlier than all that depart
+    // later, see [pareto_optimal]. RAPTOR searches the whole window at once, see [ProfilePlanner].
+    // Journeys that need step-free trips, a bike or P+R stops are the successive ones of
+    // [State::plan] instead, since RAPTOR has no range queries with them.
+    fn profile(
+        &self,
+        start: StopId,
+        target: StopId,
+        date: NaiveDate,
+        earliest_departure: DateTime<Utc>,
+        latest_departure: DateTime<Utc>,
+        options: &PlanOptions,
+    ) -> QueryResult<Vec<ParkAndRideJourney>> {
+        if let Some(raptor) = self.raptor.as_ref().filter(|_| options.only_walks()) {
+            self.check_date(date)?;
+            let mut query = options.query.clone();
+            query.avoided_trips.extend(self.suspended_trips(date, options));
+            let profile = raptor.query_profile_with(start, target, earliest_departure, latest_departure, &query)?;
+            return Ok(profile.into_iter()
+                .map(|journey| ParkAndRideJourney { access: None, journey, egress: None })
+                .collect());
+        }
+        let journeys = self.successive_journeys(start, target, date, earliest_departure, Some(latest_departure), options, MAX_PROFILE_JOURNEYS)?;
+        let profile: Vec<ParkAndRideJourney> = pareto_optimal(journeys.iter().map(|journey| journey.journey.clone())).into_iter()
+            .filter_map(|optimal| journeys.iter().find(|journey| journey.journey == optimal).cloned())
+            .collect();
+        match profile.is_empty() {
+            true => Err(QueryError::NoRouteFound),
+            false => Ok(profile),
+        }
+    }
+
+    // The journeys departing at `departure` that no other one beats in arrival and number of rides,
+    // e.g. one that arrives 5 minutes later with 2 fewer transfers, see [ParetoPlanner]. RAPTOR
+    // searches them with the options of the request. Journeys to P+R stops and timetable lookups
+    // only have the earliest arrival of [State::plan].
+    fn pareto(
+        &self,
+        start: StopId,
+        target: StopId,
+        date: NaiveDate,
+        departure: DateTime<Utc>,
+        options: &PlanOptions,
+    ) -> QueryResult<Vec<ParkAndRideJourney>> {
+        let uses_park_and_ride = options.query.access_mode != AccessMode::Walk || options.query.egress_mode != AccessMode::Walk;
+        let Some(raptor) = self.raptor.as_ref().filter(|_| !uses_park_and_ride) else {
+            return self.plan(start, target, date, departure, options, 1);
+        };
+        self.check_date(date)?;
+        let input = self.search_input(start, departure, &self.suspended_trips(date, options), options);
+        let search = |input: EarliestArrival| raptor.query_pareto_set_with(input, target);
+        let journeys = match options.wheelchair {
+            true => match search(input.clone().step_free(&self.accessibility)) {
+                Err(QueryError::NoRouteFound) => search(input),
+                result => result,
+            },
+            false => search(input),
+        }?;
+        Ok(journeys.into_iter()
+            .map(|journey| ParkAndRideJourney { access: None, journey, egress: None })
+            .collect())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn successive_journeys(
+        &self,
+        start: StopId,
+        target: StopId,
+        date: NaiveDate,
+        departure: DateTime<Utc>,
+        latest_departure: Option<DateTime<Utc>>,
+        options: &PlanOptions,
+        limit: usize,
+    ) -> QueryResult<Vec<ParkAndRideJourney>> {
+        let in_time = |journey: &ParkAndRideJourney| latest_departure.is_none_or(|latest| journey.departure().is_none_or(|departure| departure <= latest));
+        self.check_date(date)?;
+        let suspended = self.suspended_trips(date, options);
+        let journeys = match &self.engine {
+            Engine::Journeys(algorithm) => {
+                let mut journeys: Vec<ParkAndRideJourney> = vec![];
+                let mut departure = departure;
+                while journeys.len() < MAX_PROFILE_JOURNEYS && self.deduplicated(&journeys, limit).len() < limit {
+                    let input = self.search_input(start, departure, &suspended, options);
+                    let journey = match self.earliest_arrival(algorithm, input, target, options) {
+                        Ok(journey) if in_time(&journey) => journey,
+                        Ok(_) => break,
+                        Err(QueryError::NoRouteFound) if !journeys.is_empty() => break,
+                        Err(err) => return Err(err),
+                    };
+                    // Journeys that only walk have no departure, and walking later changes nothing
+                    let Some(next) = journey.journey.departure() else {
+                        journeys.push(journey);
+                        break;
+                    };
+                    // Searches a little later may catch the same first ride, then the next one
+                    // has to depart after it
+                    if journeys.last().is_some_and(|last| last.journey == journey.journey) {
+                        let first_ride = journey.journey.legs().find_map(|leg| match leg {
+                            Leg::Ride { boarding_time, .. } => Some(*boarding_time),
+                            Leg::Transfer { .. } => None,
+                        });
+                        departure = first_ride.unwrap_or(next) + TimeDelta::minutes(1);
+                        continue;
+                    }
+                    departure = next + TimeDelta::minutes(1);
+                    journeys.push(journey);
+                }
+                journeys
+            }
+            Engine::TimetableLookup(lookup) => {
+                // Rides are looked up without the options, so all of them are checked before
+                // taking the first ones
+                let input = self.search_input(start, departure, &suspended, options);
+                let journeys = lookup.direct_connections(start, target, date, departure, usize::MAX)?;
+                let allowed = |input: &EarliestArrival| journeys.iter()
+                    .filter(|journey| input.allows(journey))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let mut allowed_journeys = match options.wheelchair {
+                    true => Some(allowed(&input.clone().step_free(&self.accessibility))).filter(|journeys| !journeys.is_empty()),
+                    false => None,
+                }.unwrap_or_else(|| allowed(&input));
+                allowed_journeys.truncate(limit);
+                allowed_journeys.into_iter()
+                    .map(|journey| ParkAndRideJourney { access: None, journey, egress: None })
+                    .filter(in_time)
+                    .collect()
+            }
+        };
+        let journeys = self.deduplicated(&journeys, limit);
+        match journeys.is_empty() {
+            true => Err(QueryError::NoRouteFound),
+            false => Ok(journeys),
+        }
+    }
+
+    // Journeys that only differ by the run of the same routes they ride within a few minutes are
+    // collapsed into the one that arrives first, see [JourneyFilter]. Trips without a route are
+    // lines of their own.
+    fn deduplicated(&self, journeys: &[ParkAndRideJourney], limit: usize) -> Vec<ParkAndRideJourney> {
+        let filter = JourneyFilter { max_results: Some(limit), ..Default::default() };
+        filter.apply(journeys.iter().map(|journey| journey.journey.clone()), |trip| self.route_of_trips.get(trip).cloned().ok_or(*trip))
+            .into_iter()
+            .filter_map(|kept| journeys.iter().find(|journey| journey.journey == kept).cloned())
+            .collect()
+    }
+
+    // Nothing runs outside the calendar, which is reported instead of not finding a route
+    fn check_date(&self, date: NaiveDate) -> QueryResult<()> {
+        match self.calendar.covers(date) {
+            true => Ok(()),
+            false => Err(QueryError::DateOutOfRange(date)),
+        }
+    }
+
+    // The trips that don't run on the date, those that the trip updates cancel and those of the
+    // routes that the request suspends. Feeds describe the current service day, so their
+    // cancellations only apply to queries for today.
+    fn suspended_trips(&self, date: NaiveDate, options: &PlanOptions) -> HashSet<TripId> {
+        let mut suspended = self.calendar.trips_not_running(date);
+        suspended.extend(&options.suspended_trips);
+        if date == Utc::now().with_timezone(&self.timezone).date_naive() {
+            suspended.extend(self.trip_updates.cancelled_trips(&self.stops.original_ids));
+        }
+        suspended
+    }
+
+    fn search_input(&self, start: StopId, departure: DateTime<Utc>, suspended: &HashSet<TripId>, options: &PlanOptions) -> EarliestArrival {
+        let input = EarliestArrival::new(start, departure)
+            .with_options(&options.query)
+            .suspending(suspended.iter().copied());
+        match options.bike {
+            Some(speed) => input.with_bike(&self.bikes, speed),
+            None => input,
+        }
+    }
+
+    // Journeys that drive or cycle try the P+R stops, the others are searched as they are. For
+    // wheelchair users, the regular journey is only searched if there is no step-free one, like in
+    // `drino query`.
+    fn earliest_arrival(&self, algorithm: &ALGORITHM, input: EarliestArrival, target: StopId, options: &PlanOptions) -> QueryResult<ParkAndRideJourney> {
+        let search = |input: EarliestArrival| match &self.shadow {
+            Some(shadow) => self.park_and_ride.query_ea(&shadow.shadowing(algorithm), input, target),
+            None => self.park_and_ride.query_ea(algorithm, input, target),
+        };
+        if !options.wheelchair {
+            return search(input);
+        }
+        match search(input.clone().step_free(&self.accessibility)) {
+            Err(QueryError::NoRouteFound) => search(input),
+            result => result,
+        }
+    }
+}
+
+// The names of the stops and the original ids of stops and trips, which the routing data doesn't
+// have. They are read from the simplified timetable, whose rows are the stops by their id.
+struct Stops {
+    original_ids: OriginalIds,
+    names: Vec<String>,
+    coords: Vec<(Option<f32>, Option<f32>)>,
+    // By their original ids, which nearby stops are searched with
+    index: StopIndex,
+}
+
+impl Stops {
+    fn read(tables: &Path) -> Result<Self, DrinoError> {
+        let original_ids = read_original_ids_from(tables)?;
+        let stops = LazyFrame::scan_parquet(tables.join("stops.parquet"), Default::default())?;
+        let index = StopIndex::from_stops(stops.clone())?;
+        let stops = stops.select([col("stop_name"), col("lat"), col("lon")]).collect()?;
+
+        let names: Vec<String> = stops.column("stop_name")?.str()?.iter()
+            .map(|name| name.unwrap_or_default().to_string())
+            .collect();
+        let coords = stops.column("lat")?.f32()?.iter().zip(stops.column("lon")?.f32()?.iter()).collect();
+        Ok(Self { original_ids, names, coords, index })
+    }
+
+    // By the original id first, then by the name. If several stops share a name, the first one
+    // is used.
+    fn find(&self, stop: &str) -> Result<StopId, Problem> {
+        self.original_ids.stops.get(stop)
+            .or_else(|| self.names.iter()
+                .position(|name| name.eq_ignore_ascii_case(stop))
+                .map(|id| StopId(id as u32)))
+            .ok_or_else(|| Problem::new(ErrorCode::StopNotFound, Some(format!("No stop with the name or id {}", stop))))
+    }
+
+    fn trip_id(&self, trip: TripId) -> String {
+        self.original_ids.trip(trip).unwrap_or_default().to_string()
+    }
+
+    fn stop(&self, id: StopId) -> Stop {
+        let idx = id.0 as usize;
+        let (lat, lon) = self.coords.get(idx).copied().unwrap_or_default();
+        Stop {
+            stop_id: self.original_ids.stop(id).unwrap_or_default().to_string(),
+            name: self.names.get(idx).cloned().unwrap_or_default(),
+            lat,
+            lon,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    mode: RoutingMode,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stop {
+    stop_id: String,
+    name: String,
+    lat: Option<f32>,
+    lon: Option<f32>,
+}
+
+// Clients send it back to follow it, see [live]. What was added to the legs later is optional, so
+// that journeys planned by older releases can still be followed.
+#[derive(Serialize, Deserialize)]
+struct PlannedJourney {
+    departure: Option<String>,
+    arrival: Option<String>,
+    legs: Vec<PlannedLeg>,
+    #[serde(default)]
+    cost: PlannedCost,
+    // Whether every stop and vehicle is step-free, see [AccessibilitySummary]
+    #[serde(default)]
+    accessibility: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum PlannedLeg {
+    // Boxed, since rides are much larger than the other legs
+    Ride(Box<PlannedRide>),
+    Walk {
+        from: Stop,
+        to: Stop,
+        duration_seconds: i64,
+        #[serde(default)]
+        alerts: Vec<PlannedAlert>,
+    },
+    // Drives or rides to and from P+R stops
+    Access { mode: String, from: Stop, to: Stop, duration_seconds: i64 },
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlannedRide {
+    trip_id: String,
+    // Short or long name of the route, e.g. "S1"
+    #[serde(default)]
+    line: Option<String>,
+    from: Stop,
+    to: Stop,
+    departure: String,
+    arrival: String,
+    #[serde(default)]
+    intermediate_stops: Vec<PlannedCall>,
+    // What the trip updates say about the ride, see [status_name]. Without a feed, rides
+    // keep their scheduled times.
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    expected_departure: Option<String>,
+    #[serde(default)]
+    expected_arrival: Option<String>,
+    #[serde(default)]
+    alerts: Vec<PlannedAlert>,
+    // How to book boarding and alighting, e.g. "Call +49 711 123 to book at least 60 min
+    // ahead", see [BookingNote::text](routing::booking::BookingNote::text)
+    #[serde(default)]
+    booking: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlannedCall {
+    stop: Stop,
+    arrival: Option<String>,
+    departure: Option<String>,
+}
+
+// The parts of the cost of a journey, see [CostBreakdown]
+#[derive(Serialize, Deserialize, Default)]
+struct PlannedCost {
+    // By mode
+    in_vehicle_seconds: BTreeMap<String, i64>,
+    walk_seconds: i64,
+    walk_meters: f32,
+    wait_seconds: i64,
+    transfers: u32,
+    penalty_seconds: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlannedAlert {
+    id: String,
+    header: Option<String>,
+    description: Option<String>,
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PlannedDeparture {
+    trip_id: String,
+    departure: String,
+    destination: Stop,
+}
+
+#[derive(Deserialize)]
+struct StopsQuery {
+    q: Option<String>,
+    lat: Option<f32>,
+    lon: Option<f32>,
+    // In meters
+    radius: Option<f32>,
+    limit: Option<usize>,
+}
+
+// The options of `drino query`, with lists separated by commas
+#[derive(Deserialize)]
+struct PlanQuery {
+    from: String,
+    to: String,
+    at: NaiveDateTime,
+    // Plans the profile of the journeys that depart until then instead of `limit` of them
+    until: Option<NaiveDateTime>,
+    // Plans the journeys that arrive earliest with each number of rides instead, unless `until`
+    // is given
+    #[serde(default)]
+    pareto: bool,
+    limit: Option<usize>,
+    max_transfers: Option<usize>,
+    // In minutes, like `--min-transfer-buffer` of `drino query`
+    min_transfer_buffer: Option<u32>,
+    #[serde(default)]
+    wheelchair: bool,
+    #[serde(default)]
+    bike: bool,
+    // In km/h
+    cycling_speed: Option<f64>,
+    avoid_stops: Option<String>,
+    avoid_routes: Option<String>,
+    avoid_agencies: Option<String>,
+    access_mode: Option<AccessMode>,
+    egress_mode: Option<AccessMode>,
+    // Routes that aren't ridden to preview a planned closure, like `--suspend-route` of
+    // `drino query`
+    suspend_routes: Option<String>,
+    // Of the texts of alerts, e.g. "de"
+    language: Option<String>,
+}
+
+impl PlanQuery {
+    fn journey_options(&self) -> JourneyOptions {
+        JourneyOptions {
+            wheelchair: self.wheelchair,
+            bike: self.bike,
+            cycling_speed: self.cycling_speed.unwrap_or(CYCLING_SPEED.0),
+            max_transfers: self.max_transfers,
+            avoided_stops: list(&self.avoid_stops),
+            avoided_routes: list(&self.avoid_routes),
+            avoided_agencies: list(&self.avoid_agencies),
+            min_transfer_buffer: self.min_transfer_buffer.unwrap_or_default(),
+            access_mode: self.access_mode.unwrap_or_default(),
+            egress_mode: self.egress_mode.unwrap_or_default(),
+        }
+    }
+
+    fn suspended_routes(&self) -> Vec<String> {
+        list(&self.suspend_routes)
+    }
+}
+
+fn list(values: &Option<String>) -> Vec<String> {
+    values.iter()
+        .flat_map(|values| values.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct DeparturesQuery {
+    stop: String,
+    at: NaiveDateTime,
+    limit: Option<usize>,
+}
+
+#[get("/api/v1/health")]
+async fn health_status(state: web::Data<State>) -> impl Responder {
+    web::Json(Health { status: "ok", mode: state.mode() })
+}
+
+#[get("/api/v1/stops")]
+async fn search_stops(query: web::Query<StopsQuery>, state: web::Data<State>) -> impl Responder {
+    let text = query.q.as_deref().unwrap_or_default().to_lowercase();
+    let candidates: Box<dyn Iterator<Item = StopId>> = match (query.lat, query.lon) {
+        (Some(lat), Some(lon)) => Box::new(state.stops.index.nearby(lat, lon, query.radius.unwrap_or(DEFAULT_STOP_RADIUS)).into_iter()
+            .filter_map(|key| state.stops.original_ids.stops.get(&format!("{}:{}", key.dataset_id, key.stop_id)))),
+        _ => Box::new((0..state.stops.names.len()).map(|id| StopId(id as u32))),
+    };
+    let stops: Vec<Stop> = candidates
+        .filter(|id| state.stops.names[id.0 as usize].to_lowercase().contains(&text))
+        .take(query.limit.unwrap_or(DEFAULT_STOP_LIMIT))
+        .map(|id| state.stops.stop(id))
+        .collect();
+    web::Json(stops)
+}
+
+#[get("/api/v1/plan")]
+async fn plan_journey(request: HttpRequest, query: web::Query<PlanQuery>, state: web::Data<State>) -> Result<HttpResponse, Problem> {
+    let (start, target) = (state.stops.find(&query.from)?, state.stops.find(&query.to)?);
+    let mut options = state.plan_options(&query.journey_options())?;
+    options.suspended_trips = trips_of_routes(&query.suspended_routes(), &state.tables).map_err(options_problem)?;
+    let (at, until, pareto, limit) = (query.at, query.until, query.pareto, query.limit.unwrap_or(DEFAULT_JOURNEY_LIMIT));
+
+    // Searches block for a while, so they don't run on the workers that accept requests
+    let searching = state.clone();
+    let journeys = web::block(move || match until {
+        // The window is searched on the service day of its start
+        Some(until) => {
+            let earliest_departure = departure_in_timetable(at);
+            searching.profile(start, target, at.date(), earliest_departure, earliest_departure + (until - at).max(TimeDelta::zero()), &options)
+        }
+        None if pareto => searching.pareto(start, target, at.date(), departure_in_timetable(at), &options),
+        None => searching.plan(start, target, at.date(), departure_in_timetable(at), &options, limit),
+    })
+        .await
+        .map_err(|_| Problem::from(ErrorCode::Internal))?
+        .map_err(query_problem)?;
+
+    let accepts_geojson = request.headers().get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(MEDIA_TYPE));
+    if accepts_geojson {
+        return Ok(HttpResponse::Ok().content_type(MEDIA_TYPE).body(geojson_journeys(&state, &journeys, at.date())?));
+    }
+    let planned = journeys.iter()
+        .map(|journey| planned_journey(&state, journey, at, query.language.as_deref()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(HttpResponse::Ok().json(planned))
+}
+
+#[get("/api/v1/departures")]
+async fn list_departures(query: web::Query<DeparturesQuery>, state: web::Data<State>) -> Result<impl Responder, Problem> {
+    let Engine::TimetableLookup(lookup) = &state.engine else {
+        return Err(Problem::new(ErrorCode::NotFound, Some("Departures are only served in the timetable-lookup routing mode".into())));
+    };
+    let stop = state.stops.find(&query.stop)?;
+    state.check_date(query.at.date()).map_err(query_problem)?;
+    let departures = lookup.departures(stop, query.at.date(), departure_in_timetable(query.at), query.limit.unwrap_or(DEFAULT_DEPARTURE_LIMIT))
+        .map_err(query_problem)?;
+
+    let departures: Vec<PlannedDeparture> = departures.into_iter()
+        .map(|departure| PlannedDeparture {
+            trip_id: state.stops.trip_id(departure.trip),
+            departure: format_time(departure.departure, query.at),
+            destination: state.stops.stop(departure.destination),
+        })
+        .collect();
+    Ok(web::Json(departures))
+}
+
+// Routes and agencies of the options that don't exist aren't found, the rest fails internally
+fn options_problem(err: DrinoError) -> Problem {
+    match err {
+        DrinoError::UnknownRoute(_) | DrinoError::UnknownAgency(_) => Problem::new(ErrorCode::NotFound, Some(err.to_string())),
+        err => Problem::new(ErrorCode::Internal, Some(err.to_string())),
+    }
+}
+
+fn query_problem(err: QueryError) -> Problem {
+    Problem::new(err.code(), Some(err.to_string()))
+}
+
+// Times of the timetable are relative to the start of the service day
+fn departure_in_timetable(at: NaiveDateTime) -> DateTime<Utc> {
+    DateTime::<Utc>::UNIX_EPOCH + (at - at.date().and_time(NaiveTime::MIN))
+}
+
+// A time of the timetable on the service day that starts at `day_start`
+fn absolute_time(day_start: DateTime<Utc>, time: DateTime<Utc>) -> DateTime<Utc> {
+    day_start + (time - DateTime::<Utc>::UNIX_EPOCH)
+}
+
+// Back in the local time of the day of the request, e.g. 2024-05-01T08:03:00
+fn format_time(time: DateTime<Utc>, at: NaiveDateTime) -> String {
+    (at.date().and_time(NaiveTime::MIN) + (time - DateTime::<Utc>::UNIX_EPOCH)).format("%Y-%m-%dT%H:%M:%S").to_string()
+}
+
+// The features of all journeys in one collection, with the index of the journey of every leg
+fn geojson_journeys(state: &State, journeys: &[ParkAndRideJourney], day: NaiveDate) -> Result<String, Problem> {
+    let features = GeoFeatures::new(&state.stops.original_ids, &state.stops.names, &state.stops.coords);
+    let mut collection = features.legs([], day);
+    for (idx, ParkAndRideJourney { access, journey, egress }) in journeys.iter().enumerate() {
+        let itinerary = Itinerary::reconstruct(journey, state.timetable()).map_err(query_problem)?;
+        let legs = access.iter().map(GeoLeg::from)
+            .chain(itinerary.legs.iter().map(GeoLeg::from))
+            .chain(egress.iter().map(GeoLeg::from));
+        for mut feature in features.legs(legs, day).features {
+            feature.set_property("journey", idx);
+            collection.features.push(feature);
+        }
+    }
+    Ok(collection.to_string())
+}
+
+// With the intermediate stops, line, alerts and bookings of every ride and the expected times of
+// the latest trip updates
+fn planned_journey(state: &State, park_and_ride_journey: &ParkAndRideJourney, at: NaiveDateTime, language: Option<&str>) -> Result<PlannedJourney, Problem> {
+    let ParkAndRideJourney { access, journey, egress } = park_and_ride_journey;
+    let stops = &state.stops;
+    let itinerary = Itinerary::reconstruct(journey, state.timetable()).map_err(query_problem)?;
+    let day_start = service_day_start(at, state.timezone);
+    let alerts = state.alerts.current();
+    let alerts_of_legs = alerts.as_ref().map(|alerts| alerts.for_itinerary(&itinerary, day_start));
+    let feeds = state.trip_updates.feeds();
+    // Legs of journeys that the feeds don't know keep their scheduled times
+    let update = (!feeds.is_empty())
+        .then(|| evaluate(journey, &feeds, &stops.original_ids, state.timetable(), day_start).ok())
+        .flatten();
+    let bookings = state.bookings.for_journey(journey);
+
+    let planned_alerts = |idx: usize| -> Vec<PlannedAlert> {
+        let Some(alerts) = alerts_of_legs.as_ref().and_then(|alerts| alerts.get(idx)) else { return vec![] };
+        alerts.iter().map(|alert: &&Alert| PlannedAlert {
+            id: alert.id.clone(),
+            header: alert.header.get(language).map(str::to_string),
+            description: alert.description.get(language).map(str::to_string),
+            url: alert.url.get(language).map(str::to_string),
+        }).collect()
+    };
+    let format_call = |call: &StopCall| PlannedCall {
+        stop: stops.stop(call.stop),
+        arrival: call.arrival.map(|arrival| format_time(arrival, at)),
+        departure: call.departure.map(|departure| format_time(departure, at)),
+    };
+    let access_leg = |leg: &AccessLeg| PlannedLeg::Access {
+        mode: leg.mode.to_string(),
+        from: stops.stop(leg.start),
+        to: stops.stop(leg.end),
+        duration_seconds: leg.duration.num_seconds(),
+    };
+
+    let legs = itinerary.legs.iter().enumerate()
+        .map(|(idx, leg)| match leg {
+            ItineraryLeg::Ride { trip, boarding_stop, alight_stop, departure, arrival, intermediate_stops, .. } => {
+                let leg_update = update.as_ref().and_then(|update| update.legs.get(idx));
+                PlannedLeg::Ride(Box::new(PlannedRide {
+                    trip_id: stops.trip_id(*trip),
+                    line: state.catalog.line_name(*trip),
+                    from: stops.stop(*boarding_stop),
+                    to: stops.stop(*alight_stop),
+                    departure: format_time(*departure, at),
+                    arrival: format_time(*arrival, at),
+                    intermediate_stops: intermediate_stops.iter().map(format_call).collect(),
+                    status: leg_update.map(|leg| status_name(leg.status).to_string()),
+                    expected_departure: leg_update.and_then(|leg| leg.departure).map(|departure| format_time(departure, at)),
+                    expected_arrival: leg_update.and_then(|leg| leg.arrival).map(|arrival| format_time(arrival, at)),
+                    alerts: planned_alerts(idx),
+                    booking: bookings.iter()
+                        .filter(|(leg, _)| *leg == idx)
+                        .map(|(_, note)| note.text())
+                        .collect(),
+                }))
+            }
+            ItineraryLeg::Transfer { start, end, duration } => PlannedLeg::Walk {
+                from: stops.stop(*start),
+                to: stops.stop(*end),
+                duration_seconds: duration.num_seconds(),
+                alerts: planned_alerts(idx),
+            },
+        });
+    let legs = access.iter().map(access_leg)
+        .chain(legs)
+        .chain(egress.iter().map(access_leg))
+        .collect();
+
+    let accessibility = match state.accessibility.summarize(journey) {
+        AccessibilitySummary::StepFree => "step-free",
+        AccessibilitySummary::AssistanceRequired => "assistance-required",
+        AccessibilitySummary::Unknown => "unknown",
+    };
+    Ok(PlannedJourney {
+        departure: park_and_ride_journey.departure().map(|departure| format_time(departure, at)),
+        arrival: park_and_ride_journey.arrival().map(|arrival| format_time(arrival, at)),
+        legs,
+        cost: planned_cost(&state.costs.breakdown(journey)),
+        accessibility: accessibility.to_string(),
+    })
+}
+
+fn planned_cost(breakdown: &CostBreakdown) -> PlannedCost {
+    PlannedCost {
+        in_vehicle_seconds: breakdown.in_vehicle.iter().map(|(mode, time)| (mode.clone(), time.num_seconds())).collect(),
+        walk_seconds: breakdown.walk_time.num_seconds(),
+        walk_meters: breakdown.walk_distance,
+        wait_seconds: breakdown.wait.num_seconds(),
+        transfers: breakdown.transfers,
+        penalty_seconds: breakdown.total_penalty().num_seconds(),
+    }
+}
@@ -0,0 +1,1028 @@
+//! A subset of the GraphQL API of OpenTripPlanner 2 (the GTFS API at `/otp/gtfs/v1`, formerly
+//! `/otp/routers/default/index/graphql`), so that its clients can switch to drino without changes.
+//! The query type has the fields
+//!
+//! - `plan(from, to, fromPlace, toPlace, date, time, numItineraries, maxTransfers, minTransferTime,
+//!   wheelchair, banned, bikeSpeed, transportModes)`
+//! - `stops(ids, name, maxResults)` and `stop(id)`
+//! - `routes(ids, feeds, name, transportModes)` and `route(id)`
+//! - `trips(feeds)` and `trip(id)`
+//! - `agencies`, `agency(id)` and `feeds`
+//!
+//! Ids are the ids of the GTFS feeds prefixed by the dataset, e.g. "vvs:de:08111:6118", and the
+//! global `id`s of objects are the same. Itineraries start and end at stops: coordinates are
+//! snapped to the nearest stop instead of walking from them. Times are milliseconds since the
+//! epoch, and the date and time of a plan are in the time zone of the timetable.
+//!
+//! Of the `transportModes` of a plan, `BICYCLE` takes a bike along, and `CAR` or `BICYCLE` with
+//! the qualifier `PARK` drive or cycle to a P+R stop first. Banned stops, routes and agencies are
+//! lists of ids separated by commas, like in OpenTripPlanner.
+
+use super::{absolute_time, departure_in_timetable, State, Stops};
+use crate::bootstrap_config::JourneyOptions;
+use crate::query::service_day_start;
+use crate::DrinoError;
+use actix_web::{web, Resource};
+use async_graphql::{EmptyMutation, EmptySubscription, Enum, Error, InputObject, Object, Schema};
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, Utc};
+use common::types::{StopId, TripId};
+use common::util::speed::CYCLING_SPEED;
+use hashbrown::HashMap;
+use polars::prelude::{col, DataFrame, DataType, LazyFrame, PolarsError};
+use routing::algorithm::{AccessMode, QueryError};
+use routing::journey;
+use routing::park_and_ride::{AccessLeg, ParkAndRideJourney};
+use std::path::Path;
+use std::sync::Arc;
+
+// Like OpenTripPlanner without `numItineraries`
+const DEFAULT_ITINERARIES: usize = 3;
+// Coordinates farther from every stop are not planned from or to
+const MAX_SNAP_METERS: f64 = 1_000.0;
+// Selections nest at most this deep, which also ends fragments that spread themselves
+const MAX_DEPTH: usize = 64;
+
+pub(super) type OtpSchema = Schema<QueryType, EmptyMutation, EmptySubscription>;
+
+/// The schema of the API, which resolves its queries with the state of the server
+pub(super) fn schema(state: Arc<State>) -> OtpSchema {
+    Schema::build(QueryType(state), EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .finish()
+}
+
+/// The endpoints of the API, which take the query as JSON in the body of a POST or as the
+/// parameters of a GET
+pub(super) fn resource() -> Resource {
+    web::resource(["/otp/gtfs/v1", "/otp/routers/default/index/graphql"])
+        .route(web::post().to(graphql))
+        .route(web::get().to(graphql))
+}
+
+async fn graphql(schema: web::Data<OtpSchema>, request: GraphQLRequest) -> GraphQLResponse {
+    // Some clients send an empty name
+    let mut request = request.into_inner();
+    request.operation_name = request.operation_name.filter(|name| !name.is_empty());
+    schema.execute(request).await.into()
+}
+
+// Agencies, routes and trips of the simplified timetable that the engine is served with, with the
+// routes that serve each stop
+pub(super) struct Catalog {
+    feeds: Vec<String>,
+    agencies: Vec<AgencyData>,
+    routes: Vec<RouteData>,
+    // By the id of the routing data, so runs of frequency-based trips share the same data
+    trips: Vec<TripData>,
+    agency_ids: HashMap<String, usize>,
+    route_ids: HashMap<String, usize>,
+    // First run of every trip
+    trip_ids: HashMap<String, usize>,
+    stop_routes: HashMap<StopId, Vec<usize>>,
+    // Stops of the pattern of each route that the most trips serve
+    route_stops: Vec<Vec<StopId>>,
+}
+
+struct AgencyData {
+    gtfs_id: String,
+    name: Option<String>,
+}
+
+struct RouteData {
+    gtfs_id: String,
+    agency: Option<usize>,
+    short_name: Option<String>,
+    long_name: Option<String>,
+    route_type: Option<u32>,
+}
+
+struct TripData {
+    gtfs_id: String,
+    route: Option<usize>,
+    service_id: String,
+}
+
+impl Catalog {
+    pub(super) fn read(tables: &Path) -> Result<Self, DrinoError> {
+        let scan = |name: &str| LazyFrame::scan_parquet(tables.join(name), Default::default());
+
+        let agencies = scan("agencies.parquet")?.collect()?;
+        let mut feeds: Vec<String> = strings(&agencies, "dataset_id")?.into_iter().flatten().collect();
+        feeds.sort_unstable();
+        feeds.dedup();
+        let agencies: Vec<AgencyData> = namespaced(&agencies, "agency_id_in_dataset")?.into_iter()
+            .zip(strings(&agencies, "agency_name")?)
+            .map(|(gtfs_id, name)| AgencyData { gtfs_id, name })
+            .collect();
+        let agency_ids = index(agencies.iter().map(|agency| &agency.gtfs_id));
+
+        let routes = scan("routes.parquet")?
+            .with_column(col("route_type").cast(DataType::UInt32))
+            .collect()?;
+        let routes: Vec<RouteData> = namespaced(&routes, "route_id_in_dataset")?.into_iter()
+            .zip(namespaced(&routes, "agency_id_in_dataset")?)
+            .zip(strings(&routes, "route_short_name")?.into_iter().zip(strings(&routes, "route_long_name")?))
+            .zip(routes.column("route_type")?.u32()?.iter())
+            .map(|(((gtfs_id, agency_id), (short_name, long_name)), route_type)| RouteData {
+                gtfs_id,
+                agency: agency_ids.get(&agency_id).copied(),
+                short_name,
+                long_name,
+                route_type,
+            })
+            .collect();
+        let route_ids = index(routes.iter().map(|route| &route.gtfs_id));
+
+        let trips = scan("trips.parquet")?
+            .select([col("trip_id"), col("dataset_id"), col("trip_id_in_dataset"), col("route_id_in_dataset"), col("service_id_in_dataset")])
+            .sort(["trip_id"], Default::default())
+            .collect()?;
+        let trips: Vec<TripData> = namespaced(&trips, "trip_id_in_dataset")?.into_iter()
+            .zip(namespaced(&trips, "route_id_in_dataset")?)
+            .zip(namespaced(&trips, "service_id_in_dataset")?)
+            .map(|((gtfs_id, route_id), service_id)| TripData { gtfs_id, route: route_ids.get(&route_id).copied(), service_id })
+            .collect();
+        let trip_ids = index(trips.iter().map(|trip| &trip.gtfs_id));
+
+        let patterns = scan("route_patterns.parquet")?.collect()?;
+        let mut stop_routes: HashMap<StopId, Vec<usize>> = HashMap::new();
+        let mut route_stops = vec![(0, vec![]); routes.len()];
+        for ((route_id, num_trips), stop_ids) in namespaced(&patterns, "route_id_in_dataset")?.into_iter()
+            .zip(patterns.column("num_trips")?.u32()?.iter())
+            .zip(patterns.column("stop_ids")?.list()?.into_iter())
+        {
+            let (Some(route), Some(stop_ids)) = (route_ids.get(&route_id).copied(), stop_ids) else { continue };
+            let stops: Vec<StopId> = stop_ids.u32()?.iter().flatten().map(StopId).collect();
+            for stop in &stops {
+                let routes = stop_routes.entry(*stop).or_default();
+                if !routes.contains(&route) {
+                    routes.push(route);
+                }
+            }
+            let num_trips = num_trips.unwrap_or_default();
+            if route_stops[route].1.is_empty() || route_stops[route].0 < num_trips {
+                route_stops[route] = (num_trips, stops);
+            }
+        }
+
+        Ok(Self {
+            feeds,
+            agencies,
+            routes,
+            trips,
+            agency_ids,
+            route_ids,
+            trip_ids,
+            stop_routes,
+            route_stops: route_stops.into_iter().map(|(_, stops)| stops).collect(),
+        })
+    }
+
+    /// The name of the route of a trip as shown to travellers, e.g. "S1": the short name if it
+    /// has one, the long name otherwise
+    pub(super) fn line_name(&self, trip: TripId) -> Option<String> {
+        let route = &self.routes[self.trips.get(trip.0 as usize)?.route?];
+        route.short_name.clone().or_else(|| route.long_name.clone())
+    }
+}
+
+fn strings(frame: &DataFrame, column: &str) -> Result<Vec<Option<String>>, PolarsError> {
+    Ok(frame.column(column)?.str()?.iter().map(|value| value.map(str::to_owned)).collect())
+}
+
+// Ids of a column prefixed by the dataset, like the original ids of stops and trips
+fn namespaced(frame: &DataFrame, column: &str) -> Result<Vec<String>, PolarsError> {
+    Ok(strings(frame, "dataset_id")?.into_iter()
+        .zip(strings(frame, column)?)
+        .map(|(dataset_id, id)| format!("{}:{}", dataset_id.unwrap_or_default(), id.unwrap_or_default()))
+        .collect())
+}
+
+// Position of every id, the first one if it is duplicated
+fn index<'a>(ids: impl Iterator<Item = &'a String>) -> HashMap<String, usize> {
+    let mut index = HashMap::new();
+    for (idx, id) in ids.enumerate() {
+        index.entry(id.clone()).or_insert(idx);
+    }
+    index
+}
+
+// Milliseconds since the epoch of a time of the timetable on the service day that starts at
+// `day_start`
+fn epoch_millis(day_start: DateTime<Utc>, time: DateTime<Utc>) -> i64 {
+    absolute_time(day_start, time).timestamp_millis()
+}
+
+/// The transport modes of OpenTripPlanner
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum Mode {
+    Airplane,
+    Bicycle,
+    Bus,
+    CableCar,
+    Car,
+    Coach,
+    Ferry,
+    Funicular,
+    Gondola,
+    Monorail,
+    Rail,
+    Subway,
+    Tram,
+    Transit,
+    Trolleybus,
+    Walk,
+}
+
+/// How a transport mode is used, e.g. `PARK` to leave a car or bike at a stop
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum Qualifier {
+    Rent,
+    Have,
+    Park,
+    Keep,
+    Pickup,
+    Dropoff,
+    Access,
+    Egress,
+    Direct,
+    Hail,
+}
+
+// The mode of OpenTripPlanner for the mode of the route type in the registry
+fn transport_mode(state: &State, route_type: Option<u32>) -> Mode {
+    let Some(mode) = route_type.and_then(|route_type| state.modes.mode_of(route_type)) else { return Mode::Transit };
+    match mode.id.as_str() {
+        "tram" => Mode::Tram,
+        "subway" => Mode::Subway,
+        "rail" => Mode::Rail,
+        "bus" => Mode::Bus,
+        "ferry" => Mode::Ferry,
+        "cable-tram" => Mode::CableCar,
+        "aerial-lift" => Mode::Gondola,
+        "funicular" => Mode::Funicular,
+        "trolleybus" => Mode::Trolleybus,
+        "monorail" => Mode::Monorail,
+        // Configured modes
+        _ => match mode.category.as_deref() {
+            Some("rail") => Mode::Rail,
+            Some("road") => Mode::Bus,
+            Some("water") => Mode::Ferry,
+            Some("cable") => Mode::Gondola,
+            _ => Mode::Transit,
+        },
+    }
+}
+
+// The nearest stop with coordinates, unless all are too far to walk. Distances are approximated
+// on a plane, which is precise enough at this scale.
+fn nearest_stop(stops: &Stops, lat: f64, lon: f64) -> Option<StopId> {
+    let meters = |(stop_lat, stop_lon): (f64, f64)| {
+        let x = (stop_lon - lon) * lat.to_radians().cos() * 111_320.0;
+        let y = (stop_lat - lat) * 110_574.0;
+        (x * x + y * y).sqrt()
+    };
+    stops.coords.iter().enumerate()
+        .filter_map(|(idx, coords)| match coords {
+            (Some(lat), Some(lon)) => Some((idx, meters((*lat as f64, *lon as f64)))),
+            _ => None,
+        })
+        .filter(|(_, distance)| *distance <= MAX_SNAP_METERS)
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(idx, _)| StopId(idx as u32))
+}
+
+fn invalid(argument: &str, message: impl ToString) -> Error {
+    Error::new(format!("Invalid argument {}: {}", argument, message.to_string()))
+}
+
+fn in_feeds(gtfs_id: &str, feeds: &Option<Vec<String>>) -> bool {
+    feeds.as_ref().is_none_or(|feeds| feeds.iter().any(|feed| gtfs_id.split_once(':').is_some_and(|(prefix, _)| prefix == feed)))
+}
+
+pub(super) struct QueryType(Arc<State>);
+
+#[derive(InputObject)]
+#[graphql(name = "InputCoordinates")]
+struct Coordinates {
+    lat: f64,
+    lon: f64,
+}
+
+/// Ids separated by commas
+#[derive(InputObject, Default)]
+#[graphql(name = "InputBanned")]
+struct Banned {
+    stops: Option<String>,
+    routes: Option<String>,
+    agencies: Option<String>,
+}
+
+#[derive(InputObject)]
+struct TransportMode {
+    mode: Mode,
+    qualifier: Option<Qualifier>,
+}
+
+#[Object]
+impl QueryType {
+    #[allow(clippy::too_many_arguments)]
+    async fn plan(
+        &self,
+        from: Option<Coordinates>,
+        to: Option<Coordinates>,
+        from_place: Option<String>,
+        to_place: Option<String>,
+        date: Option<String>,
+        time: Option<String>,
+        arrive_by: Option<bool>,
+        num_itineraries: Option<usize>,
+        max_transfers: Option<usize>,
+        min_transfer_time: Option<i64>,
+        wheelchair: Option<bool>,
+        banned: Option<Banned>,
+        bike_speed: Option<f64>,
+        transport_modes: Option<Vec<TransportMode>>,
+    ) -> Result<Plan<'_>, Error> {
+        let state = &self.0;
+        if arrive_by.unwrap_or_default() {
+            return Err(invalid("arriveBy", "only departures are planned"));
+        }
+        let now = Utc::now().with_timezone(&state.timezone).naive_local();
+        let date = match date {
+            Some(date) => NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|err| invalid("date", err))?,
+            None => now.date(),
+        };
+        let time = match time {
+            Some(time) => NaiveTime::parse_from_str(&time, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M"))
+                .map_err(|err| invalid("time", err))?,
+            None => now.time(),
+        };
+        let at = date.and_time(time);
+
+        let from = location(&state.stops, from, from_place, "from", "fromPlace")?;
+        let to = location(&state.stops, to, to_place, "to", "toPlace")?;
+        let mut plan = Plan {
+            state,
+            at,
+            day_start: service_day_start(at, state.timezone),
+            from: from.ok(),
+            to: to.ok(),
+            itineraries: vec![],
+            routing_errors: vec![],
+        };
+        if let Err(code) = from {
+            plan.routing_errors.push(RoutingError { code, input_field: Some(InputField::From) });
+        }
+        if let Err(code) = to {
+            plan.routing_errors.push(RoutingError { code, input_field: Some(InputField::To) });
+        }
+        let (Ok(start), Ok(target)) = (from, to) else { return Ok(plan) };
+
+        let journey_options = journey_options(wheelchair, banned, bike_speed, max_transfers, transport_modes.unwrap_or_default());
+        let mut options = state.plan_options(&journey_options)
+            .map_err(|problem| invalid("banned", problem.detail.unwrap_or_default()))?;
+        options.query.min_transfer_buffer = TimeDelta::seconds(min_transfer_time.unwrap_or_default()).max(state.min_transfer_time);
+        let limit = num_itineraries.unwrap_or(DEFAULT_ITINERARIES);
+        let departure = departure_in_timetable(at);
+        // Plans block for a while like the searches of the REST API
+        let search = state.clone();
+        let journeys = web::block(move || search.plan(start, target, at.date(), departure, &options, limit))
+            .await
+            .map_err(|err| Error::new(format!("The search failed: {}", err)))?;
+        match journeys {
+            Ok(journeys) => {
+                plan.itineraries = journeys.iter()
+                    .map(|journey| Itinerary::new(state, plan.day_start, journey, departure))
+                    .collect();
+            }
+            Err(QueryError::NoRouteFound) => plan.routing_errors.push(RoutingError { code: RoutingErrorCode::NoTransitConnection, input_field: None }),
+            Err(QueryError::DateOutOfRange(_)) => plan.routing_errors.push(RoutingError { code: RoutingErrorCode::OutsideServicePeriod, input_field: Some(InputField::DateTime) }),
+            Err(err) => return Err(Error::new(err.to_string())),
+        }
+        Ok(plan)
+    }
+
+    async fn feeds(&self) -> Vec<Feed<'_>> {
+        let state = &self.0;
+        state.catalog.feeds.iter().map(|feed| Feed { state, feed }).collect()
+    }
+
+    async fn agencies(&self) -> Vec<Agency<'_>> {
+        let state = &self.0;
+        (0..state.catalog.agencies.len()).map(|idx| Agency { state, idx }).collect()
+    }
+
+    async fn agency(&self, id: String) -> Option<Agency<'_>> {
+        let state = &self.0;
+        state.catalog.agency_ids.get(&id).map(|idx| Agency { state, idx: *idx })
+    }
+
+    async fn stop(&self, id: String) -> Option<Stop<'_>> {
+        let state = &self.0;
+        state.stops.original_ids.stops.get(&id).map(|id| Stop { state, id })
+    }
+
+    async fn stops(&self, ids: Option<Vec<String>>, name: Option<String>, max_results: Option<usize>) -> Vec<Stop<'_>> {
+        let state = &self.0;
+        let stops: Box<dyn Iterator<Item = StopId> + Send + '_> = match ids {
+            Some(ids) => Box::new(ids.into_iter().filter_map(|id| state.stops.original_ids.stops.get(&id))),
+            None => {
+                let text = name.map(|name| name.to_lowercase());
+                Box::new((0..state.stops.names.len())
+                    .filter(move |idx| text.as_ref().is_none_or(|text| state.stops.names[*idx].to_lowercase().contains(text)))
+                    .map(|idx| StopId(idx as u32)))
+            }
+        };
+        stops.take(max_results.unwrap_or(usize::MAX)).map(|id| Stop { state, id }).collect()
+    }
+
+    async fn route(&self, id: String) -> Option<Route<'_>> {
+        let state = &self.0;
+        state.catalog.route_ids.get(&id).map(|idx| Route { state, idx: *idx })
+    }
+
+    async fn routes(
+        &self,
+        ids: Option<Vec<String>>,
+        feeds: Option<Vec<String>>,
+        name: Option<String>,
+        transport_modes: Option<Vec<Mode>>,
+    ) -> Vec<Route<'_>> {
+        let state = &self.0;
+        let catalog = &state.catalog;
+        let text = name.map(|name| name.to_lowercase());
+        let routes: Vec<usize> = match ids {
+            Some(ids) => ids.iter().filter_map(|id| catalog.route_ids.get(id).copied()).collect(),
+            None => (0..catalog.routes.len()).collect(),
+        };
+        routes.into_iter()
+            .filter(|idx| {
+                let route = &catalog.routes[*idx];
+                let named = |name: &Option<String>| name.as_ref().is_some_and(|name| name.to_lowercase().contains(text.as_deref().unwrap_or_default()));
+                in_feeds(&route.gtfs_id, &feeds)
+                    && (text.is_none() || named(&route.short_name) || named(&route.long_name))
+                    && transport_modes.as_ref().is_none_or(|modes| modes.contains(&transport_mode(state, route.route_type)))
+            })
+            .map(|idx| Route { state, idx })
+            .collect()
+    }
+
+    async fn trip(&self, id: String) -> Option<Trip<'_>> {
+        let state = &self.0;
+        state.catalog.trip_ids.get(&id).map(|idx| Trip { state, idx: *idx })
+    }
+
+    async fn trips(&self, feeds: Option<Vec<String>>) -> Vec<Trip<'_>> {
+        let state = &self.0;
+        let catalog = &state.catalog;
+        catalog.trips.iter().enumerate()
+            .filter(|(idx, trip)| catalog.trip_ids.get(&trip.gtfs_id) == Some(idx) && in_feeds(&trip.gtfs_id, &feeds))
+            .map(|(idx, _)| Trip { state, idx })
+            .collect()
+    }
+}
+
+// Like the options of `drino query`, except that the minimum transfer time is in seconds
+fn journey_options(
+    wheelchair: Option<bool>,
+    banned: Option<Banned>,
+    bike_speed: Option<f64>,
+    max_transfers: Option<usize>,
+    modes: Vec<TransportMode>,
+) -> JourneyOptions {
+    let banned = banned.unwrap_or_default();
+    let ids = |ids: Option<String>| ids.iter()
+        .flat_map(|ids| ids.split(','))
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(str::to_string)
+        .collect();
+    let parks = |mode: Mode| modes.iter().any(|transport| transport.mode == mode && transport.qualifier == Some(Qualifier::Park));
+    let access_mode = match (parks(Mode::Car), parks(Mode::Bicycle)) {
+        (true, _) => AccessMode::Car,
+        (_, true) => AccessMode::Bike,
+        _ => AccessMode::Walk,
+    };
+    JourneyOptions {
+        wheelchair: wheelchair.unwrap_or_default(),
+        bike: modes.iter().any(|transport| transport.mode == Mode::Bicycle && transport.qualifier.is_none()),
+        // OpenTripPlanner takes m/s
+        cycling_speed: bike_speed.map_or(CYCLING_SPEED.0, |speed| speed * 3.6),
+        max_transfers,
+        avoided_stops: ids(banned.stops),
+        avoided_routes: ids(banned.routes),
+        avoided_agencies: ids(banned.agencies),
+        min_transfer_buffer: 0,
+        access_mode,
+        egress_mode: AccessMode::Walk,
+    }
+}
+
+// The stop of the coordinates or the place of a location, otherwise the code of the routing
+// error. Places are "<name>::<location>" or just the location, which is "<lat>,<lon>" or a stop
+// by its id or name.
+fn location(
+    stops: &Stops,
+    coordinates: Option<Coordinates>,
+    place: Option<String>,
+    coordinates_argument: &str,
+    place_argument: &str,
+) -> Result<Result<StopId, RoutingErrorCode>, Error> {
+    if let Some(Coordinates { lat, lon }) = coordinates {
+        return Ok(nearest_stop(stops, lat, lon).ok_or(RoutingErrorCode::NoStopsInRange));
+    }
+    let Some(place) = place else {
+        return Err(invalid(coordinates_argument, format!("either {} or {} is required", coordinates_argument, place_argument)));
+    };
+    let location = place.rsplit_once("::").map_or(place.as_str(), |(_, location)| location);
+    let coords = location.split_once(',')
+        .and_then(|(lat, lon)| Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?)));
+    Ok(match coords {
+        Some((lat, lon)) => nearest_stop(stops, lat, lon).ok_or(RoutingErrorCode::NoStopsInRange),
+        None => stops.find(location).map_err(|_| RoutingErrorCode::LocationNotFound),
+    })
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum RoutingErrorCode {
+    LocationNotFound,
+    NoStopsInRange,
+    NoTransitConnection,
+    OutsideServicePeriod,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum InputField {
+    DateTime,
+    From,
+    To,
+}
+
+struct RoutingError {
+    code: RoutingErrorCode,
+    // FROM or TO if the error is about the location, DATE_TIME if it is about the date
+    input_field: Option<InputField>,
+}
+
+#[Object]
+impl RoutingError {
+    async fn code(&self) -> RoutingErrorCode {
+        self.code
+    }
+
+    async fn input_field(&self) -> Option<InputField> {
+        self.input_field
+    }
+
+    async fn description(&self) -> &'static str {
+        match self.code {
+            RoutingErrorCode::LocationNotFound => "No stop has the name or id of the location",
+            RoutingErrorCode::NoStopsInRange => "No stop is within walking distance of the location",
+            RoutingErrorCode::NoTransitConnection => "No connection was found between the locations at this time",
+            RoutingErrorCode::OutsideServicePeriod => "The date is outside the period of the timetable",
+        }
+    }
+}
+
+struct Plan<'a> {
+    state: &'a State,
+    at: NaiveDateTime,
+    day_start: DateTime<Utc>,
+    from: Option<StopId>,
+    to: Option<StopId>,
+    itineraries: Vec<Itinerary<'a>>,
+    routing_errors: Vec<RoutingError>,
+}
+
+#[Object]
+impl<'a> Plan<'a> {
+    async fn date(&self) -> i64 {
+        epoch_millis(self.day_start, departure_in_timetable(self.at))
+    }
+
+    async fn from(&self) -> Option<Place<'a>> {
+        self.from.map(|stop| Place { state: self.state, stop })
+    }
+
+    async fn to(&self) -> Option<Place<'a>> {
+        self.to.map(|stop| Place { state: self.state, stop })
+    }
+
+    async fn itineraries(&self) -> &[Itinerary<'a>] {
+        &self.itineraries
+    }
+
+    async fn routing_errors(&self) -> &[RoutingError] {
+        &self.routing_errors
+    }
+}
+
+// A leg with its start and end in the timetable, and the mode of drives and rides to and from P+R
+// stops
+type TimedLeg = (journey::Leg, DateTime<Utc>, DateTime<Utc>, Option<AccessMode>);
+
+struct Itinerary<'a> {
+    state: &'a State,
+    day_start: DateTime<Utc>,
+    // Walks start when the leg before ends, or when the journey departs
+    legs: Vec<TimedLeg>,
+}
+
+impl<'a> Itinerary<'a> {
+    fn new(state: &'a State, day_start: DateTime<Utc>, park_and_ride_journey: &ParkAndRideJourney, departure: DateTime<Utc>) -> Self {
+        let ParkAndRideJourney { access, journey, egress } = park_and_ride_journey;
+        let access_leg = |leg: &AccessLeg| (journey::Leg::Transfer { start: leg.start, end: leg.end, duration: leg.duration }, Some(leg.mode));
+        let mut time = park_and_ride_journey.departure().unwrap_or(departure);
+        let legs = access.iter().map(access_leg)
+            .chain(journey.legs().map(|leg| (leg.clone(), None)))
+            .chain(egress.iter().map(access_leg))
+            .map(|(leg, mode)| {
+                let (start, end) = match &leg {
+                    journey::Leg::Ride { boarding_time, alight_time, .. } => (*boarding_time, *alight_time),
+                    journey::Leg::Transfer { duration, .. } => (time, time + *duration),
+                };
+                time = end;
+                (leg, start, end, mode)
+            })
+            .collect();
+        Self { state, day_start, legs }
+    }
+
+    fn start(&self) -> Option<DateTime<Utc>> {
+        self.legs.first().map(|(_, start, _, _)| *start)
+    }
+
+    fn end(&self) -> Option<DateTime<Utc>> {
+        self.legs.last().map(|(_, _, end, _)| *end)
+    }
+
+    fn total_duration(&self) -> Duration {
+        self.start().zip(self.end()).map_or(Duration::zero(), |(start, end)| end - start)
+    }
+
+    fn duration_of(&self, of_leg: impl Fn(&journey::Leg, Option<AccessMode>) -> bool) -> Duration {
+        self.legs.iter()
+            .filter(|(leg, _, _, mode)| of_leg(leg, *mode))
+            .map(|(_, start, end, _)| *end - *start)
+            .sum()
+    }
+
+    fn walk_duration(&self) -> Duration {
+        self.duration_of(|leg, mode| matches!(leg, journey::Leg::Transfer { .. }) && mode.is_none())
+    }
+}
+
+#[Object]
+impl Itinerary<'_> {
+    async fn start_time(&self) -> Option<i64> {
+        self.start().map(|start| epoch_millis(self.day_start, start))
+    }
+
+    async fn end_time(&self) -> Option<i64> {
+        self.end().map(|end| epoch_millis(self.day_start, end))
+    }
+
+    async fn duration(&self) -> i64 {
+        self.total_duration().num_seconds()
+    }
+
+    async fn walk_time(&self) -> i64 {
+        self.walk_duration().num_seconds()
+    }
+
+    async fn waiting_time(&self) -> i64 {
+        // Drives and rides to P+R stops aren't waited
+        let moving_time = self.duration_of(|leg, mode| matches!(leg, journey::Leg::Ride { .. }) || mode.is_some());
+        (self.total_duration() - self.walk_duration() - moving_time).num_seconds()
+    }
+
+    async fn number_of_transfers(&self) -> usize {
+        let num_rides = self.legs.iter().filter(|(leg, _, _, _)| matches!(leg, journey::Leg::Ride { .. })).count();
+        num_rides.saturating_sub(1)
+    }
+
+    async fn legs(&self) -> Vec<Leg<'_>> {
+        self.legs.iter()
+            .map(|(leg, start, end, mode)| Leg { itinerary: self, leg, start: *start, end: *end, mode: *mode })
+            .collect()
+    }
+}
+
+struct Leg<'a> {
+    itinerary: &'a Itinerary<'a>,
+    leg: &'a journey::Leg,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    // Of drives and rides to and from P+R stops
+    mode: Option<AccessMode>,
+}
+
+impl<'a> Leg<'a> {
+    fn state(&self) -> &'a State {
+        self.itinerary.state
+    }
+
+    fn trip_idx(&self) -> Option<usize> {
+        match self.leg {
+            journey::Leg::Ride { trip: TripId(trip), .. } => self.state().catalog.trips.get(*trip as usize).map(|_| *trip as usize),
+            journey::Leg::Transfer { .. } => None,
+        }
+    }
+
+    fn route_idx(&self) -> Option<usize> {
+        self.trip_idx().and_then(|trip| self.state().catalog.trips[trip].route)
+    }
+
+    fn stops(&self) -> (StopId, StopId) {
+        match self.leg {
+            journey::Leg::Ride { boarding_stop, alight_stop, .. } => (*boarding_stop, *alight_stop),
+            journey::Leg::Transfer { start, end, .. } => (*start, *end),
+        }
+    }
+
+    fn is_ride(&self) -> bool {
+        matches!(self.leg, journey::Leg::Ride { .. })
+    }
+}
+
+#[Object]
+impl<'a> Leg<'a> {
+    async fn mode(&self) -> Mode {
+        let state = self.state();
+        if self.is_ride() {
+            return transport_mode(state, self.route_idx().and_then(|route| state.catalog.routes[route].route_type));
+        }
+        match self.mode {
+            Some(AccessMode::Car) => Mode::Car,
+            Some(AccessMode::Bike) => Mode::Bicycle,
+            Some(AccessMode::Walk) | None => Mode::Walk,
+        }
+    }
+
+    async fn start_time(&self) -> i64 {
+        epoch_millis(self.itinerary.day_start, self.start)
+    }
+
+    async fn end_time(&self) -> i64 {
+        epoch_millis(self.itinerary.day_start, self.end)
+    }
+
+    async fn duration(&self) -> f64 {
+        (self.end - self.start).num_milliseconds() as f64 / 1000.0
+    }
+
+    async fn transit_leg(&self) -> bool {
+        self.is_ride()
+    }
+
+    async fn real_time(&self) -> bool {
+        false
+    }
+
+    async fn from(&self) -> Place<'a> {
+        Place { state: self.state(), stop: self.stops().0 }
+    }
+
+    async fn to(&self) -> Place<'a> {
+        Place { state: self.state(), stop: self.stops().1 }
+    }
+
+    async fn route(&self) -> Option<Route<'a>> {
+        self.route_idx().map(|idx| Route { state: self.state(), idx })
+    }
+
+    async fn trip(&self) -> Option<Trip<'a>> {
+        self.trip_idx().map(|idx| Trip { state: self.state(), idx })
+    }
+
+    async fn agency(&self) -> Option<Agency<'a>> {
+        let state = self.state();
+        self.route_idx().and_then(|route| state.catalog.routes[route].agency).map(|idx| Agency { state, idx })
+    }
+}
+
+/// The types of the vertices of OpenTripPlanner that places are
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum VertexType {
+    Normal,
+    Transit,
+}
+
+struct Place<'a> {
+    state: &'a State,
+    stop: StopId,
+}
+
+#[Object]
+impl<'a> Place<'a> {
+    async fn name(&self) -> Option<String> {
+        self.state.stops.names.get(self.stop.0 as usize).cloned()
+    }
+
+    async fn lat(&self) -> Option<f32> {
+        self.state.stops.coords.get(self.stop.0 as usize).and_then(|(lat, _)| *lat)
+    }
+
+    async fn lon(&self) -> Option<f32> {
+        self.state.stops.coords.get(self.stop.0 as usize).and_then(|(_, lon)| *lon)
+    }
+
+    // Places are always stops
+    async fn vertex_type(&self) -> VertexType {
+        VertexType::Transit
+    }
+
+    async fn stop(&self) -> Stop<'a> {
+        Stop { state: self.state, id: self.stop }
+    }
+}
+
+struct Stop<'a> {
+    state: &'a State,
+    id: StopId,
+}
+
+impl<'a> Stop<'a> {
+    fn route_idxs(&self) -> &'a [usize] {
+        self.state.catalog.stop_routes.get(&self.id).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn original_id(&self) -> Option<String> {
+        self.state.stops.original_ids.stop(self.id).map(str::to_string)
+    }
+}
+
+#[Object]
+impl<'a> Stop<'a> {
+    async fn id(&self) -> Option<String> {
+        self.original_id()
+    }
+
+    async fn gtfs_id(&self) -> Option<String> {
+        self.original_id()
+    }
+
+    async fn name(&self) -> Option<String> {
+        self.state.stops.names.get(self.id.0 as usize).cloned()
+    }
+
+    async fn lat(&self) -> Option<f32> {
+        self.state.stops.coords.get(self.id.0 as usize).and_then(|(lat, _)| *lat)
+    }
+
+    async fn lon(&self) -> Option<f32> {
+        self.state.stops.coords.get(self.id.0 as usize).and_then(|(_, lon)| *lon)
+    }
+
+    async fn vehicle_mode(&self) -> Option<Mode> {
+        let state = self.state;
+        self.route_idxs().first().map(|route| transport_mode(state, state.catalog.routes[*route].route_type))
+    }
+
+    async fn routes(&self) -> Vec<Route<'a>> {
+        self.route_idxs().iter().map(|idx| Route { state: self.state, idx: *idx }).collect()
+    }
+}
+
+struct Route<'a> {
+    state: &'a State,
+    idx: usize,
+}
+
+impl<'a> Route<'a> {
+    fn data(&self) -> &'a RouteData {
+        &self.state.catalog.routes[self.idx]
+    }
+}
+
+#[Object]
+impl<'a> Route<'a> {
+    async fn id(&self) -> &'a str {
+        &self.data().gtfs_id
+    }
+
+    async fn gtfs_id(&self) -> &'a str {
+        &self.data().gtfs_id
+    }
+
+    async fn short_name(&self) -> Option<&'a str> {
+        self.data().short_name.as_deref()
+    }
+
+    async fn long_name(&self) -> Option<&'a str> {
+        self.data().long_name.as_deref()
+    }
+
+    async fn mode(&self) -> Mode {
+        transport_mode(self.state, self.data().route_type)
+    }
+
+    #[graphql(name = "type")]
+    async fn route_type(&self) -> Option<u32> {
+        self.data().route_type
+    }
+
+    async fn agency(&self) -> Option<Agency<'a>> {
+        self.data().agency.map(|idx| Agency { state: self.state, idx })
+    }
+
+    async fn stops(&self) -> Vec<Stop<'a>> {
+        let state = self.state;
+        state.catalog.route_stops[self.idx].iter().map(|id| Stop { state, id: *id }).collect()
+    }
+
+    async fn trips(&self) -> Vec<Trip<'a>> {
+        let state = self.state;
+        state.catalog.trips.iter().enumerate()
+            .filter(|(idx, trip)| trip.route == Some(self.idx) && state.catalog.trip_ids.get(&trip.gtfs_id) == Some(idx))
+            .map(|(idx, _)| Trip { state, idx })
+            .collect()
+    }
+}
+
+struct Trip<'a> {
+    state: &'a State,
+    idx: usize,
+}
+
+impl<'a> Trip<'a> {
+    fn data(&self) -> &'a TripData {
+        &self.state.catalog.trips[self.idx]
+    }
+}
+
+#[Object]
+impl<'a> Trip<'a> {
+    async fn id(&self) -> &'a str {
+        &self.data().gtfs_id
+    }
+
+    async fn gtfs_id(&self) -> &'a str {
+        &self.data().gtfs_id
+    }
+
+    async fn service_id(&self) -> &'a str {
+        &self.data().service_id
+    }
+
+    async fn route(&self) -> Option<Route<'a>> {
+        self.data().route.map(|idx| Route { state: self.state, idx })
+    }
+}
+
+struct Agency<'a> {
+    state: &'a State,
+    idx: usize,
+}
+
+impl<'a> Agency<'a> {
+    fn data(&self) -> &'a AgencyData {
+        &self.state.catalog.agencies[self.idx]
+    }
+}
+
+#[Object]
+impl<'a> Agency<'a> {
+    async fn id(&self) -> &'a str {
+        &self.data().gtfs_id
+    }
+
+    async fn gtfs_id(&self) -> &'a str {
+        &self.data().gtfs_id
+    }
+
+    async fn name(&self) -> Option<&'a str> {
+        self.data().name.as_deref()
+    }
+
+    async fn routes(&self) -> Vec<Route<'a>> {
+        let state = self.state;
+        (0..state.catalog.routes.len())
+            .filter(|route| state.catalog.routes[*route].agency == Some(self.idx))
+            .map(|idx| Route { state, idx })
+            .collect()
+    }
+}
+
+struct Feed<'a> {
+    state: &'a State,
+    feed: &'a str,
+}
+
+#[Object]
+impl<'a> Feed<'a> {
+    async fn feed_id(&self) -> &'a str {
+        self.feed
+    }
+
+    async fn agencies(&self) -> Vec<Agency<'a>> {
+        let state = self.state;
+        state.catalog.agencies.iter().enumerate()
+            .filter(|(_, agency)| agency.gtfs_id.split_once(':').is_some_and(|(feed, _)| feed == self.feed))
+            .map(|(idx, _)| Agency { state, idx })
+            .collect()
+    }
+}
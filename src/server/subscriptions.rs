@@ -0,0 +1,185 @@
+//! Webhooks of planned journeys. A client subscribes to a journey by posting it as `/api/v1/plan`
+//! returned it to `/api/v1/subscriptions`, together with the URL of its webhook, e.g.
+//!
+//! ```json
+//! {"journey": {...}, "webhook_url": "https://example.org/hook", "delay_threshold_seconds": 300}
+//! ```
+//!
+//! and gets the id of the journey back. Whenever a message of the trip updates feeds delays a ride
+//! at the stop where it is boarded or left by at least the threshold, cancels it or moves it to
+//! another platform, the alerts of the journey are posted to the webhook, e.g.
+//!
+//! ```json
+//! {"journey_id": "8c3b1f0e5d2a7c41", "alerts": [{"type": "delayed", "leg": 1, "stop": {...}, "delay_seconds": 420}]}
+//! ```
+//!
+//! They are posted again only once they change. `DELETE /api/v1/subscriptions/<journey id>?webhook_url=<url>`
+//! ends the subscription.
+
+use super::live::{journey_of, Rejection};
+use super::{PlannedJourney, State, Stop};
+use crate::query::service_day_start;
+use actix_web::{delete, post, web, HttpResponse};
+use chrono::{Days, NaiveDate, NaiveTime, TimeDelta, Utc};
+use common::types::errors::ErrorCode;
+use hashbrown::HashMap;
+use log::{debug, warn};
+use routing::algorithm::QueryResult;
+use routing::monitoring::{Alert, JourneyId, Subscription, Subscriptions, TripChange};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use visualization::api::problem::Problem;
+
+// Smaller delays aren't posted, unless the subscription asks for another threshold
+const DEFAULT_DELAY_THRESHOLD_SECONDS: i64 = 120;
+
+/// The subscribed journeys by the day they depart, since the times of their legs are relative to it
+#[derive(Default)]
+pub(super) struct JourneySubscriptions(Mutex<BTreeMap<NaiveDate, Subscriptions>>);
+
+#[derive(Deserialize)]
+struct SubscriptionRequest {
+    journey: PlannedJourney,
+    webhook_url: String,
+    #[serde(default = "default_delay_threshold")]
+    delay_threshold_seconds: i64,
+}
+
+fn default_delay_threshold() -> i64 {
+    DEFAULT_DELAY_THRESHOLD_SECONDS
+}
+
+#[derive(Serialize)]
+struct SubscriptionResponse {
+    journey_id: String,
+}
+
+#[derive(Deserialize)]
+struct UnsubscribeQuery {
+    webhook_url: String,
+}
+
+#[derive(Serialize)]
+struct WebhookMessage {
+    journey_id: String,
+    alerts: Vec<WebhookAlert>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum WebhookAlert {
+    Delayed { leg: usize, stop: Stop, delay_seconds: i64 },
+    Cancelled { leg: usize },
+    PlatformChanged { leg: usize, stop: Stop, new_stop: Stop },
+}
+
+#[post("/api/v1/subscriptions")]
+pub(super) async fn subscribe(request: web::Json<SubscriptionRequest>, state: web::Data<State>) -> actix_web::Result<HttpResponse> {
+    let request = request.into_inner();
+    if !request.webhook_url.starts_with("http://") && !request.webhook_url.starts_with("https://") {
+        return Err(actix_web::error::ErrorBadRequest(format!("Invalid webhook URL {}", request.webhook_url)));
+    }
+    let (journey, day) = journey_of(&request.journey, &state).map_err(|rejection| match rejection {
+        Rejection::Problem(problem) => actix_web::Error::from(problem),
+        Rejection::Invalid(description) => actix_web::error::ErrorBadRequest(description),
+    })?;
+
+    let journey_id = state.subscriptions.0.lock().unwrap()
+        .entry(day)
+        .or_default()
+        .subscribe(Subscription {
+            journey,
+            webhook_url: request.webhook_url,
+            delay_threshold: TimeDelta::seconds(request.delay_threshold_seconds),
+        });
+    Ok(HttpResponse::Created().json(SubscriptionResponse { journey_id: journey_id.to_string() }))
+}
+
+#[delete("/api/v1/subscriptions/{journey_id}")]
+pub(super) async fn unsubscribe(journey_id: web::Path<String>, query: web::Query<UnsubscribeQuery>, state: web::Data<State>) -> Result<HttpResponse, Problem> {
+    let not_found = || Problem::new(ErrorCode::NotFound, Some(format!("No subscription of the journey {} with the webhook", journey_id)));
+    let journey_id: JourneyId = journey_id.parse().map_err(|_| not_found())?;
+
+    let mut subscriptions = state.subscriptions.0.lock().unwrap();
+    let mut removed = false;
+    for subscriptions in subscriptions.values_mut() {
+        removed |= subscriptions.unsubscribe(journey_id, &query.webhook_url);
+    }
+    subscriptions.retain(|_, subscriptions| !subscriptions.is_empty());
+    if !removed {
+        return Err(not_found());
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Posts the alerts of the subscribed journeys whenever a message of the trip updates feeds
+/// changes them
+pub(super) fn spawn_notifying(state: Arc<State>) {
+    tokio::spawn(async move {
+        let client = reqwest::Client::new();
+        let mut received = state.trip_updates.subscribe();
+        // What was posted to each webhook last, so that unchanged alerts aren't posted again
+        let mut posted: HashMap<(JourneyId, String), Vec<Alert>> = HashMap::new();
+        while received.changed().await.is_ok() {
+            let evaluating = state.clone();
+            let notifications = match tokio::task::spawn_blocking(move || notifications(&evaluating)).await {
+                Ok(Ok(notifications)) => notifications,
+                Ok(Err(err)) => {
+                    warn!(target: "server", "Unable to evaluate the subscribed journeys: {}", err);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+
+            let mut current = HashMap::with_capacity(notifications.len());
+            for (journey_id, webhook_url, alerts) in notifications {
+                let key = (journey_id, webhook_url);
+                if posted.get(&key) != Some(&alerts) {
+                    post(&client, &state, journey_id, &key.1, &alerts).await;
+                }
+                current.insert(key, alerts);
+            }
+            posted = current;
+        }
+    });
+}
+
+// The alerts of all subscriptions that the latest trip updates affect. Journeys of days that are
+// over are unsubscribed.
+fn notifications(state: &State) -> QueryResult<Vec<(JourneyId, String, Vec<Alert>)>> {
+    let yesterday = Utc::now().with_timezone(&state.timezone).date_naive() - Days::new(1);
+    let mut subscriptions = state.subscriptions.0.lock().unwrap();
+    subscriptions.retain(|day, _| *day >= yesterday);
+
+    let feeds = state.trip_updates.feeds();
+    let mut notifications = vec![];
+    for (day, subscriptions) in subscriptions.iter() {
+        let service_day_start = service_day_start(day.and_time(NaiveTime::MIN), state.timezone);
+        let changes = TripChange::from_feeds(&subscriptions.trips(), &feeds, &state.stops.original_ids, state.timetable(), service_day_start)?;
+        notifications.extend(subscriptions.evaluate(&changes).into_iter()
+            .map(|notification| (notification.journey_id, notification.webhook_url.to_string(), notification.alerts)));
+    }
+    Ok(notifications)
+}
+
+async fn post(client: &reqwest::Client, state: &State, journey_id: JourneyId, webhook_url: &str, alerts: &[Alert]) {
+    let message = WebhookMessage {
+        journey_id: journey_id.to_string(),
+        alerts: alerts.iter()
+            .map(|alert| match alert {
+                Alert::Delayed { leg, stop, delay } => WebhookAlert::Delayed { leg: *leg, stop: state.stops.stop(*stop), delay_seconds: delay.num_seconds() },
+                Alert::Cancelled { leg } => WebhookAlert::Cancelled { leg: *leg },
+                Alert::PlatformChanged { leg, stop, new_stop } => WebhookAlert::PlatformChanged {
+                    leg: *leg,
+                    stop: state.stops.stop(*stop),
+                    new_stop: state.stops.stop(*new_stop),
+                },
+            })
+            .collect(),
+    };
+    match client.post(webhook_url).json(&message).send().await.and_then(|response| response.error_for_status()) {
+        Ok(_) => debug!(target: "server", "Posted {} alerts of journey {} to {}", alerts.len(), journey_id, webhook_url),
+        Err(err) => warn!(target: "server", "Unable to post the alerts of journey {} to {}: {}", journey_id, webhook_url, err),
+    }
+}
@@ -0,0 +1,59 @@
+#[path = "../examples/fixture/mod.rs"]
+mod fixture;
+
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use common::util::logging::NoProgress;
+use common::util::paths;
+use common::types::config::{Compression, TransferConfig};
+use routing::algorithm::{FromDiskInit, JourneyPlanner, PreprocessInit, SaveToDisk};
+use routing::artifacts;
+use routing::csa::ConnectionScanAlgorithm;
+use routing::journey::Leg;
+use routing::raptor::RaptorAlgorithm;
+
+// The working directory is global, so the whole cycle runs in a single test
+#[tokio::test(flavor = "multi_thread")]
+async fn test_fetch_preprocess_query() {
+    let work_dir = tempfile::tempdir().unwrap();
+    paths::init(work_dir.path().to_path_buf());
+
+    let archive = fixture::zip_feed(work_dir.path()).unwrap();
+    let input = fixture::import(&archive).await.unwrap()
+        .running_on(NaiveDate::from_ymd_opt(2025, 6, 2).unwrap())
+        .unwrap();
+    let [a, b, d] = ["a", "b", "d"].map(|stop| fixture::stop_id(&input, stop).unwrap());
+    let at = |hours: i64, minutes: i64| DateTime::<Utc>::UNIX_EPOCH + TimeDelta::minutes(hours * 60 + minutes);
+
+    let raptor = <RaptorAlgorithm as PreprocessInit>::preprocess(input.clone(), &Default::default(), false, &NoProgress).unwrap();
+    let csa = <ConnectionScanAlgorithm as PreprocessInit>::preprocess(input.clone(), &Default::default(), false, &NoProgress).unwrap();
+
+    // Marktplatz is a walk of about 8 minutes, which reaches the tram at 8:15 as well as the bus at
+    // 8:00 does and saves a ride. The tram arrives at 8:35, then the walk to the Mensa.
+    let journey = JourneyPlanner::query_ea(&raptor, a, d, at(7, 55)).unwrap();
+    let rides = journey.legs().filter(|leg| matches!(leg, Leg::Ride { .. })).count();
+    assert_eq!(rides, 1);
+    assert!(matches!(journey.legs().next(), Some(Leg::Transfer { end, .. }) if *end == b));
+    assert!(matches!(journey.legs().last(), Some(Leg::Transfer { end, .. }) if *end == d));
+    assert!(journey.arrival().unwrap() > at(8, 35) && journey.arrival().unwrap() < at(8, 45));
+
+    // Both engines find the same arrivals, and leaving after 8:07 means taking the tram at 8:45
+    for (from, departure) in [(a, at(7, 55)), (a, at(8, 10)), (b, at(8, 20))] {
+        let raptor_arrival = JourneyPlanner::query_ea(&raptor, from, d, departure).unwrap().arrival();
+        let csa_arrival = JourneyPlanner::query_ea(&csa, from, d, departure).unwrap().arrival();
+        assert_eq!(raptor_arrival, csa_arrival);
+    }
+    assert!(JourneyPlanner::query_ea(&csa, a, d, at(8, 10)).unwrap().arrival().unwrap() > at(9, 5));
+
+    // Nothing runs after the last tram
+    assert!(JourneyPlanner::query_ea(&csa, a, d, at(9, 0)).is_err());
+
+    // A process that loads the saved engine answers like the one that preprocessed it
+    let artifacts_dir = work_dir.path().join("artifacts");
+    artifacts::write_stops(&artifacts_dir, input.stops, input.transfers, &TransferConfig::default(), Compression::default()).unwrap();
+    raptor.save_to_disk(&artifacts_dir, Compression::default()).unwrap();
+    let loaded = RaptorAlgorithm::load_from_disk(&artifacts_dir).unwrap();
+    assert_eq!(
+        JourneyPlanner::query_ea(&loaded, a, d, at(7, 55)).unwrap().arrival(),
+        journey.arrival(),
+    );
+}
@@ -1 +1,2 @@
+pub mod problem;
 pub mod v1;
\ No newline at end of file
@@ -0,0 +1,71 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use common::types::errors::ErrorCode;
+use polars::error::PolarsError;
+use serde::Serialize;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+/// Error response in the `application/problem+json` format of RFC 7807. The `code` member is the
+/// stable reason clients should branch on.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    // There is no documentation per problem type, so "about:blank" is used as the RFC suggests
+    #[serde(rename = "type")]
+    pub problem_type: &'static str,
+    pub title: &'static str,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub code: ErrorCode,
+}
+
+impl Problem {
+    pub fn new(code: ErrorCode, detail: Option<String>) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title: code.title(),
+            status: code.http_status(),
+            detail,
+            code,
+        }
+    }
+}
+
+impl From<ErrorCode> for Problem {
+    fn from(code: ErrorCode) -> Self {
+        Problem::new(code, None)
+    }
+}
+
+impl From<PolarsError> for Problem {
+    fn from(err: PolarsError) -> Self {
+        match err {
+            // The tables are missing while the datasets are preprocessed for the first time
+            // TODO: Use a better way to determine whether service is ready
+            PolarsError::IO { .. } => Problem::new(ErrorCode::DataReloading, Some("Unable to read the preprocessed datasets".into())),
+            _ => ErrorCode::Internal.into(),
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{}: {}", self.title, detail),
+            None => write!(f, "{}", self.title),
+        }
+    }
+}
+
+impl ResponseError for Problem {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type("application/problem+json")
+            .json(self)
+    }
+}
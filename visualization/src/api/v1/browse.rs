@@ -0,0 +1,179 @@
+use crate::api::problem::Problem;
+use actix_web::{get, web, Responder};
+use common::types::errors::ErrorCode;
+use common::types::mode::ModeRegistry;
+use common::util::paths;
+use polars::error::PolarsError;
+use polars::frame::DataFrame;
+use polars::prelude::{col, len, lit, JoinArgs, JoinType, LazyFrame};
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize)]
+struct Agency {
+    agency_id: String,
+    name: Option<String>,
+    num_routes: u32,
+}
+
+#[derive(Serialize)]
+struct Route {
+    route_id: String,
+    agency_id: String,
+    short_name: Option<String>,
+    long_name: Option<String>,
+    route_type: Option<u32>,
+    // Id of the mode of the route type, see /api/v1/modes
+    mode: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+struct Stop {
+    stop_id: String,
+    name: Option<String>,
+    lat: Option<f32>,
+    lon: Option<f32>,
+}
+
+/// A distinct sequence of stops served by trips of a route
+#[derive(Serialize)]
+struct RoutePattern {
+    num_trips: u32,
+    stops: Vec<Stop>,
+}
+
+// Agencies, routes and stops are given by their id in the source dataset prefixed with the id of
+// the dataset, e.g. "vvs:1", like in the other APIs, since the ids of different datasets collide
+fn namespaced(frame: &DataFrame, column: &str) -> Result<Vec<String>, PolarsError> {
+    Ok(strings(frame, "dataset_id")?.into_iter()
+        .zip(strings(frame, column)?)
+        .map(|(dataset_id, id)| format!("{}:{}", dataset_id.unwrap_or_default(), id.unwrap_or_default()))
+        .collect())
+}
+
+// Rows of the dataset whose id in the dataset is the namespaced one, or none if it has no dataset
+fn of_namespaced(frame: LazyFrame, column: &str, id: &str) -> LazyFrame {
+    let (dataset_id, id) = id.split_once(':').unwrap_or_default();
+    frame.filter(col("dataset_id").eq(lit(dataset_id)).and(col(column).eq(lit(id))))
+}
+
+fn scan_simplified(name: &str) -> Result<LazyFrame, PolarsError> {
+    LazyFrame::scan_parquet(paths::tmp_dir().join("simplify").join(name), Default::default())
+}
+
+fn strings(frame: &DataFrame, column: &str) -> Result<Vec<Option<String>>, PolarsError> {
+    Ok(frame.column(column)?.str()?.iter().map(|value| value.map(str::to_owned)).collect())
+}
+
+fn collect_agencies() -> Result<Vec<Agency>, PolarsError> {
+    let num_routes = scan_simplified("routes.parquet")?
+        .group_by([col("dataset_id"), col("agency_id_in_dataset")])
+        .agg([len().alias("num_routes")]);
+
+    let agencies = scan_simplified("agencies.parquet")?
+        .join(
+            num_routes,
+            [col("dataset_id"), col("agency_id_in_dataset")],
+            [col("dataset_id"), col("agency_id_in_dataset")],
+            JoinArgs::new(JoinType::Left),
+        )
+        .collect()?;
+
+    let num_routes = agencies.column("num_routes")?.u32()?;
+    Ok(namespaced(&agencies, "agency_id_in_dataset")?.into_iter()
+        .zip(strings(&agencies, "agency_name")?)
+        .zip(num_routes.iter())
+        .map(|((agency_id, name), num_routes)| Agency {
+            agency_id,
+            name,
+            num_routes: num_routes.unwrap_or(0),
+        })
+        .collect())
+}
+
+fn collect_routes(agency_id: &str, modes: &ModeRegistry) -> Result<Vec<Route>, PolarsError> {
+    let routes = of_namespaced(scan_simplified("routes.parquet")?, "agency_id_in_dataset", agency_id).collect()?;
+
+    let route_types = routes.column("route_type")?.u32()?;
+    Ok(namespaced(&routes, "route_id_in_dataset")?.into_iter()
+        .zip(strings(&routes, "route_short_name")?)
+        .zip(strings(&routes, "route_long_name")?)
+        .zip(route_types.iter())
+        .map(|(((route_id, short_name), long_name), route_type)| Route {
+            route_id,
+            agency_id: agency_id.to_owned(),
+            short_name,
+            long_name,
+            route_type,
+            mode: route_type.and_then(|route_type| modes.mode_of(route_type)).map(|mode| mode.id.clone()),
+        })
+        .collect())
+}
+
+fn collect_route_patterns(route_id: &str) -> Result<Vec<RoutePattern>, PolarsError> {
+    let patterns = of_namespaced(scan_simplified("route_patterns.parquet")?, "route_id_in_dataset", route_id).collect()?;
+
+    let stops = scan_simplified("stops.parquet")?
+        .select([col("stop_id"), col("dataset_id"), col("stop_id_in_dataset"), col("stop_name"), col("lat"), col("lon")])
+        .collect()?;
+
+    let stop_ids = stops.column("stop_id")?.u32()?;
+    let lats = stops.column("lat")?.f32()?;
+    let lons = stops.column("lon")?.f32()?;
+    let stops: HashMap<u32, Stop> = stop_ids.iter()
+        .zip(namespaced(&stops, "stop_id_in_dataset")?)
+        .zip(strings(&stops, "stop_name")?)
+        .zip(lats.iter().zip(lons.iter()))
+        .filter_map(|(((id, stop_id), name), (lat, lon))| {
+            Some((id?, Stop { stop_id, name, lat, lon }))
+        })
+        .collect();
+
+    let num_trips = patterns.column("num_trips")?.u32()?;
+    let pattern_stop_ids = patterns.column("stop_ids")?.list()?;
+    num_trips.iter()
+        .zip(pattern_stop_ids)
+        .map(|(num_trips, stop_ids)| {
+            let stops = match stop_ids {
+                Some(stop_ids) => stop_ids.u32()?.iter()
+                    .flatten()
+                    .filter_map(|stop_id| stops.get(&stop_id).cloned())
+                    .collect(),
+                None => vec![],
+            };
+            Ok(RoutePattern { num_trips: num_trips.unwrap_or(0), stops })
+        })
+        .collect()
+}
+
+/// All agencies of the loaded datasets
+#[get("/api/v1/agencies")]
+pub(crate) async fn list_agencies() -> Result<impl Responder, Problem> {
+    Ok(web::Json(collect_agencies()?))
+}
+
+/// All modes with what clients show for them
+#[get("/api/v1/modes")]
+pub(crate) async fn list_modes(modes: web::Data<ModeRegistry>) -> Result<impl Responder, Problem> {
+    Ok(web::Json(modes.modes().to_vec()))
+}
+
+/// All routes operated by an agency, given by its id prefixed with the id of its dataset
+#[get("/api/v1/agencies/{agency_id}/routes")]
+pub(crate) async fn list_agency_routes(agency_id: web::Path<String>, modes: web::Data<ModeRegistry>) -> Result<impl Responder, Problem> {
+    let routes = collect_routes(&agency_id, &modes)?;
+    if routes.is_empty() {
+        return Err(Problem::new(ErrorCode::NotFound, Some("Unknown agency".into())));
+    }
+    Ok(web::Json(routes))
+}
+
+/// The stop sequences of a route, given like the agencies, the most frequently served first
+#[get("/api/v1/routes/{route_id}/stops")]
+pub(crate) async fn list_route_stops(route_id: web::Path<String>) -> Result<impl Responder, Problem> {
+    let patterns = collect_route_patterns(&route_id)?;
+    if patterns.is_empty() {
+        return Err(Problem::new(ErrorCode::NotFound, Some("Unknown route".into())));
+    }
+    Ok(web::Json(patterns))
+}
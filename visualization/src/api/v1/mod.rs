@@ -1,7 +1,14 @@
+pub mod browse;
 pub mod config;
 pub mod stats;
 pub mod status;
+pub mod vehicles;
 
+pub use browse::list_agencies as agencies_api;
+pub use browse::list_agency_routes as agency_routes_api;
+pub use browse::list_modes as modes_api;
+pub use browse::list_route_stops as route_stops_api;
 pub use config::config as config_api;
 pub use stats::stats as stats_api;
-pub use status::status as status_api;
\ No newline at end of file
+pub use status::status as status_api;
+pub use vehicles::vehicle_of_trip as vehicles_api;
\ No newline at end of file
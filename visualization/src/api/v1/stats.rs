@@ -1,6 +1,6 @@
-use actix_web::error::ErrorInternalServerError;
-use actix_web::{get, web, Responder, Result};
-use common::util::df;
+use crate::api::problem::Problem;
+use actix_web::{get, web, Responder};
+use common::util::{df, paths};
 use polars::error::PolarsError;
 use polars::frame::UniqueKeepStrategy;
 use polars::prelude::{col, LazyCsvReader, LazyFileListReader, LazyFrame};
@@ -14,9 +14,9 @@ struct Stats {
 }
 
 #[get("/api/v1/stats")]
-pub(crate) async fn stats() -> Result<impl Responder> {
+pub(crate) async fn stats() -> Result<impl Responder, Problem> {
     fn collect_stats() -> Result<Stats, PolarsError> {
-        let clustered_stops = LazyCsvReader::new("../data/tmp/stp/stops_clustered.csv").finish()?;
+        let clustered_stops = LazyCsvReader::new(paths::tmp_dir().join("stp").join("stops_clustered.csv")).finish()?;
         let num_stops = df::count(clustered_stops.clone())?;
 
         let clusters = clustered_stops
@@ -25,7 +25,7 @@ pub(crate) async fn stats() -> Result<impl Responder> {
         let num_clusters = df::count(clusters)?;
 
         let trips =
-            LazyFrame::scan_parquet("../data/tmp/simplify/trips.parquet", Default::default())?;
+            LazyFrame::scan_parquet(paths::tmp_dir().join("simplify").join("trips.parquet"), Default::default())?;
         let num_trips = df::count(trips)?;
 
         Ok(Stats {
@@ -35,18 +35,5 @@ pub(crate) async fn stats() -> Result<impl Responder> {
         })
     }
 
-    match collect_stats() {
-        Ok(stats) => Ok(web::Json(stats)),
-        Err(err) => {
-            match err {
-                PolarsError::IO { .. } => {
-                    // TODO: Use a better way to determine whether service is ready
-                    Err(actix_web::Error::from(ErrorInternalServerError(
-                        "Unable to read stops_clustered.csv",
-                    )))
-                }
-                _ => Err(actix_web::Error::from(ErrorInternalServerError(""))),
-            }
-        }
-    }
+    Ok(web::Json(collect_stats()?))
 }
@@ -0,0 +1,70 @@
+use crate::api::problem::Problem;
+use actix_web::{get, web, Responder};
+use common::types::config::Config;
+use common::types::errors::ErrorCode;
+use common::types::id_interner::OriginalIds;
+use common::util::paths;
+use data_harvester::realtime::poll_vehicle_positions;
+use data_harvester::step5_simplify::read_original_ids;
+use log::{debug, warn};
+use routing::realtime::vehicles::{VehiclePositions, VehiclePositionsFeed};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// The latest vehicle positions of all datasets, matched to the trips of the preprocessed timetable
+#[derive(Default)]
+pub struct VehicleStore {
+    state: RwLock<State>,
+}
+
+#[derive(Default)]
+struct State {
+    // Latest positions by dataset
+    feeds: HashMap<String, VehiclePositionsFeed>,
+    // Missing until the datasets were preprocessed for the first time
+    original_ids: Option<OriginalIds>,
+    positions: VehiclePositions,
+}
+
+impl VehicleStore {
+    /// Polls the vehicle positions feeds of all datasets of the config that have one
+    pub fn spawn_polling(self: &Arc<Self>, config: &Config) {
+        for dataset in &config.settings().datasets {
+            let Some(feed) = dataset.vehicle_positions.clone() else { continue };
+            let (store, dataset_id) = (Arc::clone(self), dataset.id.clone());
+            tokio::spawn(async move {
+                poll_vehicle_positions(&feed.src, feed.interval.into(), |positions| {
+                    store.update(VehiclePositionsFeed { dataset_id: dataset_id.clone(), positions });
+                    true
+                }).await;
+            });
+        }
+    }
+
+    fn update(&self, feed: VehiclePositionsFeed) {
+        let mut state = self.state.write().unwrap();
+        state.feeds.insert(feed.dataset_id.clone(), feed);
+        if state.original_ids.is_none() {
+            state.original_ids = read_original_ids(paths::work_dir())
+                .inspect_err(|err| warn!(target: "realtime", "Vehicle positions can't be matched yet: {}", err))
+                .ok();
+        }
+        let Some(original_ids) = &state.original_ids else { return };
+
+        let positions = VehiclePositions::new(state.feeds.values().cloned().collect(), original_ids);
+        debug!(target: "realtime", "{} vehicles serve trips, {} don't match a trip", positions.len(), positions.unmatched);
+        state.positions = positions;
+    }
+}
+
+/// Where the vehicle serving a trip is, by the id of the trip prefixed with the id of its dataset,
+/// e.g. "vvs:4711"
+#[get("/api/v1/vehicles/{trip_id}")]
+pub(crate) async fn vehicle_of_trip(trip_id: web::Path<String>, store: web::Data<Arc<VehicleStore>>) -> Result<impl Responder, Problem> {
+    let state = store.state.read().unwrap();
+    let position = state.original_ids.as_ref()
+        .and_then(|original_ids| original_ids.find_trip(&trip_id))
+        .and_then(|trip| state.positions.of_trip(trip))
+        .ok_or_else(|| Problem::new(ErrorCode::NotFound, Some("No position of a vehicle serving this trip".into())))?;
+    Ok(web::Json(position.clone()))
+}
@@ -7,7 +7,8 @@ use actix_web::dev::Server;
 use actix_web::{web, App, HttpServer};
 use actix_web_static_files::ResourceFiles;
 use api::v1::status::{Job, JobStatus, StatusBroadcaster};
-use api::v1::{config_api, stats_api, status_api};
+use api::v1::vehicles::VehicleStore;
+use api::v1::{agencies_api, agency_routes_api, config_api, modes_api, route_stops_api, stats_api, status_api, vehicles_api};
 use common::types::config::Config;
 use std::sync::Arc;
 use std::time::Duration;
@@ -21,6 +22,10 @@ pub async fn build_server(
     data_path: PathBuf,
     disable_signals: bool
 ) -> std::io::Result<Server> {
+    let modes = config.mode_registry();
+    // Shared by all workers, so that every feed is only polled once
+    let vehicles = Arc::new(VehicleStore::default());
+    vehicles.spawn_polling(&config);
     let mut http_server = HttpServer::new(move || {
         let cors = Cors::default()
             .allowed_origin("http://localhost:5173")
@@ -62,12 +67,19 @@ pub async fn build_server(
             .wrap(cors)
             // Make config available in all handlers
             .app_data(web::Data::new(config.clone()))
+            .app_data(web::Data::new(modes.clone()))
             // Build a global channel to send status data
             .app_data(web::Data::new(Arc::clone(&status_broadcaster)))
+            .app_data(web::Data::new(Arc::clone(&vehicles)))
             // API endpoints
             .service(stats_api)
             .service(config_api)
             .service(status_api)
+            .service(agencies_api)
+            .service(agency_routes_api)
+            .service(route_stops_api)
+            .service(modes_api)
+            .service(vehicles_api)
             // Static files
             .service(Files::new("/data-files", data_path.clone()).prefer_utf8(true))
             // Serve the frontend. This is a catchall, so it must be defined last.
@@ -1,42 +1,80 @@
 extern crate drino_visualization;
 
-use common::types::config::Config;
-use common::util::logging;
-use log::{info, LevelFilter};
+use common::types::config::{Config, Settings};
+use common::util::{logging, paths};
+use log::info;
 use std::str::FromStr;
 use common::types::dataset::{DataSource, Dataset, DatasetConsistency, DatasetFormat, DatasetGroup, GeoPointConsistency, IdConsistency, License};
 use url::Url;
 use common::util::distance::Distance;
+use common::util::duration::Seconds;
+use common::util::size::ByteSize;
 use drino_visualization::build_server;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    logging::init(LevelFilter::Info);
+    logging::init(logging::LevelFilter::INFO, None);
 
     build_server(
         // TODO
-        Config::Version1 {
+        Config::Version1(Settings {
             datasets: vec![
                 Dataset {
                     id: "dataset-1".into(),
                     format: DatasetFormat::Gtfs,
                     group_ids: vec![ "group-a".into() ],
                     license: Some(License::Cc0_1_0),
-                    src: DataSource::URL { url: Url::from_str("https://asdf.com").unwrap(), headers: Default::default() }
+                    src: DataSource::URL { url: Url::from_str("https://asdf.com").unwrap(), headers: Default::default(), timeout: Seconds(60), max_size: ByteSize(1 << 30) },
+                    validation: Default::default(),
+                    bounds: None,
+                    drop_implausible_stops: false,
+                    fix: false,
+                    overrides: vec![],
+                    vehicle_positions: None,
+                    trip_updates: None,
+                    alerts: None,
+                    credentials: None,
+                    refresh: None,
+                    filter: Default::default(),
+                    attribution: None,
                 },
                 Dataset {
                     id: "dataset-2".into(),
                     format: DatasetFormat::Gtfs,
                     group_ids: vec![ "group-a".into(), "group-b".into() ],
                     license: Some(License::Cc0_1_0),
-                    src: DataSource::URL { url: Url::from_str("https://asdf.com").unwrap(), headers: Default::default() }
+                    src: DataSource::URL { url: Url::from_str("https://asdf.com").unwrap(), headers: Default::default(), timeout: Seconds(60), max_size: ByteSize(1 << 30) },
+                    validation: Default::default(),
+                    bounds: None,
+                    drop_implausible_stops: false,
+                    fix: false,
+                    overrides: vec![],
+                    vehicle_positions: None,
+                    trip_updates: None,
+                    alerts: None,
+                    credentials: None,
+                    refresh: None,
+                    filter: Default::default(),
+                    attribution: None,
                 },
                 Dataset {
                     id: "dataset-3".into(),
                     format: DatasetFormat::GtfsRt,
                     group_ids: vec![ "group-b".into() ],
                     license: Some(License::Cc0_1_0),
-                    src: DataSource::URL { url: Url::from_str("https://asdf.com").unwrap(), headers: Default::default() }
+                    src: DataSource::URL { url: Url::from_str("https://asdf.com").unwrap(), headers: Default::default(), timeout: Seconds(60), max_size: ByteSize(1 << 30) },
+                    validation: Default::default(),
+                    bounds: None,
+                    drop_implausible_stops: false,
+                    fix: false,
+                    overrides: vec![],
+                    vehicle_positions: None,
+                    trip_updates: None,
+                    alerts: None,
+                    credentials: None,
+                    refresh: None,
+                    filter: Default::default(),
+                    attribution: None,
                 },
             ],
             dataset_groups: vec![
@@ -51,8 +89,13 @@ async fn main() -> std::io::Result<()> {
                     }
                 }
             ],
-        },
-        "../data".into(),
+            merge: Default::default(),
+            simplify: Default::default(),
+            routing: Default::default(),
+            modes: vec![],
+            filter: Default::default(),
+        }),
+        paths::work_dir().into(),
         false
     ).await?
     .await?;